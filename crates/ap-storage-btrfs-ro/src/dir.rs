@@ -0,0 +1,76 @@
+//! Directory iteration for btrfs.
+
+use super::{
+    file::{file_id, File},
+    inode::dir_type,
+    Key, DIR_INDEX, FIRST_FREE, ROOT_ITEM,
+};
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    msg2err, Error, Offset, Read, ReadExt,
+};
+
+/// The size of a directory item before the name.
+pub(crate) const HEADER: usize = 30;
+
+/// The location of a directory item.
+pub(crate) fn location(header: &[u8; HEADER]) -> Key {
+    Key::parse(header)
+}
+
+/// The lengths of the name and the data of a directory item.
+pub(crate) fn lengths(header: &[u8; HEADER]) -> (usize, usize) {
+    let u16 = |x: usize| u16::from_le_bytes([header[x], header[x + 1]]) as usize;
+    (u16(27), u16(25))
+}
+
+/// Iterate over the index items of a directory.
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
+    /// The index to continue with.
+    next: Option<u64>,
+}
+
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Self {
+        Self { file, next: Some(0) }
+    }
+}
+
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        let Some(index) = self.next else {
+            return Ok(None);
+        };
+        let file = self.file;
+        let item = file
+            .fs
+            .ceil(file.root, &Key::new(file.ino, DIR_INDEX, index))?
+            .filter(|x| x.key.objectid == file.ino && x.key.typ == DIR_INDEX);
+        let Some(item) = item else {
+            self.next = None;
+            return Ok(None);
+        };
+        self.next = item.key.offset.checked_add(1);
+
+        let disk = file.fs.disk();
+        let header: [u8; HEADER] = disk.read_object(item.pos)?;
+        let (nlen, _) = lengths(&header);
+        if HEADER + nlen > item.size as usize {
+            return Err(msg2err!("truncated directory item"));
+        }
+        let n = core::cmp::min(nlen, name.len());
+        disk.read_exact(item.pos + HEADER as Offset, &mut name[..n])?;
+        let location = location(&header);
+        let id = match location.typ {
+            ROOT_ITEM => file_id(location.objectid, FIRST_FREE),
+            _ => file_id(file.tree, location.objectid),
+        };
+        Ok(Some(DirEntry {
+            offset: item.key.offset,
+            id,
+            nlen,
+            typ: dir_type(header[29]),
+        }))
+    }
+}
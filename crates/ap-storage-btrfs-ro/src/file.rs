@@ -0,0 +1,321 @@
+//! Files on btrfs.
+
+use super::{
+    attr::Attr, dir, dir::Dir, inode, le64, name_hash, Btrfs, Inode, Item, Key, Root, Source, DIR_INDEX, DIR_ITEM,
+    EXTENT_DATA, INODE_ITEM,
+};
+use ap_storage::{file::FileType, msg2err, Error, Offset, Read, ReadExt};
+
+/// The size of a file extent item up to the inline data.
+const EXTENT_HEADER: usize = 21;
+
+/// A file extent item.
+#[derive(Debug, Clone, Copy)]
+enum Extent {
+    Inline {
+        /// The disk position and size of the data in the leaf.
+        pos: Offset,
+        size: u64,
+        ram: u64,
+        compression: u8,
+    },
+    Regular {
+        bytenr: u64,
+        disk_len: u64,
+        /// The offset into the uncompressed extent.
+        offset: u64,
+        len: u64,
+        ram: u64,
+        compression: u8,
+        prealloc: bool,
+    },
+}
+
+impl Extent {
+    /// The bytes of the file covered by the extent.
+    fn len(&self) -> u64 {
+        match *self {
+            Self::Inline {
+                size, ram, compression, ..
+            } => match compression {
+                0 => size,
+                _ => ram,
+            },
+            Self::Regular { len, .. } => len,
+        }
+    }
+}
+
+/// The ID of a file in a tree.
+pub(crate) fn file_id(tree: u64, ino: u64) -> u64 {
+    tree << 48 | ino
+}
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a Btrfs<'a, D>,
+    /// The tree of the subvolume.
+    pub(crate) tree: u64,
+    pub(crate) root: Root,
+    pub(crate) ino: u64,
+    pub(crate) inode: Inode,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            tree: self.tree,
+            root: self.root,
+            ino: self.ino,
+            inode: self.inode,
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("fs", &self.fs)
+            .field("tree", &self.tree)
+            .field("ino", &self.ino)
+            .field("inode", &self.inode)
+            .finish()
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    pub(crate) fn new(fs: &'a Btrfs<'a, D>, tree: u64, root: Root, ino: u64) -> Result<Self, Error> {
+        let item = fs
+            .ceil(root, &Key::new(ino, INODE_ITEM, 0))?
+            .filter(|x| x.key.objectid == ino && x.key.typ == INODE_ITEM && x.size as usize >= inode::SIZE)
+            .ok_or(msg2err!("inode not found"))?;
+        let buf = fs.disk().read_object(item.pos)?;
+        Ok(Self {
+            fs,
+            tree,
+            root,
+            ino,
+            inode: Inode::parse(&buf),
+        })
+    }
+
+    pub fn inode(&self) -> &Inode {
+        &self.inode
+    }
+
+    /// The tree of the subvolume and the inode number.
+    pub fn ino(&self) -> (u64, u64) {
+        (self.tree, self.ino)
+    }
+
+    pub fn id(&self) -> u64 {
+        file_id(self.tree, self.ino)
+    }
+
+    pub fn ftype(&self) -> FileType {
+        self.inode.file_type()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.ftype() == FileType::Directory
+    }
+
+    /// Whether the item is a file extent of this inode.
+    fn is_extent(&self, item: &Item) -> bool {
+        item.key.objectid == self.ino && item.key.typ == EXTENT_DATA
+    }
+
+    /// Parse a file extent item.
+    fn extent(&self, item: &Item) -> Result<Extent, Error> {
+        let mut buf = [0; 53];
+        let n = core::cmp::min(item.size as usize, buf.len());
+        if n < EXTENT_HEADER {
+            return Err(msg2err!("truncated extent"));
+        }
+        self.fs.disk().read_exact(item.pos, &mut buf[..n])?;
+        let (ram, compression) = (le64(&buf, 8), buf[16]);
+        if buf[17] != 0 || buf[18] != 0 || buf[19] != 0 {
+            return Err(msg2err!("unsupported extent encoding"));
+        }
+        if buf[20] == 0 {
+            return Ok(Extent::Inline {
+                pos: item.pos + EXTENT_HEADER as Offset,
+                size: (item.size as usize - EXTENT_HEADER) as u64,
+                ram,
+                compression,
+            });
+        }
+        if n < buf.len() || buf[20] > 2 {
+            return Err(msg2err!("invalid extent"));
+        }
+        Ok(Extent::Regular {
+            bytenr: le64(&buf, 21),
+            disk_len: le64(&buf, 29),
+            offset: le64(&buf, 37),
+            len: le64(&buf, 45),
+            ram,
+            compression,
+            prealloc: buf[20] == 2,
+        })
+    }
+
+    /// Copy from an extent.
+    fn read_extent(&self, extent: &Extent, within: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let n = core::cmp::min(buf.len() as u64, extent.len() - within) as usize;
+        let buf = &mut buf[..n];
+        match *extent {
+            Extent::Inline {
+                pos, compression: 0, ..
+            } => {
+                self.fs.disk().read_exact(pos + within, buf)?;
+            }
+            Extent::Inline {
+                pos,
+                size,
+                ram,
+                compression,
+            } => {
+                return self.fs.read_compressed(
+                    Source::Inline(pos),
+                    size as usize,
+                    compression,
+                    ram as usize,
+                    within as usize,
+                    buf,
+                )
+            }
+            Extent::Regular { bytenr, prealloc, .. } if prealloc || bytenr == 0 => buf.fill(0),
+            Extent::Regular {
+                bytenr,
+                offset,
+                compression: 0,
+                ..
+            } => self.fs.read_logical(bytenr + offset + within, buf)?,
+            Extent::Regular {
+                bytenr,
+                disk_len,
+                offset,
+                ram,
+                compression,
+                ..
+            } => {
+                let within = (offset + within) as usize;
+                return self.fs.read_compressed(
+                    Source::Logical(bytenr),
+                    disk_len as usize,
+                    compression,
+                    ram as usize,
+                    within,
+                    buf,
+                );
+            }
+        }
+        Ok(n)
+    }
+
+    /// Whether a data extent of the file is referenced more than once, as by a snapshot or a reflink.
+    pub fn shared(&self) -> Result<bool, Error> {
+        let mut key = Key::new(self.ino, EXTENT_DATA, 0);
+        while let Some(item) = self.fs.ceil(self.root, &key)?.filter(|x| self.is_extent(x)) {
+            if let Extent::Regular { bytenr, .. } = self.extent(&item)? {
+                if bytenr != 0 && self.fs.extent(bytenr)?.is_some_and(|x| x.1 > 1) {
+                    return Ok(true);
+                }
+            }
+            let Some(next) = item.key.offset.checked_add(1) else {
+                break;
+            };
+            key.offset = next;
+        }
+        Ok(false)
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.is_dir() {
+            return Some(Dir::new(self));
+        }
+        None
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        let key = Key::new(self.ino, DIR_INDEX, offset);
+        let item = self
+            .fs
+            .ceil(self.root, &key)?
+            .filter(|x| x.key == key && x.size as usize >= dir::HEADER)
+            .ok_or(msg2err!("not a child"))?;
+        let header = self.fs.disk().read_object(item.pos)?;
+        self.fs.open(self.tree, self.root, &dir::location(&header))
+    }
+
+    fn lookup(&self, name: &[u8]) -> Result<Option<Self>, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        let key = Key::new(self.ino, DIR_ITEM, name_hash(name));
+        let Some(item) = self.fs.ceil(self.root, &key)?.filter(|x| x.key == key) else {
+            return Ok(None);
+        };
+
+        // names with the same hash share the item
+        let mut pos = 0;
+        let mut buf = [0; 256];
+        while pos + dir::HEADER <= item.size as usize {
+            let header: [u8; dir::HEADER] = self.fs.disk().read_object(item.pos + pos as Offset)?;
+            let (nlen, dlen) = dir::lengths(&header);
+            let next = pos + dir::HEADER + nlen + dlen;
+            if next > item.size as usize {
+                return Err(msg2err!("truncated directory item"));
+            }
+            if nlen == name.len() && nlen <= buf.len() {
+                let buf = &mut buf[..nlen];
+                self.fs
+                    .disk()
+                    .read_exact(item.pos + (pos + dir::HEADER) as Offset, buf)?;
+                if buf == name {
+                    return self.fs.open(self.tree, self.root, &dir::location(&header)).map(Some);
+                }
+            }
+            pos = next;
+        }
+        Ok(None)
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if offset >= self.inode.size || !matches!(self.ftype(), FileType::File | FileType::SymLink) {
+            return Ok(0);
+        }
+        let max_n = core::cmp::min(buf.len() as Offset, self.inode.size - offset) as usize;
+        let buf = &mut buf[..max_n];
+        let key = Key::new(self.ino, EXTENT_DATA, offset);
+        if let Some(item) = self.fs.floor(self.root, &key)?.filter(|x| self.is_extent(x)) {
+            let extent = self.extent(&item)?;
+            let within = offset - item.key.offset;
+            if within < extent.len() {
+                return self.read_extent(&extent, within, buf);
+            }
+        }
+
+        // a hole up to the next extent
+        let end = match self.fs.ceil(self.root, &key)?.filter(|x| self.is_extent(x)) {
+            Some(item) => item.key.offset,
+            None => self.inode.size,
+        };
+        let n = core::cmp::min(buf.len() as u64, end.saturating_sub(offset)) as usize;
+        buf[..n].fill(0);
+        Ok(n)
+    }
+}
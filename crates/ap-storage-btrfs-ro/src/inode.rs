@@ -0,0 +1,84 @@
+//! Inode items of btrfs.
+
+use super::{le32, le64};
+use ap_storage::file::FileType;
+
+/// The size of an inode item.
+pub(crate) const SIZE: usize = 160;
+
+/// An inode item.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Inode {
+    pub generation: u64,
+    pub size: u64,
+    /// The bytes allocated on disk.
+    pub nbytes: u64,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub rdev: u64,
+    pub flags: u64,
+    /// The times in nanoseconds.
+    pub atime: i64,
+    pub ctime: i64,
+    pub mtime: i64,
+    pub otime: i64,
+}
+
+impl Inode {
+    pub fn parse(buf: &[u8; SIZE]) -> Self {
+        let time = |pos| {
+            (le64(buf, pos) as i64)
+                .saturating_mul(1_000_000_000)
+                .saturating_add(le32(buf, pos + 8) as i64)
+        };
+        Self {
+            generation: le64(buf, 0),
+            size: le64(buf, 16),
+            nbytes: le64(buf, 24),
+            nlink: le32(buf, 40),
+            uid: le32(buf, 44),
+            gid: le32(buf, 48),
+            mode: le32(buf, 52),
+            rdev: le64(buf, 56),
+            flags: le64(buf, 64),
+            atime: time(112),
+            ctime: time(124),
+            mtime: time(136),
+            otime: time(148),
+        }
+    }
+
+    pub fn file_type(&self) -> FileType {
+        match self.mode >> 12 {
+            0o01 => FileType::Fifo,
+            0o02 => FileType::CharDevice,
+            0o04 => FileType::Directory,
+            0o06 => FileType::BlockDevice,
+            0o10 => FileType::File,
+            0o12 => FileType::SymLink,
+            0o14 => FileType::Socket,
+            _ => FileType::Unknown,
+        }
+    }
+
+    /// The device number as `major << 32 | minor` from the encoding of the Linux kernel.
+    pub fn device(&self) -> u64 {
+        (self.rdev >> 20) << 32 | (self.rdev & 0xf_ffff)
+    }
+}
+
+/// The file type in a directory item.
+pub(crate) fn dir_type(typ: u8) -> FileType {
+    match typ {
+        1 => FileType::File,
+        2 => FileType::Directory,
+        3 => FileType::CharDevice,
+        4 => FileType::BlockDevice,
+        5 => FileType::Fifo,
+        6 => FileType::Socket,
+        7 => FileType::SymLink,
+        _ => FileType::Unknown,
+    }
+}
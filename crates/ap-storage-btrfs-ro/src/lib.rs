@@ -0,0 +1,860 @@
+//! Read btrfs filesystems.
+//!
+//! - chunk tree bootstrapped from the system chunks in the superblock
+//! - single, DUP and RAID1 chunks on the device of the image
+//! - root, filesystem and extent trees of any height
+//! - inline, regular and preallocated extents with zlib, zstd and lzo compression
+//! - subvolumes and snapshots as directories
+//!
+//! Trees are searched without allocations and without keeping a path, so a directory listing only remembers the
+//! index of the next entry.  Decompression works in a scratch buffer provided by the caller, which also caches the
+//! last compressed extent.  The offset of a directory entry is its index and the ID of a file is the inode number
+//! with the tree of its subvolume in the upper 16 bits.
+
+#![no_std]
+
+use ap_storage::{msg2err, Error, FileSystem, Offset, Read, ReadExt, ScratchBuf};
+use core::cell::Cell;
+
+mod attr;
+mod dir;
+pub mod file;
+mod inode;
+mod lzo;
+mod tree;
+
+pub use inode::Inode;
+pub use tree::{Item, Key, Root};
+
+/// The position of the primary superblock.
+const SUPERBLOCK: Offset = 0x10000;
+
+const MAGIC: &[u8; 8] = b"_BHRfS_M";
+
+/// The size of the system chunk array in the superblock.
+const SYS_CHUNK_ARRAY_SIZE: usize = 2048;
+
+/// The most stripes of a chunk that are considered.
+const MAX_STRIPES: usize = 16;
+
+/// The item types.
+pub const INODE_ITEM: u8 = 1;
+pub const DIR_ITEM: u8 = 84;
+pub const DIR_INDEX: u8 = 96;
+pub const EXTENT_DATA: u8 = 108;
+pub const ROOT_ITEM: u8 = 132;
+pub const EXTENT_ITEM: u8 = 168;
+pub const CHUNK_ITEM: u8 = 228;
+
+/// The well-known trees.
+pub const EXTENT_TREE: u64 = 2;
+pub const FS_TREE: u64 = 5;
+
+/// The first inode number and the object of the chunk items.
+pub const FIRST_FREE: u64 = 256;
+
+/// The chunk profiles that stripe the data over several devices.
+const STRIPED: u64 = 1 << 3 | 1 << 6 | 1 << 7 | 1 << 8;
+
+/// The largest uncompressed size of a compressed extent.
+pub const MAX_COMPRESSED: usize = 128 << 10;
+
+/// The scratch space for decompression.
+pub const SCRATCH_SIZE: usize = 2 * MAX_COMPRESSED + ap_util_zstd::MAX_BLOCK_SIZE;
+
+/// The superblock.
+#[derive(Debug, Clone, Copy)]
+pub struct Superblock {
+    pub generation: u64,
+    pub root: u64,
+    pub chunk_root: u64,
+    pub total_bytes: u64,
+    pub num_devices: u64,
+    pub sectorsize: u32,
+    pub nodesize: u32,
+    pub sys_chunk_array_size: u32,
+    pub incompat_flags: u64,
+    pub csum_type: u16,
+    pub root_level: u8,
+    pub chunk_root_level: u8,
+    /// The id of the device of the image.
+    pub devid: u64,
+}
+
+impl Superblock {
+    /// Parse and check the superblock.
+    pub fn parse(buf: &[u8; 4096]) -> Result<Self, Error> {
+        if &buf[0x40..0x48] != MAGIC {
+            return Err(msg2err!("no btrfs"));
+        }
+        let res = Self {
+            generation: le64(buf, 0x48),
+            root: le64(buf, 0x50),
+            chunk_root: le64(buf, 0x58),
+            total_bytes: le64(buf, 0x70),
+            num_devices: le64(buf, 0x88),
+            sectorsize: le32(buf, 0x90),
+            nodesize: le32(buf, 0x94),
+            sys_chunk_array_size: le32(buf, 0xa0),
+            incompat_flags: le64(buf, 0xbc),
+            csum_type: u16::from_le_bytes([buf[0xc4], buf[0xc5]]),
+            root_level: buf[0xc6],
+            chunk_root_level: buf[0xc7],
+            devid: le64(buf, 0xc9),
+        };
+        if res.csum_type == 0 && !crc32c(!0, &buf[0x20..]) != le32(buf, 0) {
+            return Err(msg2err!("superblock checksum"));
+        }
+        if !res.sectorsize.is_power_of_two()
+            || !(4096..=65536).contains(&res.sectorsize)
+            || !res.nodesize.is_power_of_two()
+            || !(res.sectorsize..=65536).contains(&res.nodesize)
+        {
+            return Err(msg2err!("block size"));
+        }
+        if res.sys_chunk_array_size as usize > SYS_CHUNK_ARRAY_SIZE
+            || res.root_level as usize >= tree::MAX_LEVEL
+            || res.chunk_root_level as usize >= tree::MAX_LEVEL
+        {
+            return Err(msg2err!("invalid superblock"));
+        }
+        Ok(res)
+    }
+}
+
+/// Whether the disk has a btrfs superblock, which is cheaper than mounting it.
+pub fn probe(disk: &dyn Read) -> bool {
+    disk.read_object::<[u8; 8]>(SUPERBLOCK + 0x40)
+        .is_ok_and(|x| &x == MAGIC)
+}
+
+/// A chunk mapped to this device.
+#[derive(Debug, Clone, Copy, Default)]
+struct Chunk {
+    logical: u64,
+    length: u64,
+    physical: Offset,
+}
+
+impl Chunk {
+    fn contains(&self, logical: u64) -> bool {
+        logical.wrapping_sub(self.logical) < self.length
+    }
+}
+
+/// The parts of the scratch buffer.
+struct Parts<'s> {
+    data: &'s mut [u8],
+    /// Compressed input.
+    input: &'s mut [u8],
+    /// The literals of zstd.
+    literals: &'s mut [u8],
+}
+
+impl<'s> Parts<'s> {
+    fn new(buf: &'s mut [u8]) -> Self {
+        let (data, rest) = buf.split_at_mut(MAX_COMPRESSED);
+        let (input, rest) = rest.split_at_mut(MAX_COMPRESSED);
+        Parts {
+            data,
+            input,
+            literals: &mut rest[..ap_util_zstd::MAX_BLOCK_SIZE],
+        }
+    }
+}
+
+/// A btrfs filesystem.
+pub struct Btrfs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+    sb: Superblock,
+    sys_chunks: [u8; SYS_CHUNK_ARRAY_SIZE],
+    /// The recently used chunks with the last one in front.
+    chunks: Cell<[Chunk; 4]>,
+    /// Set while the chunk tree is searched, so that its nodes are only mapped via the system chunks.
+    mapping: Cell<bool>,
+    fs_root: Root,
+    /// The root directory of the filesystem tree.
+    fs_dir: u64,
+    extent_root: Root,
+    scratch: ScratchBuf<'a>,
+    /// The disk position of the extent cached in the scratch buffer with its length.
+    extent: Cell<Option<(Offset, usize)>>,
+}
+
+impl<D: ?Sized> core::fmt::Debug for Btrfs<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(
+            fmt,
+            "Btrfs(gen {}, ns {}, ss {})",
+            self.sb.generation, self.sb.nodesize, self.sb.sectorsize
+        )
+    }
+}
+
+impl<'a, D: Read + ?Sized> Btrfs<'a, D> {
+    /// Mount the filesystem with a scratch buffer of at least [`SCRATCH_SIZE`] bytes.
+    pub fn new(disk: &'a D, scratch: &'a mut [u8]) -> Result<Self, Error> {
+        let buf: [u8; 4096] = (&disk as &dyn Read).read_object(SUPERBLOCK)?;
+        let sb = Superblock::parse(&buf)?;
+        if scratch.len() < SCRATCH_SIZE {
+            return Err(msg2err!("scratch buffer too small"));
+        }
+        let mut fs = Self {
+            disk,
+            sb,
+            sys_chunks: [0; SYS_CHUNK_ARRAY_SIZE],
+            chunks: Cell::new([Chunk::default(); 4]),
+            mapping: Cell::new(false),
+            fs_root: Root::default(),
+            fs_dir: 0,
+            extent_root: Root::default(),
+            scratch: ScratchBuf::new(scratch),
+            extent: Cell::new(None),
+        };
+        fs.sys_chunks.copy_from_slice(&buf[0x32b..0x32b + SYS_CHUNK_ARRAY_SIZE]);
+        (fs.fs_root, fs.fs_dir) = fs.tree(FS_TREE)?;
+        fs.extent_root = fs.tree(EXTENT_TREE)?.0;
+        Ok(fs)
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.sb
+    }
+
+    /// The root of the tree of tree roots.
+    pub fn root_tree(&self) -> Root {
+        Root {
+            bytenr: self.sb.root,
+            level: self.sb.root_level,
+        }
+    }
+
+    /// The root of the chunk tree.
+    pub fn chunk_tree(&self) -> Root {
+        Root {
+            bytenr: self.sb.chunk_root,
+            level: self.sb.chunk_root_level,
+        }
+    }
+
+    /// Translate a logical address into a disk position and the bytes left in the chunk.
+    pub fn map(&self, logical: u64) -> Result<(Offset, u64), Error> {
+        let cached = self.chunks.get().into_iter().find(|x| x.contains(logical));
+        let chunk = match cached {
+            Some(chunk) => chunk,
+            None => match self.sys_chunk(logical)? {
+                Some(chunk) => chunk,
+                None => self.tree_chunk(logical)?,
+            },
+        };
+        let mut chunks = self.chunks.get();
+        let index = chunks
+            .iter()
+            .position(|x| x.contains(logical))
+            .unwrap_or(chunks.len() - 1);
+        chunks[..=index].rotate_right(1);
+        chunks[0] = chunk;
+        self.chunks.set(chunks);
+        let within = logical - chunk.logical;
+        Ok((chunk.physical + within, chunk.length - within))
+    }
+
+    /// Select the stripe on this device from a chunk item.
+    fn parse_chunk(&self, logical: u64, item: &[u8]) -> Result<Chunk, Error> {
+        if item.len() < 48 {
+            return Err(msg2err!("truncated chunk"));
+        }
+        if le64(item, 24) & STRIPED != 0 {
+            return Err(msg2err!("unsupported chunk profile"));
+        }
+        let count = u16::from_le_bytes([item[44], item[45]]) as usize;
+        let stripe = item[48..]
+            .chunks_exact(32)
+            .take(count)
+            .find(|x| le64(x, 0) == self.sb.devid)
+            .ok_or(msg2err!("chunk on a missing device"))?;
+        Ok(Chunk {
+            logical,
+            length: le64(item, 0),
+            physical: le64(stripe, 8),
+        })
+    }
+
+    /// Search a chunk in the system chunk array of the superblock.
+    fn sys_chunk(&self, logical: u64) -> Result<Option<Chunk>, Error> {
+        let array = &self.sys_chunks[..self.sb.sys_chunk_array_size as usize];
+        let mut pos = 0;
+        while pos < array.len() {
+            let header = array.get(pos..pos + 17 + 48).ok_or(msg2err!("invalid system chunks"))?;
+            let key = Key::parse(header);
+            let end = pos + 17 + 48 + 32 * u16::from_le_bytes([header[17 + 44], header[17 + 45]]) as usize;
+            let item = array.get(pos + 17..end).ok_or(msg2err!("invalid system chunks"))?;
+            if key.typ == CHUNK_ITEM && logical.wrapping_sub(key.offset) < le64(item, 0) {
+                return self.parse_chunk(key.offset, item).map(Some);
+            }
+            pos = end;
+        }
+        Ok(None)
+    }
+
+    /// Search a chunk in the chunk tree.
+    fn tree_chunk(&self, logical: u64) -> Result<Chunk, Error> {
+        // the nodes of the chunk tree have to be in the system chunks
+        if self.mapping.replace(true) {
+            return Err(msg2err!("chunk tree outside the system chunks"));
+        }
+        let key = Key::new(FIRST_FREE, CHUNK_ITEM, logical);
+        let item = self.floor(self.chunk_tree(), &key);
+        self.mapping.set(false);
+        let item = item?
+            .filter(|x| x.key.objectid == FIRST_FREE && x.key.typ == CHUNK_ITEM)
+            .ok_or(msg2err!("unmapped address"))?;
+        let mut buf = [0; 48 + 32 * MAX_STRIPES];
+        let n = core::cmp::min(item.size as usize, buf.len());
+        self.disk().read_exact(item.pos, &mut buf[..n])?;
+        let chunk = self.parse_chunk(item.key.offset, &buf[..n])?;
+        if !chunk.contains(logical) {
+            return Err(msg2err!("unmapped address"));
+        }
+        Ok(chunk)
+    }
+
+    /// The root of a tree and the directory of a subvolume.
+    pub fn tree(&self, id: u64) -> Result<(Root, u64), Error> {
+        let item = self
+            .floor(self.root_tree(), &Key::new(id, ROOT_ITEM, u64::MAX))?
+            .filter(|x| x.key.objectid == id && x.key.typ == ROOT_ITEM && x.size >= 239)
+            .ok_or(msg2err!("tree not found"))?;
+        let mut buf = [0; 239];
+        self.disk().read_exact(item.pos, &mut buf)?;
+        let root = Root {
+            bytenr: le64(&buf, 176),
+            level: buf[238],
+        };
+        if root.level as usize >= tree::MAX_LEVEL {
+            return Err(msg2err!("invalid root item"));
+        }
+        Ok((root, le64(&buf, 168)))
+    }
+
+    /// The length and the number of references of an extent in the extent tree.
+    pub fn extent(&self, bytenr: u64) -> Result<Option<(u64, u64)>, Error> {
+        let Some(item) = self.ceil(self.extent_root, &Key::new(bytenr, EXTENT_ITEM, 0))? else {
+            return Ok(None);
+        };
+        if item.key.objectid != bytenr || item.key.typ != EXTENT_ITEM || item.size < 8 {
+            return Ok(None);
+        }
+        let refs: [u8; 8] = self.disk().read_object(item.pos)?;
+        Ok(Some((item.key.offset, u64::from_le_bytes(refs))))
+    }
+
+    /// Read from a logical address that might cross chunks.
+    pub(crate) fn read_logical(&self, mut logical: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let mut done = 0;
+        while done < buf.len() {
+            let (pos, left) = self.map(logical)?;
+            let n = core::cmp::min((buf.len() - done) as u64, left) as usize;
+            self.disk().read_exact(pos, &mut buf[done..done + n])?;
+            done += n;
+            logical += n as u64;
+        }
+        Ok(())
+    }
+
+    /// Copy from a compressed extent that is cached in the scratch buffer.
+    ///
+    /// The compressed bytes are at a logical address or inline at a disk position.  Bytes missing from the `ram`
+    /// size of the extent are zero.
+    pub(crate) fn read_compressed(
+        &self,
+        src: Source,
+        len: usize,
+        compression: u8,
+        ram: usize,
+        within: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let key = match src {
+            Source::Logical(x) => x,
+            Source::Inline(x) => !x,
+        };
+        let mut scratch = self.scratch.borrow_mut()?;
+        let len = match self.extent.get() {
+            Some((k, len)) if k == key => len,
+            _ => {
+                self.extent.set(None);
+                let Parts { data, input, literals } = Parts::new(&mut scratch);
+                if len > input.len() || ram > data.len() {
+                    return Err(msg2err!("compressed extent too large"));
+                }
+                let input = &mut input[..len];
+                match src {
+                    Source::Logical(x) => self.read_logical(x, input)?,
+                    Source::Inline(x) => self.disk().read_exact(x, input)?,
+                }
+                let n = match compression {
+                    1 => ap_util_inflate::inflate_zlib(&mut &input[..], data)?,
+                    2 => lzo::decompress(input, data, self.sb.sectorsize as usize)?,
+                    3 => ap_util_zstd::decompress_frame(&mut &input[..], data, literals)?,
+                    _ => return Err(msg2err!("unsupported compression")),
+                };
+                data[core::cmp::min(n, ram)..ram].fill(0);
+                self.extent.set(Some((key, ram)));
+                ram
+            }
+        };
+        let n = core::cmp::min(buf.len(), len.saturating_sub(within));
+        buf[..n].copy_from_slice(&Parts::new(&mut scratch).data[within..within + n]);
+        Ok(n)
+    }
+
+    /// Open the file or subvolume at the location of a directory entry.
+    pub fn open(&'a self, tree: u64, root: Root, location: &Key) -> Result<file::File<'a, D>, Error> {
+        match location.typ {
+            INODE_ITEM => file::File::new(self, tree, root, location.objectid),
+            ROOT_ITEM => {
+                let (root, dir) = self.tree(location.objectid)?;
+                file::File::new(self, location.objectid, root, dir)
+            }
+            _ => Err(msg2err!("invalid location")),
+        }
+    }
+}
+
+/// Where compressed bytes are stored.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Source {
+    Logical(u64),
+    Inline(Offset),
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for Btrfs<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        file::File::new(self, FS_TREE, self.fs_root, self.fs_dir)
+    }
+}
+
+/// The CRC-32C without the final inversion.
+pub fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for x in data {
+        crc ^= *x as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0x82f6_3b78 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// The hash of a name in the key of a directory item.
+pub fn name_hash(name: &[u8]) -> u64 {
+    crc32c(!1, name) as u64
+}
+
+/// A little-endian u32 at the position.
+pub(crate) fn le32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+/// A little-endian u64 at the position.
+pub(crate) fn le64(buf: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{
+        attr::{self, Attributes},
+        directory::DirIterator,
+        file::{File, FileType},
+    };
+    use ap_storage_memory::ReadSlice;
+    use ap_storage_test_util::*;
+    use std::{vec, vec::Vec};
+
+    const NODESIZE: usize = 4096;
+
+    /// The logical start, the disk position and the length of the system and the data chunk.
+    const SYS: [u64; 3] = [0x10_0000, 0x2_0000, 0x1_0000];
+    const DATA: [u64; 3] = [0x100_0000, 0x4_0000, 0x4_0000];
+
+    /// `0123456789` as zlib stream.
+    const ZLIB: [u8; 54] = [
+        120, 218, 237, 198, 73, 13, 0, 32, 12, 0, 48, 75, 227, 24, 3, 255, 198, 208, 177, 164, 125, 53, 198, 92, 59,
+        79, 221, 23, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102, 102,
+        109, 247, 1, 100, 14, 3, 65,
+    ];
+
+    /// `abcdefghij` as zstd frame.
+    const ZSTD: [u8; 28] = [
+        40, 181, 47, 253, 96, 16, 38, 149, 0, 0, 80, 97, 98, 99, 100, 101, 102, 103, 104, 105, 106, 1, 0, 3, 167, 124,
+        65, 2,
+    ];
+
+    /// `tiny ` eight times as zlib stream.
+    const TINY: [u8; 16] = [120, 218, 43, 201, 204, 171, 84, 40, 33, 76, 0, 0, 59, 7, 15, 33];
+
+    /// Encode periodic data in lzo segments with the literals of the first period and a single match.
+    fn lzo(data: &[u8], period: usize) -> Vec<u8> {
+        let mut res = vec![0; 4];
+        for segment in data.chunks(4096) {
+            let mut block = vec![17 + period as u8];
+            block.extend_from_slice(&segment[..period]);
+            // a long match of 33 + 255 * zeros + last byte
+            let rest = segment.len() - period - 33;
+            block.push(32);
+            block.extend(core::iter::repeat_n(0, (rest - 1) / 255));
+            block.push((rest - (rest - 1) / 255 * 255) as u8);
+            block.extend_from_slice(&(((period - 1) << 2) as u16).to_le_bytes());
+            block.extend_from_slice(&[0x11, 0, 0]);
+            res.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            res.extend_from_slice(&block);
+        }
+        let total = res.len() as u32;
+        res[..4].copy_from_slice(&total.to_le_bytes());
+        res
+    }
+
+    fn key(objectid: u64, typ: u8, offset: u64) -> Key {
+        Key::new(objectid, typ, offset)
+    }
+
+    fn put_key(buf: &mut [u8], key: &Key) {
+        buf[..8].copy_from_slice(&key.objectid.to_le_bytes());
+        buf[8] = key.typ;
+        buf[9..17].copy_from_slice(&key.offset.to_le_bytes());
+    }
+
+    fn put(buf: &mut [u8], pos: usize, value: u64, size: usize) {
+        buf[pos..pos + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    fn chunk(typ: u64, length: u64, stripes: &[(u64, u64)]) -> Vec<u8> {
+        let mut res = vec![0; 48 + 32 * stripes.len()];
+        put(&mut res, 0, length, 8);
+        put(&mut res, 24, typ, 8);
+        put(&mut res, 44, stripes.len() as u64, 2);
+        for (i, (devid, offset)) in stripes.iter().enumerate() {
+            put(&mut res, 48 + 32 * i, *devid, 8);
+            put(&mut res, 56 + 32 * i, *offset, 8);
+        }
+        res
+    }
+
+    fn inode(mode: u32, size: u64, nlink: u32) -> Vec<u8> {
+        let mut res = vec![0; 160];
+        put(&mut res, 16, size, 8);
+        put(&mut res, 40, nlink as u64, 4);
+        put(&mut res, 52, mode as u64, 4);
+        put(&mut res, 136, 1_700_000_000, 8);
+        res
+    }
+
+    fn dir_item(location: Key, typ: u8, name: &[u8]) -> Vec<u8> {
+        let mut res = vec![0; 30];
+        put_key(&mut res, &location);
+        put(&mut res, 27, name.len() as u64, 2);
+        res[29] = typ;
+        res.extend_from_slice(name);
+        res
+    }
+
+    fn inline(compression: u8, ram: usize, data: &[u8]) -> Vec<u8> {
+        let mut res = vec![0; 21];
+        put(&mut res, 8, ram as u64, 8);
+        res[16] = compression;
+        res.extend_from_slice(data);
+        res
+    }
+
+    fn regular(typ: u8, compression: u8, extent: [u64; 3], offset: u64, len: u64) -> Vec<u8> {
+        let mut res = vec![0; 53];
+        let [bytenr, disk_len, ram] = extent;
+        put(&mut res, 8, ram, 8);
+        res[16] = compression;
+        res[20] = typ;
+        for (i, x) in [bytenr, disk_len, offset, len].iter().enumerate() {
+            put(&mut res, 21 + 8 * i, *x, 8);
+        }
+        res
+    }
+
+    /// An image with a trivial allocator in the data chunk.
+    struct Image {
+        data: Vec<u8>,
+        next: u64,
+        extents: Vec<(Key, Vec<u8>)>,
+    }
+
+    impl Image {
+        fn write(&mut self, logical: u64, buf: &[u8]) {
+            let [start, pos, _] = [SYS, DATA]
+                .into_iter()
+                .find(|x| logical.wrapping_sub(x[0]) < x[2])
+                .unwrap();
+            let pos = (pos + logical - start) as usize;
+            self.data[pos..pos + buf.len()].copy_from_slice(buf);
+        }
+
+        fn alloc(&mut self, len: usize) -> u64 {
+            self.next += (len as u64).div_ceil(4096) * 4096;
+            self.next - (len as u64).div_ceil(4096) * 4096
+        }
+
+        /// Store a data extent with its references.
+        fn extent(&mut self, buf: &[u8], ram: usize, refs: u64) -> [u64; 3] {
+            let bytenr = self.alloc(buf.len());
+            self.write(bytenr, buf);
+            let disk_len = (buf.len() as u64).div_ceil(4096) * 4096;
+            let mut item = vec![0; 24];
+            put(&mut item, 0, refs, 8);
+            put(&mut item, 16, 1, 8);
+            self.extents.push((key(bytenr, EXTENT_ITEM, disk_len), item));
+            [bytenr, disk_len, ram as u64]
+        }
+
+        fn node(&mut self, bytenr: u64, owner: u64, level: u8, count: usize) -> Vec<u8> {
+            let mut res = vec![0; NODESIZE];
+            put(&mut res, 0x30, bytenr, 8);
+            put(&mut res, 0x58, owner, 8);
+            put(&mut res, 0x60, count as u64, 4);
+            res[0x64] = level;
+            res
+        }
+
+        fn leaf(&mut self, bytenr: u64, owner: u64, items: &[(Key, Vec<u8>)]) -> u64 {
+            let mut res = self.node(bytenr, owner, 0, items.len());
+            let mut end = NODESIZE - 101;
+            for (i, (key, data)) in items.iter().enumerate() {
+                end -= data.len();
+                put_key(&mut res[101 + 25 * i..], key);
+                put(&mut res, 101 + 25 * i + 17, end as u64, 4);
+                put(&mut res, 101 + 25 * i + 21, data.len() as u64, 4);
+                res[101 + end..101 + end + data.len()].copy_from_slice(data);
+            }
+            self.write(bytenr, &res);
+            bytenr
+        }
+
+        /// Store a tree with a fixed number of items per leaf and return its root.
+        fn tree(&mut self, owner: u64, mut items: Vec<(Key, Vec<u8>)>, per_leaf: usize) -> Root {
+            items.sort_by_key(|x| x.0);
+            let leaves: Vec<_> = items.chunks(per_leaf).map(|x| (x[0].0, self.alloc(NODESIZE))).collect();
+            for (chunk, (_, bytenr)) in items.chunks(per_leaf).zip(&leaves) {
+                self.leaf(*bytenr, owner, chunk);
+            }
+            if leaves.len() == 1 {
+                return Root {
+                    bytenr: leaves[0].1,
+                    level: 0,
+                };
+            }
+            let bytenr = self.alloc(NODESIZE);
+            let mut res = self.node(bytenr, owner, 1, leaves.len());
+            for (i, (key, child)) in leaves.iter().enumerate() {
+                put_key(&mut res[101 + 33 * i..], key);
+                put(&mut res, 101 + 33 * i + 17, *child, 8);
+            }
+            self.write(bytenr, &res);
+            Root { bytenr, level: 1 }
+        }
+    }
+
+    /// The items of a directory entry.
+    fn entry(items: &mut Vec<(Key, Vec<u8>)>, dir: u64, index: u64, location: Key, typ: u8, name: &[u8]) {
+        items.push((key(dir, DIR_ITEM, name_hash(name)), dir_item(location, typ, name)));
+        items.push((key(dir, DIR_INDEX, index), dir_item(location, typ, name)));
+    }
+
+    fn root_item(root: Root) -> Vec<u8> {
+        let mut res = vec![0; 439];
+        put(&mut res, 52, 0o40755, 4);
+        put(&mut res, 168, FIRST_FREE, 8);
+        put(&mut res, 176, root.bytenr, 8);
+        res[238] = root.level;
+        res
+    }
+
+    /// Build an image with a filesystem tree of two levels and a subvolume.
+    fn image() -> Vec<u8> {
+        let mut image = Image {
+            data: vec![0; (DATA[1] + DATA[2]) as usize],
+            next: DATA[0],
+            extents: Vec::new(),
+        };
+        let mut fs = Vec::new();
+        let file = |ino: u64| key(ino, INODE_ITEM, 0);
+        fs.push((file(256), inode(0o40755, 0, 1)));
+        let mut index = 2;
+        let mut add = |fs: &mut Vec<_>, ino: u64, mode: u32, size: u64, nlink: u32, name: &[u8]| {
+            fs.push((file(ino), inode(mode, size, nlink)));
+            entry(fs, 256, index, file(ino), if mode >> 12 == 4 { 2 } else { 1 }, name);
+            // gaps as left by removed entries
+            index += 2;
+        };
+
+        add(&mut fs, 257, 0o100644, 12, 2, b"hello");
+        fs.push((key(257, EXTENT_DATA, 0), inline(0, 12, b"hello world\n")));
+
+        add(&mut fs, 258, 0o100644, 10000, 1, b"zlib");
+        let extent = image.extent(&ZLIB, 10000, 1);
+        fs.push((key(258, EXTENT_DATA, 0), regular(1, 1, extent, 0, 10000)));
+
+        add(&mut fs, 259, 0o100644, 10000, 1, b"zstd");
+        let zstd = image.extent(&ZSTD, 10000, 2);
+        fs.push((key(259, EXTENT_DATA, 0), regular(1, 3, zstd, 0, 10000)));
+
+        add(&mut fs, 260, 0o100644, 10000, 1, b"lzo");
+        let extent = image.extent(&lzo(&pattern(b"ABCDEFGHIJ", 10000), 10), 10000, 1);
+        fs.push((key(260, EXTENT_DATA, 0), regular(1, 2, extent, 0, 10000)));
+
+        // preallocated, implicit hole, data with an offset, explicit hole and a hole at the end
+        add(&mut fs, 261, 0o100644, 20000, 1, b"sparse");
+        let extent = image.extent(&[0xff; 4096], 4096, 1);
+        fs.push((key(261, EXTENT_DATA, 0), regular(2, 0, extent, 0, 4096)));
+        let extent = image.extent(&pattern(b"0123456789", 10000)[..8192], 8192, 1);
+        fs.push((key(261, EXTENT_DATA, 8192), regular(1, 0, extent, 100, 4096)));
+        fs.push((key(261, EXTENT_DATA, 12288), regular(1, 0, [0, 0, 4096], 0, 4096)));
+
+        add(&mut fs, 262, 0o100644, 5000, 1, b"part");
+        fs.push((key(262, EXTENT_DATA, 0), regular(1, 3, zstd, 100, 5000)));
+
+        add(&mut fs, 263, 0o100644, 40, 1, b"tiny");
+        fs.push((key(263, EXTENT_DATA, 0), inline(1, 40, &TINY)));
+
+        add(&mut fs, 264, 0o40755, 0, 1, b"sub");
+        fs.push((file(265), inode(0o120777, 8, 1)));
+        fs.push((key(265, EXTENT_DATA, 0), inline(0, 8, b"../hello")));
+        entry(&mut fs, 264, 2, file(257), 1, b"link");
+        entry(&mut fs, 264, 3, file(265), 7, b"sym");
+
+        entry(&mut fs, 256, index, key(256, ROOT_ITEM, u64::MAX), 2, b"vol");
+        let fs_root = image.tree(FS_TREE, fs, 5);
+        assert_eq!(fs_root.level, 1);
+
+        let mut vol = Vec::new();
+        vol.push((file(256), inode(0o40755, 0, 1)));
+        vol.push((file(257), inode(0o100644, 6, 1)));
+        vol.push((key(257, EXTENT_DATA, 0), inline(0, 6, b"inner\n")));
+        entry(&mut vol, 256, 2, file(257), 1, b"inner");
+        let vol_root = image.tree(FIRST_FREE, vol, 100);
+
+        let extents = core::mem::take(&mut image.extents);
+        let extent_root = image.tree(EXTENT_TREE, extents, 100);
+        let roots = [(EXTENT_TREE, extent_root), (FS_TREE, fs_root), (FIRST_FREE, vol_root)]
+            .map(|(id, root)| (key(id, ROOT_ITEM, 0), root_item(root)));
+        let root = image.tree(1, roots.to_vec(), 100);
+
+        // the data chunk is only in the chunk tree and the image is the second device of a mirror
+        let sys = chunk(2 | 32, SYS[2], &[(1, SYS[1]), (1, SYS[1] + SYS[2])]);
+        let data = chunk(1 | 4 | 16, DATA[2], &[(2, 0), (1, DATA[1])]);
+        let chunks = [
+            (key(FIRST_FREE, CHUNK_ITEM, SYS[0]), sys.clone()),
+            (key(FIRST_FREE, CHUNK_ITEM, DATA[0]), data),
+        ];
+        image.leaf(SYS[0], 3, &chunks);
+
+        let mut sb = vec![0; 4096];
+        sb[0x40..0x48].copy_from_slice(MAGIC);
+        for (pos, value) in [(0x48, 7), (0x50, root.bytenr), (0x58, SYS[0]), (0x88, 1), (0xc9, 1)] {
+            put(&mut sb, pos, value, 8);
+        }
+        put(&mut sb, 0x90, 4096, 4);
+        put(&mut sb, 0x94, NODESIZE as u64, 4);
+        put(&mut sb, 0xa0, 17 + sys.len() as u64, 4);
+        put_key(&mut sb[0x32b..], &key(FIRST_FREE, CHUNK_ITEM, SYS[0]));
+        sb[0x32b + 17..0x32b + 17 + sys.len()].copy_from_slice(&sys);
+        let csum = !crc32c(!0, &sb[0x20..]);
+        put(&mut sb, 0, csum as u64, 4);
+        image.data[SUPERBLOCK as usize..SUPERBLOCK as usize + 4096].copy_from_slice(&sb);
+        image.data
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(!crc32c(!0, b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn inode_times() {
+        let mut buf = [0; inode::SIZE];
+        buf[112..120].copy_from_slice(&u64::MAX.to_le_bytes());
+        buf[120..124].copy_from_slice(&999_999_999u32.to_le_bytes());
+        buf[136..144].copy_from_slice(&10u64.to_le_bytes());
+        buf[144..148].copy_from_slice(&5u32.to_le_bytes());
+        let inode = inode::Inode::parse(&buf);
+        assert_eq!(inode.atime, -1_000_000_000 + 999_999_999);
+        assert_eq!(inode.mtime, 10_000_000_005);
+
+        buf[112..120].copy_from_slice(&(i64::MAX as u64).to_le_bytes());
+        assert_eq!(inode::Inode::parse(&buf).atime, i64::MAX);
+    }
+
+    #[test]
+    fn lzo1x() {
+        let src = [21, b'a', b'b', b'c', b'd', 236, 0, 50, 0x0c, 0, 0x11, 0, 0];
+        let mut out = [0; 64];
+        assert_eq!(lzo::lzo1x(&src, &mut out).unwrap(), 32);
+        assert_eq!(&out[..32], b"abcd".repeat(8));
+        assert!(lzo::lzo1x(&src[..12], &mut out).is_err());
+    }
+
+    #[test]
+    fn tree() {
+        let data = image();
+        let disk = ReadSlice(&data);
+        let mut scratch = vec![0; SCRATCH_SIZE];
+        let fs = Btrfs::new(&disk, &mut scratch).unwrap();
+        let root = fs.root().unwrap();
+
+        let mut names = Vec::new();
+        let mut dir = root.dir().unwrap();
+        let mut buf = [0; 16];
+        while let Some(entry) = dir.next(&mut buf).unwrap() {
+            names.push((
+                std::string::String::from_utf8_lossy(&buf[..entry.nlen]).into_owned(),
+                entry.typ,
+            ));
+            assert_eq!(root.open(entry.offset).unwrap().id(), entry.id);
+        }
+        let expected = ["hello", "zlib", "zstd", "lzo", "sparse", "part", "tiny", "sub", "vol"];
+        assert_eq!(names.iter().map(|x| x.0.as_str()).collect::<Vec<_>>(), expected);
+        assert_eq!(names[7].1, FileType::Directory);
+        assert_eq!(names[8].1, FileType::Directory);
+
+        let open = |path: &str| root.clone().lookup_path(path.as_bytes()).unwrap();
+        assert_eq!(read_all(&open("hello")), b"hello world\n");
+        assert_eq!(read_all(&open("zlib")), pattern(b"0123456789", 10000));
+        assert_eq!(read_all(&open("zstd")), pattern(b"abcdefghij", 10000));
+        assert_eq!(read_all(&open("lzo")), pattern(b"ABCDEFGHIJ", 10000));
+        assert_eq!(read_all(&open("part")), &pattern(b"abcdefghij", 10000)[100..5100]);
+        assert_eq!(read_all(&open("tiny")), b"tiny ".repeat(8));
+
+        let mut sparse = vec![0; 20000];
+        sparse[8192..12288].copy_from_slice(&pattern(b"0123456789", 10000)[100..4196]);
+        assert_eq!(read_all(&open("sparse")), sparse);
+
+        let sym = open("sub/sym");
+        assert_eq!(sym.ftype(), FileType::SymLink);
+        assert_eq!(read_all(&sym), b"../hello");
+        let id = |f: &file::File<_>| f.attr().get(attr::ID, &mut []).and_then(|x| x.as_u64());
+        assert_eq!(id(&open("hello")), id(&open("sub/link")));
+
+        let inner = open("vol/inner");
+        assert_eq!(read_all(&inner), b"inner\n");
+        assert_eq!(inner.ino(), (FIRST_FREE, 257));
+        assert_ne!(id(&inner), id(&open("hello")));
+        assert!(root.clone().lookup(b"missing").unwrap().is_none());
+
+        assert!(open("part").shared().unwrap());
+        assert!(!open("zlib").shared().unwrap());
+    }
+}
@@ -0,0 +1,125 @@
+//! Decompress LZO1X as framed by btrfs.
+
+use super::le32;
+use ap_storage::{msg2err, Error};
+
+/// The distance of the long matches.
+const M2_MAX_OFFSET: usize = 0x800;
+const M3_MAX_OFFSET: usize = 0x4000;
+
+/// Read a byte.
+fn byte(src: &[u8], pos: &mut usize) -> Result<usize, Error> {
+    let byte = *src.get(*pos).ok_or(msg2err!("truncated lzo block"))?;
+    *pos += 1;
+    Ok(byte as usize)
+}
+
+/// Read a length that continues with zero bytes counting 255 each.
+fn length(src: &[u8], pos: &mut usize, base: usize) -> Result<usize, Error> {
+    let mut len = base;
+    loop {
+        match byte(src, pos)? {
+            0 => len += 255,
+            x => return Ok(len + x),
+        }
+    }
+}
+
+/// Copy literals from the input.
+fn literals(src: &[u8], pos: &mut usize, out: &mut [u8], written: &mut usize, n: usize) -> Result<(), Error> {
+    let literals = src.get(*pos..*pos + n).ok_or(msg2err!("truncated lzo block"))?;
+    out.get_mut(*written..*written + n)
+        .ok_or(msg2err!("output too small"))?
+        .copy_from_slice(literals);
+    *pos += n;
+    *written += n;
+    Ok(())
+}
+
+/// Decompress a raw LZO1X block and return the bytes written.
+pub(crate) fn lzo1x(src: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let (mut pos, mut written) = (0, 0);
+
+    // the number of literals after the previous instruction or 4 after a literal run
+    let mut state = 0;
+    if src.first().is_some_and(|x| *x > 17) {
+        let n = byte(src, &mut pos)? - 17;
+        literals(src, &mut pos, out, &mut written, n)?;
+        state = if n < 4 { n } else { 4 };
+    }
+    loop {
+        let t = byte(src, &mut pos)?;
+        let (distance, n, next) = match t {
+            0..=15 if state == 0 => {
+                let n = if t == 0 { length(src, &mut pos, 15)? } else { t };
+                literals(src, &mut pos, out, &mut written, n + 3)?;
+                state = 4;
+                continue;
+            }
+            0..=15 if state < 4 => (1 + (t >> 2) + (byte(src, &mut pos)? << 2), 2, t & 3),
+            0..=15 => (1 + M2_MAX_OFFSET + (t >> 2) + (byte(src, &mut pos)? << 2), 3, t & 3),
+            64.. => (1 + ((t >> 2) & 7) + (byte(src, &mut pos)? << 3), (t >> 5) + 1, t & 3),
+            32.. => {
+                let n = 2 + if t & 31 == 0 {
+                    length(src, &mut pos, 31)?
+                } else {
+                    t & 31
+                };
+                let word = byte(src, &mut pos)? | byte(src, &mut pos)? << 8;
+                (1 + (word >> 2), n, word & 3)
+            }
+            _ => {
+                let n = 2 + if t & 7 == 0 { length(src, &mut pos, 7)? } else { t & 7 };
+                let word = byte(src, &mut pos)? | byte(src, &mut pos)? << 8;
+                let distance = ((t & 8) << 11) + (word >> 2);
+                if distance == 0 {
+                    if n != 3 || pos != src.len() {
+                        return Err(msg2err!("invalid lzo end"));
+                    }
+                    return Ok(written);
+                }
+                (distance + M3_MAX_OFFSET, n, word & 3)
+            }
+        };
+        if distance > written {
+            return Err(msg2err!("offset out of range"));
+        }
+        if written + n > out.len() {
+            return Err(msg2err!("output too small"));
+        }
+        for i in written..written + n {
+            out[i] = out[i - distance];
+        }
+        written += n;
+        literals(src, &mut pos, out, &mut written, next)?;
+        state = next;
+    }
+}
+
+/// Decompress the segments of a btrfs extent that each hold up to a sector.
+///
+/// The extent starts with its total length and every segment with its own.  A segment length never crosses a
+/// sector, so the tail of a sector is skipped if it is too short.
+pub(crate) fn decompress(src: &[u8], out: &mut [u8], sector: usize) -> Result<usize, Error> {
+    if src.len() < 4 {
+        return Err(msg2err!("truncated lzo extent"));
+    }
+    let total = le32(src, 0) as usize;
+    let src = src.get(..total).ok_or(msg2err!("truncated lzo extent"))?;
+    let (mut pos, mut written) = (4, 0);
+    while pos < total && written < out.len() {
+        if sector - pos % sector < 4 {
+            pos += sector - pos % sector;
+            continue;
+        }
+        let size = src.get(pos..pos + 4).ok_or(msg2err!("truncated lzo extent"))?;
+        let size = le32(size, 0) as usize;
+        let segment = src
+            .get(pos + 4..pos + 4 + size)
+            .ok_or(msg2err!("truncated lzo extent"))?;
+        let end = core::cmp::min(out.len(), written + sector);
+        written += lzo1x(segment, &mut out[written..end])?;
+        pos += 4 + size;
+    }
+    Ok(written)
+}
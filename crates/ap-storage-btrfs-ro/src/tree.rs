@@ -0,0 +1,138 @@
+//! Searching the B-trees of btrfs.
+
+use super::{le32, le64, Btrfs};
+use ap_storage::{msg2err, Error, Offset, Read, ReadExt};
+
+/// The most levels of a tree.
+pub(crate) const MAX_LEVEL: usize = 8;
+
+/// The size of the node header.
+const HEADER: usize = 101;
+
+/// The size of an item in a leaf and of a pointer in an internal node.
+const ITEM: usize = 25;
+const PTR: usize = 33;
+
+/// The key of an item.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key {
+    pub objectid: u64,
+    pub typ: u8,
+    pub offset: u64,
+}
+
+impl Key {
+    pub const fn new(objectid: u64, typ: u8, offset: u64) -> Self {
+        Self { objectid, typ, offset }
+    }
+
+    pub(crate) fn parse(buf: &[u8]) -> Self {
+        Self::new(le64(buf, 0), buf[8], le64(buf, 9))
+    }
+}
+
+/// The root node of a tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Root {
+    pub bytenr: u64,
+    pub level: u8,
+}
+
+/// An item in a leaf.
+#[derive(Debug, Clone, Copy)]
+pub struct Item {
+    pub key: Key,
+    /// The disk position of the data.
+    pub pos: Offset,
+    pub size: u32,
+}
+
+impl<'a, D: Read + ?Sized> Btrfs<'a, D> {
+    /// Check the header of a node and return its disk position and the number of items.
+    fn node(&self, bytenr: u64, level: u8) -> Result<(Offset, usize), Error> {
+        let nodesize = self.sb.nodesize as usize;
+        let (pos, left) = self.map(bytenr)?;
+        if left < nodesize as u64 {
+            return Err(msg2err!("node crosses a chunk"));
+        }
+        let header: [u8; HEADER] = self.disk().read_object(pos)?;
+        let count = le32(&header, 0x60) as usize;
+        let size = if level == 0 { ITEM } else { PTR };
+        if le64(&header, 0x30) != bytenr || header[0x64] != level || HEADER + count * size > nodesize {
+            return Err(msg2err!("invalid tree node"));
+        }
+        Ok((pos, count))
+    }
+
+    /// Read a slot of a node.
+    fn slot(&self, pos: Offset, level: u8, index: usize) -> Result<[u8; PTR], Error> {
+        let mut buf = [0; PTR];
+        let size = if level == 0 { ITEM } else { PTR };
+        let pos = pos + (HEADER + index * size) as Offset;
+        self.disk().read_exact(pos, &mut buf[..size])?;
+        Ok(buf)
+    }
+
+    /// The number of slots with a key smaller than the key or not larger if `equal` is set.
+    fn partition(&self, pos: Offset, level: u8, count: usize, key: &Key, equal: bool) -> Result<usize, Error> {
+        let (mut lo, mut hi) = (0, count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let other = Key::parse(&self.slot(pos, level, mid)?);
+            if other < *key || (equal && other == *key) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(lo)
+    }
+
+    /// The item of a leaf slot.
+    fn item(&self, pos: Offset, index: usize) -> Result<Item, Error> {
+        let buf = self.slot(pos, 0, index)?;
+        let (offset, size) = (le32(&buf, 17), le32(&buf, 21));
+        if HEADER as u64 + offset as u64 + size as u64 > self.sb.nodesize as u64 {
+            return Err(msg2err!("item outside the node"));
+        }
+        Ok(Item {
+            key: Key::parse(&buf),
+            pos: pos + HEADER as Offset + offset as Offset,
+            size,
+        })
+    }
+
+    /// Search a node for the last item not larger than the key or the first one not smaller.
+    fn search(&self, bytenr: u64, level: u8, key: &Key, ceil: bool) -> Result<Option<Item>, Error> {
+        let (pos, count) = self.node(bytenr, level)?;
+        if level == 0 {
+            let index = self.partition(pos, 0, count, key, !ceil)?;
+            return match ceil {
+                true if index < count => self.item(pos, index).map(Some),
+                false if index > 0 => self.item(pos, index - 1).map(Some),
+                _ => Ok(None),
+            };
+        }
+
+        // the key of a pointer is the smallest key in the child
+        let index = self.partition(pos, level, count, key, true)?.saturating_sub(1);
+        for index in index..count {
+            let child = le64(&self.slot(pos, level, index)?, 17);
+            let res = self.search(child, level - 1, key, ceil)?;
+            if res.is_some() || !ceil {
+                return Ok(res);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Search the last item with a key not larger than the key.
+    pub fn floor(&self, root: Root, key: &Key) -> Result<Option<Item>, Error> {
+        self.search(root.bytenr, root.level, key, false)
+    }
+
+    /// Search the first item with a key not smaller than the key.
+    pub fn ceil(&self, root: Root, key: &Key) -> Result<Option<Item>, Error> {
+        self.search(root.bytenr, root.level, key, true)
+    }
+}
@@ -0,0 +1,74 @@
+//! File attributes for cpio archives.
+
+use super::file::File;
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::Read;
+use ap_util_slice_writer::*;
+
+new_attr!(INO, U64, "Inode number in the archive.");
+new_attr!(NLINKS, U64, "Number of hard-links to this file.");
+
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
+}
+
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            INO,
+            NLINKS,
+            attr::FTYPE,
+            attr::GID,
+            attr::ID,
+            attr::MODE,
+            attr::MTIME,
+            attr::RDEV,
+            attr::SIZE,
+            attr::UID,
+        ]
+        .iter()
+    }
+}
+
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let header = &self.file.header;
+        Some(match name {
+            INO => (header.ino as u64).into(),
+            NLINKS => (header.nlink as u64).into(),
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            attr::GID => (header.gid as u64).into(),
+            attr::ID => self.file.id.into(),
+            attr::MODE => (header.mode as u64 & 0o7777).into(),
+            attr::MTIME => Value::Time(header.mtime as i64 * 1_000_000_000),
+            attr::RDEV => ((header.rdevmajor as u64) << 32 | header.rdevminor as u64).into(),
+            attr::SIZE => self.file.size.into(),
+            attr::UID => (header.uid as u64).into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                INO,
+                NLINKS,
+                attr::FTYPE,
+                attr::GID,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::RDEV,
+                attr::SIZE,
+                attr::UID,
+            ]
+        )
+    }
+}
@@ -0,0 +1,52 @@
+//! Directory iteration for cpio archives.
+
+use super::{file, file::File, PATH_MAX};
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    path::parent,
+    Error, Offset, Read,
+};
+
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
+    /// The position of the next header.
+    pos: Offset,
+}
+
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Self {
+        Self { file, pos: 0 }
+    }
+}
+
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        let fs = self.file.fs;
+        let mut dir = [0; PATH_MAX];
+        let dlen = self.file.path(&mut dir)?;
+        let dir = &dir[..dlen];
+        let mut buf = [0; PATH_MAX];
+        // children can be anywhere in the archive
+        while let Some(entry) = fs.member(self.pos, &mut buf)? {
+            self.pos = entry.next;
+            let path = &buf[..entry.nlen];
+            if path.is_empty() || parent(path) != dir {
+                continue;
+            }
+            if fs.find(entry.next, path)?.is_some() {
+                continue;
+            }
+            let child = &path[parent(path).len()..];
+            let child = child.strip_prefix(b"/").unwrap_or(child);
+            let nlen = core::cmp::min(child.len(), name.len());
+            name[..nlen].copy_from_slice(&child[..nlen]);
+            return Ok(Some(DirEntry {
+                offset: entry.pos,
+                id: fs.resolve(&entry)?.pos,
+                nlen,
+                typ: file::file_type(entry.header.mode),
+            }));
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,174 @@
+//! Files in cpio archives.
+
+use super::{attr::Attr, dir::Dir, CpioFs, Entry, Header, PATH_MAX};
+use ap_storage::{file::FileType, msg2err, Error, Offset, Read};
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a CpioFs<'a, D>,
+    /// The position of the header or None for an implied root.
+    pub(crate) pos: Option<Offset>,
+    pub(crate) header: Header,
+    /// The header position of the link with the data.
+    pub(crate) id: Offset,
+    pub(crate) data: Offset,
+    pub(crate) size: u64,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            pos: self.pos,
+            header: self.header,
+            id: self.id,
+            data: self.data,
+            size: self.size,
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("pos", &self.pos)
+            .field("header", &self.header)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// The file type of a mode.
+pub(crate) fn file_type(mode: u32) -> FileType {
+    match mode & 0o170000 {
+        0o100000 => FileType::File,
+        0o040000 => FileType::Directory,
+        0o120000 => FileType::SymLink,
+        0o020000 => FileType::CharDevice,
+        0o060000 => FileType::BlockDevice,
+        0o010000 => FileType::Fifo,
+        0o140000 => FileType::Socket,
+        _ => FileType::Unknown,
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    pub(crate) fn new(fs: &'a CpioFs<'a, D>, entry: &Entry) -> Result<Self, Error> {
+        let link = fs.resolve(entry)?;
+        Ok(Self {
+            fs,
+            pos: Some(entry.pos),
+            header: entry.header,
+            id: link.pos,
+            data: link.data,
+            size: link.header.filesize as u64,
+        })
+    }
+
+    /// The root directory of an archive without a `.` member.
+    pub(crate) fn root(fs: &'a CpioFs<'a, D>) -> Self {
+        Self {
+            fs,
+            pos: None,
+            header: Header {
+                mode: 0o040755,
+                nlink: 2,
+                ..Default::default()
+            },
+            id: Offset::MAX,
+            data: 0,
+            size: 0,
+        }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn ftype(&self) -> FileType {
+        file_type(self.header.mode)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.ftype() == FileType::Directory
+    }
+
+    /// Read the normalized path and return its length.
+    pub fn path(&self, buf: &mut [u8; PATH_MAX]) -> Result<usize, Error> {
+        let Some(pos) = self.pos else {
+            return Ok(0);
+        };
+        Ok(self.fs.entry(pos, buf)?.ok_or(msg2err!("no header"))?.nlen)
+    }
+
+    /// Verify the checksum of the crc format.  Returns None for the newc format.
+    pub fn verify(&self) -> Result<Option<bool>, Error> {
+        if !self.header.crc {
+            return Ok(None);
+        }
+        let mut buf = [0; 512];
+        let (mut pos, mut sum) = (0, 0u32);
+        while pos < self.size {
+            let n = self.read_bytes(pos, &mut buf)?;
+            if n == 0 {
+                return Err(msg2err!("truncated data"));
+            }
+            sum = buf[..n].iter().fold(sum, |sum, x| sum.wrapping_add(*x as u32));
+            pos += n as Offset;
+        }
+        Ok(Some(sum == self.header.check))
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.is_dir() {
+            return Some(Dir::new(self));
+        }
+        None
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        self.fs.open(offset)
+    }
+
+    /// Search the archive for the path instead of listing the directory.
+    fn lookup(&self, name: &[u8]) -> Result<Option<Self>, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        let mut path = [0; PATH_MAX];
+        let mut len = self.path(&mut path)?;
+        if len != 0 {
+            path[len] = b'/';
+            len += 1;
+        }
+        let end = len + name.len();
+        if name.is_empty() || name.contains(&b'/') || end > path.len() {
+            return Ok(None);
+        }
+        path[len..end].copy_from_slice(name);
+        match self.fs.find(0, &path[..end])? {
+            Some(pos) => Ok(Some(self.fs.open(pos)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if offset >= self.size || !matches!(self.ftype(), FileType::File | FileType::SymLink) {
+            return Ok(0);
+        }
+        let max_n = core::cmp::min(buf.len() as Offset, self.size - offset) as usize;
+        self.fs.disk().read_bytes(self.data + offset, &mut buf[..max_n])
+    }
+}
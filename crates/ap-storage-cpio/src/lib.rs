@@ -0,0 +1,338 @@
+//! Browse cpio archives in the newc and crc format.
+//!
+//! - directories are built by comparing the paths of all members
+//! - a later member replaces an earlier one with the same path
+//! - concatenated archives as used by initramfs images
+//! - hard links share the data of the last link with the same inode
+//!
+//! Nothing is cached, so every directory listing walks the whole archive.  The offset of a directory entry is the
+//! position of its header.
+
+#![no_std]
+
+mod attr;
+mod dir;
+pub mod file;
+
+use ap_storage::{msg2err, path::normalize, Error, FileSystem, Offset, Read, ReadExt};
+
+/// The longest path of a member.
+pub(crate) const PATH_MAX: usize = 4096;
+
+/// The size of a header without the name.
+const HEADER_SIZE: usize = 110;
+
+/// The name of the last member of an archive.
+const TRAILER: &[u8] = b"TRAILER!!!";
+
+/// A newc header.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Header {
+    /// The header is in the crc format with a checksum of the data.
+    pub crc: bool,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub mtime: u32,
+    pub filesize: u32,
+    pub devmajor: u32,
+    pub devminor: u32,
+    pub rdevmajor: u32,
+    pub rdevminor: u32,
+    pub namesize: u32,
+    pub check: u32,
+}
+
+impl Header {
+    /// Parse the hexadecimal fields after the magic.
+    pub fn parse(buf: &[u8; HEADER_SIZE]) -> Result<Self, Error> {
+        let crc = match &buf[..6] {
+            b"070701" => false,
+            b"070702" => true,
+            _ => return Err(msg2err!("invalid cpio magic")),
+        };
+        let mut fields = [0; 13];
+        for (i, field) in fields.iter_mut().enumerate() {
+            let hex = core::str::from_utf8(&buf[6 + i * 8..14 + i * 8]).map_err(|e| msg2err!(e))?;
+            *field = u32::from_str_radix(hex, 16).map_err(|e| msg2err!(e))?;
+        }
+        let [ino, mode, uid, gid, nlink, mtime, filesize, devmajor, devminor, rdevmajor, rdevminor, namesize, check] =
+            fields;
+        Ok(Self {
+            crc,
+            ino,
+            mode,
+            uid,
+            gid,
+            nlink,
+            mtime,
+            filesize,
+            devmajor,
+            devminor,
+            rdevmajor,
+            rdevminor,
+            namesize,
+            check,
+        })
+    }
+
+    /// Whether the member shares its data with other links.
+    pub(crate) fn is_link(&self) -> bool {
+        self.nlink > 1 && self.mode & 0o170000 != 0o040000
+    }
+}
+
+/// A member found in the archive.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Entry {
+    /// The position of the header.
+    pub(crate) pos: Offset,
+    pub(crate) header: Header,
+    /// The length of the normalized path.
+    pub(crate) nlen: usize,
+    pub(crate) data: Offset,
+    /// The position of the following header.
+    pub(crate) next: Offset,
+    pub(crate) trailer: bool,
+}
+
+/// Round up to the alignment of headers and data.
+fn align(pos: Offset) -> Offset {
+    pos.next_multiple_of(4)
+}
+
+/// A cpio archive.
+pub struct CpioFs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+}
+
+impl<D: ?Sized> core::fmt::Debug for CpioFs<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(fmt, "CpioFs")
+    }
+}
+
+impl<'a, D: Read + ?Sized> CpioFs<'a, D> {
+    /// Open an archive by checking its first header.
+    pub fn new(disk: &'a D) -> Result<Self, Error> {
+        Header::parse(&(&disk as &dyn Read).read_object(0)?)?;
+        Ok(Self { disk })
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    /// Read the header at a position and its normalized name.  Returns None at the end of the disk.
+    pub(crate) fn entry(&self, pos: Offset, name: &mut [u8]) -> Result<Option<Entry>, Error> {
+        let mut buf = [0; HEADER_SIZE];
+        let n = self.disk.read_bytes(pos, &mut buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if n < buf.len() {
+            self.disk().read_exact(pos + n as Offset, &mut buf[n..])?;
+        }
+        let header = Header::parse(&buf)?;
+        let namesize = header.namesize as usize;
+        if namesize == 0 || namesize > name.len() {
+            return Err(msg2err!("invalid name size"));
+        }
+        let name = &mut name[..namesize];
+        self.disk().read_exact(pos + HEADER_SIZE as Offset, name)?;
+        let trailer = &name[..namesize - 1] == TRAILER;
+        let data = align(pos + (HEADER_SIZE + namesize) as Offset);
+        Ok(Some(Entry {
+            pos,
+            header,
+            nlen: normalize(&mut name[..namesize - 1]),
+            data,
+            next: align(data + header.filesize as Offset),
+            trailer,
+        }))
+    }
+
+    /// Find the next archive after the zero padding of a trailer.
+    fn skip_padding(&self, mut pos: Offset) -> Result<Option<Offset>, Error> {
+        let mut buf = [0; 512];
+        loop {
+            let n = self.disk.read_bytes(pos, &mut buf)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            if let Some(i) = buf[..n].iter().position(|x| *x != 0) {
+                // trailing garbage like a compressed archive ends the walk
+                let pos = pos + i as Offset;
+                let mut magic = [0; 5];
+                if !pos.is_multiple_of(4) || self.disk.read_bytes(pos, &mut magic)? != 5 || &magic != b"07070" {
+                    return Ok(None);
+                }
+                return Ok(Some(pos));
+            }
+            pos += n as Offset;
+        }
+    }
+
+    /// Read the member at or after a position and continue into concatenated archives.
+    pub(crate) fn member(&self, mut pos: Offset, name: &mut [u8]) -> Result<Option<Entry>, Error> {
+        loop {
+            let Some(entry) = self.entry(pos, name)? else {
+                return Ok(None);
+            };
+            if !entry.trailer {
+                return Ok(Some(entry));
+            }
+            let Some(next) = self.skip_padding(entry.next)? else {
+                return Ok(None);
+            };
+            pos = next;
+        }
+    }
+
+    /// Find the last member with the normalized path starting at a position.
+    pub(crate) fn find(&self, mut pos: Offset, path: &[u8]) -> Result<Option<Offset>, Error> {
+        let mut res = None;
+        let mut name = [0; PATH_MAX];
+        while let Some(entry) = self.member(pos, &mut name)? {
+            if &name[..entry.nlen] == path {
+                res = Some(entry.pos);
+            }
+            pos = entry.next;
+        }
+        Ok(res)
+    }
+
+    /// Find the last link to the same inode before the end of the archive, as it carries the data.
+    pub(crate) fn resolve(&self, entry: &Entry) -> Result<Entry, Error> {
+        let mut res = *entry;
+        if !entry.header.is_link() {
+            return Ok(res);
+        }
+        let mut pos = entry.next;
+        let mut name = [0; PATH_MAX];
+        while let Some(next) = self.entry(pos, &mut name)? {
+            if next.trailer {
+                break;
+            }
+            let (a, b) = (&next.header, &entry.header);
+            if (a.ino, a.devmajor, a.devminor) == (b.ino, b.devmajor, b.devminor) {
+                res = next;
+            }
+            pos = next.next;
+        }
+        Ok(res)
+    }
+
+    /// Open the member at a header position.
+    pub fn open(&'a self, pos: Offset) -> Result<file::File<'a, D>, Error> {
+        let mut name = [0; PATH_MAX];
+        let entry = self.entry(pos, &mut name)?.ok_or(msg2err!("no header"))?;
+        file::File::new(self, &entry)
+    }
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for CpioFs<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        match self.find(0, b"")? {
+            Some(pos) => self.open(pos),
+            None => Ok(file::File::root(self)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{
+        attr::{self, Attributes},
+        directory::DirIterator,
+        file::File,
+    };
+    use ap_storage_memory::ReadSlice;
+    use std::vec::Vec;
+
+    /// Append a member with the given magic.
+    fn append(cpio: &mut Vec<u8>, magic: &str, name: &str, ino: u32, mode: u32, nlink: u32, data: &[u8]) {
+        let check: u32 = data.iter().map(|x| *x as u32).sum();
+        let fields = [
+            ino,
+            mode,
+            0,
+            0,
+            nlink,
+            0,
+            data.len() as u32,
+            0,
+            0,
+            0,
+            0,
+            name.len() as u32 + 1,
+            check,
+        ];
+        cpio.extend_from_slice(magic.as_bytes());
+        for field in fields {
+            cpio.extend_from_slice(std::format!("{:08x}", field).as_bytes());
+        }
+        cpio.extend_from_slice(name.as_bytes());
+        cpio.push(0);
+        cpio.resize(cpio.len().next_multiple_of(4), 0);
+        cpio.extend_from_slice(data);
+        cpio.resize(cpio.len().next_multiple_of(4), 0);
+    }
+
+    fn read_all(file: &impl Read) -> Vec<u8> {
+        let mut res = std::vec![0; 100];
+        let n = file.read_bytes(0, &mut res).unwrap();
+        res.truncate(n);
+        res
+    }
+
+    #[test]
+    fn concatenated() {
+        let mut cpio = Vec::new();
+        append(&mut cpio, "070701", ".", 1, 0o040700, 2, b"");
+        append(&mut cpio, "070701", "bin", 2, 0o040755, 2, b"");
+        append(&mut cpio, "070701", "bin/sh", 3, 0o100755, 2, b"");
+        append(&mut cpio, "070701", "bin/ash", 3, 0o100755, 2, b"elf");
+        append(&mut cpio, "070701", "TRAILER!!!", 0, 0, 1, b"");
+        cpio.resize(cpio.len().next_multiple_of(512), 0);
+        append(&mut cpio, "070702", "./bin", 3, 0o040711, 2, b"");
+        append(&mut cpio, "070702", "./etc", 5, 0o040755, 2, b"");
+        append(&mut cpio, "070702", "./etc/init", 4, 0o100644, 1, b"#!/bin/sh");
+        append(&mut cpio, "070702", "TRAILER!!!", 0, 0, 1, b"");
+
+        let disk = ReadSlice(&cpio);
+        let fs = CpioFs::new(&disk).unwrap();
+        let root = fs.root().unwrap();
+        let mode = |f: &file::File<_>| f.attr().get(attr::MODE, &mut []).and_then(|x| x.as_u64());
+        assert_eq!(mode(&root), Some(0o700));
+
+        // the later directory replaces the first one
+        let mut dir = root.dir().unwrap();
+        let mut name = [0; 16];
+        let mut names = Vec::new();
+        while let Some(entry) = dir.next(&mut name).unwrap() {
+            names.push(name[..entry.nlen].to_vec());
+        }
+        assert_eq!(names, [&b"bin"[..], b"etc"]);
+        let bin = root.lookup(b"bin").unwrap().unwrap();
+        assert_eq!(mode(&bin), Some(0o711));
+
+        // hard links share the data of the last link
+        let sh = bin.lookup(b"sh").unwrap().unwrap();
+        let ash = bin.lookup(b"ash").unwrap().unwrap();
+        assert_eq!(read_all(&sh), b"elf");
+        let id = |f: &file::File<_>| f.attr().get(attr::ID, &mut []).and_then(|x| x.as_u64());
+        assert_eq!(id(&sh), id(&ash));
+        assert_eq!(sh.verify().unwrap(), None);
+
+        let init = root.lookup_path(b"etc/init").unwrap();
+        assert_eq!(read_all(&init), b"#!/bin/sh");
+        assert_eq!(init.verify().unwrap(), Some(true));
+    }
+}
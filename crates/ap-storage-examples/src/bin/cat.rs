@@ -47,7 +47,8 @@ impl core::str::FromStr for MaxSize {
 fn main() -> Result<(), Error> {
     let opts = CommandOptions::parse_args_default_or_exit();
     let disk = LinuxDiskRO::new("/dev/stdin", opts.offset)?;
-    let fs = ap_storage_unified::UnifiedFs::new(&disk).ok_or(msg2err!("no filesystem found"))?;
+    let mut scratch = vec![0; ap_storage_unified::SCRATCH_SIZE];
+    let fs = ap_storage_unified::UnifiedFs::with_scratch(&disk, &mut scratch).ok_or(msg2err!("no filesystem found"))?;
     let start = &opts.start;
     let file = fs.root()?.lookup_path(start.as_bytes())?;
 
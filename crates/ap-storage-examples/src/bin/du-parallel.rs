@@ -4,15 +4,15 @@ use al_crunch_pool::{execute, Options, Sender};
 use al_mmap::Mmap;
 use ap_storage::{
     attr::{Attributes, ID, SIZE},
-    directory::DirIterator,
     file::File,
     file::FileType,
-    Error, FileSystem,
+    walk::{self, Entry, Frame, Walker},
+    Error, FileSystem, Read,
 };
 use ap_storage_ext4_ro::{file::Ext4File, Ext4Fs};
+use ap_storage_linux::UringDisk;
 use ap_storage_memory::ReadSlice;
 use gumdrop::Options as GumdropOptions;
-use std::rc::Rc;
 
 #[derive(Debug, GumdropOptions)]
 struct CommandOptions {
@@ -22,6 +22,13 @@ struct CommandOptions {
     /// Direct acccess.
     no_direct: bool,
 
+    /// Read with io_uring instead of a mapping.
+    uring: bool,
+
+    /// Number of io_uring entries.
+    #[options(default = "64")]
+    entries: u32,
+
     /// Leaf optimization
     leaf_optimization: bool,
 
@@ -41,43 +48,61 @@ struct CommandOptions {
     start: String,
 }
 
+/// A disk that can be shared between the workers.
+type SyncDisk = dyn Read + Sync;
+
 /// The state every worker keeps on its own.
 pub struct WorkerState {
-    fs: Rc<Ext4Fs<'static>>,
+    fs: &'static Ext4Fs<'static, SyncDisk>,
     size: u64,
     count: usize,
 }
 
 fn visit(sender: &Sender<WorkerState>, nr: u64, worker: &mut WorkerState) {
-    let fs = Rc::clone(&worker.fs);
-    let dir = Ext4File::new(&fs, nr).unwrap();
-    let Some(mut iter) = dir.dir() else { return };
+    let fs = worker.fs;
+    let dir = Ext4File::new(fs, nr).unwrap();
 
-    while let Ok(Some(entry)) = iter.next(&mut []) {
-        if matches!(entry.typ, FileType::Unknown | FileType::Parent) {
-            continue;
-        }
-        worker.count += 1;
-
-        let Ok(child) = dir.open(entry.offset) else {
-            continue;
-        };
-        worker.size += child.attr().get(SIZE, &mut []).and_then(|x| x.as_u64()).unwrap_or(0);
+    // list a single directory and leave the subdirectories to other workers
+    let options = walk::Options {
+        max_depth: 1,
+        ..Default::default()
+    };
+    let mut stack = [Frame::EMPTY; 1];
+    let mut walker = Walker::new(options, &mut stack, &mut []);
 
-        if entry.typ == FileType::Directory {
+    // an error just ends the directory early
+    let _ = walker.walk(&dir, &mut |entry: &Entry<Ext4File<SyncDisk>>| {
+        worker.count += 1;
+        worker.size += entry
+            .file
+            .attr()
+            .get(SIZE, &mut [])
+            .and_then(|x| x.as_u64())
+            .unwrap_or(0);
+
+        if entry.dirent.typ == FileType::Directory {
             let sender2 = sender.clone();
+            let id = entry.dirent.id;
             sender.send(worker, move |state| {
-                visit(&sender2, entry.id, state);
+                visit(&sender2, id, state);
             });
         }
-    }
+        Ok(false)
+    });
 }
 
 fn main() -> Result<(), Error> {
     let opts = CommandOptions::parse_args_default_or_exit();
-    let mmap = Mmap::new("/dev/stdin", !opts.no_direct, 0, 0)?;
-    let disk = ReadSlice(mmap.0);
-    let disk: &(dyn ap_storage::Read + Sync) = &disk;
+    // the disk and the filesystem live until the process exits
+    let disk: &'static SyncDisk = if opts.uring {
+        Box::leak(Box::new(UringDisk::new("/dev/stdin", 0, false, opts.entries)?))
+    } else {
+        let mmap: &'static Mmap = Box::leak(Box::new(Mmap::new("/dev/stdin", !opts.no_direct, 0, 0)?));
+        Box::leak(Box::new(ReadSlice(mmap.0)))
+    };
+
+    // all workers share the mounted filesystem
+    let fs: &'static Ext4Fs<SyncDisk> = Box::leak(Box::new(Ext4Fs::new(disk, opts.leaf_optimization)?));
 
     let options = Options::default()
         .one_is_zero()
@@ -86,14 +111,7 @@ fn main() -> Result<(), Error> {
         .slots(opts.slots);
 
     // a function to produce the state for every worker
-    let make_state = |_| {
-        WorkerState {
-            // XXX we don't handle the lifetimes correctly
-            fs: Rc::new(Ext4Fs::new(unsafe { std::mem::transmute(disk) }, opts.leaf_optimization).unwrap()),
-            size: 0,
-            count: 0,
-        }
-    };
+    let make_state = |_| WorkerState { fs, size: 0, count: 0 };
 
     // the bounded Job queue
     for _i in 0..opts.repeat {
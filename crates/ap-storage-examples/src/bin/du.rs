@@ -1,12 +1,15 @@
 //! Disk usage of the whole filesystem.
+//!
+//! Every link of a hard-linked file is counted, but its size only once.
+//! Directories nested deeper than 256 levels are reported as an error.
 
 use al_mmap::Mmap;
 use ap_storage::{
     attr::{Attributes, SIZE},
-    directory::DirIterator,
     file::File,
-    file::FileType,
-    msg2err, Error, FileSystem, Read,
+    msg2err,
+    walk::{Entry, Frame, Link, Walker},
+    Error, FileSystem, Read,
 };
 use ap_storage_linux::LinuxDiskRO;
 use ap_storage_memory::ReadSlice;
@@ -31,28 +34,21 @@ struct CommandOptions {
     start: String,
 }
 
-fn visit(dir: &impl File) -> Result<(usize, u64), Error> {
-    let Some(mut iter) = dir.dir() else {
-        return Ok((0, 0));
-    };
-
+/// Count the entries and sum up their sizes.
+fn visit<F: File>(dir: &F) -> Result<(usize, u64), Error> {
     let mut count = 0;
     let mut size = 0;
-
-    while let Some(entry) = iter.next(&mut [])? {
-        if matches!(entry.typ, FileType::Unknown | FileType::Parent) {
-            continue;
-        }
-
+    let mut stack = [Frame::EMPTY; 256];
+    let mut seen = vec![0; 1 << 20];
+    let mut walker = Walker::new(Default::default(), &mut stack, &mut seen);
+    walker.walk(dir, &mut |entry: &Entry<F>| {
         count += 1;
-        let child = dir.open(entry.offset)?;
-        size += child.attr().get(SIZE, &mut []).and_then(|x| x.as_u64()).unwrap_or(0);
-        if entry.typ == FileType::Directory {
-            let (x, y) = visit(&child)?;
-            count += x;
-            size += y;
+        // hard-linked files are only accounted once
+        if entry.link == Link::New {
+            size += entry.file.attr().get(SIZE, &mut []).and_then(|x| x.as_u64()).unwrap_or(0);
         }
-    }
+        Ok(true)
+    })?;
     Ok((count, size))
 }
 
@@ -63,7 +59,8 @@ fn main() -> Result<(), Error> {
     let disk_mmap = ReadSlice(mmap.0);
     let disk: &dyn Read = if opts.pread { &disk_pread } else { &disk_mmap };
 
-    let fs = ap_storage_unified::UnifiedFs::new(disk).ok_or(msg2err!("no filesystem found"))?;
+    let mut scratch = vec![0; ap_storage_unified::SCRATCH_SIZE];
+    let fs = ap_storage_unified::UnifiedFs::with_scratch(disk, &mut scratch).ok_or(msg2err!("no filesystem found"))?;
     let dir = fs.root()?.lookup_path(opts.start.as_bytes())?;
     let (count, size) = visit(&dir)?;
     println!("{}\t{}\t{}", opts.start, count, size);
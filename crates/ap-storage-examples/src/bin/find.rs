@@ -1,11 +1,16 @@
 //! Search for files in a file-system recursively.
+//!
+//! Hard-linked directories are listed every time but entered only once, and
+//! cycles are not entered at all.  Directories nested deeper than 256 levels
+//! are reported as an error.
 
 use ap_storage::{
     attr::{Attributes, Value},
-    directory::DirIterator,
     file::File,
     file::FileType,
-    msg2err, Error, FileSystem,
+    msg2err,
+    walk::{self, Entry, Frame, Visitor, Walker},
+    Error, FileSystem,
 };
 use ap_storage_linux::LinuxDiskRO;
 use gumdrop::Options;
@@ -35,53 +40,72 @@ struct CommandOptions {
     start: String,
 }
 
-fn visit(opts: &CommandOptions, f: &impl File, path: &String, depth: usize) -> Result<(), Error> {
-    let Some(mut iter) = f.dir() else {
-        return Ok(());
-    };
-    let mut name = [0u8; 256];
-    while let Some(entry) = iter.next(&mut name)? {
-        if entry.typ == FileType::Unknown || (!opts.all && entry.typ == FileType::Parent) {
-            continue;
-        }
-        let st = core::str::from_utf8(&name[..entry.nlen]).unwrap_or_default();
-        println!("{path}/{st}");
-        if opts.attr {
-            let f = f.open(entry.offset).unwrap();
-            let attr = f.attr();
+/// Print the entries while tracking the path.
+struct Printer<'a> {
+    opts: &'a CommandOptions,
+    path: String,
+}
+
+impl<F: File> Visitor<F> for Printer<'_> {
+    fn pre(&mut self, entry: &Entry<'_, F>) -> Result<bool, Error> {
+        let st = core::str::from_utf8(entry.name).unwrap_or_default();
+        println!("{}/{st}", self.path);
+        if self.opts.attr {
+            let attr = entry.file.attr();
             let mut value = [0u8; 256];
-            for name in f.attr() {
-                match attr.get(name, &mut value).unwrap() {
+            for name in entry.file.attr() {
+                let Some(val) = attr.get(name, &mut value) else {
+                    continue;
+                };
+                match val {
                     Value::U64(v) => println!("\t{name}\t{:#x}", v),
                     Value::I64(v) => println!("\t{name}\t{}", v),
                     Value::Bool(v) => println!("\t{name}\t{:?}", v),
-                    Value::Raw(count) => {
+                    Value::Time(v) => println!("\t{name}\t{}.{:09}", v.div_euclid(1_000_000_000), v.rem_euclid(1_000_000_000)),
+                    Value::Uuid(v) => println!("\t{name}\t{:02x?}", v),
+                    Value::Raw(count) => println!("\t{name}\t{:02x?}", &value[..core::cmp::min(value.len(), count)]),
+                    Value::Str(count) => {
                         let v = core::str::from_utf8(&value[..core::cmp::min(value.len(), count)]).unwrap_or_default();
                         println!("\t{name}\t{v}");
                     }
                 }
             }
         }
-        if opts.raw {
-            println!("\tentry\t{entry:?}\t")
+        if self.opts.raw {
+            println!("\tentry\t{:?}\t{:?}", entry.dirent, entry.link)
         }
-
-        if entry.typ == FileType::Directory && depth != 1 {
-            let mut child = path.clone();
-            child.push('/');
-            child.push_str(st);
-            let f = f.open(entry.offset).unwrap();
-            visit(opts, &f, &child, core::cmp::max(depth, 1) - 1)?;
+        if entry.dirent.typ == FileType::Directory {
+            self.path.push('/');
+            self.path.push_str(st);
         }
+        Ok(true)
+    }
+
+    fn post(&mut self, _entry: &Entry<'_, F>) -> Result<(), Error> {
+        let n = self.path.rfind('/').unwrap_or_default();
+        self.path.truncate(n);
+        Ok(())
     }
-    Ok(())
 }
 
 fn main() -> Result<(), Error> {
     let opts = CommandOptions::parse_args_default_or_exit();
     let disk = LinuxDiskRO::new("/dev/stdin", opts.offset)?;
-    let fs = ap_storage_unified::UnifiedFs::new(&disk).ok_or(msg2err!("no filesystem found"))?;
+    let mut scratch = vec![0; ap_storage_unified::SCRATCH_SIZE];
+    let fs = ap_storage_unified::UnifiedFs::with_scratch(&disk, &mut scratch).ok_or(msg2err!("no filesystem found"))?;
     let start = &opts.start;
     let child = fs.root()?.lookup_path(start.as_bytes())?;
-    visit(&opts, &child, &"".to_string(), opts.depth)
+
+    let options = walk::Options {
+        max_depth: opts.depth,
+        parents: opts.all,
+        ..Default::default()
+    };
+    let mut stack = [Frame::EMPTY; 256];
+    let mut seen = vec![0; 1 << 16];
+    let mut printer = Printer {
+        opts: &opts,
+        path: String::new(),
+    };
+    Walker::new(options, &mut stack, &mut seen).walk(&child, &mut printer)
 }
@@ -0,0 +1,51 @@
+//! Mount a filesystem read-only via FUSE.
+
+use ap_storage::{msg2err, Error, FileSystem};
+use ap_storage_fuse::{Mount, Node, Options, Server};
+use ap_storage_linux::LinuxDiskRO;
+use gumdrop::Options as GumdropOptions;
+
+#[derive(Debug, GumdropOptions)]
+struct CommandOptions {
+    /// Print the help message.
+    help: bool,
+
+    /// The bytes to skip in the disk file.
+    offset: u64,
+
+    /// Maximum size of a read request.
+    #[options(default = "131072")]
+    max_read: u32,
+
+    /// Number of nodes the kernel can know about.
+    #[options(default = "65536")]
+    nodes: usize,
+
+    /// Seconds the kernel caches entries and attributes.
+    #[options(default = "60")]
+    timeout: u64,
+
+    /// The directory to mount on.
+    #[options(free, required)]
+    target: String,
+}
+
+fn main() -> Result<(), Error> {
+    let opts = CommandOptions::parse_args_default_or_exit();
+    let disk = LinuxDiskRO::new("/dev/stdin", opts.offset)?;
+    let mut scratch = vec![0; ap_storage_unified::SCRATCH_SIZE];
+    let fs = ap_storage_unified::UnifiedFs::with_scratch(&disk, &mut scratch).ok_or(msg2err!("no filesystem found"))?;
+
+    let mut nodes = vec![Node::EMPTY; opts.nodes];
+    let options = Options {
+        timeout: opts.timeout,
+        ..Default::default()
+    };
+    let mut server = Server::new(fs.root()?, &mut nodes, options).ok_or(msg2err!("no nodes"))?;
+
+    // the requests are small as writes are not supported
+    let mount = Mount::new(&opts.target, opts.max_read)?;
+    let mut request = vec![0; 1 << 16];
+    let mut reply = vec![0; opts.max_read as usize + 4096];
+    mount.run(&mut server, &mut request, &mut reply)
+}
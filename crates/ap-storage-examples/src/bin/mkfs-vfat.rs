@@ -13,7 +13,7 @@
 //!
 //! # Assumptions
 //! -
-use ap_storage::{msg2err, Error, Offset, Read, ReadExt};
+use ap_storage::{msg2err, Error, Offset};
 use ap_storage_linux::LinuxDiskRW;
 use ap_storage_vfat_mkfs::MakeVFatFS;
 use core::str::FromStr;
@@ -129,10 +129,9 @@ fn main() -> Result<(), Error> {
     }
 
     let disk = LinuxDiskRW::new("/dev/stdin", opts.offset)?;
-    let r = &disk as &dyn Read;
 
     // silently limit the usable sectors to 32-bit -> this means 128 TiB
-    let sectors: u32 = core::cmp::min(0xffff_fffc, r.detect_size() / builder.get_sector_size() as u64) as u32;
+    let sectors: u32 = core::cmp::min(0xffff_fffc, disk.geometry()?.size / builder.get_sector_size() as u64) as u32;
     let (variant, fat_size) = builder.calc_variant(sectors as u64)?;
     if opts.verbose {
         let data_start = builder.data_start(variant, fat_size);
@@ -145,5 +144,6 @@ fn main() -> Result<(), Error> {
     if opts.dry_run {
         return Ok(());
     }
-    builder.build(&disk, sectors)
+    builder.build(&disk, sectors)?;
+    disk.sync()
 }
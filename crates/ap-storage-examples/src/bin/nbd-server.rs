@@ -0,0 +1,56 @@
+//! Serve a disk via NBD on a Unix socket.
+
+use ap_storage::{Error, Write};
+use ap_storage_linux::{LinuxDiskRO, LinuxDiskRW};
+use ap_storage_nbd::{serve, Export, UnixListener};
+use gumdrop::Options;
+
+#[derive(Debug, Options)]
+struct CommandOptions {
+    /// Print the help message.
+    help: bool,
+
+    /// The bytes to skip in the disk file.
+    offset: u64,
+
+    /// Reject writes.
+    read_only: bool,
+
+    /// The path of the socket.
+    #[options(default = "/tmp/nbd.sock")]
+    socket: String,
+
+    /// The name of the export.
+    #[options(default = "disk")]
+    name: String,
+
+    /// The disk file to serve.
+    #[options(free, required)]
+    disk: String,
+}
+
+fn main() -> Result<(), Error> {
+    let opts = CommandOptions::parse_args_default_or_exit();
+    let disk = LinuxDiskRO::new(&opts.disk, opts.offset)?;
+    let rw = match opts.read_only {
+        true => None,
+        false => Some(LinuxDiskRW::new(&opts.disk, opts.offset)?),
+    };
+    let flush = || rw.as_ref().map_or(Ok(()), |x| x.sync());
+    let export = Export {
+        name: &opts.name,
+        size: disk.geometry()?.size,
+        disk: &disk,
+        write: rw.as_ref().map(|x| x as &dyn Write),
+        flush: Some(&flush),
+    };
+
+    let listener = UnixListener::bind(&opts.socket)?;
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let mut conn = listener.accept()?;
+        if let Err(e) = serve(&export, &mut conn, &mut buf) {
+            eprintln!("client failed: {:?}", e);
+        }
+    }
+}
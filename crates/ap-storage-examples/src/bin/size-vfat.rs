@@ -6,8 +6,10 @@
 //!
 //! However it is relatively simple to calculate the required size for
 //! such an filesystem given the directory layout and the file sizes.
+//!
+//! Directories nested deeper than 256 levels are reported as an error.
 
-use ap_storage::{msg2err, Error, FileSystem, file::{File, FileType}, attr::{Attributes, SIZE}, directory::DirIterator};
+use ap_storage::{msg2err, Error, FileSystem, file::{File, FileType}, attr::{Attributes, SIZE}, walk::{self, Entry, Frame, Visitor, Walker}};
 use ap_storage_vfat::Variant;
 use ap_storage_linux::LinuxDiskRO;
 use gumdrop::Options;
@@ -44,37 +46,59 @@ struct CommandOptions {
     add: u64,
 }
 
-/// Count the clusters and directory entries per file.
-fn count(f: &impl File, cluster_size: u64) -> Result<(u64, u64), Error> {
-    let Some(mut iter) = f.dir() else {
-        let bytes = f.attr().get(SIZE, &mut []).ok_or(msg2err!("no size"))?.as_u64().unwrap_or_default();
-        return Ok((bytes.div_ceil(cluster_size), 0));
-    };
-    let mut num: u64 = 0;
-    let mut res = 0u64;
-
-    let mut name = [0u8; 256];
-    while let Some(entry) = iter.next(&mut name)? {
-        match entry.typ {
-            FileType::Unknown => {}
-            FileType::Parent => { num += 1 },
-            _ => {
-                // how many long-entries do we need?
-                let sname = core::str::from_utf8(&name[..core::cmp::min(name.len(), entry.nlen)]);
-                let nlen = sname.unwrap_or_default().encode_utf16().count();
-
-                // we don't check for valid 8.3 case where no long-entry is needed
-                num += 1 + nlen.div_ceil(13) as u64;
-
-                // measure recursively
-                let f = f.open(entry.offset).unwrap();
-                let (clusters, entries) = count(&f, cluster_size)?;
-                res += clusters + (entries*32).div_ceil(cluster_size);
-            }
+/// Count the clusters and directory entries.
+struct Counter {
+    cluster_size: u64,
+    /// The clusters used so far.
+    clusters: u64,
+    /// The number of entries per directory on the path.
+    entries: Vec<u64>,
+}
+
+impl<F: File> Visitor<F> for Counter {
+    fn pre(&mut self, entry: &Entry<'_, F>) -> Result<bool, Error> {
+        let num = self.entries.last_mut().expect("root entries");
+        if entry.dirent.typ == FileType::Parent {
+            *num += 1;
+            return Ok(false);
+        }
+
+        // how many long-entries do we need?
+        let nlen = core::str::from_utf8(entry.name).unwrap_or_default().encode_utf16().count();
+
+        // we don't check for valid 8.3 case where no long-entry is needed
+        *num += 1 + nlen.div_ceil(13) as u64;
+
+        if entry.dirent.typ == FileType::Directory {
+            self.entries.push(0);
+        } else {
+            let bytes = entry.file.attr().get(SIZE, &mut []).ok_or(msg2err!("no size"))?.as_u64().unwrap_or_default();
+            self.clusters += bytes.div_ceil(self.cluster_size);
         }
+        Ok(true)
     }
-    
-    Ok((res, num))
+
+    fn post(&mut self, _entry: &Entry<'_, F>) -> Result<(), Error> {
+        let entries = self.entries.pop().unwrap_or_default();
+        self.clusters += (entries * 32).div_ceil(self.cluster_size);
+        Ok(())
+    }
+}
+
+/// Count the clusters and directory entries of the root.
+fn count(f: &impl File, cluster_size: u64) -> Result<(u64, u64), Error> {
+    let mut counter = Counter {
+        cluster_size,
+        clusters: 0,
+        entries: vec![0],
+    };
+    let options = walk::Options {
+        parents: true,
+        ..Default::default()
+    };
+    let mut stack = [Frame::EMPTY; 256];
+    Walker::new(options, &mut stack, &mut []).walk(f, &mut counter)?;
+    Ok((counter.clusters, counter.entries[0]))
 }
 
 
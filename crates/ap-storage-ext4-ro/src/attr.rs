@@ -1,11 +1,12 @@
 //! File attributes for ext4.
 use crate::file::Ext4File;
-use ap_storage::attr::{self, new_attr, Attributes, Value};
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::{file::FileType, Read};
 use ap_util_slice_writer::*;
 
 new_attr!(BLOCKS, U64, "Number of blocks occupied.");
 new_attr!(FLAGS, U64, "Inode flags.");
-new_attr!(CTIME, I64, "Change time of file meta-data.");
+new_attr!(CTIME, Time, "Change time of file meta-data.");
 new_attr!(GID, U64, "Group id.");
 new_attr!(UID, U64, "User id.");
 new_attr!(
@@ -18,11 +19,11 @@ new_attr!(NLINKS, U64, "Number of hard-links to this file.");
 new_attr!(VERSION, U64, "Version number to detect file changes.");
 new_attr!(XATTR, U64, "Block holding the extended attributes.");
 
-pub struct Attr<'a> {
-    pub(crate) file: &'a Ext4File<'a>,
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a Ext4File<'a, D>,
 }
 
-impl<'a> IntoIterator for Attr<'a> {
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
     type Item = &'a &'a str;
     type IntoIter = core::slice::Iter<'a, &'a str>;
     fn into_iter(self) -> Self::IntoIter {
@@ -39,39 +40,81 @@ impl<'a> IntoIterator for Attr<'a> {
             XATTR,
             attr::ATIME,
             attr::BTIME,
+            attr::CTIME,
             attr::FTYPE,
+            attr::GID,
             attr::ID,
+            attr::MODE,
             attr::MTIME,
+            attr::RDEV,
             attr::SIZE,
+            attr::UID,
         ]
         .iter()
     }
 }
 
-impl<'a> Attributes<'a> for Attr<'a> {
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
     fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
         Some(match name {
             attr::FTYPE => {
                 let mut value = SliceWriter(buf, 0);
                 write!(value, "{:?}", self.file.ftype()).ok()?;
-                Value::Raw(value.1)
+                Value::Str(value.1)
             }
             BLOCKS => self.file.inode.blocks(self.file.fs.sb.block_size()).into(),
-            CTIME => self.file.inode.ctime().into(),
+            CTIME | attr::CTIME => Value::Time(self.file.inode.ctime()),
             FLAGS => self.file.inode.flags().into(),
             GENERATION => self.file.inode.generation().into(),
-            GID => self.file.inode.gid().into(),
+            GID | attr::GID => self.file.inode.gid().into(),
             MODE => self.file.inode.mode().into(),
             NLINKS => self.file.inode.nlinks().into(),
-            UID => self.file.inode.uid().into(),
+            UID | attr::UID => self.file.inode.uid().into(),
             VERSION => self.file.inode.version().into(),
             XATTR => self.file.inode.xattr().into(),
-            attr::ATIME => self.file.inode.atime().into(),
-            attr::BTIME => self.file.inode.crtime().into(),
+            attr::ATIME => Value::Time(self.file.inode.atime()),
+            attr::BTIME => Value::Time(self.file.inode.crtime()),
             attr::ID => self.file.nr.into(),
-            attr::MTIME => self.file.inode.mtime().into(),
+            attr::MODE => (self.file.inode.mode() & 0o7777).into(),
+            attr::MTIME => Value::Time(self.file.inode.mtime()),
+            attr::RDEV => match self.file.ftype() {
+                FileType::CharDevice | FileType::BlockDevice => {
+                    let (major, minor) = self.file.inode.rdev();
+                    ((major as u64) << 32 | minor as u64).into()
+                }
+                _ => 0u64.into(),
+            },
             attr::SIZE => self.file.inode.size(self.file.fs.sb.feature_incompat).into(),
             _ => return None,
         })
     }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                BLOCKS,
+                CTIME,
+                FLAGS,
+                GENERATION,
+                GID,
+                MODE,
+                NLINKS,
+                UID,
+                VERSION,
+                XATTR,
+                attr::ATIME,
+                attr::BTIME,
+                attr::CTIME,
+                attr::FTYPE,
+                attr::GID,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::RDEV,
+                attr::SIZE,
+                attr::UID,
+            ]
+        )
+    }
 }
@@ -1,21 +1,22 @@
 use crate::file::Ext4File;
-use ap_storage::Error;
+use ap_storage::{Error, Read};
 
-pub struct Ext4Blocks<'a>(pub &'a Ext4File<'a>);
+pub struct Ext4Blocks<'a, D: ?Sized>(pub &'a Ext4File<'a, D>);
 
 #[cfg(not(feature = "file_blocks"))]
-impl<'a> Ext4Blocks<'a> {
+impl<'a, D: Read + ?Sized> Ext4Blocks<'a, D> {
     pub fn search(&self, mut _block: u64) -> Result<(u64, u64), Error> {
         Err(ap_storage::msg2err!("blocks not supported"))
     }
 }
 
-/// Count the contigious block numbers in the slice.
+/// The number of adjacent blocks merged.
 #[cfg(feature = "file_blocks")]
-impl<'a> Ext4Blocks<'a> {
-    /// The number of adjacent blocks merged.
-    const MAX_MERGED: usize = 16;
+const MAX_MERGED: usize = 16;
 
+/// Count the contigious block numbers in the slice.
+#[cfg(feature = "file_blocks")]
+impl<'a, D: Read + ?Sized> Ext4Blocks<'a, D> {
     fn count_contigous(v: &[u32]) -> usize {
         let mut cnt = 1;
         let start = v[0] as u64;
@@ -61,11 +62,10 @@ impl<'a> Ext4Blocks<'a> {
         let mut cnt = 1;
         while level > 0 && res != 0 {
             let index = index_at_level(_block, level);
-            if level != 1 || (index + Self::MAX_MERGED as u64) >> log_numbers_per_block > 0 {
-                res = self.0.fs.disk.read_object(res as u64 * block_size + index * 4)?;
+            if level != 1 || (index + MAX_MERGED as u64) >> log_numbers_per_block > 0 {
+                res = self.0.fs.disk().read_object(res as u64 * block_size + index * 4)?;
             } else {
-                let blocks: [u32; Ext4Blocks::MAX_MERGED] =
-                    self.0.fs.disk.read_object(res as u64 * block_size + index * 4)?;
+                let blocks: [u32; MAX_MERGED] = self.0.fs.disk().read_object(res as u64 * block_size + index * 4)?;
                 res = blocks[0];
                 cnt = Self::count_contigous(&blocks);
             }
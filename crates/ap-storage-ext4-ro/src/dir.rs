@@ -38,6 +38,10 @@ impl<'a> DirIterator for Dir<'a> {
         let mut typ = match header.file_type {
             1 => FileType::File,
             2 => FileType::Directory,
+            3 => FileType::CharDevice,
+            4 => FileType::BlockDevice,
+            5 => FileType::Fifo,
+            6 => FileType::Socket,
             7 => FileType::SymLink,
             _ => FileType::Unknown,
         };
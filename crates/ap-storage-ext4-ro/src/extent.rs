@@ -1,9 +1,9 @@
 use crate::file::Ext4File;
-use ap_storage::{msg2err, Error};
-pub struct Ext4Extents<'a>(pub &'a Ext4File<'a>);
+use ap_storage::{msg2err, Error, Read};
+pub struct Ext4Extents<'a, D: ?Sized>(pub &'a Ext4File<'a, D>);
 
 #[cfg(not(feature = "file_extents"))]
-impl<'a> Ext4Extents<'a> {
+impl<'a, D: Read + ?Sized> Ext4Extents<'a, D> {
     pub fn search(&self, _block: u64) -> Result<(u64, u64), Error> {
         Err(msg2err!("extents not supported"))
     }
@@ -12,7 +12,7 @@ impl<'a> Ext4Extents<'a> {
 use ap_storage_ext4::extent::*;
 
 #[cfg(feature = "file_extents")]
-impl<'a> Ext4Extents<'a> {
+impl<'a, D: Read + ?Sized> Ext4Extents<'a, D> {
     /// Get an extent object at the certain disk offset.
     fn get<X: Sized + Copy>(&self, ofs: u64) -> Result<X, Error> {
         use ap_storage::ReadExt;
@@ -20,7 +20,7 @@ impl<'a> Ext4Extents<'a> {
             // The first extents are inline in the block.
             0..=48 => Ok(unsafe { *(self.0.inode.extent().unwrap().as_ptr().add(ofs as usize / 4) as *const X) }),
             // Could detect errors here
-            _ => self.0.fs.disk.read_object(ofs),
+            _ => self.0.fs.disk().read_object(ofs),
         }
     }
 
@@ -6,8 +6,8 @@ use ap_storage_ext4::dir::DirEntryHeader;
 use core::cell::RefCell;
 
 /// File object.
-pub struct Ext4File<'a> {
-    pub(crate) fs: &'a Ext4Fs<'a>,
+pub struct Ext4File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a Ext4Fs<'a, D>,
     pub(crate) inode: Inode,
     leaf_optimization: bool,
     pub(crate) nr: u64,
@@ -22,9 +22,9 @@ struct FileCache {
     cnt: u64,
 }
 
-impl<'a> Ext4File<'a> {
+impl<'a, D: Read + ?Sized> Ext4File<'a, D> {
     /// Open the given file by inode number.
-    pub fn new(fs: &'a Ext4Fs, nr: u64) -> Result<Self, Error> {
+    pub fn new(fs: &'a Ext4Fs<'a, D>, nr: u64) -> Result<Self, Error> {
         let inode = fs.inode(nr)?;
         Ok(Self {
             fs,
@@ -61,13 +61,17 @@ impl<'a> Ext4File<'a> {
             0x8 => FileType::File,
             0x4 => FileType::Directory,
             0xa => FileType::SymLink,
+            0x2 => FileType::CharDevice,
+            0x6 => FileType::BlockDevice,
+            0x1 => FileType::Fifo,
+            0xc => FileType::Socket,
             _ => FileType::Unknown,
         }
     }
 }
 
-impl<'a> File for Ext4File<'a> {
-    type AttrType<'c> = attr::Attr<'c> where Self: 'c;
+impl<'a, D: Read + ?Sized> File for Ext4File<'a, D> {
+    type AttrType<'c> = attr::Attr<'c, D> where Self: 'c;
     fn attr(&self) -> Self::AttrType<'_> {
         crate::attr::Attr { file: self }
     }
@@ -92,7 +96,7 @@ impl<'a> File for Ext4File<'a> {
     }
 }
 
-impl<'a> Read for Ext4File<'a> {
+impl<'a, D: Read + ?Sized> Read for Ext4File<'a, D> {
     /// Read in the given inode.
     fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
         let size = self.inode.size(self.fs.sb.feature_incompat);
@@ -127,6 +131,6 @@ impl<'a> Read for Ext4File<'a> {
             return Ok(valid_size);
         }
         let ofs = phys * block_size + offset_in_block;
-        self.fs.disk.read_bytes(ofs, buf)
+        self.fs.disk().read_bytes(ofs, buf)
     }
 }
@@ -19,17 +19,28 @@ use ap_storage::{file::FileType, msg2err, Error, FileSystem, Offset, Read, ReadE
 use ap_storage_ext4::{inode::Inode, superblock::SuperBlock};
 
 /// Read-only Ext{2,3,4} file-system object.
-#[derive(Clone)]
-pub struct Ext4Fs<'a> {
-    disk: &'a dyn Read,
+///
+/// The filesystem is `Sync` and can be shared between threads if the disk is.
+pub struct Ext4Fs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
     sb: SuperBlock,
     leaf_optimization: bool,
 }
 
-impl<'a> Ext4Fs<'a> {
+impl<D: ?Sized> Clone for Ext4Fs<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            disk: self.disk,
+            sb: self.sb,
+            leaf_optimization: self.leaf_optimization,
+        }
+    }
+}
+
+impl<'a, D: Read + ?Sized> Ext4Fs<'a, D> {
     /// Mount the filesystem..
-    pub fn new(disk: &'a dyn Read, leaf_optimization: bool) -> Result<Ext4Fs<'a>, Error> {
-        let sb = disk.read_object::<SuperBlock>(0x400)?;
+    pub fn new(disk: &'a D, leaf_optimization: bool) -> Result<Self, Error> {
+        let sb = (&disk as &dyn Read).read_object::<SuperBlock>(0x400)?;
 
         // check the magic
         if sb.magic != 0xef53 {
@@ -48,6 +59,11 @@ impl<'a> Ext4Fs<'a> {
         })
     }
 
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
     /// Read an inode.
     pub fn inode(&self, nr: u64) -> Result<Inode, Error> {
         if nr > self.sb.inode_count as u64 {
@@ -67,10 +83,10 @@ impl<'a> Ext4Fs<'a> {
 
         // get the inode block from the descriptor table.
         let inode_block = {
-            let lo = self.disk.read_object::<u32>(group_desc_offset + 0x8)?;
+            let lo = self.disk().read_object::<u32>(group_desc_offset + 0x8)?;
             let hi = {
                 if self.sb.desc_size() >= 64 {
-                    self.disk.read_object::<u32>(group_desc_offset + 0x28)?
+                    self.disk().read_object::<u32>(group_desc_offset + 0x28)?
                 } else {
                     0
                 }
@@ -81,14 +97,14 @@ impl<'a> Ext4Fs<'a> {
         // The inode might be smaller on disk due to backward compatiblity.
         let mut buf = [0u8; core::mem::size_of::<Inode>()];
         let n = core::cmp::min(core::mem::size_of::<Inode>(), self.sb.inode_size() as usize);
-        self.disk
+        self.disk()
             .read_exact(inode_block * self.sb.block_size() + inode_ofs, &mut buf[..n])?;
         Ok(unsafe { core::mem::transmute(buf) })
     }
 }
 
-impl<'a> FileSystem<'a> for Ext4Fs<'a> {
-    type FileType = file::Ext4File<'a>;
+impl<'a, D: Read + ?Sized> FileSystem<'a> for Ext4Fs<'a, D> {
+    type FileType = file::Ext4File<'a, D>;
     fn root(&'a self) -> Result<Self::FileType, Error> {
         file::Ext4File::new(self, 2)
     }
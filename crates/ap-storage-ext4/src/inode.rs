@@ -65,6 +65,17 @@ impl Inode {
         self.mode
     }
 
+    /// The device number of character and block devices as (major, minor).
+    pub fn rdev(&self) -> (u32, u32) {
+        // the old 8:8 encoding is used if it fits, otherwise the new 12:20 one
+        if self.blocks[0] != 0 {
+            let v = self.blocks[0];
+            return ((v >> 8) & 0xff, v & 0xff);
+        }
+        let v = self.blocks[1];
+        ((v >> 8) & 0xfff, (v & 0xff) | ((v >> 12) & 0xfff00))
+    }
+
     pub fn nlinks(&self) -> u16 {
         self.nlinks
     }
@@ -124,3 +135,28 @@ impl Inode {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty inode with the given block pointers.
+    fn device(blocks: [u32; 2]) -> Inode {
+        // SAFETY: the inode consists of integers only.
+        let mut inode: Inode = unsafe { core::mem::zeroed() };
+        inode.blocks[..2].copy_from_slice(&blocks);
+        inode
+    }
+
+    #[test]
+    fn rdev() {
+        // the old 8:8 encoding in the first block
+        assert_eq!(device([0x0801, 0]).rdev(), (8, 1));
+        assert_eq!(device([0xfeff, 0]).rdev(), (0xfe, 0xff));
+
+        // the new 12:20 encoding in the second block
+        assert_eq!(device([0, 0x0801]).rdev(), (8, 1));
+        assert_eq!(device([0, 0x1234_5678]).rdev(), (0x456, 0x1_2378));
+        assert_eq!(device([0, 0xffff_ffff]).rdev(), (0xfff, 0xf_ffff));
+    }
+}
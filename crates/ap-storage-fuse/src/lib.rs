@@ -0,0 +1,144 @@
+//! Serve ap-storage filesystems to Linux via FUSE.
+//!
+//! The [`Server`] speaks the raw kernel protocol on byte buffers, so
+//! it can be driven in-process without a mount.  A [`Mount`] connects
+//! it to `/dev/fuse`.
+//!
+//! Nodes are identified by the offset of their directory entry in the
+//! parent.  The ID attribute becomes the inode number and the standard
+//! attributes fill the rest of `stat`.  Everything is read-only.
+
+#![no_std]
+
+mod mount;
+pub mod proto;
+mod server;
+pub use mount::Mount;
+pub use server::{Node, Options, Server};
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::FileSystem;
+    use ap_storage_json::JsonFS;
+    use ap_storage_memory::ReadSlice;
+    use core::mem::size_of;
+    use proto::*;
+    use std::{vec, vec::Vec};
+
+    /// Build a request with the body.
+    fn request<T: Plain>(opcode: u32, nodeid: u64, body: &T, name: &[u8]) -> Vec<u8> {
+        let len = size_of::<InHeader>() + size_of::<T>() + name.len();
+        let header = InHeader {
+            len: len as u32,
+            opcode,
+            unique: 42,
+            nodeid,
+            ..Default::default()
+        };
+        let mut res = vec![0u8; len];
+        put(&mut res, &header);
+        put(&mut res[size_of::<InHeader>()..], body);
+        res[len - name.len()..].copy_from_slice(name);
+        res
+    }
+
+    /// Send a request and return the error and the payload.
+    fn call<F: ap_storage::file::File>(server: &mut Server<F>, req: &[u8], reply: &mut [u8]) -> (i32, Vec<u8>) {
+        let n = server.handle(req, reply);
+        let header: OutHeader = parse(reply).unwrap();
+        assert_eq!((header.len as usize, header.unique), (n, 42));
+        (header.error, reply[size_of::<OutHeader>()..n].to_vec())
+    }
+
+    #[test]
+    fn protocol() {
+        let json = br#"{"a": {"b": [1, 2]}, "c": "hello"}"#;
+        let fs = JsonFS::new(&ReadSlice(json)).unwrap();
+        let mut nodes = [Node::EMPTY; 8];
+        let mut server = Server::new(fs.root().unwrap(), &mut nodes, Options::default()).unwrap();
+        let mut reply = [0u8; 4096];
+
+        let init = InitIn {
+            major: 7,
+            minor: 38,
+            ..Default::default()
+        };
+        let (error, out) = call(&mut server, &request(INIT, 0, &init, &[]), &mut reply);
+        let init: InitOut = parse(&out).unwrap();
+        assert_eq!((error, init.major, init.minor), (0, MAJOR, MINOR));
+
+        // list the root
+        let read = ReadIn {
+            size: 4096,
+            ..Default::default()
+        };
+        let (error, out) = call(&mut server, &request(READDIR, ROOT_ID, &read, &[]), &mut reply);
+        assert_eq!(error, 0);
+        let mut names = Vec::new();
+        let mut pos = 0;
+        while pos < out.len() {
+            let dirent: Dirent = parse(&out[pos..]).unwrap();
+            let start = pos + size_of::<Dirent>();
+            names.push((
+                out[start..start + dirent.namelen as usize].to_vec(),
+                dirent.typ,
+                dirent.off,
+            ));
+            pos += (size_of::<Dirent>() + dirent.namelen as usize + 7) & !7;
+        }
+        assert_eq!(names, [(b"a".to_vec(), 4, 1), (b"c".to_vec(), 8, 2)]);
+
+        // continue after the first entry
+        let read = ReadIn { offset: 1, ..read };
+        let (_, out) = call(&mut server, &request(READDIR, ROOT_ID, &read, &[]), &mut reply);
+        assert_eq!(parse::<Dirent>(&out).unwrap().off, 2);
+
+        // lookup and read a file
+        let (error, out) = call(&mut server, &request(LOOKUP, ROOT_ID, &[0u8; 0], b"a\0"), &mut reply);
+        assert_eq!(error, 0);
+        let a: EntryOut = parse(&out).unwrap();
+        assert_eq!(a.attr.mode, libc::S_IFDIR | 0o555);
+        let (_, out) = call(&mut server, &request(LOOKUP, a.nodeid, &[0u8; 0], b"b\0"), &mut reply);
+        let b: EntryOut = parse(&out).unwrap();
+        assert_eq!((b.attr.mode, b.attr.size), (libc::S_IFREG | 0o444, 5));
+        assert_ne!(a.nodeid, b.nodeid);
+
+        // the same entry gets the same node
+        let (_, out) = call(&mut server, &request(LOOKUP, ROOT_ID, &[0u8; 0], b"a\0"), &mut reply);
+        assert_eq!(parse::<EntryOut>(&out).unwrap().nodeid, a.nodeid);
+        assert_eq!(server.len(), 3);
+
+        let open = OpenIn::default();
+        let (error, _) = call(&mut server, &request(OPEN, b.nodeid, &open, &[]), &mut reply);
+        assert_eq!(error, 0);
+        let read = ReadIn {
+            offset: 1,
+            size: 10,
+            ..Default::default()
+        };
+        let (error, out) = call(&mut server, &request(READ, b.nodeid, &read, &[]), &mut reply);
+        assert_eq!((error, &out[..]), (0, &b"1,2]"[..]));
+
+        let attr = call(&mut server, &request(GETATTR, b.nodeid, &[0u8; 16], &[]), &mut reply);
+        assert_eq!(parse::<AttrOut>(&attr.1).unwrap().attr.ino, b.attr.ino);
+
+        // errors
+        let (error, _) = call(&mut server, &request(LOOKUP, ROOT_ID, &[0u8; 0], b"x\0"), &mut reply);
+        assert_eq!(error, -libc::ENOENT);
+        let write = OpenIn {
+            flags: libc::O_RDWR as u32,
+            ..open
+        };
+        let (error, _) = call(&mut server, &request(OPEN, b.nodeid, &write, &[]), &mut reply);
+        assert_eq!(error, -libc::EROFS);
+        let (error, _) = call(&mut server, &request(READLINK, b.nodeid, &[0u8; 0], &[]), &mut reply);
+        assert_eq!(error, -libc::EINVAL);
+        let (error, _) = call(&mut server, &request(GETATTR, 99, &[0u8; 16], &[]), &mut reply);
+        assert_eq!(error, -libc::ESTALE);
+        let (error, _) = call(&mut server, &request(LOOKUP, b.nodeid, &[0u8; 0], b"x\0"), &mut reply);
+        assert_eq!(error, -libc::ENOTDIR);
+        assert_eq!(server.handle(&request(FORGET, b.nodeid, &1u64, &[]), &mut reply), 0);
+    }
+}
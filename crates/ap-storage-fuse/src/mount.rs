@@ -0,0 +1,98 @@
+//! Mounting via `/dev/fuse`.
+
+use crate::Server;
+use ap_storage::{file::File, msg2err, Error};
+use ap_storage_linux::{check_error, str2cstr};
+use ap_util_slice_writer::*;
+
+/// A read-only FUSE mount.  It is unmounted when dropped.
+pub struct Mount {
+    fd: i32,
+    target: [u8; libc::PATH_MAX as usize],
+}
+
+impl Mount {
+    /// Mount a new FUSE filesystem at the target directory.
+    ///
+    /// Reads are limited to `max_read` bytes.  Mounting requires `CAP_SYS_ADMIN`.
+    pub fn new(target: &str, max_read: u32) -> Result<Self, Error> {
+        let mut res = Self {
+            fd: -1,
+            target: [0; libc::PATH_MAX as usize],
+        };
+        str2cstr(target, &mut res.target).ok_or(msg2err!("invalid target"))?;
+        res.fd = unsafe {
+            check_error(libc::open(c"/dev/fuse".as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) as isize)
+                .map_err(|e| msg2err!("open /dev/fuse").context(e))? as i32
+        };
+
+        let mut buf = [0u8; 256];
+        let mut options = SliceWriter(&mut buf[..255], 0);
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        write!(
+            options,
+            "fd={},rootmode=40000,user_id={},group_id={},max_read={},default_permissions",
+            res.fd, uid, gid, max_read
+        )
+        .map_err(|_| msg2err!("options"))?;
+        if options.1 >= 255 {
+            return Err(msg2err!("options too long"));
+        }
+        unsafe {
+            check_error(libc::mount(
+                c"ap-storage".as_ptr(),
+                res.target.as_ptr() as *const libc::c_char,
+                c"fuse".as_ptr(),
+                libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV,
+                buf.as_ptr() as *const libc::c_void,
+            ) as isize)
+            .map_err(|e| msg2err!("mount").context(e))?;
+        }
+        Ok(res)
+    }
+
+    /// Answer requests until the filesystem is unmounted.
+    ///
+    /// The request buffer needs at least 8 KiB and the reply buffer
+    /// room for the header plus `max_read` bytes.
+    pub fn run<F: File>(&self, server: &mut Server<F>, request: &mut [u8], reply: &mut [u8]) -> Result<(), Error> {
+        loop {
+            let n = match unsafe {
+                check_error(libc::read(
+                    self.fd,
+                    request.as_mut_ptr() as *mut libc::c_void,
+                    request.len(),
+                ))
+            } {
+                Ok(n) => n as usize,
+                // interrupted requests
+                Err(libc::EINTR | libc::EAGAIN | libc::ENOENT) => continue,
+                // the filesystem was unmounted
+                Err(libc::ENODEV) => return Ok(()),
+                Err(e) => return Err(msg2err!("read /dev/fuse").context(e)),
+            };
+            let len = server.handle(&request[..n], reply);
+            if len == 0 {
+                continue;
+            }
+            match unsafe { check_error(libc::write(self.fd, reply.as_ptr() as *const libc::c_void, len)) } {
+                // the request was interrupted in the meantime
+                Ok(_) | Err(libc::ENOENT) => {}
+                Err(libc::ENODEV) => return Ok(()),
+                Err(e) => return Err(msg2err!("write /dev/fuse").context(e)),
+            }
+        }
+    }
+}
+
+/// Detach the mount and close the device.
+impl Drop for Mount {
+    fn drop(&mut self) {
+        unsafe {
+            if self.fd >= 0 {
+                libc::umount2(self.target.as_ptr() as *const libc::c_char, libc::MNT_DETACH);
+                libc::close(self.fd);
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! The structures of the FUSE kernel protocol.
+//!
+//! See `include/uapi/linux/fuse.h` in the Linux sources.
+
+/// The protocol version spoken by the server.
+pub const MAJOR: u32 = 7;
+pub const MINOR: u32 = 31;
+
+/// Init replies of kernels before 7.23 are shorter.
+pub const COMPAT_22_INIT_OUT_SIZE: usize = 24;
+
+/// The node ID of the root directory.
+pub const ROOT_ID: u64 = 1;
+
+/// Inode number for entries without an ID.
+pub const UNKNOWN_INO: u64 = 0xffff_ffff;
+
+/// Keep the page-cache between opens.
+pub const FOPEN_KEEP_CACHE: u32 = 1 << 1;
+
+pub const LOOKUP: u32 = 1;
+pub const FORGET: u32 = 2;
+pub const GETATTR: u32 = 3;
+pub const READLINK: u32 = 5;
+pub const OPEN: u32 = 14;
+pub const READ: u32 = 15;
+pub const STATFS: u32 = 17;
+pub const RELEASE: u32 = 18;
+pub const FLUSH: u32 = 25;
+pub const INIT: u32 = 26;
+pub const OPENDIR: u32 = 27;
+pub const READDIR: u32 = 28;
+pub const RELEASEDIR: u32 = 29;
+pub const INTERRUPT: u32 = 36;
+pub const DESTROY: u32 = 38;
+pub const BATCH_FORGET: u32 = 42;
+
+/// Structures that can be copied from and to the wire.
+///
+/// # Safety
+/// - every bit pattern has to be a valid value
+pub unsafe trait Plain: Copy {}
+
+/// Parse a structure from the start of the buffer.
+pub fn parse<T: Plain>(buf: &[u8]) -> Option<T> {
+    if buf.len() < core::mem::size_of::<T>() {
+        return None;
+    }
+    Some(unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const T) })
+}
+
+/// Copy a structure to the start of the buffer and return its size.
+pub fn put<T: Plain>(buf: &mut [u8], obj: &T) -> Option<usize> {
+    let n = core::mem::size_of::<T>();
+    let src = unsafe { core::slice::from_raw_parts(obj as *const T as *const u8, n) };
+    buf.get_mut(..n)?.copy_from_slice(src);
+    Some(n)
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InHeader {
+    pub len: u32,
+    pub opcode: u32,
+    pub unique: u64,
+    pub nodeid: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: u32,
+    pub total_extlen: u16,
+    pub padding: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OutHeader {
+    pub len: u32,
+    pub error: i32,
+    pub unique: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InitIn {
+    pub major: u32,
+    pub minor: u32,
+    pub max_readahead: u32,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InitOut {
+    pub major: u32,
+    pub minor: u32,
+    pub max_readahead: u32,
+    pub flags: u32,
+    pub max_background: u16,
+    pub congestion_threshold: u16,
+    pub max_write: u32,
+    pub time_gran: u32,
+    pub max_pages: u16,
+    pub map_alignment: u16,
+    pub flags2: u32,
+    pub unused: [u32; 7],
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Attr {
+    pub ino: u64,
+    pub size: u64,
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub atimensec: u32,
+    pub mtimensec: u32,
+    pub ctimensec: u32,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u32,
+    pub blksize: u32,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EntryOut {
+    pub nodeid: u64,
+    pub generation: u64,
+    pub entry_valid: u64,
+    pub attr_valid: u64,
+    pub entry_valid_nsec: u32,
+    pub attr_valid_nsec: u32,
+    pub attr: Attr,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AttrOut {
+    pub attr_valid: u64,
+    pub attr_valid_nsec: u32,
+    pub dummy: u32,
+    pub attr: Attr,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OpenIn {
+    pub flags: u32,
+    pub open_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OpenOut {
+    pub fh: u64,
+    pub open_flags: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReadIn {
+    pub fh: u64,
+    pub offset: u64,
+    pub size: u32,
+    pub read_flags: u32,
+    pub lock_owner: u64,
+    pub flags: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Dirent {
+    pub ino: u64,
+    pub off: u64,
+    pub namelen: u32,
+    pub typ: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatfsOut {
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub bsize: u32,
+    pub namelen: u32,
+    pub frsize: u32,
+    pub padding: u32,
+    pub spare: [u32; 6],
+}
+
+unsafe impl Plain for InHeader {}
+unsafe impl Plain for OutHeader {}
+unsafe impl Plain for InitIn {}
+unsafe impl Plain for InitOut {}
+unsafe impl Plain for Attr {}
+unsafe impl Plain for EntryOut {}
+unsafe impl Plain for AttrOut {}
+unsafe impl Plain for OpenIn {}
+unsafe impl Plain for OpenOut {}
+unsafe impl Plain for ReadIn {}
+unsafe impl Plain for Dirent {}
+unsafe impl Plain for StatfsOut {}
+unsafe impl Plain for u64 {}
+unsafe impl<const N: usize> Plain for [u8; N] {}
@@ -0,0 +1,428 @@
+//! The protocol handling independent of the kernel device.
+
+use crate::proto::{self, *};
+use ap_storage::{
+    attr::{self, Attributes, Value},
+    directory::{DirEntry, DirIterator},
+    file::{File, FileType},
+    Offset,
+};
+use core::mem::size_of;
+
+/// The maximum depth of a node below the root.
+const MAX_DEPTH: usize = 256;
+
+/// The block size reported in stat and statfs.
+const BLOCK_SIZE: u32 = 4096;
+
+/// The server options.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Seconds the kernel may cache entries and attributes.
+    pub timeout: u64,
+    /// The owner of files without an UID attribute.
+    pub uid: u32,
+    /// The group of files without a GID attribute.
+    pub gid: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            timeout: 60,
+            uid: 0,
+            gid: 0,
+        }
+    }
+}
+
+/// A node handed out to the kernel.
+///
+/// Nodes are opened again from their parent with the offset of the
+/// directory entry.
+#[derive(Debug, Clone, Copy)]
+pub struct Node {
+    /// The node ID of the parent.  Zero marks an empty slot.
+    parent: u64,
+    /// The offset inside the parent.
+    offset: Offset,
+    /// The type from the directory entry.
+    typ: FileType,
+}
+
+impl Node {
+    /// An unused node to initialize the table with.
+    pub const EMPTY: Self = Self {
+        parent: 0,
+        offset: 0,
+        typ: FileType::Unknown,
+    };
+}
+
+/// Answer FUSE requests for a read-only filesystem.
+///
+/// The node table is a hash-table keyed by the parent and the offset
+/// of the directory entry.  The node ID is the slot plus one, so the
+/// root directory lives in the first slot.  Nodes are never removed,
+/// the kernel gets `ENFILE` when the table is full.
+pub struct Server<'a, F> {
+    root: F,
+    nodes: &'a mut [Node],
+    options: Options,
+}
+
+/// Parse the type from the FTYPE attribute.
+fn parse_ftype(name: &[u8]) -> Option<FileType> {
+    Some(match name {
+        b"File" => FileType::File,
+        b"Directory" | b"Parent" => FileType::Directory,
+        b"SymLink" => FileType::SymLink,
+        b"CharDevice" => FileType::CharDevice,
+        b"BlockDevice" => FileType::BlockDevice,
+        b"Fifo" => FileType::Fifo,
+        b"Socket" => FileType::Socket,
+        _ => return None,
+    })
+}
+
+/// The file type bits of the mode.
+fn type_bits(typ: FileType) -> u32 {
+    match typ {
+        FileType::Directory | FileType::Parent => libc::S_IFDIR,
+        FileType::SymLink => libc::S_IFLNK,
+        FileType::CharDevice => libc::S_IFCHR,
+        FileType::BlockDevice => libc::S_IFBLK,
+        FileType::Fifo => libc::S_IFIFO,
+        FileType::Socket => libc::S_IFSOCK,
+        FileType::File | FileType::Unknown => libc::S_IFREG,
+    }
+}
+
+/// Split nano-seconds into seconds and nano-seconds.
+fn time(value: Option<Value>) -> (u64, u32) {
+    let ns = value.and_then(|v| v.as_time()).unwrap_or(0);
+    (ns.div_euclid(1_000_000_000) as u64, ns.rem_euclid(1_000_000_000) as u32)
+}
+
+/// Encode a `major << 32 | minor` device number for the kernel.
+fn encode_dev(rdev: u64) -> u32 {
+    let (major, minor) = ((rdev >> 32) as u32, rdev as u32);
+    (minor & 0xff) | (major & 0xfff) << 8 | (minor & !0xff) << 12
+}
+
+impl<'a, F: File> Server<'a, F> {
+    /// Serve the files below the root.  The table needs at least one node.
+    pub fn new(root: F, nodes: &'a mut [Node], options: Options) -> Option<Self> {
+        let first = nodes.first_mut()?;
+        *first = Node {
+            parent: ROOT_ID,
+            offset: 0,
+            typ: FileType::Directory,
+        };
+        nodes[1..].fill(Node::EMPTY);
+        Some(Self { root, nodes, options })
+    }
+
+    /// The number of nodes known to the kernel including the root.
+    pub fn len(&self) -> usize {
+        self.nodes.iter().filter(|x| x.parent != 0).count()
+    }
+
+    /// There is always the root node.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Handle a request and return the length of the reply.
+    ///
+    /// Requests that need no answer return zero.  The reply buffer
+    /// should hold the header plus the largest read.
+    pub fn handle(&mut self, request: &[u8], reply: &mut [u8]) -> usize {
+        let Some(header) = proto::parse::<InHeader>(request) else {
+            return 0;
+        };
+        let olen = size_of::<OutHeader>();
+        if reply.len() < olen {
+            return 0;
+        }
+        let end = core::cmp::min(header.len as usize, request.len());
+        let body = request.get(size_of::<InHeader>()..end).unwrap_or(&[]);
+        let (head, out) = reply.split_at_mut(olen);
+        let res = match header.opcode {
+            FORGET | BATCH_FORGET | INTERRUPT => return 0,
+            INIT => self.init(body, out),
+            LOOKUP => self.lookup(header.nodeid, body, out),
+            GETATTR => self.getattr(header.nodeid, out),
+            READLINK => self.readlink(header.nodeid, out),
+            OPEN => self.open(header.nodeid, body, out, false),
+            OPENDIR => self.open(header.nodeid, body, out, true),
+            READ => self.read(header.nodeid, body, out),
+            READDIR => self.readdir(header.nodeid, body, out),
+            STATFS => self.statfs(out),
+            RELEASE | RELEASEDIR | FLUSH | DESTROY => Ok(0),
+            _ => Err(libc::ENOSYS),
+        };
+        let (error, n) = match res {
+            Ok(n) => (0, n),
+            Err(e) => (-e, 0),
+        };
+        let header = OutHeader {
+            len: (olen + n) as u32,
+            error,
+            unique: header.unique,
+        };
+        proto::put(head, &header);
+        olen + n
+    }
+
+    /// Lookup a node in the table.
+    fn node(&self, nodeid: u64) -> Result<Node, i32> {
+        let index = nodeid.checked_sub(1).ok_or(libc::ESTALE)? as usize;
+        match self.nodes.get(index) {
+            Some(node) if node.parent != 0 => Ok(*node),
+            _ => Err(libc::ESTALE),
+        }
+    }
+
+    /// Add a node or return the existing one.
+    fn insert(&mut self, parent: u64, offset: Offset, typ: FileType) -> Result<u64, i32> {
+        let len = self.nodes.len() - 1;
+        let key = parent.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ offset;
+        let start = (key.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize;
+        for i in 0..len {
+            let slot = 1 + (start + i) % len;
+            let node = &mut self.nodes[slot];
+            if node.parent == 0 {
+                *node = Node { parent, offset, typ };
+            } else if node.parent != parent || node.offset != offset {
+                continue;
+            }
+            return Ok(slot as u64 + 1);
+        }
+        Err(libc::ENFILE)
+    }
+
+    /// Open a node by walking down from the root.
+    fn with_node<R>(&self, nodeid: u64, f: impl FnOnce(&F, FileType) -> Result<R, i32>) -> Result<R, i32> {
+        let node = self.node(nodeid)?;
+        let mut chain = [0; MAX_DEPTH];
+        let mut depth = 0;
+        let mut id = nodeid;
+        while id != ROOT_ID {
+            let node = self.node(id)?;
+            *chain.get_mut(depth).ok_or(libc::ENAMETOOLONG)? = node.offset;
+            depth += 1;
+            id = node.parent;
+        }
+        let mut file = None;
+        for offset in chain[..depth].iter().rev() {
+            let parent = file.as_ref().unwrap_or(&self.root);
+            file = Some(parent.open(*offset).map_err(|_| libc::EIO)?);
+        }
+        let file = file.as_ref().unwrap_or(&self.root);
+        let mut buf = [0u8; 16];
+        let typ = file
+            .attr()
+            .get(attr::FTYPE, &mut buf)
+            .and_then(|v| v.as_len())
+            .and_then(|n| parse_ftype(buf.get(..n)?))
+            .unwrap_or(match node.typ {
+                FileType::Parent => FileType::Directory,
+                typ => typ,
+            });
+        f(file, typ)
+    }
+
+    /// Convert the attributes of a file.
+    fn stat(&self, file: &F, typ: FileType) -> proto::Attr {
+        let attr = file.attr();
+        let get = |name| attr.get(name, &mut []);
+        let num = |name| get(name).and_then(|v| v.as_u64());
+        let size = num(attr::SIZE).unwrap_or(0);
+        let mode = num(attr::MODE).unwrap_or(match typ {
+            FileType::Directory => 0o555,
+            _ => 0o444,
+        }) as u32;
+        let (atime, atimensec) = time(get(attr::ATIME));
+        let (mtime, mtimensec) = time(get(attr::MTIME));
+        let (ctime, ctimensec) = time(get(attr::CTIME));
+        proto::Attr {
+            ino: num(attr::ID).unwrap_or(0),
+            size,
+            blocks: size.div_ceil(512),
+            atime,
+            mtime,
+            ctime,
+            atimensec,
+            mtimensec,
+            ctimensec,
+            mode: type_bits(typ) | mode & 0o7777,
+            nlink: 1,
+            uid: num(attr::UID).map_or(self.options.uid, |x| x as u32),
+            gid: num(attr::GID).map_or(self.options.gid, |x| x as u32),
+            rdev: encode_dev(num(attr::RDEV).unwrap_or(0)),
+            blksize: BLOCK_SIZE,
+            flags: 0,
+        }
+    }
+
+    fn init(&mut self, body: &[u8], out: &mut [u8]) -> Result<usize, i32> {
+        let init = proto::parse::<InitIn>(body).ok_or(libc::EINVAL)?;
+        if init.major < MAJOR {
+            return Err(libc::EPROTO);
+        }
+        let reply = InitOut {
+            major: MAJOR,
+            minor: MINOR,
+            max_readahead: init.max_readahead,
+            max_background: 16,
+            congestion_threshold: 12,
+            max_write: 4096,
+            time_gran: 1,
+            ..Default::default()
+        };
+        let n = proto::put(out, &reply).ok_or(libc::ERANGE)?;
+        Ok(if init.minor < 23 { COMPAT_22_INIT_OUT_SIZE } else { n })
+    }
+
+    fn lookup(&mut self, parent: u64, body: &[u8], out: &mut [u8]) -> Result<usize, i32> {
+        let name = body.split(|x| *x == 0).next().unwrap_or(&[]);
+        let (entry, mut attr): (DirEntry, _) = self.with_node(parent, |file, _| {
+            let mut dir = file.dir().ok_or(libc::ENOTDIR)?;
+            let mut buf = [0u8; 256];
+            while let Some(entry) = dir.next(&mut buf).map_err(|_| libc::EIO)? {
+                if entry.typ == FileType::Unknown || buf.get(..entry.nlen) != Some(name) {
+                    continue;
+                }
+                let child = file.open(entry.offset).map_err(|_| libc::EIO)?;
+                return Ok((entry, self.stat(&child, entry.typ)));
+            }
+            Err(libc::ENOENT)
+        })?;
+        let nodeid = self.insert(parent, entry.offset, entry.typ)?;
+        if attr.ino == 0 {
+            attr.ino = nodeid;
+        }
+        let reply = EntryOut {
+            nodeid,
+            generation: 0,
+            entry_valid: self.options.timeout,
+            attr_valid: self.options.timeout,
+            entry_valid_nsec: 0,
+            attr_valid_nsec: 0,
+            attr,
+        };
+        proto::put(out, &reply).ok_or(libc::ERANGE)
+    }
+
+    fn getattr(&mut self, nodeid: u64, out: &mut [u8]) -> Result<usize, i32> {
+        let mut attr = self.with_node(nodeid, |file, typ| Ok(self.stat(file, typ)))?;
+        if attr.ino == 0 {
+            attr.ino = nodeid;
+        }
+        let reply = AttrOut {
+            attr_valid: self.options.timeout,
+            attr_valid_nsec: 0,
+            dummy: 0,
+            attr,
+        };
+        proto::put(out, &reply).ok_or(libc::ERANGE)
+    }
+
+    fn readlink(&mut self, nodeid: u64, out: &mut [u8]) -> Result<usize, i32> {
+        self.with_node(nodeid, |file, typ| {
+            if typ != FileType::SymLink {
+                return Err(libc::EINVAL);
+            }
+            read_all(file, 0, out)
+        })
+    }
+
+    fn open(&mut self, nodeid: u64, body: &[u8], out: &mut [u8], dir: bool) -> Result<usize, i32> {
+        let open = proto::parse::<OpenIn>(body).ok_or(libc::EINVAL)?;
+        if open.flags as i32 & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EROFS);
+        }
+        self.with_node(nodeid, |_, typ| match (dir, typ) {
+            (true, FileType::Directory) | (false, FileType::File) => Ok(()),
+            (true, _) => Err(libc::ENOTDIR),
+            (false, FileType::Directory) => Err(libc::EISDIR),
+            (false, _) => Err(libc::ENXIO),
+        })?;
+        let reply = OpenOut {
+            fh: 0,
+            open_flags: if dir { 0 } else { FOPEN_KEEP_CACHE },
+            padding: 0,
+        };
+        proto::put(out, &reply).ok_or(libc::ERANGE)
+    }
+
+    fn read(&mut self, nodeid: u64, body: &[u8], out: &mut [u8]) -> Result<usize, i32> {
+        let read = proto::parse::<ReadIn>(body).ok_or(libc::EINVAL)?;
+        let n = core::cmp::min(read.size as usize, out.len());
+        self.with_node(nodeid, |file, _| read_all(file, read.offset, &mut out[..n]))
+    }
+
+    /// List a directory.  The offset of an entry is its position plus one.
+    fn readdir(&mut self, nodeid: u64, body: &[u8], out: &mut [u8]) -> Result<usize, i32> {
+        let read = proto::parse::<ReadIn>(body).ok_or(libc::EINVAL)?;
+        let size = core::cmp::min(read.size as usize, out.len());
+        self.with_node(nodeid, |file, _| {
+            let mut dir = file.dir().ok_or(libc::ENOTDIR)?;
+            let mut buf = [0u8; 256];
+            let (mut index, mut pos) = (0, 0);
+            while let Some(entry) = dir.next(&mut buf).map_err(|_| libc::EIO)? {
+                if entry.typ == FileType::Unknown {
+                    continue;
+                }
+                index += 1;
+                if index <= read.offset {
+                    continue;
+                }
+                let nlen = core::cmp::min(entry.nlen, buf.len());
+                let start = pos + size_of::<Dirent>();
+                let reclen = (size_of::<Dirent>() + nlen + 7) & !7;
+                if pos + reclen > size {
+                    break;
+                }
+                let dirent = Dirent {
+                    ino: if entry.id != 0 { entry.id } else { UNKNOWN_INO },
+                    off: index,
+                    namelen: nlen as u32,
+                    typ: type_bits(entry.typ) >> 12,
+                };
+                proto::put(&mut out[pos..], &dirent);
+                out[start..start + nlen].copy_from_slice(&buf[..nlen]);
+                out[start + nlen..pos + reclen].fill(0);
+                pos += reclen;
+            }
+            Ok(pos)
+        })
+    }
+
+    fn statfs(&mut self, out: &mut [u8]) -> Result<usize, i32> {
+        let reply = StatfsOut {
+            files: self.len() as u64,
+            bsize: BLOCK_SIZE,
+            namelen: 255,
+            frsize: BLOCK_SIZE,
+            ..Default::default()
+        };
+        proto::put(out, &reply).ok_or(libc::ERANGE)
+    }
+}
+
+/// Fill the buffer until the end of the file.
+fn read_all<F: File>(file: &F, offset: Offset, buf: &mut [u8]) -> Result<usize, i32> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match file
+            .read_bytes(offset + pos as Offset, &mut buf[pos..])
+            .map_err(|_| libc::EIO)?
+        {
+            0 => break,
+            n => pos += n,
+        }
+    }
+    Ok(pos)
+}
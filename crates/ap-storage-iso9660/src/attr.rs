@@ -0,0 +1,83 @@
+//! File attributes for ISO 9660.
+
+use super::file::File;
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::Read;
+use ap_util_slice_writer::*;
+
+new_attr!(FLAGS, U64, "File flags of the directory record.");
+new_attr!(NLINKS, U64, "Number of hard-links to this file.");
+
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
+}
+
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            FLAGS,
+            NLINKS,
+            attr::ATIME,
+            attr::BTIME,
+            attr::CTIME,
+            attr::FTYPE,
+            attr::GID,
+            attr::ID,
+            attr::MODE,
+            attr::MTIME,
+            attr::RDEV,
+            attr::SIZE,
+            attr::UID,
+        ]
+        .iter()
+    }
+}
+
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let (record, rr) = (&self.file.record, &self.file.rr);
+        Some(match name {
+            FLAGS => (record.flags as u64).into(),
+            NLINKS if rr.mode.is_some() => (rr.nlinks as u64).into(),
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            attr::GID if rr.mode.is_some() => (rr.gid as u64).into(),
+            attr::ID => self.file.id.into(),
+            attr::MODE => self.file.mode().into(),
+            attr::RDEV => rr.rdev?.into(),
+            attr::SIZE => self.file.size.into(),
+            attr::UID if rr.mode.is_some() => (rr.uid as u64).into(),
+            attr::ATIME => Value::Time(rr.times[2].unwrap_or(record.mtime())),
+            attr::BTIME => Value::Time(rr.times[0]?),
+            attr::CTIME => Value::Time(rr.times[3]?),
+            attr::MTIME => Value::Time(rr.times[1].unwrap_or(record.mtime())),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                FLAGS,
+                NLINKS,
+                attr::ATIME,
+                attr::BTIME,
+                attr::CTIME,
+                attr::FTYPE,
+                attr::GID,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::RDEV,
+                attr::SIZE,
+                attr::UID,
+            ]
+        )
+    }
+}
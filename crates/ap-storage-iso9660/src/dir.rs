@@ -0,0 +1,65 @@
+//! Directory iteration for ISO 9660.
+
+use super::{file::File, record, RockRidge};
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    file::FileType,
+    Error, Offset, Read,
+};
+
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
+    offset: Offset,
+    /// The previous record has more extents.
+    continued: bool,
+}
+
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Self {
+        Self {
+            file,
+            offset: 0,
+            continued: false,
+        }
+    }
+}
+
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        let fs = self.file.fs;
+        let block_size = fs.block_size as Offset;
+        let mut buf = [0; 256];
+        while self.offset < self.file.size {
+            let Some(rec) = fs.read_record(fs.data(&self.file.record) + self.offset, &mut buf)? else {
+                // skip the padding up to the next block
+                self.offset = (self.offset / block_size + 1) * block_size;
+                continue;
+            };
+            let offset = self.offset;
+            self.offset += rec.len as Offset;
+
+            // the following extents of a file are not listed
+            let skip = self.continued;
+            self.continued = rec.is_multi_extent();
+            if skip {
+                continue;
+            }
+
+            let rr = RockRidge::new(fs, &rec, &buf)?;
+            if rr.relocated {
+                continue;
+            }
+            let typ = match rec.is_parent() {
+                true => FileType::Parent,
+                false => record::file_type(&rec, &rr),
+            };
+            return Ok(Some(DirEntry {
+                offset,
+                id: record::file_id(fs, &rec, &rr),
+                nlen: record::name(fs, &rec, &buf, name)?,
+                typ,
+            }));
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,178 @@
+//! Files on ISO 9660.
+
+use super::{attr::Attr, dir::Dir, record, IsoFs, Record, RockRidge};
+use ap_storage::{file::FileType, msg2err, Error, Offset, Read};
+use core::cell::RefCell;
+
+/// The longest symlink target.
+const PATH_MAX: usize = 4096;
+
+/// The most extents of a single file.
+const MAX_EXTENTS: usize = 1 << 16;
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a IsoFs<'a, D>,
+    pub(crate) record: Record,
+    pub(crate) rr: RockRidge,
+    pub(crate) size: Offset,
+    pub(crate) id: Offset,
+    /// The current extent and its offset in the file.
+    cache: RefCell<(Offset, Record)>,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            record: self.record,
+            rr: self.rr,
+            size: self.size,
+            id: self.id,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("fs", &self.fs)
+            .field("record", &self.record)
+            .field("rr", &self.rr)
+            .field("size", &self.size)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    /// Create a file from a directory record.
+    pub(crate) fn new(fs: &'a IsoFs<'a, D>, mut record: Record, buf: &[u8]) -> Result<Self, Error> {
+        let mut rr = RockRidge::new(fs, &record, buf)?;
+        let id = record::file_id(fs, &record, &rr);
+
+        // relocated directories are represented by their self-pointer
+        if let Some(block) = rr.relocation() {
+            let mut buf = [0; 256];
+            record = fs
+                .read_record(fs.block(block), &mut buf)?
+                .ok_or(msg2err!("relocated directory"))?;
+            rr = RockRidge::new(fs, &record, &buf)?;
+        }
+
+        let mut res = Self {
+            fs,
+            record,
+            rr,
+            size: record.size as Offset,
+            id,
+            cache: RefCell::new((0, record)),
+        };
+        match res.ftype() {
+            FileType::SymLink => res.size = res.symlink(&mut [0; PATH_MAX])? as Offset,
+            _ if record.is_multi_extent() => {
+                let mut last = record;
+                for _ in 0..MAX_EXTENTS {
+                    if !last.is_multi_extent() {
+                        break;
+                    }
+                    last = fs.next_record(&last)?;
+                    res.size += last.size as Offset;
+                }
+            }
+            _ => {}
+        }
+        Ok(res)
+    }
+
+    /// The file type without the special handling of the parent pointers.
+    pub fn ftype(&self) -> FileType {
+        record::file_type(&self.record, &self.rr)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.ftype() == FileType::Directory
+    }
+
+    /// The permission bits from Rock Ridge or read-only ones.
+    pub fn mode(&self) -> u64 {
+        match self.rr.mode {
+            Some(mode) => mode as u64 & 0o7777,
+            None if self.record.is_dir() => 0o555,
+            None => 0o444,
+        }
+    }
+
+    /// Read the target of a symlink into the buffer.
+    fn symlink(&self, out: &mut [u8]) -> Result<usize, Error> {
+        let mut buf = [0; 256];
+        let record = self
+            .fs
+            .read_record(self.record.pos, &mut buf)?
+            .ok_or(msg2err!("symlink record"))?;
+        record::symlink(self.fs, &record, &buf, out)
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.is_dir() {
+            return Some(Dir::new(self));
+        }
+        None
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        if offset >= self.size {
+            return Err(msg2err!("offset out of range"));
+        }
+        let mut buf = [0; 256];
+        let record = self
+            .fs
+            .read_record(self.fs.data(&self.record) + offset, &mut buf)?
+            .ok_or(msg2err!("no directory record"))?;
+        Self::new(self.fs, record, &buf)
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+        if self.ftype() == FileType::SymLink {
+            let mut target = [0; PATH_MAX];
+            let n = self.symlink(&mut target)?;
+            let x = core::cmp::min(buf.len(), n - offset as usize);
+            buf[..x].copy_from_slice(&target[offset as usize..offset as usize + x]);
+            return Ok(x);
+        }
+
+        // rewind or follow the extents
+        let mut cache = self.cache.borrow_mut();
+        if offset < cache.0 {
+            *cache = (0, self.record);
+        }
+        while offset >= cache.0 + cache.1.size as Offset {
+            if !cache.1.is_multi_extent() {
+                return Ok(0);
+            }
+            let next = self.fs.next_record(&cache.1)?;
+            *cache = (cache.0 + cache.1.size as Offset, next);
+        }
+        let ofs = offset - cache.0;
+        let max_n = core::cmp::min(buf.len() as Offset, cache.1.size as Offset - ofs) as usize;
+        self.fs
+            .disk()
+            .read_bytes(self.fs.data(&cache.1) + ofs, &mut buf[..max_n])
+    }
+}
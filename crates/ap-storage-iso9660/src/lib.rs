@@ -0,0 +1,415 @@
+//! Read ISO 9660 filesystems.
+//!
+//! - volume descriptor set, path table and directory records
+//! - multi-extent files larger than 4GB
+//! - Joliet names from the supplementary volume descriptor
+//! - Rock Ridge names, permissions, owners, symlinks, devices and timestamps
+//! - relocated directories deeper than eight levels
+//!
+//! Rock Ridge is preferred over Joliet, which is preferred over the plain ISO names.
+
+#![no_std]
+
+use ap_storage::{msg2err, Error, FileSystem, Offset, Read, ReadExt};
+
+mod attr;
+mod dir;
+mod file;
+mod record;
+
+pub use record::{Record, RockRidge};
+
+/// The byte offset of the volume descriptor set.
+const VD_START: Offset = 16 * 2048;
+
+/// The mount options.
+#[derive(Default, Clone)]
+pub struct Options {
+    /// Ignore the Rock Ridge extensions.
+    pub ignore_rock_ridge: bool,
+    /// Ignore the Joliet directory tree.
+    pub ignore_joliet: bool,
+    /// Lowercase the plain ISO names.
+    pub lower_iso_name: bool,
+}
+
+/// The directory tree in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// Plain ISO 9660 names.
+    Iso,
+    /// UCS-2 names from the supplementary volume descriptor.
+    Joliet,
+    /// POSIX semantics via the System Use Sharing Protocol.
+    RockRidge,
+}
+
+/// An ISO 9660 filesystem.
+///
+/// The filesystem is `Sync` and can be shared between threads if the disk is.
+pub struct IsoFs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+    /// The logical block size.
+    block_size: u32,
+    /// The number of logical blocks.
+    blocks: u32,
+    /// The directory tree in use.
+    variant: Variant,
+    /// The bytes to skip in every System Use area.
+    susp_skip: u8,
+    /// The root directory record.
+    root: Record,
+    /// The location of the L-type path table.
+    path_table: u32,
+    /// The size of the path table in bytes.
+    path_table_size: u32,
+    /// The volume identifier.
+    volume_id: [u8; 32],
+    /// Mount options.
+    options: Options,
+}
+
+impl<D: ?Sized> Clone for IsoFs<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            disk: self.disk,
+            options: self.options.clone(),
+            ..*self
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for IsoFs<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(
+            fmt,
+            "IsoFs({:?}, bs {}, blocks {})",
+            self.variant, self.block_size, self.blocks
+        )
+    }
+}
+
+/// An entry of the path table.
+#[derive(Debug, Clone, Copy)]
+pub struct PathEntry {
+    /// The first block of the directory.
+    pub extent: u32,
+    /// The one-based index of the parent entry.
+    pub parent: u16,
+}
+
+impl<'a, D: Read + ?Sized> IsoFs<'a, D> {
+    /// Mount the filesystem.
+    pub fn new(disk: &'a D, options: Options) -> Result<Self, Error> {
+        let mut primary = None;
+        let mut joliet = None;
+        for i in 0..64 {
+            let vd: [u8; 2048] = (&disk as &dyn Read).read_object(VD_START + i * 2048)?;
+            if &vd[1..6] != b"CD001" || vd[6] != 1 {
+                return Err(msg2err!("volume descriptor"));
+            }
+            match vd[0] {
+                1 if primary.is_none() => primary = Some(vd),
+                2 if joliet.is_none() && vd[88] == b'%' && vd[89] == b'/' && b"@CE".contains(&vd[90]) => {
+                    joliet = Some(vd)
+                }
+                255 => break,
+                _ => {}
+            }
+        }
+        let pvd = primary.ok_or(msg2err!("no primary volume descriptor"))?;
+        let block_size = u16::from_le_bytes([pvd[128], pvd[129]]) as u32;
+        if !(512..=2048).contains(&block_size) || !block_size.is_power_of_two() {
+            return Err(msg2err!("block size"));
+        }
+
+        let mut res = Self {
+            disk,
+            block_size,
+            blocks: le32(&pvd, 80),
+            variant: Variant::Iso,
+            susp_skip: 0,
+            root: Record::parse(&pvd[156..190], VD_START).ok_or(msg2err!("root record"))?,
+            path_table: le32(&pvd, 140),
+            path_table_size: le32(&pvd, 132),
+            volume_id: pvd[40..72].try_into().unwrap(),
+            options,
+        };
+        if !res.options.ignore_rock_ridge {
+            if let Some(skip) = res.detect_rock_ridge()? {
+                res.variant = Variant::RockRidge;
+                res.susp_skip = skip;
+                return Ok(res);
+            }
+        }
+        if let Some(svd) = joliet.filter(|_| !res.options.ignore_joliet) {
+            res.variant = Variant::Joliet;
+            res.root = Record::parse(&svd[156..190], VD_START).ok_or(msg2err!("root record"))?;
+            res.path_table = le32(&svd, 140);
+            res.path_table_size = le32(&svd, 132);
+            res.volume_id = svd[40..72].try_into().unwrap();
+        }
+        Ok(res)
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    /// The directory tree in use.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// The volume identifier.
+    ///
+    /// The raw bytes are space padded and UCS-2 encoded with Joliet.
+    pub fn volume_id(&self) -> &[u8] {
+        &self.volume_id
+    }
+
+    /// The size of the filesystem in bytes.
+    pub fn size(&self) -> Offset {
+        self.blocks as Offset * self.block_size as Offset
+    }
+
+    /// The byte offset of a logical block.
+    pub(crate) fn block(&self, lba: u32) -> Offset {
+        lba as Offset * self.block_size as Offset
+    }
+
+    /// Read the record at an absolute position.
+    ///
+    /// Records do not cross block boundaries, so the rest of a block is zero padding.
+    pub(crate) fn read_record(&self, pos: Offset, buf: &mut [u8; 256]) -> Result<Option<Record>, Error> {
+        let n = core::cmp::min(
+            256,
+            self.block_size as usize - (pos % self.block_size as Offset) as usize,
+        );
+        self.disk().read_exact(pos, &mut buf[..n])?;
+        if buf[0] == 0 {
+            return Ok(None);
+        }
+        Ok(Some(
+            Record::parse(&buf[..n], pos).ok_or(msg2err!("invalid directory record"))?,
+        ))
+    }
+
+    /// The record following in the directory, skipping the padding at the end of a block.
+    pub(crate) fn next_record(&self, record: &Record) -> Result<Record, Error> {
+        let mut buf = [0; 256];
+        let pos = record.pos + record.len as Offset;
+        if let Some(res) = self.read_record(pos, &mut buf)? {
+            return Ok(res);
+        }
+        let pos = (pos / self.block_size as Offset + 1) * self.block_size as Offset;
+        self.read_record(pos, &mut buf)?.ok_or(msg2err!("missing extent"))
+    }
+
+    /// The start of the data of a record after the extended attributes.
+    pub(crate) fn data(&self, record: &Record) -> Offset {
+        self.block(record.extent + record.ext_len as u32)
+    }
+
+    /// Check the self-pointer of the root directory for the SP and RR entries.
+    ///
+    /// Returns the bytes to skip in every System Use area.
+    fn detect_rock_ridge(&self) -> Result<Option<u8>, Error> {
+        let mut buf = [0; 256];
+        let Some(dot) = self.read_record(self.block(self.root.extent), &mut buf)? else {
+            return Ok(None);
+        };
+        let mut skip = None;
+        let mut rr = false;
+        record::system_use(self, dot.system_use(&buf, 0), &mut |sig, data| {
+            match &sig {
+                b"SP" if data.len() >= 3 && data[0] == 0xbe && data[1] == 0xef => skip = Some(data[2]),
+                b"ER" | b"RR" | b"PX" | b"NM" => rr = true,
+                _ => {}
+            }
+            true
+        })?;
+        Ok(skip.filter(|_| rr))
+    }
+
+    /// Call the function for every entry of the path table together with its name.
+    ///
+    /// The iteration stops early if the function returns false.
+    pub fn path_table(&self, f: &mut dyn FnMut(PathEntry, &[u8]) -> bool) -> Result<(), Error> {
+        let start = self.block(self.path_table);
+        let mut pos = 0;
+        let mut buf = [0; 8 + 255];
+        while pos + 8 <= self.path_table_size as Offset {
+            self.disk().read_exact(start + pos, &mut buf[..8])?;
+            let nlen = buf[0] as usize;
+            if nlen == 0 {
+                break;
+            }
+            self.disk().read_exact(start + pos + 8, &mut buf[8..8 + nlen])?;
+            let entry = PathEntry {
+                extent: le32(&buf, 2),
+                parent: u16::from_le_bytes([buf[6], buf[7]]),
+            };
+            if !f(entry, &buf[8..8 + nlen]) {
+                break;
+            }
+            pos += 8 + ((nlen + 1) & !1) as Offset;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for IsoFs<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        // take the self-pointer as it carries the Rock Ridge attributes
+        let mut buf = [0; 256];
+        let dot = self
+            .read_record(self.block(self.root.extent), &mut buf)?
+            .ok_or(msg2err!("root directory"))?;
+        file::File::new(self, dot, &buf)
+    }
+}
+
+/// A little-endian u32 at the position.
+pub(crate) fn le32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{
+        attr::{self, Attributes},
+        directory::DirIterator,
+        file::{File, FileType},
+    };
+    use ap_storage_memory::ReadSlice;
+    use std::{os::unix::fs::symlink, path::Path, process::Command, vec::Vec};
+
+    /// Build an image with bsdtar.  Returns None if the tool is missing.
+    fn mkisofs(src: &Path, options: &str) -> Option<Vec<u8>> {
+        let name = src.with_extension("iso");
+        let res = Command::new("bsdtar")
+            .env("LANG", "C.UTF-8")
+            .args(["-cf", name.to_str()?, "--format", "iso9660", "--options", options, "-C"])
+            .arg(src)
+            .arg(".")
+            .status();
+        let data = match res {
+            Ok(status) if status.success() => std::fs::read(&name).ok(),
+            _ => None,
+        };
+        let _ = std::fs::remove_file(name);
+        data
+    }
+
+    fn read_all(file: &impl Read) -> Vec<u8> {
+        let mut res = std::vec![0; 8192];
+        let n = file.read_bytes(0, &mut res).unwrap();
+        res.truncate(n);
+        res
+    }
+
+    #[test]
+    fn dates() {
+        let date = |month| record::timestamp(&[124, month, 2, 3, 4, 5, 4]);
+        assert_eq!(date(1), Some(1_704_161_045_000_000_000));
+        assert_eq!(date(200), date(12));
+        assert_eq!(
+            record::timestamp(b"2024010203040550\x04"),
+            Some(1_704_161_045_500_000_000)
+        );
+        assert_eq!(
+            record::timestamp(b"2024990203040550\x04"),
+            record::timestamp(b"2024120203040550\x04")
+        );
+        assert_eq!(
+            record::timestamp(b"2024000203040550\x04"),
+            record::timestamp(b"2024010203040550\x04")
+        );
+        assert_eq!(record::timestamp(b"0000010203040550\x04"), None);
+    }
+
+    #[test]
+    fn extensions() {
+        let src = std::env::temp_dir().join("ap-storage-iso9660");
+        let _ = std::fs::remove_dir_all(&src);
+        let deep = src.join("sub/a/b/c/d/e/f/g/h");
+        std::fs::create_dir_all(&deep).unwrap();
+        std::fs::write(deep.join("file"), b"deep").unwrap();
+        std::fs::write(src.join("sub/Long Name.txt"), b"hello").unwrap();
+        symlink("../sub/Long Name.txt", src.join("sub/link")).unwrap();
+
+        let variants = [
+            ("rockridge=strict,joliet", Variant::RockRidge),
+            ("!rockridge,joliet", Variant::Joliet),
+            ("!rockridge,!joliet", Variant::Iso),
+        ];
+        for (options, variant) in variants {
+            let Some(data) = mkisofs(&src, options) else {
+                break;
+            };
+            let disk = ReadSlice(&data);
+            let fs = IsoFs::new(&disk, Default::default()).unwrap();
+            assert_eq!(fs.variant(), variant);
+            let root = fs.root().unwrap();
+            let name: &[u8] = match variant {
+                Variant::Iso => b"SUB/LONG_NAM.TXT",
+                _ => b"sub/Long Name.txt",
+            };
+            let file = root.clone().lookup_path(name).unwrap();
+            assert_eq!(read_all(&file), b"hello");
+
+            let deep = match variant {
+                Variant::Iso => &b"RR_MOVED/H/FILE"[..],
+                _ => b"sub/a/b/c/d/e/f/g/h/file",
+            };
+            assert_eq!(read_all(&root.clone().lookup_path(deep).unwrap()), b"deep");
+            if variant != Variant::RockRidge {
+                continue;
+            }
+
+            // relocated directories keep their place and identity
+            let h = root.clone().lookup_path(b"sub/a/b/c/d/e/f/g/h").unwrap();
+            let g = root.clone().lookup_path(b"sub/a/b/c/d/e/f/g").unwrap();
+            let mut dir = g.dir().unwrap();
+            let mut name = [0; 32];
+            let mut found = false;
+            while let Some(entry) = dir.next(&mut name).unwrap() {
+                if &name[..entry.nlen] == b"h" {
+                    assert_eq!(Some(entry.id), h.attr().get(attr::ID, &mut []).and_then(|x| x.as_u64()));
+                    found = true;
+                }
+            }
+            assert!(found);
+            let moved = root.lookup(b"rr_moved").unwrap().unwrap();
+            let mut dir = moved.dir().unwrap();
+            while let Some(entry) = dir.next(&mut name).unwrap() {
+                assert_eq!(entry.typ, FileType::Parent);
+            }
+
+            let link = root.clone().lookup_path(b"sub/link").unwrap();
+            assert_eq!(link.ftype(), FileType::SymLink);
+            assert_eq!(read_all(&link), b"../sub/Long Name.txt");
+            let mode = file.attr().get(attr::MODE, &mut []).unwrap().as_u64();
+            assert_eq!(mode, Some(0o644));
+
+            // invalid months in the timestamps of the TF entries
+            let mut data = data.clone();
+            let mut pos = 0;
+            while let Some(x) = data[pos..].windows(4).position(|x| x[..2] == *b"TF" && x[3] == 1) {
+                pos += x;
+                data[pos + 6] = 200;
+                pos += 2;
+            }
+            let disk = ReadSlice(&data);
+            let fs = IsoFs::new(&disk, Default::default()).unwrap();
+            let file = fs.root().unwrap().lookup_path(b"sub/Long Name.txt").unwrap();
+            assert!(file.attr().get(attr::MTIME, &mut []).is_some());
+        }
+        std::fs::remove_dir_all(src).unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+//! Directory records and the Rock Ridge extensions.
+
+use super::{le32, IsoFs, Variant};
+use ap_storage::{file::FileType, Error, Offset, Read, ReadExt};
+use ap_util_date::{date2ts, time2ts, Time};
+
+/// The file is a directory.
+const FLAG_DIRECTORY: u8 = 0x02;
+/// An associated file like a resource fork.
+const FLAG_ASSOCIATED: u8 = 0x04;
+/// Further records follow for the next extents of the file.
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// The maximum number of continuation areas followed.
+const MAX_CONTINUATIONS: usize = 32;
+
+/// A directory record.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Record {
+    /// The absolute position of the record.
+    pub pos: Offset,
+    /// The length of the record.
+    pub len: u8,
+    /// The blocks of the extended attribute record before the data.
+    pub ext_len: u8,
+    /// The first block of the extent.
+    pub extent: u32,
+    /// The size of the extent.
+    pub size: u32,
+    /// The recording date.
+    pub date: [u8; 7],
+    /// The file flags.
+    pub flags: u8,
+    /// The length of the file identifier.
+    pub name_len: u8,
+    /// The first byte of the file identifier.
+    name0: u8,
+}
+
+impl Record {
+    /// Parse the record at the start of the buffer.
+    pub fn parse(buf: &[u8], pos: Offset) -> Option<Self> {
+        let len = *buf.first()? as usize;
+        if len < 34 || len > buf.len() || 33 + buf[32] as usize > len {
+            return None;
+        }
+        Some(Self {
+            pos,
+            len: len as u8,
+            ext_len: buf[1],
+            extent: le32(buf, 2),
+            size: le32(buf, 10),
+            date: buf[18..25].try_into().unwrap(),
+            flags: buf[25],
+            name_len: buf[32],
+            name0: buf[33],
+        })
+    }
+
+    /// The raw file identifier.
+    pub fn name<'b>(&self, buf: &'b [u8]) -> &'b [u8] {
+        &buf[33..33 + self.name_len as usize]
+    }
+
+    /// The System Use area after the identifier.
+    pub fn system_use<'b>(&self, buf: &'b [u8], skip: u8) -> &'b [u8] {
+        let start = 34 + (self.name_len as usize & !1) + skip as usize;
+        &buf[core::cmp::min(start, self.len as usize)..self.len as usize]
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0
+    }
+
+    pub fn is_multi_extent(&self) -> bool {
+        self.flags & FLAG_MULTI_EXTENT != 0
+    }
+
+    /// The self-pointer or the parent directory.
+    pub fn is_parent(&self) -> bool {
+        self.name_len == 1 && self.name0 <= 1
+    }
+
+    /// The recording date in nanoseconds since 1970.
+    pub fn mtime(&self) -> Time {
+        let d = self.date;
+        let ts = date2ts(d[2] as u32, d[1] as u32, 1900 + d[0] as u32) + time2ts(d[3] as u32, d[4] as u32, d[5] as u32);
+        (ts - d[6] as i8 as Time * 15 * 60) * 1_000_000_000
+    }
+}
+
+/// The attributes from the Rock Ridge entries of a record.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RockRidge {
+    /// The POSIX file mode including the type.
+    pub mode: Option<u32>,
+    pub nlinks: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Device number as `major << 32 | minor`.
+    pub rdev: Option<u64>,
+    /// Creation, modification, access and attribute change time in nanoseconds.
+    pub times: [Option<Time>; 4],
+    /// The block of a relocated child directory.
+    pub child: Option<u32>,
+    /// The block of the real parent of a relocated directory.
+    pub parent: Option<u32>,
+    /// The directory was moved here and is reachable via its child link.
+    pub relocated: bool,
+}
+
+impl RockRidge {
+    /// Collect the entries of a record.
+    pub(crate) fn new<D: Read + ?Sized>(fs: &IsoFs<'_, D>, record: &Record, buf: &[u8]) -> Result<Self, Error> {
+        let mut res = Self::default();
+        if fs.variant != Variant::RockRidge {
+            return Ok(res);
+        }
+        system_use(fs, record.system_use(buf, fs.susp_skip), &mut |sig, data| {
+            match &sig {
+                b"PX" if data.len() >= 32 => {
+                    res.mode = Some(le32(data, 0));
+                    res.nlinks = le32(data, 8);
+                    res.uid = le32(data, 16);
+                    res.gid = le32(data, 24);
+                }
+                b"PN" if data.len() >= 16 => {
+                    let (high, low) = (le32(data, 0) as u64, le32(data, 8) as u64);
+                    // a Linux dev_t fits into the lower half
+                    res.rdev = Some(match high {
+                        0 => (low & 0xfff00) << 24 | (low & 0xff) | (low >> 12) & 0xfff00,
+                        _ => high << 32 | low,
+                    });
+                }
+                b"TF" if !data.is_empty() => {
+                    let flags = data[0];
+                    let size = if flags & 0x80 != 0 { 17 } else { 7 };
+                    let mut pos = 1;
+                    for i in 0..7 {
+                        if flags & (1 << i) == 0 {
+                            continue;
+                        }
+                        if pos + size > data.len() {
+                            break;
+                        }
+                        if i < 4 {
+                            res.times[i] = timestamp(&data[pos..pos + size]);
+                        }
+                        pos += size;
+                    }
+                }
+                b"CL" if data.len() >= 4 => res.child = Some(le32(data, 0)),
+                b"PL" if data.len() >= 4 => res.parent = Some(le32(data, 0)),
+                b"RE" => res.relocated = true,
+                _ => {}
+            }
+            true
+        })?;
+        Ok(res)
+    }
+
+    /// The target block of a relocation.
+    pub fn relocation(&self) -> Option<u32> {
+        self.child.or(self.parent)
+    }
+}
+
+/// Convert the short or long form of a timestamp to nanoseconds.
+pub(crate) fn timestamp(data: &[u8]) -> Option<Time> {
+    if data.len() == 7 {
+        return Some(
+            Record {
+                date: data.try_into().ok()?,
+                ..Default::default()
+            }
+            .mtime(),
+        );
+    }
+    let digits = |range: core::ops::Range<usize>| {
+        data[range]
+            .iter()
+            .try_fold(0u32, |acc, x| x.is_ascii_digit().then(|| acc * 10 + (x - b'0') as u32))
+    };
+    let (year, month) = (digits(0..4)?, digits(4..6)?);
+    if year == 0 {
+        return None;
+    }
+    let ts = date2ts(digits(6..8)?, month, year) + time2ts(digits(8..10)?, digits(10..12)?, digits(12..14)?);
+    Some((ts - data[16] as i8 as Time * 15 * 60) * 1_000_000_000 + digits(14..16)? as Time * 10_000_000)
+}
+
+/// Call the function for every System Use entry including the continuation areas.
+///
+/// The iteration stops early if the function returns false.
+pub(crate) fn system_use<D: Read + ?Sized>(
+    fs: &IsoFs<'_, D>,
+    area: &[u8],
+    f: &mut dyn FnMut([u8; 2], &[u8]) -> bool,
+) -> Result<(), Error> {
+    let mut buf = [0; 2048];
+    let mut n = area.len();
+    buf[..n].copy_from_slice(area);
+    for _ in 0..MAX_CONTINUATIONS {
+        let mut next = None;
+        let mut pos = 0;
+        while pos + 4 <= n {
+            let len = buf[pos + 2] as usize;
+            if len < 4 || pos + len > n {
+                break;
+            }
+            let (sig, data) = ([buf[pos], buf[pos + 1]], &buf[pos + 4..pos + len]);
+            match &sig {
+                b"CE" if data.len() >= 24 => next = Some((le32(data, 0), le32(data, 8), le32(data, 16))),
+                b"ST" => break,
+                _ => {
+                    if !f(sig, data) {
+                        return Ok(());
+                    }
+                }
+            }
+            pos += len;
+        }
+        let Some((block, offset, len)) = next else {
+            break;
+        };
+        n = core::cmp::min(len as usize, buf.len());
+        fs.disk()
+            .read_exact(fs.block(block) + offset as Offset, &mut buf[..n])?;
+    }
+    Ok(())
+}
+
+/// The file type from the flags or the Rock Ridge mode.
+pub(crate) fn file_type(record: &Record, rr: &RockRidge) -> FileType {
+    if rr.child.is_some() {
+        return FileType::Directory;
+    }
+    match rr.mode.map(|x| x & 0o170000) {
+        Some(0o040000) => FileType::Directory,
+        Some(0o100000) => FileType::File,
+        Some(0o120000) => FileType::SymLink,
+        Some(0o020000) => FileType::CharDevice,
+        Some(0o060000) => FileType::BlockDevice,
+        Some(0o010000) => FileType::Fifo,
+        Some(0o140000) => FileType::Socket,
+        Some(_) => FileType::Unknown,
+        None if record.is_dir() => FileType::Directory,
+        None if record.flags & FLAG_ASSOCIATED != 0 => FileType::Unknown,
+        None => FileType::File,
+    }
+}
+
+/// A unique ID for a record.
+///
+/// Directories and files with data are identified by their extent, so hard links share the ID.
+/// Empty files take the position of the record, which is odd to not overlap with extents.
+pub(crate) fn file_id<D: Read + ?Sized>(fs: &IsoFs<'_, D>, record: &Record, rr: &RockRidge) -> Offset {
+    if let Some(block) = rr.relocation() {
+        fs.block(block)
+    } else if record.is_dir() || record.size != 0 {
+        fs.block(record.extent)
+    } else {
+        record.pos | 1
+    }
+}
+
+/// Append bytes to a name and truncate it to the buffer.
+fn push(out: &mut [u8], n: &mut usize, bytes: &[u8]) {
+    let x = core::cmp::min(bytes.len(), out.len() - *n);
+    out[*n..*n + x].copy_from_slice(&bytes[..x]);
+    *n += x;
+}
+
+/// Decode the name of a record into the buffer and return its length.
+pub(crate) fn name<D: Read + ?Sized>(
+    fs: &IsoFs<'_, D>,
+    record: &Record,
+    buf: &[u8],
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    let mut n = 0;
+    if record.is_parent() {
+        push(out, &mut n, &b".."[..1 + record.name0 as usize]);
+        return Ok(n);
+    }
+    if fs.variant == Variant::RockRidge {
+        let mut found = false;
+        system_use(fs, record.system_use(buf, fs.susp_skip), &mut |sig, data| {
+            if &sig == b"NM" && !data.is_empty() {
+                found = true;
+                push(out, &mut n, &data[1..]);
+                return data[0] & 1 != 0;
+            }
+            true
+        })?;
+        if found {
+            return Ok(n);
+        }
+    }
+    let raw = record.name(buf);
+    if fs.variant == Variant::Joliet {
+        let end = raw
+            .chunks_exact(2)
+            .position(|x| x == [0, b';'])
+            .map_or(raw.len(), |x| x * 2);
+        return Ok(core::cmp::min(ap_util_cs0::decode_utf16be(&raw[..end], out), out.len()));
+    }
+
+    // drop the version and an empty extension
+    let mut raw = raw.split(|x| *x == b';').next().unwrap_or_default();
+    if let [rest @ .., b'.'] = raw {
+        raw = rest;
+    }
+    push(out, &mut n, raw);
+    if fs.options.lower_iso_name {
+        out[..n].make_ascii_lowercase();
+    }
+    Ok(n)
+}
+
+/// Assemble the target of a symlink from the SL entries and return its length.
+pub(crate) fn symlink<D: Read + ?Sized>(
+    fs: &IsoFs<'_, D>,
+    record: &Record,
+    buf: &[u8],
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    let mut n = 0;
+    let mut slash = false;
+    system_use(fs, record.system_use(buf, fs.susp_skip), &mut |sig, data| {
+        if &sig != b"SL" || data.is_empty() {
+            return true;
+        }
+        let mut pos = 1;
+        while pos + 2 <= data.len() {
+            let (flags, len) = (data[pos], data[pos + 1] as usize);
+            let content = &data[pos + 2..core::cmp::min(pos + 2 + len, data.len())];
+            if slash {
+                push(out, &mut n, b"/");
+            }
+            match flags & 0xe {
+                0x2 => push(out, &mut n, b"."),
+                0x4 => push(out, &mut n, b".."),
+                0x8 => push(out, &mut n, b"/"),
+                _ => push(out, &mut n, content),
+            }
+            // continued components and the root need no separator
+            slash = flags & 0x9 == 0;
+            pos += 2 + len;
+        }
+        true
+    })?;
+    Ok(n)
+}
@@ -1,5 +1,5 @@
 use crate::file::JsonFile;
-use ap_storage::attr::{Attributes, Value, ID, SIZE};
+use ap_storage::attr::{attr_meta, Attributes, Meta, Value, ID, MODE, SIZE};
 
 pub struct Attr<'a> {
     pub(crate) file: &'a JsonFile<'a>,
@@ -9,7 +9,7 @@ impl<'a> IntoIterator for Attr<'a> {
     type Item = &'a &'a str;
     type IntoIter = core::slice::Iter<'a, &'a str>;
     fn into_iter(self) -> Self::IntoIter {
-        [ID, SIZE].iter()
+        [ID, MODE, SIZE].iter()
     }
 }
 
@@ -17,8 +17,13 @@ impl<'a> Attributes<'a> for Attr<'a> {
     fn get(&self, name: &str, _buf: &mut [u8]) -> Option<Value> {
         Some(match name {
             ID => self.file.id.into(),
+            MODE => self.file.mode().into(),
             SIZE => self.file.size().into(),
             _ => return None,
         })
     }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(name, [ID, MODE, SIZE])
+    }
 }
@@ -16,6 +16,15 @@ impl<'a> JsonFile<'a> {
         }
     }
 
+    /// The permission bits.  The filesystem is read-only.
+    pub fn mode(&self) -> u64 {
+        if self.value.is_object() {
+            0o555
+        } else {
+            0o444
+        }
+    }
+
     /// Size in bytes.
     pub fn size(&self) -> u64 {
         let Ok(v) = serde_json::to_string(self.value) else {
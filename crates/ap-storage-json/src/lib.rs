@@ -25,7 +25,7 @@ impl JsonFS {
                 break;
             }
             ofs += n;
-            if let Err(e) = serde_json::from_slice::<serde_json::Value>(&data[..]) {
+            if let Err(e) = serde_json::from_slice::<serde_json::Value>(&data[..ofs]) {
                 if !e.is_eof() {
                     return Err(msg2err!("invalid JSON"));
                 }
@@ -49,3 +49,18 @@ impl<'a> FileSystem<'a> for JsonFS {
         Ok(file::JsonFile::new(&self.root, "/"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode() {
+        let value = serde_json::json!({"dir": {"a": 1}, "list": [1, 2], "str": "x"});
+        assert_eq!(file::JsonFile::new(&value, "/").mode(), 0o555);
+        assert_eq!(file::JsonFile::new(&value["dir"], "dir").mode(), 0o555);
+        for name in ["list", "str"] {
+            assert_eq!(file::JsonFile::new(&value[name], name).mode(), 0o444);
+        }
+    }
+}
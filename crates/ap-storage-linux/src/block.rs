@@ -0,0 +1,134 @@
+//! Block-device support.
+
+use super::*;
+use ap_storage::{msg2err, Error, Offset, Write};
+
+const BLKGETSIZE64: libc::Ioctl = libc::_IOR::<libc::size_t>(0x12, 114);
+const BLKDISCARD: libc::Ioctl = libc::_IO(0x12, 119);
+const BLKZEROOUT: libc::Ioctl = libc::_IO(0x12, 127);
+
+/// The geometry of a file or block device.
+#[derive(Debug, Clone, Copy)]
+pub struct Geometry {
+    /// The size in bytes after the offset of the disk.
+    pub size: u64,
+    /// The logical sector size.  Direct accesses are aligned to it.
+    pub logical: u32,
+    /// The physical sector size.  Accesses aligned to it are the fastest.
+    pub physical: u32,
+    /// Whether the file is a block device.
+    pub block_device: bool,
+}
+
+impl Geometry {
+    /// Ask the kernel.  Regular files use the preferred I/O size as sector size.
+    pub(crate) fn detect(fd: i32, offset: u64) -> Result<Self, Error> {
+        let stat = fstat(fd)?;
+        if !is_block_device(&stat) {
+            return Ok(Self {
+                size: (stat.st_size as u64).saturating_sub(offset),
+                logical: stat.st_blksize as u32,
+                physical: stat.st_blksize as u32,
+                block_device: false,
+            });
+        }
+        let mut size: u64 = 0;
+        let mut logical: libc::c_int = 0;
+        let mut physical: libc::c_uint = 0;
+        unsafe {
+            check_error(libc::ioctl(fd, BLKGETSIZE64, &mut size) as isize)
+                .map_err(|e| msg2err!("BLKGETSIZE64").context(e))?;
+            check_error(libc::ioctl(fd, libc::BLKSSZGET, &mut logical) as isize)
+                .map_err(|e| msg2err!("BLKSSZGET").context(e))?;
+            check_error(libc::ioctl(fd, libc::BLKPBSZGET, &mut physical) as isize)
+                .map_err(|e| msg2err!("BLKPBSZGET").context(e))?;
+        }
+        Ok(Self {
+            size: size.saturating_sub(offset),
+            logical: logical as u32,
+            physical,
+            block_device: true,
+        })
+    }
+}
+
+/// Stat an open file.
+pub(crate) fn fstat(fd: i32) -> Result<libc::stat, Error> {
+    // SAFETY: stat is plain old data and filled by the kernel.
+    let mut stat: libc::stat = unsafe { core::mem::zeroed() };
+    unsafe { check_error(libc::fstat(fd, &mut stat) as isize).map_err(|e| msg2err!("fstat").context(e))? };
+    Ok(stat)
+}
+
+/// Whether the stat describes a block device.
+pub(crate) fn is_block_device(stat: &libc::stat) -> bool {
+    stat.st_mode & libc::S_IFMT == libc::S_IFBLK
+}
+
+/// Flush the data and metadata to the device.
+pub(crate) fn fsync(fd: i32) -> Result<(), Error> {
+    unsafe { check_error(libc::fsync(fd) as isize).map_err(|e| msg2err!("fsync").context(e))? };
+    Ok(())
+}
+
+/// Zero or discard a range on a block device.  It has to be aligned to the logical sector size.
+fn block_range(fd: i32, request: libc::Ioctl, offset: u64, len: u64) -> Result<(), Error> {
+    let range = [offset, len];
+    unsafe {
+        check_error(libc::ioctl(fd, request, range.as_ptr()) as isize)
+            .map_err(|e| msg2err!("block range ioctl").context(e))?
+    };
+    Ok(())
+}
+
+/// Punch a hole into a regular file.
+fn punch_hole(fd: i32, offset: u64, len: u64) -> Result<(), Error> {
+    unsafe {
+        check_error(libc::fallocate(
+            fd,
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as i64,
+            len as i64,
+        ) as isize)
+        .map_err(|e| msg2err!("discard").context(e))?
+    };
+    Ok(())
+}
+
+/// The zeros written for unaligned parts of a discard.
+pub(crate) static ZEROS: [u8; 512] = [0; 512];
+
+/// Discard a range so that it reads as zeros.
+///
+/// The sector size is only given for block devices.  They use `BLKZEROOUT` for the aligned part.
+/// Unaligned parts are zeroed by writing to the disk.
+pub(crate) fn discard(disk: &dyn Write, base: &LinuxDiskRO, offset: Offset, len: Offset) -> Result<Offset, Error> {
+    let pos = base.offset + offset;
+    let Some(logical) = base.sector else {
+        punch_hole(base.fd, pos, len)?;
+        return Ok(len);
+    };
+    let head = pos % logical;
+    let aligned = len / logical * logical;
+    if head != 0 || aligned == 0 {
+        let n = core::cmp::min(core::cmp::min(logical - head, len), ZEROS.len() as Offset) as usize;
+        return Ok(disk.write_bytes(offset, &ZEROS[..n])? as Offset);
+    }
+    block_range(base.fd, BLKZEROOUT, pos, aligned)?;
+    Ok(aligned)
+}
+
+/// Tell the device that a range is unused.  The content is undefined afterwards.
+///
+/// Block devices use `BLKDISCARD` and only the aligned part of the range is trimmed.
+pub(crate) fn trim(fd: i32, sector: Option<Offset>, offset: Offset, len: Offset) -> Result<(), Error> {
+    let Some(logical) = sector else {
+        return punch_hole(fd, offset, len);
+    };
+    let start = offset.next_multiple_of(logical);
+    let end = (offset + len) / logical * logical;
+    if start < end {
+        block_range(fd, BLKDISCARD, start, end - start)?;
+    }
+    Ok(())
+}
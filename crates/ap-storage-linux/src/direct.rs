@@ -0,0 +1,158 @@
+//! Direct I/O that bypasses the page cache.
+
+use super::*;
+use ap_storage::{msg2err, Error, Offset, Read, Write};
+use core::cell::RefCell;
+
+/// The size of the bounce buffer.
+const BOUNCE_SIZE: usize = 64 * 1024;
+
+/// An aligned buffer for unaligned accesses.
+#[repr(C, align(4096))]
+struct Bounce([u8; BOUNCE_SIZE]);
+
+/// A Linux disk opened with `O_DIRECT`.
+///
+/// Accesses with aligned offsets, lengths and buffers go directly to the
+/// device.  Others are split into pieces that fit into an internal
+/// aligned bounce buffer.  Partial sectors are written with
+/// read-modify-write.
+pub struct LinuxDiskDirect {
+    disk: LinuxDiskRO,
+    writable: bool,
+    /// The required alignment.
+    align: usize,
+    bounce: RefCell<Bounce>,
+}
+
+impl LinuxDiskDirect {
+    /// Open a file or block device for direct I/O at the given offset.
+    pub fn new(filename: &str, offset: u64, writable: bool) -> Result<Self, Error> {
+        let flags = if writable { libc::O_RDWR } else { libc::O_RDONLY };
+        let disk = open(filename, flags | libc::O_DIRECT, offset)?;
+        let align = core::cmp::max(disk.geometry()?.logical as usize, 512);
+        if align > BOUNCE_SIZE || !align.is_power_of_two() {
+            return Err(msg2err!("unsupported sector size"));
+        }
+        Ok(Self {
+            disk,
+            writable,
+            align,
+            bounce: RefCell::new(Bounce([0; BOUNCE_SIZE])),
+        })
+    }
+
+    /// The size and sector sizes of the disk.
+    pub fn geometry(&self) -> Result<Geometry, Error> {
+        self.disk.geometry()
+    }
+
+    /// Tell the device that a range is unused.  The content is undefined afterwards.
+    pub fn trim(&self, offset: Offset, len: Offset) -> Result<(), Error> {
+        self.writer()?;
+        block::trim(self.disk.fd, self.disk.sector, self.disk.offset + offset, len)
+    }
+
+    /// Flush the written data to the device.
+    pub fn sync(&self) -> Result<(), Error> {
+        block::fsync(self.disk.fd)
+    }
+
+    /// Fail for read-only disks.
+    fn writer(&self) -> Result<(), Error> {
+        if !self.writable {
+            return Err(msg2err!("read-only disk"));
+        }
+        Ok(())
+    }
+
+    /// Whether the access can bypass the bounce buffer.
+    fn aligned(&self, pos: u64, ptr: *const u8, len: usize) -> bool {
+        let mask = self.align - 1;
+        pos as usize & mask == 0 && ptr as usize & mask == 0 && len & mask == 0
+    }
+
+    /// Read at an absolute position.
+    fn pread(&self, pos: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let res = unsafe {
+            check_error(libc::pread(
+                self.disk.fd,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                pos as i64,
+            ))
+            .map_err(|e| msg2err!("pread").context(e))?
+        };
+        Ok(res as usize)
+    }
+
+    /// Write at an absolute position.
+    fn pwrite(&self, pos: u64, buf: &[u8]) -> Result<usize, Error> {
+        let res = unsafe {
+            check_error(libc::pwrite(
+                self.disk.fd,
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                pos as i64,
+            ))
+            .map_err(|e| msg2err!("pwrite").context(e))?
+        };
+        Ok(res as usize)
+    }
+}
+
+impl Read for LinuxDiskDirect {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        let pos = self.disk.offset + offset;
+        if self.aligned(pos, buf.as_ptr(), buf.len()) {
+            return self.pread(pos, buf);
+        }
+        let start = pos & !(self.align as u64 - 1);
+        let skip = (pos - start) as usize;
+        let len = core::cmp::min((skip + buf.len()).next_multiple_of(self.align), BOUNCE_SIZE);
+        let bounce = &mut self.bounce.borrow_mut().0;
+        let valid = self.pread(start, &mut bounce[..len])?;
+        let n = core::cmp::min(valid.saturating_sub(skip), buf.len());
+        buf[..n].copy_from_slice(&bounce[skip..skip + n]);
+        Ok(n)
+    }
+}
+
+impl Write for LinuxDiskDirect {
+    fn write_bytes(&self, offset: Offset, buf: &[u8]) -> Result<usize, Error> {
+        self.writer()?;
+        let pos = self.disk.offset + offset;
+        if self.aligned(pos, buf.as_ptr(), buf.len()) {
+            return self.pwrite(pos, buf);
+        }
+        let start = pos & !(self.align as u64 - 1);
+        let skip = (pos - start) as usize;
+        let n = core::cmp::min(buf.len(), BOUNCE_SIZE - skip);
+        let len = (skip + n).next_multiple_of(self.align);
+        let bounce = &mut self.bounce.borrow_mut().0;
+
+        // read-modify-write with zeros after the end of the file
+        let valid = self.pread(start, &mut bounce[..len])?;
+        bounce[valid..len].fill(0);
+        bounce[skip..skip + n].copy_from_slice(&buf[..n]);
+        let written = self.pwrite(start, &bounce[..len])?;
+        if written < skip + n {
+            return Ok(written.saturating_sub(skip));
+        }
+
+        // a regular file must not grow by the padding
+        if valid < len && self.disk.sector.is_none() {
+            let size = core::cmp::max(start + valid as u64, pos + n as u64);
+            unsafe {
+                check_error(libc::ftruncate(self.disk.fd, size as i64) as isize)
+                    .map_err(|e| msg2err!("ftruncate").context(e))?
+            };
+        }
+        Ok(n)
+    }
+
+    fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error> {
+        self.writer()?;
+        block::discard(self, &self.disk, offset, len)
+    }
+}
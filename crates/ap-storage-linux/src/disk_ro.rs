@@ -1,22 +1,23 @@
 use super::*;
 use ap_storage::{msg2err, Error, Offset, Read};
 
-/// A disk backed by a file in Linux.
+/// A disk backed by a file or block device in Linux.
 pub struct LinuxDiskRO {
     pub(crate) fd: i32,
     pub(crate) offset: u64,
+    /// The logical sector size of a block device.
+    pub(crate) sector: Option<u64>,
 }
 
 impl LinuxDiskRO {
     /// Open a read-only disk at the given offset.
     pub fn new(filename: &str, offset: u64) -> Result<Self, Error> {
-        let mut buf = [0u8; libc::PATH_MAX as usize];
-        let filename = str2cstr(filename, &mut buf).ok_or(msg2err!("invalid filename"))?;
-        let fd = unsafe {
-            check_error(libc::open(filename.as_ptr(), libc::O_RDONLY) as isize)
-                .map_err(|e| msg2err!("open").context(e))? as i32
-        };
-        Ok(Self { fd, offset })
+        open(filename, libc::O_RDONLY, offset)
+    }
+
+    /// The size and sector sizes of the disk.
+    pub fn geometry(&self) -> Result<Geometry, Error> {
+        Geometry::detect(self.fd, self.offset)
     }
 }
 
@@ -2,18 +2,28 @@ use super::*;
 use ap_storage::{msg2err, Error, Offset, Read, Write};
 
 /// A writeable Linux disk.
+///
+/// Discarded ranges read as zeros.  Regular files punch holes and block devices use `BLKZEROOUT`.
 pub struct LinuxDiskRW(LinuxDiskRO);
 impl LinuxDiskRW {
     /// Use a file at a certain offset as a Linux disk.
     pub fn new(filename: &str, offset: u64) -> Result<Self, Error> {
-        let mut buf = [0u8; libc::PATH_MAX as usize];
-        let filename = str2cstr(filename, &mut buf).ok_or(msg2err!("invalid filename"))?;
-        let fd = unsafe {
-            check_error(libc::open(filename.as_ptr(), libc::O_RDWR) as isize)
-                .map_err(|e| msg2err!("could not open file").context(e))? as i32
-        };
+        Ok(Self(open(filename, libc::O_RDWR, offset)?))
+    }
 
-        Ok(Self(LinuxDiskRO { fd, offset }))
+    /// The size and sector sizes of the disk.
+    pub fn geometry(&self) -> Result<Geometry, Error> {
+        self.0.geometry()
+    }
+
+    /// Tell the device that a range is unused.  The content is undefined afterwards.
+    pub fn trim(&self, offset: Offset, len: Offset) -> Result<(), Error> {
+        block::trim(self.0.fd, self.0.sector, self.0.offset + offset, len)
+    }
+
+    /// Flush the written data to the device.
+    pub fn sync(&self) -> Result<(), Error> {
+        block::fsync(self.0.fd)
     }
 }
 
@@ -38,15 +48,6 @@ impl Write for LinuxDiskRW {
     }
 
     fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error> {
-        unsafe {
-            check_error(libc::fallocate(
-                self.0.fd,
-                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
-                (self.0.offset + offset) as i64,
-                len as i64,
-            ) as isize)
-            .map_err(|e| msg2err!("discard").context(e))? as i32
-        };
-        Ok(len)
+        block::discard(self, &self.0, offset, len)
     }
 }
@@ -0,0 +1,98 @@
+//! File attributes for host files.
+use super::LinuxFile;
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::file::FileType;
+use ap_util_slice_writer::*;
+
+new_attr!(BLOCKS, U64, "Number of 512-byte blocks allocated.");
+new_attr!(DEV, U64, "Device number of the filesystem as `major << 32 | minor`.");
+new_attr!(NLINKS, U64, "Number of hard-links to this file.");
+
+pub struct Attr<'a> {
+    pub(crate) file: &'a LinuxFile<'a>,
+}
+
+/// A device number in the format of the RDEV attribute.
+fn device(dev: libc::dev_t) -> u64 {
+    (libc::major(dev) as u64) << 32 | libc::minor(dev) as u64
+}
+
+/// Convert seconds and nanoseconds, saturating outside of the years 1678 to 2262.
+fn time(secs: i64, nsecs: i64) -> Value {
+    Value::Time(secs.saturating_mul(1_000_000_000).saturating_add(nsecs))
+}
+
+impl<'a> IntoIterator for Attr<'a> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            BLOCKS,
+            DEV,
+            NLINKS,
+            attr::ATIME,
+            attr::CTIME,
+            attr::FTYPE,
+            attr::GID,
+            attr::ID,
+            attr::MODE,
+            attr::MTIME,
+            attr::RDEV,
+            attr::SIZE,
+            attr::UID,
+        ]
+        .iter()
+    }
+}
+
+impl<'a> Attributes<'a> for Attr<'a> {
+    // the types of the stat fields differ between architectures
+    #[allow(clippy::unnecessary_cast)]
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let stat = &self.file.stat;
+        Some(match name {
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            BLOCKS => (stat.st_blocks as u64).into(),
+            DEV => device(stat.st_dev).into(),
+            NLINKS => (stat.st_nlink as u64).into(),
+            attr::ATIME => time(stat.st_atime as i64, stat.st_atime_nsec as i64),
+            attr::CTIME => time(stat.st_ctime as i64, stat.st_ctime_nsec as i64),
+            attr::GID => (stat.st_gid as u64).into(),
+            attr::ID => (stat.st_ino as u64).into(),
+            attr::MODE => ((stat.st_mode & 0o7777) as u64).into(),
+            attr::MTIME => time(stat.st_mtime as i64, stat.st_mtime_nsec as i64),
+            attr::RDEV => match self.file.ftype() {
+                FileType::CharDevice | FileType::BlockDevice => device(stat.st_rdev).into(),
+                _ => 0u64.into(),
+            },
+            attr::SIZE => (stat.st_size as u64).into(),
+            attr::UID => (stat.st_uid as u64).into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                BLOCKS,
+                DEV,
+                NLINKS,
+                attr::ATIME,
+                attr::CTIME,
+                attr::FTYPE,
+                attr::GID,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::RDEV,
+                attr::SIZE,
+                attr::UID,
+            ]
+        )
+    }
+}
@@ -0,0 +1,106 @@
+//! Directory iterator.
+
+use crate::check_error;
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    file::FileType,
+    msg2err, Error,
+};
+
+/// The size of the header of a `linux_dirent64`.
+const HEADER: usize = 19;
+
+/// A directory iterator on its own file descriptor.
+pub struct LinuxDir {
+    /// The descriptor or the errno of opening it.
+    fd: Result<i32, i32>,
+    /// The position of the next entry.
+    offset: u64,
+    buf: [u8; 2048],
+    pos: usize,
+    len: usize,
+}
+
+impl LinuxDir {
+    /// Open the directory again to get an independent position.
+    pub(crate) fn new(dir: i32) -> Self {
+        let fd = unsafe {
+            check_error(libc::openat(dir, c".".as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) as isize)
+                .map(|fd| fd as i32)
+        };
+        Self {
+            fd,
+            offset: 0,
+            buf: [0; 2048],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Continue at an offset returned in an earlier entry.
+    pub(crate) fn seek(&mut self, offset: u64) {
+        if let Ok(fd) = self.fd {
+            unsafe { libc::lseek(fd, offset as i64, libc::SEEK_SET) };
+        }
+        self.offset = offset;
+        self.pos = 0;
+        self.len = 0;
+    }
+}
+
+impl DirIterator for LinuxDir {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        let fd = self.fd.map_err(|e| msg2err!("open directory").context(e))?;
+        if self.pos >= self.len {
+            let n = unsafe {
+                check_error(libc::syscall(libc::SYS_getdents64, fd, self.buf.as_mut_ptr(), self.buf.len()) as isize)
+                    .map_err(|e| msg2err!("getdents64").context(e))? as usize
+            };
+            if n == 0 {
+                return Ok(None);
+            }
+            self.pos = 0;
+            self.len = n;
+        }
+
+        let entry = &self.buf[self.pos..self.len];
+        if entry.len() < HEADER {
+            return Err(msg2err!("truncated dirent"));
+        }
+        let id = u64::from_ne_bytes(entry[0..8].try_into().unwrap());
+        let next = u64::from_ne_bytes(entry[8..16].try_into().unwrap());
+        let reclen = u16::from_ne_bytes(entry[16..18].try_into().unwrap()) as usize;
+        if reclen < HEADER || reclen > entry.len() {
+            return Err(msg2err!("dirent length"));
+        }
+        let full = &entry[HEADER..reclen];
+        let full = &full[..full.iter().position(|&x| x == 0).unwrap_or(full.len())];
+        let nlen = core::cmp::min(full.len(), name.len());
+        name[..nlen].copy_from_slice(&full[..nlen]);
+
+        let typ = match entry[18] {
+            _ if full == b"." || full == b".." => FileType::Parent,
+            libc::DT_REG => FileType::File,
+            libc::DT_DIR => FileType::Directory,
+            libc::DT_LNK => FileType::SymLink,
+            libc::DT_CHR => FileType::CharDevice,
+            libc::DT_BLK => FileType::BlockDevice,
+            libc::DT_FIFO => FileType::Fifo,
+            libc::DT_SOCK => FileType::Socket,
+            _ => FileType::Unknown,
+        };
+        let offset = self.offset;
+        self.offset = next;
+        self.pos += reclen;
+        Ok(Some(DirEntry { offset, id, nlen, typ }))
+    }
+}
+
+/// Close the descriptor when the object drops.
+impl Drop for LinuxDir {
+    fn drop(&mut self) {
+        if let Ok(fd) = self.fd {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! File support.
+
+use super::{attr::Attr, LinuxDir, LinuxFS};
+use crate::{block, check_error};
+use ap_storage::{directory::DirIterator, file::File, file::FileType, msg2err, Error, Offset, Read};
+use core::ffi::CStr;
+
+/// A file or directory of the host.
+pub struct LinuxFile<'a> {
+    pub(crate) fs: &'a LinuxFS,
+    /// Regular files and directories are readable, others are opened with `O_PATH`.
+    pub(crate) fd: i32,
+    pub(crate) stat: libc::stat,
+}
+
+impl<'a> LinuxFile<'a> {
+    /// Open a name relative to a directory without following symlinks.
+    pub(crate) fn open_at(fs: &'a LinuxFS, dir: i32, name: &CStr) -> Result<Self, Error> {
+        // SAFETY: stat is plain old data and filled by the kernel.
+        let mut stat: libc::stat = unsafe { core::mem::zeroed() };
+        unsafe {
+            check_error(libc::fstatat(dir, name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) as isize)
+                .map_err(|e| msg2err!("fstatat").context(e))?
+        };
+        let flags = libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        let flags = match stat.st_mode & libc::S_IFMT {
+            libc::S_IFREG => flags | libc::O_RDONLY | libc::O_NONBLOCK,
+            libc::S_IFDIR => flags | libc::O_RDONLY | libc::O_DIRECTORY,
+            _ => flags | libc::O_PATH,
+        };
+        // unreadable files can still be listed
+        let fd = match unsafe { check_error(libc::openat(dir, name.as_ptr(), flags) as isize) } {
+            Err(libc::EACCES) => unsafe {
+                check_error(libc::openat(dir, name.as_ptr(), flags | libc::O_PATH) as isize)
+            },
+            res => res,
+        }
+        .map_err(|e| msg2err!("openat").context(e))? as i32;
+        // the file closes the fd on errors
+        let mut file = Self { fs, fd, stat };
+        file.stat = block::fstat(fd)?;
+        Ok(file)
+    }
+
+    /// The type of this file.
+    pub fn ftype(&self) -> FileType {
+        match self.stat.st_mode & libc::S_IFMT {
+            libc::S_IFREG => FileType::File,
+            libc::S_IFDIR => FileType::Directory,
+            libc::S_IFLNK => FileType::SymLink,
+            libc::S_IFCHR => FileType::CharDevice,
+            libc::S_IFBLK => FileType::BlockDevice,
+            libc::S_IFIFO => FileType::Fifo,
+            libc::S_IFSOCK => FileType::Socket,
+            _ => FileType::Unknown,
+        }
+    }
+
+    /// Whether this is the root of the filesystem.
+    fn is_root(&self) -> bool {
+        (self.stat.st_dev, self.stat.st_ino) == self.fs.root
+    }
+}
+
+impl<'a> File for LinuxFile<'a> {
+    type AttrType<'c>
+        = Attr<'c>
+    where
+        Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c>
+        = LinuxDir
+    where
+        Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.ftype() != FileType::Directory {
+            return None;
+        }
+        Some(LinuxDir::new(self.fd))
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        let mut dir = self.dir().ok_or(msg2err!("not a directory"))?;
+        dir.seek(offset);
+        let mut name = [0u8; 256];
+        let entry = dir.next(&mut name)?.ok_or(msg2err!("invalid offset"))?;
+        self.lookup(&name[..entry.nlen])?.ok_or(msg2err!("file not found"))
+    }
+
+    /// Open the name directly instead of searching the directory.
+    fn lookup(&self, name: &[u8]) -> Result<Option<Self>, Error> {
+        if self.ftype() != FileType::Directory {
+            return Err(msg2err!("not a directory"));
+        }
+        if name.is_empty() || name.len() > 255 || name.contains(&b'/') || name.contains(&0) {
+            return Ok(None);
+        }
+        // the root is its own parent
+        let name = if name == b".." && self.is_root() { b"." } else { name };
+        let mut buf = [0u8; 256];
+        buf[..name.len()].copy_from_slice(name);
+        let name = CStr::from_bytes_until_nul(&buf).map_err(|e| msg2err!(e))?;
+        match Self::open_at(self.fs, self.fd, name) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.downcast_ref::<i32>() == Some(&libc::ENOENT) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Read for LinuxFile<'_> {
+    /// Symlinks return their target.
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.ftype() == FileType::SymLink {
+            let mut target = [0u8; libc::PATH_MAX as usize];
+            let len = unsafe {
+                check_error(libc::readlinkat(
+                    self.fd,
+                    c"".as_ptr(),
+                    target.as_mut_ptr() as *mut libc::c_char,
+                    target.len(),
+                ))
+                .map_err(|e| msg2err!("readlinkat").context(e))? as usize
+            };
+            if offset >= len as Offset {
+                return Ok(0);
+            }
+            let n = core::cmp::min(len - offset as usize, buf.len());
+            buf[..n].copy_from_slice(&target[offset as usize..offset as usize + n]);
+            return Ok(n);
+        }
+        let res = unsafe {
+            check_error(libc::pread(
+                self.fd,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                offset as i64,
+            ))
+            .map_err(|e| msg2err!("pread").context(e))?
+        };
+        Ok(res as usize)
+    }
+}
+
+/// Close the file when the object drops.
+impl Drop for LinuxFile<'_> {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
@@ -0,0 +1,60 @@
+//! A host directory as filesystem.
+//!
+//! Files are opened with `openat` relative to their directory and never
+//! follow symlinks.  Directories are listed with `getdents64`.  The
+//! parent of the root directory is the root itself, so lookups cannot
+//! leave the tree.
+
+use super::*;
+use ap_storage::FileSystem;
+
+pub mod attr;
+mod dir;
+mod file;
+pub use dir::LinuxDir;
+pub use file::LinuxFile;
+
+/// A directory of the host as filesystem.
+pub struct LinuxFS {
+    /// The root directory opened with `O_PATH`.
+    fd: i32,
+    /// The device and inode of the root.
+    root: (u64, u64),
+}
+
+impl LinuxFS {
+    /// Use the directory at the path as root.
+    pub fn new(path: &str) -> Result<Self, Error> {
+        let mut buf = [0u8; libc::PATH_MAX as usize];
+        let path = str2cstr(path, &mut buf).ok_or(msg2err!("invalid path"))?;
+        let fd = unsafe {
+            check_error(libc::open(path.as_ptr(), libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC) as isize)
+                .map_err(|e| msg2err!("open").context(e))? as i32
+        };
+        let stat = match block::fstat(fd) {
+            Ok(stat) => stat,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            fd,
+            root: (stat.st_dev, stat.st_ino),
+        })
+    }
+}
+
+impl<'a> FileSystem<'a> for LinuxFS {
+    type FileType = LinuxFile<'a>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        LinuxFile::open_at(self, self.fd, c".")
+    }
+}
+
+/// Close the root when the object drops.
+impl Drop for LinuxFS {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
@@ -2,11 +2,20 @@
 
 #![no_std]
 
+use ap_storage::{msg2err, Error};
 use core::ffi::CStr;
+mod block;
+mod direct;
 mod disk_ro;
 mod disk_rw;
+pub mod fs;
+mod uring;
+pub use block::Geometry;
+pub use direct::LinuxDiskDirect;
 pub use disk_ro::LinuxDiskRO;
 pub use disk_rw::LinuxDiskRW;
+pub use fs::LinuxFS;
+pub use uring::{Op, Request, UringDisk};
 
 /// Convert an libc error into a Result.
 ///
@@ -31,3 +40,189 @@ pub fn str2cstr<'a>(input: &str, buf: &'a mut [u8]) -> Option<&'a CStr> {
     buf[src.len()] = 0;
     CStr::from_bytes_until_nul(buf).ok()
 }
+
+/// Open a file with the flags and detect whether it is a block device.
+fn open(filename: &str, flags: i32, offset: u64) -> Result<LinuxDiskRO, Error> {
+    let mut buf = [0u8; libc::PATH_MAX as usize];
+    let filename = str2cstr(filename, &mut buf).ok_or(msg2err!("invalid filename"))?;
+    let fd = unsafe {
+        check_error(libc::open(filename.as_ptr(), flags) as isize).map_err(|e| msg2err!("open").context(e))? as i32
+    };
+    // the disk closes the fd on errors
+    let mut disk = LinuxDiskRO {
+        fd,
+        offset,
+        sector: None,
+    };
+    let geometry = disk.geometry()?;
+    if geometry.block_device {
+        disk.sector = Some(geometry.logical as u64);
+    }
+    Ok(disk)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{Read, ReadExt, Write, WriteExt};
+    use std::{process::Command, string::String, vec, vec::Vec};
+
+    /// Write unaligned data and discard it again.
+    fn roundtrip(name: &str) {
+        let rw = LinuxDiskRW::new(name, 512).unwrap();
+        let direct = LinuxDiskDirect::new(name, 512, true).unwrap();
+        let mut expected = vec![0x55u8; 1 << 16];
+        (&rw as &dyn Write).write_exact(0, &expected).unwrap();
+
+        let data: Vec<u8> = (0..10000u32).map(|x| x as u8).collect();
+        (&direct as &dyn Write).write_exact(1234, &data).unwrap();
+        expected[1234..11234].copy_from_slice(&data);
+        for disk in [&rw as &dyn Write, &direct] {
+            disk.discard_all(3000, 5000).unwrap();
+        }
+        expected[3000..8000].fill(0);
+        direct.sync().unwrap();
+
+        let mut buf = vec![0u8; expected.len() - 1];
+        for disk in [&rw as &dyn Read, &direct] {
+            disk.read_exact(1, &mut buf).unwrap();
+            assert!(buf == expected[1..]);
+        }
+    }
+
+    #[test]
+    fn regular_file() {
+        let name = std::env::temp_dir().join("ap-storage-linux-test.img");
+        std::fs::write(&name, vec![0u8; 1 << 17]).unwrap();
+        let geometry = LinuxDiskRO::new(name.to_str().unwrap(), 4096)
+            .unwrap()
+            .geometry()
+            .unwrap();
+        assert!(!geometry.block_device);
+        assert_eq!(geometry.size, (1 << 17) - 4096);
+        roundtrip(name.to_str().unwrap());
+        std::fs::remove_file(name).unwrap();
+    }
+
+    /// A loop device stands in for real hardware.  The test is skipped without permissions.
+    #[test]
+    fn loop_device() {
+        let name = std::env::temp_dir().join("ap-storage-linux-loop.img");
+        std::fs::write(&name, vec![0u8; 1 << 20]).unwrap();
+        let res = Command::new("losetup").arg("-f").arg("--show").arg(&name).output();
+        let device = match res {
+            Ok(output) if output.status.success() => String::from_utf8(output.stdout).unwrap(),
+            _ => {
+                std::fs::remove_file(name).unwrap();
+                return;
+            }
+        };
+        let device = device.trim();
+        let geometry = LinuxDiskRO::new(device, 0).unwrap().geometry().unwrap();
+        assert!(geometry.block_device);
+        assert_eq!(geometry.size, 1 << 20);
+        roundtrip(device);
+        LinuxDiskRW::new(device, 0).unwrap().trim(0, 1 << 20).unwrap();
+        Command::new("losetup").arg("-d").arg(device).status().unwrap();
+        std::fs::remove_file(name).unwrap();
+    }
+
+    #[test]
+    fn uring_batch() {
+        let name = std::env::temp_dir().join("ap-storage-linux-uring.img");
+        let data: Vec<u8> = (0..1u32 << 18).map(|x| (x >> 3) as u8).collect();
+        std::fs::write(&name, &data).unwrap();
+        let mut fixed = vec![0u8; 1 << 14];
+        let mut disk = UringDisk::new(name.to_str().unwrap(), 100, false, 4).unwrap();
+        disk.register(&mut fixed).unwrap();
+
+        let mut bufs = vec![[0u8; 1000]; 10];
+        let mut requests: Vec<Request> = bufs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, buf)| Request::new(Op::Read(buf), i as u64 * 7777))
+            .chain((0..4).map(|i| Request::new(Op::ReadFixed(i * 4096..(i + 1) * 4096), i as u64 * 50000)))
+            .collect();
+        disk.submit(&mut requests).unwrap();
+        assert!(requests[..10].iter().all(|r| r.res == 1000));
+        assert!(requests[10..].iter().all(|r| r.res == 4096));
+        drop(requests);
+        for (i, buf) in bufs.iter().enumerate() {
+            assert!(buf[..] == data[100 + i * 7777..1100 + i * 7777]);
+        }
+        let fixed = disk.fixed().unwrap();
+        for i in 0..4 {
+            assert!(fixed[i * 4096..(i + 1) * 4096] == data[100 + i * 50000..4196 + i * 50000]);
+        }
+        assert!(disk.write_bytes(0, &[0]).is_err());
+
+        // an invalid request fails the batch without leaving entries in the ring
+        let mut buf = [0u8; 100];
+        let mut requests: Vec<Request> = (0..6)
+            .map(|i| Request::new(Op::ReadFixed(i * 100..(i + 1) * 100), i as u64))
+            .chain([Request::new(Op::ReadFixed(0..1 << 15), 0)])
+            .collect();
+        assert!(disk.submit(&mut requests).is_err());
+        assert!(requests.iter().all(|r| r.res == 0));
+        assert_eq!(disk.read_bytes(5000, &mut buf).unwrap(), 100);
+        assert!(buf[..] == data[5100..5200]);
+        std::fs::remove_file(name).unwrap();
+    }
+
+    #[test]
+    fn linux_fs() {
+        use ap_storage::{attr::Attributes, directory::DirIterator, file::File, file::FileType, FileSystem};
+        let base = std::env::temp_dir().join("ap-storage-linux-fs");
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("dir")).unwrap();
+        std::fs::write(base.join("dir/file"), b"content").unwrap();
+        std::os::unix::fs::symlink("dir/file", base.join("link")).unwrap();
+
+        // 3000-01-01 does not fit into nanoseconds
+        let future = std::time::UNIX_EPOCH + std::time::Duration::from_secs(32_503_680_000);
+        std::fs::File::create(base.join("dir/future"))
+            .unwrap()
+            .set_modified(future)
+            .unwrap();
+
+        let fs = LinuxFS::new(base.to_str().unwrap()).unwrap();
+        let root = fs.root().unwrap();
+        let mut names = Vec::new();
+        let mut dir = root.dir().unwrap();
+        let mut name = [0u8; 256];
+        while let Some(entry) = dir.next(&mut name).unwrap() {
+            names.push((String::from_utf8(name[..entry.nlen].to_vec()).unwrap(), entry.typ));
+        }
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+        let expected = [
+            (".", FileType::Parent),
+            ("..", FileType::Parent),
+            ("dir", FileType::Directory),
+            ("link", FileType::SymLink),
+        ];
+        assert_eq!(names, expected.map(|(n, t)| (String::from(n), t)));
+
+        let mut buf = [0u8; 16];
+        let file = fs.root().unwrap().lookup_path(b"dir/file").unwrap();
+        assert_eq!(file.read_bytes(0, &mut buf).unwrap(), 7);
+        assert_eq!(
+            file.attr()
+                .get(ap_storage::attr::SIZE, &mut [])
+                .and_then(|v| v.as_u64()),
+            Some(7)
+        );
+        let link = root.lookup(b"link").unwrap().unwrap();
+        assert_eq!(link.read_bytes(4, &mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"file");
+        assert!(root.lookup(b"missing").unwrap().is_none());
+        let future = fs.root().unwrap().lookup_path(b"dir/future").unwrap();
+        let mtime = future.attr().get(ap_storage::attr::MTIME, &mut []);
+        assert_eq!(mtime.and_then(|v| v.as_time()), Some(i64::MAX));
+
+        // the root is its own parent
+        let id = |f: &fs::LinuxFile| f.attr().get(ap_storage::attr::ID, &mut []);
+        assert_eq!(id(&fs.root().unwrap().lookup_path(b"dir/../..").unwrap()), id(&root));
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,523 @@
+//! Batched I/O with io_uring.
+
+use super::*;
+use ap_storage::{msg2err, Error, Offset, Read, Write};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+const IORING_OP_READ_FIXED: u8 = 4;
+const IORING_OP_WRITE_FIXED: u8 = 5;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+const IORING_REGISTER_BUFFERS: u32 = 0;
+const IORING_UNREGISTER_BUFFERS: u32 = 1;
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+const IORING_OFF_SQES: i64 = 0x1000_0000;
+
+/// The number of failed waits in a row before giving up on submitted entries.
+const MAX_RETRIES: u32 = 16;
+
+/// The offsets of the submission ring.
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// The offsets of the completion ring.
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// The parameters of io_uring_setup.
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+/// A submission queue entry.
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+/// A completion queue entry.
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A mapping of the ring memory.
+struct Mapping(*mut u8, usize);
+
+impl Mapping {
+    fn new(fd: i32, len: usize, offset: i64) -> Result<Self, Error> {
+        let ptr = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(msg2err!("mmap io_uring"));
+        }
+        Ok(Self(ptr as *mut u8, len))
+    }
+
+    /// A field at a byte offset in the mapping.
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.0.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.0 as *mut libc::c_void, self.1) };
+    }
+}
+
+/// The shared rings of an io_uring instance.
+struct Ring {
+    fd: i32,
+    sq: Mapping,
+    cq: Option<Mapping>,
+    sqes: Mapping,
+    params: Params,
+}
+
+impl Ring {
+    fn new(entries: u32) -> Result<Self, Error> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            check_error(libc::syscall(libc::SYS_io_uring_setup, entries, &mut params) as isize)
+                .map_err(|e| msg2err!("io_uring_setup").context(e))? as i32
+        };
+        // the ring is closed on errors
+        let close = |e| {
+            unsafe { libc::close(fd) };
+            e
+        };
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * core::mem::size_of::<Cqe>();
+        let single = params.features & IORING_FEAT_SINGLE_MMAP != 0;
+        let sq =
+            Mapping::new(fd, if single { sq_len.max(cq_len) } else { sq_len }, IORING_OFF_SQ_RING).map_err(close)?;
+        let cq = if single {
+            None
+        } else {
+            Some(Mapping::new(fd, cq_len, IORING_OFF_CQ_RING).map_err(close)?)
+        };
+        let sqes_len = params.sq_entries as usize * core::mem::size_of::<Sqe>();
+        let sqes = Mapping::new(fd, sqes_len, IORING_OFF_SQES).map_err(close)?;
+        Ok(Self {
+            fd,
+            sq,
+            cq,
+            sqes,
+            params,
+        })
+    }
+
+    fn cq(&self) -> &Mapping {
+        self.cq.as_ref().unwrap_or(&self.sq)
+    }
+
+    fn sq_tail(&self) -> &AtomicU32 {
+        unsafe { &*self.sq.at(self.params.sq_off.tail) }
+    }
+
+    fn cq_head(&self) -> &AtomicU32 {
+        unsafe { &*self.cq().at(self.params.cq_off.head) }
+    }
+
+    fn cq_tail(&self) -> &AtomicU32 {
+        unsafe { &*self.cq().at(self.params.cq_off.tail) }
+    }
+
+    /// Queue an entry.  The caller ensures that there is space.
+    fn push(&mut self, sqe: Sqe) {
+        let mask = unsafe { *self.sq.at::<u32>(self.params.sq_off.ring_mask) };
+        let tail = self.sq_tail().load(Ordering::Relaxed);
+        let index = tail & mask;
+        unsafe {
+            self.sqes.at::<Sqe>(0).add(index as usize).write(sqe);
+            self.sq
+                .at::<u32>(self.params.sq_off.array)
+                .add(index as usize)
+                .write(index);
+        }
+        self.sq_tail().store(tail.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Submit entries and optionally wait for a completion.
+    fn enter(&self, submit: u32, wait: u32) -> Result<u32, Error> {
+        let flags = if wait != 0 { IORING_ENTER_GETEVENTS } else { 0 };
+        loop {
+            let res = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd,
+                    submit,
+                    wait,
+                    flags,
+                    core::ptr::null::<libc::sigset_t>(),
+                    0usize,
+                )
+            };
+            match unsafe { check_error(res as isize) } {
+                Err(libc::EINTR) => continue,
+                Err(e) => return Err(msg2err!("io_uring_enter").context(e)),
+                Ok(n) => return Ok(n as u32),
+            }
+        }
+    }
+
+    /// Take the next completion or wait for one.
+    fn complete(&self) -> Result<Cqe, Error> {
+        loop {
+            let head = self.cq_head().load(Ordering::Relaxed);
+            if head != self.cq_tail().load(Ordering::Acquire) {
+                let mask = unsafe { *self.cq().at::<u32>(self.params.cq_off.ring_mask) };
+                let cqe = unsafe {
+                    self.cq()
+                        .at::<Cqe>(self.params.cq_off.cqes)
+                        .add((head & mask) as usize)
+                        .read()
+                };
+                self.cq_head().store(head.wrapping_add(1), Ordering::Release);
+                return Ok(cqe);
+            }
+            self.enter(0, 1)?;
+        }
+    }
+
+    /// Wait for submitted entries as the kernel still uses their buffers.  Errors are retried a few times in a row.
+    fn drain(&self, mut pending: usize) -> Result<(), Error> {
+        let mut retries = 0;
+        while pending != 0 {
+            match self.complete() {
+                Ok(_) => {
+                    pending -= 1;
+                    retries = 0;
+                }
+                Err(e) if retries == MAX_RETRIES => return Err(e),
+                Err(_) => retries += 1,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// The operation of a request.
+pub enum Op<'b> {
+    /// Read into the buffer.
+    Read(&'b mut [u8]),
+    /// Write from the buffer.
+    Write(&'b [u8]),
+    /// Read into a range of the registered buffer.
+    ReadFixed(Range<usize>),
+    /// Write from a range of the registered buffer.
+    WriteFixed(Range<usize>),
+}
+
+/// A single request in a batch.
+pub struct Request<'b> {
+    /// The operation and its buffer.
+    pub op: Op<'b>,
+    /// The offset on the disk.
+    pub offset: Offset,
+    /// The number of bytes transferred or a negative errno.
+    pub res: i32,
+}
+
+impl<'b> Request<'b> {
+    /// A request that was not executed yet.
+    pub fn new(op: Op<'b>, offset: Offset) -> Self {
+        Self { op, offset, res: 0 }
+    }
+}
+
+/// A Linux disk using io_uring.
+///
+/// Requests are submitted in batches and their completions are polled
+/// from the shared ring before the kernel is asked to wait.  A single
+/// buffer can be registered to avoid mapping it on every request.  The
+/// disk falls back to `pread` and `pwrite` when io_uring is unavailable
+/// or the ring is used by another thread.
+pub struct UringDisk<'a> {
+    disk: LinuxDiskRO,
+    writable: bool,
+    ring: Option<UnsafeCell<Ring>>,
+    busy: AtomicBool,
+    /// The registered buffer.
+    fixed: Option<(*mut u8, usize)>,
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+// SAFETY: the ring is only used while holding the busy flag and the
+// registered buffer is only accessed by the kernel.
+unsafe impl Send for UringDisk<'_> {}
+unsafe impl Sync for UringDisk<'_> {}
+
+impl<'a> UringDisk<'a> {
+    /// Open a file or block device with a ring of the given number of entries.
+    pub fn new(filename: &str, offset: u64, writable: bool, entries: u32) -> Result<Self, Error> {
+        let flags = if writable { libc::O_RDWR } else { libc::O_RDONLY };
+        Ok(Self {
+            disk: open(filename, flags, offset)?,
+            writable,
+            ring: Ring::new(entries).ok().map(UnsafeCell::new),
+            busy: AtomicBool::new(false),
+            fixed: None,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Whether io_uring is used.
+    pub fn is_uring(&self) -> bool {
+        self.ring.is_some()
+    }
+
+    /// The size and sector sizes of the disk.
+    pub fn geometry(&self) -> Result<Geometry, Error> {
+        self.disk.geometry()
+    }
+
+    /// Flush the written data to the device.
+    pub fn sync(&self) -> Result<(), Error> {
+        block::fsync(self.disk.fd)
+    }
+
+    /// Register a buffer for the fixed operations.  It replaces an earlier one, which stays unregistered on errors.
+    pub fn register(&mut self, buffer: &'a mut [u8]) -> Result<(), Error> {
+        if let Some(ring) = &mut self.ring {
+            let iov = libc::iovec {
+                iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.len(),
+            };
+            let ring = ring.get_mut();
+            if self.fixed.take().is_some() {
+                let null = core::ptr::null::<libc::iovec>();
+                unsafe { libc::syscall(libc::SYS_io_uring_register, ring.fd, IORING_UNREGISTER_BUFFERS, null, 0) };
+            }
+            unsafe {
+                check_error(
+                    libc::syscall(libc::SYS_io_uring_register, ring.fd, IORING_REGISTER_BUFFERS, &iov, 1) as isize,
+                )
+                .map_err(|e| msg2err!("io_uring_register").context(e))?
+            };
+        }
+        self.fixed = Some((buffer.as_mut_ptr(), buffer.len()));
+        Ok(())
+    }
+
+    /// Access the registered buffer between batches.
+    pub fn fixed(&mut self) -> Option<&mut [u8]> {
+        // SAFETY: no request is in flight while self is borrowed mutably.
+        self.fixed
+            .map(|(ptr, len)| unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    /// The pointer and length of a request.
+    fn buffer(&self, op: &Op) -> Result<(*mut u8, usize, bool), Error> {
+        let fixed = |range: &Range<usize>| match self.fixed {
+            Some((ptr, len)) if range.start <= range.end && range.end <= len => {
+                Ok((unsafe { ptr.add(range.start) }, range.end - range.start))
+            }
+            _ => Err(msg2err!("invalid fixed range")),
+        };
+        let write = matches!(op, Op::Write(_) | Op::WriteFixed(_));
+        if write && !self.writable {
+            return Err(msg2err!("read-only disk"));
+        }
+        let (ptr, len) = match op {
+            Op::Read(buf) => (buf.as_ptr() as *mut u8, buf.len()),
+            Op::Write(buf) => (buf.as_ptr() as *mut u8, buf.len()),
+            Op::ReadFixed(range) | Op::WriteFixed(range) => fixed(range)?,
+        };
+        Ok((ptr, len, write))
+    }
+
+    /// Execute a request with pread or pwrite.
+    fn fallback(&self, request: &mut Request) -> Result<(), Error> {
+        let (ptr, len, write) = self.buffer(&request.op)?;
+        let pos = (self.disk.offset + request.offset) as i64;
+        let res = unsafe {
+            if write {
+                libc::pwrite(self.disk.fd, ptr as *const libc::c_void, len, pos)
+            } else {
+                libc::pread(self.disk.fd, ptr as *mut libc::c_void, len, pos)
+            }
+        };
+        request.res = match unsafe { check_error(res) } {
+            Ok(n) => n as i32,
+            Err(e) => -e,
+        };
+        Ok(())
+    }
+
+    /// Execute a batch of requests and wait for all of them.
+    ///
+    /// The result of every request is stored in it.  Requests larger
+    /// than 2 GiB are transferred partially.
+    pub fn submit(&self, requests: &mut [Request]) -> Result<(), Error> {
+        // invalid requests must not leave entries in the ring
+        for request in requests.iter() {
+            self.buffer(&request.op)?;
+        }
+        let Some(ring) = &self.ring else {
+            return requests.iter_mut().try_for_each(|r| self.fallback(r));
+        };
+        if self.busy.swap(true, Ordering::Acquire) {
+            return requests.iter_mut().try_for_each(|r| self.fallback(r));
+        }
+        // SAFETY: the busy flag gives exclusive access.
+        let res = self.submit_ring(unsafe { &mut *ring.get() }, requests);
+        self.busy.store(false, Ordering::Release);
+        res
+    }
+
+    /// Submit the requests in chunks of the ring size.
+    ///
+    /// Entries are only returned with an error after the kernel completed them.
+    fn submit_ring(&self, ring: &mut Ring, requests: &mut [Request]) -> Result<(), Error> {
+        let entries = ring.params.sq_entries as usize;
+        let mut done = 0;
+        while done < requests.len() {
+            let chunk = core::cmp::min(entries, requests.len() - done);
+            let start = ring.sq_tail().load(Ordering::Relaxed);
+            for (i, request) in requests[done..done + chunk].iter().enumerate() {
+                // the buffers were validated before, but nothing may stay queued
+                let (ptr, len, write) = self.buffer(&request.op).inspect_err(|_| {
+                    ring.sq_tail().store(start, Ordering::Release);
+                })?;
+                let fixed = matches!(request.op, Op::ReadFixed(_) | Op::WriteFixed(_));
+                let opcode = match (write, fixed) {
+                    (false, false) => IORING_OP_READ,
+                    (true, false) => IORING_OP_WRITE,
+                    (false, true) => IORING_OP_READ_FIXED,
+                    (true, true) => IORING_OP_WRITE_FIXED,
+                };
+                ring.push(Sqe {
+                    opcode,
+                    fd: self.disk.fd,
+                    off: self.disk.offset + request.offset,
+                    addr: ptr as u64,
+                    len: core::cmp::min(len, i32::MAX as usize) as u32,
+                    user_data: i as u64,
+                    ..Default::default()
+                });
+            }
+            let mut submitted = 0;
+            while submitted < chunk as u32 {
+                match ring.enter(chunk as u32 - submitted, 0) {
+                    Ok(n) => submitted += n,
+                    Err(e) => {
+                        // drop the entries the kernel did not take
+                        ring.sq_tail().store(start.wrapping_add(submitted), Ordering::Release);
+                        return Err(ring.drain(submitted as usize).err().unwrap_or(e));
+                    }
+                }
+            }
+            let batch = &mut requests[done..done + chunk];
+            let mut pending = chunk;
+            while pending != 0 {
+                let cqe = ring.complete().map_err(|e| ring.drain(pending).err().unwrap_or(e))?;
+                if let Some(request) = batch.get_mut(cqe.user_data as usize) {
+                    request.res = cqe.res;
+                    pending -= 1;
+                }
+            }
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Execute a single request and convert its result.
+    fn single(&self, op: Op, offset: Offset) -> Result<usize, Error> {
+        let mut request = [Request::new(op, offset)];
+        self.submit(&mut request)?;
+        match request[0].res {
+            res if res < 0 => Err(msg2err!("io_uring request").context(-res)),
+            res => Ok(res as usize),
+        }
+    }
+}
+
+impl Read for UringDisk<'_> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        self.single(Op::Read(buf), offset)
+    }
+}
+
+impl Write for UringDisk<'_> {
+    fn write_bytes(&self, offset: Offset, buf: &[u8]) -> Result<usize, Error> {
+        self.single(Op::Write(buf), offset)
+    }
+
+    fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error> {
+        if !self.writable {
+            return Err(msg2err!("read-only disk"));
+        }
+        block::discard(self, &self.disk, offset, len)
+    }
+}
@@ -0,0 +1,254 @@
+//! An encoder for LittleFS images that is independent of the driver.
+//!
+//! Metadata blocks are built tag by tag with explicit commits, so that tests can produce histories the driver has to
+//! replay.  The tree builder splits directories after a fixed number of entries.
+
+pub const REG: u16 = 0x001;
+pub const DIR: u16 = 0x002;
+pub const SUPERBLOCK: u16 = 0x0ff;
+pub const DIRSTRUCT: u16 = 0x200;
+pub const INLINESTRUCT: u16 = 0x201;
+pub const CTZSTRUCT: u16 = 0x202;
+pub const CREATE: u16 = 0x401;
+pub const DELETE: u16 = 0x4ff;
+pub const CCRC: u16 = 0x500;
+pub const SOFTTAIL: u16 = 0x600;
+pub const HARDTAIL: u16 = 0x601;
+pub const MOVESTATE: u16 = 0x7ff;
+
+/// The id of tags without an entry.
+pub const NONE: u16 = 0x3ff;
+
+/// The CRC-32 of littlefs computed bit by bit.
+pub fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for x in data {
+        crc ^= *x as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// Encode a pair or any other list of words.
+pub fn words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+/// The inline struct of a superblock.
+pub fn superblock(block_size: usize, block_count: usize) -> Vec<u8> {
+    words(&[
+        0x0002_0000,
+        block_size as u32,
+        block_count as u32,
+        255,
+        0x7fff_ffff,
+        1022,
+    ])
+}
+
+/// A metadata block that is written commit by commit.
+pub struct MetaBlock {
+    pub buf: Vec<u8>,
+    pub off: usize,
+    ptag: u32,
+    crc: u32,
+}
+
+impl MetaBlock {
+    pub fn new(block_size: usize, rev: u32) -> Self {
+        let mut buf = vec![0xff; block_size];
+        buf[..4].copy_from_slice(&rev.to_le_bytes());
+        Self {
+            buf,
+            off: 4,
+            ptag: u32::MAX,
+            crc: crc32(u32::MAX, &rev.to_le_bytes()),
+        }
+    }
+
+    fn raw(&mut self, tag: u32, data: &[u8]) -> &mut Self {
+        let raw = ((tag & 0x7fff_ffff) ^ self.ptag).to_be_bytes();
+        self.buf[self.off..self.off + 4].copy_from_slice(&raw);
+        self.buf[self.off + 4..self.off + 4 + data.len()].copy_from_slice(data);
+        self.crc = crc32(self.crc, &self.buf[self.off..self.off + 4 + data.len()]);
+        self.off += 4 + data.len();
+        self.ptag = tag & 0x7fff_ffff;
+        self
+    }
+
+    /// Append a tag with its data.
+    pub fn attr(&mut self, typ: u16, id: u16, data: &[u8]) -> &mut Self {
+        self.raw((typ as u32) << 20 | (id as u32) << 10 | data.len() as u32, data)
+    }
+
+    /// Create or delete an id.
+    pub fn splice(&mut self, typ: u16, id: u16) -> &mut Self {
+        self.raw((typ as u32) << 20 | (id as u32) << 10, &[])
+    }
+
+    /// Close a commit with a checksum that is followed by some padding.
+    pub fn commit(&mut self, padding: usize) -> &mut Self {
+        let tag = (CCRC as u32) << 20 | (NONE as u32) << 10 | (4 + padding) as u32;
+        let raw = ((tag & 0x7fff_ffff) ^ self.ptag).to_be_bytes();
+        self.buf[self.off..self.off + 4].copy_from_slice(&raw);
+        let crc = crc32(self.crc, &raw);
+        self.buf[self.off + 4..self.off + 8].copy_from_slice(&crc.to_le_bytes());
+        self.off += 8 + padding;
+        // the erased byte behind the commit has the top bit set, so the valid bit is not flipped
+        self.ptag = tag;
+        self.crc = u32::MAX;
+        self
+    }
+}
+
+/// An image with a trivial allocator.
+pub struct Image {
+    pub data: Vec<u8>,
+    pub block_size: usize,
+    next: u32,
+}
+
+impl Image {
+    pub fn new(block_size: usize, block_count: usize) -> Self {
+        Self {
+            data: vec![0xff; block_size * block_count],
+            block_size,
+            next: 2,
+        }
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.data.len() / self.block_size
+    }
+
+    pub fn alloc(&mut self) -> u32 {
+        self.next += 1;
+        self.next - 1
+    }
+
+    /// Store a metadata block.
+    pub fn put(&mut self, block: u32, meta: &MetaBlock) {
+        let start = block as usize * self.block_size;
+        self.data[start..start + self.block_size].copy_from_slice(&meta.buf);
+    }
+
+    /// Write a CTZ skip-list and return the last block.
+    pub fn ctz(&mut self, data: &[u8]) -> u32 {
+        let mut blocks = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let index = blocks.len();
+            let block = self.alloc();
+            let start = block as usize * self.block_size;
+            // block n points to the blocks n - 2^k for every 2^k dividing n
+            let skips = if index == 0 {
+                0
+            } else {
+                index.trailing_zeros() as usize + 1
+            };
+            for k in 0..skips {
+                let pos = start + 4 * k;
+                self.data[pos..pos + 4].copy_from_slice(&u32::to_le_bytes(blocks[index - (1 << k)]));
+            }
+            let n = rest.len().min(self.block_size - 4 * skips);
+            let pos = start + 4 * skips;
+            self.data[pos..pos + n].copy_from_slice(&rest[..n]);
+            rest = &rest[n..];
+            blocks.push(block);
+        }
+        *blocks.last().unwrap()
+    }
+}
+
+/// A tree to encode.
+pub enum Node {
+    File(Vec<u8>),
+    Dir(Vec<(String, Node)>),
+}
+
+/// Encode a tree with a fixed number of entries per metadata pair.
+pub struct Builder {
+    pub image: Image,
+    pub per_pair: usize,
+    pub inline_max: usize,
+}
+
+type Entries = [(String, Node)];
+
+/// Collect the directories in the order of the metadata list together with the indices of their children.
+fn collect<'n>(entries: &'n Entries, dirs: &mut Vec<(&'n Entries, Vec<usize>)>) -> usize {
+    let index = dirs.len();
+    dirs.push((entries, Vec::new()));
+    for (_, node) in entries {
+        if let Node::Dir(children) = node {
+            let child = collect(children, dirs);
+            dirs[index].1.push(child);
+        }
+    }
+    index
+}
+
+impl Builder {
+    pub fn build(mut self, root: &Entries) -> Vec<u8> {
+        let mut dirs = Vec::new();
+        collect(root, &mut dirs);
+
+        // the pairs of all directories in the threaded list
+        let mut list = Vec::new();
+        let mut first = Vec::new();
+        for (index, (entries, _)) in dirs.iter().enumerate() {
+            first.push(list.len());
+            for chunk in 0..entries.len().div_ceil(self.per_pair).max(1) {
+                let pair = match (index, chunk) {
+                    (0, 0) => [0, 1],
+                    _ => [self.image.alloc(), self.image.alloc()],
+                };
+                list.push((index, chunk, pair));
+            }
+        }
+
+        let (block_size, block_count) = (self.image.block_size, self.image.block_count());
+        for (i, (index, chunk, pair)) in list.iter().enumerate() {
+            let mut meta = MetaBlock::new(block_size, 1);
+            let mut id = 0;
+            if *index == 0 && *chunk == 0 {
+                meta.attr(SUPERBLOCK, 0, b"littlefs");
+                meta.attr(INLINESTRUCT, 0, &superblock(block_size, block_count));
+                id += 1;
+            }
+            let (entries, children) = &dirs[*index];
+            let subdirs = entries[..chunk * self.per_pair]
+                .iter()
+                .filter(|x| matches!(x.1, Node::Dir(_)))
+                .count();
+            let mut children = children[subdirs..].iter();
+            for (name, node) in entries.iter().skip(chunk * self.per_pair).take(self.per_pair) {
+                match node {
+                    Node::File(data) => {
+                        meta.attr(REG, id, name.as_bytes());
+                        if data.len() <= self.inline_max {
+                            meta.attr(INLINESTRUCT, id, data);
+                        } else {
+                            let head = self.image.ctz(data);
+                            meta.attr(CTZSTRUCT, id, &words(&[head, data.len() as u32]));
+                        }
+                    }
+                    Node::Dir(_) => {
+                        let child = *children.next().unwrap();
+                        meta.attr(DIR, id, name.as_bytes());
+                        meta.attr(DIRSTRUCT, id, &words(&list[first[child]].2));
+                    }
+                }
+                id += 1;
+            }
+            if let Some((next, _, tail)) = list.get(i + 1) {
+                let typ = if next == index { HARDTAIL } else { SOFTTAIL };
+                meta.attr(typ, NONE, &words(tail));
+            }
+            meta.commit(0);
+            self.image.put(pair[0], &meta);
+        }
+        self.image.data
+    }
+}
@@ -0,0 +1,336 @@
+//! End-to-end tests for the ap-storage-littlefs crate.
+//!
+//! The images are produced by an independent encoder, so that the driver is not only checked against its own writer.
+
+#[cfg(test)]
+mod image;
+
+#[cfg(test)]
+mod tests {
+    use crate::image::*;
+    use ap_storage::{
+        attr::{self, Attributes},
+        directory::DirIterator,
+        file::{File, FileType},
+        FileSystem, Read,
+    };
+    use ap_storage_littlefs::{LittleFs, ROOT_PAIR};
+    use ap_storage_memory::{ReadSlice, VecDisk};
+    use std::collections::BTreeMap;
+
+    type Tree = BTreeMap<String, Option<Vec<u8>>>;
+
+    /// Some data that differs in every block.
+    fn data(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed.wrapping_mul(2_654_435_761) | 1;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect()
+    }
+
+    /// Read with odd chunks to cross the block boundaries.
+    fn read<F: Read>(file: &F, offset: u64, len: usize) -> Vec<u8> {
+        let mut res = vec![0; len];
+        let mut pos = 0;
+        while pos < len {
+            let end = (pos + 777).min(len);
+            match file.read_bytes(offset + pos as u64, &mut res[pos..end]).unwrap() {
+                0 => break,
+                n => pos += n,
+            }
+        }
+        res.truncate(pos);
+        res
+    }
+
+    /// List a tree with the content of the files.
+    fn list<F: File>(file: &F, prefix: &str, tree: &mut Tree) {
+        let mut dir = file.dir().unwrap();
+        let mut name = [0; 256];
+        while let Some(entry) = dir.next(&mut name).unwrap() {
+            let path = format!("{prefix}{}", std::str::from_utf8(&name[..entry.nlen]).unwrap());
+            let child = file.open(entry.offset).unwrap();
+            let size = child.attr().get(attr::SIZE, &mut []).unwrap().as_u64().unwrap();
+            match entry.typ {
+                FileType::Directory => {
+                    assert!(tree.insert(path.clone(), None).is_none());
+                    list(&child, &format!("{path}/"), tree);
+                }
+                FileType::File => {
+                    assert!(tree.insert(path, Some(read(&child, 0, size as usize + 1))).is_none());
+                }
+                typ => panic!("unexpected {typ:?}"),
+            }
+        }
+    }
+
+    fn tree_of(disk: &dyn Read) -> Tree {
+        let fs = LittleFs::new(disk).unwrap();
+        let mut tree = Tree::new();
+        list(&fs.root().unwrap(), "", &mut tree);
+        tree
+    }
+
+    /// Flatten the nodes of the encoder.
+    fn flatten(entries: &[(String, Node)], prefix: &str, tree: &mut Tree) {
+        for (name, node) in entries {
+            let path = format!("{prefix}{name}");
+            match node {
+                Node::File(data) => {
+                    tree.insert(path, Some(data.clone()));
+                }
+                Node::Dir(children) => {
+                    tree.insert(path.clone(), None);
+                    flatten(children, &format!("{path}/"), tree);
+                }
+            }
+        }
+    }
+
+    fn file(name: &str, data: Vec<u8>) -> (String, Node) {
+        (name.into(), Node::File(data))
+    }
+
+    fn dir(name: &str, children: Vec<(String, Node)>) -> (String, Node) {
+        (name.into(), Node::Dir(children))
+    }
+
+    fn sample() -> Vec<(String, Node)> {
+        let many = (0..10)
+            .map(|i| file(&format!("file{i}"), data(i * 100, i as u32)))
+            .collect();
+        vec![
+            file("big", data(20_000, 1)),
+            file("ctz1", data(248, 2)),
+            file("ctz2", data(249, 3)),
+            dir("empty", vec![]),
+            file("inline", data(32, 4)),
+            file("nothing", vec![]),
+            dir(
+                "sub",
+                vec![dir("deep", vec![file("x", b"x".to_vec())]), dir("many", many)],
+            ),
+        ]
+    }
+
+    /// Trees with split directories, inline and CTZ files.
+    #[test]
+    fn tree() {
+        let roots = sample();
+        let mut expected = Tree::new();
+        flatten(&roots, "", &mut expected);
+        for per_pair in [3, 4, 100] {
+            let builder = Builder {
+                image: Image::new(256, 512),
+                per_pair,
+                inline_max: 32,
+            };
+            let image = builder.build(&roots);
+            let disk = ReadSlice(&image);
+            assert_eq!(tree_of(&disk), expected);
+
+            let fs = LittleFs::new(&disk).unwrap();
+            assert_eq!(fs.superblock().block_size, 256);
+            assert_eq!(fs.superblock().block_count, 512);
+            let root = fs.root().unwrap();
+            let big = root.lookup(b"big").unwrap().unwrap();
+            let content = data(20_000, 1);
+            for offset in [0, 247, 248, 1000, 4567, 19_999] {
+                assert_eq!(
+                    read(&big, offset, 300),
+                    content[offset as usize..].iter().take(300).copied().collect::<Vec<_>>()
+                );
+            }
+            let x = root.clone().lookup_path(b"sub/deep/x").unwrap();
+            assert_eq!(read(&x, 0, 10), b"x");
+            let nine = root.clone().lookup_path(b"/sub/many/file9").unwrap();
+            assert_eq!(read(&nine, 850, 100), &data(900, 9)[850..]);
+            assert!(root.lookup(b"missing").unwrap().is_none());
+            assert!(big.lookup(b"x").is_err());
+
+            // the ids are unique across split directories
+            let many = root.clone().lookup_path(b"sub/many").unwrap();
+            let mut ids = Vec::new();
+            let mut dir = many.dir().unwrap();
+            while let Some(entry) = dir.next(&mut []).unwrap() {
+                ids.push(entry.id);
+            }
+            ids.sort();
+            ids.dedup();
+            assert_eq!(ids.len(), 10);
+        }
+    }
+
+    /// A root with a history of commits.
+    fn history(rev: u32) -> MetaBlock {
+        let mut meta = MetaBlock::new(256, rev);
+        meta.attr(SUPERBLOCK, 0, b"littlefs")
+            .attr(INLINESTRUCT, 0, &superblock(256, 16));
+        meta.attr(REG, 1, b"a").attr(INLINESTRUCT, 1, b"first");
+        meta.attr(REG, 2, b"c").attr(INLINESTRUCT, 2, b"see").commit(0);
+        // a new entry in the middle shifts the ids behind it
+        meta.splice(CREATE, 2)
+            .attr(REG, 2, b"b")
+            .attr(INLINESTRUCT, 2, b"bee")
+            .commit(12);
+        meta.splice(DELETE, 1).attr(INLINESTRUCT, 1, b"bee2").commit(0);
+        // a torn commit is ignored
+        meta.splice(DELETE, 2).commit(0);
+        let off = meta.off;
+        meta.buf[off - 2] ^= 1;
+        meta
+    }
+
+    /// An older state of the root.
+    fn older(rev: u32) -> MetaBlock {
+        let mut meta = MetaBlock::new(256, rev);
+        meta.attr(SUPERBLOCK, 0, b"littlefs")
+            .attr(INLINESTRUCT, 0, &superblock(256, 16));
+        meta.attr(REG, 1, b"old").attr(INLINESTRUCT, 1, b"old").commit(0);
+        meta
+    }
+
+    /// Commits are replayed and the newer block of a pair wins.
+    #[test]
+    fn commits() {
+        let current = Tree::from([
+            ("b".into(), Some(b"bee2".to_vec())),
+            ("c".into(), Some(b"see".to_vec())),
+        ]);
+        let old = Tree::from([("old".into(), Some(b"old".to_vec()))]);
+        for (revs, expected) in [([5, 4], &current), ([0, u32::MAX], &current), ([4, 5], &old)] {
+            let mut image = Image::new(256, 16);
+            image.put(0, &history(revs[0]));
+            image.put(1, &older(revs[1]));
+            assert_eq!(&tree_of(&ReadSlice(&image.data)), expected, "{revs:?}");
+        }
+
+        // the superblock is also found in the second block
+        let mut image = Image::new(256, 16);
+        image.data[..256].fill(0);
+        image.put(1, &older(7));
+        assert_eq!(tree_of(&ReadSlice(&image.data)), old);
+    }
+
+    /// An interrupted move hides the source entry.
+    #[test]
+    fn pending_move() {
+        let mut image = Image::new(256, 16);
+        let mut meta = older(1);
+        meta.attr(REG, 2, b"x").attr(INLINESTRUCT, 2, b"moved");
+        meta.attr(REG, 3, b"y").attr(INLINESTRUCT, 3, b"why").commit(0);
+        let gstate = words(&[(DELETE as u32) << 20 | 2 << 10, 0, 1]);
+        meta.attr(MOVESTATE, NONE, &gstate).commit(0);
+        image.put(0, &meta);
+
+        let disk = VecDisk::new(image.data);
+        let expected = Tree::from([
+            ("old".into(), Some(b"old".to_vec())),
+            ("y".into(), Some(b"why".to_vec())),
+        ]);
+        assert_eq!(tree_of(&disk), expected);
+        let mut fs = LittleFs::new(&disk).unwrap();
+        assert!(fs.root().unwrap().lookup(b"x").unwrap().is_none());
+        assert!(fs.write_file(b"z", b"zed").is_err());
+    }
+
+    /// The number of metadata pairs in the threaded list.
+    fn pairs<D: Read + ?Sized>(fs: &LittleFs<'_, D>) -> usize {
+        let mut pair = ROOT_PAIR;
+        let mut count = 0;
+        while pair != [u32::MAX; 2] {
+            pair = fs.fetch(pair).unwrap().tail;
+            count += 1;
+        }
+        count
+    }
+
+    /// Format an image and change it.
+    #[test]
+    fn write() {
+        let disk = VecDisk::new(vec![0x55; 512 * 1024]);
+        let mut fs = LittleFs::format(&disk, 512, 1024).unwrap();
+        let mut expected = Tree::new();
+        assert_eq!(pairs(&fs), 1);
+
+        fs.create_dir(b"docs").unwrap();
+        fs.create_dir(b"docs/empty").unwrap();
+        fs.write_file(b"docs/readme", b"hello").unwrap();
+        fs.write_file(b"docs/readme", b"updated").unwrap();
+        fs.write_file(b"big", &data(100_000, 1)).unwrap();
+        assert!(fs.create_dir(b"docs").is_err());
+        assert!(fs.write_file(b"docs", b"").is_err());
+        assert!(fs.write_file(b"missing/file", b"").is_err());
+        assert!(fs.remove(b"docs").is_err());
+        expected.insert("docs".into(), None);
+        expected.insert("docs/empty".into(), None);
+        expected.insert("docs/readme".into(), Some(b"updated".to_vec()));
+
+        // the old blocks are freed on overwrite
+        for i in 0..20 {
+            fs.write_file(b"big", &data(200_000, i)).unwrap();
+        }
+        expected.insert("big".into(), Some(data(200_000, 19)));
+
+        // enough entries to split the directory
+        fs.create_dir(b"many").unwrap();
+        for i in 0..100 {
+            fs.write_file(format!("many/f{i:03}").as_bytes(), &data(i, i as u32))
+                .unwrap();
+            expected.insert(format!("many/f{i:03}"), Some(data(i, i as u32)));
+        }
+        expected.insert("many".into(), None);
+        for i in (0..100).step_by(3) {
+            fs.remove(format!("many/f{i:03}").as_bytes()).unwrap();
+            expected.remove(&format!("many/f{i:03}"));
+        }
+        assert!(pairs(&fs) > 4);
+        assert_eq!(tree_of(&disk), expected);
+
+        let count = pairs(&fs);
+        fs.remove(b"docs/empty").unwrap();
+        expected.remove("docs/empty");
+        assert_eq!(pairs(&fs), count - 1);
+        assert!(fs.remove(b"docs/empty").is_err());
+        assert_eq!(tree_of(&disk), expected);
+    }
+
+    /// Change an image of the encoder.
+    #[test]
+    fn write_encoded() {
+        let roots = sample();
+        let mut expected = Tree::new();
+        flatten(&roots, "", &mut expected);
+        let builder = Builder {
+            image: Image::new(256, 512),
+            per_pair: 3,
+            inline_max: 32,
+        };
+        let disk = VecDisk::new(builder.build(&roots));
+        let mut fs = LittleFs::new(&disk).unwrap();
+
+        fs.write_file(b"sub/many/file0", &data(5000, 5)).unwrap();
+        fs.write_file(b"sub/deep/y", b"y").unwrap();
+        fs.create_dir(b"sub/deep/more").unwrap();
+        fs.remove(b"big").unwrap();
+        fs.remove(b"empty").unwrap();
+        fs.remove(b"sub/many/file4").unwrap();
+        expected.insert("sub/many/file0".into(), Some(data(5000, 5)));
+        expected.insert("sub/deep/y".into(), Some(b"y".to_vec()));
+        expected.insert("sub/deep/more".into(), None);
+        expected.remove("big");
+        expected.remove("empty");
+        expected.remove("sub/many/file4");
+        assert_eq!(tree_of(&disk), expected);
+
+        // the blocks of the removed file are reused
+        fs.write_file(b"again", &data(20_000, 1)).unwrap();
+        expected.insert("again".into(), Some(data(20_000, 1)));
+        assert_eq!(tree_of(&disk), expected);
+    }
+}
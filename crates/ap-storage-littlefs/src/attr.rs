@@ -0,0 +1,37 @@
+//! File attributes for LittleFS images.
+
+use super::file::File;
+use ap_storage::attr::{self, attr_meta, Attributes, Meta, Value};
+use ap_storage::Read;
+use ap_util_slice_writer::*;
+
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
+}
+
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [attr::FTYPE, attr::ID, attr::SIZE].iter()
+    }
+}
+
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        Some(match name {
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            attr::ID => self.file.id.into(),
+            attr::SIZE => self.file.size.into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(name, [attr::FTYPE, attr::ID, attr::SIZE])
+    }
+}
@@ -0,0 +1,67 @@
+//! Directory iteration for LittleFS images.
+
+use super::{file::File, meta::*};
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    file::FileType,
+    msg2err, Error, Read,
+};
+
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
+    /// The current metadata pair of the directory.
+    mdir: MetaDir,
+    /// The number of pairs before the current one.
+    index: u64,
+    /// The next id to check.
+    id: u16,
+}
+
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Result<Self, Error> {
+        let pair = file.pair().ok_or(msg2err!("not a directory"))?;
+        Ok(Self {
+            file,
+            mdir: file.fs.fetch(pair)?,
+            index: 0,
+            id: 0,
+        })
+    }
+}
+
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        let fs = self.file.fs;
+        loop {
+            if self.id >= self.mdir.count {
+                if !self.mdir.split {
+                    return Ok(None);
+                }
+                self.mdir = fs.fetch(self.mdir.tail)?;
+                self.index += 1;
+                self.id = 0;
+                continue;
+            }
+            let id = self.id;
+            self.id += 1;
+            // deleted entries and the superblock do not match
+            let Some((ntag, off)) = fs.get(&self.mdir, tag(0x780, 0x3ff, 0), tag(TYPE_NAME, id, 0))? else {
+                continue;
+            };
+            let nlen = core::cmp::min(tag_size(ntag) as usize, name.len());
+            fs.read(self.mdir.pair[0], off, &mut name[..nlen])?;
+            let typ = match tag_type3(ntag) {
+                TYPE_REG => FileType::File,
+                TYPE_DIR => FileType::Directory,
+                _ => FileType::Unknown,
+            };
+            let pair = self.mdir.pair;
+            return Ok(Some(DirEntry {
+                offset: self.index << 10 | id as u64,
+                id: (core::cmp::min(pair[0], pair[1]) as u64) << 10 | id as u64,
+                nlen,
+                typ,
+            }));
+        }
+    }
+}
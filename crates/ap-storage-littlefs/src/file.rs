@@ -0,0 +1,150 @@
+//! Files in LittleFS images.
+
+use super::{attr::Attr, ctz_index, dir::Dir, meta::*, LittleFs, ROOT_PAIR};
+use ap_storage::{file::FileType, msg2err, Error, Offset, Read};
+
+/// Where the data of a file is stored.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Kind {
+    Dir([u32; 2]),
+    Inline { block: u32, off: u32 },
+    Ctz { head: u32 },
+}
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a LittleFs<'a, D>,
+    /// The first block of the metadata pair with the entry shifted by ten bits plus the id.
+    pub(crate) id: u64,
+    pub(crate) kind: Kind,
+    pub(crate) size: u64,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            id: self.id,
+            kind: self.kind,
+            size: self.size,
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("id", &self.id)
+            .field("kind", &self.kind)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    pub(crate) fn root(fs: &'a LittleFs<'a, D>) -> Self {
+        Self {
+            fs,
+            id: ID_NONE as u64,
+            kind: Kind::Dir(ROOT_PAIR),
+            size: 0,
+        }
+    }
+
+    /// Open the entry with the id in a metadata pair.
+    pub(crate) fn from_entry(fs: &'a LittleFs<'a, D>, mdir: &MetaDir, id: u16) -> Result<Self, Error> {
+        let (stag, off) = fs
+            .get(mdir, tag(0x700, 0x3ff, 0), tag(TYPE_STRUCT, id, 0))?
+            .ok_or(msg2err!("entry without struct"))?;
+        let mut buf = [0; 8];
+        let word = |buf: &[u8; 8], i: usize| u32::from_le_bytes(buf[i * 4..i * 4 + 4].try_into().unwrap());
+        let block = mdir.pair[0];
+        let (kind, size) = match tag_type3(stag) {
+            TYPE_DIRSTRUCT | TYPE_CTZSTRUCT if tag_size(stag) < 8 => return Err(msg2err!("short struct")),
+            TYPE_DIRSTRUCT => {
+                fs.read(block, off, &mut buf)?;
+                (Kind::Dir([word(&buf, 0), word(&buf, 1)]), 0)
+            }
+            TYPE_INLINESTRUCT => (Kind::Inline { block, off }, tag_size(stag) as u64),
+            TYPE_CTZSTRUCT => {
+                fs.read(block, off, &mut buf)?;
+                (Kind::Ctz { head: word(&buf, 0) }, word(&buf, 1) as u64)
+            }
+            _ => return Err(msg2err!("unknown struct")),
+        };
+        Ok(Self {
+            fs,
+            id: (core::cmp::min(mdir.pair[0], mdir.pair[1]) as u64) << 10 | id as u64,
+            kind,
+            size,
+        })
+    }
+
+    pub fn ftype(&self) -> FileType {
+        match self.kind {
+            Kind::Dir(_) => FileType::Directory,
+            _ => FileType::File,
+        }
+    }
+
+    /// The pair of the first metadata block of a directory.
+    pub(crate) fn pair(&self) -> Option<[u32; 2]> {
+        match self.kind {
+            Kind::Dir(pair) => Some(pair),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        Dir::new(self).ok()
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        let pair = self.pair().ok_or(msg2err!("not a directory"))?;
+        let mut mdir = self.fs.fetch(pair)?;
+        for _ in 0..offset >> 10 {
+            if !mdir.split {
+                return Err(msg2err!("not a child"));
+            }
+            mdir = self.fs.fetch(mdir.tail)?;
+        }
+        Self::from_entry(self.fs, &mdir, (offset & 0x3ff) as u16)
+    }
+
+    fn lookup(&self, name: &[u8]) -> Result<Option<Self>, Error> {
+        let pair = self.pair().ok_or(msg2err!("not a directory"))?;
+        let (mdir, id) = self.fs.find(pair, name)?;
+        id.map(|id| Self::from_entry(self.fs, &mdir, id)).transpose()
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+        let max_n = core::cmp::min(buf.len() as Offset, self.size - offset) as usize;
+        let buf = &mut buf[..max_n];
+        match self.kind {
+            Kind::Dir(_) => Ok(0),
+            Kind::Inline { block, off } => {
+                self.fs.read(block, off + offset as u32, buf)?;
+                Ok(buf.len())
+            }
+            Kind::Ctz { head } => {
+                let (index, off) = ctz_index(self.fs.sb.block_size, offset);
+                let block = self.fs.ctz_find(head, self.size, index)?;
+                let n = core::cmp::min(buf.len(), (self.fs.sb.block_size - off) as usize);
+                self.fs.read(block, off, &mut buf[..n])?;
+                Ok(n)
+            }
+        }
+    }
+}
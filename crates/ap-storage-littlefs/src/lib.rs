@@ -0,0 +1,233 @@
+//! Browse LittleFS v2 images as found on the flash of microcontrollers.
+//!
+//! - metadata pairs with revision counts and checksummed commits
+//! - directories that are split over several metadata pairs
+//! - inline files and files in CTZ skip-lists
+//! - entries hidden by an interrupted move in the global state
+//! - writing files and directories behind the `write` feature
+//!
+//! Reading needs no allocations and walks the tags of a metadata block backwards like the reference
+//! implementation.  The offset of a directory entry is the index of the metadata pair in the directory shifted by ten
+//! bits plus the id.
+
+#![no_std]
+
+#[cfg(feature = "write")]
+extern crate alloc;
+
+mod attr;
+mod dir;
+pub mod file;
+mod meta;
+#[cfg(feature = "write")]
+mod write;
+
+use ap_storage::{msg2err, Error, FileSystem, Offset, Read, ReadExt};
+use meta::*;
+pub use meta::{crc32, MetaDir};
+
+/// The pair of the superblock and the root directory.
+pub const ROOT_PAIR: [u32; 2] = [0, 1];
+
+/// The magic string as name of the superblock entry.
+const MAGIC: &[u8; 8] = b"littlefs";
+
+/// The superblock stored inline in the first metadata pair.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Superblock {
+    pub version: u32,
+    pub block_size: u32,
+    pub block_count: u32,
+    pub name_max: u32,
+    pub file_max: u32,
+    pub attr_max: u32,
+}
+
+impl Superblock {
+    fn from_bytes(buf: &[u8; 24]) -> Self {
+        let word = |i: usize| u32::from_le_bytes(buf[i * 4..i * 4 + 4].try_into().unwrap());
+        Self {
+            version: word(0),
+            block_size: word(1),
+            block_count: word(2),
+            name_max: word(3),
+            file_max: word(4),
+            attr_max: word(5),
+        }
+    }
+}
+
+/// The index of the CTZ block that holds a file offset and the offset inside that block.
+pub(crate) fn ctz_index(block_size: u32, pos: u64) -> (u32, u32) {
+    let b = (block_size - 8) as u64;
+    let i = pos / b;
+    if i == 0 {
+        return (0, pos as u32);
+    }
+    let i = (pos - 4 * ((i - 1).count_ones() as u64 + 2)) / b;
+    (i as u32, (pos - b * i - 4 * i.count_ones() as u64) as u32)
+}
+
+/// A LittleFS image.
+pub struct LittleFs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+    pub(crate) sb: Superblock,
+    pub(crate) gstate: GState,
+}
+
+impl<D: ?Sized> core::fmt::Debug for LittleFs<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("LittleFs").field("sb", &self.sb).finish()
+    }
+}
+
+impl<'a, D: Read + ?Sized> LittleFs<'a, D> {
+    /// Mount an image by searching the superblock and collecting the global state.
+    pub fn new(disk: &'a D) -> Result<Self, Error> {
+        let mut fs = Self {
+            disk,
+            sb: Superblock::default(),
+            gstate: GState::default(),
+        };
+        // the magic is at a fixed place in both superblock blocks, but the second needs the block size
+        let mut magic = [0; 8];
+        let probe = (7..=24).map(|x| 1u64 << x);
+        let first = core::iter::once(0)
+            .chain(probe)
+            .find(|x| fs.disk().read_exact(x + 8, &mut magic).is_ok() && &magic == MAGIC)
+            .ok_or(msg2err!("no littlefs superblock"))?;
+        fs.sb.block_size = if first == 0 { 1 << 30 } else { first as u32 };
+        fs.sb.block_count = 2;
+        let dir = fs.fetch([(first != 0) as u32; 2])?;
+        fs.sb = fs.read_superblock(&dir)?;
+        if fs.sb.version >> 16 != 2 {
+            return Err(msg2err!("unsupported littlefs version"));
+        }
+        if fs.sb.block_size < 128 || fs.sb.block_count < 2 {
+            return Err(msg2err!("invalid littlefs geometry"));
+        }
+
+        // the superblock might be newer in the other block
+        let dir = fs.fetch(ROOT_PAIR)?;
+        fs.sb = fs.read_superblock(&dir)?;
+        let mut gstate = GState::default();
+        fs.tails(|fs, dir| {
+            gstate.xor(&fs.gdelta(dir)?);
+            Ok(())
+        })?;
+        fs.gstate = gstate;
+        Ok(fs)
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    /// The parsed superblock.
+    pub fn superblock(&self) -> &Superblock {
+        &self.sb
+    }
+
+    /// Read from a block.
+    pub(crate) fn read(&self, block: u32, off: u32, buf: &mut [u8]) -> Result<(), Error> {
+        if block >= self.sb.block_count || off as u64 + buf.len() as u64 > self.sb.block_size as u64 {
+            return Err(msg2err!("block out of range"));
+        }
+        let pos = block as Offset * self.sb.block_size as Offset + off as Offset;
+        self.disk().read_exact(pos, buf)
+    }
+
+    /// Parse the superblock entry of a metadata pair.
+    fn read_superblock(&self, dir: &MetaDir) -> Result<Superblock, Error> {
+        let mask = tag(0x7ff, 0x3ff, 0);
+        let (name, off) = self
+            .get(dir, mask, tag(TYPE_SUPERBLOCK, 0, 0))?
+            .ok_or(msg2err!("no littlefs superblock"))?;
+        let mut magic = [0; 8];
+        if tag_size(name) == 8 {
+            self.read(dir.pair[0], off, &mut magic)?;
+        }
+        if &magic != MAGIC {
+            return Err(msg2err!("no littlefs superblock"));
+        }
+        let (inline, off) = self
+            .get(dir, mask, tag(TYPE_INLINESTRUCT, 0, 0))?
+            .ok_or(msg2err!("no littlefs superblock"))?;
+        let mut buf = [0; 24];
+        let n = core::cmp::min(tag_size(inline), 24) as usize;
+        self.read(dir.pair[0], off, &mut buf[..n])?;
+        Ok(Superblock::from_bytes(&buf))
+    }
+
+    /// Call a function for every metadata pair in the list threaded through all directories.
+    pub(crate) fn tails(&self, mut f: impl FnMut(&Self, &MetaDir) -> Result<(), Error>) -> Result<(), Error> {
+        let mut pair = ROOT_PAIR;
+        let mut count = 0;
+        while pair != NULL_PAIR {
+            count += 1;
+            if count > self.sb.block_count {
+                return Err(msg2err!("cycle in the metadata list"));
+            }
+            let dir = self.fetch(pair)?;
+            f(self, &dir)?;
+            pair = dir.tail;
+        }
+        Ok(())
+    }
+
+    /// Compare the name of an entry without copying it.
+    fn name_matches(&self, mdir: &MetaDir, id: u16, name: &[u8]) -> Result<bool, Error> {
+        let Some((ntag, off)) = self.get(mdir, tag(0x780, 0x3ff, 0), tag(TYPE_NAME, id, 0))? else {
+            return Ok(false);
+        };
+        if tag_size(ntag) as usize != name.len() {
+            return Ok(false);
+        }
+        let mut buf = [0; 64];
+        for (i, chunk) in name.chunks(buf.len()).enumerate() {
+            let buf = &mut buf[..chunk.len()];
+            self.read(mdir.pair[0], off + (i * 64) as u32, buf)?;
+            if buf != chunk {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Search a name in the metadata pairs of a directory.  Returns the last pair if nothing was found.
+    pub(crate) fn find(&self, pair: [u32; 2], name: &[u8]) -> Result<(MetaDir, Option<u16>), Error> {
+        let mut mdir = self.fetch(pair)?;
+        loop {
+            for id in 0..mdir.count {
+                if self.name_matches(&mdir, id, name)? {
+                    return Ok((mdir, Some(id)));
+                }
+            }
+            if !mdir.split {
+                return Ok((mdir, None));
+            }
+            mdir = self.fetch(mdir.tail)?;
+        }
+    }
+
+    /// Follow a CTZ skip-list from the last block to the one with the index.
+    pub(crate) fn ctz_find(&self, mut head: u32, size: u64, target: u32) -> Result<u32, Error> {
+        let (mut current, _) = ctz_index(self.sb.block_size, size - 1);
+        while current > target {
+            let skip = core::cmp::min(31 - (current - target).leading_zeros(), current.trailing_zeros());
+            let mut buf = [0; 4];
+            self.read(head, 4 * skip, &mut buf)?;
+            head = u32::from_le_bytes(buf);
+            current -= 1 << skip;
+        }
+        Ok(head)
+    }
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for LittleFs<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        Ok(file::File::root(self))
+    }
+}
@@ -0,0 +1,282 @@
+//! Metadata pairs and their tags.
+
+use super::LittleFs;
+use ap_storage::{msg2err, Error, Read};
+
+pub(crate) const TYPE_REG: u16 = 0x001;
+pub(crate) const TYPE_DIR: u16 = 0x002;
+pub(crate) const TYPE_SUPERBLOCK: u16 = 0x0ff;
+pub(crate) const TYPE_NAME: u16 = 0x000;
+pub(crate) const TYPE_STRUCT: u16 = 0x200;
+#[cfg(feature = "write")]
+pub(crate) const TYPE_USERATTR: u16 = 0x300;
+pub(crate) const TYPE_SPLICE: u16 = 0x400;
+pub(crate) const TYPE_CREATE: u16 = 0x401;
+pub(crate) const TYPE_CCRC: u16 = 0x500;
+pub(crate) const TYPE_TAIL: u16 = 0x600;
+#[cfg(feature = "write")]
+pub(crate) const TYPE_SOFTTAIL: u16 = 0x600;
+#[cfg(feature = "write")]
+pub(crate) const TYPE_HARDTAIL: u16 = 0x601;
+#[cfg(feature = "write")]
+pub(crate) const TYPE_GLOBALS: u16 = 0x700;
+pub(crate) const TYPE_MOVESTATE: u16 = 0x7ff;
+pub(crate) const TYPE_DIRSTRUCT: u16 = 0x200;
+pub(crate) const TYPE_INLINESTRUCT: u16 = 0x201;
+pub(crate) const TYPE_CTZSTRUCT: u16 = 0x202;
+
+/// The id of tags that belong to the metadata pair.
+pub(crate) const ID_NONE: u16 = 0x3ff;
+
+/// The tail of the last metadata pair.
+pub(crate) const NULL_PAIR: [u32; 2] = [u32::MAX; 2];
+
+/// Build a tag from its type, id and size.
+pub(crate) const fn tag(typ: u16, id: u16, size: u32) -> u32 {
+    (typ as u32) << 20 | (id as u32) << 10 | size
+}
+
+pub(crate) fn tag_type1(tag: u32) -> u16 {
+    ((tag & 0x7000_0000) >> 20) as u16
+}
+
+pub(crate) fn tag_type2(tag: u32) -> u16 {
+    ((tag & 0x7800_0000) >> 20) as u16
+}
+
+pub(crate) fn tag_type3(tag: u32) -> u16 {
+    ((tag & 0x7ff0_0000) >> 20) as u16
+}
+
+pub(crate) fn tag_chunk(tag: u32) -> u8 {
+    ((tag & 0x0ff0_0000) >> 20) as u8
+}
+
+pub(crate) fn tag_id(tag: u32) -> u16 {
+    ((tag & 0x000f_fc00) >> 10) as u16
+}
+
+pub(crate) fn tag_size(tag: u32) -> u32 {
+    tag & 0x3ff
+}
+
+pub(crate) fn tag_is_delete(tag: u32) -> bool {
+    tag_size(tag) == 0x3ff
+}
+
+/// The size of a tag with its data.
+pub(crate) fn tag_dsize(tag: u32) -> u32 {
+    4 + if tag_is_delete(tag) { 0 } else { tag_size(tag) }
+}
+
+/// The CRC-32 of littlefs without the final inversion.
+pub fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    const TABLE: [u32; 16] = [
+        0x00000000, 0x1db71064, 0x3b6e20c8, 0x26d930ac, 0x76dc4190, 0x6b6b51f4, 0x4db26158, 0x5005713c, 0xedb88320,
+        0xf00f9344, 0xd6d6a3e8, 0xcb61b38c, 0x9b64c2b0, 0x86d3d2d4, 0xa00ae278, 0xbdbdf21c,
+    ];
+    for x in data {
+        crc = (crc >> 4) ^ TABLE[((crc ^ *x as u32) & 0xf) as usize];
+        crc = (crc >> 4) ^ TABLE[((crc ^ (*x as u32 >> 4)) & 0xf) as usize];
+    }
+    crc
+}
+
+/// Whether two pairs share a block.
+pub(crate) fn pair_overlaps(a: [u32; 2], b: [u32; 2]) -> bool {
+    a[0] == b[0] || a[1] == b[1] || a[0] == b[1] || a[1] == b[0]
+}
+
+/// The state of a metadata pair after its last valid commit.
+#[derive(Debug, Clone, Copy)]
+pub struct MetaDir {
+    /// The blocks with the current one first.
+    pub pair: [u32; 2],
+    pub rev: u32,
+    /// The end of the last valid commit.
+    pub(crate) off: u32,
+    /// The last tag including the valid bit expected for the next commit.
+    pub(crate) etag: u32,
+    /// The number of ids.
+    pub count: u16,
+    pub tail: [u32; 2],
+    /// The tail continues the same directory.
+    pub split: bool,
+}
+
+/// The global state as XOR of the deltas in all metadata pairs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct GState {
+    pub(crate) tag: u32,
+    pub(crate) pair: [u32; 2],
+}
+
+impl GState {
+    pub(crate) fn from_bytes(buf: &[u8; 12]) -> Self {
+        let word = |i: usize| u32::from_le_bytes(buf[i * 4..i * 4 + 4].try_into().unwrap());
+        Self {
+            tag: word(0),
+            pair: [word(1), word(2)],
+        }
+    }
+
+    #[cfg(feature = "write")]
+    pub(crate) fn to_bytes(self) -> [u8; 12] {
+        let mut res = [0; 12];
+        for (i, x) in [self.tag, self.pair[0], self.pair[1]].iter().enumerate() {
+            res[i * 4..i * 4 + 4].copy_from_slice(&x.to_le_bytes());
+        }
+        res
+    }
+
+    pub(crate) fn xor(&mut self, other: &Self) {
+        self.tag ^= other.tag;
+        self.pair[0] ^= other.pair[0];
+        self.pair[1] ^= other.pair[1];
+    }
+
+    /// Whether an interrupted move left a stale entry in the pair.
+    pub(crate) fn moved_from(&self, pair: [u32; 2]) -> bool {
+        tag_type1(self.tag) != 0 && pair_overlaps(self.pair, pair)
+    }
+}
+
+impl<'a, D: Read + ?Sized> LittleFs<'a, D> {
+    /// The revision count at the start of a block.
+    pub(crate) fn revision(&self, block: u32) -> Result<u32, Error> {
+        let mut buf = [0; 4];
+        self.read(block, 0, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    /// Validate the commits of a metadata block.  Returns None without a valid commit.
+    fn fetch_block(&self, block: u32) -> Result<Option<MetaDir>, Error> {
+        let block_size = self.sb.block_size;
+        let rev = self.revision(block)?;
+        let mut crc = crc32(u32::MAX, &rev.to_le_bytes());
+        let (mut off, mut ptag) = (0, u32::MAX);
+        let (mut count, mut tail, mut split) = (0u16, NULL_PAIR, false);
+        let mut res = None;
+        loop {
+            off += tag_dsize(ptag);
+            if off + 4 > block_size {
+                break;
+            }
+            let mut raw = [0; 4];
+            self.read(block, off, &mut raw)?;
+            crc = crc32(crc, &raw);
+            let tag = u32::from_be_bytes(raw) ^ ptag;
+            if tag & 0x8000_0000 != 0 || off + tag_dsize(tag) > block_size {
+                break;
+            }
+            ptag = tag;
+
+            if tag_type2(tag) == TYPE_CCRC {
+                let mut stored = [0; 4];
+                if tag_size(tag) < 4 {
+                    break;
+                }
+                self.read(block, off + 4, &mut stored)?;
+                if crc != u32::from_le_bytes(stored) {
+                    break;
+                }
+                // the commit decides the valid bit of the next one
+                ptag ^= ((tag_chunk(tag) & 1) as u32) << 31;
+                res = Some(MetaDir {
+                    pair: [block, block],
+                    rev,
+                    off: off + tag_dsize(tag),
+                    etag: ptag,
+                    count,
+                    tail,
+                    split,
+                });
+                crc = u32::MAX;
+                continue;
+            }
+
+            let mut buf = [0; 64];
+            let mut pos = off + 4;
+            while pos < off + tag_dsize(tag) {
+                let n = core::cmp::min(buf.len() as u32, off + tag_dsize(tag) - pos);
+                self.read(block, pos, &mut buf[..n as usize])?;
+                crc = crc32(crc, &buf[..n as usize]);
+                pos += n;
+            }
+            match tag_type1(tag) {
+                TYPE_NAME if tag_id(tag) >= count => count = tag_id(tag) + 1,
+                TYPE_SPLICE => count = count.wrapping_add(tag_chunk(tag) as i8 as u16),
+                TYPE_TAIL if tag_size(tag) >= 8 => {
+                    split = tag_chunk(tag) & 1 != 0;
+                    let mut buf = [0; 8];
+                    self.read(block, off + 4, &mut buf)?;
+                    tail = [
+                        u32::from_le_bytes(buf[..4].try_into().unwrap()),
+                        u32::from_le_bytes(buf[4..].try_into().unwrap()),
+                    ];
+                }
+                _ => {}
+            }
+        }
+        Ok(res)
+    }
+
+    /// Fetch the newest valid block of a metadata pair.
+    pub fn fetch(&self, pair: [u32; 2]) -> Result<MetaDir, Error> {
+        let revs = [self.revision(pair[0])?, self.revision(pair[1])?];
+        // revision counts are compared as sequence numbers
+        let first = ((revs[1].wrapping_sub(revs[0]) as i32) > 0) as usize;
+        for i in [first, 1 - first] {
+            if let Some(mut dir) = self.fetch_block(pair[i])? {
+                dir.pair = [pair[i], pair[1 - i]];
+                return Ok(dir);
+            }
+        }
+        Err(msg2err!("corrupted metadata pair"))
+    }
+
+    /// Find the newest tag matching under a mask and return it with the offset of its data.
+    ///
+    /// The commits are walked backwards while ids are shifted around created and deleted entries.
+    pub(crate) fn get(&self, dir: &MetaDir, gmask: u32, gtag: u32) -> Result<Option<(u32, u32)>, Error> {
+        let (mut off, mut ntag) = (dir.off, dir.etag);
+        let mut gdiff = 0u32;
+        let by_id = tag_id(gmask) != 0;
+        if by_id && self.gstate.moved_from(dir.pair) && tag_id(self.gstate.tag) <= tag_id(gtag) {
+            // the source of an interrupted move is hidden
+            gdiff = gdiff.wrapping_sub(tag(0, 1, 0));
+        }
+        while off >= 4 + tag_dsize(ntag) {
+            off -= tag_dsize(ntag);
+            let tag = ntag;
+            let mut raw = [0; 4];
+            self.read(dir.pair[0], off, &mut raw)?;
+            ntag = (u32::from_be_bytes(raw) ^ tag) & 0x7fff_ffff;
+
+            let want = gtag.wrapping_sub(gdiff);
+            if by_id && tag_type1(tag) == TYPE_SPLICE && tag_id(tag) <= tag_id(want) {
+                if tag == self::tag(TYPE_CREATE, tag_id(want), 0) {
+                    return Ok(None);
+                }
+                gdiff = gdiff.wrapping_add(((tag_chunk(tag) as i8 as i32) << 10) as u32);
+            }
+            if gmask & tag == gmask & gtag.wrapping_sub(gdiff) {
+                if tag_is_delete(tag) {
+                    return Ok(None);
+                }
+                return Ok(Some((tag.wrapping_add(gdiff), off + 4)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read the global state delta of a metadata pair.
+    pub(crate) fn gdelta(&self, dir: &MetaDir) -> Result<GState, Error> {
+        let mut buf = [0; 12];
+        if let Some((tag, off)) = self.get(dir, tag(0x7ff, 0, 0), tag(TYPE_MOVESTATE, 0, 0))? {
+            let n = core::cmp::min(tag_size(tag), 12) as usize;
+            self.read(dir.pair[0], off, &mut buf[..n])?;
+        }
+        Ok(GState::from_bytes(&buf))
+    }
+}
@@ -0,0 +1,495 @@
+//! Writing files and directories.
+//!
+//! Every change compacts the affected metadata pair into its older block, so the previous state stays valid until
+//! the new commit is complete.  A pair that does not fit into a block is split into two.
+
+use super::{ctz_index, file::File, meta::*, LittleFs, Superblock, MAGIC};
+use alloc::{vec, vec::Vec};
+use ap_storage::{msg2err, Error, Offset, Read, Write, WriteExt};
+
+/// Commits end on this alignment.
+const PROG_SIZE: usize = 16;
+
+/// The decoded content of a metadata pair.
+#[derive(Debug, Clone)]
+struct Model {
+    /// The tags with their data for every id.
+    ids: Vec<Vec<(u32, Vec<u8>)>>,
+    tail: [u32; 2],
+    split: bool,
+    gdelta: [u8; 12],
+}
+
+/// Tags with the same key replace each other.
+fn key(tag: u32) -> u16 {
+    match tag_type1(tag) {
+        TYPE_USERATTR => tag_type3(tag),
+        x => x,
+    }
+}
+
+impl Model {
+    fn new() -> Self {
+        Self {
+            ids: Vec::new(),
+            tail: NULL_PAIR,
+            split: false,
+            gdelta: [0; 12],
+        }
+    }
+
+    /// Set or delete an attribute of an id.
+    fn set(&mut self, tag: u32, data: Vec<u8>) {
+        let id = tag_id(tag) as usize;
+        if id >= self.ids.len() {
+            self.ids.resize(id + 1, Vec::new());
+        }
+        let attrs = &mut self.ids[id];
+        attrs.retain(|(x, _)| key(*x) != key(tag));
+        if !tag_is_delete(tag) {
+            attrs.push((tag, data));
+            attrs.sort_by_key(|(x, _)| tag_type3(*x));
+        }
+    }
+
+    /// The name tag and the name of an id.
+    fn name(&self, id: usize) -> Option<(u32, &[u8])> {
+        let attrs = self.ids.get(id)?;
+        attrs
+            .iter()
+            .find(|(x, _)| tag_type1(*x) == TYPE_NAME)
+            .map(|(x, name)| (*x, &name[..]))
+    }
+
+    /// Insert a new entry sorted by name behind the superblock.
+    fn insert(&mut self, name: &[u8], typ: u16, stag: u32, data: Vec<u8>) {
+        let id = (0..self.ids.len())
+            .find(|i| matches!(self.name(*i), Some((x, n)) if tag_type3(x) != TYPE_SUPERBLOCK && n > name))
+            .unwrap_or(self.ids.len());
+        self.ids.insert(id, Vec::new());
+        self.set(tag(typ, id as u16, name.len() as u32), name.to_vec());
+        self.set(stag | (id as u32) << 10, data);
+    }
+
+    /// Encode the model as a block with a single commit.  Returns None if it does not fit.
+    fn encode(&self, rev: u32, block_size: usize) -> Option<Vec<u8>> {
+        let mut commit = Commit {
+            buf: vec![0xff; block_size],
+            off: 4,
+            ptag: u32::MAX,
+        };
+        commit.buf[..4].copy_from_slice(&rev.to_le_bytes());
+        for (id, attrs) in self.ids.iter().enumerate() {
+            for (tag, data) in attrs {
+                commit.push(tag & !0x000f_fc00 | (id as u32) << 10, data)?;
+            }
+        }
+        if self.tail != NULL_PAIR {
+            let typ = if self.split { TYPE_HARDTAIL } else { TYPE_SOFTTAIL };
+            let mut data = [0; 8];
+            data[..4].copy_from_slice(&self.tail[0].to_le_bytes());
+            data[4..].copy_from_slice(&self.tail[1].to_le_bytes());
+            commit.push(tag(typ, ID_NONE, 8), &data)?;
+        }
+        if self.gdelta != [0; 12] {
+            commit.push(tag(TYPE_MOVESTATE, ID_NONE, 12), &self.gdelta)?;
+        }
+
+        // the checksum is padded and the erased bytes behind it invalidate the next commit
+        let off = commit.off;
+        let noff = (off + 8).next_multiple_of(PROG_SIZE);
+        if noff > block_size {
+            return None;
+        }
+        let ctag = tag(TYPE_CCRC, ID_NONE, (noff - off - 4) as u32);
+        commit.buf[off..off + 4].copy_from_slice(&(ctag ^ commit.ptag).to_be_bytes());
+        let crc = crc32(u32::MAX, &commit.buf[..off + 4]);
+        commit.buf[off + 4..off + 8].copy_from_slice(&crc.to_le_bytes());
+        Some(commit.buf)
+    }
+}
+
+/// A commit that is built in memory.
+struct Commit {
+    buf: Vec<u8>,
+    off: usize,
+    ptag: u32,
+}
+
+impl Commit {
+    fn push(&mut self, tag: u32, data: &[u8]) -> Option<()> {
+        // keep space for the checksum
+        if self.off + 4 + data.len() + 8 > self.buf.len() {
+            return None;
+        }
+        let tag = tag & 0x7fff_ffff;
+        self.buf[self.off..self.off + 4].copy_from_slice(&(tag ^ self.ptag).to_be_bytes());
+        self.buf[self.off + 4..self.off + 4 + data.len()].copy_from_slice(data);
+        self.off += 4 + data.len();
+        self.ptag = tag;
+        Some(())
+    }
+}
+
+/// Mark a block as used.
+fn mark(used: &mut [bool], block: u32) -> Result<(), Error> {
+    *used.get_mut(block as usize).ok_or(msg2err!("block out of range"))? = true;
+    Ok(())
+}
+
+/// Allocate the first free block.
+fn alloc(used: &mut [bool]) -> Result<u32, Error> {
+    let block = used.iter().position(|x| !x).ok_or(msg2err!("no space left"))?;
+    used[block] = true;
+    Ok(block as u32)
+}
+
+impl Superblock {
+    fn to_bytes(self) -> [u8; 24] {
+        let mut res = [0; 24];
+        let words = [
+            self.version,
+            self.block_size,
+            self.block_count,
+            self.name_max,
+            self.file_max,
+            self.attr_max,
+        ];
+        for (i, x) in words.iter().enumerate() {
+            res[i * 4..i * 4 + 4].copy_from_slice(&x.to_le_bytes());
+        }
+        res
+    }
+}
+
+/// A metadata pair without a valid commit.
+fn blank(pair: [u32; 2], rev: u32) -> MetaDir {
+    MetaDir {
+        pair,
+        rev,
+        off: 0,
+        etag: 0,
+        count: 0,
+        tail: NULL_PAIR,
+        split: false,
+    }
+}
+
+impl<'a, D: Read + Write + ?Sized> LittleFs<'a, D> {
+    /// Format a disk with an empty filesystem.
+    pub fn format(disk: &'a D, block_size: u32, block_count: u32) -> Result<Self, Error> {
+        if block_size < 128 || block_size & 3 != 0 || block_count < 2 {
+            return Err(msg2err!("invalid littlefs geometry"));
+        }
+        let fs = Self {
+            disk,
+            sb: Superblock {
+                version: 0x0002_0000,
+                block_size,
+                block_count,
+                name_max: 255,
+                file_max: 0x7fff_ffff,
+                attr_max: 0x3fe,
+            },
+            gstate: GState::default(),
+        };
+        let mut model = Model::new();
+        model.set(tag(TYPE_SUPERBLOCK, 0, 8), MAGIC.to_vec());
+        model.set(tag(TYPE_INLINESTRUCT, 0, 24), fs.sb.to_bytes().to_vec());
+        let mut used = vec![false; block_count as usize];
+        used[..2].fill(true);
+
+        // both blocks get the superblock, as stale data could look like a newer revision
+        fs.store(&mut used, &blank([1, 0], 0), model.clone())?;
+        fs.store(&mut used, &blank([0, 1], 1), model)?;
+        Ok(fs)
+    }
+
+    /// The disk as trait object for writing.
+    fn writer(&self) -> &dyn Write {
+        &self.disk
+    }
+
+    /// Files up to this size are stored in the metadata.
+    fn inline_max(&self) -> usize {
+        let attr_max = match self.sb.attr_max {
+            0 => 0x3fe,
+            x => core::cmp::min(x, 0x3fe),
+        };
+        core::cmp::min(attr_max, self.sb.block_size / 8) as usize
+    }
+
+    /// Changes are refused while a move is pending, as the hidden entry would be kept.
+    fn writable(&self) -> Result<(), Error> {
+        if self.gstate != GState::default() {
+            return Err(msg2err!("pending move in the global state"));
+        }
+        Ok(())
+    }
+
+    /// Replay the commits of a metadata pair.
+    fn load(&self, dir: &MetaDir) -> Result<Model, Error> {
+        let block = dir.pair[0];
+        let mut model = Model::new();
+        let (mut off, mut ptag) = (0, u32::MAX);
+        loop {
+            off += tag_dsize(ptag);
+            if off >= dir.off {
+                break;
+            }
+            let mut raw = [0; 4];
+            self.read(block, off, &mut raw)?;
+            let tag = u32::from_be_bytes(raw) ^ ptag;
+            ptag = tag;
+            if tag_type2(tag) == TYPE_CCRC {
+                ptag ^= ((tag_chunk(tag) & 1) as u32) << 31;
+                continue;
+            }
+            let mut data = vec![0; tag_dsize(tag) as usize - 4];
+            self.read(block, off + 4, &mut data)?;
+            let id = tag_id(tag) as usize;
+            match tag_type1(tag) {
+                TYPE_SPLICE if tag_chunk(tag) == 1 && id <= model.ids.len() => model.ids.insert(id, Vec::new()),
+                TYPE_SPLICE if tag_chunk(tag) == 0xff && id < model.ids.len() => {
+                    model.ids.remove(id);
+                }
+                TYPE_NAME | TYPE_STRUCT | TYPE_USERATTR => model.set(tag, data),
+                TYPE_TAIL if data.len() >= 8 => {
+                    model.split = tag_chunk(tag) & 1 != 0;
+                    model.tail = [
+                        u32::from_le_bytes(data[..4].try_into().unwrap()),
+                        u32::from_le_bytes(data[4..8].try_into().unwrap()),
+                    ];
+                }
+                // the newest delta holds the ones before
+                TYPE_GLOBALS => {
+                    let n = core::cmp::min(data.len(), 12);
+                    model.gdelta = [0; 12];
+                    model.gdelta[..n].copy_from_slice(&data[..n]);
+                }
+                _ => {}
+            }
+        }
+        Ok(model)
+    }
+
+    /// Compact a model into the older block of a pair and split it when it does not fit.
+    fn store(&self, used: &mut [bool], dir: &MetaDir, mut model: Model) -> Result<MetaDir, Error> {
+        let block_size = self.sb.block_size;
+        let rev = dir.rev.wrapping_add(1);
+        if let Some(buf) = model
+            .encode(rev, block_size as usize)
+            .filter(|_| model.ids.len() < 0xff)
+        {
+            let pos = dir.pair[1] as Offset * block_size as Offset;
+            self.writer().write_exact(pos, &buf)?;
+            return self.fetch([dir.pair[1], dir.pair[0]]);
+        }
+        if model.ids.len() < 2 {
+            return Err(msg2err!("entry does not fit into a block"));
+        }
+        let mut upper = Model::new();
+        upper.ids = model.ids.split_off(model.ids.len() / 2);
+        upper.tail = model.tail;
+        upper.split = model.split;
+        let next = self.alloc_pair(used)?;
+        let next = self.store(used, &next, upper)?;
+        model.tail = next.pair;
+        model.split = true;
+        self.store(used, dir, model)
+    }
+
+    /// Allocate a new metadata pair that is written to its second block.
+    fn alloc_pair(&self, used: &mut [bool]) -> Result<MetaDir, Error> {
+        let pair = [alloc(used)?, alloc(used)?];
+        Ok(blank(pair, self.revision(pair[0])?))
+    }
+
+    /// Collect the blocks in use by metadata pairs and files.
+    fn used(&self) -> Result<Vec<bool>, Error> {
+        let mut used = vec![false; self.sb.block_count as usize];
+        self.tails(|fs, dir| {
+            mark(&mut used, dir.pair[0])?;
+            mark(&mut used, dir.pair[1])?;
+            for id in 0..dir.count {
+                let Some((stag, off)) = fs.get(dir, tag(0x700, 0x3ff, 0), tag(TYPE_STRUCT, id, 0))? else {
+                    continue;
+                };
+                if tag_type3(stag) != TYPE_CTZSTRUCT || tag_size(stag) < 8 {
+                    continue;
+                }
+                let mut buf = [0; 8];
+                fs.read(dir.pair[0], off, &mut buf)?;
+                let mut block = u32::from_le_bytes(buf[..4].try_into().unwrap());
+                let size = u32::from_le_bytes(buf[4..].try_into().unwrap());
+                if size == 0 {
+                    continue;
+                }
+                // the first pointer of every block leads to the previous one
+                let (last, _) = ctz_index(fs.sb.block_size, size as u64 - 1);
+                for index in (0..=last).rev() {
+                    mark(&mut used, block)?;
+                    if index != 0 {
+                        let mut buf = [0; 4];
+                        fs.read(block, 0, &mut buf)?;
+                        block = u32::from_le_bytes(buf);
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        Ok(used)
+    }
+
+    /// Write the data into a new CTZ skip-list and return its last block.
+    fn write_ctz(&self, used: &mut [bool], data: &[u8]) -> Result<u32, Error> {
+        let block_size = self.sb.block_size as usize;
+        let mut blocks: Vec<u32> = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let index = blocks.len();
+            let block = alloc(used)?;
+            let skips = if index == 0 {
+                0
+            } else {
+                index.trailing_zeros() as usize + 1
+            };
+            let n = core::cmp::min(block_size - 4 * skips, data.len() - pos);
+            let mut buf = vec![0; 4 * skips + n];
+            for k in 0..skips {
+                buf[4 * k..4 * k + 4].copy_from_slice(&blocks[index - (1 << k)].to_le_bytes());
+            }
+            buf[4 * skips..].copy_from_slice(&data[pos..pos + n]);
+            self.writer()
+                .write_exact(block as Offset * block_size as Offset, &buf)?;
+            blocks.push(block);
+            pos += n;
+        }
+        blocks.last().copied().ok_or(msg2err!("empty file"))
+    }
+
+    /// Resolve the directory of a path and return its pair with the last name.
+    fn parent<'p>(&self, path: &'p [u8]) -> Result<([u32; 2], &'p [u8]), Error> {
+        let (dir, name) = match path.iter().rposition(|x| *x == b'/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => (&path[..0], path),
+        };
+        if name.is_empty() || name == b"." || name == b".." {
+            return Err(msg2err!("invalid name"));
+        }
+        let name_max = match self.sb.name_max {
+            0 => 255,
+            x => x,
+        };
+        if name.len() > name_max as usize {
+            return Err(msg2err!("name too long"));
+        }
+        let dir = ap_storage::file::File::lookup_path(File::root(self), dir)?;
+        Ok((dir.pair().ok_or(msg2err!("not a directory"))?, name))
+    }
+
+    /// Create an empty directory.
+    pub fn create_dir(&mut self, path: &[u8]) -> Result<(), Error> {
+        self.writable()?;
+        let (pair, name) = self.parent(path)?;
+        let (last, found) = self.find(pair, name)?;
+        if found.is_some() {
+            return Err(msg2err!("file exists"));
+        }
+        let mut used = self.used()?;
+
+        // the new directory follows the last pair of its parent in the list of all pairs
+        let mut child = Model::new();
+        child.tail = last.tail;
+        let dir = self.alloc_pair(&mut used)?;
+        let dir = self.store(&mut used, &dir, child)?;
+        let mut model = self.load(&last)?;
+        let mut data = [0; 8];
+        data[..4].copy_from_slice(&dir.pair[0].to_le_bytes());
+        data[4..].copy_from_slice(&dir.pair[1].to_le_bytes());
+        model.insert(name, TYPE_DIR, tag(TYPE_DIRSTRUCT, 0, 8), data.to_vec());
+        model.tail = dir.pair;
+        self.store(&mut used, &last, model)?;
+        Ok(())
+    }
+
+    /// Create or replace a regular file.
+    pub fn write_file(&mut self, path: &[u8], data: &[u8]) -> Result<(), Error> {
+        self.writable()?;
+        let (pair, name) = self.parent(path)?;
+        if data.len() as u64 > self.sb.file_max as u64 {
+            return Err(msg2err!("file too large"));
+        }
+        let (mdir, found) = self.find(pair, name)?;
+        let mut used = self.used()?;
+        let (stag, sdata) = if data.len() <= self.inline_max() {
+            (tag(TYPE_INLINESTRUCT, 0, data.len() as u32), data.to_vec())
+        } else {
+            let mut sdata = self.write_ctz(&mut used, data)?.to_le_bytes().to_vec();
+            sdata.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            (tag(TYPE_CTZSTRUCT, 0, 8), sdata)
+        };
+        let mut model = self.load(&mdir)?;
+        match found {
+            Some(id) => {
+                if matches!(model.name(id as usize), Some((x, _)) if tag_type3(x) != TYPE_REG) {
+                    return Err(msg2err!("not a file"));
+                }
+                model.set(stag | (id as u32) << 10, sdata);
+            }
+            None => model.insert(name, TYPE_REG, stag, sdata),
+        }
+        self.store(&mut used, &mdir, model)?;
+        Ok(())
+    }
+
+    /// Remove a file or an empty directory.
+    pub fn remove(&mut self, path: &[u8]) -> Result<(), Error> {
+        self.writable()?;
+        let (pair, name) = self.parent(path)?;
+        let (mdir, found) = self.find(pair, name)?;
+        let id = found.ok_or(msg2err!("file not found"))?;
+        let child = File::from_entry(self, &mdir, id)?.pair();
+        let mut used = self.used()?;
+
+        // the pairs of a directory are dropped from the list and their deltas move to the predecessor
+        let mut gdelta = GState::default();
+        let mut last = None;
+        if let Some(child) = child {
+            let mut dir = self.fetch(child)?;
+            loop {
+                if dir.count != 0 {
+                    return Err(msg2err!("directory not empty"));
+                }
+                gdelta.xor(&self.gdelta(&dir)?);
+                if !dir.split {
+                    break;
+                }
+                dir = self.fetch(dir.tail)?;
+            }
+            last = Some(dir);
+        }
+        let mut model = self.load(&mdir)?;
+        model.ids.remove(id as usize);
+        self.store(&mut used, &mdir, model)?;
+
+        let (Some(child), Some(last)) = (child, last) else {
+            return Ok(());
+        };
+        let mut pred = None;
+        self.tails(|_, dir| {
+            if pair_overlaps(dir.tail, child) {
+                pred = Some(*dir);
+            }
+            Ok(())
+        })?;
+        let pred = pred.ok_or(msg2err!("directory not in the list of pairs"))?;
+        let mut model = self.load(&pred)?;
+        model.tail = last.tail;
+        let mut delta = GState::from_bytes(&model.gdelta);
+        delta.xor(&gdelta);
+        model.gdelta = delta.to_bytes();
+        self.store(&mut used, &pred, model)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+//! Cache statistics as attributes.
+
+use crate::Stats;
+use ap_storage::attr::{attr_meta, new_attr, Attributes, Meta, Value};
+
+new_attr!(EVICTIONS, U64, "Valid pages that were replaced.");
+new_attr!(HITS, U64, "Reads served from the cache.");
+new_attr!(MISSES, U64, "Reads that went to the parent.");
+new_attr!(PAGES, U64, "Number of pages in the cache.");
+new_attr!(PAGE_SIZE, U64, "The size of a page in bytes.");
+new_attr!(WRITEBACKS, U64, "Dirty pages written to the parent.");
+
+/// A snapshot of the cache statistics.
+pub struct Attr<'a> {
+    pub(crate) stats: Stats,
+    pub(crate) pages: usize,
+    pub(crate) page_size: usize,
+    pub(crate) _marker: core::marker::PhantomData<&'a ()>,
+}
+
+impl<'a> IntoIterator for Attr<'a> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [EVICTIONS, HITS, MISSES, PAGES, PAGE_SIZE, WRITEBACKS].iter()
+    }
+}
+
+impl<'a> Attributes<'a> for Attr<'a> {
+    fn get(&self, name: &str, _buf: &mut [u8]) -> Option<Value> {
+        Some(match name {
+            EVICTIONS => self.stats.evictions.into(),
+            HITS => self.stats.hits.into(),
+            MISSES => self.stats.misses.into(),
+            PAGES => (self.pages as u64).into(),
+            PAGE_SIZE => (self.page_size as u64).into(),
+            WRITEBACKS => self.stats.writebacks.into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(name, [EVICTIONS, HITS, MISSES, PAGES, PAGE_SIZE, WRITEBACKS])
+    }
+}
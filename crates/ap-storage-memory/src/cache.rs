@@ -1,96 +1,452 @@
-use ap_storage::{Error, Offset, Read};
+use ap_storage::{msg2err, Error, Offset, Read, Write, WriteExt};
 
-/// A memory cache to speedup reads to an underlying disk.
+/// The cache options.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The size of a page in bytes. Must be a power of two.
+    pub page_size: usize,
+    /// The number of pages per set.  Zero means fully associative.
+    pub ways: usize,
+    /// The number of dirty pages that triggers a flush.  Zero means all pages.
+    ///
+    /// Only used by the write-back cache.
+    pub max_dirty: usize,
+}
+
+impl Default for Options {
+    /// 4k pages in 8-way sets.
+    fn default() -> Self {
+        Self {
+            page_size: 4096,
+            ways: 8,
+            max_dirty: 0,
+        }
+    }
+}
+
+impl Options {
+    /// Check that the options are valid.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if !self.page_size.is_power_of_two() {
+            return Err(msg2err!("page_size must be a power of two"));
+        }
+        Ok(())
+    }
+}
+
+/// The hit and miss counters of a cache.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    /// Accesses served from the cache.
+    pub hits: u64,
+    /// Accesses that went to the parent.
+    pub misses: u64,
+    /// Valid pages that were replaced.
+    pub evictions: u64,
+    /// Dirty pages written to the parent.
+    pub writebacks: u64,
+}
+
+/// A set-associative memory cache with LRU replacement to speedup accesses to an underlying disk.
 ///
-/// TODO: LRU linked list, Multiple CacheSets
+/// A page maps by its hash to a single set and only the pages in this set are searched.
+/// Writes are only supported if a writer was given.
 pub struct MemoryCacheImpl<'a> {
     /// The disk to cache the data for.
     parent: &'a dyn Read,
+    /// The same disk for writing back dirty pages.
+    writer: Option<&'a dyn Write>,
     /// The pages of user-data.
     userdata: &'a mut [u8],
     /// The metadata per page.
     meta: &'a mut [Metadata],
-    /// The last-recently used entry for a simple round-robing strategy.
-    lru: usize,
+    /// The dirty pages sorted during a flush.  Empty without a writer.
+    order: &'a mut [u32],
+    /// The size of a page.
+    page_size: usize,
+    /// The number of pages per set.
+    ways: usize,
+    /// A counter to timestamp the accesses.
+    clock: u64,
+    /// The number of dirty pages.
+    dirty: usize,
+    /// The number of dirty pages that triggers a flush.
+    max_dirty: usize,
+    /// The statistics.
+    pub(crate) stats: Stats,
 }
 
 impl<'a> MemoryCacheImpl<'a> {
-    const PAGE_SIZE: usize = 4096;
+    /// The data is used as backing store.  The options have to be validated before.
+    pub fn new(data: &'a mut [u8], parent: &'a dyn Read, writer: Option<&'a dyn Write>, options: &Options) -> Self {
+        let page_size = options.page_size;
 
-    /// The data is used as backing store.
-    pub fn new(data: &'a mut [u8], parent: &'a dyn Read) -> Self {
-        let meta_size = core::mem::size_of::<Metadata>();
-        let pages = data.len() / (Self::PAGE_SIZE + meta_size);
-
-        // we seperate the backing store here - the first is used for the pages, the remaining for the metadata
-        let split = pages * Self::PAGE_SIZE;
-        let meta = unsafe { core::slice::from_raw_parts_mut(data.as_mut_ptr().add(split) as *mut Metadata, pages) };
-        let userdata = &mut data[..split];
+        // the metadata comes first as it needs to be aligned
+        let skip = core::cmp::min(
+            data.as_ptr().align_offset(core::mem::align_of::<Metadata>()),
+            data.len(),
+        );
+        let data = &mut data[skip..];
+        let order_size = match writer {
+            Some(_) => core::mem::size_of::<u32>(),
+            None => 0,
+        };
+        let mut pages = data.len() / (page_size + core::mem::size_of::<Metadata>() + order_size);
+        let ways = match options.ways {
+            0 => pages,
+            x => core::cmp::min(x, pages),
+        };
+        if ways != 0 {
+            pages -= pages % ways;
+        }
 
-        // initialize the metadata to point to nothing
+        let (meta, rest) = data.split_at_mut(pages * core::mem::size_of::<Metadata>());
+        let (order, userdata) = rest.split_at_mut(pages * order_size);
+        // SAFETY: the bytes are aligned, large enough and exclusively borrowed.
+        let meta = unsafe { core::slice::from_raw_parts_mut(meta.as_mut_ptr() as *mut Metadata, pages) };
+        // SAFETY: the metadata keeps the alignment and the bytes are exclusively borrowed.
+        let order =
+            unsafe { core::slice::from_raw_parts_mut(order.as_mut_ptr() as *mut u32, order.len() / order_size.max(1)) };
         for entry in meta.iter_mut() {
-            entry.page_offset = !0;
+            *entry = Metadata::INVALID;
         }
 
         Self {
             parent,
-            userdata,
+            writer,
+            userdata: &mut userdata[..pages * page_size],
             meta,
-            lru: 0,
+            order,
+            page_size,
+            ways,
+            clock: 0,
+            dirty: 0,
+            max_dirty: match options.max_dirty {
+                0 => pages,
+                x => core::cmp::min(x, pages),
+            },
+            stats: Stats::default(),
+        }
+    }
+
+    /// A snapshot of the statistics as attributes.
+    pub fn attr(&self) -> crate::attr::Attr<'static> {
+        crate::attr::Attr {
+            stats: self.stats,
+            pages: self.meta.len(),
+            page_size: self.page_size,
+            _marker: core::marker::PhantomData,
+        }
+    }
+
+    /// The range of metadata entries forming the set of a page.
+    pub(crate) fn set(&self, page: Offset) -> core::ops::Range<usize> {
+        let sets = self.meta.len() / self.ways;
+        let start = (page.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % sets * self.ways;
+        start..start + self.ways
+    }
+
+    /// The writer or an error for a read-only cache.
+    fn writer(&self) -> Result<&'a dyn Write, Error> {
+        self.writer.ok_or(msg2err!("read-only cache"))
+    }
+
+    /// Write a dirty page to the parent.
+    fn write_back(&mut self, index: usize) -> Result<(), Error> {
+        let entry = &self.meta[index];
+        if !entry.dirty {
+            return Ok(());
+        }
+        let base = index * self.page_size;
+        let data = &self.userdata[base..base + entry.len as usize];
+        self.writer()?
+            .write_exact(entry.page * self.page_size as Offset, data)?;
+        self.meta[index].dirty = false;
+        self.dirty -= 1;
+        self.stats.writebacks += 1;
+        Ok(())
+    }
+
+    /// Find a page in its set or load it into the least-recently used slot.
+    ///
+    /// Read errors are ignored if more than `need` bytes are available.  The slot is
+    /// not cached in this case. A page that is fully overwritten does not need to be filled.
+    fn fetch(&mut self, page: Offset, need: usize, fill: bool) -> Result<usize, Error> {
+        self.clock += 1;
+        let mut victim = usize::MAX;
+        let mut oldest = u64::MAX;
+        for index in self.set(page) {
+            let entry = &mut self.meta[index];
+            if entry.page == page {
+                entry.stamp = self.clock;
+                self.stats.hits += 1;
+                return Ok(index);
+            }
+            if entry.stamp < oldest {
+                oldest = entry.stamp;
+                victim = index;
+            }
+        }
+        self.stats.misses += 1;
+        self.write_back(victim)?;
+        if self.meta[victim].page != Metadata::INVALID.page {
+            self.stats.evictions += 1;
+        }
+
+        // invalidate the entry as we might destroy the data in it
+        self.meta[victim] = Metadata::INVALID;
+        let ofs = page * self.page_size as Offset;
+        let our = &mut self.userdata[victim * self.page_size..(victim + 1) * self.page_size];
+
+        let (n, complete) = match fill {
+            true => load(self.parent, ofs, our, need)?,
+            false => (0, true),
+        };
+        if !complete {
+            self.meta[victim].len = n as u32;
+            return Ok(victim);
+        }
+        self.meta[victim] = Metadata {
+            page,
+            stamp: self.clock,
+            len: n as u32,
+            ..Metadata::INVALID
+        };
+        Ok(victim)
+    }
+
+    /// Copy from a cached page into the buffer.
+    fn copy(&self, index: usize, in_page: usize, buf: &mut [u8]) -> usize {
+        let available = (self.meta[index].len as usize).saturating_sub(in_page);
+        let maxn = core::cmp::min(available, buf.len());
+        let pofs = index * self.page_size + in_page;
+        buf[..maxn].copy_from_slice(&self.userdata[pofs..pofs + maxn]);
+        maxn
+    }
+
+    /// Read from a cached page or reserve a slot to load it into.
+    ///
+    /// The reserved slot is skipped by other lookups, so that it can be filled without holding the lock of a
+    /// shared cache.  No slot is reserved if the cache is empty or all slots of the set are reserved already.
+    pub fn lookup(&mut self, ofs: Offset, buf: &mut [u8]) -> Result<Lookup, Error> {
+        if self.meta.is_empty() {
+            return Ok(Lookup::Miss(None));
+        }
+        let page = ofs / self.page_size as Offset;
+        let in_page = (ofs % self.page_size as Offset) as usize;
+        self.clock += 1;
+        let mut victim = None;
+        let mut oldest = u64::MAX;
+        for index in self.set(page) {
+            let entry = &mut self.meta[index];
+            if entry.busy {
+                continue;
+            }
+            if entry.page == page {
+                entry.stamp = self.clock;
+                self.stats.hits += 1;
+                return Ok(Lookup::Hit(self.copy(index, in_page, buf)));
+            }
+            if entry.stamp < oldest {
+                oldest = entry.stamp;
+                victim = Some(index);
+            }
         }
+        self.stats.misses += 1;
+        let Some(victim) = victim else {
+            return Ok(Lookup::Miss(None));
+        };
+        self.write_back(victim)?;
+        if self.meta[victim].page != Metadata::INVALID.page {
+            self.stats.evictions += 1;
+        }
+        self.meta[victim] = Metadata {
+            busy: true,
+            ..Metadata::INVALID
+        };
+        let data = self.userdata[victim * self.page_size..(victim + 1) * self.page_size].as_mut_ptr();
+        Ok(Lookup::Miss(Some(Reserved {
+            index: victim,
+            page,
+            data,
+            len: self.page_size,
+        })))
+    }
+
+    /// Publish a reserved slot with the number of loaded bytes.
+    ///
+    /// The slot is dropped instead if loading failed or another thread has cached the page in the meantime.
+    pub fn publish(&mut self, slot: Reserved, len: Option<usize>) {
+        let duplicate = self.set(slot.page).any(|i| {
+            let entry = &self.meta[i];
+            i != slot.index && !entry.busy && entry.page == slot.page
+        });
+        self.meta[slot.index] = match len {
+            Some(len) if !duplicate => Metadata {
+                page: slot.page,
+                stamp: self.clock,
+                len: len as u32,
+                ..Metadata::INVALID
+            },
+            _ => Metadata::INVALID,
+        };
     }
 
     /// Read from the disk. This requires a mutable self.
     pub fn read_mut(&mut self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
-        let page_offset = ofs / Self::PAGE_SIZE as Offset;
-        let in_page = (ofs % Self::PAGE_SIZE as Offset) as usize;
-        let maxn = core::cmp::min(Self::PAGE_SIZE - in_page, buf.len());
-        let pages = self.meta.len();
-
-        // search in the cache
-        for i in 0..pages {
-            let index = (self.lru + i) % pages;
-            if self.meta[index].page_offset == page_offset {
-                let pofs = index * Self::PAGE_SIZE + in_page;
-                buf[..maxn].copy_from_slice(&self.userdata[pofs..pofs + maxn]);
-                return Ok(maxn);
-            }
+        if self.meta.is_empty() {
+            return self.parent.read_bytes(ofs, buf);
         }
+        let page = ofs / self.page_size as Offset;
+        let in_page = (ofs % self.page_size as Offset) as usize;
+        let index = self.fetch(page, in_page, true)?;
+        Ok(self.copy(index, in_page, buf))
+    }
 
-        // advance the LRU pointer
-        self.lru = (self.lru + 1) % pages;
+    /// Write into the cache.  Partial pages are read from the disk first.
+    pub fn write_mut(&mut self, ofs: Offset, buf: &[u8]) -> Result<usize, Error> {
+        if self.meta.is_empty() {
+            return self.writer()?.write_bytes(ofs, buf);
+        }
+        let page = ofs / self.page_size as Offset;
+        let in_page = (ofs % self.page_size as Offset) as usize;
+        let maxn = core::cmp::min(self.page_size - in_page, buf.len());
+        let index = self.fetch(page, self.page_size, maxn != self.page_size)?;
 
-        // invalidate the entry as we might destroy the data in it
-        self.meta[self.lru].page_offset = !0;
-        let our = &mut self.userdata[self.lru * Self::PAGE_SIZE..(self.lru + 1) * Self::PAGE_SIZE];
+        // a gap after the end of the disk reads as zeros
+        let base = index * self.page_size;
+        let entry = &mut self.meta[index];
+        let len = entry.len as usize;
+        if in_page > len {
+            self.userdata[base + len..base + in_page].fill(0);
+        }
+        self.userdata[base + in_page..base + in_page + maxn].copy_from_slice(&buf[..maxn]);
+        entry.len = core::cmp::max(len, in_page + maxn) as u32;
+        if !entry.dirty {
+            entry.dirty = true;
+            self.dirty += 1;
+        }
+        // the bytes are buffered, and pages that fail stay dirty for the next flush
+        if self.dirty >= self.max_dirty {
+            let _ = self.flush();
+        }
+        Ok(maxn)
+    }
 
-        // try to read a full page
-        let mut n = 0;
-        while n != Self::PAGE_SIZE {
-            let x = self.parent.read_bytes(ofs - (in_page + n) as Offset, &mut our[n..]);
-            match x {
-                Err(_) if n > in_page => break,
-                Err(e) => return Err(e),
-                Ok(0) => break,
-                Ok(c) => {
-                    n += c;
+    /// Forward the discard to the disk and drop the cached pages in the range.
+    ///
+    /// Dirty pages that are only partially discarded are zeroed instead.
+    pub fn discard_mut(&mut self, ofs: Offset, len: Offset) -> Result<Offset, Error> {
+        let n = self.writer()?.discard(ofs, len)?;
+        let end = ofs + n;
+        let page_size = self.page_size as Offset;
+        for index in 0..self.meta.len() {
+            let entry = &mut self.meta[index];
+            let start = entry.page.wrapping_mul(page_size);
+            if entry.page == Metadata::INVALID.page || start + page_size <= ofs || start >= end {
+                continue;
+            }
+            if entry.dirty && (start < ofs || start + page_size > end) {
+                let from = (core::cmp::max(start, ofs) - start) as usize;
+                let to = core::cmp::min((end - start) as usize, entry.len as usize);
+                let base = index * self.page_size;
+                if from < to {
+                    self.userdata[base + from..base + to].fill(0);
                 }
+                continue;
+            }
+            if entry.dirty {
+                self.dirty -= 1;
             }
+            *entry = Metadata::INVALID;
         }
+        Ok(n)
+    }
 
-        // only enable the cache if the whole page is filled
-        if n == Self::PAGE_SIZE {
-            self.meta[self.lru].page_offset = page_offset;
+    /// Write all dirty pages in ascending order.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let mut n = 0;
+        for index in 0..self.meta.len() {
+            if self.meta[index].dirty {
+                self.order[n] = index as u32;
+                n += 1;
+            }
         }
-        let maxn = core::cmp::min(maxn, n - in_page);
-        buf[..maxn].copy_from_slice(&our[in_page..in_page + maxn]);
-        Ok(maxn)
+        let meta = &*self.meta;
+        self.order[..n].sort_unstable_by_key(|&i| meta[i as usize].page);
+        for i in 0..n {
+            self.write_back(self.order[i] as usize)?;
+        }
+        Ok(())
     }
 }
 
+/// The result of a lookup in a shared cache.
+pub(crate) enum Lookup {
+    /// The number of bytes copied from the cache.
+    Hit(usize),
+    /// The page is not cached.  A slot might be reserved to load it into.
+    Miss(Option<Reserved>),
+}
+
+/// A slot that is reserved for loading a page.
+pub(crate) struct Reserved {
+    index: usize,
+    page: Offset,
+    data: *mut u8,
+    len: usize,
+}
+
+impl Reserved {
+    /// The data of the slot.
+    ///
+    /// # Safety
+    ///
+    /// The cache must outlive the returned slice and the slot must not be published while the slice is used.
+    pub unsafe fn data<'b>(&self) -> &'b mut [u8] {
+        // SAFETY: reserved slots are not accessed by the cache until they are published
+        unsafe { core::slice::from_raw_parts_mut(self.data, self.len) }
+    }
+}
+
+/// Load a page from the parent and return the number of bytes and whether the page can be cached.
+///
+/// Read errors are ignored if more than `need` bytes are available.  The page is incomplete in this case.
+pub(crate) fn load(parent: &dyn Read, ofs: Offset, our: &mut [u8], need: usize) -> Result<(usize, bool), Error> {
+    let mut n = 0;
+    while n != our.len() {
+        match parent.read_bytes(ofs + n as Offset, &mut our[n..]) {
+            Err(_) if n > need => return Ok((n, false)),
+            Err(e) => return Err(e),
+            Ok(0) => break,
+            Ok(c) => n += c,
+        }
+    }
+    Ok((n, true))
+}
+
 /// The metadata for a single entry in the cache.
 #[repr(C)]
 struct Metadata {
-    /// The page offset.
-    page_offset: Offset,
+    /// The page number.
+    page: Offset,
+    /// The time of the last access.
+    stamp: u64,
+    /// The valid bytes in the page.
+    len: u32,
+    /// The page was modified.
+    dirty: bool,
+    /// The page is loaded without holding the lock of a shared cache.
+    busy: bool,
+}
+
+impl Metadata {
+    /// An entry pointing to nothing.
+    const INVALID: Self = Self {
+        page: !0,
+        stamp: 0,
+        len: 0,
+        dirty: false,
+        busy: false,
+    };
 }
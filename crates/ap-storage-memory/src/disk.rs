@@ -0,0 +1,125 @@
+//! Read and write memory as a disk.
+use super::{Error, Offset, Read, Write};
+use core::cell::RefCell;
+
+/// Copy from memory at an offset.
+fn read_from(data: &[u8], ofs: Offset, buf: &mut [u8]) -> usize {
+    if ofs >= data.len() as Offset {
+        return 0;
+    }
+    let ofs = ofs as usize;
+    let n = core::cmp::min(data.len() - ofs, buf.len());
+    buf[..n].copy_from_slice(&data[ofs..ofs + n]);
+    n
+}
+
+/// Copy into memory at an offset.
+fn write_to(data: &mut [u8], ofs: Offset, buf: &[u8]) -> usize {
+    if ofs >= data.len() as Offset {
+        return 0;
+    }
+    let ofs = ofs as usize;
+    let n = core::cmp::min(data.len() - ofs, buf.len());
+    data[ofs..ofs + n].copy_from_slice(&buf[..n]);
+    n
+}
+
+/// Zero memory at an offset.
+fn discard_in(data: &mut [u8], ofs: Offset, len: Offset) -> Offset {
+    if ofs >= data.len() as Offset {
+        return 0;
+    }
+    let ofs = ofs as usize;
+    let n = core::cmp::min((data.len() - ofs) as Offset, len) as usize;
+    data[ofs..ofs + n].fill(0);
+    n as Offset
+}
+
+/// A disk in a slice of memory.
+///
+/// Writes and discards are clipped at the end of the slice, so nothing is
+/// written after it.  Discarded ranges are zeroed.
+pub struct MemDisk<'a>(RefCell<&'a mut [u8]>);
+
+impl<'a> MemDisk<'a> {
+    /// Use the memory as disk.
+    pub fn new(data: &'a mut [u8]) -> Self {
+        Self(RefCell::new(data))
+    }
+
+    /// Return the memory.
+    pub fn into_inner(self) -> &'a mut [u8] {
+        self.0.into_inner()
+    }
+}
+
+impl Read for MemDisk<'_> {
+    fn read_bytes(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        Ok(read_from(&self.0.borrow(), ofs, buf))
+    }
+}
+
+impl Write for MemDisk<'_> {
+    fn write_bytes(&self, ofs: Offset, buf: &[u8]) -> Result<usize, Error> {
+        Ok(write_to(&mut self.0.borrow_mut(), ofs, buf))
+    }
+    fn discard(&self, ofs: Offset, len: Offset) -> Result<Offset, Error> {
+        Ok(discard_in(&mut self.0.borrow_mut(), ofs, len))
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod vec {
+    use super::*;
+    use alloc::vec::Vec;
+    use ap_storage::msg2err;
+
+    /// A disk in a vector that grows on writes.
+    ///
+    /// Discards are clipped at the end of the vector.  Discarded ranges are zeroed.
+    #[derive(Default)]
+    pub struct VecDisk(RefCell<Vec<u8>>);
+
+    impl VecDisk {
+        /// Use the vector as disk.
+        pub fn new(data: Vec<u8>) -> Self {
+            Self(RefCell::new(data))
+        }
+
+        /// Return the vector.
+        pub fn into_inner(self) -> Vec<u8> {
+            self.0.into_inner()
+        }
+    }
+
+    impl Read for VecDisk {
+        fn read_bytes(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+            Ok(read_from(&self.0.borrow(), ofs, buf))
+        }
+    }
+
+    impl Write for VecDisk {
+        fn write_bytes(&self, ofs: Offset, buf: &[u8]) -> Result<usize, Error> {
+            let mut data = self.0.borrow_mut();
+            grow(&mut data, ofs, buf.len() as Offset)?;
+            Ok(write_to(&mut data, ofs, buf))
+        }
+        fn discard(&self, ofs: Offset, len: Offset) -> Result<Offset, Error> {
+            Ok(discard_in(&mut self.0.borrow_mut(), ofs, len))
+        }
+    }
+
+    /// Make the vector large enough for the range.
+    fn grow(data: &mut Vec<u8>, ofs: Offset, len: Offset) -> Result<(), Error> {
+        let end = ofs
+            .checked_add(len)
+            .and_then(|end| usize::try_from(end).ok())
+            .ok_or(msg2err!("offset too large"))?;
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        Ok(())
+    }
+}
+#[cfg(feature = "alloc")]
+pub use vec::VecDisk;
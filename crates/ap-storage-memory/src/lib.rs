@@ -1,14 +1,28 @@
 //! In-memory data structures.
 //!
-//! Provides caches and read-access to slices.
+//! Provides read, write-back and thread-safe caches, readahead, copy-on-write overlays and memory disks.
+//!
+//! ## Features
+//!
+//! - `alloc` - a growable `VecDisk`.
 #![no_std]
 
-use ap_storage::{Error, Offset, Read};
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use ap_storage::{msg2err, Error, Offset, Read, Write};
 use core::cell::RefCell;
 
+pub mod attr;
 mod cache;
+mod disk;
 mod inline;
+mod lock;
+mod overlay;
+mod readahead;
 mod slice;
+pub use cache::{Options, Stats};
+pub use disk::*;
 pub use slice::*;
 
 /// A memory cache storing its data in an external slice.
@@ -17,7 +31,25 @@ pub struct MemoryCache<'a>(RefCell<cache::MemoryCacheImpl<'a>>);
 impl<'a> MemoryCache<'a> {
     /// Create a new cache by using data as backing store.
     pub fn new(data: &'a mut [u8], parent: &'a dyn Read) -> Self {
-        Self(RefCell::new(cache::MemoryCacheImpl::new(data, parent)))
+        Self::with_options(data, parent, Options::default()).unwrap()
+    }
+
+    /// Create a new cache with a different page size or associativity.
+    pub fn with_options(data: &'a mut [u8], parent: &'a dyn Read, options: Options) -> Result<Self, Error> {
+        options.validate()?;
+        Ok(Self(RefCell::new(cache::MemoryCacheImpl::new(
+            data, parent, None, &options,
+        ))))
+    }
+
+    /// Return the hit and miss counters.
+    pub fn stats(&self) -> Stats {
+        self.0.borrow().stats
+    }
+
+    /// A snapshot of the statistics as attributes.
+    pub fn attr(&self) -> attr::Attr<'_> {
+        self.0.borrow().attr()
     }
 }
 
@@ -27,6 +59,251 @@ impl Read for MemoryCache<'_> {
     }
 }
 
+/// A write-back cache storing its data in an external slice.
+///
+/// Writes are collected in dirty pages.  They are written in ascending order
+/// on `flush()`, when too many pages are dirty and when the cache is dropped.
+/// Dirty pages are also written when they are evicted.
+///
+/// A write that triggers a flush succeeds even if the flush fails, as its
+/// bytes are already buffered.  The pages stay dirty, so the error is
+/// returned by the next `flush()`.
+pub struct WriteBackCache<'a>(RefCell<cache::MemoryCacheImpl<'a>>);
+
+impl<'a> WriteBackCache<'a> {
+    /// Create a new cache by using data as backing store.
+    pub fn new<P: Read + Write>(data: &'a mut [u8], parent: &'a P) -> Self {
+        Self::with_options(data, parent, Options::default()).unwrap()
+    }
+
+    /// Create a new cache with a different page size, associativity or dirty limit.
+    pub fn with_options<P: Read + Write>(data: &'a mut [u8], parent: &'a P, options: Options) -> Result<Self, Error> {
+        options.validate()?;
+        Ok(Self(RefCell::new(cache::MemoryCacheImpl::new(
+            data,
+            parent,
+            Some(parent),
+            &options,
+        ))))
+    }
+
+    /// Write all dirty pages to the parent.
+    pub fn flush(&self) -> Result<(), Error> {
+        self.0.borrow_mut().flush()
+    }
+
+    /// Return the hit and miss counters.
+    pub fn stats(&self) -> Stats {
+        self.0.borrow().stats
+    }
+
+    /// A snapshot of the statistics as attributes.
+    pub fn attr(&self) -> attr::Attr<'_> {
+        self.0.borrow().attr()
+    }
+}
+
+impl Read for WriteBackCache<'_> {
+    fn read_bytes(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        self.0.borrow_mut().read_mut(ofs, buf)
+    }
+}
+
+impl Write for WriteBackCache<'_> {
+    fn write_bytes(&self, ofs: Offset, buf: &[u8]) -> Result<usize, Error> {
+        self.0.borrow_mut().write_mut(ofs, buf)
+    }
+    fn discard(&self, ofs: Offset, len: Offset) -> Result<Offset, Error> {
+        self.0.borrow_mut().discard_mut(ofs, len)
+    }
+}
+
+impl Drop for WriteBackCache<'_> {
+    /// Errors are ignored. Call `flush()` to see them.
+    fn drop(&mut self) {
+        let _ = self.0.get_mut().flush();
+    }
+}
+
+/// A shard of the thread-safe cache.
+struct Shard<'a>(cache::MemoryCacheImpl<'a>);
+
+// SAFETY: shards are only created from a `Sync` parent and without a writer.
+unsafe impl Send for Shard<'_> {}
+
+/// A thread-safe memory cache storing its data in an external slice.
+///
+/// The pages are distributed over `N` independently locked shards, so
+/// threads only wait for each other when they access the same shard.
+/// Misses are read from the parent without holding the lock.
+pub struct SyncMemoryCache<'a, const N: usize> {
+    shards: [lock::SpinLock<Shard<'a>>; N],
+    parent: &'a (dyn Read + Sync),
+    page_size: usize,
+}
+
+impl<'a, const N: usize> SyncMemoryCache<'a, N> {
+    /// Create a new cache by using data as backing store.
+    pub fn new(data: &'a mut [u8], parent: &'a (dyn Read + Sync)) -> Self {
+        Self::with_options(data, parent, Options::default()).unwrap()
+    }
+
+    /// Create a new cache with a different page size or associativity per shard.
+    pub fn with_options(data: &'a mut [u8], parent: &'a (dyn Read + Sync), options: Options) -> Result<Self, Error> {
+        options.validate()?;
+        if N == 0 {
+            return Err(msg2err!("at least one shard is required"));
+        }
+        let chunk = data.len() / N;
+        let mut rest = data;
+        let shards = core::array::from_fn(|_| {
+            let (data, tail) = core::mem::take(&mut rest).split_at_mut(chunk);
+            rest = tail;
+            lock::SpinLock::new(Shard(cache::MemoryCacheImpl::new(data, parent, None, &options)))
+        });
+        Ok(Self {
+            shards,
+            parent,
+            page_size: options.page_size,
+        })
+    }
+
+    /// Return the hit and miss counters summed over all shards.
+    pub fn stats(&self) -> Stats {
+        self.attr().stats
+    }
+
+    /// A snapshot of the statistics as attributes.
+    pub fn attr(&self) -> attr::Attr<'_> {
+        let mut res = self.shards[0].lock().0.attr();
+        for shard in &self.shards[1..] {
+            let other = shard.lock().0.attr();
+            res.stats.hits += other.stats.hits;
+            res.stats.misses += other.stats.misses;
+            res.stats.evictions += other.stats.evictions;
+            res.stats.writebacks += other.stats.writebacks;
+            res.pages += other.pages;
+        }
+        res
+    }
+}
+
+impl<const N: usize> Read for SyncMemoryCache<'_, N> {
+    fn read_bytes(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        let page = ofs / self.page_size as Offset;
+        let shard = &self.shards[page as usize % N];
+        let lookup = shard.lock().0.lookup(ofs, buf)?;
+        let slot = match lookup {
+            cache::Lookup::Hit(n) => return Ok(n),
+            cache::Lookup::Miss(None) => return self.parent.read_bytes(ofs, buf),
+            cache::Lookup::Miss(Some(slot)) => slot,
+        };
+
+        // SAFETY: the slot is reserved until it is published and the cache outlives this call
+        let data = unsafe { slot.data() };
+        let in_page = (ofs % self.page_size as Offset) as usize;
+        let res = cache::load(self.parent, page * self.page_size as Offset, data, in_page);
+        let n = match &res {
+            Ok((n, _)) => {
+                let maxn = core::cmp::min(n.saturating_sub(in_page), buf.len());
+                buf[..maxn].copy_from_slice(&data[in_page..in_page + maxn]);
+                maxn
+            }
+            Err(_) => 0,
+        };
+        let len = res.as_ref().ok().and_then(|&(n, complete)| complete.then_some(n));
+        shard.lock().0.publish(slot, len);
+        res.map(|_| n)
+    }
+}
+
+/// Prefetch sequential reads into an external buffer.
+///
+/// Up to `S` sequential streams are detected.  They are prefetched with
+/// windows that double on every refill, starting with `min_window` bytes
+/// and growing to the per-stream share of the buffer.
+pub struct ReadAhead<'a, const S: usize>(RefCell<readahead::ReadAheadImpl<'a, S>>);
+
+impl<'a, const S: usize> ReadAhead<'a, S> {
+    /// Create a new readahead by using data as buffer.
+    pub fn new(data: &'a mut [u8], parent: &'a dyn Read, min_window: usize) -> Self {
+        Self(RefCell::new(readahead::ReadAheadImpl::new(data, parent, min_window)))
+    }
+}
+
+impl<const S: usize> Read for ReadAhead<'_, S> {
+    fn read_bytes(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        self.0.borrow_mut().read_mut(ofs, buf)
+    }
+}
+
+/// A copy-on-write overlay over a read-only parent.
+///
+/// Modified blocks are stored in a separate disk, for instance a sparse
+/// file or a [`MemDisk`].  The index has one entry per block the store can
+/// hold.  The delta can be listed, exported, discarded or committed to
+/// a writable parent.
+pub struct Overlay<'a, S: ?Sized>(RefCell<overlay::OverlayImpl<'a, S>>);
+
+impl<'a, S: Read + Write + ?Sized> Overlay<'a, S> {
+    /// Create an empty overlay.  The block size must not be zero.
+    pub fn new(parent: &'a dyn Read, store: &'a S, index: &'a mut [Offset], block_size: usize) -> Result<Self, Error> {
+        if block_size == 0 {
+            return Err(msg2err!("block_size must not be zero"));
+        }
+        Ok(Self(RefCell::new(overlay::OverlayImpl::new(
+            parent, store, index, block_size,
+        ))))
+    }
+
+    /// Call a function for the number of every modified block.
+    pub fn blocks(&self, mut f: impl FnMut(Offset)) {
+        self.0.borrow().blocks(&mut f)
+    }
+
+    /// The number of modified blocks.
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    /// Are there any modified blocks?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the modified blocks to their offsets in the target.
+    pub fn export(&self, target: &dyn Write) -> Result<(), Error> {
+        self.0.borrow().export(target)
+    }
+
+    /// Apply the delta to the parent and start with an empty one.
+    pub fn commit(&self, parent: &dyn Write) -> Result<(), Error> {
+        self.export(parent)?;
+        self.reset();
+        Ok(())
+    }
+
+    /// Throw away the delta.
+    pub fn reset(&self) {
+        self.0.borrow_mut().reset()
+    }
+}
+
+impl<S: Read + Write + ?Sized> Read for Overlay<'_, S> {
+    fn read_bytes(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        self.0.borrow().read_mut(ofs, buf)
+    }
+}
+
+impl<S: Read + Write + ?Sized> Write for Overlay<'_, S> {
+    fn write_bytes(&self, ofs: Offset, buf: &[u8]) -> Result<usize, Error> {
+        self.0.borrow_mut().write_mut(ofs, buf)
+    }
+    fn discard(&self, ofs: Offset, len: Offset) -> Result<Offset, Error> {
+        self.0.borrow_mut().discard_mut(ofs, len)
+    }
+}
+
 /// A memory cache storing its data inside the object.
 pub struct InlineCache<'a, const N: usize>(RefCell<inline::InlineCacheImpl<'a, N>>);
 
@@ -42,3 +319,438 @@ impl<const N: usize> Read for InlineCache<'_, N> {
         self.0.borrow_mut().read_mut(ofs, buf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{attr::Attributes, WriteExt};
+    use core::{
+        cell::Cell,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use std::{
+        time::{Duration, Instant},
+        vec::Vec,
+    };
+
+    /// A disk that records the reads and writes.
+    struct Recorder<'a> {
+        disk: MemDisk<'a>,
+        reads: RefCell<Vec<(Offset, usize)>>,
+        writes: RefCell<Vec<(Offset, usize)>>,
+    }
+
+    impl<'a> Recorder<'a> {
+        fn new(data: &'a mut [u8]) -> Self {
+            Self {
+                disk: MemDisk::new(data),
+                reads: RefCell::new(Vec::new()),
+                writes: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Read for Recorder<'_> {
+        fn read_bytes(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+            self.reads.borrow_mut().push((ofs, buf.len()));
+            self.disk.read_bytes(ofs, buf)
+        }
+    }
+
+    impl Write for Recorder<'_> {
+        fn write_bytes(&self, ofs: Offset, buf: &[u8]) -> Result<usize, Error> {
+            self.writes.borrow_mut().push((ofs, buf.len()));
+            self.disk.write_bytes(ofs, buf)
+        }
+        fn discard(&self, ofs: Offset, len: Offset) -> Result<Offset, Error> {
+            self.disk.discard(ofs, len)
+        }
+    }
+
+    /// A disk that fails all requests while `fail` is set.
+    struct Flaky<'a> {
+        disk: MemDisk<'a>,
+        fail: Cell<bool>,
+    }
+
+    impl Read for Flaky<'_> {
+        fn read_bytes(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+            match self.fail.get() {
+                true => Err(msg2err!("read failed")),
+                false => self.disk.read_bytes(ofs, buf),
+            }
+        }
+    }
+
+    impl Write for Flaky<'_> {
+        fn write_bytes(&self, ofs: Offset, buf: &[u8]) -> Result<usize, Error> {
+            match self.fail.get() {
+                true => Err(msg2err!("write failed")),
+                false => self.disk.write_bytes(ofs, buf),
+            }
+        }
+        fn discard(&self, ofs: Offset, len: Offset) -> Result<Offset, Error> {
+            match self.fail.get() {
+                true => Err(msg2err!("discard failed")),
+                false => self.disk.discard(ofs, len),
+            }
+        }
+    }
+
+    /// A disk that blocks reads of the second page until it is released.
+    struct Blocking {
+        data: [u8; 2048],
+        entered: AtomicBool,
+        release: AtomicBool,
+        timeout: AtomicBool,
+    }
+
+    impl Read for Blocking {
+        fn read_bytes(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+            if (512..1024).contains(&ofs) {
+                self.entered.store(true, Ordering::SeqCst);
+                let start = Instant::now();
+                while !self.release.load(Ordering::SeqCst) {
+                    if start.elapsed() > Duration::from_secs(5) {
+                        self.timeout.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                    std::thread::yield_now();
+                }
+            }
+            ReadSlice(&self.data).read_bytes(ofs, buf)
+        }
+    }
+
+    #[test]
+    fn sync_cache() {
+        let disk = Blocking {
+            data: core::array::from_fn(|i| (i / 512) as u8 + 1),
+            entered: AtomicBool::new(false),
+            release: AtomicBool::new(false),
+            timeout: AtomicBool::new(false),
+        };
+        let options = Options {
+            page_size: 512,
+            ways: 0,
+            max_dirty: 0,
+        };
+        let mut data = [0; 8 * 1024];
+        let cache = SyncMemoryCache::<1>::with_options(&mut data, &disk, options).unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(cache.read_bytes(10, &mut buf).unwrap(), 16);
+        assert_eq!(buf, [1; 16]);
+
+        // a miss does not block hits on the same shard
+        std::thread::scope(|s| {
+            let reader = s.spawn(|| {
+                let mut buf = [0; 32];
+                assert_eq!(cache.read_bytes(1000, &mut buf).unwrap(), 24);
+                assert_eq!(buf[..24], [2; 24]);
+            });
+            while !disk.entered.load(Ordering::SeqCst) {
+                std::thread::yield_now();
+            }
+            assert_eq!(cache.read_bytes(500, &mut buf).unwrap(), 12);
+            assert_eq!(buf[..12], [1; 12]);
+            disk.release.store(true, Ordering::SeqCst);
+            reader.join().unwrap();
+        });
+        assert!(!disk.timeout.load(Ordering::SeqCst));
+
+        // the loaded page was published
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!(cache.read_bytes(1020, &mut buf).unwrap(), 4);
+        assert_eq!(buf[..4], [2; 4]);
+        assert_eq!(cache.stats().hits, 2);
+    }
+
+    #[test]
+    fn write_back() {
+        let mut backing = [7; 4096];
+        let disk = Recorder::new(&mut backing);
+        let options = Options {
+            page_size: 512,
+            ways: 0,
+            max_dirty: 0,
+        };
+        let mut data = [0; 8 * 1024];
+        let cache = WriteBackCache::with_options(&mut data, &disk, options).unwrap();
+
+        // partial pages are read before they are modified
+        for (ofs, val) in [
+            (5 * 512, 5),
+            (1024 + 100, 2),
+            (512, 1),
+            (1024 + 300, 3),
+            (3 * 512 + 500, 4),
+        ] {
+            assert_eq!(cache.write_bytes(ofs, &[val; 12]).unwrap(), 12);
+        }
+        assert_eq!(cache.write_bytes(1024 + 500, &[8; 100]).unwrap(), 12);
+        assert!(disk.writes.borrow().is_empty());
+        let mut buf = [0; 512];
+        assert_eq!(cache.read_bytes(1024, &mut buf).unwrap(), 512);
+        assert_eq!((buf[99], buf[100], buf[111], buf[112]), (7, 2, 2, 7));
+        assert_eq!((buf[300], buf[499], buf[500], buf[511]), (3, 7, 8, 8));
+
+        // every page is written once in ascending order
+        cache.flush().unwrap();
+        let pages: Vec<_> = [1, 2, 3, 5].iter().map(|&p| (p * 512, 512)).collect();
+        assert_eq!(*disk.writes.borrow(), pages);
+        assert_eq!(cache.stats().writebacks, 4);
+        cache.flush().unwrap();
+        assert_eq!(disk.writes.borrow().len(), 4);
+        drop(cache);
+        let backing = disk.disk.into_inner();
+        assert_eq!(backing[1024..1024 + 100], [7; 100]);
+        assert_eq!(backing[1024 + 100..1024 + 112], [2; 12]);
+        assert_eq!(backing[5 * 512..5 * 512 + 13], [5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 7]);
+        assert_eq!(backing[4 * 512..5 * 512], [7; 512]);
+    }
+
+    #[test]
+    fn dirty_limit() {
+        let mut backing = [0; 4096];
+        let disk = Recorder::new(&mut backing);
+        let options = Options {
+            page_size: 512,
+            ways: 0,
+            max_dirty: 3,
+        };
+        let mut data = [0; 8 * 1024];
+        let cache = WriteBackCache::with_options(&mut data, &disk, options.clone()).unwrap();
+        for page in [4, 2] {
+            cache.write_bytes(page * 512, &[1; 512]).unwrap();
+        }
+        assert!(disk.writes.borrow().is_empty());
+        cache.write_bytes(512, &[1; 512]).unwrap();
+        assert_eq!(*disk.writes.borrow(), [(512, 512), (1024, 512), (2048, 512)]);
+
+        // the writes are flushed on drop
+        cache.write_bytes(0, &[1; 512]).unwrap();
+        assert_eq!(disk.writes.borrow().len(), 3);
+        drop(cache);
+        assert_eq!(disk.writes.borrow()[3], (0, 512));
+
+        // a failed flush keeps the pages dirty for the next one
+        let mut backing = [0; 4096];
+        let disk = Flaky {
+            disk: MemDisk::new(&mut backing),
+            fail: Cell::new(true),
+        };
+        let cache = WriteBackCache::with_options(&mut data, &disk, options).unwrap();
+        for page in [4, 2, 1] {
+            assert_eq!(cache.write_bytes(page * 512, &[2; 512]).unwrap(), 512);
+        }
+        assert!(cache.flush().is_err());
+        disk.fail.set(false);
+        cache.flush().unwrap();
+        assert_eq!(cache.stats().writebacks, 3);
+        let mut buf = [0; 4];
+        assert_eq!(disk.read_bytes(2048, &mut buf).unwrap(), 4);
+        assert_eq!(buf, [2; 4]);
+    }
+
+    #[test]
+    fn read_ahead() {
+        let mut backing: Vec<u8> = (0..32 * 1024).map(|x| (x % 251) as u8).collect();
+        let expected = backing.clone();
+        let disk = Recorder::new(&mut backing);
+        let mut data = [0; 2 * 1024];
+        let cache = ReadAhead::<2>::new(&mut data, &disk, 128);
+        let mut buf = [0; 32];
+
+        // the second sequential read starts a window that doubles up to half of the buffer
+        for ofs in (0..2976).step_by(32) {
+            assert_eq!(cache.read_bytes(ofs, &mut buf).unwrap(), 32);
+            assert_eq!(buf[..], expected[ofs as usize..ofs as usize + 32]);
+        }
+        let windows = [(0, 32), (32, 128), (160, 256), (416, 512), (928, 1024), (1952, 1024)];
+        assert_eq!(*disk.reads.borrow(), windows);
+
+        // a second stream uses the other half
+        disk.reads.borrow_mut().clear();
+        for ofs in [10000, 10032, 10064] {
+            assert_eq!(cache.read_bytes(ofs, &mut buf).unwrap(), 32);
+            assert_eq!(buf[..], expected[ofs as usize..ofs as usize + 32]);
+        }
+        assert_eq!(*disk.reads.borrow(), [(10000, 32), (10032, 128)]);
+
+        // a third stream replaces the least-recently used one
+        disk.reads.borrow_mut().clear();
+        assert_eq!(cache.read_bytes(20000, &mut buf).unwrap(), 32);
+        assert_eq!(cache.read_bytes(10096, &mut buf).unwrap(), 32);
+        assert_eq!(cache.read_bytes(2976, &mut buf).unwrap(), 32);
+        assert_eq!(buf[..], expected[2976..2976 + 32]);
+        assert_eq!(*disk.reads.borrow(), [(20000, 32), (2976, 32)]);
+
+        // large reads bypass the buffer
+        disk.reads.borrow_mut().clear();
+        let mut large = [0; 4096];
+        assert_eq!(cache.read_bytes(3008, &mut large).unwrap(), 4096);
+        assert_eq!(large[..], expected[3008..3008 + 4096]);
+        assert_eq!(*disk.reads.borrow(), [(3008, 4096)]);
+    }
+
+    #[test]
+    fn sets() {
+        let mut backing: Vec<u8> = (0..64 * 512).map(|x| (x / 512) as u8).collect();
+        let disk = Recorder::new(&mut backing);
+        let options = Options {
+            page_size: 512,
+            ways: 2,
+            max_dirty: 0,
+        };
+        let mut invalid = [0; 1024];
+        let odd = Options {
+            page_size: 1000,
+            ..options.clone()
+        };
+        assert!(MemoryCache::with_options(&mut invalid, &disk, odd).is_err());
+
+        // eight pages in four sets spread over by the page hash
+        let mut data = [0; 8 * (512 + 32) + 8];
+        let mut cache = cache::MemoryCacheImpl::new(&mut data, &disk, None, &options);
+        let get = |cache: &cache::MemoryCacheImpl, name| cache.attr().get(name, &mut []).and_then(|x| x.as_u64());
+        assert_eq!(get(&cache, attr::PAGES), Some(8));
+        let sets: Vec<_> = (0..64).map(|page| cache.set(page)).collect();
+        assert!(sets
+            .iter()
+            .all(|set| set.len() == 2 && set.start % 2 == 0 && set.end <= 8));
+        for start in [0, 2, 4, 6] {
+            assert!(sets.iter().any(|set| set.start == start));
+        }
+        assert_eq!(cache.set(5), sets[5]);
+
+        // the least-recently used page of a full set is replaced
+        let same: Vec<Offset> = (0..64).filter(|&page| sets[page as usize] == sets[0]).take(3).collect();
+        assert_eq!(same.len(), 3);
+        let mut buf = [0; 4];
+        for page in [same[0], same[1], same[0], same[2], same[0], same[1]] {
+            assert_eq!(cache.read_mut(page * 512, &mut buf).unwrap(), 4);
+            assert_eq!(buf, [page as u8; 4]);
+        }
+        let misses = [same[0], same[1], same[2], same[1]].map(|page| (page * 512, 512));
+        assert_eq!(*disk.reads.borrow(), misses);
+        assert_eq!(get(&cache, attr::HITS), Some(2));
+        assert_eq!(get(&cache, attr::MISSES), Some(4));
+        assert_eq!(get(&cache, attr::EVICTIONS), Some(2));
+        assert_eq!(get(&cache, attr::WRITEBACKS), Some(0));
+    }
+
+    #[test]
+    fn overlay() {
+        let parent: [u8; 1000] = core::array::from_fn(|i| (i / 256) as u8 + 1);
+        let mut store = [0; 2 * 256];
+        let store = MemDisk::new(&mut store);
+        let mut index = [0; 2];
+        let parent = ReadSlice(&parent);
+        let overlay = Overlay::new(&parent, &store, &mut index, 256).unwrap();
+        let mut buf = [0; 256];
+
+        // partial writes copy the rest of the block from the parent
+        assert_eq!(overlay.write_bytes(300, &[9; 400]).unwrap(), 212);
+        assert_eq!(overlay.write_bytes(10, &[8; 4]).unwrap(), 4);
+        assert_eq!(overlay.len(), 2);
+        assert_eq!(overlay.read_bytes(256, &mut buf).unwrap(), 256);
+        assert_eq!((buf[43], buf[44], buf[255]), (2, 9, 9));
+        assert_eq!(overlay.read_bytes(0, &mut buf).unwrap(), 256);
+        assert_eq!((buf[9], buf[10], buf[13], buf[14]), (1, 8, 8, 1));
+        assert_eq!(overlay.read_bytes(512, &mut buf).unwrap(), 256);
+        assert_eq!(buf, [3; 256]);
+        assert!(overlay.write_bytes(600, &[1]).is_err());
+        let mut blocks = Vec::new();
+        overlay.blocks(|x| blocks.push(x));
+        blocks.sort();
+        assert_eq!(blocks, [0, 1]);
+
+        // the export only touches the modified blocks
+        let mut target = [0; 1024];
+        overlay.export(&MemDisk::new(&mut target)).unwrap();
+        assert_eq!((target[9], target[10], target[299], target[300]), (1, 8, 2, 9));
+        assert_eq!(target[512..], [0; 512]);
+
+        // reused slots do not leak data from before the reset
+        overlay.reset();
+        assert!(overlay.is_empty());
+        assert_eq!(overlay.read_bytes(300, &mut buf).unwrap(), 212);
+        assert_eq!(buf[..212], [2; 212]);
+        assert_eq!(overlay.discard(900, 10).unwrap(), 10);
+        assert_eq!(overlay.write_bytes(700, &[5; 4]).unwrap(), 4);
+        assert_eq!(overlay.read_bytes(768, &mut buf).unwrap(), 256);
+        assert_eq!(buf[..132], [4; 132]);
+        assert_eq!(buf[132..142], [0; 10]);
+        assert_eq!(buf[142..232], [4; 90]);
+        assert_eq!(buf[232..], [0; 24]);
+        assert_eq!(overlay.read_bytes(512, &mut buf).unwrap(), 256);
+        assert_eq!((buf[187], buf[188], buf[191], buf[192]), (3, 5, 5, 3));
+    }
+
+    #[test]
+    fn overlay_errors() {
+        let mut data = [1; 1024];
+        let parent = Flaky {
+            disk: MemDisk::new(&mut data),
+            fail: Cell::new(false),
+        };
+        let mut store = [0; 2 * 256];
+        let store = Flaky {
+            disk: MemDisk::new(&mut store),
+            fail: Cell::new(true),
+        };
+        let mut index = [0; 2];
+        let overlay = Overlay::new(&parent, &store, &mut index, 256).unwrap();
+        let mut buf = [0; 4];
+
+        // failed copies, writes and discards do not map the block
+        assert!(overlay.write_bytes(10, &[2; 4]).is_err());
+        assert!(overlay.write_bytes(256, &[2; 256]).is_err());
+        assert!(overlay.discard(512, 256).is_err());
+        store.fail.set(false);
+        parent.fail.set(true);
+        assert!(overlay.write_bytes(10, &[2; 4]).is_err());
+        assert!(overlay.is_empty());
+        parent.fail.set(false);
+        for ofs in [8, 256, 512] {
+            assert_eq!(overlay.read_bytes(ofs, &mut buf).unwrap(), 4);
+            assert_eq!(buf, [1; 4]);
+        }
+
+        // the slots are still free
+        assert_eq!(overlay.write_bytes(256, &[2; 256]).unwrap(), 256);
+        assert_eq!(overlay.discard(512, 256).unwrap(), 256);
+        assert_eq!(overlay.read_bytes(508, &mut buf).unwrap(), 4);
+        assert_eq!(buf, [2; 4]);
+        assert_eq!(overlay.read_bytes(512, &mut buf).unwrap(), 4);
+        assert_eq!(buf, [0; 4]);
+    }
+
+    #[test]
+    fn mem_disk() {
+        let mut data = [1; 8];
+        let disk = MemDisk::new(&mut data);
+
+        // writes and discards are clipped at the end
+        assert_eq!(disk.write_bytes(6, b"abc").unwrap(), 2);
+        assert_eq!(disk.write_bytes(8, b"abc").unwrap(), 0);
+        assert!((&disk as &dyn Write).write_exact(6, b"abc").is_err());
+        assert_eq!(disk.discard(2, 10).unwrap(), 6);
+        assert_eq!(disk.discard(8, 10).unwrap(), 0);
+        assert_eq!(disk.into_inner(), [1, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn vec_disk() {
+        let disk = VecDisk::new(std::vec![1; 8]);
+
+        // writes grow the vector, discards do not
+        assert_eq!(disk.write_bytes(10, b"abc").unwrap(), 3);
+        assert_eq!(disk.discard(11, 100).unwrap(), 2);
+        assert_eq!(disk.discard(20, 100).unwrap(), 0);
+        assert_eq!(disk.into_inner(), [1, 1, 1, 1, 1, 1, 1, 1, 0, 0, b'a', 0, 0]);
+    }
+}
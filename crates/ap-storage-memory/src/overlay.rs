@@ -0,0 +1,181 @@
+//! Copy-on-write overlay over a read-only disk.
+
+use ap_storage::{msg2err, Error, Offset, Read, Write, WriteExt};
+
+/// Record the modified blocks of a disk in a separate store.
+///
+/// The index maps block numbers to slots in the store with open
+/// addressing.  A block at slot `i` lives at `i * block_size` in the
+/// store, so a sparse file or memory of `index.len() * block_size`
+/// bytes can hold the whole delta.  The store has to read discarded
+/// ranges as zeros.
+pub struct OverlayImpl<'a, S: ?Sized> {
+    parent: &'a dyn Read,
+    store: &'a S,
+    /// The block number plus one of every slot.  Zero marks an empty slot.
+    index: &'a mut [Offset],
+    block_size: usize,
+    /// The number of used slots.
+    used: usize,
+}
+
+impl<'a, S: Read + Write + ?Sized> OverlayImpl<'a, S> {
+    pub fn new(parent: &'a dyn Read, store: &'a S, index: &'a mut [Offset], block_size: usize) -> Self {
+        index.fill(0);
+        Self {
+            parent,
+            store,
+            index,
+            block_size,
+            used: 0,
+        }
+    }
+
+    /// The store as trait objects.
+    fn store(&self) -> (&dyn Read, &dyn Write) {
+        (&self.store, &self.store)
+    }
+
+    /// The first slot to probe for a block.
+    fn start(&self, block: Offset) -> usize {
+        (block.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % self.index.len()
+    }
+
+    /// Find the slot of a modified block.
+    fn find(&self, block: Offset) -> Option<usize> {
+        if self.index.is_empty() {
+            return None;
+        }
+        let len = self.index.len();
+        let start = self.start(block);
+        for i in 0..len {
+            let slot = (start + i) % len;
+            match self.index[slot] {
+                0 => return None,
+                x if x == block + 1 => return Some(slot),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Fill a free slot for a block.  The slot is recorded in the index only if it was filled.
+    fn insert(&mut self, block: Offset, fill: impl FnOnce(&Self, Offset) -> Result<(), Error>) -> Result<usize, Error> {
+        if self.used == self.index.len() {
+            return Err(msg2err!("overlay full"));
+        }
+        let len = self.index.len();
+        let start = self.start(block);
+        let slot = (0..len)
+            .map(|i| (start + i) % len)
+            .find(|&slot| self.index[slot] == 0)
+            .ok_or(msg2err!("overlay full"))?;
+        fill(self, slot as Offset * self.block_size as Offset)?;
+        self.index[slot] = block + 1;
+        self.used += 1;
+        Ok(slot)
+    }
+
+    /// Copy a block from the parent into the store.  Missing bytes at the end of the parent are zero.
+    fn copy_up(&self, block: Offset, pos: Offset) -> Result<(), Error> {
+        let (_, store) = self.store();
+        let bs = self.block_size as Offset;
+        store.discard_all(pos, bs)?;
+        let mut buf = [0u8; 512];
+        let mut done = 0;
+        while done < bs {
+            let n = core::cmp::min(buf.len() as Offset, bs - done) as usize;
+            let n = match self.parent.read_bytes(block * bs + done, &mut buf[..n])? {
+                0 => break,
+                n => n,
+            };
+            store.write_exact(pos + done, &buf[..n])?;
+            done += n as Offset;
+        }
+        Ok(())
+    }
+
+    /// Read from the delta or the parent. Reads do not cross block boundaries.
+    pub fn read_mut(&self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        let bs = self.block_size as Offset;
+        let block = ofs / bs;
+        let in_block = ofs % bs;
+        let n = core::cmp::min((bs - in_block) as usize, buf.len());
+        match self.find(block) {
+            Some(slot) => self.store().0.read_bytes(slot as Offset * bs + in_block, &mut buf[..n]),
+            None => self.parent.read_bytes(ofs, &mut buf[..n]),
+        }
+    }
+
+    /// Write into the delta.  Partially written blocks are copied from the parent first.
+    pub fn write_mut(&mut self, ofs: Offset, buf: &[u8]) -> Result<usize, Error> {
+        let bs = self.block_size as Offset;
+        let block = ofs / bs;
+        let in_block = ofs % bs;
+        let n = core::cmp::min((bs - in_block) as usize, buf.len());
+        let slot = match self.find(block) {
+            Some(slot) => slot,
+            None if n as Offset == bs => {
+                self.insert(block, |o, pos| o.store().1.write_exact(pos, &buf[..n]))?;
+                return Ok(n);
+            }
+            None => self.insert(block, |o, pos| o.copy_up(block, pos))?,
+        };
+        self.store().1.write_bytes(slot as Offset * bs + in_block, &buf[..n])
+    }
+
+    /// Discard in the delta.  The parent is not touched.
+    pub fn discard_mut(&mut self, ofs: Offset, len: Offset) -> Result<Offset, Error> {
+        let bs = self.block_size as Offset;
+        let block = ofs / bs;
+        let in_block = ofs % bs;
+        let n = core::cmp::min(bs - in_block, len);
+        let slot = match self.find(block) {
+            Some(slot) => slot,
+            None if n == bs => {
+                self.insert(block, |o, pos| o.store().1.discard_all(pos, bs))?;
+                return Ok(n);
+            }
+            None => self.insert(block, |o, pos| o.copy_up(block, pos))?,
+        };
+        self.store().1.discard(slot as Offset * bs + in_block, n)
+    }
+
+    /// Call a function for every modified block.
+    pub fn blocks(&self, f: &mut dyn FnMut(Offset)) {
+        for key in self.index.iter().filter(|&&x| x != 0) {
+            f(key - 1)
+        }
+    }
+
+    /// The number of modified blocks.
+    pub fn len(&self) -> usize {
+        self.used
+    }
+
+    /// Write the modified blocks to their offsets in the target.
+    pub fn export(&self, target: &dyn Write) -> Result<(), Error> {
+        let (store, _) = self.store();
+        let bs = self.block_size as Offset;
+        let mut buf = [0u8; 512];
+        for (slot, key) in self.index.iter().enumerate().filter(|(_, &x)| x != 0) {
+            let mut done = 0;
+            while done < bs {
+                let n = core::cmp::min(buf.len() as Offset, bs - done) as usize;
+                let n = match store.read_bytes(slot as Offset * bs + done, &mut buf[..n])? {
+                    0 => break,
+                    n => n,
+                };
+                target.write_exact((key - 1) * bs + done, &buf[..n])?;
+                done += n as Offset;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget all modifications.
+    pub fn reset(&mut self) {
+        self.index.fill(0);
+        self.used = 0;
+    }
+}
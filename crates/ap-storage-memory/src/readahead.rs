@@ -0,0 +1,124 @@
+//! Sequential readahead.
+
+use ap_storage::{Error, Offset, Read};
+
+/// A sequential stream of reads.
+#[derive(Debug, Clone, Copy)]
+struct Stream {
+    /// The disk offset of the buffered data.
+    start: Offset,
+    /// The number of valid bytes in the buffer.
+    valid: usize,
+    /// The offset the next sequential read is expected at.
+    next: Offset,
+    /// The current size of the prefetch window.  Zero if the stream is not sequential.
+    window: usize,
+    /// The time of the last access.
+    stamp: u64,
+}
+
+impl Stream {
+    const EMPTY: Self = Self {
+        start: 0,
+        valid: 0,
+        next: !0,
+        window: 0,
+        stamp: 0,
+    };
+}
+
+/// Detect sequential reads and prefetch them with growing windows.
+///
+/// The buffer is split between `S` streams.  A stream starts to prefetch
+/// on the second sequential read and doubles its window on every refill
+/// until its part of the buffer is exhausted.  Random reads and reads
+/// larger than the window go directly to the parent.
+pub struct ReadAheadImpl<'a, const S: usize> {
+    parent: &'a dyn Read,
+    buffer: &'a mut [u8],
+    streams: [Stream; S],
+    /// The first window of a sequential stream.
+    min_window: usize,
+    /// A counter to timestamp the accesses.
+    clock: u64,
+}
+
+impl<'a, const S: usize> ReadAheadImpl<'a, S> {
+    pub fn new(buffer: &'a mut [u8], parent: &'a dyn Read, min_window: usize) -> Self {
+        Self {
+            parent,
+            buffer,
+            streams: [Stream::EMPTY; S],
+            min_window: core::cmp::max(min_window, 1),
+            clock: 0,
+        }
+    }
+
+    /// The maximum window of a stream.
+    fn max_window(&self) -> usize {
+        self.buffer.len().checked_div(S).unwrap_or(0)
+    }
+
+    /// Read from the disk. This requires a mutable self.
+    pub fn read_mut(&mut self, ofs: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        self.clock += 1;
+        let max_window = self.max_window();
+
+        // served from a buffer?
+        for i in 0..S {
+            let stream = &mut self.streams[i];
+            if stream.start <= ofs && ofs < stream.start + stream.valid as Offset {
+                let skip = (ofs - stream.start) as usize;
+                let n = core::cmp::min(stream.valid - skip, buf.len());
+                let pos = i * max_window + skip;
+                buf[..n].copy_from_slice(&self.buffer[pos..pos + n]);
+                stream.next = ofs + n as Offset;
+                stream.stamp = self.clock;
+                return Ok(n);
+            }
+        }
+        if S == 0 {
+            return self.parent.read_bytes(ofs, buf);
+        }
+
+        // continue a stream or replace the least-recently used one
+        let victim = (0..S)
+            .find(|&i| self.streams[i].next == ofs)
+            .or_else(|| (0..S).min_by_key(|&i| self.streams[i].stamp))
+            .unwrap_or(0);
+
+        let stream = &mut self.streams[victim];
+        if stream.next != ofs {
+            // a new stream starts without prefetching
+            *stream = Stream::EMPTY;
+        } else {
+            stream.window = core::cmp::min(core::cmp::max(stream.window * 2, self.min_window), max_window);
+        }
+        stream.stamp = self.clock;
+        stream.valid = 0;
+
+        // large or random reads bypass the buffer
+        if buf.len() >= stream.window {
+            let n = self.parent.read_bytes(ofs, buf)?;
+            stream.next = ofs + n as Offset;
+            return Ok(n);
+        }
+
+        // fill the window with as few reads as possible
+        let window = &mut self.buffer[victim * max_window..victim * max_window + stream.window];
+        let mut valid = 0;
+        while valid < window.len() {
+            match self.parent.read_bytes(ofs + valid as Offset, &mut window[valid..]) {
+                Err(e) if valid == 0 => return Err(e),
+                Err(_) | Ok(0) => break,
+                Ok(n) => valid += n,
+            }
+        }
+        stream.start = ofs;
+        stream.valid = valid;
+        let n = core::cmp::min(valid, buf.len());
+        buf[..n].copy_from_slice(&window[..n]);
+        stream.next = ofs + n as Offset;
+        Ok(n)
+    }
+}
@@ -0,0 +1,230 @@
+//! Serve ap-storage disks via the network block device protocol.
+//!
+//! Implements the fixed newstyle handshake with the EXPORT_NAME, INFO,
+//! GO, LIST and STRUCTURED_REPLY options.  The READ, WRITE, TRIM and
+//! FLUSH commands are supported, larger requests are split into chunks
+//! of the buffer size.
+
+#![no_std]
+
+use ap_storage::{msg2err, Error, Offset, Read, Write};
+
+pub mod proto;
+mod server;
+mod unix;
+pub use unix::{UnixListener, UnixStream};
+
+/// A bidirectional byte stream.
+pub trait Connection {
+    /// Receive exactly the buffer.
+    fn recv(&mut self, buf: &mut [u8]) -> Result<(), Error>;
+    /// Send the whole buffer.
+    fn send(&mut self, buf: &[u8]) -> Result<(), Error>;
+}
+
+/// A disk to serve.
+pub struct Export<'a> {
+    /// The name announced to clients.  The empty name matches as well.
+    pub name: &'a str,
+    /// The size in bytes.
+    pub size: Offset,
+    /// The data to read.
+    pub disk: &'a dyn Read,
+    /// Where writes and trims go.  The export is read-only without it.
+    pub write: Option<&'a dyn Write>,
+    /// Called for FLUSH commands and writes with FUA.
+    pub flush: Option<&'a dyn Fn() -> Result<(), Error>>,
+}
+
+/// Serve the export to a single client until it disconnects.
+///
+/// The buffer limits the size of the chunks sent and received.
+pub fn serve<C: Connection>(export: &Export, conn: &mut C, buf: &mut [u8]) -> Result<(), Error> {
+    if buf.len() < 512 {
+        return Err(msg2err!("buffer too small"));
+    }
+    let mut session = server::Session {
+        export,
+        conn,
+        buf,
+        structured: false,
+    };
+    if !session.handshake()? {
+        return Ok(());
+    }
+    session.transmission()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage_memory::MemDisk;
+    use core::cell::Cell;
+    use proto::*;
+    use std::{vec, vec::Vec};
+
+    /// A test client speaking the protocol.
+    struct Client(UnixStream);
+
+    impl Client {
+        fn u16(&mut self) -> u16 {
+            let mut buf = [0; 2];
+            self.0.recv(&mut buf).unwrap();
+            u16::from_be_bytes(buf)
+        }
+        fn u32(&mut self) -> u32 {
+            let mut buf = [0; 4];
+            self.0.recv(&mut buf).unwrap();
+            u32::from_be_bytes(buf)
+        }
+        fn u64(&mut self) -> u64 {
+            let mut buf = [0; 8];
+            self.0.recv(&mut buf).unwrap();
+            u64::from_be_bytes(buf)
+        }
+        fn bytes(&mut self, n: usize) -> Vec<u8> {
+            let mut buf = vec![0; n];
+            self.0.recv(&mut buf).unwrap();
+            buf
+        }
+
+        /// Send an option and return the type and data of the reply.
+        fn option(&mut self, option: u32, data: &[u8]) -> (u32, Vec<u8>) {
+            let mut req = Vec::new();
+            req.extend_from_slice(&IHAVEOPT.to_be_bytes());
+            req.extend_from_slice(&option.to_be_bytes());
+            req.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            req.extend_from_slice(data);
+            self.0.send(&req).unwrap();
+            self.reply(option)
+        }
+
+        fn reply(&mut self, option: u32) -> (u32, Vec<u8>) {
+            assert_eq!((self.u64(), self.u32()), (OPTION_REPLY_MAGIC, option));
+            let typ = self.u32();
+            let len = self.u32() as usize;
+            (typ, self.bytes(len))
+        }
+
+        fn command(&mut self, flags: u16, typ: u16, offset: u64, len: u32, data: &[u8]) {
+            let mut req = Vec::new();
+            req.extend_from_slice(&REQUEST_MAGIC.to_be_bytes());
+            req.extend_from_slice(&flags.to_be_bytes());
+            req.extend_from_slice(&typ.to_be_bytes());
+            req.extend_from_slice(&(typ as u64 + 100).to_be_bytes());
+            req.extend_from_slice(&offset.to_be_bytes());
+            req.extend_from_slice(&len.to_be_bytes());
+            req.extend_from_slice(data);
+            self.0.send(&req).unwrap();
+        }
+
+        /// Receive a structured chunk and return the flags, type and payload.
+        fn chunk(&mut self, typ: u16) -> (u16, u16, Vec<u8>) {
+            assert_eq!(self.u32(), STRUCTURED_REPLY_MAGIC);
+            let (flags, reply) = (self.u16(), self.u16());
+            assert_eq!(self.u64(), typ as u64 + 100);
+            let len = self.u32() as usize;
+            (flags, reply, self.bytes(len))
+        }
+
+        /// Read with structured replies.
+        fn read(&mut self, offset: u64, len: u32) -> Result<Vec<u8>, u32> {
+            self.command(0, CMD_READ, offset, len, &[]);
+            let mut res = vec![0; len as usize];
+            loop {
+                let (flags, typ, data) = self.chunk(CMD_READ);
+                match typ {
+                    REPLY_TYPE_OFFSET_DATA => {
+                        let pos = u64::from_be_bytes(data[..8].try_into().unwrap()) - offset;
+                        res[pos as usize..pos as usize + data.len() - 8].copy_from_slice(&data[8..]);
+                    }
+                    REPLY_TYPE_ERROR | REPLY_TYPE_ERROR_OFFSET => {
+                        return Err(u32::from_be_bytes(data[..4].try_into().unwrap()))
+                    }
+                    _ => {}
+                }
+                if flags & REPLY_FLAG_DONE != 0 {
+                    return Ok(res);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn protocol() {
+        let mut data = vec![0u8; 1 << 16];
+        let disk = MemDisk::new(&mut data);
+        let flushes = Cell::new(0);
+        let flush = || {
+            flushes.set(flushes.get() + 1);
+            Ok(())
+        };
+        let export = Export {
+            name: "disk",
+            size: 1 << 16,
+            disk: &disk,
+            write: Some(&disk),
+            flush: Some(&flush),
+        };
+        let (server, client) = UnixStream::pair().unwrap();
+
+        let client = std::thread::spawn(move || {
+            let mut c = Client(client);
+            assert_eq!((c.u64(), c.u64()), (NBDMAGIC, IHAVEOPT));
+            assert_eq!(c.u16(), FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES);
+            c.0.send(&[0, 0, 0, 3]).unwrap();
+
+            let (typ, data) = c.option(OPT_LIST, &[]);
+            assert_eq!((typ, &data[..]), (REP_SERVER, &b"\0\0\0\x04disk"[..]));
+            assert_eq!(c.reply(OPT_LIST).0, REP_ACK);
+            assert_eq!(c.option(99, &[]).0, REP_ERR_UNSUP);
+            assert_eq!(c.option(OPT_STRUCTURED_REPLY, &[]).0, REP_ACK);
+            assert_eq!(c.option(OPT_GO, b"\0\0\0\x01x\0\0").0, REP_ERR_UNKNOWN);
+            assert_eq!(c.option(OPT_GO, b"\0\0\0\x01").0, REP_ERR_INVALID);
+
+            let (typ, info) = c.option(OPT_GO, b"\0\0\0\x04disk\0\x01\0\x03");
+            assert_eq!((typ, &info[..10]), (REP_INFO, &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0][..]));
+            assert_eq!(
+                u16::from_be_bytes([info[10], info[11]]) & FLAG_SEND_TRIM,
+                FLAG_SEND_TRIM
+            );
+            let (typ, sizes) = c.reply(OPT_GO);
+            assert_eq!((typ, &sizes[..2]), (REP_INFO, &[0, 3][..]));
+            assert_eq!(c.reply(OPT_GO).0, REP_ACK);
+
+            // writes larger than the buffer
+            let pattern: Vec<u8> = (0..20000u32).map(|x| (x % 251) as u8).collect();
+            c.command(CMD_FLAG_FUA, CMD_WRITE, 1000, pattern.len() as u32, &pattern);
+            assert_eq!(c.chunk(CMD_WRITE).1, REPLY_TYPE_NONE);
+            assert_eq!(c.read(1000, 20000).unwrap(), pattern);
+
+            c.command(0, CMD_TRIM, 2000, 1000, &[]);
+            assert_eq!(c.chunk(CMD_TRIM), (REPLY_FLAG_DONE, REPLY_TYPE_NONE, vec![]));
+            let data = c.read(1500, 2000).unwrap();
+            assert_eq!(
+                (&data[..500], &data[500..1500]),
+                (&pattern[500..1000], &[0u8; 1000][..])
+            );
+
+            c.command(0, CMD_FLUSH, 0, 0, &[]);
+            assert_eq!(c.chunk(CMD_FLUSH).1, REPLY_TYPE_NONE);
+
+            // out of range
+            assert_eq!(c.read(65000, 1000), Err(EINVAL));
+            c.command(0, CMD_WRITE, 65000, 1000, &[1; 1000]);
+            let (flags, typ, data) = c.chunk(CMD_WRITE);
+            assert_eq!(
+                (flags, typ, &data[..4]),
+                (REPLY_FLAG_DONE, REPLY_TYPE_ERROR, &[0, 0, 0, 22][..])
+            );
+            c.command(0, CMD_DISC, 0, 0, &[]);
+        });
+
+        let mut conn = server;
+        let mut buf = [0u8; 4096];
+        serve(&export, &mut conn, &mut buf).unwrap();
+        client.join().unwrap();
+        assert_eq!(flushes.get(), 2);
+    }
+}
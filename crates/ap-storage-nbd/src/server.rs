@@ -0,0 +1,330 @@
+//! The handshake and transmission phases of a connection.
+
+use crate::{proto::*, Connection, Export};
+use ap_storage::{msg2err, Error, Offset, WriteExt};
+
+/// Big-endian fields in a fixed buffer.
+struct Packet {
+    buf: [u8; 32],
+    len: usize,
+}
+
+impl Packet {
+    fn new() -> Self {
+        Self { buf: [0; 32], len: 0 }
+    }
+    fn put(mut self, bytes: &[u8]) -> Self {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        self
+    }
+    fn u16(self, v: u16) -> Self {
+        self.put(&v.to_be_bytes())
+    }
+    fn u32(self, v: u32) -> Self {
+        self.put(&v.to_be_bytes())
+    }
+    fn u64(self, v: u64) -> Self {
+        self.put(&v.to_be_bytes())
+    }
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([buf[pos], buf[pos + 1]])
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], pos: usize) -> u64 {
+    u64::from_be_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+/// The state of a single connection.
+pub(crate) struct Session<'a, 'b, C> {
+    pub(crate) export: &'b Export<'a>,
+    pub(crate) conn: &'b mut C,
+    pub(crate) buf: &'b mut [u8],
+    pub(crate) structured: bool,
+}
+
+impl<C: Connection> Session<'_, '_, C> {
+    fn send(&mut self, packet: Packet) -> Result<(), Error> {
+        self.conn.send(packet.as_bytes())
+    }
+
+    /// Receive and drop some bytes.
+    fn drain(&mut self, mut len: usize) -> Result<(), Error> {
+        while len != 0 {
+            let n = core::cmp::min(len, self.buf.len());
+            self.conn.recv(&mut self.buf[..n])?;
+            len -= n;
+        }
+        Ok(())
+    }
+
+    /// The flags sent to the client.
+    fn flags(&self) -> u16 {
+        let mut flags = FLAG_HAS_FLAGS | FLAG_SEND_FLUSH | FLAG_SEND_FUA;
+        if self.export.write.is_some() {
+            flags |= FLAG_SEND_TRIM;
+        } else {
+            flags |= FLAG_READ_ONLY;
+        }
+        flags
+    }
+
+    /// The empty name selects the default export.
+    fn matches(&self, name: &[u8]) -> bool {
+        name.is_empty() || name == self.export.name.as_bytes()
+    }
+
+    fn reply(&mut self, option: u32, typ: u32, data: &[u8]) -> Result<(), Error> {
+        let header = Packet::new()
+            .u64(OPTION_REPLY_MAGIC)
+            .u32(option)
+            .u32(typ)
+            .u32(data.len() as u32);
+        self.send(header)?;
+        self.conn.send(data)
+    }
+
+    /// Negotiate the options.  Returns false if the client aborted.
+    pub(crate) fn handshake(&mut self) -> Result<bool, Error> {
+        self.send(
+            Packet::new()
+                .u64(NBDMAGIC)
+                .u64(IHAVEOPT)
+                .u16(FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES),
+        )?;
+        let mut head = [0u8; 16];
+        self.conn.recv(&mut head[..4])?;
+        let no_zeroes = u32_at(&head, 0) & FLAG_C_NO_ZEROES != 0;
+        loop {
+            self.conn.recv(&mut head)?;
+            if u64_at(&head, 0) != IHAVEOPT {
+                return Err(msg2err!("invalid option"));
+            }
+            let (option, len) = (u32_at(&head, 8), u32_at(&head, 12) as usize);
+            if len > self.buf.len() {
+                if option == OPT_EXPORT_NAME {
+                    return Err(msg2err!("export name too long"));
+                }
+                self.drain(len)?;
+                self.reply(option, REP_ERR_TOO_BIG, &[])?;
+                continue;
+            }
+            self.conn.recv(&mut self.buf[..len])?;
+            match option {
+                OPT_EXPORT_NAME => {
+                    if !self.matches(&self.buf[..len]) {
+                        return Err(msg2err!("unknown export"));
+                    }
+                    self.send(Packet::new().u64(self.export.size).u16(self.flags()))?;
+                    if !no_zeroes {
+                        self.conn.send(&[0; 124])?;
+                    }
+                    return Ok(true);
+                }
+                OPT_ABORT => {
+                    // the client may not wait for the answer
+                    let _ = self.reply(option, REP_ACK, &[]);
+                    return Ok(false);
+                }
+                OPT_LIST | OPT_STRUCTURED_REPLY if len != 0 => self.reply(option, REP_ERR_INVALID, &[])?,
+                OPT_LIST => {
+                    let name = self.export.name.as_bytes();
+                    let header = Packet::new()
+                        .u64(OPTION_REPLY_MAGIC)
+                        .u32(option)
+                        .u32(REP_SERVER)
+                        .u32(name.len() as u32 + 4)
+                        .u32(name.len() as u32);
+                    self.send(header)?;
+                    self.conn.send(name)?;
+                    self.reply(option, REP_ACK, &[])?;
+                }
+                OPT_STRUCTURED_REPLY => {
+                    self.structured = true;
+                    self.reply(option, REP_ACK, &[])?;
+                }
+                OPT_INFO | OPT_GO => {
+                    if self.info(option, len)? && option == OPT_GO {
+                        return Ok(true);
+                    }
+                }
+                _ => self.reply(option, REP_ERR_UNSUP, &[])?,
+            }
+        }
+    }
+
+    /// Answer INFO and GO requests.  Returns true on success.
+    fn info(&mut self, option: u32, len: usize) -> Result<bool, Error> {
+        let data = &self.buf[..len];
+        let nlen = if len >= 4 { u32_at(data, 0) as usize } else { len };
+        let valid = len >= 6 + nlen && len == 6 + nlen + 2 * u16_at(data, 4 + nlen) as usize;
+        let known = valid && self.matches(&data[4..4 + nlen]);
+        let block_size = valid && data[6 + nlen..].chunks(2).any(|x| u16_at(x, 0) == INFO_BLOCK_SIZE);
+        if !known {
+            let error = if valid { REP_ERR_UNKNOWN } else { REP_ERR_INVALID };
+            self.reply(option, error, &[])?;
+            return Ok(false);
+        }
+        let export = Packet::new().u16(INFO_EXPORT).u64(self.export.size).u16(self.flags());
+        self.reply(option, REP_INFO, export.as_bytes())?;
+        if block_size {
+            let sizes = Packet::new().u16(INFO_BLOCK_SIZE).u32(1).u32(4096).u32(MAX_PAYLOAD);
+            self.reply(option, REP_INFO, sizes.as_bytes())?;
+        }
+        self.reply(option, REP_ACK, &[])?;
+        Ok(true)
+    }
+
+    /// Process commands until the client disconnects.
+    pub(crate) fn transmission(&mut self) -> Result<(), Error> {
+        let mut head = [0u8; 28];
+        loop {
+            self.conn.recv(&mut head)?;
+            if u32_at(&head, 0) != REQUEST_MAGIC {
+                return Err(msg2err!("invalid request"));
+            }
+            let (flags, typ, cookie) = (u16_at(&head, 4), u16_at(&head, 6), u64_at(&head, 8));
+            let (offset, len) = (u64_at(&head, 16), u32_at(&head, 24));
+            let res = match typ {
+                CMD_DISC => return Ok(()),
+                CMD_READ => {
+                    self.read(cookie, offset, len)?;
+                    continue;
+                }
+                CMD_WRITE => self.write(offset, len, flags)?,
+                CMD_FLUSH => self.flush(),
+                CMD_TRIM => self.trim(offset, len, flags),
+                _ => Err(EINVAL),
+            };
+            self.done(cookie, res)?;
+        }
+    }
+
+    /// Check that a request is inside the export.
+    fn check(&self, offset: Offset, len: u32) -> Result<(), u32> {
+        match offset.checked_add(len as Offset) {
+            Some(end) if end <= self.export.size => Ok(()),
+            _ => Err(EINVAL),
+        }
+    }
+
+    fn flush(&self) -> Result<(), u32> {
+        match self.export.flush {
+            Some(flush) => flush().map_err(|_| EIO),
+            None => Ok(()),
+        }
+    }
+
+    /// Receive the payload and write it.  The outer error closes the connection.
+    fn write(&mut self, offset: Offset, len: u32, flags: u16) -> Result<Result<(), u32>, Error> {
+        let mut res = self.check(offset, len).and(self.export.write.ok_or(EPERM));
+        let mut done = 0;
+        while done < len as usize {
+            let n = core::cmp::min(len as usize - done, self.buf.len());
+            self.conn.recv(&mut self.buf[..n])?;
+            if let Ok(write) = res {
+                if write.write_exact(offset + done as Offset, &self.buf[..n]).is_err() {
+                    res = Err(EIO);
+                }
+            }
+            done += n;
+        }
+        Ok(match res {
+            Ok(_) if flags & CMD_FLAG_FUA != 0 => self.flush(),
+            res => res.map(|_| ()),
+        })
+    }
+
+    fn trim(&self, offset: Offset, len: u32, flags: u16) -> Result<(), u32> {
+        self.check(offset, len)?;
+        let write = self.export.write.ok_or(EPERM)?;
+        write.discard_all(offset, len as Offset).map_err(|_| EIO)?;
+        if flags & CMD_FLAG_FUA != 0 {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Send the final reply of a command.
+    fn done(&mut self, cookie: u64, res: Result<(), u32>) -> Result<(), Error> {
+        if !self.structured {
+            let error = res.err().unwrap_or(0);
+            return self.send(Packet::new().u32(SIMPLE_REPLY_MAGIC).u32(error).u64(cookie));
+        }
+        let chunk = Packet::new().u32(STRUCTURED_REPLY_MAGIC).u16(REPLY_FLAG_DONE);
+        match res {
+            Ok(()) => self.send(chunk.u16(REPLY_TYPE_NONE).u64(cookie).u32(0)),
+            Err(e) => self.send(chunk.u16(REPLY_TYPE_ERROR).u64(cookie).u32(6).u32(e).u16(0)),
+        }
+    }
+
+    /// Fill the buffer from the disk.  Bytes after the end of the disk are zero.
+    fn fill(&mut self, offset: Offset, n: usize) -> Result<(), Error> {
+        let disk = self.export.disk;
+        let mut done = 0;
+        while done < n {
+            match disk.read_bytes(offset + done as Offset, &mut self.buf[done..n])? {
+                0 => {
+                    self.buf[done..n].fill(0);
+                    break;
+                }
+                x => done += x,
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the data in chunks of the buffer size.
+    fn read(&mut self, cookie: u64, offset: Offset, len: u32) -> Result<(), Error> {
+        if let Err(e) = self
+            .check(offset, len)
+            .and(if len > MAX_PAYLOAD { Err(EINVAL) } else { Ok(()) })
+        {
+            return self.done(cookie, Err(e));
+        }
+        if len == 0 {
+            return self.done(cookie, Ok(()));
+        }
+        let mut done = 0;
+        while done < len as usize {
+            let n = core::cmp::min(len as usize - done, self.buf.len());
+            let pos = offset + done as Offset;
+            let res = self.fill(pos, n);
+            let last = done + n == len as usize;
+            if self.structured {
+                let chunk = Packet::new().u32(STRUCTURED_REPLY_MAGIC).u16(if last || res.is_err() {
+                    REPLY_FLAG_DONE
+                } else {
+                    0
+                });
+                if res.is_err() {
+                    let error = chunk.u16(REPLY_TYPE_ERROR_OFFSET).u64(cookie).u32(14).u32(EIO).u16(0);
+                    self.send(error)?;
+                    return self.send(Packet::new().u64(pos));
+                }
+                self.send(chunk.u16(REPLY_TYPE_OFFSET_DATA).u64(cookie).u32(n as u32 + 8))?;
+                self.send(Packet::new().u64(pos))?;
+            } else if done == 0 {
+                if res.is_err() {
+                    return self.done(cookie, Err(EIO));
+                }
+                self.done(cookie, Ok(()))?;
+            } else {
+                // the simple reply was already sent
+                res?;
+            }
+            self.conn.send(&self.buf[..n])?;
+            done += n;
+        }
+        Ok(())
+    }
+}
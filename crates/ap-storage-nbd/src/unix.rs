@@ -0,0 +1,157 @@
+//! Unix domain sockets.
+
+use crate::Connection;
+use ap_storage::{msg2err, Error};
+use ap_storage_linux::check_error;
+
+/// A connected stream socket.
+pub struct UnixStream(i32);
+
+/// A listening socket bound to a path.
+pub struct UnixListener(i32);
+
+/// Fill the address of a socket path.
+fn address(path: &str) -> Result<libc::sockaddr_un, Error> {
+    let mut addr: libc::sockaddr_un = unsafe { core::mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let path = path.as_bytes();
+    if path.len() >= addr.sun_path.len() || path.contains(&0) {
+        return Err(msg2err!("invalid socket path"));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+        *dst = *src as libc::c_char;
+    }
+    Ok(addr)
+}
+
+/// Create a new socket.
+fn socket() -> Result<i32, Error> {
+    unsafe {
+        check_error(libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) as isize)
+            .map(|fd| fd as i32)
+            .map_err(|e| msg2err!("socket").context(e))
+    }
+}
+
+impl UnixStream {
+    /// A pair of connected sockets.
+    pub fn pair() -> Result<(Self, Self), Error> {
+        let mut fds = [0; 2];
+        unsafe {
+            check_error(libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            ) as isize)
+            .map_err(|e| msg2err!("socketpair").context(e))?;
+        }
+        Ok((Self(fds[0]), Self(fds[1])))
+    }
+
+    /// Connect to a listening socket.
+    pub fn connect(path: &str) -> Result<Self, Error> {
+        let addr = address(path)?;
+        let res = Self(socket()?);
+        unsafe {
+            check_error(libc::connect(
+                res.0,
+                &addr as *const _ as *const libc::sockaddr,
+                core::mem::size_of_val(&addr) as libc::socklen_t,
+            ) as isize)
+            .map_err(|e| msg2err!("connect").context(e))?;
+        }
+        Ok(res)
+    }
+}
+
+impl Connection for UnixStream {
+    fn recv(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let mut done = 0;
+        while done < buf.len() {
+            let n = unsafe {
+                check_error(libc::recv(
+                    self.0,
+                    buf[done..].as_mut_ptr() as *mut libc::c_void,
+                    buf.len() - done,
+                    0,
+                ))
+            };
+            match n {
+                Ok(0) => return Err(msg2err!("connection closed")),
+                Ok(n) => done += n as usize,
+                Err(libc::EINTR) => {}
+                Err(e) => return Err(msg2err!("recv").context(e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, buf: &[u8]) -> Result<(), Error> {
+        let mut done = 0;
+        while done < buf.len() {
+            let n = unsafe {
+                check_error(libc::send(
+                    self.0,
+                    buf[done..].as_ptr() as *const libc::c_void,
+                    buf.len() - done,
+                    libc::MSG_NOSIGNAL,
+                ))
+            };
+            match n {
+                Ok(n) => done += n as usize,
+                Err(libc::EINTR) => {}
+                Err(e) => return Err(msg2err!("send").context(e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Close the socket when the object drops.
+impl Drop for UnixStream {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+impl UnixListener {
+    /// Listen on a new socket at the path.
+    pub fn bind(path: &str) -> Result<Self, Error> {
+        let addr = address(path)?;
+        let res = Self(socket()?);
+        unsafe {
+            check_error(libc::bind(
+                res.0,
+                &addr as *const _ as *const libc::sockaddr,
+                core::mem::size_of_val(&addr) as libc::socklen_t,
+            ) as isize)
+            .map_err(|e| msg2err!("bind").context(e))?;
+            check_error(libc::listen(res.0, 16) as isize).map_err(|e| msg2err!("listen").context(e))?;
+        }
+        Ok(res)
+    }
+
+    /// Wait for the next client.
+    pub fn accept(&self) -> Result<UnixStream, Error> {
+        loop {
+            let fd = unsafe {
+                check_error(
+                    libc::accept4(self.0, core::ptr::null_mut(), core::ptr::null_mut(), libc::SOCK_CLOEXEC) as isize,
+                )
+            };
+            match fd {
+                Ok(fd) => return Ok(UnixStream(fd as i32)),
+                Err(libc::EINTR) => {}
+                Err(e) => return Err(msg2err!("accept").context(e)),
+            }
+        }
+    }
+}
+
+/// Close the socket when the object drops.
+impl Drop for UnixListener {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
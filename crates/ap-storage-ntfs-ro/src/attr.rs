@@ -0,0 +1,81 @@
+//! File attributes for NTFS.
+
+use super::file::File;
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::Read;
+use ap_util_slice_writer::*;
+
+new_attr!(FLAGS, U64, "DOS and NT file attributes.");
+new_attr!(NLINKS, U64, "Number of hard-links to this file.");
+new_attr!(STREAMS, Str, "Names of the alternate data streams, each after a colon.");
+
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
+}
+
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            FLAGS,
+            NLINKS,
+            STREAMS,
+            attr::ATIME,
+            attr::BTIME,
+            attr::CTIME,
+            attr::FTYPE,
+            attr::ID,
+            attr::MTIME,
+            attr::SIZE,
+        ]
+        .iter()
+    }
+}
+
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let info = &self.file.info;
+        Some(match name {
+            FLAGS => (info.flags as u64).into(),
+            NLINKS => (self.file.nlink as u64).into(),
+            STREAMS => {
+                let mut value = SliceWriter(buf, 0);
+                self.file
+                    .streams(&mut |x| write!(value, ":{}", core::str::from_utf8(x).unwrap_or_default()).is_ok())
+                    .ok()?;
+                Value::Str(value.1)
+            }
+            attr::ATIME => Value::Time(info.atime),
+            attr::BTIME => Value::Time(info.btime),
+            attr::CTIME => Value::Time(info.ctime),
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            attr::ID => self.file.id().into(),
+            attr::MTIME => Value::Time(info.mtime),
+            attr::SIZE => self.file.size().into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                FLAGS,
+                NLINKS,
+                STREAMS,
+                attr::ATIME,
+                attr::BTIME,
+                attr::CTIME,
+                attr::FTYPE,
+                attr::ID,
+                attr::MTIME,
+                attr::SIZE,
+            ]
+        )
+    }
+}
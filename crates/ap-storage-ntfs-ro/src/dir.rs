@@ -0,0 +1,297 @@
+//! Directories as B+ trees of file names.
+
+use super::{
+    file::File,
+    le16, le32, le64,
+    record::{fixup, utf8, Name, RECORD_MASK},
+    INDEX_ROOT, MAX_RECORD,
+};
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    file::FileType,
+    msg2err, Error, Read,
+};
+use core::{cell::Cell, cmp::Ordering};
+
+/// The name of the index of file names.
+pub(crate) const I30: Name = Name::ascii(b"$I30");
+
+const INDX: &[u8; 4] = b"INDX";
+
+/// The position of the index header in the root value and in an index block.
+const ROOT_HEADER: usize = 16;
+const BLOCK_HEADER: usize = 24;
+
+/// The entry flags.
+const ENTRY_SUBNODE: u32 = 1 << 0;
+const ENTRY_LAST: u32 = 1 << 1;
+
+/// The size of a file name key before the name.
+const KEY_HEADER: usize = 66;
+
+/// The namespace of short names that have a long name as well.
+const NAMESPACE_DOS: u8 = 2;
+
+/// The file attribute of directories in a file name.
+const FILE_NAME_DIRECTORY: u32 = 0x1000_0000;
+
+/// The height of the tree.
+const MAX_DEPTH: usize = 32;
+
+/// An entry of an index node.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    pos: usize,
+    len: usize,
+    flags: u32,
+    reference: u64,
+}
+
+impl Entry {
+    fn parse(buf: &[u8], pos: usize, end: usize) -> Result<Self, Error> {
+        if pos + 16 > end {
+            return Err(msg2err!("truncated index entry"));
+        }
+        let (len, keylen, flags) = (
+            le16(buf, pos + 8) as usize,
+            le16(buf, pos + 10) as usize,
+            le32(buf, pos + 12),
+        );
+        let subnode = if flags & ENTRY_SUBNODE != 0 { 8 } else { 0 };
+        if len % 8 != 0 || pos + len > end || 16 + keylen + subnode > len {
+            return Err(msg2err!("invalid index entry"));
+        }
+        if flags & ENTRY_LAST == 0 && (keylen < KEY_HEADER || KEY_HEADER + 2 * buf[pos + 16 + 64] as usize > keylen) {
+            return Err(msg2err!("invalid file name key"));
+        }
+        Ok(Self {
+            pos,
+            len,
+            flags,
+            reference: le64(buf, pos),
+        })
+    }
+
+    fn key<'b>(&self, buf: &'b [u8]) -> &'b [u8] {
+        &buf[self.pos + 16..]
+    }
+
+    /// The little-endian UTF-16 name.
+    fn name<'b>(&self, buf: &'b [u8]) -> &'b [u8] {
+        let key = self.key(buf);
+        &key[KEY_HEADER..KEY_HEADER + 2 * key[64] as usize]
+    }
+
+    fn subnode(&self, buf: &[u8]) -> Option<u64> {
+        (self.flags & ENTRY_SUBNODE != 0).then(|| le64(buf, self.pos + self.len - 8))
+    }
+}
+
+/// The range of the entries behind an index header.
+fn entries(buf: &[u8], header: usize, limit: usize) -> Result<(usize, usize), Error> {
+    let (start, end) = (
+        header + le32(buf, header) as usize,
+        header + le32(buf, header + 4) as usize,
+    );
+    if start < header + 16 || start > end || end > limit {
+        return Err(msg2err!("invalid index header"));
+    }
+    Ok((start, end))
+}
+
+/// Load a node of the tree into the buffer and return the range of its entries.
+///
+/// The root is the first node and the index blocks follow.  Unused blocks have no entries.
+fn node<D: Read + ?Sized>(
+    file: &File<D>,
+    node: u64,
+    buf: &mut [u8; MAX_RECORD],
+) -> Result<Option<(usize, usize)>, Error> {
+    let fs = file.fs;
+    let size = fs.boot.index_size as usize;
+    if node == 0 {
+        let mut record = [0; MAX_RECORD];
+        let attr = fs
+            .find(file.record, INDEX_ROOT, &I30, 0, &mut record)?
+            .filter(|x| !x.non_resident)
+            .ok_or(msg2err!("no index root"))?;
+        let value = attr.value(&record);
+        if value.len() < ROOT_HEADER + 16 || le32(value, 8) as usize != size {
+            return Err(msg2err!("invalid index root"));
+        }
+        buf[..value.len()].copy_from_slice(value);
+        return entries(buf, ROOT_HEADER, value.len()).map(Some);
+    }
+    let (Some(index), pos) = (&file.index, (node - 1) * size as u64) else {
+        return Ok(None);
+    };
+    if pos >= index.size {
+        return Ok(None);
+    }
+    let bit = node - 1;
+    let mut byte = [0];
+    let used = match &file.bitmap {
+        Some(x) => fs.read_stream(x, &Cell::new(None), bit / 8, &mut byte)? == 1 && byte[0] >> (bit % 8) & 1 != 0,
+        None => false,
+    };
+    if !used {
+        return Ok(Some((0, 0)));
+    }
+    fs.read_stream_exact(index, &file.index_cache, pos, &mut buf[..size])?;
+    fixup(&mut buf[..size], INDX)?;
+    if le64(buf, 16) != pos / fs.index_vcn_size() {
+        return Err(msg2err!("index block at the wrong place"));
+    }
+    entries(buf, BLOCK_HEADER, size).map(Some)
+}
+
+/// The node that is referenced by a subnode VCN.
+fn subnode<D: Read + ?Sized>(file: &File<D>, vcn: u64) -> Result<u64, Error> {
+    let (pos, size) = (
+        vcn.saturating_mul(file.fs.index_vcn_size()),
+        file.fs.boot.index_size as u64,
+    );
+    if pos % size != 0 {
+        return Err(msg2err!("unaligned index block"));
+    }
+    Ok(pos / size + 1)
+}
+
+/// Compare a name with the one of an entry, ignoring the case first.
+fn collate<D: Read + ?Sized>(file: &File<D>, name: &Name, other: &[u8]) -> Result<Ordering, Error> {
+    let (name, n) = (name.as_slice(), other.len() / 2);
+    let unit = |i: usize| le16(other, i * 2);
+    for (i, x) in name.iter().enumerate().take(n) {
+        match file.fs.upcase(*x)?.cmp(&file.fs.upcase(unit(i))?) {
+            Ordering::Equal => {}
+            x => return Ok(x),
+        }
+    }
+    Ok(name
+        .len()
+        .cmp(&n)
+        .then_with(|| name.iter().copied().cmp((0..n).map(unit))))
+}
+
+/// Search a name in the tree and return the file reference.
+pub(crate) fn lookup<D: Read + ?Sized>(file: &File<D>, name: &[u8]) -> Result<Option<u64>, Error> {
+    let Some(key) = Name::from_utf8(name) else {
+        return Ok(None);
+    };
+    let mut buf = [0; MAX_RECORD];
+    let mut current = 0;
+    for _ in 0..MAX_DEPTH {
+        let (mut pos, end) = node(file, current, &mut buf)?.ok_or(msg2err!("index block out of range"))?;
+        let entry = loop {
+            let entry = Entry::parse(&buf, pos, end)?;
+            if entry.flags & ENTRY_LAST != 0 {
+                break entry;
+            }
+            match collate(file, &key, entry.name(&buf))? {
+                Ordering::Less => break entry,
+                Ordering::Equal => return Ok(Some(entry.reference)),
+                Ordering::Greater => pos += entry.len,
+            }
+        };
+        match entry.subnode(&buf) {
+            Some(vcn) => current = subnode(file, vcn)?,
+            None => return Ok(None),
+        }
+    }
+    Err(msg2err!("index too deep"))
+}
+
+/// The file reference of the entry at a directory offset.
+pub(crate) fn child<D: Read + ?Sized>(file: &File<D>, offset: u64) -> Result<u64, Error> {
+    let mut buf = [0; MAX_RECORD];
+    let (mut pos, end) = node(file, offset >> 32, &mut buf)?.ok_or(msg2err!("invalid directory offset"))?;
+    while pos < end {
+        let entry = Entry::parse(&buf, pos, end)?;
+        if entry.flags & ENTRY_LAST != 0 {
+            break;
+        }
+        if pos as u64 == offset & 0xffff_ffff {
+            return Ok(entry.reference);
+        }
+        pos += entry.len;
+    }
+    Err(msg2err!("invalid directory offset"))
+}
+
+/// Iterate over the entries of the nodes in the order of the index blocks.
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
+    /// The node in the buffer with the range of its entries.
+    loaded: Option<(u64, usize, usize)>,
+    /// The node and the position of the next entry, which is zero at the start of a node.
+    node: u64,
+    pos: usize,
+    done: bool,
+    buf: [u8; MAX_RECORD],
+}
+
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Self {
+        Self {
+            file,
+            loaded: None,
+            node: 0,
+            pos: 0,
+            done: false,
+            buf: [0; MAX_RECORD],
+        }
+    }
+}
+
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        while !self.done {
+            let (start, end) = match self.loaded {
+                Some((node, start, end)) if node == self.node => (start, end),
+                _ => match node(self.file, self.node, &mut self.buf)? {
+                    None => {
+                        self.done = true;
+                        break;
+                    }
+                    Some((start, end)) => {
+                        self.loaded = Some((self.node, start, end));
+                        (start, end)
+                    }
+                },
+            };
+            let pos = core::cmp::max(self.pos, start);
+            let entry = match pos < end {
+                true => Entry::parse(&self.buf, pos, end)?,
+                false => Entry {
+                    pos,
+                    len: 0,
+                    flags: ENTRY_LAST,
+                    reference: 0,
+                },
+            };
+            if entry.flags & ENTRY_LAST != 0 {
+                (self.node, self.pos) = (self.node + 1, 0);
+                continue;
+            }
+            self.pos = pos + entry.len;
+            let key = entry.key(&self.buf);
+            if key[65] == NAMESPACE_DOS {
+                continue;
+            }
+            let nlen = utf8(entry.name(&self.buf), name);
+            let id = entry.reference & RECORD_MASK;
+            let typ = match le32(key, 56) & FILE_NAME_DIRECTORY {
+                _ if id == self.file.record => FileType::Parent,
+                0 => FileType::File,
+                _ => FileType::Directory,
+            };
+            return Ok(Some(DirEntry {
+                offset: self.node << 32 | pos as u64,
+                id,
+                nlen,
+                typ,
+            }));
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,234 @@
+//! Files on NTFS.
+
+use super::{
+    attr::Attr,
+    dir,
+    dir::{Dir, I30},
+    le16, le32, le64,
+    record::{utf8, Attributes, Name, RECORD_MASK},
+    Ntfs, Run, Stream, BITMAP, DATA, INDEX_ALLOCATION, MAX_RECORD, RECORD_DIRECTORY, RECORD_IN_USE,
+    STANDARD_INFORMATION,
+};
+use ap_storage::{file::FileType, msg2err, Error, Offset, Read};
+use core::cell::Cell;
+
+/// The difference between the Windows epoch in 1601 and the Unix one in 100ns.
+const EPOCH: i64 = 116_444_736_000_000_000;
+
+/// The standard information of a file.
+#[derive(Debug, Clone, Copy)]
+pub struct Info {
+    /// The times in nanoseconds.
+    pub btime: i64,
+    pub mtime: i64,
+    /// The last change of the record.
+    pub ctime: i64,
+    pub atime: i64,
+    /// The DOS and NT file attributes.
+    pub flags: u32,
+}
+
+impl Info {
+    fn parse(value: &[u8]) -> Result<Self, Error> {
+        if value.len() < 48 {
+            return Err(msg2err!("truncated standard information"));
+        }
+        let time = |pos| (le64(value, pos) as i64).wrapping_sub(EPOCH).saturating_mul(100);
+        Ok(Self {
+            btime: time(0),
+            mtime: time(8),
+            ctime: time(16),
+            atime: time(24),
+            flags: le32(value, 32),
+        })
+    }
+}
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a Ntfs<'a, D>,
+    pub(crate) record: u64,
+    pub(crate) info: Info,
+    pub(crate) nlink: u16,
+    dir: bool,
+    /// The data stream that is read.
+    pub(crate) stream: Option<Stream>,
+    /// The index blocks and their bitmap of directories.
+    pub(crate) index: Option<Stream>,
+    pub(crate) bitmap: Option<Stream>,
+    /// The last run of the data stream and of the index blocks to speedup linear reads.
+    cache: Cell<Option<Run>>,
+    pub(crate) index_cache: Cell<Option<Run>>,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            record: self.record,
+            info: self.info,
+            nlink: self.nlink,
+            dir: self.dir,
+            stream: self.stream,
+            index: self.index,
+            bitmap: self.bitmap,
+            cache: self.cache.clone(),
+            index_cache: self.index_cache.clone(),
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("fs", &self.fs)
+            .field("record", &self.record)
+            .field("info", &self.info)
+            .field("stream", &self.stream)
+            .finish()
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    /// Open the file by a reference, which includes the sequence number of the record unless it is zero.
+    pub fn new(fs: &'a Ntfs<'a, D>, reference: u64) -> Result<Self, Error> {
+        let record = reference & RECORD_MASK;
+        let mut buf = [0; MAX_RECORD];
+        let buf = &mut buf[..fs.boot.record_size as usize];
+        fs.record(record, buf)?;
+        let (seq, flags) = (le16(buf, 16), le16(buf, 22));
+        let expected = (reference >> 48) as u16;
+        if flags & RECORD_IN_USE == 0 || (expected != 0 && expected != seq) {
+            return Err(msg2err!("stale file reference"));
+        }
+        if le64(buf, 32) != 0 {
+            return Err(msg2err!("not a base record"));
+        }
+        let mut info = None;
+        for attr in Attributes::new(buf)? {
+            let attr = attr?;
+            if attr.typ == STANDARD_INFORMATION && !attr.non_resident {
+                info = Some(Info::parse(attr.value(buf))?);
+                break;
+            }
+        }
+        let dir = flags & RECORD_DIRECTORY != 0;
+        let mut res = Self {
+            fs,
+            record,
+            info: info.ok_or(msg2err!("no standard information"))?,
+            nlink: le16(buf, 18),
+            dir,
+            stream: None,
+            index: None,
+            bitmap: None,
+            cache: Cell::new(None),
+            index_cache: Cell::new(None),
+        };
+        match dir {
+            true => {
+                res.index = fs.stream(record, INDEX_ALLOCATION, &I30)?;
+                res.bitmap = fs.stream(record, BITMAP, &I30)?;
+            }
+            false => res.stream = fs.stream(record, DATA, &Name::EMPTY)?,
+        }
+        Ok(res)
+    }
+
+    pub fn info(&self) -> &Info {
+        &self.info
+    }
+
+    pub fn id(&self) -> u64 {
+        self.record
+    }
+
+    pub fn ftype(&self) -> FileType {
+        match self.dir {
+            true => FileType::Directory,
+            false => FileType::File,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.dir
+    }
+
+    /// The size of the stream that is read.
+    pub fn size(&self) -> u64 {
+        self.stream.map_or(0, |x| x.size)
+    }
+
+    /// Call the function with the name of each alternate data stream, which stops when it returns false.
+    pub fn streams(&self, f: &mut dyn FnMut(&[u8]) -> bool) -> Result<(), Error> {
+        let mut name = [0; 3 * 255];
+        self.fs.attributes(self.record, &mut |typ, raw| {
+            if typ != DATA || raw.is_empty() {
+                return true;
+            }
+            let n = utf8(raw, &mut name);
+            f(&name[..n])
+        })
+    }
+
+    /// Open an alternate data stream of the file by name.
+    pub fn stream(&self, name: &[u8]) -> Result<Option<Self>, Error> {
+        let Some(name) = Name::from_utf8(name) else {
+            return Ok(None);
+        };
+        let Some(stream) = self.fs.stream(self.record, DATA, &name)? else {
+            return Ok(None);
+        };
+        let mut res = self.clone();
+        res.stream = Some(stream);
+        res.cache = Cell::new(None);
+        Ok(Some(res))
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.is_dir() {
+            return Some(Dir::new(self));
+        }
+        None
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        Self::new(self.fs, dir::child(self, offset)?)
+    }
+
+    fn lookup(&self, name: &[u8]) -> Result<Option<Self>, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        if let Some(reference) = dir::lookup(self, name)? {
+            return Self::new(self.fs, reference).map(Some);
+        }
+        // an alternate data stream as `file:stream`
+        let Some(split) = name.iter().position(|x| *x == b':') else {
+            return Ok(None);
+        };
+        match dir::lookup(self, &name[..split])? {
+            Some(reference) => Self::new(self.fs, reference)?.stream(&name[split + 1..]),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        match &self.stream {
+            Some(stream) => self.fs.read_stream(stream, &self.cache, offset, buf),
+            None => Ok(0),
+        }
+    }
+}
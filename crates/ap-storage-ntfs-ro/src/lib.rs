@@ -0,0 +1,1168 @@
+//! Read NTFS filesystems.
+//!
+//! - boot sector with sectors of up to 4K and MFT records and index blocks of up to 4K
+//! - MFT records with update sequence fixups and attribute lists that spread a file over extension records
+//! - resident and non-resident attributes with sparse runs and LZNT1 compression
+//! - directories as B+ trees in `$INDEX_ROOT` and `$INDEX_ALLOCATION`
+//! - alternate data streams as `streams` attribute and via `file:stream` lookups
+//!
+//! Reading needs no allocations.  Directories are listed in the order of their index blocks, while names are
+//! looked up by descending the tree.  Names are matched exactly, but DOS names that are hidden from listings can
+//! be looked up as well.  The offset of a directory entry is the node, with the root as zero and the index blocks
+//! after it, in the upper 32 bits and the position in the node in the lower ones.  The ID of a file is its MFT
+//! record number.  Compression units are decompressed into a scratch buffer provided by the caller, which also
+//! caches the last unit.
+
+#![no_std]
+
+use ap_storage::{msg2err, Error, FileSystem, Offset, Read, ReadExt, ScratchBuf};
+use core::cell::Cell;
+
+mod attr;
+mod dir;
+pub mod file;
+mod lznt1;
+mod record;
+
+use lznt1::decompress;
+pub use record::Run;
+
+use record::{find_run, fixup, Attribute, Attributes, Name, RECORD_MASK};
+
+const OEM: &[u8; 8] = b"NTFS    ";
+
+/// The largest MFT record and index block.
+pub const MAX_RECORD: usize = 4096;
+
+/// The largest compression unit.
+pub const MAX_UNIT: usize = 64 << 10;
+
+/// The scratch space for decompression.
+pub const SCRATCH_SIZE: usize = 2 * MAX_UNIT;
+
+/// The attribute types.
+pub const STANDARD_INFORMATION: u32 = 0x10;
+pub const ATTRIBUTE_LIST: u32 = 0x20;
+pub const FILE_NAME: u32 = 0x30;
+pub const DATA: u32 = 0x80;
+pub const INDEX_ROOT: u32 = 0x90;
+pub const INDEX_ALLOCATION: u32 = 0xa0;
+pub const BITMAP: u32 = 0xb0;
+
+/// The well-known records.
+pub const MFT: u64 = 0;
+pub const ROOT: u64 = 5;
+pub const UPCASE: u64 = 10;
+
+/// The attribute flags.
+const FLAG_COMPRESSED: u16 = 0x00ff;
+const FLAG_ENCRYPTED: u16 = 0x4000;
+
+/// The record flags.
+pub(crate) const RECORD_IN_USE: u16 = 1 << 0;
+pub(crate) const RECORD_DIRECTORY: u16 = 1 << 1;
+
+/// How often reading a record may lead to reading another one through the attribute lists of the MFT.
+const MAX_DEPTH: u8 = 8;
+
+/// The boot sector.
+#[derive(Debug, Clone, Copy)]
+pub struct BootSector {
+    pub sector_size: u32,
+    pub cluster_size: u64,
+    pub total_sectors: u64,
+    pub mft_lcn: u64,
+    pub mftmirr_lcn: u64,
+    pub record_size: u32,
+    pub index_size: u32,
+    pub serial: u64,
+}
+
+impl BootSector {
+    /// Parse and check the boot sector.
+    pub fn parse(buf: &[u8; 512]) -> Result<Self, Error> {
+        if &buf[3..11] != OEM || buf[510..] != [0x55, 0xaa] {
+            return Err(msg2err!("no ntfs"));
+        }
+        let sector_size = le16(buf, 11) as u32;
+        let per_cluster = match buf[13] {
+            x if x > 0x80 => 1u64.checked_shl(256 - x as u32).unwrap_or(0),
+            x => x as u64,
+        };
+        let cluster_size = sector_size as u64 * per_cluster;
+        // positive values count clusters and negative ones are the shift of the bytes
+        let size = |x: u8| match x as i8 {
+            x @ 1.. => cluster_size.saturating_mul(x as u64),
+            x => 1u64.checked_shl(-(x as i32) as u32).unwrap_or(0),
+        };
+        let res = Self {
+            sector_size,
+            cluster_size,
+            total_sectors: le64(buf, 40),
+            mft_lcn: le64(buf, 48),
+            mftmirr_lcn: le64(buf, 56),
+            record_size: size(buf[64]) as u32,
+            index_size: size(buf[68]) as u32,
+            serial: le64(buf, 72),
+        };
+        let valid = |x: u64, max: u64| x.is_power_of_two() && (512..=max).contains(&x);
+        if !valid(sector_size as u64, 4096) || !valid(cluster_size, 2 << 20) {
+            return Err(msg2err!("invalid cluster size"));
+        }
+        if !valid(res.record_size as u64, MAX_RECORD as u64) || !valid(res.index_size as u64, MAX_RECORD as u64) {
+            return Err(msg2err!("unsupported record size"));
+        }
+        Ok(res)
+    }
+}
+
+/// Whether the disk has an NTFS boot sector, which is cheaper than mounting it.
+pub fn probe(disk: &dyn Read) -> bool {
+    disk.read_object::<[u8; 8]>(3).is_ok_and(|x| &x == OEM)
+}
+
+/// An attribute that holds the bytes of a stream.
+#[derive(Debug, Clone, Copy)]
+pub struct Stream {
+    /// The base record of the file.
+    pub record: u64,
+    pub typ: u32,
+    pub(crate) name: Name,
+    pub resident: bool,
+    pub flags: u16,
+    /// The clusters of a compression unit as shift.
+    pub unit: u8,
+    pub size: u64,
+    /// The bytes after this read as zero.
+    pub initialized: u64,
+    /// The number of the first piece of the attribute in its record.
+    pub instance: u16,
+}
+
+impl Stream {
+    /// An unnamed data stream that is not yet parsed.
+    const fn data(record: u64, size: u64) -> Self {
+        Self {
+            record,
+            typ: DATA,
+            name: Name::EMPTY,
+            resident: false,
+            flags: 0,
+            unit: 0,
+            size,
+            initialized: size,
+            instance: 0,
+        }
+    }
+}
+
+/// An entry of an attribute list.
+pub(crate) struct ListEntry<'b> {
+    pub typ: u32,
+    pub name: &'b [u8],
+    pub lowest_vcn: u64,
+    pub reference: u64,
+    pub instance: u16,
+}
+
+/// Split the scratch buffer into the decompressed data and the compressed input.
+fn parts(buf: &mut [u8]) -> (&mut [u8], &mut [u8]) {
+    let (data, rest) = buf.split_at_mut(MAX_UNIT);
+    (data, &mut rest[..MAX_UNIT])
+}
+
+/// An NTFS filesystem.
+pub struct Ntfs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+    boot: BootSector,
+    mft: Stream,
+    mft_cache: Cell<Option<Run>>,
+    upcase: Stream,
+    upcase_cache: Cell<Option<Run>>,
+    /// The records that are read at the moment.
+    depth: Cell<u8>,
+    scratch: ScratchBuf<'a>,
+    /// The record, the instance of the stream and the index of the unit cached in the scratch buffer.
+    unit: Cell<Option<(u64, u16, u64)>>,
+}
+
+impl<D: ?Sized> core::fmt::Debug for Ntfs<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(
+            fmt,
+            "Ntfs(cs {}, rs {}, serial {:016x})",
+            self.boot.cluster_size, self.boot.record_size, self.boot.serial
+        )
+    }
+}
+
+impl<'a, D: Read + ?Sized> Ntfs<'a, D> {
+    /// Mount the filesystem with a scratch buffer of at least [`SCRATCH_SIZE`] bytes.
+    pub fn new(disk: &'a D, scratch: &'a mut [u8]) -> Result<Self, Error> {
+        let buf: [u8; 512] = (&disk as &dyn Read).read_object(0)?;
+        let boot = BootSector::parse(&buf)?;
+        if scratch.len() < SCRATCH_SIZE {
+            return Err(msg2err!("scratch buffer too small"));
+        }
+        // the MFT maps itself, so that its own stream is parsed via a stream without limits
+        let mut fs = Self {
+            disk,
+            boot,
+            mft: Stream::data(MFT, u64::MAX),
+            mft_cache: Cell::new(None),
+            upcase: Stream::data(UPCASE, 0),
+            upcase_cache: Cell::new(None),
+            depth: Cell::new(0),
+            scratch: ScratchBuf::new(scratch),
+            unit: Cell::new(None),
+        };
+        fs.mft = fs.stream(MFT, DATA, &Name::EMPTY)?.ok_or(msg2err!("no MFT data"))?;
+        if fs.mft.resident || fs.mft.flags & (FLAG_COMPRESSED | FLAG_ENCRYPTED) != 0 {
+            return Err(msg2err!("invalid MFT data"));
+        }
+        fs.upcase = fs
+            .stream(UPCASE, DATA, &Name::EMPTY)?
+            .ok_or(msg2err!("no upcase table"))?;
+        Ok(fs)
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    pub fn boot_sector(&self) -> &BootSector {
+        &self.boot
+    }
+
+    /// The disk position of a cluster.
+    fn cluster_pos(&self, lcn: u64) -> Result<Offset, Error> {
+        lcn.checked_mul(self.boot.cluster_size)
+            .ok_or(msg2err!("cluster out of range"))
+    }
+
+    /// The bytes per VCN of the index blocks.
+    pub(crate) fn index_vcn_size(&self) -> u64 {
+        match self.boot.index_size as u64 >= self.boot.cluster_size {
+            true => self.boot.cluster_size,
+            false => 512,
+        }
+    }
+
+    /// Read an MFT record into a buffer of the record size and apply the fixups.
+    pub fn record(&self, number: u64, buf: &mut [u8]) -> Result<(), Error> {
+        if buf.len() != self.boot.record_size as usize {
+            return Err(msg2err!("invalid record buffer"));
+        }
+        let depth = self.depth.get();
+        if depth >= MAX_DEPTH {
+            return Err(msg2err!("MFT references itself"));
+        }
+        self.depth.set(depth + 1);
+        let pos = number
+            .checked_mul(buf.len() as u64)
+            .ok_or(msg2err!("record out of range"));
+        let res = match number {
+            // the first record is where the MFT starts
+            MFT => self
+                .cluster_pos(self.boot.mft_lcn)
+                .and_then(|x| self.disk().read_exact(x, buf)),
+            _ => pos.and_then(|x| self.read_stream_exact(&self.mft, &self.mft_cache, x, buf)),
+        };
+        self.depth.set(depth);
+        res?;
+        fixup(buf, b"FILE")?;
+        // newer records include their number after the header
+        if le16(buf, 4) >= 48 && le32(buf, 44) as u64 != number & 0xffff_ffff {
+            return Err(msg2err!("record number mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Call the function for each entry of an attribute list, which stops when it returns false.
+    ///
+    /// The list is in the record that is in the buffer.
+    fn walk_list(&self, buf: &[u8], list: &Attribute, f: &mut dyn FnMut(&ListEntry) -> bool) -> Result<(), Error> {
+        let mut pos = 0;
+        while pos < list.size {
+            let mut entry = [0; 26 + 2 * 255 + 8];
+            let n = core::cmp::min(entry.len() as u64, list.size - pos) as usize;
+            match list.non_resident {
+                true => self.read_runs(list.pairs(buf), list.lowest_vcn, pos, &mut entry[..n])?,
+                false => entry[..n].copy_from_slice(&list.value(buf)[pos as usize..pos as usize + n]),
+            }
+            let len = le16(&entry, 4) as usize;
+            let name = (entry[7] as usize, entry[6] as usize * 2);
+            if n < 26 || len < 26 || len > n || name.0 + name.1 > len {
+                return Err(msg2err!("invalid attribute list"));
+            }
+            let entry = ListEntry {
+                typ: le32(&entry, 0),
+                name: &entry[name.0..name.0 + name.1],
+                lowest_vcn: le64(&entry, 8),
+                reference: le64(&entry, 16),
+                instance: le16(&entry, 24),
+            };
+            if !f(&entry) {
+                break;
+            }
+            pos += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Find the piece of an attribute that maps a VCN and leave its record in the buffer.
+    pub(crate) fn find(
+        &self,
+        record: u64,
+        typ: u32,
+        name: &Name,
+        vcn: u64,
+        buf: &mut [u8; MAX_RECORD],
+    ) -> Result<Option<Attribute>, Error> {
+        let size = self.boot.record_size as usize;
+        self.record(record, &mut buf[..size])?;
+        let (mut list, mut best) = (None, None::<Attribute>);
+        for attr in Attributes::new(&buf[..size])? {
+            let attr = attr?;
+            if attr.typ == ATTRIBUTE_LIST {
+                list = Some(attr);
+            }
+            if attr.typ == typ
+                && name.matches(attr.name(buf))
+                && attr.lowest_vcn <= vcn
+                && best.is_none_or(|x| x.lowest_vcn < attr.lowest_vcn)
+            {
+                best = Some(attr);
+            }
+        }
+        let Some(list) = list else {
+            return Ok(best);
+        };
+
+        // the list is sorted by the first VCN of the pieces
+        let mut target = None;
+        self.walk_list(&buf[..size], &list, &mut |x| {
+            if x.typ == typ && name.matches(x.name) && x.lowest_vcn <= vcn {
+                target = Some((x.reference, x.instance));
+            }
+            true
+        })?;
+        let Some((reference, instance)) = target else {
+            return Ok(None);
+        };
+        let number = reference & RECORD_MASK;
+        self.record(number, &mut buf[..size])?;
+        if number != record && le64(buf, 32) & RECORD_MASK != record {
+            return Err(msg2err!("extension of another record"));
+        }
+        for attr in Attributes::new(&buf[..size])? {
+            let attr = attr?;
+            if attr.typ == typ && attr.instance == instance {
+                return Ok(Some(attr));
+            }
+        }
+        Err(msg2err!("attribute of the list not found"))
+    }
+
+    /// Call the function with the type and the name of each attribute of a file, which stops when it returns false.
+    pub(crate) fn attributes(&self, record: u64, f: &mut dyn FnMut(u32, &[u8]) -> bool) -> Result<(), Error> {
+        let mut buf = [0; MAX_RECORD];
+        let buf = &mut buf[..self.boot.record_size as usize];
+        self.record(record, buf)?;
+        for attr in Attributes::new(buf)? {
+            let attr = attr?;
+            if attr.typ == ATTRIBUTE_LIST {
+                return self.walk_list(buf, &attr, &mut |x| x.lowest_vcn != 0 || f(x.typ, x.name));
+            }
+        }
+        for attr in Attributes::new(buf)? {
+            let attr = attr?;
+            if attr.lowest_vcn == 0 && !f(attr.typ, attr.name(buf)) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The stream of an attribute.
+    pub(crate) fn stream(&self, record: u64, typ: u32, name: &Name) -> Result<Option<Stream>, Error> {
+        let mut buf = [0; MAX_RECORD];
+        let Some(attr) = self.find(record, typ, name, 0, &mut buf)? else {
+            return Ok(None);
+        };
+        if attr.lowest_vcn != 0 {
+            return Err(msg2err!("first piece of an attribute missing"));
+        }
+        Ok(Some(Stream {
+            record,
+            typ,
+            name: *name,
+            resident: !attr.non_resident,
+            flags: attr.flags,
+            unit: attr.unit,
+            size: attr.size,
+            initialized: core::cmp::min(attr.initialized, attr.size),
+            instance: attr.instance,
+        }))
+    }
+
+    /// The run of a non-resident stream that holds a VCN.
+    fn run(&self, stream: &Stream, cache: &Cell<Option<Run>>, vcn: u64) -> Result<Option<Run>, Error> {
+        if let Some(run) = cache.get().filter(|x| x.contains(vcn)) {
+            return Ok(Some(run));
+        }
+        let mut buf = [0; MAX_RECORD];
+        let Some(attr) = self.find(stream.record, stream.typ, &stream.name, vcn, &mut buf)? else {
+            return Ok(None);
+        };
+        if !attr.non_resident {
+            return Err(msg2err!("resident piece of a non-resident stream"));
+        }
+        let run = find_run(attr.pairs(&buf), attr.lowest_vcn, vcn)?;
+        cache.set(run);
+        Ok(run)
+    }
+
+    /// Read from the mapping pairs of a single attribute piece.
+    fn read_runs(&self, pairs: &[u8], lowest_vcn: u64, mut pos: u64, mut buf: &mut [u8]) -> Result<(), Error> {
+        let cs = self.boot.cluster_size;
+        while !buf.is_empty() {
+            let run = find_run(pairs, lowest_vcn, pos / cs)?.ok_or(msg2err!("unmapped cluster"))?;
+            let end = run.vcn.saturating_add(run.len).saturating_mul(cs);
+            let (now, rest) = buf.split_at_mut(core::cmp::min(buf.len() as u64, end - pos) as usize);
+            match run.lcn {
+                None => now.fill(0),
+                Some(lcn) => self
+                    .disk()
+                    .read_exact(self.cluster_pos(lcn)? + pos - run.vcn * cs, now)?,
+            }
+            pos += now.len() as u64;
+            buf = rest;
+        }
+        Ok(())
+    }
+
+    /// Read from a stream.  Bytes after the initialized size and in sparse runs read as zero.
+    pub(crate) fn read_stream(
+        &self,
+        stream: &Stream,
+        cache: &Cell<Option<Run>>,
+        offset: Offset,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        if offset >= stream.size {
+            return Ok(0);
+        }
+        let n = core::cmp::min(buf.len() as u64, stream.size - offset) as usize;
+        let buf = &mut buf[..n];
+        if stream.resident {
+            let mut record = [0; MAX_RECORD];
+            let attr = self
+                .find(stream.record, stream.typ, &stream.name, 0, &mut record)?
+                .filter(|x| !x.non_resident)
+                .ok_or(msg2err!("resident stream changed"))?;
+            let value = attr.value(&record).get(offset as usize..offset as usize + n);
+            buf.copy_from_slice(value.ok_or(msg2err!("resident stream changed"))?);
+            return Ok(n);
+        }
+        if offset >= stream.initialized {
+            buf.fill(0);
+            return Ok(n);
+        }
+        let n = core::cmp::min(n as u64, stream.initialized - offset) as usize;
+        let buf = &mut buf[..n];
+        if stream.flags & FLAG_ENCRYPTED != 0 {
+            return Err(msg2err!("encrypted streams are not supported"));
+        }
+        if stream.flags & FLAG_COMPRESSED != 0 {
+            return self.read_compressed(stream, cache, offset, buf);
+        }
+        let cs = self.boot.cluster_size;
+        let run = self
+            .run(stream, cache, offset / cs)?
+            .ok_or(msg2err!("unmapped cluster"))?;
+        let end = run.vcn.saturating_add(run.len).saturating_mul(cs);
+        let n = core::cmp::min(buf.len() as u64, end - offset) as usize;
+        let buf = &mut buf[..n];
+        match run.lcn {
+            None => buf.fill(0),
+            Some(lcn) => self
+                .disk()
+                .read_exact(self.cluster_pos(lcn)? + offset - run.vcn * cs, buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    /// Fill the buffer from a stream.
+    pub(crate) fn read_stream_exact(
+        &self,
+        stream: &Stream,
+        cache: &Cell<Option<Run>>,
+        mut offset: Offset,
+        mut buf: &mut [u8],
+    ) -> Result<(), Error> {
+        while !buf.is_empty() {
+            let n = self.read_stream(stream, cache, offset, buf)?;
+            if n == 0 {
+                return Err(msg2err!("unexpected end of stream"));
+            }
+            offset += n as Offset;
+            buf = &mut buf[n..];
+        }
+        Ok(())
+    }
+
+    /// Copy from a compression unit that is cached in the scratch buffer.
+    ///
+    /// Units with all clusters allocated are stored raw and units without any are zero.
+    fn read_compressed(
+        &self,
+        stream: &Stream,
+        cache: &Cell<Option<Run>>,
+        offset: Offset,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let cs = self.boot.cluster_size;
+        let clusters = 1u64.checked_shl(stream.unit as u32).unwrap_or(u64::MAX);
+        let unit_size = clusters.saturating_mul(cs);
+        if stream.unit == 0 || unit_size > MAX_UNIT as u64 {
+            return Err(msg2err!("unsupported compression unit"));
+        }
+        let (index, within) = (offset / unit_size, (offset % unit_size) as usize);
+        let key = (stream.record, stream.instance, index);
+        let mut scratch = self.scratch.borrow_mut()?;
+        if self.unit.get() != Some(key) {
+            self.unit.set(None);
+            let (data, input) = parts(&mut scratch);
+            let (data, input) = (&mut data[..unit_size as usize], &mut input[..unit_size as usize]);
+            let (first, mut vcn, mut allocated) = (index * clusters, index * clusters, 0);
+            while vcn < first + clusters {
+                let run = self.run(stream, cache, vcn)?.ok_or(msg2err!("unmapped cluster"))?;
+                let count = core::cmp::min(run.vcn.saturating_add(run.len), first + clusters) - vcn;
+                if let Some(lcn) = run.lcn {
+                    let start = ((vcn - first) * cs) as usize;
+                    let pos = self.cluster_pos(lcn + (vcn - run.vcn))?;
+                    self.disk()
+                        .read_exact(pos, &mut input[start..start + (count * cs) as usize])?;
+                    allocated += count;
+                }
+                vcn += count;
+            }
+            match allocated {
+                0 => data.fill(0),
+                x if x == clusters => data.copy_from_slice(input),
+                x => decompress(&input[..(x * cs) as usize], data)?,
+            }
+            self.unit.set(Some(key));
+        }
+        let n = core::cmp::min(buf.len(), unit_size as usize - within);
+        buf[..n].copy_from_slice(&parts(&mut scratch).0[within..within + n]);
+        Ok(n)
+    }
+
+    /// The uppercase of a UTF-16 code unit according to the table of the volume.
+    pub(crate) fn upcase(&self, x: u16) -> Result<u16, Error> {
+        if x < 0x80 {
+            return Ok((x as u8).to_ascii_uppercase() as u16);
+        }
+        let mut buf = [0; 2];
+        Ok(
+            match self.read_stream(&self.upcase, &self.upcase_cache, x as u64 * 2, &mut buf)? {
+                2 => u16::from_le_bytes(buf),
+                _ => x,
+            },
+        )
+    }
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for Ntfs<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        file::File::new(self, ROOT)
+    }
+}
+
+/// A little-endian u16 at the position.
+pub(crate) fn le16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+/// A little-endian u32 at the position.
+pub(crate) fn le32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+/// A little-endian u64 at the position.
+pub(crate) fn le64(buf: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{
+        attr::{self, Attributes, Value},
+        file::{File, FileType},
+    };
+    use ap_storage_memory::ReadSlice;
+    use ap_storage_test_util::*;
+    use std::{string::String, vec, vec::Vec};
+
+    const CS: usize = 512;
+    const RS: usize = 1024;
+
+    /// The records of the files.
+    const HELLO: u64 = 16;
+    const SPARSE: u64 = 17;
+    const COMPRESSED: u64 = 18;
+    const ADS: u64 = 19;
+    const LIST: u64 = 20;
+    const SUB: u64 = 23;
+
+    /// One second after the Unix epoch as file time.
+    const SECOND: u64 = 116_444_736_010_000_000;
+
+    fn put(buf: &mut [u8], pos: usize, value: u64, size: usize) {
+        buf[pos..pos + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    fn utf16(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    fn align(x: usize) -> usize {
+        (x + 7) & !7
+    }
+
+    /// A reference with the sequence number of the records.
+    fn reference(record: u64) -> u64 {
+        1 << 48 | record
+    }
+
+    /// Protect a block with an update sequence at a position.
+    fn protect(buf: &mut [u8], usa: usize) {
+        let count = buf.len() / 512 + 1;
+        put(buf, 4, usa as u64, 2);
+        put(buf, 6, count as u64, 2);
+        put(buf, usa, 0x1234, 2);
+        for i in 1..count {
+            let pos = i * 512 - 2;
+            buf.copy_within(pos..pos + 2, usa + 2 * i);
+            put(buf, pos, 0x1234, 2);
+        }
+    }
+
+    fn resident(typ: u32, name: &str, value: &[u8]) -> Vec<u8> {
+        let name = utf16(name);
+        let offset = align(24 + name.len());
+        let mut res = vec![0; align(offset + value.len())];
+        let len = res.len() as u64;
+        put(&mut res, 0, typ as u64, 4);
+        put(&mut res, 4, len, 4);
+        res[9] = (name.len() / 2) as u8;
+        put(&mut res, 10, 24, 2);
+        put(&mut res, 16, value.len() as u64, 4);
+        put(&mut res, 20, offset as u64, 2);
+        res[24..24 + name.len()].copy_from_slice(&name);
+        res[offset..offset + value.len()].copy_from_slice(value);
+        res
+    }
+
+    /// A piece of a non-resident attribute with runs of clusters, which are sparse without a position.
+    fn non_resident(typ: u32, name: &str, lowest: u64, runs: &[(u64, Option<u64>)], size: u64) -> Vec<u8> {
+        let bytes = |x: i64| (1..8).find(|n| (x << (64 - 8 * n)) >> (64 - 8 * n) == x).unwrap_or(8);
+        let (mut pairs, mut last, mut clusters) = (vec![], 0i64, 0);
+        for &(len, lcn) in runs {
+            let n = bytes(len as i64);
+            let delta = lcn.map(|x| x as i64 - last);
+            let m = delta.map_or(0, bytes);
+            pairs.push((m << 4 | n) as u8);
+            pairs.extend_from_slice(&len.to_le_bytes()[..n]);
+            pairs.extend_from_slice(&delta.unwrap_or(0).to_le_bytes()[..m]);
+            last = lcn.map_or(last, |x| x as i64);
+            clusters += len;
+        }
+        pairs.push(0);
+        let name = utf16(name);
+        let offset = align(64 + name.len());
+        let mut res = vec![0; align(offset + pairs.len())];
+        let len = res.len() as u64;
+        put(&mut res, 0, typ as u64, 4);
+        put(&mut res, 4, len, 4);
+        res[8] = 1;
+        res[9] = (name.len() / 2) as u8;
+        put(&mut res, 10, 64, 2);
+        put(&mut res, 16, lowest, 8);
+        put(&mut res, 24, lowest + clusters - 1, 8);
+        put(&mut res, 32, offset as u64, 2);
+        put(&mut res, 40, clusters * CS as u64, 8);
+        put(&mut res, 48, size, 8);
+        put(&mut res, 56, size, 8);
+        res[64..64 + name.len()].copy_from_slice(&name);
+        res[offset..offset + pairs.len()].copy_from_slice(&pairs);
+        res
+    }
+
+    fn info() -> Vec<u8> {
+        let mut value = vec![0; 48];
+        for i in 0..4 {
+            put(&mut value, i * 8, SECOND + i as u64 * 10_000_000, 8);
+        }
+        put(&mut value, 32, 0x20, 4);
+        resident(STANDARD_INFORMATION, "", &value)
+    }
+
+    /// An MFT record with the attributes, which get their index as instance.
+    fn record(number: u64, flags: u16, base: u64, nlink: u16, attrs: &[Vec<u8>]) -> Vec<u8> {
+        let mut res = vec![0; RS];
+        res[..4].copy_from_slice(b"FILE");
+        put(&mut res, 16, 1, 2);
+        put(&mut res, 18, nlink as u64, 2);
+        put(&mut res, 20, 56, 2);
+        put(&mut res, 22, flags as u64, 2);
+        put(&mut res, 28, RS as u64, 4);
+        put(&mut res, 32, base, 8);
+        put(&mut res, 40, attrs.len() as u64, 2);
+        put(&mut res, 44, number, 4);
+        let mut pos = 56;
+        for (i, attr) in attrs.iter().enumerate() {
+            res[pos..pos + attr.len()].copy_from_slice(attr);
+            put(&mut res, pos + 14, i as u64, 2);
+            pos += attr.len();
+        }
+        put(&mut res, pos, 0xffff_ffff, 4);
+        put(&mut res, 24, pos as u64 + 8, 4);
+        protect(&mut res, 48);
+        res
+    }
+
+    fn entry(record: u64, name: &str, namespace: u8, subnode: Option<u64>) -> Vec<u8> {
+        let name = utf16(name);
+        let keylen = 66 + name.len();
+        let len = align(16 + keylen) + subnode.map_or(0, |_| 8);
+        let mut res = vec![0; len];
+        put(&mut res, 0, reference(record), 8);
+        put(&mut res, 8, len as u64, 2);
+        put(&mut res, 10, keylen as u64, 2);
+        put(&mut res, 12, subnode.is_some() as u64, 4);
+        put(&mut res, 16, reference(ROOT), 8);
+        if [ROOT, SUB].contains(&record) {
+            put(&mut res, 16 + 56, 0x1000_0000, 4);
+        }
+        res[16 + 64] = (name.len() / 2) as u8;
+        res[16 + 65] = namespace;
+        res[16 + 66..16 + 66 + name.len()].copy_from_slice(&name);
+        if let Some(vcn) = subnode {
+            put(&mut res, len - 8, vcn, 8);
+        }
+        res
+    }
+
+    fn last(subnode: Option<u64>) -> Vec<u8> {
+        let len = 16 + subnode.map_or(0, |_| 8);
+        let mut res = vec![0; len];
+        put(&mut res, 8, len as u64, 2);
+        put(&mut res, 12, 2 | subnode.is_some() as u64, 4);
+        if let Some(vcn) = subnode {
+            put(&mut res, 16, vcn, 8);
+        }
+        res
+    }
+
+    /// An index header with the entries at an offset.
+    fn index(entries: &[Vec<u8>], offset: usize, large: bool) -> Vec<u8> {
+        let mut res = vec![0; offset];
+        res.extend(entries.concat());
+        let len = res.len() as u64;
+        put(&mut res, 0, offset as u64, 4);
+        put(&mut res, 4, len, 4);
+        put(&mut res, 8, len, 4);
+        res[12] = large as u8;
+        res
+    }
+
+    fn index_root(entries: &[Vec<u8>], large: bool) -> Vec<u8> {
+        let mut value = vec![0; 16];
+        put(&mut value, 0, FILE_NAME as u64, 4);
+        put(&mut value, 4, 1, 4);
+        put(&mut value, 8, 1024, 4);
+        value[12] = 2;
+        value.extend(index(entries, 16, large));
+        resident(INDEX_ROOT, "$I30", &value)
+    }
+
+    fn index_block(vcn: u64, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut res = vec![0; 1024];
+        res[..4].copy_from_slice(b"INDX");
+        put(&mut res, 16, vcn, 8);
+        let header = index(entries, 40, false);
+        res[24..24 + header.len()].copy_from_slice(&header);
+        protect(&mut res, 40);
+        res
+    }
+
+    fn list_entry(typ: u32, name: &str, lowest: u64, record: u64, instance: u16) -> Vec<u8> {
+        let name = utf16(name);
+        let mut res = vec![0; align(26 + name.len())];
+        let len = res.len() as u64;
+        put(&mut res, 0, typ as u64, 4);
+        put(&mut res, 4, len, 2);
+        res[6] = (name.len() / 2) as u8;
+        res[7] = 26;
+        put(&mut res, 8, lowest, 8);
+        put(&mut res, 16, reference(record), 8);
+        put(&mut res, 24, instance as u64, 2);
+        res[26..26 + name.len()].copy_from_slice(&name);
+        res
+    }
+
+    /// The first unit of the compressed file with a compressed and a stored chunk.
+    fn compressed() -> Vec<u8> {
+        let mut res = vec![0x05, 0xb0, 0x08, b'a', b'b', b'c', 0xfa, 0x2f, 0xff, 0x3f];
+        res.extend((0..4096).map(|x| (x * 7 % 251) as u8));
+        res
+    }
+
+    /// A volume with 512 byte clusters and sectors and 1K records and index blocks.
+    fn image() -> Vec<u8> {
+        let mut data = vec![0; 1024 * CS];
+        data[3..11].copy_from_slice(OEM);
+        put(&mut data, 11, CS as u64, 2);
+        data[13] = 1;
+        put(&mut data, 40, 1024, 8);
+        put(&mut data, 48, 16, 8);
+        put(&mut data, 56, 2, 8);
+        data[64] = 0xf6;
+        data[68] = 0xf6;
+        put(&mut data, 72, 0x1234_5678, 8);
+        data[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let mut cluster = |lcn: usize, value: &[u8]| data[lcn * CS..lcn * CS + value.len()].copy_from_slice(value);
+
+        // the MFT is split in two runs
+        let mut records = vec![
+            record(
+                MFT,
+                1,
+                0,
+                1,
+                &[
+                    info(),
+                    non_resident(DATA, "", 0, &[(24, Some(16)), (24, Some(100))], 24 * RS as u64),
+                ],
+            ),
+            record(
+                UPCASE,
+                1,
+                0,
+                1,
+                &[
+                    info(),
+                    resident(
+                        DATA,
+                        "",
+                        &(0..256)
+                            .flat_map(|x: u16| {
+                                let upper = match x {
+                                    0x61..=0x7a | 0xe0..=0xf6 | 0xf8..=0xfe => x - 0x20,
+                                    _ => x,
+                                };
+                                upper.to_le_bytes()
+                            })
+                            .collect::<Vec<_>>(),
+                    ),
+                ],
+            ),
+        ];
+
+        // the root has two leaves and an unused block, where the upcase table orders the last names
+        let mut bitmap = vec![0; 8];
+        bitmap[0] = 0b011;
+        records.push(record(
+            ROOT,
+            3,
+            0,
+            1,
+            &[
+                info(),
+                index_root(&[entry(HELLO, "hello.txt", 1, Some(0)), last(Some(2))], true),
+                non_resident(INDEX_ALLOCATION, "$I30", 0, &[(6, Some(800))], 3072),
+                resident(BITMAP, "$I30", &bitmap),
+            ],
+        ));
+        cluster(
+            800,
+            &index_block(
+                0,
+                &[
+                    entry(ROOT, ".", 3, None),
+                    entry(ADS, "ads.bin", 1, None),
+                    entry(COMPRESSED, "compressed.bin", 1, None),
+                    entry(HELLO, "dosname long.txt", 1, None),
+                    entry(HELLO, "DOSNAM~1.TXT", 2, None),
+                    last(None),
+                ],
+            ),
+        );
+        cluster(
+            802,
+            &index_block(
+                2,
+                &[
+                    entry(LIST, "list.bin", 1, None),
+                    entry(SPARSE, "sparse.bin", 1, None),
+                    entry(SUB, "sub", 1, None),
+                    entry(HELLO, "öl.txt", 1, None),
+                    entry(HELLO, "Üb.txt", 1, None),
+                    last(None),
+                ],
+            ),
+        );
+        cluster(804, &index_block(4, &[entry(HELLO, "stale.txt", 1, None), last(None)]));
+        records.push(record(
+            SUB,
+            3,
+            0,
+            1,
+            &[
+                info(),
+                index_root(&[entry(HELLO, "inner.txt", 1, None), last(None)], false),
+            ],
+        ));
+
+        records.push(record(HELLO, 1, 0, 4, &[info(), resident(DATA, "", b"Hello, NTFS!\n")]));
+        records.push(record(
+            SPARSE,
+            1,
+            0,
+            1,
+            &[
+                info(),
+                non_resident(DATA, "", 0, &[(2, Some(200)), (4, None), (2, Some(150))], 4000),
+            ],
+        ));
+        put(records.last_mut().unwrap(), 56 + 72 + 56, 3500, 8);
+        cluster(200, &ramp(2 * CS, 26));
+        cluster(150, &[b'Z'; 2 * CS]);
+
+        // compressed units of 16 clusters: mixed chunks, stored and sparse
+        let mut data_attr = non_resident(
+            DATA,
+            "",
+            0,
+            &[(9, Some(300)), (7, None), (16, Some(320)), (16, None)],
+            16484,
+        );
+        data_attr[12] = 1;
+        data_attr[34] = 4;
+        records.push(record(COMPRESSED, 1, 0, 1, &[info(), data_attr]));
+        cluster(300, &compressed());
+        cluster(320, &ramp(16 * CS, 253));
+
+        // the main stream crosses the end of the first sector
+        records.push(record(
+            ADS,
+            1,
+            0,
+            1,
+            &[
+                info(),
+                resident(DATA, "", &ramp(400, 26)),
+                resident(DATA, "Zone.Identifier", b"[ZoneTransfer]\r\nZoneId=3\r\n"),
+                non_resident(DATA, "big", 0, &[(1, Some(400))], 300),
+            ],
+        ));
+        cluster(400, &[b'B'; CS]);
+
+        // the data is in two extension records that are found via a non-resident list
+        let list = [
+            list_entry(STANDARD_INFORMATION, "", 0, LIST, 0),
+            list_entry(DATA, "", 0, LIST + 1, 1),
+            list_entry(DATA, "", 4, LIST + 2, 1),
+            list_entry(DATA, "alt", 0, LIST + 1, 2),
+        ]
+        .concat();
+        cluster(700, &list);
+        records.push(record(
+            LIST,
+            1,
+            0,
+            1,
+            &[
+                info(),
+                non_resident(ATTRIBUTE_LIST, "", 0, &[(1, Some(700))], list.len() as u64),
+            ],
+        ));
+        let filler = resident(0x40, "", &[0; 16]);
+        records.push(record(
+            LIST + 1,
+            1,
+            reference(LIST),
+            0,
+            &[
+                filler.clone(),
+                non_resident(DATA, "", 0, &[(4, Some(500))], 4096),
+                resident(DATA, "alt", b"alternate"),
+            ],
+        ));
+        records.push(record(
+            LIST + 2,
+            1,
+            reference(LIST),
+            0,
+            &[filler, non_resident(DATA, "", 4, &[(4, Some(600))], 0)],
+        ));
+        cluster(500, &[b'1'; 4 * CS]);
+        cluster(600, &[b'2'; 4 * CS]);
+
+        for buf in records {
+            let number = le32(&buf, 44) as usize;
+            let pos = match number {
+                0..12 => 16 * CS + number * RS,
+                _ => 100 * CS + (number - 12) * RS,
+            };
+            data[pos..pos + RS].copy_from_slice(&buf);
+        }
+        data
+    }
+
+    #[test]
+    fn lznt1() {
+        // literals and a match with a displacement of more than 12 bits
+        let mut chunk = vec![0];
+        chunk.extend_from_slice(b"abcdefgh\0ijklmnop\x02q");
+        chunk.extend_from_slice(&(16u16 << 11 | 17).to_le_bytes());
+        let mut input = ((0xb000 | (chunk.len() - 1)) as u16).to_le_bytes().to_vec();
+        input.extend_from_slice(&chunk);
+        input.extend_from_slice(&[0x02, 0x30, b'x', b'y', b'z', 0, 0]);
+        let mut out = vec![0xff; 8200];
+        decompress(&input, &mut out).unwrap();
+        let mut expected = vec![0; 8200];
+        expected[..37].copy_from_slice(b"abcdefghijklmnopqabcdefghijklmnopqabc");
+        expected[4096..4099].copy_from_slice(b"xyz");
+        assert_eq!(out, expected);
+
+        // a match before the start
+        assert!(decompress(&[0x02, 0xb0, 0x02, b'a', 0x00, 0x50], &mut out).is_err());
+        assert!(decompress(&[0x10, 0xb0, 0x00], &mut out).is_err());
+    }
+
+    #[test]
+    fn files() {
+        let data = image();
+        let disk = ReadSlice(&data);
+        let mut scratch = vec![0; SCRATCH_SIZE];
+        assert!(probe(&disk));
+        let fs = Ntfs::new(&disk, &mut scratch).unwrap();
+        let root = fs.root().unwrap();
+        let open = |path: &str| root.clone().lookup_path(path.as_bytes()).unwrap();
+
+        let hello = open("hello.txt");
+        assert_eq!(read_all(&hello), b"Hello, NTFS!\n");
+        assert_eq!(
+            hello.attr().get(crate::attr::NLINKS, &mut []).and_then(|x| x.as_u64()),
+            Some(4)
+        );
+        assert!(matches!(
+            hello.attr().get(attr::BTIME, &mut []),
+            Some(Value::Time(1_000_000_000))
+        ));
+        assert!(matches!(
+            hello.attr().get(attr::ATIME, &mut []),
+            Some(Value::Time(4_000_000_000))
+        ));
+
+        let mut sparse = vec![0; 4000];
+        sparse[..2 * CS].copy_from_slice(&ramp(2 * CS, 26));
+        sparse[6 * CS..3500].fill(b'Z');
+        assert_eq!(read_all(&open("sparse.bin")), sparse);
+
+        let mut expected: Vec<u8> = b"abc".iter().copied().cycle().take(4096).collect();
+        expected.extend_from_slice(&compressed()[10..]);
+        expected.extend(ramp(16 * CS, 253));
+        expected.extend([0; 100]);
+        assert_eq!(read_all(&open("compressed.bin")), expected);
+
+        let list = open("list.bin");
+        let mut expected = vec![b'1'; 4 * CS];
+        expected.extend([b'2'; 4 * CS]);
+        assert_eq!(read_all(&list), expected);
+        assert_eq!(read_all(&list.stream(b"alt").unwrap().unwrap()), b"alternate");
+        assert_eq!(
+            list.attr().get(attr::SIZE, &mut []).and_then(|x| x.as_u64()),
+            Some(4096)
+        );
+
+        // alternate data streams
+        let ads = open("ads.bin");
+        assert_eq!(read_all(&ads), ramp(400, 26));
+        let mut buf = [0; 64];
+        for (file, streams) in [(&ads, ":Zone.Identifier:big"), (&list, ":alt"), (&hello, "")] {
+            let Some(Value::Str(n)) = file.attr().get(crate::attr::STREAMS, &mut buf) else {
+                panic!("no streams");
+            };
+            assert_eq!(&buf[..n], streams.as_bytes());
+        }
+        assert_eq!(
+            read_all(&open("ads.bin:Zone.Identifier")),
+            b"[ZoneTransfer]\r\nZoneId=3\r\n"
+        );
+        assert_eq!(read_all(&open("ads.bin:big")), [b'B'; 300]);
+        assert!(root.lookup(b"ads.bin:gone").unwrap().is_none());
+        assert!(root.lookup(b"gone:big").unwrap().is_none());
+
+        // a torn record
+        let mut data = image();
+        data[100 * CS + (HELLO as usize - 12) * RS + 510] ^= 1;
+        let disk = ReadSlice(&data);
+        let mut scratch = vec![0; SCRATCH_SIZE];
+        let fs = Ntfs::new(&disk, &mut scratch).unwrap();
+        assert!(fs.root().unwrap().lookup(b"hello.txt").is_err());
+    }
+
+    #[test]
+    fn directories() {
+        let data = image();
+        let disk = ReadSlice(&data);
+        let mut scratch = vec![0; SCRATCH_SIZE];
+        let fs = Ntfs::new(&disk, &mut scratch).unwrap();
+        let root = fs.root().unwrap();
+        let listing = list(&root);
+        let names: Vec<_> = listing.iter().map(|x| x.0.as_str()).collect();
+        let expected = [
+            "hello.txt",
+            ".",
+            "ads.bin",
+            "compressed.bin",
+            "dosname long.txt",
+            "list.bin",
+            "sparse.bin",
+            "sub",
+            "öl.txt",
+            "Üb.txt",
+        ];
+        assert_eq!(names, expected);
+        assert_eq!(listing[1].1, FileType::Parent);
+        assert_eq!(listing[2].1, FileType::File);
+        assert_eq!(listing[7].1, FileType::Directory);
+
+        let id = |path: &str| root.clone().lookup_path(path.as_bytes()).unwrap().id();
+        for name in expected {
+            assert!(root.lookup(name.as_bytes()).unwrap().is_some(), "{name}");
+        }
+        assert_eq!(id("DOSNAM~1.TXT"), HELLO);
+        assert_eq!(id("öl.txt"), HELLO);
+        assert_eq!(id("sub/inner.txt"), HELLO);
+        assert_eq!(id("sub"), SUB);
+        for name in ["HELLO.TXT", "stale.txt", "a", "zzz", "hello.tx", "öl.txt2", "ÖL.TXT"] {
+            assert!(root.lookup(name.as_bytes()).unwrap().is_none(), "{name}");
+        }
+        assert_eq!(
+            list(&root.lookup(b"sub").unwrap().unwrap()),
+            [(String::from("inner.txt"), FileType::File)]
+        );
+        assert!(root.open(1 << 32 | 1).is_err());
+        assert!(root.open(3 << 32 | 64).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! LZNT1 decompression of NTFS compression units.
+
+use super::le16;
+use ap_storage::{msg2err, Error};
+
+/// The size of the output of a chunk.
+const CHUNK: usize = 4096;
+
+/// The chunk header flag of compressed data.
+const COMPRESSED: u16 = 0x8000;
+
+/// Decompress the chunks of a unit.  Chunks that are shorter than 4K and the rest of the output are zero.
+pub(crate) fn decompress(input: &[u8], out: &mut [u8]) -> Result<(), Error> {
+    let (mut ip, mut op) = (0, 0);
+    while ip + 2 <= input.len() && op < out.len() {
+        let header = le16(input, ip);
+        if header == 0 {
+            break;
+        }
+        let size = (header & 0xfff) as usize + 1;
+        let chunk = input.get(ip + 2..ip + 2 + size).ok_or(msg2err!("truncated chunk"))?;
+        ip += 2 + size;
+        let end = core::cmp::min(op + CHUNK, out.len());
+        let dst = &mut out[op..end];
+        let n = match header & COMPRESSED {
+            0 => {
+                let raw = dst.get_mut(..size).ok_or(msg2err!("chunk too large"))?;
+                raw.copy_from_slice(chunk);
+                size
+            }
+            _ => chunk_decompress(chunk, dst)?,
+        };
+        dst[n..].fill(0);
+        op = end;
+    }
+    out[op..].fill(0);
+    Ok(())
+}
+
+/// Decompress a single chunk and return its size.
+fn chunk_decompress(chunk: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let (mut ip, mut op) = (0, 0);
+    while ip < chunk.len() {
+        let flags = chunk[ip];
+        ip += 1;
+        for bit in 0..8 {
+            if ip >= chunk.len() {
+                break;
+            }
+            if flags >> bit & 1 == 0 {
+                *out.get_mut(op).ok_or(msg2err!("chunk overflow"))? = chunk[ip];
+                ip += 1;
+                op += 1;
+                continue;
+            }
+            if ip + 2 > chunk.len() || op == 0 {
+                return Err(msg2err!("invalid token"));
+            }
+            let token = le16(chunk, ip);
+            ip += 2;
+
+            // the displacement gets more bits the further the output is
+            let mut lg = 0;
+            let mut x = op - 1;
+            while x >= 0x10 {
+                lg += 1;
+                x >>= 1;
+            }
+            let disp = (token >> (12 - lg)) as usize + 1;
+            let len = (token & (0xfff >> lg)) as usize + 3;
+            if disp > op || op + len > out.len() {
+                return Err(msg2err!("invalid token"));
+            }
+            // the copy may overlap with itself
+            for i in op..op + len {
+                out[i] = out[i - disp];
+            }
+            op += len;
+        }
+    }
+    Ok(op)
+}
@@ -0,0 +1,270 @@
+//! MFT records, their attributes and the runlists of non-resident attributes.
+
+use super::{le16, le32, le64};
+use ap_storage::{msg2err, Error};
+
+/// The distance of the update sequence numbers.
+const STRIDE: usize = 512;
+
+/// The end marker of the attributes.
+const END: u32 = 0xffff_ffff;
+
+/// The record number in a file reference.
+pub(crate) const RECORD_MASK: u64 = (1 << 48) - 1;
+
+/// Check the update sequence and restore the original last bytes of each sector.
+pub(crate) fn fixup(buf: &mut [u8], magic: &[u8; 4]) -> Result<(), Error> {
+    if &buf[..4] != magic {
+        return Err(msg2err!("invalid record magic"));
+    }
+    let (usa, count) = (le16(buf, 4) as usize, le16(buf, 6) as usize);
+    if count != buf.len() / STRIDE + 1 || usa + count * 2 > buf.len() || usa % 2 != 0 {
+        return Err(msg2err!("invalid update sequence"));
+    }
+    for i in 1..count {
+        let pos = i * STRIDE - 2;
+        if buf[pos..pos + 2] != buf[usa..usa + 2] {
+            return Err(msg2err!("torn record"));
+        }
+        buf.copy_within(usa + i * 2..usa + i * 2 + 2, pos);
+    }
+    Ok(())
+}
+
+/// A UTF-16 name of a file or an attribute.
+#[derive(Clone, Copy)]
+pub(crate) struct Name {
+    len: usize,
+    buf: [u16; 255],
+}
+
+impl Name {
+    pub(crate) const EMPTY: Self = Self { len: 0, buf: [0; 255] };
+
+    /// A name of ASCII characters.
+    pub(crate) const fn ascii(name: &[u8]) -> Self {
+        let mut res = Self::EMPTY;
+        while res.len < name.len() {
+            res.buf[res.len] = name[res.len] as u16;
+            res.len += 1;
+        }
+        res
+    }
+
+    /// Encode an UTF-8 name.
+    pub(crate) fn from_utf8(name: &[u8]) -> Option<Self> {
+        let mut res = Self::EMPTY;
+        for x in core::str::from_utf8(name).ok()?.encode_utf16() {
+            *res.buf.get_mut(res.len)? = x;
+            res.len += 1;
+        }
+        Some(res)
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u16] {
+        &self.buf[..self.len]
+    }
+
+    /// Whether the little-endian bytes hold the name.
+    pub(crate) fn matches(&self, bytes: &[u8]) -> bool {
+        bytes.len() == self.len * 2
+            && bytes
+                .chunks_exact(2)
+                .zip(self.as_slice())
+                .all(|(x, y)| u16::from_le_bytes([x[0], x[1]]) == *y)
+    }
+}
+
+impl core::fmt::Debug for Name {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        for x in char::decode_utf16(self.as_slice().iter().copied()) {
+            write!(fmt, "{}", x.unwrap_or(char::REPLACEMENT_CHARACTER))?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the UTF-8 encoding of a little-endian UTF-16 name into the buffer and return its full length.
+pub(crate) fn utf8(bytes: &[u8], buf: &mut [u8]) -> usize {
+    let units = bytes.chunks_exact(2).map(|x| u16::from_le_bytes([x[0], x[1]]));
+    let mut n = 0;
+    for x in char::decode_utf16(units) {
+        let mut tmp = [0; 4];
+        let x = x
+            .unwrap_or(char::REPLACEMENT_CHARACTER)
+            .encode_utf8(&mut tmp)
+            .as_bytes();
+        if let Some(dst) = buf.get_mut(n..n + x.len()) {
+            dst.copy_from_slice(x);
+        }
+        n += x.len();
+    }
+    n
+}
+
+/// An attribute in a record.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Attribute {
+    /// The position in the record and the length.
+    pub pos: usize,
+    pub len: usize,
+    pub typ: u32,
+    pub non_resident: bool,
+    /// The position and the length in bytes of the name.
+    pub name: (usize, usize),
+    pub flags: u16,
+    pub instance: u16,
+    /// The position and the length of a resident value.
+    pub value: (usize, usize),
+    pub lowest_vcn: u64,
+    pub highest_vcn: u64,
+    /// The position of the mapping pairs.
+    pub pairs: usize,
+    /// The compression unit as shift of the clusters.
+    pub unit: u8,
+    pub size: u64,
+    pub initialized: u64,
+}
+
+impl Attribute {
+    fn parse(buf: &[u8], pos: usize) -> Result<Self, Error> {
+        if pos + 16 > buf.len() {
+            return Err(msg2err!("truncated attribute"));
+        }
+        let len = le32(buf, pos + 4) as usize;
+        let non_resident = buf[pos + 8] != 0;
+        let name = (pos + le16(buf, pos + 10) as usize, buf[pos + 9] as usize * 2);
+        if len < if non_resident { 64 } else { 24 }
+            || len % 8 != 0
+            || pos + len > buf.len()
+            || name.0 + name.1 > pos + len
+        {
+            return Err(msg2err!("invalid attribute"));
+        }
+        let mut res = Self {
+            pos,
+            len,
+            typ: le32(buf, pos),
+            non_resident,
+            name,
+            flags: le16(buf, pos + 12),
+            instance: le16(buf, pos + 14),
+            ..Default::default()
+        };
+        if !non_resident {
+            res.value = (pos + le16(buf, pos + 20) as usize, le32(buf, pos + 16) as usize);
+            if res.value.0 + res.value.1 > pos + len {
+                return Err(msg2err!("invalid resident attribute"));
+            }
+            res.size = res.value.1 as u64;
+            res.initialized = res.size;
+            return Ok(res);
+        }
+        res.lowest_vcn = le64(buf, pos + 16);
+        res.highest_vcn = le64(buf, pos + 24);
+        res.pairs = pos + le16(buf, pos + 32) as usize;
+        res.unit = buf[pos + 34];
+        res.size = le64(buf, pos + 48);
+        res.initialized = le64(buf, pos + 56);
+        if res.pairs >= pos + len {
+            return Err(msg2err!("invalid non-resident attribute"));
+        }
+        Ok(res)
+    }
+
+    /// The mapping pairs in the record.
+    pub(crate) fn pairs<'b>(&self, buf: &'b [u8]) -> &'b [u8] {
+        &buf[self.pairs..self.pos + self.len]
+    }
+
+    /// The resident value in the record.
+    pub(crate) fn value<'b>(&self, buf: &'b [u8]) -> &'b [u8] {
+        &buf[self.value.0..self.value.0 + self.value.1]
+    }
+
+    pub(crate) fn name<'b>(&self, buf: &'b [u8]) -> &'b [u8] {
+        &buf[self.name.0..self.name.0 + self.name.1]
+    }
+}
+
+/// Iterate over the attributes of a record.
+pub(crate) struct Attributes<'b> {
+    buf: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Attributes<'b> {
+    pub(crate) fn new(buf: &'b [u8]) -> Result<Self, Error> {
+        let (pos, used) = (le16(buf, 20) as usize, le32(buf, 24) as usize);
+        if used > buf.len() || pos + 4 > used {
+            return Err(msg2err!("invalid record"));
+        }
+        Ok(Self { buf: &buf[..used], pos })
+    }
+}
+
+impl Iterator for Attributes<'_> {
+    type Item = Result<Attribute, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos + 4 > self.buf.len() || le32(self.buf, self.pos) == END {
+            return None;
+        }
+        let res = Attribute::parse(self.buf, self.pos);
+        match &res {
+            Ok(attr) => self.pos += attr.len,
+            Err(_) => self.pos = self.buf.len(),
+        }
+        Some(res)
+    }
+}
+
+/// A run of clusters.  Sparse runs have no position on the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Run {
+    pub vcn: u64,
+    pub lcn: Option<u64>,
+    pub len: u64,
+}
+
+impl Run {
+    pub fn contains(&self, vcn: u64) -> bool {
+        vcn.wrapping_sub(self.vcn) < self.len
+    }
+}
+
+/// Decode the mapping pairs that start at a VCN and return the run that holds another.
+pub(crate) fn find_run(pairs: &[u8], mut vcn: u64, target: u64) -> Result<Option<Run>, Error> {
+    let (mut pos, mut lcn) = (0, 0i64);
+    let int = |bytes: &[u8], signed: bool| -> i64 {
+        let mut buf = [0; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        if signed && bytes.last().is_some_and(|x| x & 0x80 != 0) {
+            buf[bytes.len()..].fill(0xff);
+        }
+        i64::from_le_bytes(buf)
+    };
+    while let Some(header) = pairs.get(pos).filter(|x| **x != 0) {
+        let (length, offset) = ((header & 0xf) as usize, (header >> 4) as usize);
+        let bytes = pairs
+            .get(pos + 1..pos + 1 + length + offset)
+            .ok_or(msg2err!("truncated runlist"))?;
+        if length == 0 || length > 8 || offset > 8 {
+            return Err(msg2err!("invalid runlist"));
+        }
+        let len = int(&bytes[..length], false) as u64;
+        let run_lcn = match offset {
+            0 => None,
+            _ => {
+                lcn = lcn.wrapping_add(int(&bytes[length..], true));
+                Some(u64::try_from(lcn).map_err(|_| msg2err!("invalid runlist"))?)
+            }
+        };
+        let run = Run { vcn, lcn: run_lcn, len };
+        if run.contains(target) {
+            return Ok(Some(run));
+        }
+        vcn = vcn.checked_add(len).ok_or(msg2err!("invalid runlist"))?;
+        pos += 1 + length + offset;
+    }
+    Ok(None)
+}
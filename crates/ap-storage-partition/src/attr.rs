@@ -1,5 +1,5 @@
 use crate::file::PartitionFile;
-use ap_storage::attr::{new_attr, Attributes, Value, FTYPE, ID, SIZE};
+use ap_storage::attr::{attr_meta, new_attr, Attributes, Meta, Value, FTYPE, ID, SIZE};
 use ap_util_slice_writer::*;
 
 new_attr!(BOOT, Bool, "Boot flag.");
@@ -24,7 +24,7 @@ impl<'a> Attributes<'a> for Attr<'a> {
             FTYPE => {
                 let mut value = SliceWriter(buf, 0);
                 write!(value, "{:?}", self.file.ftype()).ok()?;
-                Value::Raw(value.1)
+                Value::Str(value.1)
             }
             BOOT => (self.file.drive & 0x80 != 0).into(),
             ID => self.file.id.into(),
@@ -34,4 +34,8 @@ impl<'a> Attributes<'a> for Attr<'a> {
             _ => return None,
         })
     }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(name, [BOOT, FTYPE, ID, OFFSET, SIZE, TYP])
+    }
 }
@@ -1,7 +1,7 @@
 //! File implementation for partitions.
 
 use crate::{attr::Attr, dir::PartitionDir, Partition};
-use ap_storage::{file::File, file::FileType, msg2err, Error, Offset, Read, ReadExt};
+use ap_storage::{file::File, file::FileType, msg2err, Error, Offset, Read, ReadExt, SubDisk};
 
 pub struct PartitionFile<'a> {
     pub(crate) disk: &'a dyn Read,
@@ -36,11 +36,7 @@ impl<'a> PartitionFile<'a> {
 
 impl Read for PartitionFile<'_> {
     fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Error> {
-        if offset >= self.offset + self.len {
-            return Ok(0);
-        }
-        let maxn = core::cmp::min(self.offset + self.len - offset, buf.len() as u64) as usize;
-        self.disk.read_bytes(self.offset + offset, &mut buf[..maxn])
+        SubDisk::new(self.disk, self.offset, self.len).read_bytes(offset, buf)
     }
 }
 
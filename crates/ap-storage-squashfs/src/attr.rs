@@ -0,0 +1,70 @@
+//! File attributes for SquashFS.
+
+use super::file::File;
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::Read;
+use ap_util_slice_writer::*;
+
+new_attr!(NLINKS, U64, "Number of hard-links to this file.");
+
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
+}
+
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            NLINKS,
+            attr::FTYPE,
+            attr::GID,
+            attr::ID,
+            attr::MODE,
+            attr::MTIME,
+            attr::RDEV,
+            attr::SIZE,
+            attr::UID,
+        ]
+        .iter()
+    }
+}
+
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let inode = &self.file.inode;
+        Some(match name {
+            NLINKS => (inode.nlink as u64).into(),
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            attr::GID => (self.file.fs.id(inode.gid).ok()? as u64).into(),
+            attr::ID => (inode.number as u64).into(),
+            attr::MODE => (inode.mode as u64 & 0o7777).into(),
+            attr::MTIME => Value::Time(inode.mtime as i64 * 1_000_000_000),
+            attr::RDEV if matches!(inode.typ, 4 | 5) => inode.rdev().into(),
+            attr::SIZE => inode.size.into(),
+            attr::UID => (self.file.fs.id(inode.uid).ok()? as u64).into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                NLINKS,
+                attr::FTYPE,
+                attr::GID,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::RDEV,
+                attr::SIZE,
+                attr::UID,
+            ]
+        )
+    }
+}
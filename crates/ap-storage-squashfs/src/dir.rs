@@ -0,0 +1,83 @@
+//! Directory iteration for SquashFS.
+
+use super::{file::File, inode, MetaPos};
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    msg2err, Error, Read,
+};
+
+/// The most entries after a header.
+const MAX_ENTRIES: u32 = 256;
+
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
+    pos: MetaPos,
+    /// The bytes left in the listing.
+    left: usize,
+    /// The entries left after the last header.
+    count: u32,
+    /// The inode block and the base inode number of the header.
+    start: u32,
+    base: u32,
+}
+
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Self {
+        let inode = &file.inode;
+        Self {
+            file,
+            pos: MetaPos {
+                block: file.fs.sb.directory_table + inode.start,
+                offset: inode.offset as usize,
+            },
+            // the size includes the implicit `.` and `..` entries
+            left: inode.size.saturating_sub(3) as usize,
+            count: 0,
+            start: 0,
+            base: 0,
+        }
+    }
+
+    /// Read from the listing.
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        self.left = self
+            .left
+            .checked_sub(buf.len())
+            .ok_or(msg2err!("truncated directory"))?;
+        self.pos = self.file.fs.read_meta(self.pos, buf)?;
+        Ok(())
+    }
+}
+
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        if self.count == 0 {
+            let mut header = [0; 12];
+            self.read(&mut header)?;
+            let [count, start, base] = [0, 4, 8].map(|x| super::le32(&header, x));
+            if count >= MAX_ENTRIES {
+                return Err(msg2err!("invalid directory header"));
+            }
+            (self.count, self.start, self.base) = (count + 1, start, base);
+        }
+        self.count -= 1;
+
+        let mut entry = [0; 8];
+        self.read(&mut entry)?;
+        let u16 = |x: usize| u16::from_le_bytes([entry[x], entry[x + 1]]);
+        let mut buf = [0; 256];
+        let nlen = u16(6) as usize + 1;
+        self.read(&mut buf[..nlen])?;
+        let n = core::cmp::min(nlen, name.len());
+        name[..n].copy_from_slice(&buf[..n]);
+        Ok(Some(DirEntry {
+            offset: (self.start as u64) << 16 | u16(0) as u64,
+            id: self.base.wrapping_add(u16(2) as i16 as u32) as u64,
+            nlen,
+            typ: inode::file_type(u16(4)),
+        }))
+    }
+}
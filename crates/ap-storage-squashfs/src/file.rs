@@ -0,0 +1,207 @@
+//! Files on SquashFS.
+
+use super::{attr::Attr, dir::Dir, inode::NONE, Inode, MetaPos, SquashFs};
+use ap_storage::{file::FileType, msg2err, Error, Offset, Read};
+use core::cell::RefCell;
+
+/// The prefixes of the xattr name spaces.
+const PREFIXES: [&[u8]; 3] = [b"user.", b"trusted.", b"security."];
+
+/// The longest xattr name including its prefix.
+const XATTR_NAME_MAX: usize = 9 + 255;
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a SquashFs<'a, D>,
+    pub(crate) inode: Inode,
+    /// The index of a block, its disk position and the position of its size in the block list.
+    cache: RefCell<(u64, Offset, MetaPos)>,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            inode: self.inode,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("fs", &self.fs)
+            .field("inode", &self.inode)
+            .finish()
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    pub(crate) fn new(fs: &'a SquashFs<'a, D>, inode: Inode) -> Result<Self, Error> {
+        Ok(Self {
+            fs,
+            inode,
+            cache: RefCell::new((0, inode.start, inode.tail)),
+        })
+    }
+
+    pub fn inode(&self) -> &Inode {
+        &self.inode
+    }
+
+    pub fn ftype(&self) -> FileType {
+        self.inode.file_type()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.ftype() == FileType::Directory
+    }
+
+    /// The number of full blocks of a regular file.
+    fn blocks(&self) -> u64 {
+        let block_size = self.fs.sb.block_size as u64;
+        match self.inode.fragment {
+            NONE => self.inode.size.div_ceil(block_size),
+            _ => self.inode.size / block_size,
+        }
+    }
+
+    /// Call the function for every extended attribute.
+    ///
+    /// The iteration stops early if the function returns false.
+    pub fn xattrs(&self, f: &mut dyn FnMut(&[u8]) -> bool) -> Result<(), Error> {
+        self.walk_xattrs(&mut |name, _, _| f(name))
+    }
+
+    /// Get the value of an extended attribute and return its full length.
+    pub fn xattr(&self, name: &[u8], buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        let mut value = None;
+        self.walk_xattrs(&mut |key, pos, size| {
+            if key != name {
+                return true;
+            }
+            value = Some((pos, size));
+            false
+        })?;
+        let Some((pos, size)) = value else {
+            return Ok(None);
+        };
+        let n = core::cmp::min(buf.len(), size as usize);
+        self.fs.read_meta(pos, &mut buf[..n])?;
+        Ok(Some(size as usize))
+    }
+
+    /// Walk the xattrs with their full name and the position and size of the value.
+    fn walk_xattrs(&self, f: &mut dyn FnMut(&[u8], MetaPos, u32) -> bool) -> Result<(), Error> {
+        if self.inode.xattr == NONE {
+            return Ok(());
+        }
+        let (mut pos, count) = self.fs.xattr(self.inode.xattr)?;
+        let mut name = [0; XATTR_NAME_MAX];
+        for _ in 0..count {
+            let mut header = [0; 4];
+            pos = self.fs.read_meta(pos, &mut header)?;
+            let typ = u16::from_le_bytes([header[0], header[1]]);
+            let prefix = PREFIXES
+                .get(typ as usize & 0xff)
+                .ok_or(msg2err!("unknown xattr prefix"))?;
+            let nlen = prefix.len() + u16::from_le_bytes([header[2], header[3]]) as usize;
+            if nlen > name.len() {
+                return Err(msg2err!("xattr name too long"));
+            }
+            name[..prefix.len()].copy_from_slice(prefix);
+            pos = self.fs.read_meta(pos, &mut name[prefix.len()..nlen])?;
+
+            let mut size = [0; 4];
+            pos = self.fs.read_meta(pos, &mut size)?;
+            let size = u32::from_le_bytes(size);
+            let (value, size) = if typ & 0x100 != 0 {
+                // the value is stored out of line
+                let mut reference = [0; 8];
+                pos = self.fs.read_meta(pos, &mut reference)?;
+                let value = self.fs.xattr_pos(u64::from_le_bytes(reference))?;
+                let mut size = [0; 4];
+                (self.fs.read_meta(value, &mut size)?, u32::from_le_bytes(size))
+            } else {
+                let value = pos;
+                pos.offset += size as usize;
+                (value, size)
+            };
+            if !f(&name[..nlen], value, size) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.is_dir() {
+            return Some(Dir::new(self));
+        }
+        None
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        self.fs.open(offset)
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if offset >= self.inode.size {
+            return Ok(0);
+        }
+        let max_n = core::cmp::min(buf.len() as Offset, self.inode.size - offset) as usize;
+        let buf = &mut buf[..max_n];
+        match self.ftype() {
+            FileType::SymLink => {
+                let pos = MetaPos {
+                    offset: self.inode.tail.offset + offset as usize,
+                    ..self.inode.tail
+                };
+                self.fs.read_meta(pos, buf)?;
+                return Ok(max_n);
+            }
+            FileType::File => {}
+            _ => return Ok(0),
+        }
+
+        let block_size = self.fs.sb.block_size as u64;
+        let index = offset / block_size;
+        let within = (offset % block_size) as usize;
+        if index >= self.blocks() {
+            let (pos, word) = self.fs.fragment(self.inode.fragment)?;
+            return self.fs.read_block(pos, word, self.inode.offset as usize + within, buf);
+        }
+
+        // rewind or follow the block list
+        let mut cache = self.cache.borrow_mut();
+        if index < cache.0 {
+            *cache = (0, self.inode.start, self.inode.tail);
+        }
+        let mut word = [0; 4];
+        loop {
+            let next = self.fs.read_meta(cache.2, &mut word)?;
+            if cache.0 == index {
+                break;
+            }
+            *cache = (
+                cache.0 + 1,
+                cache.1 + (u32::from_le_bytes(word) & 0xff_ffff) as Offset,
+                next,
+            );
+        }
+        self.fs.read_block(cache.1, u32::from_le_bytes(word), within, buf)
+    }
+}
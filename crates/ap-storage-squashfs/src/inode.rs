@@ -0,0 +1,145 @@
+//! Inodes in the inode table.
+
+use super::{le32, le64, MetaPos, SquashFs};
+use ap_storage::{file::FileType, msg2err, Error, Read};
+
+/// No fragment or no xattrs.
+pub(crate) const NONE: u32 = u32::MAX;
+
+/// An inode with the fields of all types.
+#[derive(Debug, Clone, Copy)]
+pub struct Inode {
+    /// The basic type from 1 to 7.
+    pub typ: u16,
+    pub mode: u16,
+    pub uid: u16,
+    pub gid: u16,
+    pub mtime: u32,
+    pub number: u32,
+    pub nlink: u32,
+    /// The file size, the directory listing size plus three or the symlink target size.
+    pub size: u64,
+    /// The first data block or the block of the listing relative to the directory table.
+    pub start: u64,
+    /// The offset of the listing in its block or of the tail end in the fragment.
+    pub offset: u32,
+    pub fragment: u32,
+    pub rdev: u32,
+    pub xattr: u32,
+    /// The block list or the symlink target.
+    pub tail: MetaPos,
+}
+
+impl Inode {
+    /// Read the inode at a position.
+    pub fn read<D: Read + ?Sized>(fs: &SquashFs<'_, D>, pos: MetaPos) -> Result<Self, Error> {
+        let mut buf = [0; 16 + 40];
+        let pos = fs.read_meta(pos, &mut buf[..16])?;
+        let u16 = |buf: &[u8], x: usize| u16::from_le_bytes([buf[x], buf[x + 1]]);
+        let typ = u16(&buf, 0);
+        let size = match typ {
+            1 | 2 => 16,
+            3..=5 | 10 | 13 | 14 => 8,
+            6 | 7 => 4,
+            8 => 24,
+            9 => 40,
+            11 | 12 => 12,
+            _ => return Err(msg2err!("invalid inode type")),
+        };
+        let tail = fs.read_meta(pos, &mut buf[16..16 + size])?;
+        let mut res = Self {
+            typ: if typ > 7 { typ - 7 } else { typ },
+            mode: u16(&buf, 2),
+            uid: u16(&buf, 4),
+            gid: u16(&buf, 6),
+            mtime: le32(&buf, 8),
+            number: le32(&buf, 12),
+            nlink: le32(&buf, 16),
+            size: 0,
+            start: 0,
+            offset: 0,
+            fragment: NONE,
+            rdev: 0,
+            xattr: NONE,
+            tail,
+        };
+        let buf = &buf[16..];
+        match typ {
+            1 => {
+                res.start = le32(buf, 0) as u64;
+                res.nlink = le32(buf, 4);
+                res.size = u16(buf, 8) as u64;
+                res.offset = u16(buf, 10) as u32;
+            }
+            8 => {
+                res.size = le32(buf, 4) as u64;
+                res.start = le32(buf, 8) as u64;
+                res.offset = u16(buf, 18) as u32;
+                res.xattr = le32(buf, 20);
+            }
+            2 => {
+                res.start = le32(buf, 0) as u64;
+                res.fragment = le32(buf, 4);
+                res.offset = le32(buf, 8);
+                res.size = le32(buf, 12) as u64;
+                res.nlink = 1;
+            }
+            9 => {
+                res.start = le64(buf, 0);
+                res.size = le64(buf, 8);
+                res.nlink = le32(buf, 24);
+                res.fragment = le32(buf, 28);
+                res.offset = le32(buf, 32);
+                res.xattr = le32(buf, 36);
+            }
+            3 | 10 => {
+                res.size = le32(buf, 4) as u64;
+                if typ == 10 {
+                    let mut xattr = [0; 4];
+                    let pos = MetaPos {
+                        offset: tail.offset + res.size as usize,
+                        ..tail
+                    };
+                    fs.read_meta(pos, &mut xattr)?;
+                    res.xattr = u32::from_le_bytes(xattr);
+                }
+            }
+            4 | 5 | 11 | 12 => {
+                res.rdev = le32(buf, 4);
+                if typ > 7 {
+                    res.xattr = le32(buf, 8);
+                }
+            }
+            _ => {
+                if typ > 7 {
+                    res.xattr = le32(buf, 4);
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    pub fn file_type(&self) -> FileType {
+        file_type(self.typ)
+    }
+
+    /// The device number as `major << 32 | minor`.
+    pub fn rdev(&self) -> u64 {
+        let dev = self.rdev as u64;
+        (dev & 0xfff00) << 24 | (dev & 0xff) | (dev >> 12) & 0xfff00
+    }
+}
+
+/// The file type of a basic inode type.
+pub(crate) fn file_type(typ: u16) -> FileType {
+    match typ {
+        1 => FileType::Directory,
+        2 => FileType::File,
+        3 => FileType::SymLink,
+        4 => FileType::BlockDevice,
+        5 => FileType::CharDevice,
+        6 => FileType::Fifo,
+        7 => FileType::Socket,
+        _ => FileType::Unknown,
+    }
+}
@@ -0,0 +1,874 @@
+//! Read SquashFS images.
+//!
+//! - version 4.0 superblock, inode, directory, fragment and id tables
+//! - gzip and zstd compression, `xz` and `lz4` behind features
+//! - sparse blocks, fragments and hard links
+//! - extended attributes via [`file::File::xattrs`]
+//!
+//! Decompression works in a scratch buffer provided by the caller, which also caches the last data and metadata
+//! block.  The offset of a directory entry is the reference of its inode.
+
+#![no_std]
+
+use ap_storage::{msg2err, Error, FileSystem, Offset, Read, ReadExt, ScratchBuf};
+use core::cell::Cell;
+
+mod attr;
+mod dir;
+pub mod file;
+mod inode;
+#[cfg(feature = "lz4")]
+mod lz4;
+#[cfg(feature = "xz")]
+mod xz;
+
+pub use inode::Inode;
+
+const MAGIC: u32 = 0x7371_7368;
+
+/// The uncompressed size of a metadata block.
+const META_SIZE: usize = 8192;
+
+/// The largest block size.
+pub const MAX_BLOCK_SIZE: u32 = 1 << 20;
+
+/// The scratch space that is sufficient for any image.
+pub const SCRATCH_SIZE: usize = scratch_size(MAX_BLOCK_SIZE);
+
+/// The scratch space needed for images with the block size.
+pub const fn scratch_size(block_size: u32) -> usize {
+    let block = block_size as usize;
+    let input = if block > META_SIZE { block } else { META_SIZE };
+    let literals = if input > ap_util_zstd::MAX_BLOCK_SIZE {
+        ap_util_zstd::MAX_BLOCK_SIZE
+    } else {
+        input
+    };
+    block + META_SIZE + input + literals
+}
+
+/// The compression algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compressor {
+    Gzip,
+    Lzma,
+    Lzo,
+    Xz,
+    Lz4,
+    Zstd,
+    Unknown(u16),
+}
+
+impl From<u16> for Compressor {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => Self::Gzip,
+            2 => Self::Lzma,
+            3 => Self::Lzo,
+            4 => Self::Xz,
+            5 => Self::Lz4,
+            6 => Self::Zstd,
+            x => Self::Unknown(x),
+        }
+    }
+}
+
+/// The superblock.
+#[derive(Debug, Clone, Copy)]
+pub struct Superblock {
+    pub inode_count: u32,
+    pub mod_time: u32,
+    pub block_size: u32,
+    pub frag_count: u32,
+    pub compressor: Compressor,
+    pub flags: u16,
+    pub id_count: u16,
+    pub root_inode: u64,
+    pub bytes_used: u64,
+    pub id_table: u64,
+    pub xattr_table: u64,
+    pub inode_table: u64,
+    pub directory_table: u64,
+    pub fragment_table: u64,
+}
+
+impl Superblock {
+    /// Parse and check the superblock.
+    pub fn parse(buf: &[u8; 96]) -> Result<Self, Error> {
+        let u16 = |pos| u16::from_le_bytes([buf[pos], buf[pos + 1]]);
+        if le32(buf, 0) != MAGIC {
+            return Err(msg2err!("no squashfs"));
+        }
+        if u16(28) != 4 || u16(30) != 0 {
+            return Err(msg2err!("unsupported version"));
+        }
+        let res = Self {
+            inode_count: le32(buf, 4),
+            mod_time: le32(buf, 8),
+            block_size: le32(buf, 12),
+            frag_count: le32(buf, 16),
+            compressor: u16(20).into(),
+            flags: u16(24),
+            id_count: u16(26),
+            root_inode: le64(buf, 32),
+            bytes_used: le64(buf, 40),
+            id_table: le64(buf, 48),
+            xattr_table: le64(buf, 56),
+            inode_table: le64(buf, 64),
+            directory_table: le64(buf, 72),
+            fragment_table: le64(buf, 80),
+        };
+        if !res.block_size.is_power_of_two()
+            || !(4096..=MAX_BLOCK_SIZE).contains(&res.block_size)
+            || 1 << u16(22) != res.block_size
+        {
+            return Err(msg2err!("block size"));
+        }
+        Ok(res)
+    }
+}
+
+/// A position in a metadata table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetaPos {
+    /// The disk offset of the metadata block.
+    pub block: Offset,
+    /// The offset in the uncompressed block.
+    pub offset: usize,
+}
+
+/// The parts of the scratch buffer.
+struct Parts<'s> {
+    data: &'s mut [u8],
+    meta: &'s mut [u8],
+    /// Compressed input.
+    input: &'s mut [u8],
+    /// The literals of zstd.
+    literals: &'s mut [u8],
+}
+
+impl<'s> Parts<'s> {
+    fn new(buf: &'s mut [u8], block_size: u32) -> Self {
+        let block_size = block_size as usize;
+        let input = core::cmp::max(block_size, META_SIZE);
+        let (data, rest) = buf.split_at_mut(block_size);
+        let (meta, rest) = rest.split_at_mut(META_SIZE);
+        let (input, rest) = rest.split_at_mut(input);
+        let literals = &mut rest[..core::cmp::min(input.len(), ap_util_zstd::MAX_BLOCK_SIZE)];
+        Parts {
+            data,
+            meta,
+            input,
+            literals,
+        }
+    }
+}
+
+/// A SquashFS filesystem.
+pub struct SquashFs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+    sb: Superblock,
+    scratch: ScratchBuf<'a>,
+    /// The position and length of the data block in the scratch buffer.
+    data_key: Cell<Option<(Offset, usize)>>,
+    /// The position and length of the metadata block in the scratch buffer and the position of the next one.
+    meta_key: Cell<Option<(Offset, usize, Offset)>>,
+}
+
+impl<D: ?Sized> core::fmt::Debug for SquashFs<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(
+            fmt,
+            "SquashFs({:?}, bs {}, inodes {})",
+            self.sb.compressor, self.sb.block_size, self.sb.inode_count
+        )
+    }
+}
+
+impl<'a, D: Read + ?Sized> SquashFs<'a, D> {
+    /// Mount the filesystem with a scratch buffer of at least [`scratch_size`] bytes.
+    pub fn new(disk: &'a D, scratch: &'a mut [u8]) -> Result<Self, Error> {
+        let sb = Superblock::parse(&(&disk as &dyn Read).read_object(0)?)?;
+        match sb.compressor {
+            Compressor::Gzip | Compressor::Zstd => {}
+            #[cfg(feature = "lz4")]
+            Compressor::Lz4 => {}
+            #[cfg(feature = "xz")]
+            Compressor::Xz => {}
+            _ => return Err(msg2err!("unsupported compressor")),
+        }
+        if scratch.len() < scratch_size(sb.block_size) {
+            return Err(msg2err!("scratch buffer too small"));
+        }
+        Ok(Self {
+            disk,
+            sb,
+            scratch: ScratchBuf::new(scratch),
+            data_key: Cell::new(None),
+            meta_key: Cell::new(None),
+        })
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.sb
+    }
+
+    /// Decompress a block into the output.
+    fn decompress(&self, src: &[u8], out: &mut [u8], literals: &mut [u8]) -> Result<usize, Error> {
+        match self.sb.compressor {
+            Compressor::Gzip => ap_util_inflate::inflate_zlib(&mut &src[..], out),
+            Compressor::Zstd => ap_util_zstd::decompress(src, out, literals),
+            #[cfg(feature = "lz4")]
+            Compressor::Lz4 => lz4::decompress(src, out),
+            #[cfg(feature = "xz")]
+            Compressor::Xz => xz::decompress(src, out),
+            _ => Err(msg2err!("unsupported compressor")),
+        }
+    }
+
+    /// Read from the metadata starting at a position and return the position after it.
+    pub fn read_meta(&self, mut pos: MetaPos, out: &mut [u8]) -> Result<MetaPos, Error> {
+        let mut scratch = self.scratch.borrow_mut()?;
+        let mut done = 0;
+        while done < out.len() {
+            let (len, next) = self.load_meta(&mut scratch, pos.block)?;
+            if pos.offset >= len {
+                pos = MetaPos {
+                    block: next,
+                    offset: pos.offset - len,
+                };
+                continue;
+            }
+            let n = core::cmp::min(out.len() - done, len - pos.offset);
+            let meta = Parts::new(&mut scratch, self.sb.block_size).meta;
+            out[done..done + n].copy_from_slice(&meta[pos.offset..pos.offset + n]);
+            done += n;
+            pos.offset += n;
+        }
+        Ok(pos)
+    }
+
+    /// Load a metadata block into the cache and return its length and the position of the next block.
+    fn load_meta(&self, scratch: &mut [u8], block: Offset) -> Result<(usize, Offset), Error> {
+        if let Some((key, len, next)) = self.meta_key.get() {
+            if key == block {
+                return Ok((len, next));
+            }
+        }
+        self.meta_key.set(None);
+        let header: [u8; 2] = self.disk().read_object(block)?;
+        let header = u16::from_le_bytes(header);
+        let size = (header & 0x7fff) as usize;
+        let next = block + 2 + size as Offset;
+        if size == 0 || size > META_SIZE || next > self.sb.bytes_used {
+            return Err(msg2err!("invalid metadata block"));
+        }
+        let Parts {
+            meta, input, literals, ..
+        } = Parts::new(scratch, self.sb.block_size);
+        let len = if header & 0x8000 != 0 {
+            self.disk().read_exact(block + 2, &mut meta[..size])?;
+            size
+        } else {
+            self.disk().read_exact(block + 2, &mut input[..size])?;
+            self.decompress(&input[..size], meta, literals)?
+        };
+        self.meta_key.set(Some((block, len, next)));
+        Ok((len, next))
+    }
+
+    /// Copy from a data block at a disk position with its size word.
+    ///
+    /// Uncompressed blocks are read directly, sparse blocks are zero.
+    pub(crate) fn read_block(&self, pos: Offset, word: u32, offset: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        let size = (word & 0xff_ffff) as usize;
+        if size > self.sb.block_size as usize {
+            return Err(msg2err!("invalid block size"));
+        }
+        if size == 0 {
+            let n = core::cmp::min(buf.len(), (self.sb.block_size as usize).saturating_sub(offset));
+            buf[..n].fill(0);
+            return Ok(n);
+        }
+        if word & (1 << 24) != 0 {
+            let n = core::cmp::min(buf.len(), size.saturating_sub(offset));
+            self.disk().read_exact(pos + offset as Offset, &mut buf[..n])?;
+            return Ok(n);
+        }
+
+        let mut scratch = self.scratch.borrow_mut()?;
+        let len = match self.data_key.get() {
+            Some((key, len)) if key == pos => len,
+            _ => {
+                self.data_key.set(None);
+                let Parts {
+                    data, input, literals, ..
+                } = Parts::new(&mut scratch, self.sb.block_size);
+                self.disk().read_exact(pos, &mut input[..size])?;
+                let len = self.decompress(&input[..size], data, literals)?;
+                self.data_key.set(Some((pos, len)));
+                len
+            }
+        };
+        let n = core::cmp::min(buf.len(), len.saturating_sub(offset));
+        buf[..n].copy_from_slice(&Parts::new(&mut scratch, self.sb.block_size).data[offset..offset + n]);
+        Ok(n)
+    }
+
+    /// Lookup an entry in one of the tables that are indexed by a list of metadata blocks.
+    fn table_entry(&self, table: Offset, index: u64, size: usize, out: &mut [u8]) -> Result<(), Error> {
+        let per_block = (META_SIZE / size) as u64;
+        let block: [u8; 8] = self.disk().read_object(table + 8 * (index / per_block))?;
+        let pos = MetaPos {
+            block: u64::from_le_bytes(block),
+            offset: (index % per_block) as usize * size,
+        };
+        self.read_meta(pos, &mut out[..size])?;
+        Ok(())
+    }
+
+    /// Lookup a user or group id.
+    pub fn id(&self, index: u16) -> Result<u32, Error> {
+        if index >= self.sb.id_count {
+            return Err(msg2err!("id out of range"));
+        }
+        let mut buf = [0; 4];
+        self.table_entry(self.sb.id_table, index as u64, 4, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    /// The disk position and size word of a fragment block.
+    pub(crate) fn fragment(&self, index: u32) -> Result<(Offset, u32), Error> {
+        if index >= self.sb.frag_count {
+            return Err(msg2err!("fragment out of range"));
+        }
+        let mut buf = [0; 16];
+        self.table_entry(self.sb.fragment_table, index as u64, 16, &mut buf)?;
+        Ok((le64(&buf, 0), le32(&buf, 8)))
+    }
+
+    /// The position of the key-value pairs of an xattr index and their number.
+    pub(crate) fn xattr(&self, index: u32) -> Result<(MetaPos, u32), Error> {
+        let header: [u8; 16] = self.disk().read_object(self.sb.xattr_table)?;
+        if index >= le32(&header, 8) {
+            return Err(msg2err!("xattr out of range"));
+        }
+        let mut buf = [0; 16];
+        self.table_entry(self.sb.xattr_table + 16, index as u64, 16, &mut buf)?;
+        Ok((self.xattr_pos(le64(&buf, 0))?, le32(&buf, 8)))
+    }
+
+    /// Resolve a reference into the xattr key-value pairs.
+    pub(crate) fn xattr_pos(&self, reference: u64) -> Result<MetaPos, Error> {
+        let header: [u8; 16] = self.disk().read_object(self.sb.xattr_table)?;
+        Ok(MetaPos {
+            block: le64(&header, 0) + (reference >> 16),
+            offset: (reference & 0xffff) as usize,
+        })
+    }
+
+    /// The position of an inode reference.
+    pub fn inode_pos(&self, reference: u64) -> MetaPos {
+        MetaPos {
+            block: self.sb.inode_table + (reference >> 16),
+            offset: (reference & 0xffff) as usize,
+        }
+    }
+
+    /// Open a file by its inode reference.
+    pub fn open(&'a self, reference: u64) -> Result<file::File<'a, D>, Error> {
+        file::File::new(self, Inode::read(self, self.inode_pos(reference))?)
+    }
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for SquashFs<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        self.open(self.sb.root_inode)
+    }
+}
+
+/// A little-endian u32 at the position.
+pub(crate) fn le32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+/// A little-endian u64 at the position.
+pub(crate) fn le64(buf: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{
+        attr::{self, Attributes, Value},
+        file::{File, FileType},
+    };
+    use ap_storage_memory::ReadSlice;
+    use ap_storage_test_util::*;
+    use std::{os::unix::fs::symlink, path::Path, process::Command, string::String, vec::Vec};
+
+    /// Build an image with mksquashfs.  Returns None if the tool is missing.
+    fn mksquashfs(src: &Path, comp: &str) -> Option<Vec<u8>> {
+        let name = src.with_extension("sqfs");
+        let status = Command::new("mksquashfs")
+            .arg(src)
+            .arg(&name)
+            .args(["-noappend", "-quiet", "-no-progress", "-b", "4096", "-comp", comp])
+            .status()
+            .ok()?;
+        assert!(status.success());
+        let data = std::fs::read(&name).unwrap();
+        std::fs::remove_file(name).unwrap();
+        Some(data)
+    }
+
+    /// Compress the data with a command line tool.  Returns None if the tool is missing.
+    #[cfg(any(feature = "xz", feature = "lz4"))]
+    fn compress(tool: &str, args: &[&str], data: &[u8]) -> Option<Vec<u8>> {
+        use std::{io::Write, process::Stdio};
+        let mut child = Command::new(tool)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let mut stdin = child.stdin.take()?;
+        let data = data.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&data));
+        let output = child.wait_with_output().ok()?;
+        writer.join().unwrap().ok()?;
+        assert!(output.status.success());
+        Some(output.stdout)
+    }
+
+    /// Compressible data with some noise.
+    #[cfg(any(feature = "xz", feature = "lz4"))]
+    fn sample(len: usize) -> Vec<u8> {
+        let mut x = 1u32;
+        (0..len)
+            .map(|i| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                match i % 7 {
+                    0 => (x >> 24) as u8,
+                    _ => b"squashfs"[i / 3 % 8],
+                }
+            })
+            .collect()
+    }
+
+    const BS: usize = 4096;
+
+    /// The start of the data blocks after the superblock.
+    const DATA: usize = 96;
+
+    /// An uncompressed metadata block.
+    fn meta(data: &[u8]) -> Vec<u8> {
+        [&(data.len() as u16 | 0x8000).to_le_bytes()[..], data].concat()
+    }
+
+    /// A zlib stream with a single stored block.
+    fn stored(data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &x| {
+            let a = (a + x as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        [
+            &[0x78, 0x01, 0x01][..],
+            &len.to_le_bytes(),
+            &(!len).to_le_bytes(),
+            data,
+            &(b << 16 | a).to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    /// An inode with the common header.
+    fn inode(typ: u16, mode: u16, uid: u16, number: u32, rest: &[&[u8]]) -> Vec<u8> {
+        let header = [
+            &typ.to_le_bytes()[..],
+            &mode.to_le_bytes(),
+            &uid.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &1_700_000_000u32.to_le_bytes(),
+            &number.to_le_bytes(),
+        ];
+        [&header[..], rest].concat().concat()
+    }
+
+    /// A directory listing with a single header and the name, inode offset, number and type of the entries.
+    fn listing(entries: &[(&str, usize, u32, u16)]) -> Vec<u8> {
+        let base = entries[0].2;
+        let mut res = [entries.len() as u32 - 1, 0, base].map(u32::to_le_bytes).concat();
+        for &(name, offset, number, typ) in entries {
+            res.extend((offset as u16).to_le_bytes());
+            res.extend(((number - base) as i16).to_le_bytes());
+            res.extend(typ.to_le_bytes());
+            res.extend((name.len() as u16 - 1).to_le_bytes());
+            res.extend(name.as_bytes());
+        }
+        res
+    }
+
+    /// An uncompressed image with a compressed data block, a fragment, a sparse block, hard links and xattrs.
+    fn image() -> Vec<u8> {
+        let u32 = |x: u32| x.to_le_bytes();
+        let u64 = |x: u64| x.to_le_bytes();
+        let (comp, frag) = (DATA + BS, DATA + BS + stored(&ramp(3000, 251)).len());
+
+        // the inodes with the position and size of the directory listings
+        let inodes = |dirs: [(usize, usize); 2]| {
+            let dir = |number, (offset, len): (usize, usize), parent| {
+                let rest = [
+                    &u32(0)[..],
+                    &u32(2),
+                    &(len as u16 + 3).to_le_bytes(),
+                    &(offset as u16).to_le_bytes(),
+                    &u32(parent),
+                ];
+                inode(1, 0o755, 0, number, &rest)
+            };
+            let none = u32(inode::NONE);
+            let big = [
+                &u64(DATA as u64)[..],
+                &u64(2 * BS as u64 + 1000),
+                &u64(BS as u64),
+                &u32(1),
+                &u32(0),
+                &u32(0),
+                &none,
+            ];
+            let small = [&u64(0)[..], &u64(5), &u64(0), &u32(2), &u32(0), &u32(1000), &u32(0)];
+            let list = [
+                dir(1, dirs[0], 8),
+                inode(9, 0o644, 0, 2, &[&big.concat(), &u32(BS as u32 | 1 << 24), &u32(0)]),
+                inode(
+                    2,
+                    0o600,
+                    1,
+                    3,
+                    &[&u32(comp as u32), &none, &u32(0), &u32(3000), &u32(3011)],
+                ),
+                inode(9, 0o4755, 1, 4, &[&small.concat()]),
+                dir(5, dirs[1], 1),
+                inode(3, 0o777, 0, 6, &[&u32(1), &u32(8), b"../small"]),
+                inode(5, 0o620, 0, 7, &[&u32(1), &u32(4 << 8 | 0x34 | 0x1200 << 12)]),
+            ];
+            let offsets: Vec<usize> = list
+                .iter()
+                .scan(0, |pos, x| Some(core::mem::replace(pos, *pos + x.len())))
+                .collect();
+            (list.concat(), offsets)
+        };
+        let (_, at) = inodes([(0, 0); 2]);
+        let root = listing(&[
+            ("big", at[1], 2, 2),
+            ("comp", at[2], 3, 2),
+            ("small", at[3], 4, 2),
+            ("sub", at[4], 5, 1),
+            ("dev", at[6], 7, 5),
+        ]);
+        let sub = listing(&[("link", at[3], 4, 2), ("sym", at[5], 6, 3)]);
+        let (inodes, _) = inodes([(0, root.len()), (root.len(), sub.len())]);
+
+        let mut data = std::vec![0; DATA];
+        data.extend(ramp(BS, 251));
+        data.extend(stored(&ramp(3000, 251)));
+        data.extend([b'Z'; 1000]);
+        data.extend(b"hello");
+        let table = |data: &mut Vec<u8>, content: &[u8]| {
+            let pos = data.len() as u64;
+            data.extend(content);
+            pos
+        };
+        let inode_table = table(&mut data, &meta(&inodes));
+        let directory_table = table(&mut data, &meta(&[root, sub].concat()));
+        let frags = table(
+            &mut data,
+            &meta(&[&u64(frag as u64)[..], &u32(1005 | 1 << 24), &u32(0)].concat()),
+        );
+        let fragment_table = table(&mut data, &u64(frags));
+        let ids = table(&mut data, &meta(&[u32(1000), u32(100)].concat()));
+        let id_table = table(&mut data, &u64(ids));
+
+        // an out-of-line value followed by the key-value pairs of the file
+        let kv = [
+            &u32(3)[..],
+            b"ool",
+            &0u16.to_le_bytes(),
+            &4u16.to_le_bytes(),
+            b"test",
+            &u32(5),
+            b"value",
+            &0x101u16.to_le_bytes(),
+            &3u16.to_le_bytes(),
+            b"big",
+            &u32(8),
+            &u64(0),
+        ]
+        .concat();
+        let kv = table(&mut data, &meta(&kv));
+        let xattr_ids = table(&mut data, &meta(&[&u64(7)[..], &u32(2), &u32(40)].concat()));
+        let xattr_table = table(&mut data, &[&u64(kv)[..], &u32(1), &u32(0), &u64(xattr_ids)].concat());
+
+        let sb = [
+            &u32(MAGIC)[..],
+            &u32(7),
+            &u32(1_700_000_000),
+            &u32(BS as u32),
+            &u32(1),
+            &1u16.to_le_bytes(),
+            &12u16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &2u16.to_le_bytes(),
+            &4u16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &u64(at[0] as u64),
+            &u64(data.len() as u64),
+            &u64(id_table),
+            &u64(xattr_table),
+            &u64(inode_table),
+            &u64(directory_table),
+            &u64(fragment_table),
+            &u64(!0),
+        ]
+        .concat();
+        data[..96].copy_from_slice(&sb);
+        data
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn xz_streams() {
+        let mut out = std::vec![0; 1 << 20];
+        for len in [0, 1, 5000, 300_000] {
+            let data = sample(len);
+            for args in [
+                ["-0", "--check=none"],
+                ["-6", "--check=crc32"],
+                ["-9e", "--check=crc64"],
+                ["-1", "--check=sha256"],
+            ] {
+                let Some(src) = compress("xz", &[&["-c", "--format=xz"], &args[..]].concat(), &data) else {
+                    return;
+                };
+                assert_eq!(xz::decompress(&src, &mut out).unwrap(), len, "{args:?}");
+                assert_eq!(out[..len], data, "{args:?}");
+            }
+        }
+
+        // incompressible data is stored in uncompressed chunks
+        let mut x = 7u64;
+        let data: Vec<u8> = (0..100_000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect();
+        let src = compress("xz", &["-c"], &data).unwrap();
+        assert_eq!(xz::decompress(&src, &mut out).unwrap(), data.len());
+        assert_eq!(out[..data.len()], data);
+        assert!(xz::decompress(&src[..src.len() / 2], &mut out).is_err());
+        assert!(xz::decompress(&src, &mut out[..1000]).is_err());
+        assert!(xz::decompress(b"no xz stream", &mut out).is_err());
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_blocks() {
+        // an overlapping match repeats the last byte
+        let mut out = [0; 64];
+        let block = [0x14, b'a', 1, 0, 0x30, b'x', b'y', b'z'];
+        assert_eq!(lz4::decompress(&block, &mut out).unwrap(), 12);
+        assert_eq!(&out[..12], b"aaaaaaaaaxyz");
+        assert!(lz4::decompress(&block[..3], &mut out).is_err());
+        assert!(lz4::decompress(&block, &mut out[..8]).is_err());
+
+        let mut out = std::vec![0; 1 << 16];
+        for len in [100, 5000, 60000] {
+            let data = sample(len);
+            for level in ["-1", "-9", "-12"] {
+                let Some(frame) = compress("lz4", &["-c", "-B4", level], &data) else {
+                    return;
+                };
+
+                // extract the single block from the frame
+                let flags = frame[4];
+                let mut pos = 7 + if flags & 8 != 0 { 8 } else { 0 } + if flags & 1 != 0 { 4 } else { 0 };
+                let size = u32::from_le_bytes(frame[pos..pos + 4].try_into().unwrap());
+                assert_eq!(size >> 31, 0, "{len} {level}");
+                pos += 4;
+                let block = &frame[pos..pos + size as usize];
+                assert_eq!(lz4::decompress(block, &mut out).unwrap(), len, "{level}");
+                assert_eq!(out[..len], data, "{level}");
+            }
+        }
+    }
+
+    #[test]
+    fn files() {
+        let data = image();
+        let disk = ReadSlice(&data);
+        let mut scratch = std::vec![0; scratch_size(4096)];
+        let fs = SquashFs::new(&disk, &mut scratch).unwrap();
+        assert_eq!(fs.superblock().compressor, Compressor::Gzip);
+        let root = fs.root().unwrap();
+        let open = |path: &str| root.clone().lookup_path(path.as_bytes()).unwrap();
+
+        // a full block, a sparse one and the tail end in a fragment
+        let big = open("big");
+        let mut expected = ramp(BS, 251);
+        expected.extend([0; BS]);
+        expected.extend([b'Z'; 1000]);
+        assert_eq!(read_all(&big), expected);
+        let mut buf = [0; 8];
+        assert_eq!(big.read_bytes(2 * BS as u64 - 4, &mut buf).unwrap(), 4);
+        assert_eq!(big.read_bytes(10, &mut buf).unwrap(), 8);
+        assert_eq!(buf[..], ramp(18, 251)[10..]);
+        assert_eq!(read_all(&open("comp")), ramp(3000, 251));
+
+        let small = open("small");
+        let get = |file: &file::File<_>, name| file.attr().get(name, &mut []).and_then(|x| x.as_u64());
+        assert_eq!(read_all(&small), b"hello");
+        assert_eq!(get(&small, attr::ID), get(&open("sub/link"), attr::ID));
+        assert_eq!(get(&small, crate::attr::NLINKS), Some(2));
+        assert_eq!(get(&small, attr::MODE), Some(0o4755));
+        assert_eq!(
+            (get(&small, attr::UID), get(&small, attr::GID)),
+            (Some(100), Some(1000))
+        );
+        assert!(matches!(
+            small.attr().get(attr::MTIME, &mut []),
+            Some(Value::Time(1_700_000_000_000_000_000))
+        ));
+
+        let sym = open("sub/sym");
+        assert_eq!(sym.ftype(), FileType::SymLink);
+        assert_eq!(read_all(&sym), b"../small");
+        let dev = open("dev");
+        assert_eq!(dev.ftype(), FileType::CharDevice);
+        assert_eq!(get(&dev, attr::RDEV), Some(4 << 32 | 0x1234));
+        assert_eq!(get(&big, attr::RDEV), None);
+
+        // inline and out-of-line values
+        let mut names = Vec::new();
+        small
+            .xattrs(&mut |name| {
+                names.push(name.to_vec());
+                true
+            })
+            .unwrap();
+        assert_eq!(names, [&b"user.test"[..], b"trusted.big"]);
+        let mut buf = [0; 16];
+        assert_eq!(small.xattr(b"user.test", &mut buf).unwrap(), Some(5));
+        assert_eq!(&buf[..5], b"value");
+        assert_eq!(small.xattr(b"trusted.big", &mut buf).unwrap(), Some(3));
+        assert_eq!(&buf[..3], b"ool");
+        assert_eq!(small.xattr(b"user.none", &mut buf).unwrap(), None);
+        assert_eq!(big.xattr(b"user.test", &mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn directories() {
+        let data = image();
+        let disk = ReadSlice(&data);
+        let mut scratch = std::vec![0; scratch_size(4096)];
+        let fs = SquashFs::new(&disk, &mut scratch).unwrap();
+        let root = fs.root().unwrap();
+        assert_eq!(
+            list(&root),
+            [
+                ("big", FileType::File),
+                ("comp", FileType::File),
+                ("small", FileType::File),
+                ("sub", FileType::Directory),
+                ("dev", FileType::CharDevice),
+            ]
+            .map(|(x, y)| (String::from(x), y))
+        );
+        let sub = root.lookup(b"sub").unwrap().unwrap();
+        assert_eq!(list(&sub).len(), 2);
+        assert!(root.lookup(b"gone").unwrap().is_none());
+        assert!(sub.lookup_path(b"sym/x").is_err());
+
+        // broken superblocks and metadata blocks are rejected
+        assert!(SquashFs::new(&disk, &mut [0; 100]).is_err());
+        for (pos, value) in [(0, 0), (12, 3), (28, 3)] {
+            let mut data = image();
+            data[pos] = value;
+            let disk = ReadSlice(&data);
+            let mut scratch = std::vec![0; SCRATCH_SIZE];
+            assert!(SquashFs::new(&disk, &mut scratch).is_err());
+        }
+        let mut data = image();
+        let inodes = le64(&data, 64) as usize;
+        data[inodes..inodes + 2].copy_from_slice(&0x9000u16.to_le_bytes());
+        let disk = ReadSlice(&data);
+        let mut scratch = std::vec![0; scratch_size(4096)];
+        let fs = SquashFs::new(&disk, &mut scratch).unwrap();
+        assert!(fs.root().is_err());
+    }
+
+    #[test]
+    fn compressors() {
+        let src = std::env::temp_dir().join("ap-storage-squashfs");
+        let _ = std::fs::remove_dir_all(&src);
+        std::fs::create_dir_all(src.join("sub")).unwrap();
+        let big: Vec<u8> = (0..10000u32).flat_map(|x| x.to_le_bytes()).collect();
+        std::fs::write(src.join("sub/big"), &big).unwrap();
+        std::fs::write(src.join("small"), b"hello").unwrap();
+        std::fs::hard_link(src.join("small"), src.join("sub/link")).unwrap();
+        symlink("../small", src.join("sub/sym")).unwrap();
+        let xattr = Command::new("setfattr")
+            .args(["-n", "user.test", "-v", "value"])
+            .arg(src.join("small"))
+            .status()
+            .is_ok_and(|x| x.success());
+
+        let mut comps = std::vec!["gzip", "zstd"];
+        if cfg!(feature = "xz") {
+            comps.push("xz");
+        }
+        if cfg!(feature = "lz4") {
+            comps.push("lz4");
+        }
+        let mut scratch = std::vec![0; scratch_size(4096)];
+        for comp in comps {
+            let Some(data) = mksquashfs(&src, comp) else {
+                break;
+            };
+            let disk = ReadSlice(&data);
+            let fs = SquashFs::new(&disk, &mut scratch).unwrap();
+            let root = fs.root().unwrap();
+            assert_eq!(read_all(&root.clone().lookup_path(b"sub/big").unwrap()), big);
+
+            let small = root.clone().lookup_path(b"small").unwrap();
+            let link = root.clone().lookup_path(b"sub/link").unwrap();
+            assert_eq!(read_all(&small), b"hello");
+            assert_eq!(read_all(&link), b"hello");
+            let id = |f: &file::File<_>| f.attr().get(attr::ID, &mut []).and_then(|x| x.as_u64());
+            assert_eq!(id(&small), id(&link));
+
+            let sym = root.clone().lookup_path(b"sub/sym").unwrap();
+            assert_eq!(sym.ftype(), FileType::SymLink);
+            assert_eq!(read_all(&sym), b"../small");
+
+            if xattr {
+                let mut buf = [0; 16];
+                assert_eq!(small.xattr(b"user.test", &mut buf).unwrap(), Some(5));
+                assert_eq!(&buf[..5], b"value");
+            }
+        }
+        std::fs::remove_dir_all(src).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+//! Decompress LZ4 blocks.
+
+use ap_storage::{msg2err, Error};
+
+/// Read a length that continues with 255 bytes.
+fn length(src: &[u8], pos: &mut usize, mut len: usize) -> Result<usize, Error> {
+    if len == 15 {
+        loop {
+            let byte = *src.get(*pos).ok_or(msg2err!("truncated lz4 block"))?;
+            *pos += 1;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// Decompress a raw block without frame and return the bytes written.
+pub(crate) fn decompress(src: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let (mut pos, mut written) = (0, 0);
+    loop {
+        let token = *src.get(pos).ok_or(msg2err!("truncated lz4 block"))?;
+        pos += 1;
+        let n = length(src, &mut pos, token as usize >> 4)?;
+        let literals = src.get(pos..pos + n).ok_or(msg2err!("truncated lz4 block"))?;
+        out.get_mut(written..written + n)
+            .ok_or(msg2err!("output too small"))?
+            .copy_from_slice(literals);
+        pos += n;
+        written += n;
+
+        // the last sequence has only literals
+        if pos == src.len() {
+            return Ok(written);
+        }
+        let offset = src.get(pos..pos + 2).ok_or(msg2err!("truncated lz4 block"))?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        pos += 2;
+        let n = length(src, &mut pos, token as usize & 0xf)? + 4;
+        if offset == 0 || offset > written {
+            return Err(msg2err!("offset out of range"));
+        }
+        if written + n > out.len() {
+            return Err(msg2err!("output too small"));
+        }
+        for i in written..written + n {
+            out[i] = out[i - offset];
+        }
+        written += n;
+    }
+}
@@ -0,0 +1,423 @@
+//! Decompress xz streams with a single LZMA2 filter.
+
+use ap_storage::{msg2err, Error};
+
+const MAGIC: &[u8; 6] = b"\xfd7zXZ\0";
+
+/// The LZMA2 filter id.
+const LZMA2: u64 = 0x21;
+
+/// The initial probability of one half.
+const HALF: u16 = 1 << 10;
+
+const STATES: usize = 12;
+const POS_STATES: usize = 16;
+const DIST_STATES: usize = 4;
+const DIST_MODEL_END: u32 = 14;
+const FULL_DISTANCES: usize = 128;
+
+/// A variable length integer of the xz container.
+fn vli(src: &[u8], pos: &mut usize) -> Result<u64, Error> {
+    let mut res = 0;
+    for i in 0..9 {
+        let byte = *src.get(*pos).ok_or(msg2err!("truncated xz header"))?;
+        *pos += 1;
+        res |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(res);
+        }
+    }
+    Err(msg2err!("invalid xz integer"))
+}
+
+/// Decompress all blocks of a stream and return the bytes written.
+pub(crate) fn decompress(src: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    if src.len() < 12 || &src[..6] != MAGIC || src[6] != 0 {
+        return Err(msg2err!("no xz stream"));
+    }
+    let check = match src[7] & 0xf {
+        0 => 0,
+        1 => 4,
+        4 => 8,
+        10 => 32,
+        _ => return Err(msg2err!("unsupported xz check")),
+    };
+    let mut pos = 12;
+    let mut written = 0;
+    let mut lzma = Lzma::new();
+    loop {
+        // the index follows the last block
+        let size = *src.get(pos).ok_or(msg2err!("truncated xz stream"))? as usize;
+        if size == 0 {
+            return Ok(written);
+        }
+        let end = pos + (size + 1) * 4;
+        let header = src.get(..end).ok_or(msg2err!("truncated xz header"))?;
+        let flags = header[pos + 1];
+        let mut x = pos + 2;
+        if flags & 3 != 0 {
+            return Err(msg2err!("unsupported xz filter"));
+        }
+        if flags & 0x40 != 0 {
+            vli(header, &mut x)?;
+        }
+        if flags & 0x80 != 0 {
+            vli(header, &mut x)?;
+        }
+        if vli(header, &mut x)? != LZMA2 || vli(header, &mut x)? != 1 {
+            return Err(msg2err!("unsupported xz filter"));
+        }
+        if *header.get(x).ok_or(msg2err!("truncated xz header"))? > 40 {
+            return Err(msg2err!("invalid dictionary size"));
+        }
+
+        let used = lzma.lzma2(&src[end..], out, &mut written)?;
+        pos = (end + used).next_multiple_of(4) + check;
+    }
+}
+
+/// The range decoder.
+struct Rc<'a> {
+    src: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> Rc<'a> {
+    fn new(src: &'a [u8]) -> Result<Self, Error> {
+        if src.len() < 5 || src[0] != 0 {
+            return Err(msg2err!("invalid range coder"));
+        }
+        Ok(Self {
+            src,
+            pos: 5,
+            range: u32::MAX,
+            code: u32::from_be_bytes(src[1..5].try_into().unwrap()),
+        })
+    }
+
+    fn normalize(&mut self) -> Result<(), Error> {
+        if self.range < 1 << 24 {
+            let byte = *self.src.get(self.pos).ok_or(msg2err!("truncated lzma chunk"))?;
+            self.pos += 1;
+            self.range <<= 8;
+            self.code = self.code << 8 | byte as u32;
+        }
+        Ok(())
+    }
+
+    fn bit(&mut self, prob: &mut u16) -> Result<u32, Error> {
+        self.normalize()?;
+        let bound = (self.range >> 11) * *prob as u32;
+        if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << 11) - *prob) >> 5;
+            Ok(0)
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *prob -= *prob >> 5;
+            Ok(1)
+        }
+    }
+
+    /// Decode a symbol with the highest bit first.
+    fn tree(&mut self, probs: &mut [u16], limit: u32) -> Result<u32, Error> {
+        let mut symbol = 1;
+        while symbol < limit {
+            symbol = symbol << 1 | self.bit(&mut probs[symbol as usize])?;
+        }
+        Ok(symbol - limit)
+    }
+
+    /// Decode `bits` with the lowest bit first.
+    fn reverse(&mut self, probs: &mut [u16], bits: u32) -> Result<u32, Error> {
+        let mut symbol = 1;
+        let mut res = 0;
+        for i in 0..bits {
+            let bit = self.bit(&mut probs[symbol as usize - 1])?;
+            symbol = symbol << 1 | bit;
+            res |= bit << i;
+        }
+        Ok(res)
+    }
+
+    /// Decode bits with fixed probabilities.
+    fn direct(&mut self, bits: u32) -> Result<u32, Error> {
+        let mut res = 0;
+        for _ in 0..bits {
+            self.normalize()?;
+            self.range >>= 1;
+            self.code = self.code.wrapping_sub(self.range);
+            let mask = 0u32.wrapping_sub(self.code >> 31);
+            self.code = self.code.wrapping_add(self.range & mask);
+            res = res << 1 | mask.wrapping_add(1);
+        }
+        Ok(res)
+    }
+}
+
+/// The probabilities of a length decoder.
+#[derive(Clone)]
+struct Len {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 8]; POS_STATES],
+    mid: [[u16; 8]; POS_STATES],
+    high: [u16; 256],
+}
+
+impl Len {
+    const NEW: Self = Self {
+        choice: HALF,
+        choice2: HALF,
+        low: [[HALF; 8]; POS_STATES],
+        mid: [[HALF; 8]; POS_STATES],
+        high: [HALF; 256],
+    };
+
+    fn decode(&mut self, rc: &mut Rc, pos_state: usize) -> Result<usize, Error> {
+        Ok(if rc.bit(&mut self.choice)? == 0 {
+            2 + rc.tree(&mut self.low[pos_state], 8)?
+        } else if rc.bit(&mut self.choice2)? == 0 {
+            10 + rc.tree(&mut self.mid[pos_state], 8)?
+        } else {
+            18 + rc.tree(&mut self.high, 256)?
+        } as usize)
+    }
+}
+
+/// The LZMA decoder state.
+struct Lzma {
+    lc: u32,
+    lp: u32,
+    pb: u32,
+    state: usize,
+    reps: [usize; 4],
+    is_match: [[u16; POS_STATES]; STATES],
+    is_rep: [u16; STATES],
+    is_rep0: [u16; STATES],
+    is_rep1: [u16; STATES],
+    is_rep2: [u16; STATES],
+    is_rep0_long: [[u16; POS_STATES]; STATES],
+    dist_slot: [[u16; 64]; DIST_STATES],
+    dist_special: [u16; FULL_DISTANCES - DIST_MODEL_END as usize],
+    dist_align: [u16; 16],
+    match_len: Len,
+    rep_len: Len,
+    literal: [[u16; 0x300]; 16],
+    /// Whether properties were seen.
+    ready: bool,
+}
+
+impl Lzma {
+    fn new() -> Self {
+        Self {
+            lc: 0,
+            lp: 0,
+            pb: 0,
+            state: 0,
+            reps: [0; 4],
+            is_match: [[HALF; POS_STATES]; STATES],
+            is_rep: [HALF; STATES],
+            is_rep0: [HALF; STATES],
+            is_rep1: [HALF; STATES],
+            is_rep2: [HALF; STATES],
+            is_rep0_long: [[HALF; POS_STATES]; STATES],
+            dist_slot: [[HALF; 64]; DIST_STATES],
+            dist_special: [HALF; FULL_DISTANCES - DIST_MODEL_END as usize],
+            dist_align: [HALF; 16],
+            match_len: Len::NEW,
+            rep_len: Len::NEW,
+            literal: [[HALF; 0x300]; 16],
+            ready: false,
+        }
+    }
+
+    /// Reset the state but keep the properties.
+    fn reset(&mut self) {
+        *self = Self {
+            lc: self.lc,
+            lp: self.lp,
+            pb: self.pb,
+            ready: self.ready,
+            ..Self::new()
+        };
+    }
+
+    /// Decode LZMA2 chunks up to the end marker and return the bytes consumed.
+    fn lzma2(&mut self, src: &[u8], out: &mut [u8], written: &mut usize) -> Result<usize, Error> {
+        let mut pos = 0;
+        let truncated = || msg2err!("truncated lzma2 chunk");
+        loop {
+            let control = *src.get(pos).ok_or(truncated())?;
+            pos += 1;
+            if control == 0 {
+                return Ok(pos);
+            }
+            let header = src.get(pos..pos + 4).ok_or(truncated())?;
+            let be16 = |x: usize| u16::from_be_bytes([header[x], header[x + 1]]) as usize;
+            if control < 0x80 {
+                if control > 2 {
+                    return Err(msg2err!("invalid lzma2 chunk"));
+                }
+                let n = be16(0) + 1;
+                let data = src.get(pos + 2..pos + 2 + n).ok_or(truncated())?;
+                out.get_mut(*written..*written + n)
+                    .ok_or(msg2err!("output too small"))?
+                    .copy_from_slice(data);
+                *written += n;
+                pos += 2 + n;
+                continue;
+            }
+
+            let size = ((control as usize & 0x1f) << 16) + be16(0) + 1;
+            let packed = be16(2) + 1;
+            pos += 4;
+            if control >= 0xc0 {
+                let props = *src.get(pos).ok_or(truncated())? as u32;
+                pos += 1;
+                let (lc, lp, pb) = (props % 9, props / 9 % 5, props / 45);
+                if lc + lp > 4 || pb > 4 {
+                    return Err(msg2err!("invalid lzma properties"));
+                }
+                (self.lc, self.lp, self.pb, self.ready) = (lc, lp, pb, true);
+            }
+            if !self.ready {
+                return Err(msg2err!("missing lzma properties"));
+            }
+            if control >= 0xa0 {
+                self.reset();
+            }
+            let end = *written + size;
+            if end > out.len() {
+                return Err(msg2err!("output too small"));
+            }
+            let mut rc = Rc::new(src.get(pos..pos + packed).ok_or(truncated())?)?;
+            self.chunk(&mut rc, &mut out[..end], written)?;
+            rc.normalize()?;
+            if rc.pos != packed || rc.code != 0 {
+                return Err(msg2err!("corrupt lzma chunk"));
+            }
+            pos += packed;
+        }
+    }
+
+    /// Decode a chunk that fills the output.
+    fn chunk(&mut self, rc: &mut Rc, out: &mut [u8], written: &mut usize) -> Result<(), Error> {
+        let mut pos = *written;
+        while pos < out.len() {
+            let pos_state = pos & ((1 << self.pb) - 1);
+            if rc.bit(&mut self.is_match[self.state][pos_state])? == 0 {
+                out[pos] = self.literal(rc, out, pos)?;
+                pos += 1;
+                self.state = match self.state {
+                    0..=3 => 0,
+                    4..=9 => self.state - 3,
+                    _ => self.state - 6,
+                };
+                continue;
+            }
+
+            let len = if rc.bit(&mut self.is_rep[self.state])? == 0 {
+                self.new_match(rc, pos_state)?
+            } else if rc.bit(&mut self.is_rep0[self.state])? == 0 {
+                if rc.bit(&mut self.is_rep0_long[self.state][pos_state])? == 0 {
+                    self.state = if self.state < 7 { 9 } else { 11 };
+                    1
+                } else {
+                    self.long_rep(rc, pos_state)?
+                }
+            } else {
+                let dist = if rc.bit(&mut self.is_rep1[self.state])? == 0 {
+                    self.reps[1]
+                } else {
+                    let dist = if rc.bit(&mut self.is_rep2[self.state])? == 0 {
+                        self.reps[2]
+                    } else {
+                        let dist = self.reps[3];
+                        self.reps[3] = self.reps[2];
+                        dist
+                    };
+                    self.reps[2] = self.reps[1];
+                    dist
+                };
+                self.reps[1] = self.reps[0];
+                self.reps[0] = dist;
+                self.long_rep(rc, pos_state)?
+            };
+
+            let dist = self.reps[0] + 1;
+            if dist > pos {
+                return Err(msg2err!("lzma distance out of range"));
+            }
+            if pos + len > out.len() {
+                return Err(msg2err!("lzma match beyond chunk"));
+            }
+            for i in pos..pos + len {
+                out[i] = out[i - dist];
+            }
+            pos += len;
+        }
+        *written = pos;
+        Ok(())
+    }
+
+    fn literal(&mut self, rc: &mut Rc, out: &[u8], pos: usize) -> Result<u8, Error> {
+        let prev = if pos > 0 { out[pos - 1] as usize } else { 0 };
+        let index = ((pos & ((1 << self.lp) - 1)) << self.lc) + (prev >> (8 - self.lc));
+        let probs = &mut self.literal[index];
+        if self.state < 7 {
+            return Ok(rc.tree(probs, 0x100)? as u8);
+        }
+
+        // the byte at the last distance guides the decoding
+        let mut matched = *out
+            .get(pos.wrapping_sub(self.reps[0] + 1))
+            .ok_or(msg2err!("lzma distance out of range"))? as u32;
+        let mut offset = 0x100;
+        let mut symbol = 1;
+        while symbol < 0x100 {
+            matched <<= 1;
+            let bit = matched & offset;
+            if rc.bit(&mut probs[(offset + bit + symbol) as usize])? != 0 {
+                symbol = symbol << 1 | 1;
+                offset = bit;
+            } else {
+                symbol <<= 1;
+                offset ^= bit;
+            }
+        }
+        Ok(symbol as u8)
+    }
+
+    fn long_rep(&mut self, rc: &mut Rc, pos_state: usize) -> Result<usize, Error> {
+        self.state = if self.state < 7 { 8 } else { 11 };
+        self.rep_len.decode(rc, pos_state)
+    }
+
+    fn new_match(&mut self, rc: &mut Rc, pos_state: usize) -> Result<usize, Error> {
+        self.state = if self.state < 7 { 7 } else { 10 };
+        self.reps.copy_within(0..3, 1);
+        let len = self.match_len.decode(rc, pos_state)?;
+        let slot = rc.tree(&mut self.dist_slot[core::cmp::min(len - 2, DIST_STATES - 1)], 64)?;
+        self.reps[0] = if slot < 4 {
+            slot
+        } else {
+            let bits = (slot >> 1) - 1;
+            let base = (2 | (slot & 1)) << bits;
+            if slot < DIST_MODEL_END {
+                let probs = &mut self.dist_special[(base - slot) as usize..];
+                base + rc.reverse(probs, bits)?
+            } else {
+                let high = rc.direct(bits - 4)?;
+                base + (high << 4) + rc.reverse(&mut self.dist_align, 4)?
+            }
+        } as usize;
+        if self.reps[0] == u32::MAX as usize {
+            return Err(msg2err!("unexpected lzma end marker"));
+        }
+        Ok(len)
+    }
+}
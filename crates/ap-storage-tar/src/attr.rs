@@ -0,0 +1,87 @@
+//! File attributes for tar archives.
+
+use super::file::File;
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::Read;
+use ap_util_slice_writer::*;
+
+new_attr!(GNAME, Str, "Group name of the owner.");
+new_attr!(UNAME, Str, "User name of the owner.");
+
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
+}
+
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            GNAME,
+            UNAME,
+            attr::ATIME,
+            attr::CTIME,
+            attr::FTYPE,
+            attr::GID,
+            attr::ID,
+            attr::MODE,
+            attr::MTIME,
+            attr::RDEV,
+            attr::SIZE,
+            attr::UID,
+        ]
+        .iter()
+    }
+}
+
+/// Copy a string into the buffer.
+fn get_string(value: &[u8], buf: &mut [u8]) -> Value {
+    let n = core::cmp::min(value.len(), buf.len());
+    buf[..n].copy_from_slice(&value[..n]);
+    Value::Str(value.len())
+}
+
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let member = self.file.member();
+        Some(match name {
+            GNAME if !member.gname.is_empty() => get_string(&member.gname, buf),
+            UNAME if !member.uname.is_empty() => get_string(&member.uname, buf),
+            attr::ATIME => Value::Time(member.atime?),
+            attr::CTIME => Value::Time(member.ctime?),
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            attr::GID => member.gid.into(),
+            attr::ID => member.id.into(),
+            attr::MODE => (member.mode & 0o7777).into(),
+            attr::MTIME => Value::Time(member.mtime),
+            attr::RDEV if matches!(member.kind, b'3' | b'4') => member.rdev.into(),
+            attr::SIZE => member.size.into(),
+            attr::UID => member.uid.into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                GNAME,
+                UNAME,
+                attr::ATIME,
+                attr::CTIME,
+                attr::FTYPE,
+                attr::GID,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::RDEV,
+                attr::SIZE,
+                attr::UID,
+            ]
+        )
+    }
+}
@@ -0,0 +1,144 @@
+//! Files in tar archives.
+
+use super::{attr::Attr, dir::Dir, Member, TarFs};
+use ap_storage::{file::FileType, msg2err, Error, Offset, Read};
+use core::cell::Ref;
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a TarFs<'a, D>,
+    pub(crate) index: usize,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            index: self.index,
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("fs", &self.fs)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+/// The file type of a typeflag.
+pub(crate) fn file_type(kind: u8) -> FileType {
+    match kind {
+        b'0' => FileType::File,
+        b'2' => FileType::SymLink,
+        b'3' => FileType::CharDevice,
+        b'4' => FileType::BlockDevice,
+        b'5' => FileType::Directory,
+        b'6' => FileType::Fifo,
+        // unresolved hard links
+        b'1' => FileType::Unknown,
+        // vendor extensions are regular files
+        _ => FileType::File,
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    pub(crate) fn new(fs: &'a TarFs<'a, D>, index: usize) -> Self {
+        Self { fs, index }
+    }
+
+    /// The indexed member.
+    pub(crate) fn member(&self) -> Ref<'_, Member> {
+        Ref::map(self.fs.index.borrow(), |x| &x.members[self.index])
+    }
+
+    pub fn ftype(&self) -> FileType {
+        file_type(self.member().kind)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.ftype() == FileType::Directory
+    }
+
+    /// Call the function for every extended attribute.
+    ///
+    /// The iteration stops early if the function returns false.
+    pub fn xattrs(&self, f: &mut dyn FnMut(&[u8]) -> bool) -> Result<(), Error> {
+        for (name, _) in &self.member().xattrs {
+            if !f(name) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the value of an extended attribute and return its full length.
+    pub fn xattr(&self, name: &[u8], buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        let member = self.member();
+        let Some((_, value)) = member.xattrs.iter().find(|x| x.0 == name) else {
+            return Ok(None);
+        };
+        let n = core::cmp::min(buf.len(), value.len());
+        buf[..n].copy_from_slice(&value[..n]);
+        Ok(Some(value.len()))
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.is_dir() {
+            return Some(Dir::new(self));
+        }
+        None
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        let index = offset as usize;
+        if self.fs.member(index)?.map(|x| x.parent) != Some(self.index) || index == 0 {
+            return Err(msg2err!("not a child"));
+        }
+        Ok(Self::new(self.fs, index))
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        let member = self.member();
+        if offset >= member.size {
+            return Ok(0);
+        }
+        let max_n = core::cmp::min(buf.len() as Offset, member.size - offset) as usize;
+        let buf = &mut buf[..max_n];
+        match file_type(member.kind) {
+            FileType::SymLink => {
+                buf.copy_from_slice(&member.link[offset as usize..][..max_n]);
+                return Ok(max_n);
+            }
+            FileType::File => {}
+            _ => return Ok(0),
+        }
+        if member.sparse.is_empty() {
+            return self.fs.disk().read_bytes(member.data + offset, buf);
+        }
+
+        // holes before the next segment read as zero
+        let i = member.sparse.partition_point(|x| x.offset + x.len <= offset);
+        let Some(segment) = member.sparse.get(i).filter(|x| x.offset <= offset) else {
+            let end = member.sparse.get(i).map_or(member.size, |x| x.offset);
+            let n = core::cmp::min(max_n as Offset, end - offset) as usize;
+            buf[..n].fill(0);
+            return Ok(n);
+        };
+        let n = core::cmp::min(max_n as Offset, segment.offset + segment.len - offset) as usize;
+        self.fs
+            .disk()
+            .read_bytes(segment.data + offset - segment.offset, &mut buf[..n])
+    }
+}
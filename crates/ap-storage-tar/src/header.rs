@@ -0,0 +1,168 @@
+//! Tar headers and pax records.
+
+use alloc::vec::Vec;
+use ap_storage::{msg2err, Error};
+
+/// The size of a header and the unit of the data.
+pub(crate) const BLOCK: u64 = 512;
+
+/// A raw header block.
+pub(crate) type Header = [u8; BLOCK as usize];
+
+/// The string up to the first zero byte.
+pub(crate) fn cstr(field: &[u8]) -> &[u8] {
+    let n = field.iter().position(|x| *x == 0).unwrap_or(field.len());
+    &field[..n]
+}
+
+/// Parse a numeric field in octal or in the base-256 encoding of GNU tar.
+pub(crate) fn number(field: &[u8]) -> Option<u64> {
+    if field.first()? & 0x80 != 0 {
+        // negative numbers are not supported
+        if field[0] & 0x40 != 0 {
+            return None;
+        }
+        return field[1..].iter().try_fold((field[0] & 0x3f) as u64, |res, x| {
+            res.checked_mul(256)?.checked_add(*x as u64)
+        });
+    }
+    let mut res = 0u64;
+    for x in field.iter().skip_while(|x| **x == b' ') {
+        match x {
+            b'0'..=b'7' => res = res.checked_mul(8)?.checked_add((x - b'0') as u64)?,
+            b' ' | 0 => break,
+            _ => return None,
+        }
+    }
+    Some(res)
+}
+
+/// Parse a decimal number.
+pub(crate) fn decimal(value: &[u8]) -> Option<u64> {
+    if value.is_empty() {
+        return None;
+    }
+    value.iter().try_fold(0u64, |res, x| match x {
+        b'0'..=b'9' => res.checked_mul(10)?.checked_add((x - b'0') as u64),
+        _ => None,
+    })
+}
+
+/// Parse a pax timestamp with optional fraction into nanoseconds.
+fn time(value: &[u8]) -> Option<i64> {
+    let (negative, value) = match value.strip_prefix(b"-") {
+        Some(x) => (true, x),
+        None => (false, value),
+    };
+    let (secs, frac) = match value.iter().position(|x| *x == b'.') {
+        Some(n) => (&value[..n], &value[n + 1..]),
+        None => (value, &b""[..]),
+    };
+    let mut nsecs = 0;
+    for i in 0..9 {
+        let digit = match frac.get(i) {
+            Some(x @ b'0'..=b'9') => x - b'0',
+            Some(_) => return None,
+            None => 0,
+        };
+        nsecs = nsecs * 10 + digit as i64;
+    }
+    let res = i64::try_from(decimal(secs)?).ok()?.checked_mul(1_000_000_000)? + nsecs;
+    Some(if negative { -res } else { res })
+}
+
+/// Check the header checksum, which historic implementations computed over signed bytes.
+pub(crate) fn checksum_ok(header: &Header) -> bool {
+    let Some(stored) = number(&header[148..156]) else {
+        return false;
+    };
+    let (mut unsigned, mut signed) = (0u64, 0i64);
+    for (i, x) in header.iter().enumerate() {
+        let x = if (148..156).contains(&i) { b' ' } else { *x };
+        unsigned += x as u64;
+        signed += x as i8 as i64;
+    }
+    stored == unsigned || stored as i64 == signed
+}
+
+/// The values of extended headers that apply to the next member.
+#[derive(Default, Clone)]
+pub(crate) struct Pax {
+    pub(crate) path: Option<Vec<u8>>,
+    pub(crate) linkpath: Option<Vec<u8>>,
+    pub(crate) size: Option<u64>,
+    pub(crate) uid: Option<u64>,
+    pub(crate) gid: Option<u64>,
+    pub(crate) uname: Option<Vec<u8>>,
+    pub(crate) gname: Option<Vec<u8>>,
+    pub(crate) mtime: Option<i64>,
+    pub(crate) atime: Option<i64>,
+    pub(crate) ctime: Option<i64>,
+    pub(crate) devmajor: Option<u64>,
+    pub(crate) devminor: Option<u64>,
+    pub(crate) xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The sparse map as offset and length pairs of the 0.0 and 0.1 formats.
+    pub(crate) sparse_map: Vec<u64>,
+    /// The sparse format 1.0 stores the map in the data.
+    pub(crate) sparse_major: Option<u64>,
+    pub(crate) sparse_name: Option<Vec<u8>>,
+    pub(crate) sparse_size: Option<u64>,
+}
+
+impl Pax {
+    /// Parse the records of an extended header.
+    pub(crate) fn parse(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() && data[0] != 0 {
+            let space = data
+                .iter()
+                .position(|x| *x == b' ')
+                .ok_or(msg2err!("invalid pax record"))?;
+            let len = decimal(&data[..space]).ok_or(msg2err!("invalid pax length"))? as usize;
+            if len <= space + 1 || len > data.len() || data[len - 1] != b'\n' {
+                return Err(msg2err!("invalid pax record"));
+            }
+            let record = &data[space + 1..len - 1];
+            let eq = record
+                .iter()
+                .position(|x| *x == b'=')
+                .ok_or(msg2err!("invalid pax record"))?;
+            self.apply(&record[..eq], &record[eq + 1..]);
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Remember a single key.  Unknown keys and invalid values are ignored.
+    fn apply(&mut self, key: &[u8], value: &[u8]) {
+        if let Some(name) = key.strip_prefix(b"SCHILY.xattr.") {
+            self.xattrs.retain(|x| x.0 != name);
+            self.xattrs.push((name.into(), value.into()));
+            return;
+        }
+        match key {
+            b"path" => self.path = Some(value.into()),
+            b"linkpath" => self.linkpath = Some(value.into()),
+            b"size" => self.size = decimal(value),
+            b"uid" => self.uid = decimal(value),
+            b"gid" => self.gid = decimal(value),
+            b"uname" => self.uname = Some(value.into()),
+            b"gname" => self.gname = Some(value.into()),
+            b"mtime" => self.mtime = time(value),
+            b"atime" => self.atime = time(value),
+            b"ctime" => self.ctime = time(value),
+            b"SCHILY.devmajor" => self.devmajor = decimal(value),
+            b"SCHILY.devminor" => self.devminor = decimal(value),
+            b"GNU.sparse.offset" | b"GNU.sparse.numbytes" => self.sparse_map.extend(decimal(value)),
+            b"GNU.sparse.map" => self.sparse_map = value.split(|x| *x == b',').filter_map(decimal).collect(),
+            b"GNU.sparse.major" => self.sparse_major = decimal(value),
+            b"GNU.sparse.name" => self.sparse_name = Some(value.into()),
+            b"GNU.sparse.size" | b"GNU.sparse.realsize" => self.sparse_size = decimal(value),
+            _ => {}
+        }
+    }
+
+    /// Whether the member is stored sparse.
+    pub(crate) fn is_sparse(&self) -> bool {
+        self.sparse_size.is_some() || !self.sparse_map.is_empty()
+    }
+}
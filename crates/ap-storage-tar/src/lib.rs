@@ -0,0 +1,575 @@
+//! Browse tar archives.
+//!
+//! - ustar, v7 and GNU headers with long names and links
+//! - pax extended headers for long paths, precise times and xattrs
+//! - sparse members in the old GNU format and the pax formats 0.0, 0.1 and 1.0
+//! - directories that are implied only by the paths of their children
+//!
+//! The headers are indexed lazily while the archive is walked.  The offset of a directory entry is the index of the
+//! member and a later member replaces an earlier one with the same path.
+
+#![no_std]
+
+extern crate alloc;
+
+mod attr;
+mod dir;
+pub mod file;
+mod header;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use ap_storage::{
+    msg2err,
+    path::{normalize, parent},
+    Error, FileSystem, Offset, Read, ReadExt,
+};
+use core::cell::{Ref, RefCell};
+use header::{checksum_ok, cstr, decimal, number, Header, Pax, BLOCK};
+
+/// The largest extended header that is read into memory.
+const MAX_EXTENDED: u64 = 1 << 24;
+
+/// A continuous piece of a sparse file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Segment {
+    /// The offset inside the file.
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+    /// The offset in the archive.
+    pub(crate) data: Offset,
+}
+
+/// A member of the archive or a directory implied by a path.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Member {
+    /// The path without leading or trailing slashes.
+    pub(crate) path: Vec<u8>,
+    pub(crate) parent: usize,
+    /// The offset of the header or an odd number for implied directories.
+    pub(crate) id: u64,
+    /// The typeflag with regular files as `b'0'`.
+    pub(crate) kind: u8,
+    pub(crate) mode: u64,
+    pub(crate) uid: u64,
+    pub(crate) gid: u64,
+    pub(crate) uname: Vec<u8>,
+    pub(crate) gname: Vec<u8>,
+    pub(crate) mtime: i64,
+    pub(crate) atime: Option<i64>,
+    pub(crate) ctime: Option<i64>,
+    pub(crate) rdev: u64,
+    pub(crate) link: Vec<u8>,
+    pub(crate) xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+    pub(crate) data: Offset,
+    pub(crate) size: u64,
+    pub(crate) sparse: Vec<Segment>,
+}
+
+impl Member {
+    /// The last component of the path.
+    pub(crate) fn name(&self) -> &[u8] {
+        self.path.rsplit(|x| *x == b'/').next().unwrap_or_default()
+    }
+}
+
+/// The members found so far.
+struct Index {
+    members: Vec<Member>,
+    paths: BTreeMap<Vec<u8>, usize>,
+    /// The next header or None at the end of the archive.
+    next: Option<Offset>,
+    global: Pax,
+}
+
+impl Index {
+    /// Find a directory or create it with all its parents.
+    fn dir(&mut self, path: &[u8]) -> usize {
+        if let Some(i) = self.paths.get(path) {
+            return *i;
+        }
+        let parent = self.dir(parent(path));
+        let i = self.members.len();
+        self.members.push(Member {
+            path: path.into(),
+            parent,
+            id: 2 * i as u64 + 1,
+            kind: b'5',
+            mode: 0o755,
+            ..Default::default()
+        });
+        self.paths.insert(path.into(), i);
+        i
+    }
+
+    /// Add a member or replace the earlier one with the same path.
+    fn insert(&mut self, mut member: Member) {
+        if member.path.is_empty() {
+            // the archive root keeps its place
+            if member.kind == b'5' {
+                member.id = self.members[0].id;
+                self.members[0] = member;
+            }
+            return;
+        }
+        member.parent = self.dir(parent(&member.path));
+        if let Some(i) = self.paths.get(&member.path) {
+            self.members[*i] = member;
+            return;
+        }
+        self.paths.insert(member.path.clone(), self.members.len());
+        self.members.push(member);
+    }
+}
+
+/// A tar archive.
+pub struct TarFs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+    index: RefCell<Index>,
+}
+
+impl<D: ?Sized> core::fmt::Debug for TarFs<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(fmt, "TarFs({} members)", self.index.borrow().members.len())
+    }
+}
+
+impl<'a, D: Read + ?Sized> TarFs<'a, D> {
+    /// Open an archive by checking its first header.
+    pub fn new(disk: &'a D) -> Result<Self, Error> {
+        let header: Header = (&disk as &dyn Read).read_object(0)?;
+        if !checksum_ok(&header) {
+            return Err(msg2err!("not a tar archive"));
+        }
+        let root = Member {
+            id: 1,
+            kind: b'5',
+            mode: 0o755,
+            ..Default::default()
+        };
+        let mut paths = BTreeMap::new();
+        paths.insert(Vec::new(), 0);
+        Ok(Self {
+            disk,
+            index: RefCell::new(Index {
+                members: alloc::vec![root],
+                paths,
+                next: Some(0),
+                global: Pax::default(),
+            }),
+        })
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    /// Get a member by its index and walk the archive until it is known.
+    pub(crate) fn member(&self, index: usize) -> Result<Option<Ref<'_, Member>>, Error> {
+        while self.index.borrow().members.len() <= index {
+            if !self.scan()? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(Ref::map(self.index.borrow(), |x| &x.members[index])))
+    }
+
+    /// Index the whole archive and return the number of members including implied directories.
+    pub fn index_all(&self) -> Result<usize, Error> {
+        while self.scan()? {}
+        Ok(self.index.borrow().members.len())
+    }
+
+    /// Read a header and return None at the end of the archive.
+    fn header(&self, pos: Offset) -> Result<Option<Header>, Error> {
+        let mut header = [0; BLOCK as usize];
+        let mut n = 0;
+        while n < header.len() {
+            match self.disk.read_bytes(pos + n as Offset, &mut header[n..])? {
+                0 if n == 0 => return Ok(None),
+                0 => return Err(msg2err!("truncated header")),
+                c => n += c,
+            }
+        }
+        if header.iter().all(|x| *x == 0) {
+            return Ok(None);
+        }
+        if !checksum_ok(&header) {
+            return Err(msg2err!("invalid header checksum"));
+        }
+        Ok(Some(header))
+    }
+
+    /// Read the data of an extended header.
+    fn extended(&self, pos: Offset, size: u64) -> Result<Vec<u8>, Error> {
+        if size > MAX_EXTENDED {
+            return Err(msg2err!("extended header too large"));
+        }
+        let mut res = alloc::vec![0; size as usize];
+        self.disk().read_exact(pos, &mut res)?;
+        Ok(res)
+    }
+
+    /// Index the next member and return false at the end of the archive.
+    fn scan(&self) -> Result<bool, Error> {
+        let mut index = self.index.borrow_mut();
+        let Some(mut pos) = index.next else {
+            return Ok(false);
+        };
+        let mut pax = index.global.clone();
+        let (mut long_name, mut long_link) = (None, None);
+        let header = loop {
+            let Some(header) = self.header(pos)? else {
+                index.next = None;
+                return Ok(false);
+            };
+            let size = number(&header[124..136]).ok_or(msg2err!("invalid size"))?;
+            let data = pos + BLOCK;
+            match header[156] {
+                b'x' => pax.parse(&self.extended(data, size)?)?,
+                b'g' => {
+                    let buf = self.extended(data, size)?;
+                    index.global.parse(&buf)?;
+                    pax.parse(&buf)?;
+                }
+                b'L' => long_name = Some(cstr(&self.extended(data, size)?).into()),
+                b'K' => long_link = Some(cstr(&self.extended(data, size)?).into()),
+                b'V' => {}
+                _ => break header,
+            }
+            pos = data + size.next_multiple_of(BLOCK);
+        };
+
+        let ustar = &header[257..263] == b"ustar\0";
+        let gnu = &header[257..263] == b"ustar ";
+        let mut path = match (long_name, pax.sparse_name.take(), pax.path.take()) {
+            (_, Some(path), _) | (Some(path), _, _) | (_, _, Some(path)) => path,
+            _ => {
+                let mut path = Vec::new();
+                let prefix = cstr(&header[345..500]);
+                if ustar && !prefix.is_empty() {
+                    path.extend_from_slice(prefix);
+                    path.push(b'/');
+                }
+                path.extend_from_slice(cstr(&header[..100]));
+                path
+            }
+        };
+        let field = |value: Option<u64>, range: core::ops::Range<usize>| match value {
+            Some(x) => Ok(x),
+            None => number(&header[range]).ok_or(msg2err!("invalid number")),
+        };
+        let mut kind = match header[156] {
+            0 | b'7' => b'0',
+            b'D' => b'5',
+            x => x,
+        };
+        if kind == b'0' && path.ends_with(b"/") {
+            kind = b'5';
+        }
+        let n = normalize(&mut path);
+        path.truncate(n);
+        let mut member = Member {
+            path,
+            id: pos,
+            kind,
+            mode: field(None, 100..108)?,
+            uid: field(pax.uid, 108..116)?,
+            gid: field(pax.gid, 116..124)?,
+            uname: pax.uname.take().unwrap_or_else(|| cstr(&header[265..297]).into()),
+            gname: pax.gname.take().unwrap_or_else(|| cstr(&header[297..329]).into()),
+            mtime: match pax.mtime {
+                Some(x) => x,
+                None => field(None, 136..148)?
+                    .saturating_mul(1_000_000_000)
+                    .min(i64::MAX as u64) as i64,
+            },
+            atime: pax.atime,
+            ctime: pax.ctime,
+            link: long_link
+                .or(pax.linkpath.take())
+                .unwrap_or_else(|| cstr(&header[157..257]).into()),
+            xattrs: core::mem::take(&mut pax.xattrs),
+            data: pos + BLOCK,
+            size: field(pax.size, 124..136)?,
+            ..Default::default()
+        };
+        if kind == b'2' {
+            member.size = member.link.len() as u64;
+        }
+        if ustar || gnu {
+            let major = field(pax.devmajor, 329..337)?;
+            let minor = field(pax.devminor, 337..345)?;
+            member.rdev = major << 32 | minor;
+        }
+
+        // devices, directories and links have no data
+        let stored = match kind {
+            b'1'..=b'6' => 0,
+            _ => member.size,
+        };
+        let mut end = member.data + stored.next_multiple_of(BLOCK);
+        if kind == b'S' && gnu {
+            member.kind = b'0';
+            self.old_sparse(&header, &mut member, &mut end)?;
+        } else if pax.is_sparse() && kind == b'0' {
+            self.pax_sparse(&pax, &mut member)?;
+        }
+        index.next = Some(end);
+
+        // hard links share the data and the id with their target
+        if kind == b'1' {
+            let mut target = member.link.clone();
+            let n = normalize(&mut target);
+            target.truncate(n);
+            if let Some(target) = index.paths.get(&target).map(|x| &index.members[*x]) {
+                member.kind = target.kind;
+                member.id = target.id;
+                member.link = target.link.clone();
+                member.data = target.data;
+                member.size = target.size;
+                member.sparse = target.sparse.clone();
+                if member.xattrs.is_empty() {
+                    member.xattrs = target.xattrs.clone();
+                }
+            }
+        }
+        index.insert(member);
+        Ok(true)
+    }
+
+    /// Read the sparse map of the old GNU format from the header and its extensions.
+    fn old_sparse(&self, header: &Header, member: &mut Member, end: &mut Offset) -> Result<(), Error> {
+        let mut map = Vec::new();
+        let mut entries = |block: &[u8], count: usize| {
+            for entry in block.chunks(24).take(count) {
+                if entry[0] == 0 {
+                    break;
+                }
+                map.extend(number(&entry[..12]));
+                map.extend(number(&entry[12..]));
+            }
+        };
+        entries(&header[386..482], 4);
+        let mut extended = header[482] != 0;
+        while extended {
+            let mut block = [0; BLOCK as usize];
+            self.disk().read_exact(member.data, &mut block)?;
+            entries(&block, 21);
+            extended = block[504] != 0;
+            member.data += BLOCK;
+            *end += BLOCK;
+        }
+        let stored = member.size;
+        member.size = number(&header[483..495]).ok_or(msg2err!("invalid size"))?;
+        segments(member, &map, stored)
+    }
+
+    /// Read the sparse map of the pax formats.
+    fn pax_sparse(&self, pax: &Pax, member: &mut Member) -> Result<(), Error> {
+        let stored = member.size;
+        if pax.sparse_major != Some(1) {
+            member.size = pax.sparse_size.unwrap_or(stored);
+            return segments(member, &pax.sparse_map, stored);
+        }
+
+        // the map is a list of decimals at the start of the data
+        let (mut map, mut count, mut value) = (Vec::new(), None, Vec::new());
+        let mut block = [0; BLOCK as usize];
+        let mut pos = 0;
+        while count.is_none_or(|count| map.len() < 2 * count) {
+            if pos % BLOCK == 0 {
+                self.disk().read_exact(member.data + pos, &mut block)?;
+            }
+            let x = block[(pos % BLOCK) as usize];
+            pos += 1;
+            if x != b'\n' {
+                value.push(x);
+                continue;
+            }
+            let number = decimal(&value).ok_or(msg2err!("invalid sparse map"))?;
+            value.clear();
+            match count {
+                None if number > stored => return Err(msg2err!("invalid sparse map")),
+                None => count = Some(number as usize),
+                Some(_) => map.push(number),
+            }
+        }
+        let skip = pos.next_multiple_of(BLOCK);
+        member.data += skip;
+        member.size = pax.sparse_size.unwrap_or(0);
+        segments(member, &map, stored.saturating_sub(skip))
+    }
+}
+
+/// Convert the offset and length pairs into segments.
+fn segments(member: &mut Member, map: &[u64], stored: u64) -> Result<(), Error> {
+    let mut data = member.data;
+    for pair in map.chunks_exact(2) {
+        let (offset, len) = (pair[0], pair[1]);
+        if data + len > member.data + stored || member.sparse.last().is_some_and(|x| x.offset + x.len > offset) {
+            return Err(msg2err!("invalid sparse map"));
+        }
+        member.sparse.push(Segment { offset, len, data });
+        member.size = member.size.max(offset + len);
+        data += len;
+    }
+    Ok(())
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for TarFs<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        // the first member might describe the root
+        self.member(1)?;
+        Ok(file::File::new(self, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{
+        attr::{self, Attributes, Value},
+        directory::DirIterator,
+        file::{File, FileType},
+    };
+    use ap_storage_memory::ReadSlice;
+    use std::{os::unix::fs::symlink, process::Command};
+
+    /// Append a ustar header and its padded data.
+    fn append(tar: &mut Vec<u8>, name: &str, typ: u8, data: &[u8]) {
+        let mut header = [0; BLOCK as usize];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(std::format!("{:011o}", data.len()).as_bytes());
+        header[136..147].copy_from_slice(b"00000000012");
+        header[156] = typ;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|x| *x as u32).sum();
+        header[148..155].copy_from_slice(std::format!("{:06o}\0", sum).as_bytes());
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(data);
+        tar.resize(tar.len().next_multiple_of(BLOCK as usize), 0);
+    }
+
+    fn read_all(file: &impl Read) -> Vec<u8> {
+        let mut res = Vec::new();
+        let mut buf = [0; 1000];
+        loop {
+            let n = file.read_bytes(res.len() as u64, &mut buf).unwrap();
+            if n == 0 {
+                return res;
+            }
+            res.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn pax() {
+        let long = "dir/".repeat(40) + "file";
+        let mut records = Vec::new();
+        for (key, value) in [
+            ("path", long.as_str()),
+            ("mtime", "1234567890.5"),
+            ("SCHILY.xattr.user.key", "value"),
+        ] {
+            let len = key.len() + value.len() + 3;
+            let len = len + std::format!("{}", len + 2).len();
+            records.extend_from_slice(std::format!("{len} {key}={value}\n").as_bytes());
+        }
+        let mut tar = Vec::new();
+        append(&mut tar, "PaxHeader", b'x', &records);
+        append(&mut tar, "short", b'0', b"hello");
+        append(&mut tar, "./a/b/", b'5', b"");
+        tar.resize(tar.len() + 2 * BLOCK as usize, 0);
+
+        let disk = ReadSlice(&tar);
+        let fs = TarFs::new(&disk).unwrap();
+        let root = fs.root().unwrap();
+        let file = root.clone().lookup_path(long.as_bytes()).unwrap();
+        assert_eq!(read_all(&file), b"hello");
+        assert!(matches!(
+            file.attr().get(attr::MTIME, &mut []),
+            Some(Value::Time(1_234_567_890_500_000_000))
+        ));
+        let mut buf = [0; 16];
+        assert_eq!(file.xattr(b"user.key", &mut buf).unwrap(), Some(5));
+        assert_eq!(&buf[..5], b"value");
+
+        // implied directories are listed once
+        let mut dir = root.dir().unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = dir.next(&mut buf).unwrap() {
+            assert_eq!(entry.typ, FileType::Directory);
+            names.push(buf[..entry.nlen].to_vec());
+        }
+        assert_eq!(names, [&b"dir"[..], b"a"]);
+        let b = root.lookup_path(b"a/b").unwrap();
+        assert!(matches!(
+            b.attr().get(attr::MTIME, &mut []),
+            Some(Value::Time(10_000_000_000))
+        ));
+        assert_eq!(fs.index_all().unwrap(), 44);
+    }
+
+    #[test]
+    fn gnu_tar() {
+        let src = std::env::temp_dir().join("ap-storage-tar");
+        let _ = std::fs::remove_dir_all(&src);
+        let deep = src.join("x".repeat(120));
+        std::fs::create_dir_all(&deep).unwrap();
+        let file = std::fs::File::create(src.join("sparse")).unwrap();
+        for i in 0..10u8 {
+            std::os::unix::fs::FileExt::write_at(&file, &[i + 1; 1000], i as u64 * 100_000).unwrap();
+        }
+        file.set_len(2_000_000).unwrap();
+        std::fs::write(deep.join("y".repeat(120)), b"deep").unwrap();
+        std::fs::hard_link(src.join("sparse"), deep.join("link")).unwrap();
+        symlink("z".repeat(200), src.join("symlink")).unwrap();
+
+        for format in ["gnu", "posix"] {
+            let name = src.with_extension("tar");
+            let res = Command::new("tar")
+                .args(["--sparse", "-cf", name.to_str().unwrap(), "--format", format, "-C"])
+                .arg(&src)
+                .arg(".")
+                .status();
+            let Ok(true) = res.map(|x| x.success()) else {
+                break;
+            };
+            let data = std::fs::read(&name).unwrap();
+            let _ = std::fs::remove_file(name);
+            assert!(data.len() < 100_000);
+
+            let disk = ReadSlice(&data);
+            let fs = TarFs::new(&disk).unwrap();
+            let root = fs.root().unwrap();
+            let sparse = root.clone().lookup_path(b"sparse").unwrap();
+            let content = read_all(&sparse);
+            assert_eq!(content.len(), 2_000_000);
+            for (i, x) in content.iter().enumerate() {
+                let expected = match i % 100_000 {
+                    0..1000 if i < 1_000_000 => (i / 100_000) as u8 + 1,
+                    _ => 0,
+                };
+                assert_eq!(*x, expected);
+            }
+
+            let path = std::format!("{}/{}", "x".repeat(120), "y".repeat(120));
+            assert_eq!(read_all(&root.clone().lookup_path(path.as_bytes()).unwrap()), b"deep");
+            let path = std::format!("{}/link", "x".repeat(120));
+            let link = root.clone().lookup_path(path.as_bytes()).unwrap();
+            let id = |f: &file::File<_>| f.attr().get(attr::ID, &mut []).and_then(|x| x.as_u64());
+            assert_eq!(id(&link), id(&sparse));
+            assert_eq!(read_all(&link), content);
+
+            let symlink = root.lookup_path(b"symlink").unwrap();
+            assert_eq!(symlink.ftype(), FileType::SymLink);
+            assert_eq!(read_all(&symlink), "z".repeat(200).as_bytes());
+        }
+        std::fs::remove_dir_all(src).unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+//! Helpers shared by the tests of the filesystem drivers.
+
+use ap_storage::{
+    attr::{self, Attributes},
+    directory::DirIterator,
+    file::{File, FileType},
+    Read,
+};
+
+/// Repeat a period until the data has the given length.
+pub fn pattern(period: &[u8], len: usize) -> Vec<u8> {
+    period.iter().copied().cycle().take(len).collect()
+}
+
+/// Count up to a modulo and wrap around until the data has the given length.
+pub fn ramp(len: usize, modulo: usize) -> Vec<u8> {
+    (0..len).map(|x| (x % modulo) as u8).collect()
+}
+
+/// Read a file to its end with a buffer that is not a multiple of the block size.
+pub fn read_all(file: &(impl Read + ?Sized)) -> Vec<u8> {
+    let mut res = Vec::new();
+    let mut buf = [0; 1000];
+    loop {
+        let n = file.read_bytes(res.len() as u64, &mut buf).unwrap();
+        if n == 0 {
+            return res;
+        }
+        res.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// List the names and types in a directory.
+///
+/// Every entry must open by its offset to a file with the ID of the entry.
+pub fn list<F: File>(dir: &F) -> Vec<(String, FileType)> {
+    let id = |file: &F| file.attr().get(attr::ID, &mut []).and_then(|x| x.as_u64());
+    let mut res = Vec::new();
+    let mut iter = dir.dir().unwrap();
+    let mut buf = [0; 256];
+    while let Some(entry) = iter.next(&mut buf).unwrap() {
+        res.push((String::from_utf8_lossy(&buf[..entry.nlen]).into_owned(), entry.typ));
+        assert_eq!(id(&dir.open(entry.offset).unwrap()), Some(entry.id));
+    }
+    res
+}
+
+/// The names of a listing.
+pub fn names(list: &[(String, FileType)]) -> Vec<&str> {
+    list.iter().map(|x| x.0.as_str()).collect()
+}
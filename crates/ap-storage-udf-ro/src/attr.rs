@@ -0,0 +1,76 @@
+//! File attributes for UDF.
+
+use super::file::File;
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::Read;
+use ap_util_slice_writer::*;
+
+new_attr!(NLINKS, U64, "Number of hard-links to this file.");
+
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
+}
+
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            NLINKS,
+            attr::ATIME,
+            attr::BTIME,
+            attr::CTIME,
+            attr::FTYPE,
+            attr::GID,
+            attr::ID,
+            attr::MODE,
+            attr::MTIME,
+            attr::SIZE,
+            attr::UID,
+        ]
+        .iter()
+    }
+}
+
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let entry = &self.file.entry;
+        Some(match name {
+            NLINKS => (entry.nlink as u64).into(),
+            attr::ATIME => Value::Time(entry.atime),
+            attr::BTIME => Value::Time(entry.btime?),
+            attr::CTIME => Value::Time(entry.ctime),
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            attr::GID => (entry.gid as u64).into(),
+            attr::ID => self.file.id().into(),
+            attr::MODE => self.file.mode().into(),
+            attr::MTIME => Value::Time(entry.mtime),
+            attr::SIZE => entry.size.into(),
+            attr::UID => (entry.uid as u64).into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                NLINKS,
+                attr::ATIME,
+                attr::BTIME,
+                attr::CTIME,
+                attr::FTYPE,
+                attr::GID,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::SIZE,
+                attr::UID,
+            ]
+        )
+    }
+}
@@ -0,0 +1,98 @@
+//! Directories as a sequence of file identifier descriptors.
+
+use super::{check_tag, file::File, le16, Lba, MAX_BLOCK, TAG_FILE_ID};
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    file::FileType,
+    msg2err, Error, Read, ReadExt,
+};
+
+/// The file characteristics.
+const FID_DIRECTORY: u8 = 1 << 1;
+const FID_DELETED: u8 = 1 << 2;
+const FID_PARENT: u8 = 1 << 3;
+
+/// The size of a file identifier descriptor before the implementation use.
+const FID_HEADER: usize = 38;
+
+/// A file identifier descriptor.
+pub(crate) struct Fid {
+    pub characteristics: u8,
+    pub icb: Lba,
+    /// The range of the CS0 name in the buffer.
+    pub name: (usize, usize),
+    /// The length including the padding.
+    pub len: usize,
+}
+
+impl Fid {
+    /// Read the descriptor at an offset of the directory into the buffer.
+    pub fn read<D: Read + ?Sized>(file: &File<D>, offset: u64, buf: &mut [u8; MAX_BLOCK]) -> Result<Self, Error> {
+        let dir = file as &dyn Read;
+        dir.read_exact(offset, &mut buf[..FID_HEADER])?;
+        let (name_len, iu_len) = (buf[19] as usize, le16(buf, 36) as usize);
+        let len = (FID_HEADER + iu_len + name_len + 3) & !3;
+        if len > MAX_BLOCK {
+            return Err(msg2err!("file identifier too large"));
+        }
+        dir.read_exact(offset + FID_HEADER as u64, &mut buf[FID_HEADER..len])?;
+        check_tag(&buf[..len], TAG_FILE_ID, None)?;
+        Ok(Self {
+            characteristics: buf[18],
+            icb: Lba::parse(buf, 24),
+            name: (FID_HEADER + iu_len, FID_HEADER + iu_len + name_len),
+            len,
+        })
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.characteristics & FID_DELETED != 0
+    }
+}
+
+/// Iterate over the file identifier descriptors of a directory.
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
+    offset: u64,
+}
+
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Self {
+        Self { file, offset: 0 }
+    }
+}
+
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        let mut buf = [0; MAX_BLOCK];
+        while self.offset < self.file.entry.size {
+            let (offset, fid) = (self.offset, Fid::read(self.file, self.offset, &mut buf)?);
+            self.offset += fid.len as u64;
+            if fid.is_deleted() {
+                continue;
+            }
+            let (nlen, typ) = match fid.characteristics {
+                x if x & FID_PARENT != 0 => {
+                    let n = core::cmp::min(name.len(), 2);
+                    name[..n].copy_from_slice(&b".."[..n]);
+                    (2, FileType::Parent)
+                }
+                x => (
+                    ap_util_cs0::decode(&buf[fid.name.0..fid.name.1], name)
+                        .ok_or(msg2err!("invalid file identifier"))?,
+                    match x & FID_DIRECTORY {
+                        0 => FileType::File,
+                        _ => FileType::Directory,
+                    },
+                ),
+            };
+            return Ok(Some(DirEntry {
+                offset,
+                id: fid.icb.id(),
+                nlen,
+                typ,
+            }));
+        }
+        Ok(None)
+    }
+}
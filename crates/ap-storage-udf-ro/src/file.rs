@@ -0,0 +1,200 @@
+//! Files on UDF.
+
+use super::{
+    attr::Attr,
+    dir::{Dir, Fid},
+    icb::{Entry, Extent},
+    Lba, Udf, MAX_BLOCK,
+};
+use ap_storage::{directory::DirIterator, file::FileType, msg2err, Error, Offset, Read};
+use core::cell::Cell;
+
+/// The file types of the ICB tag.
+const TYPE_DIRECTORY: u8 = 4;
+const TYPE_FILE: u8 = 5;
+const TYPE_BLOCK_DEVICE: u8 = 6;
+const TYPE_CHAR_DEVICE: u8 = 7;
+const TYPE_FIFO: u8 = 9;
+const TYPE_SOCKET: u8 = 10;
+const TYPE_SYMLINK: u8 = 12;
+
+/// The ICB flags with the setuid, setgid and sticky bits.
+const FLAG_SETUID: u16 = 1 << 6;
+const FLAG_SETGID: u16 = 1 << 7;
+const FLAG_STICKY: u16 = 1 << 8;
+
+/// The largest symbolic link as path components.
+const MAX_LINK: usize = 1024;
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a Udf<'a, D>,
+    pub(crate) entry: Entry,
+    /// The last extent to speedup linear reads.
+    cache: Cell<Option<Extent>>,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            entry: self.entry,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("fs", &self.fs)
+            .field("entry", &self.entry)
+            .finish()
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    /// Open the file by the location of its ICB.
+    pub fn new(fs: &'a Udf<'a, D>, location: Lba) -> Result<Self, Error> {
+        Ok(Self {
+            fs,
+            entry: Entry::read(fs, location)?,
+            cache: Cell::new(None),
+        })
+    }
+
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    pub fn id(&self) -> u64 {
+        self.entry.location.id()
+    }
+
+    pub fn ftype(&self) -> FileType {
+        match self.entry.file_type {
+            TYPE_DIRECTORY => FileType::Directory,
+            TYPE_FILE => FileType::File,
+            TYPE_BLOCK_DEVICE => FileType::BlockDevice,
+            TYPE_CHAR_DEVICE => FileType::CharDevice,
+            TYPE_FIFO => FileType::Fifo,
+            TYPE_SOCKET => FileType::Socket,
+            TYPE_SYMLINK => FileType::SymLink,
+            _ => FileType::Unknown,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.entry.file_type == TYPE_DIRECTORY
+    }
+
+    /// The POSIX permission bits from the read, write and execute bits of the owner, group and others.
+    pub fn mode(&self) -> u64 {
+        let (p, flags) = (self.entry.permissions as u64, self.entry.flags);
+        let mut mode = (p & 7) | (p >> 5 & 7) << 3 | (p >> 10 & 7) << 6;
+        for (flag, bit) in [(FLAG_SETUID, 0o4000), (FLAG_SETGID, 0o2000), (FLAG_STICKY, 0o1000)] {
+            if flags & flag != 0 {
+                mode |= bit;
+            }
+        }
+        mode
+    }
+
+    /// Decode the path components of a symbolic link into the buffer and return the length.
+    fn link(&self, out: &mut [u8; 2 * MAX_LINK]) -> Result<usize, Error> {
+        let mut raw = [0; MAX_LINK];
+        let raw = raw
+            .get_mut(..self.entry.size as usize)
+            .ok_or(msg2err!("symbolic link too long"))?;
+        let mut pos = 0;
+        while pos < raw.len() {
+            let x = self
+                .fs
+                .read_data(&self.entry, &self.cache, pos as u64, &mut raw[pos..])?;
+            if x == 0 {
+                return Err(msg2err!("truncated symbolic link"));
+            }
+            pos += x;
+        }
+        let (mut pos, mut n) = (0, 0);
+        while pos + 4 <= raw.len() {
+            let (typ, len) = (raw[pos], raw[pos + 1] as usize);
+            let ident = raw
+                .get(pos + 4..pos + 4 + len)
+                .ok_or(msg2err!("truncated path component"))?;
+            pos += 4 + len;
+            if matches!(typ, 1 | 2) {
+                (out[0], n) = (b'/', 1);
+                continue;
+            }
+            if n != 0 && out[n - 1] != b'/' {
+                out[n] = b'/';
+                n += 1;
+            }
+            // a path component does not get longer than twice its size
+            n += match typ {
+                3 => {
+                    out[n..n + 2].copy_from_slice(b"..");
+                    2
+                }
+                4 => {
+                    out[n] = b'.';
+                    1
+                }
+                5 => ap_util_cs0::decode(ident, &mut out[n..]).ok_or(msg2err!("invalid path component"))?,
+                _ => return Err(msg2err!("invalid path component")),
+            };
+        }
+        Ok(n)
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.is_dir() {
+            return Some(Dir::new(self));
+        }
+        None
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        let fid = Fid::read(self, offset, &mut [0; MAX_BLOCK])?;
+        if fid.is_deleted() {
+            return Err(msg2err!("deleted file"));
+        }
+        Self::new(self.fs, fid.icb)
+    }
+
+    fn lookup(&self, name: &[u8]) -> Result<Option<Self>, Error> {
+        let mut dir = self.dir().ok_or(msg2err!("not a directory"))?;
+        let mut buf = [0; 3 * 255];
+        while let Some(entry) = dir.next(&mut buf)? {
+            if buf.get(..entry.nlen) == Some(name) {
+                return self.open(entry.offset).map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.entry.file_type != TYPE_SYMLINK {
+            return self.fs.read_data(&self.entry, &self.cache, offset, buf);
+        }
+        let mut link = [0; 2 * MAX_LINK];
+        let n = self.link(&mut link)?;
+        let link = link.get(offset as usize..n).unwrap_or_default();
+        let n = core::cmp::min(link.len(), buf.len());
+        buf[..n].copy_from_slice(&link[..n]);
+        Ok(n)
+    }
+}
@@ -0,0 +1,172 @@
+//! File entries and their allocation descriptors.
+
+use super::{
+    check_tag, le16, le32, le64, Lba, Udf, MAX_BLOCK, TAG_ALLOCATION_EXTENT, TAG_EXTENDED_FILE_ENTRY, TAG_FILE_ENTRY,
+};
+use ap_storage::{msg2err, Error, Offset, Read, ReadExt};
+use ap_util_date::{date2ts, time2ts};
+
+/// The allocation descriptor types in the ICB flags.
+const AD_MASK: u16 = 7;
+const AD_SHORT: u16 = 0;
+const AD_LONG: u16 = 1;
+const AD_INLINE: u16 = 3;
+
+/// The extent types in the upper bits of the length.
+const EXTENT_RECORDED: u32 = 0;
+const EXTENT_NEXT: u32 = 3;
+
+/// The ICB strategy of a single direct entry.
+const STRATEGY_DIRECT: u16 = 4;
+
+/// How many allocation extent descriptors are followed.
+const MAX_CHAIN: usize = 4096;
+
+/// A file entry or an extended file entry.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub location: Lba,
+    pub file_type: u8,
+    /// The ICB flags with the allocation type and the setuid, setgid and sticky bits.
+    pub flags: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub permissions: u32,
+    pub nlink: u16,
+    pub size: u64,
+    /// The times in nanoseconds, where only extended file entries have a creation time.
+    pub atime: i64,
+    pub mtime: i64,
+    pub ctime: i64,
+    pub btime: Option<i64>,
+    /// The disk position and length of the allocation descriptors or the inline data.
+    pub(crate) ads: Offset,
+    pub(crate) ads_len: u32,
+}
+
+/// An extent of a file.
+#[derive(Debug, Clone, Copy)]
+pub struct Extent {
+    /// The position in the file and the length in bytes.
+    pub offset: u64,
+    pub len: u32,
+    /// The first block unless the extent is not recorded and reads as zeros.
+    pub start: Option<Lba>,
+}
+
+impl Entry {
+    /// Read the entry from the ICB at the location.
+    pub fn read<D: Read + ?Sized>(fs: &Udf<D>, location: Lba) -> Result<Self, Error> {
+        let mut buf = [0; MAX_BLOCK];
+        let buf = &mut buf[..fs.block_size as usize];
+        let (pos, _) = fs.position(location)?;
+        fs.disk().read_exact(pos, buf)?;
+        let id = le16(buf, 0);
+        let header = match id {
+            TAG_FILE_ENTRY => 176,
+            TAG_EXTENDED_FILE_ENTRY => 216,
+            _ => return Err(msg2err!("not a file entry")),
+        };
+        check_tag(buf, id, Some(location.block))?;
+        if le16(buf, 20) != STRATEGY_DIRECT {
+            return Err(msg2err!("unsupported ICB strategy"));
+        }
+        let (ea_len, ads_len) = (le32(buf, header - 8) as usize, le32(buf, header - 4));
+        let ads = header
+            .checked_add(ea_len)
+            .filter(|x| x.saturating_add(ads_len as usize) <= buf.len())
+            .ok_or(msg2err!("invalid file entry"))?;
+        let time = |pos: usize| timestamp(&buf[pos..pos + 12]);
+        let extended = id == TAG_EXTENDED_FILE_ENTRY;
+        Ok(Self {
+            location,
+            file_type: buf[27],
+            flags: le16(buf, 34),
+            uid: le32(buf, 36),
+            gid: le32(buf, 40),
+            permissions: le32(buf, 44),
+            nlink: le16(buf, 48),
+            size: le64(buf, 56),
+            atime: time(if extended { 80 } else { 72 }),
+            mtime: time(if extended { 92 } else { 84 }),
+            ctime: time(if extended { 116 } else { 96 }),
+            btime: extended.then(|| time(104)),
+            ads: pos + ads as u64,
+            ads_len,
+        })
+    }
+
+    /// Whether the data is stored instead of the allocation descriptors.
+    pub fn is_inline(&self) -> bool {
+        self.flags & AD_MASK == AD_INLINE
+    }
+
+    /// Find the extent that contains the offset by walking the allocation descriptors.
+    pub fn extent<D: Read + ?Sized>(&self, fs: &Udf<D>, offset: u64) -> Result<Option<Extent>, Error> {
+        let size = match self.flags & AD_MASK {
+            AD_SHORT => 8,
+            AD_LONG => 16,
+            _ => return Err(msg2err!("unsupported allocation descriptors")),
+        };
+        let mut buf = [0; MAX_BLOCK];
+        let (mut pos, mut end) = (0, self.ads_len as usize);
+        fs.disk().read_exact(self.ads, &mut buf[..end])?;
+        let mut start = 0u64;
+        for _ in 0..MAX_CHAIN {
+            let next = loop {
+                if pos + size > end {
+                    return Ok(None);
+                }
+                let ad = &buf[pos..pos + size];
+                let (typ, len) = (le32(ad, 0) >> 30, le32(ad, 0) & 0x3fff_ffff);
+                let location = match size {
+                    8 => Lba {
+                        part: self.location.part,
+                        block: le32(ad, 4),
+                    },
+                    _ => Lba::parse(ad, 4),
+                };
+                if len == 0 {
+                    return Ok(None);
+                }
+                if typ == EXTENT_NEXT {
+                    break location;
+                }
+                if offset < start + len as u64 {
+                    return Ok(Some(Extent {
+                        offset: start,
+                        len,
+                        start: (typ == EXTENT_RECORDED).then_some(location),
+                    }));
+                }
+                start += len as u64;
+                pos += size;
+            };
+
+            // the descriptors continue in an allocation extent descriptor
+            let bs = fs.block_size as usize;
+            fs.disk().read_exact(fs.position(next)?.0, &mut buf[..bs])?;
+            check_tag(&buf[..bs], TAG_ALLOCATION_EXTENT, Some(next.block))?;
+            (pos, end) = (24, 24 + le32(&buf, 20) as usize);
+            if end > bs {
+                return Err(msg2err!("invalid allocation extent descriptor"));
+            }
+        }
+        Err(msg2err!("too many allocation extent descriptors"))
+    }
+}
+
+/// A timestamp in nanoseconds since 1970.
+fn timestamp(buf: &[u8]) -> i64 {
+    let (typ, year) = (le16(buf, 0), le16(buf, 2) as i16);
+    let mut ts = date2ts(buf[5] as u32, buf[4] as u32, year.max(0) as u32)
+        + time2ts(buf[6] as u32, buf[7] as u32, buf[8] as u32);
+
+    // local times have a signed offset in minutes in the lower 12 bits, where -2047 is unknown
+    let offset = ((typ << 4) as i16) >> 4;
+    if typ >> 12 == 1 && offset != -2047 {
+        ts -= offset as i64 * 60;
+    }
+    let fraction = buf[9] as i64 * 10_000_000 + buf[10] as i64 * 100_000 + buf[11] as i64 * 1000;
+    ts.saturating_mul(1_000_000_000).saturating_add(fraction)
+}
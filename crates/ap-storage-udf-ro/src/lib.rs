@@ -0,0 +1,706 @@
+//! Read UDF filesystems.
+//!
+//! - anchor volume descriptor pointer at sector 256 with blocks from 512 bytes up to 4K
+//! - volume descriptor sequences with pointers, partition and logical volume descriptors
+//! - type 1 partition maps and the metadata partitions of UDF 2.50, with a fallback to the metadata mirror file
+//! - file set descriptor, file entries and extended file entries
+//! - short, long and inline allocation descriptors, continued by allocation extent descriptors
+//! - directories of file identifier descriptors and symbolic links
+//!
+//! Reading needs no allocations.  Descriptors are checked by their tag checksum and CRC.  Virtual and sparable
+//! partitions, the ICB strategy 4096 and extended allocation descriptors are not supported.  Names are decoded from
+//! OSTA CS0 into UTF-8.  The offset of a directory entry is the position of its file identifier descriptor in the
+//! directory.  The ID of a file is the partition reference number of its ICB in the upper and the logical block in
+//! the lower 32 bits.
+
+#![no_std]
+
+use ap_storage::{msg2err, Error, FileSystem, Offset, Read, ReadExt};
+use core::cell::Cell;
+
+mod attr;
+mod dir;
+pub mod file;
+mod icb;
+
+pub use icb::{Entry, Extent};
+
+/// The largest logical block.
+pub const MAX_BLOCK: usize = 4096;
+
+/// The sector of the anchor volume descriptor pointer.
+const ANCHOR: u32 = 256;
+
+/// The tag identifiers.
+pub const TAG_ANCHOR: u16 = 2;
+pub const TAG_POINTER: u16 = 3;
+pub const TAG_PARTITION: u16 = 5;
+pub const TAG_LOGICAL_VOLUME: u16 = 6;
+pub const TAG_TERMINATING: u16 = 8;
+pub const TAG_FILE_SET: u16 = 256;
+pub const TAG_FILE_ID: u16 = 257;
+pub const TAG_ALLOCATION_EXTENT: u16 = 258;
+pub const TAG_FILE_ENTRY: u16 = 261;
+pub const TAG_EXTENDED_FILE_ENTRY: u16 = 266;
+
+/// The partition maps and partitions of a volume.
+const MAX_PARTITIONS: usize = 4;
+
+/// How many descriptors of the volume descriptor sequence are read, including pointers.
+const MAX_DESCRIPTORS: usize = 256;
+
+/// The identifier of a metadata partition map.
+const METADATA_PARTITION: &[u8] = b"*UDF Metadata Partition";
+
+/// A logical block address by the partition reference number and the block in the partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lba {
+    pub part: u16,
+    pub block: u32,
+}
+
+impl Lba {
+    pub(crate) fn parse(buf: &[u8], pos: usize) -> Self {
+        Self {
+            block: le32(buf, pos),
+            part: le16(buf, pos + 4),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        (self.part as u64) << 32 | self.block as u64
+    }
+}
+
+/// A partition descriptor.
+#[derive(Debug, Clone, Copy)]
+pub struct Partition {
+    pub number: u16,
+    /// The first sector and the length in sectors.
+    pub start: u32,
+    pub length: u32,
+    seq: u32,
+}
+
+/// A partition map of the logical volume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionMap {
+    /// A physical partition by its number.
+    Physical(u16),
+    /// A metadata partition inside a physical one with the blocks of the metadata file and of its mirror.
+    Metadata { partition: u16, file: u32, mirror: u32 },
+}
+
+pub struct Udf<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+    block_size: u32,
+    partitions: [Option<Partition>; MAX_PARTITIONS],
+    maps: [Option<PartitionMap>; MAX_PARTITIONS],
+    /// The metadata file and its last extent.
+    metadata: Option<Entry>,
+    metadata_cache: Cell<Option<Extent>>,
+    /// The logical volume identifier as dstring.
+    label: [u8; 128],
+    root: Lba,
+}
+
+impl<D: ?Sized> core::fmt::Debug for Udf<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(fmt, "Udf(bs {}, maps {:?})", self.block_size, self.maps)
+    }
+}
+
+impl<'a, D: Read + ?Sized> Udf<'a, D> {
+    pub fn new(disk: &'a D) -> Result<Self, Error> {
+        let mut buf = [0; MAX_BLOCK];
+        let block_size = [2048, 512, 1024, 4096]
+            .into_iter()
+            .find(|bs| {
+                let buf = &mut buf[..*bs];
+                (&disk as &dyn Read).read_exact(ANCHOR as u64 * *bs as u64, buf).is_ok()
+                    && check_tag(buf, TAG_ANCHOR, Some(ANCHOR)).is_ok()
+            })
+            .ok_or(msg2err!("no anchor volume descriptor"))?;
+        let mut fs = Self {
+            disk,
+            block_size: block_size as u32,
+            partitions: [None; MAX_PARTITIONS],
+            maps: [None; MAX_PARTITIONS],
+            metadata: None,
+            metadata_cache: Cell::new(None),
+            label: [0; 128],
+            root: Lba { part: 0, block: 0 },
+        };
+        let fsd = fs.volume(le32(&buf, 20), le32(&buf, 16))?;
+
+        // the metadata file is in the physical partition that is mapped as well
+        if let Some(PartitionMap::Metadata {
+            partition,
+            file,
+            mirror,
+        }) = fs
+            .maps
+            .iter()
+            .flatten()
+            .copied()
+            .find(|x| matches!(x, PartitionMap::Metadata { .. }))
+        {
+            let part = fs
+                .maps
+                .iter()
+                .position(|x| *x == Some(PartitionMap::Physical(partition)))
+                .ok_or(msg2err!("metadata partition without physical map"))? as u16;
+            let metadata = match Entry::read(&fs, Lba { part, block: file }) {
+                Ok(x) => x,
+                Err(_) => Entry::read(&fs, Lba { part, block: mirror })?,
+            };
+            fs.metadata = Some(metadata);
+        }
+
+        let buf = &mut buf[..block_size];
+        fs.disk().read_exact(fs.position(fsd)?.0, buf)?;
+        check_tag(buf, TAG_FILE_SET, Some(fsd.block))?;
+        fs.root = Lba::parse(buf, 404);
+        Ok(fs)
+    }
+
+    /// Read the volume descriptor sequence and return the location of the file set descriptor.
+    fn volume(&mut self, mut location: u32, length: u32) -> Result<Lba, Error> {
+        let bs = self.block_size as usize;
+        let mut buf = [0; MAX_BLOCK];
+        let buf = &mut buf[..bs];
+        let (mut count, mut lvd_seq, mut fsd) = (length / bs as u32, None, None);
+        for _ in 0..MAX_DESCRIPTORS {
+            if count == 0 {
+                break;
+            }
+            self.disk().read_exact(location as u64 * bs as u64, buf)?;
+            let id = le16(buf, 0);
+            if id == 0 || id == TAG_TERMINATING {
+                break;
+            }
+            check_tag(buf, id, Some(location))?;
+            (location, count) = (location + 1, count - 1);
+            match id {
+                TAG_POINTER => (location, count) = (le32(buf, 24), le32(buf, 20) / bs as u32),
+                TAG_PARTITION => {
+                    if !matches!(&buf[25..31], b"+NSR02" | b"+NSR03") {
+                        continue;
+                    }
+                    let partition = Partition {
+                        number: le16(buf, 22),
+                        start: le32(buf, 188),
+                        length: le32(buf, 192),
+                        seq: le32(buf, 16),
+                    };
+                    let slot = self
+                        .partitions
+                        .iter_mut()
+                        .find(|x| x.is_none_or(|x| x.number == partition.number))
+                        .ok_or(msg2err!("too many partitions"))?;
+                    if slot.is_none_or(|x| x.seq <= partition.seq) {
+                        *slot = Some(partition);
+                    }
+                }
+                TAG_LOGICAL_VOLUME => {
+                    let seq = le32(buf, 16);
+                    if lvd_seq.is_some_and(|x| x > seq) {
+                        continue;
+                    }
+                    lvd_seq = Some(seq);
+                    if le32(buf, 212) != self.block_size {
+                        return Err(msg2err!("unsupported logical block size"));
+                    }
+                    self.label.copy_from_slice(&buf[84..212]);
+                    fsd = Some(Lba::parse(buf, 252));
+                    self.maps = partition_maps(buf)?;
+                }
+                _ => {}
+            }
+        }
+        fsd.ok_or(msg2err!("no logical volume descriptor"))
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    /// Decode the logical volume identifier into the buffer and return its full length.
+    pub fn label(&self, buf: &mut [u8]) -> usize {
+        ap_util_cs0::decode_dstring(&self.label, buf).unwrap_or_default()
+    }
+
+    fn partition(&self, number: u16) -> Result<&Partition, Error> {
+        self.partitions
+            .iter()
+            .flatten()
+            .find(|x| x.number == number)
+            .ok_or(msg2err!("unknown partition"))
+    }
+
+    /// The disk position of a block in a physical partition and the number of bytes until the partition ends.
+    fn physical(&self, part: u16, block: u32) -> Result<(Offset, u64), Error> {
+        let Some(Some(PartitionMap::Physical(number))) = self.maps.get(part as usize) else {
+            return Err(msg2err!("not a physical partition"));
+        };
+        let (partition, bs) = (self.partition(*number)?, self.block_size as u64);
+        if block >= partition.length {
+            return Err(msg2err!("block outside of the partition"));
+        }
+        Ok((
+            (partition.start as u64 + block as u64) * bs,
+            (partition.length - block) as u64 * bs,
+        ))
+    }
+
+    /// The disk position of a logical block and the number of bytes that follow it contiguously.
+    pub fn position(&self, lba: Lba) -> Result<(Offset, u64), Error> {
+        let map = self.maps.get(lba.part as usize).copied().flatten();
+        match map.ok_or(msg2err!("invalid partition reference"))? {
+            PartitionMap::Physical(_) => self.physical(lba.part, lba.block),
+            PartitionMap::Metadata { .. } => {
+                let metadata = self.metadata.as_ref().ok_or(msg2err!("no metadata file"))?;
+                let bs = self.block_size as u64;
+                let offset = lba.block as u64 * bs;
+                if offset >= metadata.size {
+                    return Err(msg2err!("block outside of the metadata partition"));
+                }
+                let extent = self.extent(metadata, &self.metadata_cache, offset)?;
+                let start = extent.start.ok_or(msg2err!("unrecorded metadata"))?;
+                let within = offset - extent.offset;
+                let block = u32::try_from(within / bs)
+                    .ok()
+                    .and_then(|x| x.checked_add(start.block))
+                    .ok_or(msg2err!("invalid metadata extent"))?;
+                let (pos, contiguous) = self.physical(start.part, block)?;
+                Ok((pos, core::cmp::min(contiguous, extent.len as u64 - within)))
+            }
+        }
+    }
+
+    /// The extent of a file entry at an offset, which caches the last one.
+    fn extent(&self, entry: &Entry, cache: &Cell<Option<Extent>>, offset: u64) -> Result<Extent, Error> {
+        if let Some(x) = cache
+            .get()
+            .filter(|x| x.offset <= offset && offset < x.offset + x.len as u64)
+        {
+            return Ok(x);
+        }
+        let extent = entry
+            .extent(self, offset)?
+            .ok_or(msg2err!("file larger than its extents"))?;
+        cache.set(Some(extent));
+        Ok(extent)
+    }
+
+    /// Read the data of a file entry, which stops at the end of an extent.
+    pub(crate) fn read_data(
+        &self,
+        entry: &Entry,
+        cache: &Cell<Option<Extent>>,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        if offset >= entry.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let n = core::cmp::min(buf.len() as u64, entry.size - offset);
+        if entry.is_inline() {
+            if entry.size > entry.ads_len as u64 {
+                return Err(msg2err!("truncated inline data"));
+            }
+            let buf = &mut buf[..n as usize];
+            self.disk().read_exact(entry.ads + offset, buf)?;
+            return Ok(buf.len());
+        }
+        let extent = self.extent(entry, cache, offset)?;
+        let within = offset - extent.offset;
+        let n = core::cmp::min(n, extent.len as u64 - within);
+        let Some(start) = extent.start else {
+            buf[..n as usize].fill(0);
+            return Ok(n as usize);
+        };
+        let bs = self.block_size as u64;
+        let block = u32::try_from(within / bs)
+            .ok()
+            .and_then(|x| x.checked_add(start.block))
+            .ok_or(msg2err!("invalid extent"))?;
+        let (pos, contiguous) = self.position(Lba {
+            part: start.part,
+            block,
+        })?;
+        let buf = &mut buf[..core::cmp::min(n, contiguous - within % bs) as usize];
+        self.disk().read_exact(pos + within % bs, buf)?;
+        Ok(buf.len())
+    }
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for Udf<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        file::File::new(self, self.root)
+    }
+}
+
+/// Parse the partition maps of a logical volume descriptor.
+fn partition_maps(buf: &[u8]) -> Result<[Option<PartitionMap>; MAX_PARTITIONS], Error> {
+    let mut res = [None; MAX_PARTITIONS];
+    let (len, count) = (le32(buf, 264) as usize, le32(buf, 268) as usize);
+    let table = buf.get(440..440 + len).ok_or(msg2err!("truncated partition maps"))?;
+    if count > MAX_PARTITIONS {
+        return Err(msg2err!("too many partition maps"));
+    }
+    let mut pos = 0;
+    for slot in res.iter_mut().take(count) {
+        let map = table.get(pos..pos + 2).ok_or(msg2err!("truncated partition maps"))?;
+        let map = table
+            .get(pos..pos + map[1] as usize)
+            .ok_or(msg2err!("truncated partition maps"))?;
+        *slot = Some(match (map[0], map.len()) {
+            (1, 6) => PartitionMap::Physical(le16(map, 4)),
+            (2, 64) if map[5..5 + METADATA_PARTITION.len()] == *METADATA_PARTITION => PartitionMap::Metadata {
+                partition: le16(map, 38),
+                file: le32(map, 40),
+                mirror: le32(map, 44),
+            },
+            _ => return Err(msg2err!("unsupported partition map")),
+        });
+        pos += map.len();
+    }
+    Ok(res)
+}
+
+/// Check the tag of a descriptor by its identifier, checksum and CRC, and the location unless it is unknown.
+pub(crate) fn check_tag(buf: &[u8], id: u16, location: Option<u32>) -> Result<(), Error> {
+    if buf.len() < 16 {
+        return Err(msg2err!("truncated descriptor"));
+    }
+    let sum = buf[..16]
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 4)
+        .fold(0u8, |sum, (_, x)| sum.wrapping_add(*x));
+    if sum != buf[4] || le16(buf, 0) != id {
+        return Err(msg2err!("invalid descriptor tag"));
+    }
+    let data = buf
+        .get(16..16 + le16(buf, 10) as usize)
+        .ok_or(msg2err!("truncated descriptor"))?;
+    if crc16(data) != le16(buf, 8) {
+        return Err(msg2err!("descriptor CRC mismatch"));
+    }
+    if location.is_some_and(|x| x != le32(buf, 12)) {
+        return Err(msg2err!("descriptor at the wrong place"));
+    }
+    Ok(())
+}
+
+/// The CRC-ITU-T of the descriptors.
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for x in data {
+        crc ^= (*x as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => crc << 1 ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
+
+/// A little-endian u16 at the position.
+pub(crate) fn le16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+/// A little-endian u32 at the position.
+pub(crate) fn le32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+/// A little-endian u64 at the position.
+pub(crate) fn le64(buf: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{
+        attr::{self, Attributes, Value},
+        file::{File, FileType},
+    };
+    use ap_storage_memory::ReadSlice;
+    use ap_storage_test_util::*;
+    use std::{string::String, vec, vec::Vec};
+
+    const BS: usize = 2048;
+
+    /// The physical partition and the metadata partition as reference numbers.
+    const START: usize = 300;
+    const PHYS: u16 = 0;
+    const META: u16 = 1;
+
+    fn put(buf: &mut [u8], pos: usize, value: u64, size: usize) {
+        buf[pos..pos + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    /// Fill in the tag of a descriptor that covers the whole buffer.
+    fn tag(buf: &mut [u8], id: u16, location: u32) {
+        let len = buf.len() - 16;
+        put(buf, 0, id as u64, 2);
+        put(buf, 2, 3, 2);
+        put(buf, 8, crc16(&buf[16..]) as u64, 2);
+        put(buf, 10, len as u64, 2);
+        put(buf, 12, location as u64, 4);
+        buf[4] = 0;
+        buf[4] = buf[..16].iter().fold(0u8, |sum, x| sum.wrapping_add(*x));
+    }
+
+    fn long_ad(len: u32, block: u32, part: u16) -> Vec<u8> {
+        let mut res = vec![0; 16];
+        put(&mut res, 0, len as u64, 4);
+        put(&mut res, 4, block as u64, 4);
+        put(&mut res, 8, part as u64, 2);
+        res
+    }
+
+    fn short_ad(len: u32, block: u32) -> Vec<u8> {
+        long_ad(len, block, 0)[..8].to_vec()
+    }
+
+    fn timestamp(buf: &mut [u8]) {
+        put(buf, 0, 0x1000 | 60, 2);
+        put(buf, 2, 2024, 2);
+        buf[4..12].copy_from_slice(&[1, 2, 3, 4, 5, 50, 0, 0]);
+    }
+
+    /// A file entry or an extended one with the allocation type in the flags.
+    fn entry(extended: bool, location: u32, typ: u8, flags: u16, size: u64, ads: &[u8]) -> Vec<u8> {
+        let header = if extended { 216 } else { 176 };
+        let mut res = vec![0; header + ads.len()];
+        put(&mut res, 20, 4, 2);
+        put(&mut res, 24, 1, 2);
+        res[27] = typ;
+        put(&mut res, 34, flags as u64, 2);
+        put(&mut res, 36, 1000, 4);
+        put(&mut res, 40, 100, 4);
+        put(&mut res, 44, 7 << 10 | 5 << 5 | 4, 4);
+        put(&mut res, 48, 2, 2);
+        put(&mut res, 56, size, 8);
+        timestamp(&mut res[if extended { 92 } else { 84 }..]);
+        put(&mut res, header - 4, ads.len() as u64, 4);
+        res[header..].copy_from_slice(ads);
+        tag(
+            &mut res,
+            if extended {
+                TAG_EXTENDED_FILE_ENTRY
+            } else {
+                TAG_FILE_ENTRY
+            },
+            location,
+        );
+        res
+    }
+
+    fn fid(characteristics: u8, block: u32, name: &[u8]) -> Vec<u8> {
+        let mut res = vec![0; (38 + name.len() + 3) & !3];
+        put(&mut res, 16, 1, 2);
+        res[18] = characteristics;
+        res[19] = name.len() as u8;
+        res[20..36].copy_from_slice(&long_ad(BS as u32, block, META));
+        res[38..38 + name.len()].copy_from_slice(name);
+        tag(&mut res, TAG_FILE_ID, 0);
+        res
+    }
+
+    fn cs0_16(name: &str) -> Vec<u8> {
+        let mut res = vec![16];
+        res.extend(name.encode_utf16().flat_map(|x| x.to_be_bytes()));
+        res
+    }
+
+    fn image() -> Vec<u8> {
+        let mut data = vec![0; (START + 100) * BS];
+        let mut write = |sector: usize, buf: &[u8]| data[sector * BS..sector * BS + buf.len()].copy_from_slice(buf);
+
+        // the anchor points to a sequence that continues via a pointer
+        let mut avdp = vec![0; 512];
+        put(&mut avdp, 16, 4 * BS as u64, 4);
+        put(&mut avdp, 20, 32, 4);
+        tag(&mut avdp, TAG_ANCHOR, 256);
+        write(256, &avdp);
+
+        let mut pd = vec![0; 512];
+        put(&mut pd, 16, 1, 4);
+        pd[25..31].copy_from_slice(b"+NSR03");
+        put(&mut pd, 188, START as u64, 4);
+        put(&mut pd, 192, 100, 4);
+        tag(&mut pd, TAG_PARTITION, 32);
+        write(32, &pd);
+
+        let mut vdp = vec![0; 512];
+        put(&mut vdp, 20, 2 * BS as u64, 4);
+        put(&mut vdp, 24, 40, 4);
+        tag(&mut vdp, TAG_POINTER, 33);
+        write(33, &vdp);
+
+        let mut lvd = vec![0; 440 + 70];
+        lvd[84..92].copy_from_slice(b"\x08Service");
+        lvd[211] = 8;
+        put(&mut lvd, 212, BS as u64, 4);
+        lvd[248..264].copy_from_slice(&long_ad(BS as u32, 0, META));
+        put(&mut lvd, 264, 70, 4);
+        put(&mut lvd, 268, 2, 4);
+        lvd[440..446].copy_from_slice(&[1, 6, 1, 0, 0, 0]);
+        let map = &mut lvd[446..];
+        map[..2].copy_from_slice(&[2, 64]);
+        map[5..5 + METADATA_PARTITION.len()].copy_from_slice(METADATA_PARTITION);
+        put(map, 40, 1, 4);
+        put(map, 44, 2, 4);
+        tag(&mut lvd, TAG_LOGICAL_VOLUME, 40);
+        write(40, &lvd);
+        let mut td = vec![0; 512];
+        tag(&mut td, TAG_TERMINATING, 41);
+        write(41, &td);
+
+        // the metadata file and its mirror map the metadata blocks 0-3 and 4-7
+        let ads = [short_ad(4 * BS as u32, 10), short_ad(4 * BS as u32, 20)].concat();
+        write(START + 1, &entry(false, 1, 250, 0, 8 * BS as u64, &ads));
+        write(START + 2, &entry(false, 2, 251, 0, 8 * BS as u64, &ads));
+        let meta = |block: usize| START + if block < 4 { 10 + block } else { 16 + block };
+
+        let mut fsd = vec![0; 512];
+        fsd[400..416].copy_from_slice(&long_ad(BS as u32, 1, META));
+        tag(&mut fsd, TAG_FILE_SET, 0);
+        write(meta(0), &fsd);
+
+        let root = [
+            fid(0x0a, 1, b""),
+            fid(0, 2, b"\x08hello.txt"),
+            fid(0, 3, b"\x08big.bin"),
+            fid(0x04, 2, b"\x08gone"),
+            fid(0x02, 5, b"\x08sub"),
+            fid(0, 2, &cs0_16("Ünï😀")),
+        ]
+        .concat();
+        write(
+            meta(1),
+            &entry(true, 1, 4, 0, root.len() as u64, &short_ad(root.len() as u32, 4)),
+        );
+        write(meta(4), &root);
+
+        write(meta(2), &entry(false, 2, 5, 3, 12, b"Hello, UDF!\n"));
+
+        // recorded, not allocated and then the rest via an allocation extent descriptor
+        let ads = [
+            long_ad(2 * BS as u32, 50, PHYS),
+            long_ad(2 << 30 | BS as u32, 50, PHYS),
+            long_ad(3 << 30 | BS as u32, 60, PHYS),
+        ]
+        .concat();
+        write(meta(3), &entry(true, 3, 5, 1, 3 * BS as u64 + 1000, &ads));
+        write(START + 50, &pattern(b"abcdefghijklmnopqrstuvwxyz", 2 * BS));
+        let mut aed = vec![0; 24 + 16];
+        put(&mut aed, 20, 16, 4);
+        aed[24..].copy_from_slice(&long_ad(1000, 70, PHYS));
+        tag(&mut aed, TAG_ALLOCATION_EXTENT, 60);
+        write(START + 60, &aed);
+        write(START + 70, &[b'Z'; 1000]);
+
+        let sub = [fid(0x0a, 1, b""), fid(0, 7, b"\x08link")].concat();
+        write(
+            meta(5),
+            &entry(false, 5, 4, 0, sub.len() as u64, &short_ad(sub.len() as u32, 6)),
+        );
+        write(meta(6), &sub);
+        let name = cs0_16("hello.txt");
+        let link = [
+            &[1, 0, 0, 0, 5, 4, 0, 0][..],
+            b"\x08sub",
+            &[3, 0, 0, 0, 5, name.len() as u8, 0, 0],
+            &name,
+        ]
+        .concat();
+        write(meta(7), &entry(false, 7, 12, 3, link.len() as u64, &link));
+        data
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn files() {
+        let data = image();
+        let disk = ReadSlice(&data);
+        let fs = Udf::new(&disk).unwrap();
+        let mut buf = [0; 32];
+        let n = fs.label(&mut buf);
+        assert_eq!(&buf[..n], b"Service");
+        let root = fs.root().unwrap();
+        let open = |path: &str| root.clone().lookup_path(path.as_bytes()).unwrap();
+
+        let hello = open("hello.txt");
+        assert_eq!(read_all(&hello), b"Hello, UDF!\n");
+        let get = |name| hello.attr().get(name, &mut []);
+        assert_eq!(get(crate::attr::NLINKS).and_then(|x| x.as_u64()), Some(2));
+        assert_eq!(get(attr::MODE).and_then(|x| x.as_u64()), Some(0o754));
+        assert_eq!(get(attr::UID).and_then(|x| x.as_u64()), Some(1000));
+        assert!(matches!(get(attr::MTIME), Some(Value::Time(1_704_161_045_500_000_000))));
+        assert!(get(attr::BTIME).is_none());
+
+        let mut expected = pattern(b"abcdefghijklmnopqrstuvwxyz", 2 * BS);
+        expected.extend([0; BS]);
+        expected.extend([b'Z'; 1000]);
+        assert_eq!(read_all(&open("big.bin")), expected);
+
+        let link = open("sub/link");
+        assert_eq!(link.ftype(), FileType::SymLink);
+        assert_eq!(read_all(&link), b"/sub/../hello.txt");
+        assert_eq!(open("Ünï😀").id(), hello.id());
+        assert_eq!(open("sub/../big.bin").id(), 1 << 32 | 3);
+        assert!(root.lookup(b"gone").unwrap().is_none());
+    }
+
+    #[test]
+    fn directories() {
+        let mut data = image();
+
+        // the mirror of the metadata file is used when the main one is broken
+        data[(START + 1) * BS + 100] ^= 1;
+        let disk = ReadSlice(&data);
+        let fs = Udf::new(&disk).unwrap();
+        let root = fs.root().unwrap();
+        assert_eq!(
+            list(&root),
+            [
+                ("..", FileType::Parent),
+                ("hello.txt", FileType::File),
+                ("big.bin", FileType::File),
+                ("sub", FileType::Directory),
+                ("Ünï😀", FileType::File),
+            ]
+            .map(|(x, y)| (String::from(x), y))
+        );
+        let sub = root.lookup(b"sub").unwrap().unwrap();
+        assert_eq!(list(&sub).len(), 2);
+        assert_eq!(sub.lookup(b"..").unwrap().unwrap().id(), root.id());
+
+        // descriptors with a broken CRC are rejected
+        let mut data = image();
+        data[(START + 12) * BS + 40] ^= 1;
+        let disk = ReadSlice(&data);
+        let fs = Udf::new(&disk).unwrap();
+        assert!(fs.root().unwrap().lookup(b"hello.txt").is_err());
+    }
+}
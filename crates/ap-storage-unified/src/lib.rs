@@ -6,35 +6,104 @@
 
 use ap_storage::directory::{DirEntry, DirIterator};
 use ap_storage::{
-    attr::{Attributes, Value},
+    attr::{Attributes, Meta, Value},
     file::File,
     Error, FileSystem, Read,
 };
+use ap_storage_btrfs_ro::Btrfs;
+use ap_storage_cpio::CpioFs;
 use ap_storage_ext4_ro::Ext4Fs;
+use ap_storage_iso9660::IsoFs;
 use ap_storage_json::JsonFS;
+use ap_storage_littlefs::LittleFs;
+use ap_storage_ntfs_ro::Ntfs;
 use ap_storage_partition::PartitionFS;
+use ap_storage_squashfs::SquashFs;
+use ap_storage_tar::TarFs;
+use ap_storage_udf_ro::Udf;
 use ap_storage_vfat_ro::VFatFS;
+use ap_storage_xfs_ro::XfsFs;
+use ap_storage_zip::ZipFs;
+
+/// The scratch space needed by all file-systems.
+pub const SCRATCH_SIZE: usize = max(
+    max(ap_storage_squashfs::SCRATCH_SIZE, ap_storage_btrfs_ro::SCRATCH_SIZE),
+    ap_storage_ntfs_ro::SCRATCH_SIZE,
+);
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
 
 #[allow(clippy::large_enum_variant)]
 pub enum UnifiedFs<'a> {
     Ext4(Ext4Fs<'a>),
     Json(JsonFS),
     Vfat(VFatFS<'a>),
+    Iso(IsoFs<'a>),
+    Squash(SquashFs<'a>),
+    Tar(TarFs<'a>),
+    Cpio(CpioFs<'a>),
+    Zip(ZipFs<'a>),
+    LittleFs(LittleFs<'a>),
+    Btrfs(Btrfs<'a>),
+    Ntfs(Ntfs<'a>),
+    Udf(Udf<'a>),
+    Xfs(XfsFs<'a>),
     Partition(PartitionFS<'a>),
 }
 
 impl<'a> UnifiedFs<'a> {
-    /// try to mount all file-systems.
+    /// try to mount all file-systems that work without scratch space.
     pub fn new(disk: &'a dyn Read) -> Option<Self> {
+        Self::with_scratch(disk, &mut [])
+    }
+
+    /// try to mount all file-systems with scratch space of up to [`SCRATCH_SIZE`] bytes.
+    pub fn with_scratch(disk: &'a dyn Read, scratch: &'a mut [u8]) -> Option<Self> {
         if let Ok(f) = Ext4Fs::new(disk, false) {
             return Some(Self::Ext4(f));
         }
+        if let Ok(f) = XfsFs::new(disk) {
+            return Some(Self::Xfs(f));
+        }
+        if ap_storage_ntfs_ro::probe(disk) {
+            return Ntfs::new(disk, scratch).ok().map(Self::Ntfs);
+        }
         if let Ok(f) = JsonFS::new(disk) {
             return Some(Self::Json(f));
         }
         if let Ok(f) = VFatFS::new(disk, Default::default()) {
             return Some(Self::Vfat(f));
         }
+        if let Ok(f) = Udf::new(disk) {
+            return Some(Self::Udf(f));
+        }
+        if let Ok(f) = IsoFs::new(disk, Default::default()) {
+            return Some(Self::Iso(f));
+        }
+        if ap_storage_btrfs_ro::probe(disk) {
+            return Btrfs::new(disk, scratch).ok().map(Self::Btrfs);
+        }
+        if let Ok(f) = SquashFs::new(disk, scratch) {
+            return Some(Self::Squash(f));
+        }
+        if let Ok(f) = TarFs::new(disk) {
+            return Some(Self::Tar(f));
+        }
+        if let Ok(f) = CpioFs::new(disk) {
+            return Some(Self::Cpio(f));
+        }
+        if let Ok(f) = ZipFs::new(disk) {
+            return Some(Self::Zip(f));
+        }
+        if let Ok(f) = LittleFs::new(disk) {
+            return Some(Self::LittleFs(f));
+        }
         if let Ok(f) = PartitionFS::new(disk) {
             return Some(Self::Partition(f));
         }
@@ -49,15 +118,36 @@ impl<'a> FileSystem<'a> for UnifiedFs<'a> {
             UnifiedFs::Ext4(f) => UnifiedFile::Ext4(f.root()?),
             UnifiedFs::Json(f) => UnifiedFile::Json(f.root()?),
             UnifiedFs::Vfat(f) => UnifiedFile::Vfat(f.root()?),
+            UnifiedFs::Iso(f) => UnifiedFile::Iso(f.root()?),
+            UnifiedFs::Squash(f) => UnifiedFile::Squash(f.root()?),
+            UnifiedFs::Tar(f) => UnifiedFile::Tar(f.root()?),
+            UnifiedFs::Cpio(f) => UnifiedFile::Cpio(f.root()?),
+            UnifiedFs::Zip(f) => UnifiedFile::Zip(f.root()?),
+            UnifiedFs::LittleFs(f) => UnifiedFile::LittleFs(f.root()?),
+            UnifiedFs::Btrfs(f) => UnifiedFile::Btrfs(f.root()?),
+            UnifiedFs::Ntfs(f) => UnifiedFile::Ntfs(f.root()?),
+            UnifiedFs::Udf(f) => UnifiedFile::Udf(f.root()?),
+            UnifiedFs::Xfs(f) => UnifiedFile::Xfs(f.root()?),
             UnifiedFs::Partition(f) => UnifiedFile::Partition(f.root()?),
         })
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum UnifiedFile<'a> {
     Ext4(<Ext4Fs<'a> as FileSystem<'a>>::FileType),
     Json(<JsonFS as FileSystem<'a>>::FileType),
     Vfat(<VFatFS<'a> as FileSystem<'a>>::FileType),
+    Iso(<IsoFs<'a> as FileSystem<'a>>::FileType),
+    Squash(<SquashFs<'a> as FileSystem<'a>>::FileType),
+    Tar(<TarFs<'a> as FileSystem<'a>>::FileType),
+    Cpio(<CpioFs<'a> as FileSystem<'a>>::FileType),
+    Zip(<ZipFs<'a> as FileSystem<'a>>::FileType),
+    LittleFs(<LittleFs<'a> as FileSystem<'a>>::FileType),
+    Btrfs(<Btrfs<'a> as FileSystem<'a>>::FileType),
+    Ntfs(<Ntfs<'a> as FileSystem<'a>>::FileType),
+    Udf(<Udf<'a> as FileSystem<'a>>::FileType),
+    Xfs(<XfsFs<'a> as FileSystem<'a>>::FileType),
     Partition(<PartitionFS<'a> as FileSystem<'a>>::FileType),
 }
 
@@ -68,6 +158,16 @@ impl<'a> File for UnifiedFile<'a> {
             UnifiedFile::Ext4(f) => UnifiedAttr::Ext4(f.attr()),
             UnifiedFile::Json(f) => UnifiedAttr::Json(f.attr()),
             UnifiedFile::Vfat(f) => UnifiedAttr::Vfat(f.attr()),
+            UnifiedFile::Iso(f) => UnifiedAttr::Iso(f.attr()),
+            UnifiedFile::Squash(f) => UnifiedAttr::Squash(f.attr()),
+            UnifiedFile::Tar(f) => UnifiedAttr::Tar(f.attr()),
+            UnifiedFile::Cpio(f) => UnifiedAttr::Cpio(f.attr()),
+            UnifiedFile::Zip(f) => UnifiedAttr::Zip(f.attr()),
+            UnifiedFile::LittleFs(f) => UnifiedAttr::LittleFs(f.attr()),
+            UnifiedFile::Btrfs(f) => UnifiedAttr::Btrfs(f.attr()),
+            UnifiedFile::Ntfs(f) => UnifiedAttr::Ntfs(f.attr()),
+            UnifiedFile::Udf(f) => UnifiedAttr::Udf(f.attr()),
+            UnifiedFile::Xfs(f) => UnifiedAttr::Xfs(f.attr()),
             UnifiedFile::Partition(f) => UnifiedAttr::Partition(f.attr()),
         }
     }
@@ -77,6 +177,16 @@ impl<'a> File for UnifiedFile<'a> {
             UnifiedFile::Ext4(f) => UnifiedDir::Ext4(f.dir()?),
             UnifiedFile::Json(f) => UnifiedDir::Json(f.dir()?),
             UnifiedFile::Vfat(f) => UnifiedDir::Vfat(f.dir()?),
+            UnifiedFile::Iso(f) => UnifiedDir::Iso(f.dir()?),
+            UnifiedFile::Squash(f) => UnifiedDir::Squash(f.dir()?),
+            UnifiedFile::Tar(f) => UnifiedDir::Tar(f.dir()?),
+            UnifiedFile::Cpio(f) => UnifiedDir::Cpio(f.dir()?),
+            UnifiedFile::Zip(f) => UnifiedDir::Zip(f.dir()?),
+            UnifiedFile::LittleFs(f) => UnifiedDir::LittleFs(f.dir()?),
+            UnifiedFile::Btrfs(f) => UnifiedDir::Btrfs(f.dir()?),
+            UnifiedFile::Ntfs(f) => UnifiedDir::Ntfs(f.dir()?),
+            UnifiedFile::Udf(f) => UnifiedDir::Udf(f.dir()?),
+            UnifiedFile::Xfs(f) => UnifiedDir::Xfs(f.dir()?),
             UnifiedFile::Partition(f) => UnifiedDir::Partition(f.dir()?),
         })
     }
@@ -85,6 +195,16 @@ impl<'a> File for UnifiedFile<'a> {
             UnifiedFile::Ext4(f) => UnifiedFile::Ext4(f.open(offset)?),
             UnifiedFile::Json(f) => UnifiedFile::Json(f.open(offset)?),
             UnifiedFile::Vfat(f) => UnifiedFile::Vfat(f.open(offset)?),
+            UnifiedFile::Iso(f) => UnifiedFile::Iso(f.open(offset)?),
+            UnifiedFile::Squash(f) => UnifiedFile::Squash(f.open(offset)?),
+            UnifiedFile::Tar(f) => UnifiedFile::Tar(f.open(offset)?),
+            UnifiedFile::Cpio(f) => UnifiedFile::Cpio(f.open(offset)?),
+            UnifiedFile::Zip(f) => UnifiedFile::Zip(f.open(offset)?),
+            UnifiedFile::LittleFs(f) => UnifiedFile::LittleFs(f.open(offset)?),
+            UnifiedFile::Btrfs(f) => UnifiedFile::Btrfs(f.open(offset)?),
+            UnifiedFile::Ntfs(f) => UnifiedFile::Ntfs(f.open(offset)?),
+            UnifiedFile::Udf(f) => UnifiedFile::Udf(f.open(offset)?),
+            UnifiedFile::Xfs(f) => UnifiedFile::Xfs(f.open(offset)?),
             UnifiedFile::Partition(f) => UnifiedFile::Partition(f.open(offset)?),
         })
     }
@@ -96,14 +216,35 @@ impl<'a> Read for UnifiedFile<'a> {
             UnifiedFile::Ext4(f) => f.read_bytes(ofs, buf),
             UnifiedFile::Json(f) => f.read_bytes(ofs, buf),
             UnifiedFile::Vfat(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::Iso(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::Squash(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::Tar(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::Cpio(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::Zip(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::LittleFs(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::Btrfs(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::Ntfs(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::Udf(f) => f.read_bytes(ofs, buf),
+            UnifiedFile::Xfs(f) => f.read_bytes(ofs, buf),
             UnifiedFile::Partition(f) => f.read_bytes(ofs, buf),
         }
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum UnifiedDir<'a> {
     Ext4(<<Ext4Fs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
     Vfat(<<VFatFS<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    Iso(<<IsoFs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    Squash(<<SquashFs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    Tar(<<TarFs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    Cpio(<<CpioFs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    Zip(<<ZipFs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    LittleFs(<<LittleFs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    Btrfs(<<Btrfs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    Ntfs(<<Ntfs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    Udf(<<Udf<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
+    Xfs(<<XfsFs<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
     Json(<<JsonFS as FileSystem<'a>>::FileType as File>::DirType<'a>),
     Partition(<<PartitionFS<'a> as FileSystem<'a>>::FileType as File>::DirType<'a>),
 }
@@ -114,6 +255,16 @@ impl<'a> DirIterator for UnifiedDir<'a> {
             UnifiedDir::Ext4(f) => f.next(name),
             UnifiedDir::Json(f) => f.next(name),
             UnifiedDir::Vfat(f) => f.next(name),
+            UnifiedDir::Iso(f) => f.next(name),
+            UnifiedDir::Squash(f) => f.next(name),
+            UnifiedDir::Tar(f) => f.next(name),
+            UnifiedDir::Cpio(f) => f.next(name),
+            UnifiedDir::Zip(f) => f.next(name),
+            UnifiedDir::LittleFs(f) => f.next(name),
+            UnifiedDir::Btrfs(f) => f.next(name),
+            UnifiedDir::Ntfs(f) => f.next(name),
+            UnifiedDir::Udf(f) => f.next(name),
+            UnifiedDir::Xfs(f) => f.next(name),
             UnifiedDir::Partition(f) => f.next(name),
         }
     }
@@ -122,6 +273,16 @@ impl<'a> DirIterator for UnifiedDir<'a> {
 pub enum UnifiedAttr<'a> {
     Ext4(<<Ext4Fs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
     Vfat(<<VFatFS<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    Iso(<<IsoFs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    Squash(<<SquashFs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    Tar(<<TarFs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    Cpio(<<CpioFs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    Zip(<<ZipFs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    LittleFs(<<LittleFs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    Btrfs(<<Btrfs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    Ntfs(<<Ntfs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    Udf(<<Udf<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
+    Xfs(<<XfsFs<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
     Json(<<JsonFS as FileSystem<'a>>::FileType as File>::AttrType<'a>),
     Partition(<<PartitionFS<'a> as FileSystem<'a>>::FileType as File>::AttrType<'a>),
 }
@@ -134,6 +295,16 @@ impl<'a> IntoIterator for UnifiedAttr<'a> {
             UnifiedAttr::Ext4(f) => f.into_iter(),
             UnifiedAttr::Json(f) => f.into_iter(),
             UnifiedAttr::Vfat(f) => f.into_iter(),
+            UnifiedAttr::Iso(f) => f.into_iter(),
+            UnifiedAttr::Squash(f) => f.into_iter(),
+            UnifiedAttr::Tar(f) => f.into_iter(),
+            UnifiedAttr::Cpio(f) => f.into_iter(),
+            UnifiedAttr::Zip(f) => f.into_iter(),
+            UnifiedAttr::LittleFs(f) => f.into_iter(),
+            UnifiedAttr::Btrfs(f) => f.into_iter(),
+            UnifiedAttr::Ntfs(f) => f.into_iter(),
+            UnifiedAttr::Udf(f) => f.into_iter(),
+            UnifiedAttr::Xfs(f) => f.into_iter(),
             UnifiedAttr::Partition(f) => f.into_iter(),
         }
     }
@@ -144,7 +315,35 @@ impl<'a> Attributes<'a> for UnifiedAttr<'a> {
             UnifiedAttr::Ext4(f) => f.get(name, buf),
             UnifiedAttr::Json(f) => f.get(name, buf),
             UnifiedAttr::Vfat(f) => f.get(name, buf),
+            UnifiedAttr::Iso(f) => f.get(name, buf),
+            UnifiedAttr::Squash(f) => f.get(name, buf),
+            UnifiedAttr::Tar(f) => f.get(name, buf),
+            UnifiedAttr::Cpio(f) => f.get(name, buf),
+            UnifiedAttr::Zip(f) => f.get(name, buf),
+            UnifiedAttr::LittleFs(f) => f.get(name, buf),
+            UnifiedAttr::Btrfs(f) => f.get(name, buf),
+            UnifiedAttr::Ntfs(f) => f.get(name, buf),
+            UnifiedAttr::Udf(f) => f.get(name, buf),
+            UnifiedAttr::Xfs(f) => f.get(name, buf),
             UnifiedAttr::Partition(f) => f.get(name, buf),
         }
     }
+    fn meta(&self, name: &str) -> Option<Meta> {
+        match self {
+            UnifiedAttr::Ext4(f) => f.meta(name),
+            UnifiedAttr::Json(f) => f.meta(name),
+            UnifiedAttr::Vfat(f) => f.meta(name),
+            UnifiedAttr::Iso(f) => f.meta(name),
+            UnifiedAttr::Squash(f) => f.meta(name),
+            UnifiedAttr::Tar(f) => f.meta(name),
+            UnifiedAttr::Cpio(f) => f.meta(name),
+            UnifiedAttr::Zip(f) => f.meta(name),
+            UnifiedAttr::LittleFs(f) => f.meta(name),
+            UnifiedAttr::Btrfs(f) => f.meta(name),
+            UnifiedAttr::Ntfs(f) => f.meta(name),
+            UnifiedAttr::Udf(f) => f.meta(name),
+            UnifiedAttr::Xfs(f) => f.meta(name),
+            UnifiedAttr::Partition(f) => f.meta(name),
+        }
+    }
 }
@@ -0,0 +1,128 @@
+//! Builder options as writable attributes.
+
+use crate::MakeVFatFS;
+use ap_storage::attr::{attr_meta, new_attr, Attributes, AttributesMut, Meta, Value};
+use ap_storage::{msg2err, Error};
+
+new_attr!(ALIGN, Bool, "Align the data area to the cluster.");
+new_attr!(DRIVE, U64, "BIOS drive number.");
+new_attr!(LABEL, Str, "Volume label.");
+new_attr!(MEDIA, U64, "Media type.");
+new_attr!(NUM_FATS, U64, "Number of FAT copies.");
+new_attr!(OEM, Str, "OEM field.");
+new_attr!(PER_CLUSTER, U64, "Sectors per cluster.");
+new_attr!(RESERVED, U64, "Number of reserved sectors.");
+new_attr!(ROOT_ENTRIES, U64, "Minimum number of root entries.");
+new_attr!(SECTOR_SIZE, U64, "The size of the sector in bytes.");
+new_attr!(VOLUME_ID, U64, "Identification of the filesystem.");
+
+/// The attributes of a builder.
+pub struct Attr<'a> {
+    pub(crate) fs: &'a MakeVFatFS,
+}
+
+impl<'a> IntoIterator for Attr<'a> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            ALIGN,
+            DRIVE,
+            LABEL,
+            MEDIA,
+            NUM_FATS,
+            OEM,
+            PER_CLUSTER,
+            RESERVED,
+            ROOT_ENTRIES,
+            SECTOR_SIZE,
+            VOLUME_ID,
+        ]
+        .iter()
+    }
+}
+
+/// Copy a space padded string into the buffer.
+fn get_string(v: &[u8], buf: &mut [u8]) -> Value {
+    let v = v.trim_ascii_end();
+    let n = core::cmp::min(v.len(), buf.len());
+    buf[..n].copy_from_slice(&v[..n]);
+    Value::Str(v.len())
+}
+
+impl<'a> Attributes<'a> for Attr<'a> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let fs = self.fs;
+        Some(match name {
+            ALIGN => fs.align.into(),
+            DRIVE => fs.drive.into(),
+            LABEL => get_string(&fs.label, buf),
+            MEDIA => fs.media.into(),
+            NUM_FATS => fs.num_fats.into(),
+            OEM => get_string(&fs.oem, buf),
+            PER_CLUSTER => fs.per_cluster.into(),
+            RESERVED => fs.reserved.into(),
+            ROOT_ENTRIES => fs.root_entries.into(),
+            SECTOR_SIZE => fs.sector_size.into(),
+            VOLUME_ID => fs.volume_id.into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        let meta = attr_meta!(
+            name,
+            [
+                ALIGN,
+                DRIVE,
+                LABEL,
+                MEDIA,
+                NUM_FATS,
+                OEM,
+                PER_CLUSTER,
+                RESERVED,
+                ROOT_ENTRIES,
+                SECTOR_SIZE,
+                VOLUME_ID,
+            ]
+        )?;
+        Some(Meta { writable: true, ..meta })
+    }
+}
+
+/// Convert a value into a smaller integer.
+fn int<T: TryFrom<u64>>(value: Value) -> Result<T, Error> {
+    value
+        .as_u64()
+        .and_then(|x| x.try_into().ok())
+        .ok_or(msg2err!("invalid integer"))
+}
+
+/// Convert a value into a string.
+fn string(value: Value, buf: &[u8]) -> Result<&str, Error> {
+    let n = value
+        .as_len()
+        .filter(|&n| n <= buf.len())
+        .ok_or(msg2err!("invalid string"))?;
+    core::str::from_utf8(&buf[..n]).map_err(|e| msg2err!(e))
+}
+
+impl AttributesMut for MakeVFatFS {
+    fn set(&mut self, name: &str, value: Value, buf: &[u8]) -> Result<(), Error> {
+        match name {
+            ALIGN => self.align(value.as_bool().ok_or(msg2err!("invalid bool"))?),
+            DRIVE => self.drive(int(value)?),
+            LABEL => self.label(string(value, buf)?),
+            MEDIA => self.media(int(value)?),
+            NUM_FATS => self.num_fats(int(value)?),
+            OEM => self.oem(string(value, buf)?),
+            PER_CLUSTER => self.per_cluster(int(value)?)?,
+            RESERVED => self.reserved(int(value)?),
+            ROOT_ENTRIES => self.root_entries(int(value)?),
+            SECTOR_SIZE => self.sector_size(int(value)?)?,
+            VOLUME_ID => self.volume_id(int(value)?),
+            _ => return Err(msg2err!("unknown attribute")),
+        };
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 //! Make a FAT filesystem.
 #![no_std]
 
+pub mod attr;
+
 use ap_storage::{check, msg2err, Error, Write, WriteExt};
 use ap_storage_vfat::{BiosParameterBlock, ExtBiosParameterBlock16, ExtBiosParameterBlock32, Variant};
 
@@ -99,6 +101,11 @@ impl MakeVFatFS {
         *self
     }
 
+    /// The options as attributes.
+    pub fn attr(&self) -> attr::Attr<'_> {
+        attr::Attr { fs: self }
+    }
+
     /// Return the sector size.
     pub fn get_sector_size(&self) -> u16 {
         self.sector_size
@@ -1,16 +1,17 @@
 //! File attributes for vfat.
 
 use super::file::File;
-use ap_storage::attr::{self, new_attr, Attributes, Value};
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::Read;
 use ap_util_slice_writer::*;
 
 new_attr!(ATTR, U64, "File attribute bits.");
 
-pub struct Attr<'a> {
-    pub(crate) file: &'a File<'a>,
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
 }
 
-impl<'a> IntoIterator for Attr<'a> {
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
     type Item = &'a &'a str;
     type IntoIter = core::slice::Iter<'a, &'a str>;
     fn into_iter(self) -> Self::IntoIter {
@@ -20,6 +21,7 @@ impl<'a> IntoIterator for Attr<'a> {
             attr::BTIME,
             attr::FTYPE,
             attr::ID,
+            attr::MODE,
             attr::MTIME,
             attr::SIZE,
         ]
@@ -27,21 +29,38 @@ impl<'a> IntoIterator for Attr<'a> {
     }
 }
 
-impl<'a> Attributes<'a> for Attr<'a> {
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
     fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
         Some(match name {
             ATTR => self.file.inode.attr().into(),
             attr::FTYPE => {
                 let mut value = SliceWriter(buf, 0);
                 write!(value, "{:?}", self.file.ftype()).ok()?;
-                Value::Raw(value.1)
+                Value::Str(value.1)
             }
             attr::ID => self.file.id.into(),
+            attr::MODE => self.file.mode().into(),
             attr::SIZE => self.file.size().into(),
-            attr::ATIME => self.file.inode.atime().into(),
-            attr::BTIME => self.file.inode.btime().into(),
-            attr::MTIME => self.file.inode.mtime().into(),
+            attr::ATIME => Value::Time(self.file.inode.atime()),
+            attr::BTIME => Value::Time(self.file.inode.btime()),
+            attr::MTIME => Value::Time(self.file.inode.mtime()),
             _ => return None,
         })
     }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                ATTR,
+                attr::ATIME,
+                attr::BTIME,
+                attr::FTYPE,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::SIZE,
+            ]
+        )
+    }
 }
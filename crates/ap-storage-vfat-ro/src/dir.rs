@@ -7,13 +7,13 @@ use ap_storage::{
     Read, ReadExt,
 };
 
-pub struct Dir<'a> {
-    file: &'a File<'a>,
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
     offset: Offset,
 }
 
-impl<'a> Dir<'a> {
-    pub(crate) fn new(file: &'a File<'a>) -> Self {
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Self {
         Self { file, offset: 0 }
     }
 
@@ -97,7 +97,7 @@ impl<'a> Dir<'a> {
     }
 }
 
-impl<'a> DirIterator for Dir<'a> {
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
     fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
         #[cfg(not(feature = "long-name"))]
         let entry = self.get_next()?;
@@ -4,14 +4,35 @@ use super::{attr::Attr, dir::Dir, DirectoryEntry, VFatFS};
 use ap_storage::{file::FileType, msg2err, Error, Offset, Read, ReadExt};
 use core::cell::RefCell;
 
-#[derive(Debug, Clone)]
-pub struct File<'a> {
-    pub(crate) fs: &'a VFatFS<'a>,
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a VFatFS<'a, D>,
     pub(crate) inode: DirectoryEntry,
     pub(crate) id: Offset,
     cache: RefCell<FileCache>,
 }
 
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            inode: self.inode,
+            id: self.id,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("fs", &self.fs)
+            .field("inode", &self.inode)
+            .field("id", &self.id)
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
 /// The in-file cache to speedup linear reads.
 #[derive(Debug, Default, Clone)]
 struct FileCache {
@@ -20,9 +41,9 @@ struct FileCache {
     last_offset: u64,
 }
 
-impl<'a> File<'a> {
+impl<'a, D: Read + ?Sized> File<'a, D> {
     /// Creating a file from a directory entry.
-    pub(crate) fn new(fs: &'a VFatFS<'a>, inode: DirectoryEntry, id: Offset) -> Self {
+    pub(crate) fn new(fs: &'a VFatFS<'a, D>, inode: DirectoryEntry, id: Offset) -> Self {
         Self {
             inode,
             fs,
@@ -51,6 +72,11 @@ impl<'a> File<'a> {
         res as Offset
     }
 
+    /// Emulate the POSIX permission bits.
+    pub fn mode(&self) -> u64 {
+        self.inode.mode()
+    }
+
     pub fn ftype(&self) -> FileType {
         if self.inode.attr & 0x8 != 0 || self.inode.name[0] == 0xe5 {
             FileType::Unknown
@@ -66,13 +92,13 @@ impl<'a> File<'a> {
     }
 }
 
-impl<'a> ap_storage::file::File for File<'a> {
-    type AttrType<'c> = Attr<'c> where Self: 'c;
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
     fn attr(&self) -> Self::AttrType<'_> {
         Attr { file: self }
     }
 
-    type DirType<'c> = Dir<'c> where Self: 'c;
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
     fn dir(&self) -> Option<Self::DirType<'_>> {
         if self.inode.is_dir() {
             return Some(Dir::new(self));
@@ -106,7 +132,7 @@ impl<'a> ap_storage::file::File for File<'a> {
     }
 }
 
-impl Read for File<'_> {
+impl<D: Read + ?Sized> Read for File<'_, D> {
     fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
         let size = self.inode.size();
         if offset >= size {
@@ -153,6 +179,6 @@ impl Read for File<'_> {
 
         // limit the bytes to the current block
         let max_n = core::cmp::min(max_n, self.fs.cluster_size as usize - offset_in_block as usize);
-        self.fs.disk.read_bytes(ofs + offset_in_block, &mut buf[..max_n])
+        self.fs.disk().read_bytes(ofs + offset_in_block, &mut buf[..max_n])
     }
 }
@@ -28,9 +28,10 @@ pub struct Options {
 }
 
 /// An VFAT filesystem.
-#[derive(Clone)]
-pub struct VFatFS<'a> {
-    disk: &'a dyn Read,
+///
+/// The filesystem is `Sync` and can be shared between threads if the disk is.
+pub struct VFatFS<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
     /// bytes per cluster.
     cluster_size: u32,
     /// The number of clusters in the data-area.
@@ -55,7 +56,17 @@ pub struct VFatFS<'a> {
     options: Options,
 }
 
-impl core::fmt::Debug for VFatFS<'_> {
+impl<D: ?Sized> Clone for VFatFS<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            disk: self.disk,
+            options: self.options.clone(),
+            ..*self
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for VFatFS<'_, D> {
     fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
         write!(
             fmt,
@@ -65,10 +76,10 @@ impl core::fmt::Debug for VFatFS<'_> {
     }
 }
 
-impl<'a> VFatFS<'a> {
+impl<'a, D: Read + ?Sized> VFatFS<'a, D> {
     /// Mount the filesystem.
-    pub fn new(disk: &'a dyn Read, options: Options) -> Result<Self, Error> {
-        let buf: [u8; 512] = disk.read_object(options.sb_offset)?;
+    pub fn new(disk: &'a D, options: Options) -> Result<Self, Error> {
+        let buf: [u8; 512] = (&disk as &dyn Read).read_object(options.sb_offset)?;
         let bpb = unsafe { *(buf.as_ptr() as *const BiosParameterBlock) };
         let ebp16 = unsafe { *(buf.as_ptr().add(36) as *const ExtBiosParameterBlock16) };
         let ebp32 = unsafe { *(buf.as_ptr().add(36) as *const ExtBiosParameterBlock32) };
@@ -141,6 +152,11 @@ impl<'a> VFatFS<'a> {
         })
     }
 
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
     /// Follow the fat one entry at a time.
     fn follow_fat(&self, cluster: u32) -> Result<u32, Error> {
         if cluster == 0 || cluster >= self.clusters + 2 {
@@ -149,8 +165,8 @@ impl<'a> VFatFS<'a> {
         let ofs = self.fat_start + cluster as Offset * self.variant as Offset / 8;
 
         let mut value = match self.variant {
-            Variant::Fat32 => self.disk.read_object::<u32>(ofs)?,
-            _ => self.disk.read_object::<u16>(ofs)? as u32,
+            Variant::Fat32 => self.disk().read_object::<u32>(ofs)?,
+            _ => self.disk().read_object::<u16>(ofs)? as u32,
         };
 
         // this is the odd-case
@@ -161,8 +177,8 @@ impl<'a> VFatFS<'a> {
     }
 }
 
-impl<'a> FileSystem<'a> for VFatFS<'a> {
-    type FileType = file::File<'a>;
+impl<'a, D: Read + ?Sized> FileSystem<'a> for VFatFS<'a, D> {
+    type FileType = file::File<'a, D>;
     fn root(&'a self) -> Result<Self::FileType, Error> {
         let root_dir = DirectoryEntry {
             attr: 0x10,
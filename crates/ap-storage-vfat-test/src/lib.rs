@@ -66,4 +66,70 @@ mod tests {
             assert!(builder.calc_variant(limit).is_err(), "{order} {limit:x}");
         }
     }
+
+    /// Change the builder options through the attributes.
+    #[test]
+    fn mkfs_attributes() {
+        use ap_storage::attr::{Attributes, AttributesMut, Type, Value};
+        use ap_storage_vfat_mkfs::attr::{ALIGN, LABEL, NUM_FATS, OEM, PER_CLUSTER, SECTOR_SIZE};
+
+        let mut builder = MakeVFatFS::small();
+        builder.set(LABEL, Value::Str(5), b"DISK1 and more").unwrap();
+        builder.set(NUM_FATS, Value::U64(1), &[]).unwrap();
+        builder.set(PER_CLUSTER, Value::U64(8), &[]).unwrap();
+        builder.set(ALIGN, Value::Bool(true), &[]).unwrap();
+        assert_eq!(builder.get_per_cluster(), 8);
+
+        let attr = builder.attr();
+        let mut buf = [0; 16];
+        assert_eq!(attr.get(LABEL, &mut buf), Some(Value::Str(5)));
+        assert_eq!(&buf[..5], b"DISK1");
+        assert_eq!(attr.get(NUM_FATS, &mut buf), Some(Value::U64(1)));
+        assert_eq!(attr.get(ALIGN, &mut buf), Some(Value::Bool(true)));
+        let meta = attr.meta(OEM).unwrap();
+        assert_eq!((meta.typ, meta.writable), (Type::Str, true));
+        assert!(builder
+            .attr()
+            .into_iter()
+            .all(|name| builder.attr().meta(name).is_some_and(|x| x.writable)));
+
+        // invalid values are rejected
+        assert!(builder.set(NUM_FATS, Value::U64(256), &[]).is_err());
+        assert!(builder.set(NUM_FATS, Value::I64(1), &[]).is_err());
+        assert!(builder.set(PER_CLUSTER, Value::U64(3), &[]).is_err());
+        assert!(builder.set(SECTOR_SIZE, Value::U64(100), &[]).is_err());
+        assert!(builder.set(LABEL, Value::Str(20), b"short").is_err());
+        assert!(builder.set(OEM, Value::Str(2), &[0xff, 0xfe]).is_err());
+        assert!(builder.set("unknown", Value::U64(0), &[]).is_err());
+        assert_eq!(builder.get_per_cluster(), 8);
+    }
+
+    /// Format a disk in memory and mount it again.
+    #[test]
+    fn mkfs_in_memory() {
+        use ap_storage::{directory::DirIterator, file::File, file::FileType, FileSystem, Read, Write};
+        use ap_storage_memory::{MemDisk, VecDisk};
+        use ap_storage_vfat_ro::VFatFS;
+
+        fn check(disk: &dyn Read) {
+            let fs = VFatFS::new(disk, Default::default()).unwrap();
+            let root = fs.root().unwrap();
+            let mut name = [0u8; 256];
+            let mut dir = root.dir().unwrap();
+            while let Some(entry) = dir.next(&mut name).unwrap() {
+                assert_eq!(entry.typ, FileType::Parent);
+            }
+        }
+
+        let sectors = 8192;
+        let mut data = vec![0u8; sectors * 512];
+        let disk = MemDisk::new(&mut data);
+        MakeVFatFS::small().build(&disk as &dyn Write, sectors as u32).unwrap();
+        check(&disk);
+
+        // discards do not grow the vector, so it starts with the full size
+        let disk = VecDisk::new(vec![0xff; sectors * 512]);
+        MakeVFatFS::small().build(&disk as &dyn Write, sectors as u32).unwrap();
+        check(&disk);
+    }
 }
@@ -51,6 +51,20 @@ impl DirectoryEntry {
         self.attr
     }
 
+    /// Emulate the POSIX permission bits.
+    ///
+    /// Read-only entries lose their write bits and hidden ones are only accessible by the owner.
+    pub fn mode(&self) -> u64 {
+        let mut res = if self.is_dir() { 0o777 } else { 0o666 };
+        if self.attr & 0x1 != 0 {
+            res &= !0o222;
+        }
+        if self.attr & 0x2 != 0 {
+            res &= !0o077;
+        }
+        res
+    }
+
     /// Return the cluster number.
     pub fn cluster(&self) -> u32 {
         // Volume ID?
@@ -181,3 +195,24 @@ pub struct ExtBiosParameterBlock32 {
     pub res: [u8; 12],
     pub ext: ExtBiosParameterBlock16,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode() {
+        let entry = |attr| DirectoryEntry {
+            attr,
+            ..Default::default()
+        };
+        assert_eq!(entry(0x20).mode(), 0o666);
+        assert_eq!(entry(0x10).mode(), 0o777);
+        assert_eq!(entry(0x01).mode(), 0o444);
+
+        // hidden entries are owner-only
+        assert_eq!(entry(0x02).mode(), 0o600);
+        assert_eq!(entry(0x12).mode(), 0o700);
+        assert_eq!(entry(0x13).mode(), 0o500);
+    }
+}
@@ -0,0 +1,89 @@
+//! File attributes for XFS.
+
+use super::file::File;
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::{file::FileType, Read};
+use ap_util_slice_writer::*;
+
+new_attr!(FLAGS, U64, "Inode flags.");
+new_attr!(NBLOCKS, U64, "Number of blocks allocated to this file.");
+new_attr!(NLINKS, U64, "Number of hard-links to this file.");
+
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
+}
+
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            FLAGS,
+            NBLOCKS,
+            NLINKS,
+            attr::ATIME,
+            attr::BTIME,
+            attr::CTIME,
+            attr::FTYPE,
+            attr::GID,
+            attr::ID,
+            attr::MODE,
+            attr::MTIME,
+            attr::RDEV,
+            attr::SIZE,
+            attr::UID,
+        ]
+        .iter()
+    }
+}
+
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let inode = &self.file.inode;
+        Some(match name {
+            FLAGS => (inode.flags as u64).into(),
+            NBLOCKS => inode.nblocks.into(),
+            NLINKS => (inode.nlink as u64).into(),
+            attr::ATIME => Value::Time(inode.atime),
+            attr::BTIME => Value::Time(inode.crtime),
+            attr::CTIME => Value::Time(inode.ctime),
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            attr::GID => (inode.gid as u64).into(),
+            attr::ID => self.file.id().into(),
+            attr::MODE => (inode.mode as u64 & 0o7777).into(),
+            attr::MTIME => Value::Time(inode.mtime),
+            attr::RDEV if matches!(self.file.ftype(), FileType::CharDevice | FileType::BlockDevice) => {
+                inode.device().into()
+            }
+            attr::SIZE => inode.size.into(),
+            attr::UID => (inode.uid as u64).into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                FLAGS,
+                NBLOCKS,
+                NLINKS,
+                attr::ATIME,
+                attr::BTIME,
+                attr::CTIME,
+                attr::FTYPE,
+                attr::GID,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::RDEV,
+                attr::SIZE,
+                attr::UID,
+            ]
+        )
+    }
+}
@@ -0,0 +1,128 @@
+//! Mapping file blocks to disk blocks via the extents of the data fork.
+
+use super::{be16, be32, be64, inode::Format, partition, Inode, XfsFs};
+use ap_storage::{msg2err, Error, Offset, Read, ReadExt};
+
+const BMAP_MAGIC: u32 = 0x424d_4133;
+
+/// The size of the header of long btree blocks as used for the extents.
+const LONG_HEADER: usize = 72;
+
+/// The size of an extent record and of a key with its pointer.
+const RECORD: usize = 16;
+
+/// The maximum height of the btree.
+const MAX_LEVEL: u16 = 9;
+
+/// The end of the sibling chain.
+const NULL_BLOCK: u64 = u64::MAX;
+
+/// A mapping of file blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// The first file block.
+    pub offset: u64,
+    /// The first filesystem block.
+    pub block: u64,
+    pub count: u64,
+    /// Preallocated blocks that read as zero.
+    pub unwritten: bool,
+}
+
+impl Extent {
+    fn parse(buf: &[u8; RECORD]) -> Self {
+        let (hi, lo) = (be64(buf, 0), be64(buf, 8));
+        Self {
+            offset: (hi >> 9) & ((1 << 54) - 1),
+            block: (hi & 0x1ff) << 43 | lo >> 21,
+            count: lo & ((1 << 21) - 1),
+            unwritten: hi >> 63 != 0,
+        }
+    }
+
+    /// The file block after the extent.
+    pub fn end(&self) -> u64 {
+        self.offset + self.count
+    }
+}
+
+/// Search a sorted list of extent records for the first that ends after the block.
+fn search(disk: &dyn Read, pos: Offset, count: usize, block: u64) -> Result<Option<Extent>, Error> {
+    let record =
+        |i: usize| -> Result<Extent, Error> { Ok(Extent::parse(&disk.read_object(pos + (i * RECORD) as Offset)?)) };
+    let index = partition(count, |i| Ok(record(i)?.end() <= block))?;
+    if index == count {
+        return Ok(None);
+    }
+    record(index).map(Some)
+}
+
+/// The index of the child of a btree node that may hold the block.
+fn child(disk: &dyn Read, keys: Offset, count: usize, block: u64) -> Result<usize, Error> {
+    let index = partition(count, |i| {
+        let key: [u8; 8] = disk.read_object(keys + (i * 8) as Offset)?;
+        Ok(u64::from_be_bytes(key) <= block)
+    })?;
+    Ok(index.saturating_sub(1))
+}
+
+/// Find the first extent of the data fork that ends after the block.
+pub(crate) fn extent<D: Read + ?Sized>(fs: &XfsFs<D>, inode: &Inode, block: u64) -> Result<Option<Extent>, Error> {
+    let disk = fs.disk();
+    match inode.format {
+        Format::Extents => {
+            if inode.nextents > (inode.fork_size / RECORD) as u64 {
+                return Err(msg2err!("too many extents"));
+            }
+            search(disk, inode.fork, inode.nextents as usize, block)
+        }
+        Format::Btree => {
+            let header: [u8; 4] = disk.read_object(inode.fork)?;
+            let (mut level, count) = (be16(&header, 0), be16(&header, 2) as usize);
+            let maxrecs = (inode.fork_size - 4) / RECORD;
+            if level == 0 || level > MAX_LEVEL || count == 0 || count > maxrecs {
+                return Err(msg2err!("invalid extent btree root"));
+            }
+            let index = child(disk, inode.fork + 4, count, block)?;
+            let ptr: [u8; 8] = disk.read_object(inode.fork + (4 + maxrecs * 8 + index * 8) as Offset)?;
+            let mut ptr = u64::from_be_bytes(ptr);
+            let blocksize = fs.sb.blocksize as usize;
+            let mut sibling = false;
+            loop {
+                level -= 1;
+                let pos = fs.block_pos(ptr, 1)?;
+                let header: [u8; LONG_HEADER] = disk.read_object(pos)?;
+                let count = be16(&header, 6) as usize;
+                if be32(&header, 0) != BMAP_MAGIC
+                    || be16(&header, 4) != level
+                    || be64(&header, 24) != pos >> 9
+                    || &header[40..56] != fs.sb.metadata_uuid()
+                    || be64(&header, 56) != inode.ino
+                    || count == 0
+                    || LONG_HEADER + count * RECORD > blocksize
+                {
+                    return Err(msg2err!("invalid extent btree block"));
+                }
+                fs.check_crc(pos, blocksize, 64)?;
+                if level != 0 {
+                    let maxrecs = (blocksize - LONG_HEADER) / RECORD;
+                    let index = child(disk, pos + LONG_HEADER as Offset, count, block)?;
+                    let next: [u8; 8] = disk.read_object(pos + (LONG_HEADER + maxrecs * 8 + index * 8) as Offset)?;
+                    ptr = u64::from_be_bytes(next);
+                    continue;
+                }
+                if let Some(extent) = search(disk, pos + LONG_HEADER as Offset, count, block)? {
+                    return Ok(Some(extent));
+                }
+
+                // the extent starts in the next leaf
+                match be64(&header, 16) {
+                    NULL_BLOCK => return Ok(None),
+                    _ if sibling => return Err(msg2err!("unsorted extent btree")),
+                    next => (ptr, level, sibling) = (next, 1, true),
+                }
+            }
+        }
+        _ => Err(msg2err!("no extents")),
+    }
+}
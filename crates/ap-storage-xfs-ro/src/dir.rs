@@ -0,0 +1,474 @@
+//! Directories on XFS.
+//!
+//! Larger directories have three regions in their data fork: the data blocks with the entries, the leaf blocks
+//! with the hashes of the names from 32 GiB on, and the free-space index from 64 GiB on, which is not needed for
+//! reading.
+
+use super::{be16, be32, be64, file::File, inode::dir_type, inode::Format, partition};
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    file::FileType,
+    msg2err, Error, Offset, Read, ReadExt,
+};
+
+pub(crate) const BLOCK_MAGIC: u32 = 0x5844_4233;
+pub(crate) const DATA_MAGIC: u32 = 0x5844_4433;
+const LEAF1_MAGIC: u16 = 0x3df1;
+const LEAFN_MAGIC: u16 = 0x3dff;
+const NODE_MAGIC: u16 = 0x3ebe;
+
+/// The start of the leaf blocks.
+const LEAF_OFFSET: Offset = 32 << 30;
+
+/// The size of the header of data blocks.
+const DATA_HEADER: usize = 64;
+
+/// The size of the header of leaf and node blocks including the count.
+const DA_HEADER: usize = 64;
+
+/// The tag of unused space in data blocks.
+const FREE_TAG: u16 = 0xffff;
+
+/// The offsets of `.` and `..` in the first data block, which shortform directories report as well.
+const DOT_OFFSET: Offset = DATA_HEADER as Offset;
+const DOTDOT_OFFSET: Offset = DOT_OFFSET + 16;
+
+/// The maximum height of the node tree.
+const MAX_LEVEL: usize = 5;
+
+/// Hash a name as in the leaf blocks.
+pub(crate) fn hash(name: &[u8]) -> u32 {
+    let mut chunks = name.chunks_exact(4);
+    let mut hash = 0u32;
+    for x in &mut chunks {
+        hash = (x[0] as u32) << 21 ^ (x[1] as u32) << 14 ^ (x[2] as u32) << 7 ^ x[3] as u32 ^ hash.rotate_left(28);
+    }
+    match *chunks.remainder() {
+        [a, b, c] => (a as u32) << 14 ^ (b as u32) << 7 ^ c as u32 ^ hash.rotate_left(21),
+        [a, b] => (a as u32) << 7 ^ b as u32 ^ hash.rotate_left(14),
+        [a] => a as u32 ^ hash.rotate_left(7),
+        _ => hash,
+    }
+}
+
+/// The layout of a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Form {
+    /// The entries in the inode.
+    Short,
+    /// A single block with the entries and their hashes.
+    Block,
+    /// Data blocks and leaf blocks in a single leaf or a node tree.
+    Leaf,
+}
+
+/// An entry in a shortform directory or a data block.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    offset: Offset,
+    ino: u64,
+    nlen: usize,
+    /// The position of the name.
+    name: Offset,
+    typ: u8,
+}
+
+impl Entry {
+    /// Compare the name.
+    fn matches<D: Read + ?Sized>(&self, file: &File<D>, name: &[u8]) -> Result<bool, Error> {
+        if self.nlen != name.len() {
+            return Ok(false);
+        }
+        let mut buf = [0; 255];
+        let buf = &mut buf[..self.nlen];
+        read_entry(file, self.name, buf)?;
+        Ok(buf == name)
+    }
+
+    /// Whether the name is `.` or `..`.
+    fn is_parent<D: Read + ?Sized>(&self, file: &File<D>) -> Result<bool, Error> {
+        Ok(self.matches(file, b".")? || self.matches(file, b"..")?)
+    }
+}
+
+/// Read from the data of a directory, which is in the inode for shortform directories.
+fn read_entry<D: Read + ?Sized>(file: &File<D>, pos: Offset, buf: &mut [u8]) -> Result<(), Error> {
+    match file.inode.format {
+        Format::Local => file.fs.disk().read_exact(pos, buf),
+        _ => file.read_data_exact(pos, buf),
+    }
+}
+
+/// The layout follows from the format and whether there are leaf blocks.
+fn form<D: Read + ?Sized>(file: &File<D>) -> Result<Form, Error> {
+    if file.inode.format == Format::Local {
+        return Ok(Form::Short);
+    }
+    Ok(match file.extent(LEAF_OFFSET / file.fs.sb.blocksize as Offset)? {
+        Some(_) => Form::Leaf,
+        None => Form::Block,
+    })
+}
+
+/// The size of directory blocks.
+fn block_size<D: Read + ?Sized>(file: &File<D>) -> usize {
+    (file.fs.sb.blocksize as usize) << file.fs.sb.dirblklog
+}
+
+/// The parent and the entries of a shortform directory, which are ordered by their offset.
+fn shortform<D: Read + ?Sized, R>(
+    file: &File<D>,
+    mut f: impl FnMut(&Entry) -> Result<Option<R>, Error>,
+) -> Result<(u64, Option<R>), Error> {
+    let inode = &file.inode;
+    let end = core::cmp::min(inode.size, inode.fork_size as u64);
+    let disk = file.fs.disk();
+    let header: [u8; 2] = disk.read_object(inode.fork)?;
+    let width = if header[1] == 0 { 4 } else { 8 };
+    let ino = |pos| -> Result<u64, Error> {
+        let mut buf = [0; 8];
+        if pos + width > end {
+            return Err(msg2err!("truncated directory"));
+        }
+        disk.read_exact(inode.fork + pos, &mut buf[8 - width as usize..])?;
+        Ok(u64::from_be_bytes(buf))
+    };
+    let parent = ino(2)?;
+    let mut pos = 2 + width;
+    for _ in 0..header[0] {
+        if pos + 3 > end {
+            return Err(msg2err!("truncated directory"));
+        }
+        let buf: [u8; 3] = disk.read_object(inode.fork + pos)?;
+        let nlen = buf[0] as u64;
+        let mut typ = [0];
+        if pos + 3 + nlen + 1 > end {
+            return Err(msg2err!("truncated directory"));
+        }
+        disk.read_exact(inode.fork + pos + 3 + nlen, &mut typ)?;
+        let entry = Entry {
+            offset: be16(&buf, 1) as Offset,
+            ino: ino(pos + 3 + nlen + 1)?,
+            nlen: nlen as usize,
+            name: inode.fork + pos + 3,
+            typ: typ[0],
+        };
+        if let Some(res) = f(&entry)? {
+            return Ok((parent, Some(res)));
+        }
+        pos += 3 + nlen + 1 + width;
+    }
+    Ok((parent, None))
+}
+
+/// Check a data block and return the end of its entries.  Single-block directories have their hashes at the end.
+fn data_block<D: Read + ?Sized>(file: &File<D>, start: Offset) -> Result<Offset, Error> {
+    let size = block_size(file);
+    let mut header = [0; DATA_HEADER];
+    file.read_data_exact(start, &mut header)?;
+    let block = start == 0 && be32(&header, 0) == BLOCK_MAGIC;
+    if !(block || be32(&header, 0) == DATA_MAGIC)
+        || &header[24..40] != file.fs.sb.metadata_uuid()
+        || be64(&header, 40) != file.inode.ino
+    {
+        return Err(msg2err!("invalid directory block"));
+    }
+    file.check_crc(start, size, 4)?;
+    if !block {
+        return Ok(start + size as Offset);
+    }
+    let (count, _) = block_tail(file)?;
+    Ok(start + (size - 8 - count * 8) as Offset)
+}
+
+/// The number of hashes at the end of a single-block directory and their position.
+fn block_tail<D: Read + ?Sized>(file: &File<D>) -> Result<(usize, Offset), Error> {
+    let size = block_size(file);
+    let mut tail = [0; 4];
+    file.read_data_exact((size - 8) as Offset, &mut tail)?;
+    let count = u32::from_be_bytes(tail) as usize;
+    if count > (size - DATA_HEADER - 8) / 8 {
+        return Err(msg2err!("invalid directory block"));
+    }
+    Ok((count, (size - 8 - count * 8) as Offset))
+}
+
+/// Parse an entry in a data block and return its size.
+fn data_entry<D: Read + ?Sized>(file: &File<D>, pos: Offset, end: Offset) -> Result<(Option<Entry>, Offset), Error> {
+    let mut buf = [0; 9];
+    if pos + 8 > end {
+        return Err(msg2err!("truncated directory block"));
+    }
+    file.read_data_exact(pos, &mut buf[..8])?;
+    if be16(&buf, 0) == FREE_TAG {
+        let size = be16(&buf, 2) as Offset;
+        if size == 0 || size % 8 != 0 || pos + size > end {
+            return Err(msg2err!("invalid free space in directory"));
+        }
+        return Ok((None, size));
+    }
+    file.read_data_exact(pos, &mut buf)?;
+    let nlen = buf[8] as Offset;
+    let size = (8 + 1 + nlen + 1 + 2 + 7) & !7;
+    if nlen == 0 || pos + size > end {
+        return Err(msg2err!("invalid directory entry"));
+    }
+    let mut typ = [0];
+    file.read_data_exact(pos + 9 + nlen, &mut typ)?;
+    let entry = Entry {
+        offset: pos,
+        ino: be64(&buf, 0),
+        nlen: nlen as usize,
+        name: pos + 9,
+        typ: typ[0],
+    };
+    Ok((Some(entry), size))
+}
+
+/// The entry at an offset of a directory with data blocks.
+fn entry_at<D: Read + ?Sized>(file: &File<D>, offset: Offset) -> Result<Entry, Error> {
+    let size = block_size(file) as Offset;
+    let start = offset / size * size;
+    if offset >= LEAF_OFFSET || offset % 8 != 0 || offset < start + DATA_HEADER as Offset {
+        return Err(msg2err!("not a child"));
+    }
+    let end = data_block(file, start)?;
+    match data_entry(file, offset, end)? {
+        (Some(entry), _) => Ok(entry),
+        _ => Err(msg2err!("not a child")),
+    }
+}
+
+/// The inode number of the entry at an offset.
+pub(crate) fn child<D: Read + ?Sized>(file: &File<D>, offset: Offset) -> Result<u64, Error> {
+    if file.inode.format != Format::Local {
+        return Ok(entry_at(file, offset)?.ino);
+    }
+    let (parent, ino) = shortform(file, |x| Ok((x.offset == offset).then_some(x.ino)))?;
+    match offset {
+        DOT_OFFSET => Ok(file.inode.ino),
+        DOTDOT_OFFSET => Ok(parent),
+        _ => ino.ok_or(msg2err!("not a child")),
+    }
+}
+
+/// Check a leaf or node block and return its header.
+fn da_block<D: Read + ?Sized>(file: &File<D>, start: Offset, magic: &[u16]) -> Result<[u8; DA_HEADER], Error> {
+    let size = block_size(file);
+    let mut header = [0; DA_HEADER];
+    file.read_data_exact(start, &mut header)?;
+    if !magic.contains(&be16(&header, 8))
+        || &header[32..48] != file.fs.sb.metadata_uuid()
+        || be64(&header, 48) != file.inode.ino
+        || DA_HEADER + be16(&header, 56) as usize * 8 > size
+    {
+        return Err(msg2err!("invalid directory leaf"));
+    }
+    file.check_crc(start, size, 12)?;
+    Ok(header)
+}
+
+/// Search the hashes of a leaf for the name.  The name may continue in the next leaf if the last hash matches.
+fn search_leaf<D: Read + ?Sized>(
+    file: &File<D>,
+    pos: Offset,
+    count: usize,
+    name: &[u8],
+) -> Result<(Option<u64>, bool), Error> {
+    let hash = hash(name);
+    let leaf = |i: usize| -> Result<(u32, u32), Error> {
+        let mut buf = [0; 8];
+        file.read_data_exact(pos + (i * 8) as Offset, &mut buf)?;
+        Ok((be32(&buf, 0), be32(&buf, 4)))
+    };
+    let mut index = partition(count, |i| Ok(leaf(i)?.0 < hash))?;
+    while index < count {
+        let (value, address) = leaf(index)?;
+        if value != hash {
+            return Ok((None, false));
+        }
+        if address != 0 {
+            let entry = entry_at(file, (address as Offset) << 3)?;
+            if entry.matches(file, name)? {
+                return Ok((Some(entry.ino), false));
+            }
+        }
+        index += 1;
+    }
+    Ok((None, true))
+}
+
+/// Lookup a name via its hash and return the inode number.
+pub(crate) fn lookup<D: Read + ?Sized>(file: &File<D>, name: &[u8]) -> Result<Option<u64>, Error> {
+    if name.is_empty() || name.len() > 255 {
+        return Ok(None);
+    }
+    match form(file)? {
+        Form::Short => {
+            let (parent, ino) = shortform(file, |x| Ok(x.matches(file, name)?.then_some(x.ino)))?;
+            Ok(match name {
+                b"." => Some(file.inode.ino),
+                b".." => Some(parent),
+                _ => ino,
+            })
+        }
+        Form::Block => {
+            if data_block(file, 0)? == block_size(file) as Offset {
+                return Err(msg2err!("invalid directory block"));
+            }
+            let (count, pos) = block_tail(file)?;
+            Ok(search_leaf(file, pos, count, name)?.0)
+        }
+        Form::Leaf => {
+            let blocklog = file.fs.sb.blocklog;
+            let mut start = LEAF_OFFSET;
+            let mut header = da_block(file, start, &[LEAF1_MAGIC, NODE_MAGIC])?;
+            if be16(&header, 8) == LEAF1_MAGIC {
+                let count = be16(&header, 56) as usize;
+                return Ok(search_leaf(file, start + DA_HEADER as Offset, count, name)?.0);
+            }
+
+            // descend the node tree to the first leaf that may hold the hash
+            let hash = hash(name);
+            let mut level = be16(&header, 58) as usize;
+            if level == 0 || level > MAX_LEVEL {
+                return Err(msg2err!("invalid directory node"));
+            }
+            while level > 0 {
+                let count = be16(&header, 56) as usize;
+                let node = |i: usize| -> Result<(u32, u32), Error> {
+                    let mut buf = [0; 8];
+                    file.read_data_exact(start + (DA_HEADER + i * 8) as Offset, &mut buf)?;
+                    Ok((be32(&buf, 0), be32(&buf, 4)))
+                };
+                let index = partition(count, |i| Ok(node(i)?.0 < hash))?;
+                if index == count {
+                    return Ok(None);
+                }
+                start = (node(index)?.1 as Offset) << blocklog;
+                level -= 1;
+                let magic = if level == 0 { LEAFN_MAGIC } else { NODE_MAGIC };
+                header = da_block(file, start, &[magic])?;
+                if level != 0 && be16(&header, 58) as usize != level {
+                    return Err(msg2err!("invalid directory node"));
+                }
+            }
+
+            // the hash may continue in the following leaves
+            loop {
+                let count = be16(&header, 56) as usize;
+                match search_leaf(file, start + DA_HEADER as Offset, count, name)? {
+                    (None, true) => {}
+                    (res, _) => return Ok(res),
+                }
+                let next = (be32(&header, 0) as Offset) << blocklog;
+                if next <= start {
+                    return Ok(None);
+                }
+                start = next;
+                header = da_block(file, start, &[LEAFN_MAGIC])?;
+            }
+        }
+    }
+}
+
+/// Iterate over the entries of a directory.
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
+    /// The offset of the next entry.
+    pos: Offset,
+    /// The end of the entries in the current data block.
+    end: Offset,
+}
+
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Self {
+        Self { file, pos: 0, end: 0 }
+    }
+
+    /// The next entry of a shortform directory, which are found by their offset.
+    fn next_short(&mut self) -> Result<Option<(Entry, FileType)>, Error> {
+        let (dot, dotdot) = (self.pos <= DOT_OFFSET, self.pos <= DOTDOT_OFFSET);
+        let (parent, entry) = shortform(self.file, |x| Ok((x.offset >= self.pos).then_some(*x)))?;
+        let (entry, typ) = if dot || dotdot {
+            let (offset, ino, nlen) = if dot {
+                (DOT_OFFSET, self.file.inode.ino, 1)
+            } else {
+                (DOTDOT_OFFSET, parent, 2)
+            };
+            let entry = Entry {
+                offset,
+                ino,
+                nlen,
+                name: 0,
+                typ: 0,
+            };
+            (entry, FileType::Parent)
+        } else {
+            let Some(entry) = entry else {
+                return Ok(None);
+            };
+            (entry, dir_type(entry.typ))
+        };
+        self.pos = entry.offset + 1;
+        Ok(Some((entry, typ)))
+    }
+
+    /// The next entry in the data blocks.
+    fn next_data(&mut self) -> Result<Option<(Entry, FileType)>, Error> {
+        let file = self.file;
+        let size = block_size(file) as Offset;
+        loop {
+            if self.pos >= self.end {
+                // skip holes between the data blocks
+                let blocksize = file.fs.sb.blocksize as Offset;
+                let mut start = self.pos.div_ceil(size) * size;
+                match file.extent(start / blocksize)? {
+                    Some(x) => start = core::cmp::max(start, (x.offset * blocksize).div_ceil(size) * size),
+                    None => start = LEAF_OFFSET,
+                }
+                if start >= LEAF_OFFSET {
+                    self.pos = LEAF_OFFSET;
+                    return Ok(None);
+                }
+                self.end = data_block(file, start)?;
+                self.pos = start + DATA_HEADER as Offset;
+                continue;
+            }
+            let (entry, size) = data_entry(file, self.pos, self.end)?;
+            self.pos += size;
+            if let Some(entry) = entry {
+                let typ = if entry.is_parent(file)? {
+                    FileType::Parent
+                } else {
+                    dir_type(entry.typ)
+                };
+                return Ok(Some((entry, typ)));
+            }
+        }
+    }
+}
+
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        let res = match self.file.inode.format {
+            Format::Local => self.next_short()?,
+            _ => self.next_data()?,
+        };
+        let Some((entry, typ)) = res else {
+            return Ok(None);
+        };
+        let n = core::cmp::min(entry.nlen, name.len());
+        if typ == FileType::Parent && entry.name == 0 {
+            name[..n].fill(b'.');
+        } else {
+            read_entry(self.file, entry.name, &mut name[..n])?;
+        }
+        Ok(Some(DirEntry {
+            offset: entry.offset,
+            id: entry.ino,
+            nlen: entry.nlen,
+            typ,
+        }))
+    }
+}
@@ -0,0 +1,207 @@
+//! Files on XFS.
+
+use super::{
+    attr::Attr, be32, be64, bmap, check_crc, dir, dir::Dir, inode::Format, inode::FLAG_REALTIME, Extent, Inode, XfsFs,
+};
+use ap_storage::{file::FileType, msg2err, Error, Offset, Read, ReadExt};
+use core::cell::Cell;
+
+const SYMLINK_MAGIC: u32 = 0x5853_4c4d;
+
+/// The size of the header in each block of a remote symlink.
+const SYMLINK_HEADER: usize = 56;
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a XfsFs<'a, D>,
+    pub(crate) inode: Inode,
+    /// The last extent to speedup linear reads.
+    cache: Cell<Option<Extent>>,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            inode: self.inode,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("fs", &self.fs)
+            .field("inode", &self.inode)
+            .finish()
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    /// Open the given file by inode number.
+    pub fn new(fs: &'a XfsFs<'a, D>, ino: u64) -> Result<Self, Error> {
+        let inode = fs.inode(ino)?;
+        let res = Self {
+            fs,
+            inode,
+            cache: Cell::new(None),
+        };
+        let valid = match inode.format {
+            Format::Device => matches!(
+                res.ftype(),
+                FileType::CharDevice | FileType::BlockDevice | FileType::Fifo | FileType::Socket
+            ),
+            Format::Local => matches!(res.ftype(), FileType::Directory | FileType::SymLink),
+            Format::Extents | Format::Btree => {
+                matches!(res.ftype(), FileType::File | FileType::Directory | FileType::SymLink)
+            }
+        };
+        if !valid {
+            return Err(msg2err!("invalid inode format"));
+        }
+        Ok(res)
+    }
+
+    pub fn inode(&self) -> &Inode {
+        &self.inode
+    }
+
+    pub fn id(&self) -> u64 {
+        self.inode.ino
+    }
+
+    pub fn ftype(&self) -> FileType {
+        self.inode.file_type()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.ftype() == FileType::Directory
+    }
+
+    /// The first extent of the data fork that ends after the file block.
+    pub fn extent(&self, block: u64) -> Result<Option<Extent>, Error> {
+        if let Some(extent) = self.cache.get().filter(|x| x.offset <= block && block < x.end()) {
+            return Ok(Some(extent));
+        }
+        let res = bmap::extent(self.fs, &self.inode, block)?;
+        self.cache.set(res);
+        Ok(res)
+    }
+
+    /// Read from the data fork independent of the file size.  Holes read as zero.
+    pub(crate) fn read_data(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.inode.format == Format::Local {
+            let end = core::cmp::min(self.inode.size, self.inode.fork_size as u64);
+            let n = core::cmp::min(buf.len() as u64, end.saturating_sub(offset)) as usize;
+            self.fs.disk().read_exact(self.inode.fork + offset, &mut buf[..n])?;
+            return Ok(n);
+        }
+        let blocksize = self.fs.sb.blocksize as Offset;
+        let block = offset / blocksize;
+        let (extent, end) = match self.extent(block)? {
+            None => (None, Offset::MAX),
+            Some(x) if x.offset > block => (None, x.offset.saturating_mul(blocksize)),
+            Some(x) => (Some(x), x.end().saturating_mul(blocksize)),
+        };
+        let n = core::cmp::min(buf.len() as u64, end - offset) as usize;
+        let buf = &mut buf[..n];
+        match extent {
+            Some(x) if !x.unwritten => {
+                let pos = self.fs.block_pos(x.block, x.count)?;
+                self.fs.disk().read_bytes(pos + offset - x.offset * blocksize, buf)
+            }
+            _ => {
+                buf.fill(0);
+                Ok(n)
+            }
+        }
+    }
+
+    /// Fill the buffer from the data fork.
+    pub(crate) fn read_data_exact(&self, mut offset: Offset, mut buf: &mut [u8]) -> Result<(), Error> {
+        while !buf.is_empty() {
+            let n = self.read_data(offset, buf)?;
+            if n == 0 {
+                return Err(msg2err!("unexpected end of data"));
+            }
+            offset += n as Offset;
+            buf = &mut buf[n..];
+        }
+        Ok(())
+    }
+
+    /// Check the CRC of a metadata block in the data fork.
+    pub(crate) fn check_crc(&self, offset: Offset, len: usize, crc: usize) -> Result<(), Error> {
+        check_crc(|x, buf| self.read_data_exact(offset + x as Offset, buf), len, crc)
+    }
+
+    /// Read a symlink that is stored in blocks, which each start with a header.
+    fn read_remote_symlink(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        let blocksize = self.fs.sb.blocksize as usize;
+        let payload = (blocksize - SYMLINK_HEADER) as Offset;
+        let (index, within) = (offset / payload, offset % payload);
+        let start = index << self.fs.sb.blocklog;
+        let mut header = [0; SYMLINK_HEADER];
+        self.read_data_exact(start, &mut header)?;
+        if be32(&header, 0) != SYMLINK_MAGIC
+            || be32(&header, 4) as Offset != index * payload
+            || &header[16..32] != self.fs.sb.metadata_uuid()
+            || be64(&header, 32) != self.inode.ino
+        {
+            return Err(msg2err!("invalid symlink block"));
+        }
+        self.check_crc(start, blocksize, 12)?;
+        let n = core::cmp::min(buf.len() as Offset, payload - within) as usize;
+        self.read_data_exact(start + SYMLINK_HEADER as Offset + within, &mut buf[..n])?;
+        Ok(n)
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.is_dir() {
+            return Some(Dir::new(self));
+        }
+        None
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        Self::new(self.fs, dir::child(self, offset)?)
+    }
+
+    fn lookup(&self, name: &[u8]) -> Result<Option<Self>, Error> {
+        if !self.is_dir() {
+            return Err(msg2err!("not a directory"));
+        }
+        match dir::lookup(self, name)? {
+            Some(ino) => Self::new(self.fs, ino).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if offset >= self.inode.size || !matches!(self.ftype(), FileType::File | FileType::SymLink) {
+            return Ok(0);
+        }
+        if self.inode.flags & FLAG_REALTIME != 0 {
+            return Err(msg2err!("realtime files are not supported"));
+        }
+        let max_n = core::cmp::min(buf.len() as Offset, self.inode.size - offset) as usize;
+        let buf = &mut buf[..max_n];
+        if self.ftype() == FileType::SymLink && self.inode.format != Format::Local {
+            return self.read_remote_symlink(offset, buf);
+        }
+        self.read_data(offset, buf)
+    }
+}
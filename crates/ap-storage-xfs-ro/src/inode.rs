@@ -0,0 +1,143 @@
+//! Inodes of XFS.
+
+use super::{be16, be32, be64, Superblock};
+use ap_storage::{file::FileType, msg2err, Error, Offset};
+
+/// The size of the inode core in front of the data fork.
+pub(crate) const CORE: usize = 176;
+
+/// The bytes parsed from an inode, which includes the device number at the start of the data fork.
+pub(crate) const SIZE: usize = CORE + 4;
+
+const MAGIC: u16 = 0x494e;
+
+/// The flag of files on the realtime device.
+pub(crate) const FLAG_REALTIME: u16 = 1 << 0;
+
+const FLAG2_BIGTIME: u64 = 1 << 3;
+const FLAG2_NREXT64: u64 = 1 << 4;
+
+/// The offset of large timestamps, which count from 1901.
+pub(crate) const BIGTIME_EPOCH: i64 = (1 << 31) * 1_000_000_000;
+
+/// The format of a fork.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A device number.
+    Device,
+    /// The data in the inode.
+    Local,
+    /// A list of extents in the inode.
+    Extents,
+    /// A btree of extents with the root in the inode.
+    Btree,
+}
+
+/// A v3 inode.
+#[derive(Debug, Clone, Copy)]
+pub struct Inode {
+    pub mode: u16,
+    pub format: Format,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub size: u64,
+    /// The blocks allocated to the file.
+    pub nblocks: u64,
+    pub nextents: u64,
+    pub flags: u16,
+    pub flags2: u64,
+    pub generation: u32,
+    /// The times in nanoseconds.
+    pub atime: i64,
+    pub mtime: i64,
+    pub ctime: i64,
+    pub crtime: i64,
+    pub ino: u64,
+    /// The raw device number.
+    pub rdev: u32,
+    /// The disk position and size of the data fork.
+    pub(crate) fork: Offset,
+    pub(crate) fork_size: usize,
+}
+
+impl Inode {
+    pub fn parse(buf: &[u8; SIZE], pos: Offset, sb: &Superblock) -> Result<Self, Error> {
+        if be16(buf, 0) != MAGIC || buf[4] != 3 || &buf[160..176] != sb.metadata_uuid() {
+            return Err(msg2err!("invalid inode"));
+        }
+        let flags2 = be64(buf, 120);
+        let time = |pos| match flags2 & FLAG2_BIGTIME {
+            0 => (be32(buf, pos) as i32 as i64) * 1_000_000_000 + be32(buf, pos + 4) as i64,
+            _ => (be64(buf, pos) as i64).wrapping_sub(BIGTIME_EPOCH),
+        };
+        let format = match buf[5] {
+            0 => Format::Device,
+            1 => Format::Local,
+            2 => Format::Extents,
+            3 => Format::Btree,
+            _ => return Err(msg2err!("unsupported inode format")),
+        };
+        let fork_size = match buf[82] as usize * 8 {
+            0 => sb.inodesize as usize - CORE,
+            x if x <= sb.inodesize as usize - CORE => x,
+            _ => return Err(msg2err!("invalid fork offset")),
+        };
+        Ok(Self {
+            mode: be16(buf, 2),
+            format,
+            uid: be32(buf, 8),
+            gid: be32(buf, 12),
+            nlink: be32(buf, 16),
+            size: be64(buf, 56),
+            nblocks: be64(buf, 64),
+            nextents: match flags2 & FLAG2_NREXT64 {
+                0 => be32(buf, 76) as u64,
+                _ => be64(buf, 24),
+            },
+            flags: be16(buf, 90),
+            flags2,
+            generation: be32(buf, 92),
+            atime: time(32),
+            mtime: time(40),
+            ctime: time(48),
+            crtime: time(144),
+            ino: be64(buf, 152),
+            rdev: be32(buf, CORE),
+            fork: pos + CORE as Offset,
+            fork_size,
+        })
+    }
+
+    pub fn file_type(&self) -> FileType {
+        match self.mode >> 12 {
+            0o01 => FileType::Fifo,
+            0o02 => FileType::CharDevice,
+            0o04 => FileType::Directory,
+            0o06 => FileType::BlockDevice,
+            0o10 => FileType::File,
+            0o12 => FileType::SymLink,
+            0o14 => FileType::Socket,
+            _ => FileType::Unknown,
+        }
+    }
+
+    /// The device number as `major << 32 | minor` from the 9-bit major and 18-bit minor on disk.
+    pub fn device(&self) -> u64 {
+        ((self.rdev >> 18) as u64 & 0x1ff) << 32 | (self.rdev & 0x3_ffff) as u64
+    }
+}
+
+/// The file type in a directory entry.
+pub(crate) fn dir_type(typ: u8) -> FileType {
+    match typ {
+        1 => FileType::File,
+        2 => FileType::Directory,
+        3 => FileType::CharDevice,
+        4 => FileType::BlockDevice,
+        5 => FileType::Fifo,
+        6 => FileType::Socket,
+        7 => FileType::SymLink,
+        _ => FileType::Unknown,
+    }
+}
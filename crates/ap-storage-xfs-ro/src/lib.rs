@@ -0,0 +1,962 @@
+//! Read-only access to XFS filesystems.
+//!
+//! - v5 superblocks with checksums on all metadata that is read
+//! - allocation group headers and the inode btree to check that an inode is in an allocated chunk
+//! - extent-list and btree data forks with holes and unwritten extents
+//! - shortform, block, leaf, node and btree directories
+//!
+//! Reading needs no allocations.  Directories are listed from their data blocks, while names are looked up via
+//! their hash in the leaf blocks or the node tree.  The offset of a directory entry is its byte position in the
+//! directory data, with `.` and `..` of shortform directories at the places they would have in a block.
+
+#![no_std]
+
+mod attr;
+mod bmap;
+mod dir;
+pub mod file;
+mod inode;
+
+pub use bmap::Extent;
+pub use inode::{Format, Inode};
+
+use ap_storage::{msg2err, Error, FileSystem, Offset, Read, ReadExt};
+
+const MAGIC: u32 = 0x5846_5342;
+const AGF_MAGIC: u32 = 0x5841_4746;
+const AGI_MAGIC: u32 = 0x5841_4749;
+const IBT_MAGIC: u32 = 0x4941_4233;
+
+/// The version bit of case-insensitive names, which hash differently.
+const VERSION_ASCII_CI: u16 = 0x4000;
+
+/// The incompatible features that do not change how files are read.
+const INCOMPAT_FTYPE: u32 = 1 << 0;
+const INCOMPAT_SPINODES: u32 = 1 << 1;
+const INCOMPAT_META_UUID: u32 = 1 << 2;
+const INCOMPAT_BIGTIME: u32 = 1 << 3;
+const INCOMPAT_NEEDSREPAIR: u32 = 1 << 4;
+const INCOMPAT_NREXT64: u32 = 1 << 5;
+const INCOMPAT_EXCHRANGE: u32 = 1 << 6;
+const INCOMPAT_PARENT: u32 = 1 << 7;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FTYPE
+    | INCOMPAT_SPINODES
+    | INCOMPAT_META_UUID
+    | INCOMPAT_BIGTIME
+    | INCOMPAT_NEEDSREPAIR
+    | INCOMPAT_NREXT64
+    | INCOMPAT_EXCHRANGE
+    | INCOMPAT_PARENT;
+
+/// The size of the header of short btree blocks as used by the inode btree.
+const SHORT_HEADER: usize = 56;
+
+/// The inodes in a chunk and per bit of the hole mask.
+const CHUNK_INODES: u32 = 64;
+const HOLEMASK_INODES: u32 = 4;
+
+/// The superblock.
+#[derive(Debug, Clone, Copy)]
+pub struct Superblock {
+    pub blocksize: u32,
+    pub dblocks: u64,
+    pub rblocks: u64,
+    pub uuid: [u8; 16],
+    pub rootino: u64,
+    pub agblocks: u32,
+    pub agcount: u32,
+    pub versionnum: u16,
+    pub sectsize: u16,
+    pub inodesize: u16,
+    pub inopblock: u16,
+    pub label: [u8; 12],
+    pub blocklog: u8,
+    pub inodelog: u8,
+    pub inopblog: u8,
+    pub agblklog: u8,
+    pub icount: u64,
+    pub ifree: u64,
+    pub fdblocks: u64,
+    pub dirblklog: u8,
+    pub features_compat: u32,
+    pub features_ro_compat: u32,
+    pub features_incompat: u32,
+    pub meta_uuid: [u8; 16],
+}
+
+impl Superblock {
+    /// Parse and check the superblock in the first sector.
+    pub fn parse(buf: &[u8; 512]) -> Result<Self, Error> {
+        if be32(buf, 0) != MAGIC {
+            return Err(msg2err!("no xfs"));
+        }
+        let res = Self {
+            blocksize: be32(buf, 4),
+            dblocks: be64(buf, 8),
+            rblocks: be64(buf, 16),
+            uuid: buf[32..48].try_into().unwrap(),
+            rootino: be64(buf, 56),
+            agblocks: be32(buf, 84),
+            agcount: be32(buf, 88),
+            versionnum: be16(buf, 100),
+            sectsize: be16(buf, 102),
+            inodesize: be16(buf, 104),
+            inopblock: be16(buf, 106),
+            label: buf[108..120].try_into().unwrap(),
+            blocklog: buf[120],
+            inodelog: buf[122],
+            inopblog: buf[123],
+            agblklog: buf[124],
+            icount: be64(buf, 128),
+            ifree: be64(buf, 136),
+            fdblocks: be64(buf, 144),
+            dirblklog: buf[192],
+            features_compat: be32(buf, 208),
+            features_ro_compat: be32(buf, 212),
+            features_incompat: be32(buf, 216),
+            meta_uuid: buf[248..264].try_into().unwrap(),
+        };
+        if res.versionnum & 0xf != 5 {
+            return Err(msg2err!("unsupported xfs version"));
+        }
+        if res.versionnum & VERSION_ASCII_CI != 0 {
+            return Err(msg2err!("case-insensitive names"));
+        }
+        if res.features_incompat & !INCOMPAT_SUPPORTED != 0 || res.features_incompat & INCOMPAT_FTYPE == 0 {
+            return Err(msg2err!("incompatible features"));
+        }
+        if !(9..=16).contains(&res.blocklog)
+            || res.blocksize != 1 << res.blocklog
+            || !(8..=11).contains(&res.inodelog)
+            || res.inodesize != 1 << res.inodelog
+            || res.blocklog < res.inodelog
+            || res.inopblog != res.blocklog - res.inodelog
+            || res.inopblock != 1 << res.inopblog
+            || !res.sectsize.is_power_of_two()
+            || !(512..=res.blocksize).contains(&(res.sectsize as u32))
+            || res.blocklog + res.dirblklog > 16
+            || res.agcount == 0
+            || res.agblocks == 0
+            || res.agblklog > 31
+            || res.agblocks as u64 > 1 << res.agblklog
+        {
+            return Err(msg2err!("invalid geometry"));
+        }
+        Ok(res)
+    }
+
+    /// The uuid stamped into metadata blocks.
+    pub fn metadata_uuid(&self) -> &[u8; 16] {
+        match self.features_incompat & INCOMPAT_META_UUID {
+            0 => &self.uuid,
+            _ => &self.meta_uuid,
+        }
+    }
+}
+
+/// The free-space header of an allocation group.
+#[derive(Debug, Clone, Copy)]
+pub struct Agf {
+    pub length: u32,
+    pub freeblks: u32,
+    pub longest: u32,
+}
+
+/// The inode header of an allocation group.
+#[derive(Debug, Clone, Copy)]
+pub struct Agi {
+    pub length: u32,
+    pub count: u32,
+    pub root: u32,
+    pub level: u32,
+    pub freecount: u32,
+}
+
+/// Read-only XFS file-system object.
+pub struct XfsFs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+    sb: Superblock,
+}
+
+impl<D: ?Sized> Clone for XfsFs<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            disk: self.disk,
+            sb: self.sb,
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for XfsFs<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(
+            fmt,
+            "XfsFs(bs {}, ags {}, inodes {})",
+            self.sb.blocksize, self.sb.agcount, self.sb.icount
+        )
+    }
+}
+
+impl<'a, D: Read + ?Sized> XfsFs<'a, D> {
+    /// Mount the filesystem and check the headers of the first allocation group.
+    pub fn new(disk: &'a D) -> Result<Self, Error> {
+        let sb = Superblock::parse(&(&disk as &dyn Read).read_object(0)?)?;
+        let fs = Self { disk, sb };
+        fs.check_crc(0, sb.sectsize as usize, 224)?;
+        fs.agf(0)?;
+        fs.agi(0)?;
+        Ok(fs)
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.sb
+    }
+
+    /// The disk position of a block in an allocation group.
+    pub(crate) fn ag_pos(&self, agno: u32, agbno: u32) -> Result<Offset, Error> {
+        if agno >= self.sb.agcount || agbno >= self.sb.agblocks {
+            return Err(msg2err!("block out of range"));
+        }
+        Ok((agno as Offset * self.sb.agblocks as Offset + agbno as Offset) << self.sb.blocklog)
+    }
+
+    /// The disk position of a run of filesystem blocks, which have the allocation group in the upper bits.
+    pub fn block_pos(&self, block: u64, count: u64) -> Result<Offset, Error> {
+        let (agno, agbno) = (block >> self.sb.agblklog, block & ((1 << self.sb.agblklog) - 1));
+        if agno >= self.sb.agcount as u64 || agbno + count > self.sb.agblocks as u64 {
+            return Err(msg2err!("block out of range"));
+        }
+        self.ag_pos(agno as u32, agbno as u32)
+    }
+
+    /// Check the CRC of a metadata structure that is stored as little-endian at an offset.
+    pub(crate) fn check_crc(&self, pos: Offset, len: usize, offset: usize) -> Result<(), Error> {
+        check_crc(|x, buf| self.disk().read_exact(pos + x as Offset, buf), len, offset)
+    }
+
+    /// Read an allocation group header from its sector.
+    fn ag_header(&self, agno: u32, sector: u64, magic: u32, uuid: usize, crc: usize) -> Result<[u8; 512], Error> {
+        if agno >= self.sb.agcount {
+            return Err(msg2err!("allocation group out of range"));
+        }
+        let pos =
+            ((agno as Offset * self.sb.agblocks as Offset) << self.sb.blocklog) + sector * self.sb.sectsize as u64;
+        let buf: [u8; 512] = self.disk().read_object(pos)?;
+        if be32(&buf, 0) != magic || be32(&buf, 8) != agno || &buf[uuid..uuid + 16] != self.sb.metadata_uuid() {
+            return Err(msg2err!("invalid allocation group header"));
+        }
+        self.check_crc(pos, self.sb.sectsize as usize, crc)?;
+        Ok(buf)
+    }
+
+    /// The free-space header of an allocation group.
+    pub fn agf(&self, agno: u32) -> Result<Agf, Error> {
+        let buf = self.ag_header(agno, 1, AGF_MAGIC, 64, 216)?;
+        Ok(Agf {
+            length: be32(&buf, 12),
+            freeblks: be32(&buf, 52),
+            longest: be32(&buf, 56),
+        })
+    }
+
+    /// The inode header of an allocation group.
+    pub fn agi(&self, agno: u32) -> Result<Agi, Error> {
+        let buf = self.ag_header(agno, 2, AGI_MAGIC, 296, 312)?;
+        Ok(Agi {
+            length: be32(&buf, 12),
+            count: be32(&buf, 16),
+            root: be32(&buf, 20),
+            level: be32(&buf, 24),
+            freecount: be32(&buf, 28),
+        })
+    }
+
+    /// Split an inode number into the allocation group and the inode in it.
+    fn split_ino(&self, ino: u64) -> Result<(u32, u32), Error> {
+        let bits = self.sb.agblklog + self.sb.inopblog;
+        let agno = ino >> bits;
+        if agno >= self.sb.agcount as u64 {
+            return Err(msg2err!("inode out of range"));
+        }
+        Ok((agno as u32, (ino & ((1 << bits) - 1)) as u32))
+    }
+
+    /// Whether the inode is in an allocated chunk and not free, according to the inode btree.
+    pub fn allocated(&self, ino: u64) -> Result<bool, Error> {
+        let (agno, agino) = self.split_ino(ino)?;
+        let agi = self.agi(agno)?;
+        let blocksize = self.sb.blocksize as usize;
+        let (mut agbno, mut level) = (agi.root, agi.level);
+        if level == 0 || level > 8 {
+            return Err(msg2err!("invalid inode btree"));
+        }
+        loop {
+            level -= 1;
+            let pos = self.ag_pos(agno, agbno)?;
+            let header: [u8; SHORT_HEADER] = self.disk().read_object(pos)?;
+            let count = be16(&header, 6) as usize;
+            if be32(&header, 0) != IBT_MAGIC
+                || be16(&header, 4) as u32 != level
+                || be32(&header, 48) != agno
+                || &header[32..48] != self.sb.metadata_uuid()
+            {
+                return Err(msg2err!("invalid inode btree block"));
+            }
+            self.check_crc(pos, blocksize, 52)?;
+            let (size, ptrs) = if level == 0 {
+                (16, 0)
+            } else {
+                (4, (blocksize - SHORT_HEADER) / 8 * 4)
+            };
+            if SHORT_HEADER + count * size + ptrs > blocksize {
+                return Err(msg2err!("invalid inode btree block"));
+            }
+
+            // the last record or key that starts before the inode
+            let index = partition(count, |i| {
+                let mut buf = [0; 4];
+                self.disk()
+                    .read_exact(pos + (SHORT_HEADER + i * size) as Offset, &mut buf)?;
+                Ok(u32::from_be_bytes(buf) <= agino)
+            })?;
+            let Some(index) = index.checked_sub(1) else {
+                return Ok(false);
+            };
+            let pos = pos + (SHORT_HEADER + ptrs + index * size) as Offset;
+            if level != 0 {
+                let ptr: [u8; 4] = self.disk().read_object(pos)?;
+                agbno = u32::from_be_bytes(ptr);
+                continue;
+            }
+            let rec: [u8; 16] = self.disk().read_object(pos)?;
+            let bit = agino - be32(&rec, 0);
+            return Ok(bit < CHUNK_INODES
+                && be16(&rec, 4) >> (bit / HOLEMASK_INODES) & 1 == 0
+                && be64(&rec, 8) >> bit & 1 == 0);
+        }
+    }
+
+    /// Read an allocated inode.
+    pub fn inode(&self, ino: u64) -> Result<Inode, Error> {
+        if !self.allocated(ino)? {
+            return Err(msg2err!("inode not allocated"));
+        }
+        let (agno, agino) = self.split_ino(ino)?;
+        let pos = self.ag_pos(agno, agino >> self.sb.inopblog)?
+            + ((agino & (self.sb.inopblock as u32 - 1)) as Offset) * self.sb.inodesize as Offset;
+        let inode = Inode::parse(&self.disk().read_object(pos)?, pos, &self.sb)?;
+        if inode.ino != ino {
+            return Err(msg2err!("inode number mismatch"));
+        }
+        self.check_crc(pos, self.sb.inodesize as usize, 100)?;
+        Ok(inode)
+    }
+
+    /// Open a file by its inode number.
+    pub fn open(&'a self, ino: u64) -> Result<file::File<'a, D>, Error> {
+        file::File::new(self, ino)
+    }
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for XfsFs<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        self.open(self.sb.rootino)
+    }
+}
+
+/// The number of leading elements for which the predicate holds.
+pub(crate) fn partition(count: usize, mut pred: impl FnMut(usize) -> Result<bool, Error>) -> Result<usize, Error> {
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if pred(mid)? {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    Ok(lo)
+}
+
+/// Check the CRC of a structure that is read in pieces.  The checksum is stored as little-endian at an offset.
+pub(crate) fn check_crc(
+    mut read: impl FnMut(usize, &mut [u8]) -> Result<(), Error>,
+    len: usize,
+    offset: usize,
+) -> Result<(), Error> {
+    let mut buf = [0; 512];
+    let (mut crc, mut stored) = (!0, [0; 4]);
+    for pos in (0..len).step_by(buf.len()) {
+        let buf = &mut buf[..core::cmp::min(512, len - pos)];
+        read(pos, buf)?;
+        if (pos..pos + buf.len()).contains(&offset) {
+            stored.copy_from_slice(&buf[offset - pos..offset - pos + 4]);
+            buf[offset - pos..offset - pos + 4].fill(0);
+        }
+        crc = crc32c(crc, buf);
+    }
+    if !crc != u32::from_le_bytes(stored) {
+        return Err(msg2err!("checksum mismatch"));
+    }
+    Ok(())
+}
+
+/// The CRC-32C without the final inversion.
+pub fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for x in data {
+        crc ^= *x as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0x82f6_3b78 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// A big-endian u16 at the position.
+pub(crate) fn be16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([buf[pos], buf[pos + 1]])
+}
+
+/// A big-endian u32 at the position.
+pub(crate) fn be32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+/// A big-endian u64 at the position.
+pub(crate) fn be64(buf: &[u8], pos: usize) -> u64 {
+    u64::from_be_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{
+        attr::{self, Attributes, Value},
+        file::{File, FileType},
+    };
+    use ap_storage_memory::ReadSlice;
+    use ap_storage_test_util::*;
+    use std::{string::String, vec, vec::Vec};
+
+    const BS: usize = 1024;
+    const AGBLOCKS: usize = 100;
+    const UUID: [u8; 16] = [0x11; 16];
+
+    /// The first leaf block of a directory.
+    const LEAF: u64 = 32 << 20;
+
+    /// The inodes are in the blocks 8 to 12 of the first allocation group.
+    const ROOT: u64 = 16;
+    const SHORT: u64 = 17;
+    const LEAFDIR: u64 = 18;
+    const NODE: u64 = 19;
+    const HELLO: u64 = 20;
+    const SPARSE: u64 = 21;
+    const BIG: u64 = 22;
+    const SYM: u64 = 23;
+    const LONG: u64 = 24;
+    const TTY: u64 = 25;
+    const FAR: u64 = 1 << 8 | 16;
+
+    /// The modification time in nanoseconds.
+    const MTIME: i64 = 1_700_000_000_000_000_005;
+
+    fn put(buf: &mut [u8], pos: usize, value: u64, size: usize) {
+        buf[pos..pos + size].copy_from_slice(&value.to_be_bytes()[8 - size..]);
+    }
+
+    /// Store the checksum of a structure.
+    fn crc(buf: &mut [u8], offset: usize) {
+        buf[offset..offset + 4].fill(0);
+        let crc = !crc32c(!0, buf);
+        buf[offset..offset + 4].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn extent(offset: u64, block: u64, count: u64, unwritten: bool) -> [u8; 16] {
+        let mut res = [0; 16];
+        put(&mut res, 0, (unwritten as u64) << 63 | offset << 9 | block >> 43, 8);
+        put(&mut res, 8, block << 21 | count, 8);
+        res
+    }
+
+    /// Sorted hashes of the entries and their addresses in the directory.
+    type Hashes = Vec<(u32, u32)>;
+
+    /// A data block with the entries up to the end.  Entries without a name are unused space.
+    fn data_block(magic: u32, owner: u64, start: u64, entries: &[(u64, &str, u8)], end: usize) -> (Vec<u8>, Hashes) {
+        let mut buf = vec![0; BS];
+        let mut hashes = Vec::new();
+        put(&mut buf, 0, magic as u64, 4);
+        buf[24..40].copy_from_slice(&UUID);
+        put(&mut buf, 40, owner, 8);
+        let mut pos = 64;
+        let free = |buf: &mut Vec<u8>, pos: usize, size: usize| {
+            put(buf, pos, 0xffff, 2);
+            put(buf, pos + 2, size as u64, 2);
+            put(buf, pos + size - 2, pos as u64, 2);
+        };
+        for (ino, name, typ) in entries {
+            if name.is_empty() {
+                free(&mut buf, pos, 16);
+                pos += 16;
+                continue;
+            }
+            let size = (8 + 1 + name.len() + 1 + 2 + 7) & !7;
+            put(&mut buf, pos, *ino, 8);
+            buf[pos + 8] = name.len() as u8;
+            buf[pos + 9..pos + 9 + name.len()].copy_from_slice(name.as_bytes());
+            buf[pos + 9 + name.len()] = *typ;
+            put(&mut buf, pos + size - 2, pos as u64, 2);
+            hashes.push((dir::hash(name.as_bytes()), ((start as usize * BS + pos) / 8) as u32));
+            pos += size;
+        }
+        if pos < end {
+            free(&mut buf, pos, end - pos);
+        }
+        crc(&mut buf, 4);
+        (buf, hashes)
+    }
+
+    /// A leaf or node block with the hashes.
+    fn da_block(magic: u16, owner: u64, forw: u64, level: u16, hashes: &[(u32, u32)]) -> Vec<u8> {
+        let mut buf = vec![0; BS];
+        put(&mut buf, 0, forw, 4);
+        put(&mut buf, 8, magic as u64, 2);
+        buf[32..48].copy_from_slice(&UUID);
+        put(&mut buf, 48, owner, 8);
+        put(&mut buf, 56, hashes.len() as u64, 2);
+        put(&mut buf, 58, level as u64, 2);
+        for (i, (hash, address)) in hashes.iter().enumerate() {
+            put(&mut buf, 64 + i * 8, *hash as u64, 4);
+            put(&mut buf, 68 + i * 8, *address as u64, 4);
+        }
+        crc(&mut buf, 12);
+        buf
+    }
+
+    /// The root of an extent btree in the inode.
+    fn bmap_root(children: &[(u64, u64)]) -> Vec<u8> {
+        let mut buf = vec![0; 512 - 176];
+        let maxrecs = (buf.len() - 4) / 16;
+        put(&mut buf, 0, 1, 2);
+        put(&mut buf, 2, children.len() as u64, 2);
+        for (i, (key, ptr)) in children.iter().enumerate() {
+            put(&mut buf, 4 + i * 8, *key, 8);
+            put(&mut buf, 4 + maxrecs * 8 + i * 8, *ptr, 8);
+        }
+        buf
+    }
+
+    struct Image {
+        data: Vec<u8>,
+        /// The next free block per allocation group.
+        next: [u64; 2],
+    }
+
+    impl Image {
+        fn pos(block: u64) -> usize {
+            ((block >> 7) as usize * AGBLOCKS + (block & 127) as usize) * BS
+        }
+
+        fn alloc(&mut self, ag: usize) -> u64 {
+            self.next[ag] += 1;
+            (ag as u64) << 7 | (self.next[ag] - 1)
+        }
+
+        fn write(&mut self, block: u64, buf: &[u8]) {
+            let pos = Self::pos(block);
+            self.data[pos..pos + buf.len()].copy_from_slice(buf);
+        }
+
+        /// Allocate a block with data.
+        fn block(&mut self, ag: usize, buf: &[u8]) -> u64 {
+            let block = self.alloc(ag);
+            self.write(block, buf);
+            block
+        }
+
+        fn inode(&mut self, ino: u64, mode: u16, format: u8, size: u64, nextents: usize, fork: &[u8]) {
+            let mut buf = vec![0; 512];
+            put(&mut buf, 0, 0x494e, 2);
+            put(&mut buf, 2, mode as u64, 2);
+            (buf[4], buf[5]) = (3, format);
+            put(&mut buf, 8, 1000, 4);
+            put(&mut buf, 12, 100, 4);
+            put(&mut buf, 16, 1, 4);
+            if ino == HELLO {
+                put(&mut buf, 40, (MTIME + inode::BIGTIME_EPOCH) as u64, 8);
+                put(&mut buf, 120, 1 << 3, 8);
+            } else {
+                put(&mut buf, 40, (MTIME / 1_000_000_000) as u64, 4);
+                put(&mut buf, 44, (MTIME % 1_000_000_000) as u64, 4);
+            }
+            put(&mut buf, 56, size, 8);
+            put(&mut buf, 76, nextents as u64, 4);
+            put(&mut buf, 152, ino, 8);
+            buf[160..176].copy_from_slice(&UUID);
+            buf[176..176 + fork.len()].copy_from_slice(fork);
+            crc(&mut buf, 100);
+            let agino = (ino & 255) as usize;
+            let pos = Self::pos((ino >> 8) << 7 | (agino >> 1) as u64) + (agino & 1) * 512;
+            self.data[pos..pos + 512].copy_from_slice(&buf);
+        }
+
+        fn file(&mut self, ino: u64, mode: u16, size: u64, extents: &[[u8; 16]]) {
+            self.inode(ino, mode, 2, size, extents.len(), &extents.concat());
+        }
+
+        /// A leaf of an extent btree.
+        fn bmap_block(&mut self, block: u64, owner: u64, extents: &[[u8; 16]], right: u64) {
+            let mut buf = vec![0; BS];
+            put(&mut buf, 0, 0x424d_4133, 4);
+            put(&mut buf, 6, extents.len() as u64, 2);
+            put(&mut buf, 8, u64::MAX, 8);
+            put(&mut buf, 16, right, 8);
+            put(&mut buf, 24, (Self::pos(block) / 512) as u64, 8);
+            buf[40..56].copy_from_slice(&UUID);
+            put(&mut buf, 56, owner, 8);
+            buf[72..72 + extents.len() * 16].copy_from_slice(&extents.concat());
+            crc(&mut buf, 64);
+            self.write(block, &buf);
+        }
+
+        /// A block of the inode btree with the records or the keys and pointers.
+        fn inobt_block(&mut self, agno: u64, agbno: u64, level: u16, records: &[[u8; 16]]) {
+            let mut buf = vec![0; BS];
+            put(&mut buf, 0, IBT_MAGIC as u64, 4);
+            put(&mut buf, 4, level as u64, 2);
+            put(&mut buf, 6, records.len() as u64, 2);
+            put(&mut buf, 8, u32::MAX as u64, 4);
+            put(&mut buf, 12, u32::MAX as u64, 4);
+            buf[32..48].copy_from_slice(&UUID);
+            put(&mut buf, 48, agno, 4);
+            if level == 0 {
+                buf[56..56 + records.len() * 16].copy_from_slice(&records.concat());
+            } else {
+                let maxrecs = (BS - 56) / 8;
+                for (i, record) in records.iter().enumerate() {
+                    buf[56 + i * 4..60 + i * 4].copy_from_slice(&record[..4]);
+                    put(&mut buf, 56 + maxrecs * 4 + i * 4, agbno - 1, 4);
+                }
+            }
+            crc(&mut buf, 52);
+            self.write(agno << 7 | agbno, &buf);
+        }
+
+        /// The headers of an allocation group with a sparse inode chunk.
+        fn ag(&mut self, agno: u64, holemask: u16, free: u64, level: u32) {
+            let mut record = [0; 16];
+            put(&mut record, 4, holemask as u64, 2);
+            put(&mut record, 8, free, 8);
+            for level in 0..level as u16 {
+                self.inobt_block(agno, 3 + level as u64, level, &[record]);
+            }
+            let mut agf = vec![0; 512];
+            put(&mut agf, 0, AGF_MAGIC as u64, 4);
+            put(&mut agf, 4, 1, 4);
+            put(&mut agf, 8, agno, 4);
+            put(&mut agf, 12, AGBLOCKS as u64, 4);
+            agf[64..80].copy_from_slice(&UUID);
+            crc(&mut agf, 216);
+            let mut agi = vec![0; 512];
+            put(&mut agi, 0, AGI_MAGIC as u64, 4);
+            put(&mut agi, 4, 1, 4);
+            put(&mut agi, 8, agno, 4);
+            put(&mut agi, 12, AGBLOCKS as u64, 4);
+            put(&mut agi, 20, 2 + level as u64, 4);
+            put(&mut agi, 24, level as u64, 4);
+            agi[296..312].copy_from_slice(&UUID);
+            crc(&mut agi, 312);
+            let pos = Self::pos(agno << 7);
+            self.data[pos + 512..pos + 1024].copy_from_slice(&agf);
+            self.data[pos + 1024..pos + 1536].copy_from_slice(&agi);
+        }
+    }
+
+    fn image() -> Vec<u8> {
+        let mut img = Image {
+            data: vec![0; 2 * AGBLOCKS * BS],
+            next: [16, 16],
+        };
+        let mut sb = vec![0; 512];
+        put(&mut sb, 0, MAGIC as u64, 4);
+        put(&mut sb, 4, BS as u64, 4);
+        put(&mut sb, 8, 2 * AGBLOCKS as u64, 8);
+        sb[32..48].copy_from_slice(&UUID);
+        put(&mut sb, 56, ROOT, 8);
+        put(&mut sb, 84, AGBLOCKS as u64, 4);
+        put(&mut sb, 88, 2, 4);
+        put(&mut sb, 100, 0xb4a5, 2);
+        put(&mut sb, 102, 512, 2);
+        put(&mut sb, 104, 512, 2);
+        put(&mut sb, 106, 2, 2);
+        sb[108..112].copy_from_slice(b"test");
+        sb[120..125].copy_from_slice(&[10, 9, 9, 1, 7]);
+        put(
+            &mut sb,
+            216,
+            (INCOMPAT_FTYPE | INCOMPAT_SPINODES | INCOMPAT_BIGTIME) as u64,
+            4,
+        );
+        crc(&mut sb, 224);
+        img.data[..512].copy_from_slice(&sb);
+
+        // the inodes 16 to 25 are allocated in the first group and 16 in the second with a two-level btree
+        img.ag(0, !0xf0, !(0x3ff << 16), 1);
+        img.ag(1, !0x10, !(1 << 16), 2);
+
+        let hello = img.block(0, b"hello world\n");
+        img.file(HELLO, 0o100644, 12, &[extent(0, hello, 1, false)]);
+        let far = img.block(1, b"far\n");
+        img.file(FAR, 0o100644, 4, &[extent(0, far, 1, false)]);
+
+        // a hole and unwritten blocks that are not zero on disk
+        let (a, b) = (img.block(0, &[b'a'; BS]), img.block(0, &[b'x'; BS]));
+        img.block(0, &[b'x'; BS]);
+        let c = img.block(0, &[b'c'; BS]);
+        let extents = [extent(0, a, 1, false), extent(2, b, 2, true), extent(4, c, 1, false)];
+        img.file(SPARSE, 0o100644, 5000, &extents);
+
+        // holes within and between the leaves of an extent btree
+        let (left, right) = (img.alloc(0), img.alloc(0));
+        let mut extents = Vec::new();
+        for (i, block) in [0, 2, 4, 8, 10, 12].into_iter().enumerate() {
+            extents.push(extent(block, img.block(0, &[b'A' + i as u8; BS]), 1, false));
+        }
+        img.bmap_block(left, BIG, &extents[..3], right);
+        img.bmap_block(right, BIG, &extents[3..], u64::MAX);
+        img.inode(
+            BIG,
+            0o100644,
+            3,
+            13 * BS as u64,
+            6,
+            &bmap_root(&[(0, left), (8, right)]),
+        );
+
+        img.inode(SYM, 0o120777, 1, 5, 0, b"hello");
+        let target = pattern(b"abcdefghij/", 1000);
+        let mut extents = Vec::new();
+        for (i, chunk) in target.chunks(BS - 56).enumerate() {
+            let block = img.alloc(0);
+            let mut buf = vec![0; BS];
+            put(&mut buf, 0, 0x5853_4c4d, 4);
+            put(&mut buf, 4, (i * (BS - 56)) as u64, 4);
+            put(&mut buf, 8, chunk.len() as u64, 4);
+            buf[16..32].copy_from_slice(&UUID);
+            put(&mut buf, 32, LONG, 8);
+            put(&mut buf, 40, (Image::pos(block) / 512) as u64, 8);
+            buf[56..56 + chunk.len()].copy_from_slice(chunk);
+            crc(&mut buf, 12);
+            img.write(block, &buf);
+            extents.push(extent(i as u64, block, 1, false));
+        }
+        img.file(LONG, 0o120777, target.len() as u64, &extents);
+
+        let mut rdev = [0; 4];
+        put(&mut rdev, 0, 4 << 18 | 64, 4);
+        img.inode(TTY, 0o020620, 0, 0, 0, &rdev);
+
+        let mut short = vec![2, 0, 0, 0, 0, ROOT as u8];
+        short.extend_from_slice(&[1, 0, 96, b'a', 1, 0, 0, 0, HELLO as u8]);
+        short.extend_from_slice(&[3, 0, 112, b'f', b'a', b'r', 1, 0, 0, 1, 16]);
+        img.inode(SHORT, 0o040755, 1, short.len() as u64, 0, &short);
+
+        // a single leaf with data blocks around a hole
+        let names: Vec<String> = (0..10).map(|i| std::format!("f{i}")).collect();
+        let mut entries = vec![(LEAFDIR, ".", 2), (ROOT, "..", 2)];
+        entries.extend(names.iter().map(|x| (HELLO, x.as_str(), 1)));
+        let (first, mut hashes) = data_block(dir::DATA_MAGIC, LEAFDIR, 0, &entries[..7], BS);
+        let (second, more) = data_block(dir::DATA_MAGIC, LEAFDIR, 2, &entries[7..], BS);
+        hashes.extend(more);
+        hashes.push((dir::hash(b"gone"), 0));
+        hashes.sort();
+        let mut leaf = da_block(0x3df1, LEAFDIR, 0, 0, &hashes);
+        put(&mut leaf, 58, 1, 2);
+        crc(&mut leaf, 12);
+        let extents = [
+            extent(0, img.block(0, &first), 1, false),
+            extent(2, img.block(0, &second), 1, false),
+            extent(LEAF, img.block(0, &leaf), 1, false),
+        ];
+        img.file(LEAFDIR, 0o040755, 3 * BS as u64, &extents);
+
+        // a node with two leaves and an extent btree
+        let names: Vec<String> = (0..20).map(|i| std::format!("n{i:02}")).collect();
+        let mut entries = vec![(NODE, ".", 2), (ROOT, "..", 2)];
+        entries.extend(names.iter().map(|x| (HELLO, x.as_str(), 1)));
+        let (first, mut hashes) = data_block(dir::DATA_MAGIC, NODE, 0, &entries[..12], BS);
+        let (second, more) = data_block(dir::DATA_MAGIC, NODE, 1, &entries[12..], BS);
+        hashes.extend(more);
+        hashes.sort();
+        let (lo, hi) = hashes.split_at(11);
+        let node = [(lo[10].0, (LEAF + 1) as u32), (hi[10].0, (LEAF + 2) as u32)];
+        let blocks = [
+            img.block(0, &first),
+            img.block(0, &second),
+            img.block(0, &da_block(0x3ebe, NODE, 0, 1, &node)),
+            img.block(0, &da_block(0x3dff, NODE, LEAF + 2, 0, lo)),
+            img.block(0, &da_block(0x3dff, NODE, 0, 0, hi)),
+        ];
+        let extents: Vec<_> = [0, 1, LEAF, LEAF + 1, LEAF + 2]
+            .iter()
+            .zip(blocks)
+            .map(|(offset, block)| extent(*offset, block, 1, false))
+            .collect();
+        let leaf = img.alloc(0);
+        img.bmap_block(leaf, NODE, &extents, u64::MAX);
+        img.inode(NODE, 0o040755, 3, 2 * BS as u64, 5, &bmap_root(&[(0, leaf)]));
+
+        // a single block with unused space and a stale hash
+        let entries = [
+            (ROOT, ".", 2),
+            (ROOT, "..", 2),
+            (SHORT, "short", 2),
+            (LEAFDIR, "leaf", 2),
+            (NODE, "node", 2),
+            (HELLO, "hello", 1),
+            (0, "", 0),
+            (SPARSE, "sparse", 1),
+            (BIG, "big", 1),
+            (SYM, "sym", 7),
+            (LONG, "long", 7),
+            (TTY, "tty", 3),
+            (FAR, "far", 1),
+        ];
+        let mut hashes = data_block(0, 0, 0, &entries, BS).1;
+        hashes.push((dir::hash(b"gone"), 0));
+        hashes.sort();
+        let end = BS - 8 - hashes.len() * 8;
+        let mut block = data_block(dir::BLOCK_MAGIC, ROOT, 0, &entries, end).0;
+        for (i, (hash, address)) in hashes.iter().enumerate() {
+            put(&mut block, end + i * 8, *hash as u64, 4);
+            put(&mut block, end + i * 8 + 4, *address as u64, 4);
+        }
+        put(&mut block, BS - 8, hashes.len() as u64, 4);
+        put(&mut block, BS - 4, 1, 4);
+        crc(&mut block, 4);
+        let root = img.block(0, &block);
+        img.file(ROOT, 0o040755, BS as u64, &[extent(0, root, 1, false)]);
+        img.data
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(!crc32c(!0, b"123456789"), 0xe306_9283);
+        assert_eq!(dir::hash(b".."), 0x172e);
+        assert_eq!(dir::hash(b"hello.txt"), 0x9d16_8f12);
+        assert_eq!(dir::hash(b"lost+foundx"), 0x0d53_0679);
+        assert_eq!(dir::hash(b"lost+found.d"), 0xa983_17e2);
+        let mut data = image();
+        assert!(XfsFs::new(&ReadSlice(&data)).is_ok());
+        data[108] = b'T';
+        assert!(XfsFs::new(&ReadSlice(&data)).is_err());
+    }
+
+    #[test]
+    fn files() {
+        let data = image();
+        let disk = ReadSlice(&data);
+        let fs = XfsFs::new(&disk).unwrap();
+        let root = fs.root().unwrap();
+        let listing = list(&root);
+        let expected = [
+            ".", "..", "short", "leaf", "node", "hello", "sparse", "big", "sym", "long", "tty", "far",
+        ];
+        assert_eq!(names(&listing), expected);
+        assert_eq!(listing[1].1, FileType::Parent);
+        assert_eq!(listing[4].1, FileType::Directory);
+        assert_eq!(listing[9].1, FileType::SymLink);
+
+        let open = |path: &str| root.clone().lookup_path(path.as_bytes()).unwrap();
+        assert_eq!(read_all(&open("hello")), b"hello world\n");
+        assert_eq!(read_all(&open("far")), b"far\n");
+        let mut sparse = vec![0; 5000];
+        sparse[..BS].fill(b'a');
+        sparse[4 * BS..].fill(b'c');
+        assert_eq!(read_all(&open("sparse")), sparse);
+        let mut big = vec![0; 13 * BS];
+        for (i, block) in [0, 2, 4, 8, 10, 12].into_iter().enumerate() {
+            big[block * BS..(block + 1) * BS].fill(b'A' + i as u8);
+        }
+        assert_eq!(read_all(&open("big")), big);
+        assert_eq!(read_all(&open("sym")), b"hello");
+        assert_eq!(read_all(&open("long")), pattern(b"abcdefghij/", 1000));
+        assert!(root.lookup(b"gone").unwrap().is_none());
+
+        let tty = open("tty");
+        assert_eq!(tty.ftype(), FileType::CharDevice);
+        assert_eq!(
+            tty.attr().get(attr::RDEV, &mut []).and_then(|x| x.as_u64()),
+            Some(4 << 32 | 64)
+        );
+        for name in ["hello", "sparse"] {
+            assert!(matches!(
+                open(name).attr().get(attr::MTIME, &mut []),
+                Some(Value::Time(MTIME))
+            ));
+        }
+
+        // allocated inodes in sparse chunks
+        assert!(fs.allocated(FAR).unwrap());
+        assert!(!fs.allocated(26).unwrap());
+        assert!(!fs.allocated(12).unwrap());
+        assert!(!fs.allocated(1 << 8 | 20).unwrap());
+        assert!(fs.inode(26).is_err());
+    }
+
+    #[test]
+    fn directories() {
+        let data = image();
+        let disk = ReadSlice(&data);
+        let fs = XfsFs::new(&disk).unwrap();
+        let root = fs.root().unwrap();
+        let open = |path: &str| root.clone().lookup_path(path.as_bytes()).unwrap();
+
+        let short = open("short");
+        let listing = list(&short);
+        assert_eq!(names(&listing), [".", "..", "a", "far"]);
+        assert_eq!(listing[0].1, FileType::Parent);
+        assert_eq!(open("short/..").id(), ROOT);
+        assert_eq!(open("short/far").id(), FAR);
+        assert!(short.lookup(b"b").unwrap().is_none());
+
+        let leaf = list(&open("leaf"));
+        assert_eq!(leaf.len(), 12);
+        for i in 0..10 {
+            assert_eq!(leaf[i + 2].0, std::format!("f{i}"));
+            assert_eq!(open(&std::format!("leaf/f{i}")).id(), HELLO);
+        }
+        assert_eq!(open("leaf/.").id(), LEAFDIR);
+        assert!(open("leaf").lookup(b"gone").unwrap().is_none());
+
+        let node = list(&open("node"));
+        assert_eq!(node.len(), 22);
+        for i in 0..20 {
+            assert_eq!(node[i + 2].0, std::format!("n{i:02}"));
+            assert_eq!(open(&std::format!("node/n{i:02}")).id(), HELLO);
+        }
+        assert_eq!(open("node/..").id(), ROOT);
+        assert!(open("node").lookup(b"n20").unwrap().is_none());
+
+        // a broken checksum of a directory block
+        let mut data = data.clone();
+        let pos = data
+            .chunks(BS)
+            .position(|x| x[..4] == dir::BLOCK_MAGIC.to_be_bytes())
+            .unwrap()
+            * BS
+            + 100;
+        data[pos] ^= 1;
+        let disk = ReadSlice(&data);
+        let fs = XfsFs::new(&disk).unwrap();
+        assert!(fs.root().unwrap().lookup(b"hello").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! File attributes for ZIP archives.
+
+use super::file::File;
+use ap_storage::attr::{self, attr_meta, new_attr, Attributes, Meta, Value};
+use ap_storage::Read;
+use ap_util_slice_writer::*;
+
+new_attr!(CRC, U64, "CRC-32 of the uncompressed data.");
+new_attr!(CSIZE, U64, "The compressed size in bytes.");
+new_attr!(METHOD, U64, "The compression method.");
+
+pub struct Attr<'a, D: ?Sized> {
+    pub(crate) file: &'a File<'a, D>,
+}
+
+impl<'a, D: ?Sized> IntoIterator for Attr<'a, D> {
+    type Item = &'a &'a str;
+    type IntoIter = core::slice::Iter<'a, &'a str>;
+    fn into_iter(self) -> Self::IntoIter {
+        [
+            CRC,
+            CSIZE,
+            METHOD,
+            attr::FTYPE,
+            attr::GID,
+            attr::ID,
+            attr::MODE,
+            attr::MTIME,
+            attr::SIZE,
+            attr::UID,
+        ]
+        .iter()
+    }
+}
+
+impl<'a, D: Read + ?Sized> Attributes<'a> for Attr<'a, D> {
+    fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+        let member = self.file.member();
+        let local = member.local.is_some();
+        Some(match name {
+            CRC if local => (member.crc as u64).into(),
+            CSIZE if local => member.csize.into(),
+            METHOD if local => (member.method as u64).into(),
+            attr::FTYPE => {
+                let mut value = SliceWriter(buf, 0);
+                write!(value, "{:?}", self.file.ftype()).ok()?;
+                Value::Str(value.1)
+            }
+            attr::GID => member.gid?.into(),
+            attr::ID => (self.file.index as u64).into(),
+            attr::MODE => (member.mode as u64).into(),
+            attr::MTIME => Value::Time(member.mtime),
+            attr::SIZE => member.size.into(),
+            attr::UID => member.uid?.into(),
+            _ => return None,
+        })
+    }
+
+    fn meta(&self, name: &str) -> Option<Meta> {
+        attr_meta!(
+            name,
+            [
+                CRC,
+                CSIZE,
+                METHOD,
+                attr::FTYPE,
+                attr::GID,
+                attr::ID,
+                attr::MODE,
+                attr::MTIME,
+                attr::SIZE,
+                attr::UID,
+            ]
+        )
+    }
+}
@@ -0,0 +1,41 @@
+//! Directory iteration for ZIP archives.
+
+use super::file::File;
+use ap_storage::{
+    directory::{DirEntry, DirIterator},
+    Error, Read,
+};
+
+pub struct Dir<'a, D: ?Sized> {
+    file: &'a File<'a, D>,
+    /// The index of the next member to check.
+    pos: usize,
+}
+
+impl<'a, D: Read + ?Sized> Dir<'a, D> {
+    pub(crate) fn new(file: &'a File<'a, D>) -> Self {
+        Self { file, pos: 1 }
+    }
+}
+
+impl<'a, D: Read + ?Sized> DirIterator for Dir<'a, D> {
+    fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+        while let Some(member) = self.file.fs.member(self.pos) {
+            let offset = self.pos;
+            self.pos += 1;
+            if member.parent != self.file.index {
+                continue;
+            }
+            let child = member.name();
+            let nlen = core::cmp::min(child.len(), name.len());
+            name[..nlen].copy_from_slice(&child[..nlen]);
+            return Ok(Some(DirEntry {
+                offset: offset as u64,
+                id: offset as u64,
+                nlen,
+                typ: member.ftype,
+            }));
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,106 @@
+//! Files in ZIP archives.
+
+use super::{attr::Attr, dir::Dir, Member, ZipFs, DEFLATED, FLAG_ENCRYPTED, STORED};
+use ap_storage::{file::FileType, msg2err, Error, Offset, Read};
+use core::cell::Cell;
+
+pub struct File<'a, D: ?Sized = dyn Read + 'a> {
+    pub(crate) fs: &'a ZipFs<'a, D>,
+    pub(crate) index: usize,
+    /// The offset of the data once the local header was read.
+    data: Cell<Option<Offset>>,
+}
+
+impl<D: ?Sized> Clone for File<'_, D> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs,
+            index: self.index,
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl<D: ?Sized> core::fmt::Debug for File<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        fmt.debug_struct("File")
+            .field("fs", &self.fs)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl<'a, D: Read + ?Sized> File<'a, D> {
+    pub(crate) fn new(fs: &'a ZipFs<'a, D>, index: usize) -> Self {
+        Self {
+            fs,
+            index,
+            data: Cell::new(None),
+        }
+    }
+
+    pub(crate) fn member(&self) -> &'a Member {
+        &self.fs.index.members[self.index]
+    }
+
+    pub fn ftype(&self) -> FileType {
+        self.member().ftype
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.ftype() == FileType::Directory
+    }
+
+    /// The offset of the data behind the local header.
+    fn data(&self) -> Result<Offset, Error> {
+        if let Some(data) = self.data.get() {
+            return Ok(data);
+        }
+        let local = self.member().local.ok_or(msg2err!("no data"))?;
+        let data = self.fs.data(local)?;
+        self.data.set(Some(data));
+        Ok(data)
+    }
+}
+
+impl<'a, D: Read + ?Sized> ap_storage::file::File for File<'a, D> {
+    type AttrType<'c> = Attr<'c, D> where Self: 'c;
+    fn attr(&self) -> Self::AttrType<'_> {
+        Attr { file: self }
+    }
+
+    type DirType<'c> = Dir<'c, D> where Self: 'c;
+    fn dir(&self) -> Option<Self::DirType<'_>> {
+        if self.is_dir() {
+            return Some(Dir::new(self));
+        }
+        None
+    }
+
+    fn open(&self, offset: Offset) -> Result<Self, Error> {
+        let index = offset as usize;
+        if self.fs.member(index).map(|x| x.parent) != Some(self.index) || index == 0 {
+            return Err(msg2err!("not a child"));
+        }
+        Ok(Self::new(self.fs, index))
+    }
+}
+
+impl<D: Read + ?Sized> Read for File<'_, D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        let member = self.member();
+        if offset >= member.size || !matches!(member.ftype, FileType::File | FileType::SymLink) {
+            return Ok(0);
+        }
+        if member.flags & FLAG_ENCRYPTED != 0 {
+            return Err(msg2err!("encrypted member"));
+        }
+        let max_n = core::cmp::min(buf.len() as Offset, member.size - offset) as usize;
+        let buf = &mut buf[..max_n];
+        match member.method {
+            STORED if member.csize == member.size => self.fs.disk().read_bytes(self.data()? + offset, buf),
+            DEFLATED => self.fs.inflate(self.index, self.data()?, offset, buf),
+            _ => Err(msg2err!("unsupported compression method")),
+        }
+    }
+}
@@ -0,0 +1,580 @@
+//! Browse ZIP archives.
+//!
+//! - the central directory including the ZIP64 extensions
+//! - stored members are read directly from the disk
+//! - deflated members are decompressed as a stream that restarts on backwards seeks
+//! - directories that are implied only by the paths of their members
+//! - data prepended to the archive like in self-extracting executables
+//!
+//! The sizes are taken from the central directory, so data descriptors behind the data are never needed.  The offset
+//! of a directory entry is the index of the member and a later member replaces an earlier one with the same path.
+
+#![no_std]
+
+extern crate alloc;
+
+mod attr;
+mod dir;
+pub mod file;
+mod stream;
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use ap_storage::{
+    file::FileType,
+    msg2err,
+    path::{normalize, parent},
+    Error, FileSystem, Offset, Read, ReadExt,
+};
+use core::cell::RefCell;
+use stream::Stream;
+
+/// The signature of a local header.
+const LOCAL_SIG: u32 = 0x04034b50;
+/// The signature of a central directory header.
+const CENTRAL_SIG: u32 = 0x02014b50;
+/// The signature of the end of central directory record.
+const EOCD_SIG: u32 = 0x06054b50;
+/// The signature of the ZIP64 end of central directory record.
+const EOCD64_SIG: u32 = 0x06064b50;
+/// The signature of the ZIP64 end of central directory locator.
+const LOCATOR_SIG: u32 = 0x07064b50;
+
+/// The size of the fixed part of a local header.
+const LOCAL_SIZE: usize = 30;
+/// The size of the fixed part of a central directory header.
+const CENTRAL_SIZE: usize = 46;
+/// The size of the end of central directory record without the comment.
+const EOCD_SIZE: usize = 22;
+/// The size of the ZIP64 end of central directory record without extensions.
+const EOCD64_SIZE: usize = 56;
+/// The size of the ZIP64 end of central directory locator.
+const LOCATOR_SIZE: usize = 20;
+
+/// The compression methods.
+pub(crate) const STORED: u16 = 0;
+pub(crate) const DEFLATED: u16 = 8;
+
+/// The flag of encrypted members.
+pub(crate) const FLAG_ENCRYPTED: u16 = 1;
+
+/// Read a little-endian u16.
+fn u16le(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+/// Read a little-endian u32.
+fn u32le(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+/// Read a little-endian u64.
+fn u64le(buf: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+/// The file type of a POSIX mode.
+pub(crate) fn file_type(mode: u32) -> FileType {
+    match mode & 0o170000 {
+        0o100000 => FileType::File,
+        0o040000 => FileType::Directory,
+        0o120000 => FileType::SymLink,
+        0o020000 => FileType::CharDevice,
+        0o060000 => FileType::BlockDevice,
+        0o010000 => FileType::Fifo,
+        0o140000 => FileType::Socket,
+        _ => FileType::Unknown,
+    }
+}
+
+/// Convert a DOS date and time into nanoseconds.
+fn dos_time(date: u16, time: u16) -> i64 {
+    (ap_util_date::dos_date2ts(date) + ap_util_date::dos_time2ts(time)) * 1_000_000_000
+}
+
+/// Parse a size-prefixed little-endian id of the Info-ZIP unix extra field.
+fn unix_id(data: &[u8]) -> Option<(u64, &[u8])> {
+    let (size, rest) = data.split_first()?;
+    let value = rest.get(..*size as usize).filter(|x| x.len() <= 8)?;
+    let value = value.iter().rev().fold(0u64, |res, x| res << 8 | *x as u64);
+    Some((value, &rest[*size as usize..]))
+}
+
+/// A member of the archive or a directory implied by a path.
+#[derive(Debug, Clone)]
+pub(crate) struct Member {
+    /// The path without leading or trailing slashes.
+    pub(crate) path: Vec<u8>,
+    pub(crate) parent: usize,
+    pub(crate) ftype: FileType,
+    pub(crate) mode: u32,
+    pub(crate) uid: Option<u64>,
+    pub(crate) gid: Option<u64>,
+    pub(crate) mtime: i64,
+    pub(crate) method: u16,
+    pub(crate) flags: u16,
+    pub(crate) crc: u32,
+    pub(crate) csize: u64,
+    pub(crate) size: u64,
+    /// The offset of the local header or None for implied directories.
+    pub(crate) local: Option<Offset>,
+}
+
+impl Member {
+    /// A directory without a member in the archive.
+    fn implied(path: &[u8], parent: usize) -> Self {
+        Self {
+            path: path.into(),
+            parent,
+            ftype: FileType::Directory,
+            mode: 0o755,
+            uid: None,
+            gid: None,
+            mtime: 0,
+            method: STORED,
+            flags: 0,
+            crc: 0,
+            csize: 0,
+            size: 0,
+            local: None,
+        }
+    }
+
+    /// The last component of the path.
+    pub(crate) fn name(&self) -> &[u8] {
+        self.path.rsplit(|x| *x == b'/').next().unwrap_or_default()
+    }
+}
+
+/// The members of the archive.
+struct Index {
+    members: Vec<Member>,
+    paths: BTreeMap<Vec<u8>, usize>,
+}
+
+impl Index {
+    /// Find a directory or create it with all its parents.
+    fn dir(&mut self, path: &[u8]) -> usize {
+        if let Some(i) = self.paths.get(path) {
+            return *i;
+        }
+        let parent = self.dir(parent(path));
+        let i = self.members.len();
+        self.members.push(Member::implied(path, parent));
+        self.paths.insert(path.into(), i);
+        i
+    }
+
+    /// Add a member or replace the earlier one with the same path.
+    fn insert(&mut self, mut member: Member) {
+        if member.path.is_empty() {
+            // the archive root keeps its place
+            if member.ftype == FileType::Directory {
+                self.members[0] = member;
+            }
+            return;
+        }
+        member.parent = self.dir(parent(&member.path));
+        if let Some(i) = self.paths.get(&member.path) {
+            self.members[*i] = member;
+            return;
+        }
+        self.paths.insert(member.path.clone(), self.members.len());
+        self.members.push(member);
+    }
+}
+
+/// The location of the central directory.
+#[derive(Debug, Clone, Copy)]
+struct Directory {
+    /// The offset of the first header.
+    start: Offset,
+    size: u64,
+    /// The bytes prepended to the archive.
+    base: Offset,
+}
+
+/// A ZIP archive.
+pub struct ZipFs<'a, D: ?Sized = dyn Read + 'a> {
+    disk: &'a D,
+    index: Index,
+    /// The last deflated member that was read.
+    stream: RefCell<Option<Box<Stream>>>,
+}
+
+impl<D: ?Sized> core::fmt::Debug for ZipFs<'_, D> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(fmt, "ZipFs({} members)", self.index.members.len())
+    }
+}
+
+impl<'a, D: Read + ?Sized> ZipFs<'a, D> {
+    /// Open an archive by reading its central directory.
+    pub fn new(disk: &'a D) -> Result<Self, Error> {
+        let mut fs = Self {
+            disk,
+            index: Index {
+                members: alloc::vec![Member::implied(b"", 0)],
+                paths: BTreeMap::new(),
+            },
+            stream: RefCell::new(None),
+        };
+        fs.index.paths.insert(Vec::new(), 0);
+        let directory = fs.directory()?;
+        let mut pos = directory.start;
+        let end = directory.start + directory.size;
+        while pos < end {
+            let (member, next) = fs.central(pos, directory.base)?;
+            fs.index.insert(member);
+            pos = next;
+        }
+        Ok(fs)
+    }
+
+    /// The disk as trait object.
+    pub(crate) fn disk(&self) -> &dyn Read {
+        &self.disk
+    }
+
+    /// Get a member by its index.
+    pub(crate) fn member(&self, index: usize) -> Option<&Member> {
+        self.index.members.get(index)
+    }
+
+    /// Find the central directory via the end records behind it.
+    fn directory(&self) -> Result<Directory, Error> {
+        // the record is followed by a comment of at most 64k
+        let size = self.disk().detect_size();
+        let start = size.saturating_sub((EOCD_SIZE + 0xffff) as Offset);
+        let mut tail = alloc::vec![0; (size - start) as usize];
+        self.disk().read_exact(start, &mut tail)?;
+        let eocd = (0..tail.len().saturating_sub(EOCD_SIZE - 1))
+            .rev()
+            .find(|i| u32le(&tail, *i) == EOCD_SIG && i + EOCD_SIZE + u16le(&tail, i + 20) as usize <= tail.len())
+            .ok_or(msg2err!("no end of central directory"))?;
+        let record = &tail[eocd..eocd + EOCD_SIZE];
+        let mut end = start + eocd as Offset;
+        let mut size = u32le(record, 12) as u64;
+        let mut offset = u32le(record, 16) as u64;
+
+        // ZIP64 archives have a locator in front of the record
+        if eocd >= LOCATOR_SIZE && u32le(&tail, eocd - LOCATOR_SIZE) == LOCATOR_SIG {
+            let locator = &tail[eocd - LOCATOR_SIZE..eocd];
+            let stored = u64le(locator, 8);
+            let mut record = [0; EOCD64_SIZE];
+            // the record is searched relative to the locator to support prepended data
+            let pos = (end - LOCATOR_SIZE as Offset)
+                .checked_sub(EOCD64_SIZE as Offset)
+                .ok_or(msg2err!("invalid zip64 locator"))?;
+            self.disk().read_exact(pos, &mut record)?;
+            if u32le(&record, 0) != EOCD64_SIG {
+                // the record carries extensible data, so trust the stored offset
+                self.disk().read_exact(stored, &mut record)?;
+                if u32le(&record, 0) != EOCD64_SIG {
+                    return Err(msg2err!("no zip64 end of central directory"));
+                }
+                end = stored;
+            } else {
+                end = pos;
+            }
+            size = u64le(&record, 40);
+            offset = u64le(&record, 48);
+        }
+        let start = end
+            .checked_sub(size)
+            .ok_or(msg2err!("invalid central directory size"))?;
+        let base = start
+            .checked_sub(offset)
+            .ok_or(msg2err!("invalid central directory offset"))?;
+        Ok(Directory { start, size, base })
+    }
+
+    /// Parse the central directory header at a position and return the position of the next one.
+    fn central(&self, pos: Offset, base: Offset) -> Result<(Member, Offset), Error> {
+        let header: [u8; CENTRAL_SIZE] = self.disk().read_object(pos)?;
+        if u32le(&header, 0) != CENTRAL_SIG {
+            return Err(msg2err!("invalid central directory header"));
+        }
+        let (nlen, xlen, clen) = (
+            u16le(&header, 28) as usize,
+            u16le(&header, 30) as usize,
+            u16le(&header, 32),
+        );
+        let mut buf = alloc::vec![0; nlen + xlen];
+        self.disk().read_exact(pos + CENTRAL_SIZE as Offset, &mut buf)?;
+        let (name, extra) = buf.split_at(nlen);
+        let mut path = name.to_vec();
+        let n = normalize(&mut path);
+        path.truncate(n);
+
+        let mut member = Member {
+            path,
+            parent: 0,
+            ftype: FileType::File,
+            mode: 0o644,
+            uid: None,
+            gid: None,
+            mtime: dos_time(u16le(&header, 14), u16le(&header, 12)),
+            method: u16le(&header, 10),
+            flags: u16le(&header, 8),
+            crc: u32le(&header, 16),
+            csize: u32le(&header, 20) as u64,
+            size: u32le(&header, 24) as u64,
+            local: None,
+        };
+        let mut local = u32le(&header, 42) as u64;
+        let attributes = u32le(&header, 38);
+        let is_dir = name.last() == Some(&b'/') || attributes & 0x10 != 0;
+        // the high half of the external attributes is a POSIX mode on unix hosts
+        if header[5] == 3 && attributes >> 16 != 0 {
+            let mode = attributes >> 16;
+            member.ftype = match file_type(mode) {
+                FileType::Unknown if is_dir => FileType::Directory,
+                FileType::Unknown => FileType::File,
+                x => x,
+            };
+            member.mode = mode & 0o7777;
+        } else if is_dir {
+            member.ftype = FileType::Directory;
+            member.mode = 0o755;
+        } else if attributes & 1 != 0 {
+            member.mode = 0o444;
+        }
+
+        let mut extra = extra;
+        while extra.len() >= 4 {
+            let (id, len) = (u16le(extra, 0), u16le(extra, 2) as usize);
+            let Some(data) = extra.get(4..4 + len) else {
+                break;
+            };
+            match id {
+                // ZIP64 values are present only for the saturated fields
+                0x0001 => {
+                    let mut values = data.chunks_exact(8).map(|x| u64::from_le_bytes(x.try_into().unwrap()));
+                    for field in [&mut member.size, &mut member.csize, &mut local] {
+                        if *field == 0xffff_ffff {
+                            *field = values.next().ok_or(msg2err!("truncated zip64 field"))?;
+                        }
+                    }
+                }
+                // extended timestamp with the modification time first
+                0x5455 if len >= 5 && data[0] & 1 != 0 => {
+                    member.mtime = i32::from_le_bytes(data[1..5].try_into().unwrap()) as i64 * 1_000_000_000;
+                }
+                // Info-ZIP unix owner with variable sized ids
+                0x7875 if len >= 3 && data[0] == 1 => {
+                    if let Some((uid, rest)) = unix_id(&data[1..]) {
+                        member.uid = Some(uid);
+                        member.gid = unix_id(rest).map(|x| x.0);
+                    }
+                }
+                _ => {}
+            }
+            extra = &extra[4 + len..];
+        }
+        member.local = Some(base + local);
+        Ok((member, pos + (CENTRAL_SIZE + nlen + xlen) as Offset + clen as Offset))
+    }
+
+    /// The offset of the data behind a local header.
+    pub(crate) fn data(&self, local: Offset) -> Result<Offset, Error> {
+        let header: [u8; LOCAL_SIZE] = self.disk().read_object(local)?;
+        if u32le(&header, 0) != LOCAL_SIG {
+            return Err(msg2err!("invalid local header"));
+        }
+        Ok(local + (LOCAL_SIZE + u16le(&header, 26) as usize + u16le(&header, 28) as usize) as Offset)
+    }
+
+    /// Decompress a deflated member by continuing or restarting the last stream.
+    pub(crate) fn inflate(&self, index: usize, data: Offset, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut stream = self.stream.borrow_mut();
+        let csize = self.index.members[index].csize;
+        let stream = match &mut *stream {
+            Some(x) if x.index == index => x,
+            x => x.insert(Box::new(Stream::new(index, data, csize))),
+        };
+        stream.read(self.disk(), offset, buf)
+    }
+}
+
+impl<'a, D: Read + ?Sized> FileSystem<'a> for ZipFs<'a, D> {
+    type FileType = file::File<'a, D>;
+    fn root(&'a self) -> Result<Self::FileType, Error> {
+        Ok(file::File::new(self, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use ap_storage::{
+        attr::{self, Attributes, Value},
+        directory::DirIterator,
+        file::File,
+    };
+    use ap_storage_memory::ReadSlice;
+    use std::vec::Vec;
+
+    /// Build an archive with data descriptors behind some prepended bytes.
+    fn archive(prefix: usize, zip64: bool, members: &[(&str, u16, u32, &[u8], u64)]) -> Vec<u8> {
+        let mut res = std::vec![b'#'; prefix];
+        let mut central = Vec::new();
+        for (name, method, mode, data, size) in members {
+            let local = (res.len() - prefix) as u64;
+            res.extend(LOCAL_SIG.to_le_bytes());
+            res.extend([20, 0, 8, 0]);
+            res.extend(method.to_le_bytes());
+            res.extend([0; 16]);
+            res.extend((name.len() as u16).to_le_bytes());
+            res.extend([0, 0]);
+            res.extend(name.as_bytes());
+            res.extend(*data);
+            res.extend([0x50, 0x4b, 0x07, 0x08]);
+            res.extend([0; 12]);
+
+            let (csize, size32, local32) = match zip64 {
+                true => (u32::MAX, u32::MAX, u32::MAX),
+                false => (data.len() as u32, *size as u32, local as u32),
+            };
+            central.extend(CENTRAL_SIG.to_le_bytes());
+            central.extend([20, 3, 20, 0, 8, 0]);
+            central.extend(method.to_le_bytes());
+            // 2024-01-01 12:00:00
+            central.extend([0x00, 0x60, 0x21, 0x58, 0, 0, 0, 0]);
+            central.extend(csize.to_le_bytes());
+            central.extend(size32.to_le_bytes());
+            central.extend((name.len() as u16).to_le_bytes());
+            central.extend((if zip64 { 28u16 } else { 0 }).to_le_bytes());
+            central.extend([0; 6]);
+            central.extend((mode << 16).to_le_bytes());
+            central.extend(local32.to_le_bytes());
+            central.extend(name.as_bytes());
+            if zip64 {
+                central.extend([1, 0, 24, 0]);
+                central.extend(size.to_le_bytes());
+                central.extend((data.len() as u64).to_le_bytes());
+                central.extend(local.to_le_bytes());
+            }
+        }
+        let start = (res.len() - prefix) as u64;
+        res.extend(&central);
+        let count = members.len() as u64;
+        if zip64 {
+            let record = (res.len() - prefix) as u64;
+            res.extend(EOCD64_SIG.to_le_bytes());
+            res.extend(44u64.to_le_bytes());
+            res.extend([45, 3, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            for value in [count, count, central.len() as u64, start] {
+                res.extend(value.to_le_bytes());
+            }
+            res.extend(LOCATOR_SIG.to_le_bytes());
+            res.extend(0u32.to_le_bytes());
+            res.extend(record.to_le_bytes());
+            res.extend(1u32.to_le_bytes());
+        }
+        res.extend(EOCD_SIG.to_le_bytes());
+        res.extend([0; 4]);
+        let (count, size, start) = match zip64 {
+            true => (u16::MAX, u32::MAX, u32::MAX),
+            false => (count as u16, central.len() as u32, start as u32),
+        };
+        res.extend(count.to_le_bytes());
+        res.extend(count.to_le_bytes());
+        res.extend(size.to_le_bytes());
+        res.extend(start.to_le_bytes());
+        res.extend([2, 0, b'h', b'i']);
+        res
+    }
+
+    fn read(file: &impl Read, offset: u64, len: usize) -> Vec<u8> {
+        let mut res = std::vec![0; len];
+        let n = file.read_bytes(offset, &mut res).unwrap();
+        res.truncate(n);
+        res
+    }
+
+    #[test]
+    fn members() {
+        // raw DEFLATE of "0123456789" repeated 10000 times
+        let mut deflated = std::vec![
+            0xed, 0xc6, 0x49, 0x01, 0x00, 0x20, 0x08, 0x00, 0xb0, 0x4a, 0x78, 0x20, 0xda, 0xbf, 0x98, 0x35, 0x78, 0x6c,
+            0xaf, 0xc5, 0x98, 0x6b, 0xe7, 0xa9, 0xfb, 0xc2,
+        ];
+        deflated.resize(219, 0xcc);
+        deflated.extend([0x9a, 0xed, 0x03]);
+        let members: [(&str, u16, u32, &[u8], u64); 5] = [
+            ("a/b/", STORED, 0o040750, b"", 0),
+            ("a/b/text", STORED, 0o100644, b"hello", 5),
+            ("digits", DEFLATED, 0o100600, &deflated, 100000),
+            ("link", STORED, 0o120777, b"a/b/text", 8),
+            ("./a/b/text", STORED, 0o100640, b"world", 5),
+        ];
+        for (prefix, zip64) in [(0, false), (100, false), (0, true), (100, true)] {
+            let data = archive(prefix, zip64, &members);
+            let disk = ReadSlice(&data);
+            let fs = ZipFs::new(&disk).unwrap();
+            let root = fs.root().unwrap();
+
+            let mut dir = root.dir().unwrap();
+            let mut name = [0; 16];
+            let mut names = Vec::new();
+            while let Some(entry) = dir.next(&mut name).unwrap() {
+                names.push((name[..entry.nlen].to_vec(), entry.typ));
+            }
+            assert_eq!(
+                names,
+                [
+                    (b"a".to_vec(), FileType::Directory),
+                    (b"digits".to_vec(), FileType::File),
+                    (b"link".to_vec(), FileType::SymLink)
+                ]
+            );
+
+            let mode = |f: &file::File<_>| f.attr().get(attr::MODE, &mut []).and_then(|x| x.as_u64());
+            assert_eq!(mode(&root.lookup(b"a").unwrap().unwrap()), Some(0o755));
+            let text = root.clone().lookup_path(b"a/b/text").unwrap();
+            assert_eq!(read(&text, 0, 10), b"world");
+            assert_eq!(mode(&text), Some(0o640));
+            assert!(matches!(
+                text.attr().get(attr::MTIME, &mut []),
+                Some(Value::Time(1_704_110_400_000_000_000))
+            ));
+            let link = root.lookup(b"link").unwrap().unwrap();
+            assert_eq!(read(&link, 0, 100), b"a/b/text");
+
+            // forward skips and backward restarts
+            let digits = root.lookup(b"digits").unwrap().unwrap();
+            assert_eq!(read(&digits, 99_990, 20), b"0123456789");
+            assert_eq!(read(&digits, 5, 10), b"5678901234");
+            assert_eq!(read(&digits, 50_003, 4), b"3456");
+            let mut all = std::vec![0; 100_000];
+            let mut pos = 0;
+            while pos < all.len() {
+                let n = digits.read_bytes(pos as u64, &mut all[pos..]).unwrap();
+                assert_ne!(n, 0);
+                pos += n;
+            }
+            assert!(all.chunks(10).all(|x| x == b"0123456789"));
+        }
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(dos_time(0x5821, 0x6000), 1_704_110_400_000_000_000);
+        assert_eq!(dos_time(0x5801, 0x6000), dos_time(0x5821, 0x6000));
+        for month in [13, 14, 15] {
+            assert_eq!(dos_time(0x5801 | month << 5, 0x6000), dos_time(0x5981, 0x6000));
+        }
+
+        // an invalid month in the central directory
+        let mut data = archive(0, false, &[("text", STORED, 0o100644, b"hello", 5)]);
+        let pos = data.windows(4).position(|x| x == [0x00, 0x60, 0x21, 0x58]).unwrap();
+        data[pos + 2] = 0xe1;
+        data[pos + 3] = 0x59;
+        let disk = ReadSlice(&data);
+        let fs = ZipFs::new(&disk).unwrap();
+        let text = fs.root().unwrap().lookup(b"text").unwrap().unwrap();
+        let december = Value::Time(dos_time(0x5981, 0x6000));
+        assert_eq!(text.attr().get(attr::MTIME, &mut []), Some(december));
+    }
+}
@@ -0,0 +1,112 @@
+//! Streaming decompression of deflated members.
+
+use alloc::vec::Vec;
+use ap_storage::{msg2err, Error, Offset, Read};
+use ap_util_inflate::{Inflate, Input, Window, WINDOW_SIZE};
+
+/// The compressed bytes read ahead from the disk.
+struct Buffer {
+    buf: [u8; 4096],
+    start: usize,
+    len: usize,
+    /// The disk offset behind the buffered bytes.
+    pos: Offset,
+    /// The end of the compressed data.
+    end: Offset,
+}
+
+/// The input of the inflater pulled through the buffer.
+struct Source<'s> {
+    disk: &'s dyn Read,
+    buffer: &'s mut Buffer,
+}
+
+impl Input for Source<'_> {
+    fn byte(&mut self) -> Result<Option<u8>, Error> {
+        let b = &mut *self.buffer;
+        if b.start == b.len {
+            let n = core::cmp::min(b.buf.len() as Offset, b.end.saturating_sub(b.pos)) as usize;
+            let n = if n == 0 {
+                0
+            } else {
+                self.disk.read_bytes(b.pos, &mut b.buf[..n])?
+            };
+            if n == 0 {
+                return Ok(None);
+            }
+            b.pos += n as Offset;
+            (b.start, b.len) = (0, n);
+        }
+        b.start += 1;
+        Ok(Some(b.buf[b.start - 1]))
+    }
+}
+
+/// The decompression state of a single member.
+pub(crate) struct Stream {
+    /// The index of the member.
+    pub(crate) index: usize,
+    /// The start of the compressed data.
+    data: Offset,
+    inflate: Inflate,
+    input: Buffer,
+    window: Vec<u8>,
+    /// The bytes decompressed so far.
+    pos: u64,
+}
+
+impl Stream {
+    pub(crate) fn new(index: usize, data: Offset, csize: u64) -> Self {
+        Self {
+            index,
+            data,
+            inflate: Inflate::default(),
+            input: Buffer {
+                buf: [0; 4096],
+                start: 0,
+                len: 0,
+                pos: data,
+                end: data + csize,
+            },
+            window: alloc::vec![0; WINDOW_SIZE],
+            pos: 0,
+        }
+    }
+
+    /// Start again at the beginning of the member.
+    fn restart(&mut self) {
+        self.inflate = Inflate::default();
+        (self.input.start, self.input.len, self.input.pos) = (0, 0, self.data);
+        self.pos = 0;
+    }
+
+    /// Decompress the next bytes.
+    fn next(&mut self, disk: &dyn Read, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut window = Window::resume(&mut self.window, self.pos).ok_or(msg2err!("invalid window"))?;
+        let mut source = Source {
+            disk,
+            buffer: &mut self.input,
+        };
+        let n = self.inflate.read(&mut source, &mut window, buf)?;
+        self.pos = window.position();
+        Ok(n)
+    }
+
+    /// Read at an offset by skipping forward or restarting the stream.
+    pub(crate) fn read(&mut self, disk: &dyn Read, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if offset < self.pos {
+            self.restart();
+        }
+        // the buffer is used as scratch space while skipping
+        while self.pos < offset {
+            let n = core::cmp::min(buf.len() as u64, offset - self.pos) as usize;
+            if self.next(disk, &mut buf[..n])? == 0 {
+                return Ok(0);
+            }
+        }
+        self.next(disk, buf)
+    }
+}
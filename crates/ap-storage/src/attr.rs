@@ -1,10 +1,15 @@
 //! Support for extended attributes in files.
 
-pub use ap_util_attr::{new_attr, Attributes, Value};
+pub use ap_util_attr::{attr_meta, new_attr, Attributes, AttributesMut, Meta, Type, Value};
 
-new_attr!(ATIME, I64, "Time of last file access.");
-new_attr!(BTIME, I64, "Time of file birth.");
-new_attr!(FTYPE, Raw, "File type.");
+new_attr!(ATIME, Time, "Time of last file access.");
+new_attr!(BTIME, Time, "Time of file birth.");
+new_attr!(CTIME, Time, "Time of last status change.");
+new_attr!(FTYPE, Str, "File type.");
+new_attr!(GID, U64, "Group id of the owner.");
 new_attr!(ID, U64, "A unique ID of the file, used to detect hard-links.");
-new_attr!(MTIME, I64, "Time of last file modification.");
+new_attr!(MODE, U64, "POSIX permission bits including setuid, setgid and sticky.");
+new_attr!(MTIME, Time, "Time of last file modification.");
+new_attr!(RDEV, U64, "Device number of special files as `major << 32 | minor`.");
 new_attr!(SIZE, U64, "The size of the file in bytes.");
+new_attr!(UID, U64, "User id of the owner.");
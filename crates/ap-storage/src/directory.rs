@@ -4,7 +4,7 @@ use super::Error;
 use crate::file::FileType;
 
 /// Directory entry.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct DirEntry {
     /// The offset inside the parent. This is used to open the file relative to the parent.
     pub offset: u64,
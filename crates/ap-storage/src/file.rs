@@ -3,7 +3,7 @@
 use crate::{attr::Attributes, directory::DirIterator, msg2err, Error, Offset};
 
 /// Generic file-types.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
     /// A plain file.
     File,
@@ -13,6 +13,14 @@ pub enum FileType {
     Parent,
     /// A symbolic link.
     SymLink,
+    /// A character device.
+    CharDevice,
+    /// A block device.
+    BlockDevice,
+    /// A named pipe.
+    Fifo,
+    /// A unix domain socket.
+    Socket,
     /// An unsupported entry.
     Unknown,
 }
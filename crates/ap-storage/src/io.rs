@@ -0,0 +1,403 @@
+//! Adapters between the storage traits and the `std::io` and `embedded-io` ecosystems.
+//!
+//! - [`Cursor`] turns any positional [`Read`] or [`Write`] into a stream with a position.
+//! - [`StdDisk`] and [`EmbeddedDisk`] use a seekable stream as a disk.
+
+use crate::{msg2err, Error, Offset, Read, ReadExt, Write};
+use core::cell::RefCell;
+
+/// A stream over a positional reader or writer.
+pub struct Cursor<T> {
+    inner: T,
+    pos: Offset,
+}
+
+impl<T> Cursor<T> {
+    /// Start the stream at the beginning.
+    pub fn new(inner: T) -> Self {
+        Self { inner, pos: 0 }
+    }
+
+    /// The current position.
+    pub fn position(&self) -> Offset {
+        self.pos
+    }
+
+    /// Move to an absolute position.
+    pub fn set_position(&mut self, pos: Offset) {
+        self.pos = pos;
+    }
+
+    /// Return the wrapped object.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read> Cursor<T> {
+    /// Read at the current position and advance it.
+    fn read_inner(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let n = self.inner.read_bytes(self.pos, buf)?;
+        self.pos += n as Offset;
+        Ok(n)
+    }
+
+    /// Move the position relative to a base.  The end is detected by probing.
+    fn seek_inner(&mut self, from_end: bool, base: Offset, delta: i64) -> Result<Offset, Error> {
+        let base = if from_end {
+            (&self.inner as &dyn Read).detect_size()
+        } else {
+            base
+        };
+        self.pos = base.checked_add_signed(delta).ok_or(msg2err!("invalid seek"))?;
+        Ok(self.pos)
+    }
+}
+
+impl<T: Write> Cursor<T> {
+    /// Write at the current position and advance it.
+    fn write_inner(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let n = self.inner.write_bytes(self.pos, buf)?;
+        self.pos += n as Offset;
+        Ok(n)
+    }
+}
+
+/// The chunk of zeros written on discards.
+const ZEROS: [u8; 512] = [0; 512];
+
+#[cfg(feature = "std")]
+mod std_io {
+    use super::*;
+    extern crate std;
+    use std::io::{Seek, SeekFrom};
+
+    /// Use a seekable stream as a disk.
+    ///
+    /// Discarding writes zeros as streams have no notion of holes.
+    pub struct StdDisk<T>(RefCell<T>);
+
+    impl<T: Read> std::io::Read for Cursor<T> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.read_inner(buf).map_err(std::io::Error::other)
+        }
+    }
+
+    impl<T: Read> Seek for Cursor<T> {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            let res = match pos {
+                SeekFrom::Start(x) => self.seek_inner(false, x, 0),
+                SeekFrom::End(x) => self.seek_inner(true, 0, x),
+                SeekFrom::Current(x) => self.seek_inner(false, self.pos, x),
+            };
+            res.map_err(std::io::Error::other)
+        }
+    }
+
+    impl<T: Write> std::io::Write for Cursor<T> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.write_inner(buf).map_err(std::io::Error::other)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<T> StdDisk<T> {
+        /// Wrap a stream.
+        pub fn new(inner: T) -> Self {
+            Self(RefCell::new(inner))
+        }
+
+        /// Return the wrapped stream.
+        pub fn into_inner(self) -> T {
+            self.0.into_inner()
+        }
+    }
+
+    impl<T: std::io::Read + Seek> Read for StdDisk<T> {
+        fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+            let mut inner = self.0.borrow_mut();
+            inner.seek(SeekFrom::Start(offset)).map_err(|e| msg2err!(e))?;
+            inner.read(buf).map_err(|e| msg2err!(e))
+        }
+    }
+
+    impl<T: std::io::Write + Seek> Write for StdDisk<T> {
+        fn write_bytes(&self, offset: Offset, buf: &[u8]) -> Result<usize, Error> {
+            let mut inner = self.0.borrow_mut();
+            inner.seek(SeekFrom::Start(offset)).map_err(|e| msg2err!(e))?;
+            inner.write(buf).map_err(|e| msg2err!(e))
+        }
+
+        fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error> {
+            let n = core::cmp::min(len, ZEROS.len() as Offset) as usize;
+            Ok(self.write_bytes(offset, &ZEROS[..n])? as Offset)
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+mod embedded {
+    use super::*;
+    use embedded_io::{ErrorKind, ErrorType, Seek, SeekFrom};
+
+    /// Use a seekable embedded-io stream as a disk.
+    ///
+    /// Discarding writes zeros as streams have no notion of holes.
+    pub struct EmbeddedDisk<T>(RefCell<T>);
+
+    /// The error type of the cursor.
+    #[derive(Debug)]
+    pub struct CursorError(pub Error);
+
+    impl embedded_io::Error for CursorError {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    /// An embedded-io stream failed.
+    #[derive(Debug)]
+    pub struct StreamError(pub ErrorKind);
+
+    impl core::fmt::Display for StreamError {
+        fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+            write!(fmt, "{:?}", self)
+        }
+    }
+
+    /// Convert the error of a stream.
+    fn stream_error<E: embedded_io::Error>(e: E) -> Error {
+        msg2err!(StreamError(e.kind()))
+    }
+
+    impl<T> ErrorType for Cursor<T> {
+        type Error = CursorError;
+    }
+
+    impl<T: Read> embedded_io::Read for Cursor<T> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.read_inner(buf).map_err(CursorError)
+        }
+    }
+
+    impl<T: Read> Seek for Cursor<T> {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+            let res = match pos {
+                SeekFrom::Start(x) => self.seek_inner(false, x, 0),
+                SeekFrom::End(x) => self.seek_inner(true, 0, x),
+                SeekFrom::Current(x) => self.seek_inner(false, self.pos, x),
+            };
+            res.map_err(CursorError)
+        }
+    }
+
+    impl<T: Write> embedded_io::Write for Cursor<T> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.write_inner(buf).map_err(CursorError)
+        }
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl<T> EmbeddedDisk<T> {
+        /// Wrap a stream.
+        pub fn new(inner: T) -> Self {
+            Self(RefCell::new(inner))
+        }
+
+        /// Return the wrapped stream.
+        pub fn into_inner(self) -> T {
+            self.0.into_inner()
+        }
+    }
+
+    impl<T: embedded_io::Read + Seek> Read for EmbeddedDisk<T> {
+        fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+            let mut inner = self.0.borrow_mut();
+            inner.seek(SeekFrom::Start(offset)).map_err(stream_error)?;
+            inner.read(buf).map_err(stream_error)
+        }
+    }
+
+    impl<T: embedded_io::Write + Seek> Write for EmbeddedDisk<T> {
+        fn write_bytes(&self, offset: Offset, buf: &[u8]) -> Result<usize, Error> {
+            let mut inner = self.0.borrow_mut();
+            inner.seek(SeekFrom::Start(offset)).map_err(stream_error)?;
+            inner.write(buf).map_err(stream_error)
+        }
+
+        fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error> {
+            let n = core::cmp::min(len, ZEROS.len() as Offset) as usize;
+            Ok(self.write_bytes(offset, &ZEROS[..n])? as Offset)
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+pub use embedded::{CursorError, EmbeddedDisk, StreamError};
+#[cfg(feature = "std")]
+pub use std_io::StdDisk;
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    /// A positional disk of a fixed size.
+    struct Mem(RefCell<Vec<u8>>);
+
+    impl Mem {
+        fn new(len: usize) -> Self {
+            Self(RefCell::new((0..len).map(|x| x as u8).collect()))
+        }
+    }
+
+    impl Read for Mem {
+        fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+            let data = self.0.borrow();
+            let data = data.get(offset as usize..).unwrap_or_default();
+            let n = core::cmp::min(data.len(), buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            Ok(n)
+        }
+    }
+
+    impl Write for Mem {
+        fn write_bytes(&self, offset: Offset, buf: &[u8]) -> Result<usize, Error> {
+            let mut data = self.0.borrow_mut();
+            let data = data.get_mut(offset as usize..).unwrap_or_default();
+            let n = core::cmp::min(data.len(), buf.len());
+            data[..n].copy_from_slice(&buf[..n]);
+            Ok(n)
+        }
+        fn discard(&self, _offset: Offset, _len: Offset) -> Result<Offset, Error> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn cursor() {
+        let mem = Mem::new(1000);
+
+        // the references forward to the disk
+        let disk = &&mem;
+        assert_eq!(disk.write_bytes(998, b"xyz").unwrap(), 2);
+        let mut buf = [0; 4];
+        assert_eq!(disk.read_bytes(996, &mut buf).unwrap(), 4);
+        assert_eq!(buf, [228, 229, b'x', b'y']);
+
+        let mut cursor = Cursor::new(&mem);
+        assert_eq!(cursor.write_inner(b"abc").unwrap(), 3);
+        assert_eq!(cursor.position(), 3);
+        cursor.set_position(1);
+        assert_eq!(cursor.read_inner(&mut buf).unwrap(), 4);
+        assert_eq!(buf, [b'b', b'c', 3, 4]);
+        assert_eq!(cursor.seek_inner(true, 0, -1).unwrap(), 999);
+        assert_eq!(cursor.read_inner(&mut buf).unwrap(), 1);
+        assert_eq!(cursor.read_inner(&mut buf).unwrap(), 0);
+        assert_eq!(cursor.seek_inner(false, 10, -4).unwrap(), 6);
+        assert!(cursor.seek_inner(false, 2, -3).is_err());
+        assert_eq!(cursor.into_inner().0.borrow()[..4], [b'a', b'b', b'c', 3]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn std_io() {
+        use std::io::{Read as _, Seek, SeekFrom, Write as _};
+
+        // a stream over a disk
+        let mem = Mem::new(1000);
+        let mut cursor = Cursor::new(&mem);
+        cursor.write_all(b"hello").unwrap();
+        assert_eq!(cursor.seek(SeekFrom::End(-10)).unwrap(), 990);
+        let mut rest = Vec::new();
+        assert_eq!(cursor.read_to_end(&mut rest).unwrap(), 10);
+        assert_eq!(rest, (990..1000).map(|x| x as u8).collect::<Vec<_>>());
+        assert_eq!(cursor.seek(SeekFrom::Start(1)).unwrap(), 1);
+        assert_eq!(cursor.seek(SeekFrom::Current(2)).unwrap(), 3);
+        let mut buf = [0; 4];
+        cursor.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"lo\x05\x06");
+        assert!(cursor.seek(SeekFrom::Current(-8)).is_err());
+        assert!(cursor.write_all(&[0; 1000]).is_err());
+
+        // a disk over a stream
+        let disk = StdDisk::new(std::io::Cursor::new(Vec::new()));
+        assert_eq!(disk.write_bytes(4, b"data").unwrap(), 4);
+        assert_eq!((&disk as &dyn Read).detect_size(), 8);
+        assert_eq!(disk.read_bytes(2, &mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"\0\0da");
+        assert_eq!(disk.read_bytes(8, &mut buf).unwrap(), 0);
+        assert_eq!(disk.discard(5, 2).unwrap(), 2);
+        assert_eq!(disk.discard(6, 1000).unwrap(), 512);
+        assert_eq!(
+            disk.into_inner().into_inner(),
+            [&[0, 0, 0, 0, b'd'][..], &[0; 513]].concat()
+        );
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn embedded_io() {
+        use embedded_io::{ErrorKind, ErrorType, Read as _, Seek, SeekFrom, Write as _};
+
+        /// An embedded stream over a std one.
+        struct Stream(std::io::Cursor<Vec<u8>>);
+
+        impl ErrorType for Stream {
+            type Error = ErrorKind;
+        }
+
+        impl embedded_io::Read for Stream {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+                std::io::Read::read(&mut self.0, buf).map_err(|_| ErrorKind::Other)
+            }
+        }
+
+        impl embedded_io::Write for Stream {
+            fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+                std::io::Write::write(&mut self.0, buf).map_err(|_| ErrorKind::Other)
+            }
+            fn flush(&mut self) -> Result<(), ErrorKind> {
+                Ok(())
+            }
+        }
+
+        impl Seek for Stream {
+            fn seek(&mut self, pos: SeekFrom) -> Result<u64, ErrorKind> {
+                let pos = match pos {
+                    SeekFrom::Start(x) => std::io::SeekFrom::Start(x),
+                    SeekFrom::End(x) => std::io::SeekFrom::End(x),
+                    SeekFrom::Current(x) => std::io::SeekFrom::Current(x),
+                };
+                std::io::Seek::seek(&mut self.0, pos).map_err(|_| ErrorKind::InvalidInput)
+            }
+        }
+
+        // a stream over a disk
+        let mem = Mem::new(100);
+        let mut cursor = Cursor::new(&mem);
+        cursor.write_all(b"hi").unwrap();
+        assert_eq!(cursor.seek(SeekFrom::End(-2)).unwrap(), 98);
+        let mut buf = [0; 4];
+        assert_eq!(cursor.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf[..2], [98, 99]);
+        assert_eq!(cursor.seek(SeekFrom::Current(-100)).unwrap(), 0);
+        cursor.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hi\x02\x03");
+        assert!(cursor.seek(SeekFrom::Current(-5)).is_err());
+
+        // a disk over a stream
+        let disk = EmbeddedDisk::new(Stream(std::io::Cursor::new(Vec::new())));
+        assert_eq!(disk.write_bytes(2, b"data").unwrap(), 4);
+        assert_eq!((&disk as &dyn Read).detect_size(), 6);
+        assert_eq!(disk.read_bytes(1, &mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"\0dat");
+        assert_eq!(disk.discard(3, 2).unwrap(), 2);
+        assert_eq!(disk.into_inner().0.into_inner(), b"\0\0d\0\0a");
+    }
+}
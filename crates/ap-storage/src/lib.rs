@@ -1,4 +1,10 @@
 //! The alpico storage interfaces.
+//!
+//! ## Features
+//!
+//! - `std`         - adapters to `std::io` in the `io` module.
+//! - `embedded-io` - adapters to `embedded_io` in the `io` module.
+
 #![no_std]
 
 /// Offset in the underlying storage.
@@ -10,10 +16,18 @@ pub type Error = anyhow::Error;
 pub mod attr;
 pub mod directory;
 pub mod file;
+#[cfg(any(feature = "std", feature = "embedded-io"))]
+pub mod io;
+pub mod path;
 mod read;
+mod scratch;
+mod sub;
+pub mod walk;
 mod write;
 
 pub use read::*;
+pub use scratch::*;
+pub use sub::*;
 pub use write::*;
 
 /// Hierarchical filesystem.
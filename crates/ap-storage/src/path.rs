@@ -0,0 +1,55 @@
+//! Helpers for slash-separated paths in archives.
+
+/// The parent of a normalized path.
+pub fn parent(path: &[u8]) -> &[u8] {
+    &path[..path.iter().rposition(|x| *x == b'/').unwrap_or(0)]
+}
+
+/// Remove empty and dot components and resolve dot-dot in place.  Returns the new length.
+pub fn normalize(path: &mut [u8]) -> usize {
+    let (mut read, mut write) = (0, 0);
+    while read < path.len() {
+        let len = path[read..]
+            .iter()
+            .position(|x| *x == b'/')
+            .unwrap_or(path.len() - read);
+        match &path[read..read + len] {
+            b"" | b"." => {}
+            b".." => write = parent(&path[..write]).len(),
+            _ => {
+                if write != 0 {
+                    path[write] = b'/';
+                    write += 1;
+                }
+                path.copy_within(read..read + len, write);
+                write += len;
+            }
+        }
+        read += len + 1;
+    }
+    write
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let check = |path: &[u8], expected: &[u8]| {
+            let mut buf = [0; 64];
+            buf[..path.len()].copy_from_slice(path);
+            let n = normalize(&mut buf[..path.len()]);
+            assert_eq!(&buf[..n], expected);
+        };
+        check(b"a/b/c", b"a/b/c");
+        check(b"/a//./b/", b"a/b");
+        check(b"./a/../b/./c/..", b"b");
+        check(b"../../a", b"a");
+        check(b"a/b/../../..", b"");
+        check(b".", b"");
+        assert_eq!(parent(b"a/b/c"), b"a/b");
+        assert_eq!(parent(b"a"), b"");
+        assert_eq!(parent(b""), b"");
+    }
+}
@@ -8,6 +8,13 @@ pub trait Read {
     fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error>;
 }
 
+/// References can be used wherever a reader is expected.
+impl<T: Read + ?Sized> Read for &T {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        (**self).read_bytes(offset, buf)
+    }
+}
+
 /// Extension methods to make implementations easier.
 pub trait ReadExt {
     /// Fill the buffer.
@@ -0,0 +1,79 @@
+//! A scratch buffer shared by the files of a filesystem.
+
+use crate::{msg2err, Error};
+use core::{
+    cell::{RefCell, RefMut},
+    marker::PhantomData,
+};
+
+/// A scratch buffer that is borrowed mutably for the lifetime of a filesystem.
+///
+/// The buffer is kept as raw parts, as a `RefCell<&'a mut [u8]>` would make the
+/// filesystem invariant over its lifetime.
+pub struct ScratchBuf<'a> {
+    raw: RefCell<(*mut u8, usize)>,
+    _buf: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> ScratchBuf<'a> {
+    /// Take the buffer for the lifetime.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            raw: RefCell::new((buf.as_mut_ptr(), buf.len())),
+            _buf: PhantomData,
+        }
+    }
+
+    /// The size of the buffer.
+    pub fn len(&self) -> usize {
+        self.raw.borrow().1
+    }
+
+    /// Is the buffer empty?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Borrow the buffer.  Fails if it is already borrowed.
+    pub fn borrow_mut(&self) -> Result<RefMut<'_, [u8]>, Error> {
+        let raw = self
+            .raw
+            .try_borrow_mut()
+            .map_err(|_| msg2err!("scratch buffer in use"))?;
+        // SAFETY: the buffer is borrowed mutably for 'a and only reached via the RefCell
+        Ok(RefMut::map(raw, |(ptr, len)| unsafe {
+            core::slice::from_raw_parts_mut(*ptr, *len)
+        }))
+    }
+}
+
+impl core::fmt::Debug for ScratchBuf<'_> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> Result<(), core::fmt::Error> {
+        write!(fmt, "ScratchBuf({})", self.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The buffer can be used with a shorter lifetime.
+    fn shorten<'s>(scratch: ScratchBuf<'static>) -> ScratchBuf<'s> {
+        scratch
+    }
+
+    #[test]
+    fn scratch() {
+        let mut buf = [0; 16];
+        let scratch = ScratchBuf::new(&mut buf);
+        assert_eq!(scratch.len(), 16);
+        {
+            let mut first = scratch.borrow_mut().unwrap();
+            first[3] = 7;
+            assert!(scratch.borrow_mut().is_err());
+        }
+        assert_eq!(scratch.borrow_mut().unwrap()[3], 7);
+        assert_eq!(buf[3], 7);
+        assert!(shorten(ScratchBuf::new(&mut [])).is_empty());
+    }
+}
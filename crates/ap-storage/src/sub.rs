@@ -0,0 +1,128 @@
+//! Clip a disk to a range.
+
+use crate::{Error, Offset, Read, Write};
+
+/// A range of an underlying disk.
+///
+/// Offsets are relative to the start of the range and accesses are clipped at its end.
+#[derive(Debug, Clone, Copy)]
+pub struct SubDisk<D> {
+    disk: D,
+    offset: Offset,
+    len: Offset,
+}
+
+impl<D> SubDisk<D> {
+    /// Use `len` bytes starting at `offset` of the disk.
+    pub fn new(disk: D, offset: Offset, len: Offset) -> Self {
+        Self { disk, offset, len }
+    }
+
+    /// The start of the range in the underlying disk.
+    pub fn offset(&self) -> Offset {
+        self.offset
+    }
+
+    /// The length of the range.
+    pub fn len(&self) -> Offset {
+        self.len
+    }
+
+    /// Is the range empty?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the underlying disk.
+    pub fn into_inner(self) -> D {
+        self.disk
+    }
+
+    /// The number of bytes available at the offset.
+    fn available(&self, offset: Offset, n: usize) -> usize {
+        core::cmp::min(self.len.saturating_sub(offset), n as Offset) as usize
+    }
+}
+
+impl<D: Read> Read for SubDisk<D> {
+    fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+        let n = self.available(offset, buf.len());
+        if n == 0 {
+            return Ok(0);
+        }
+        self.disk.read_bytes(self.offset + offset, &mut buf[..n])
+    }
+}
+
+impl<D: Write> Write for SubDisk<D> {
+    fn write_bytes(&self, offset: Offset, buf: &[u8]) -> Result<usize, Error> {
+        let n = self.available(offset, buf.len());
+        if n == 0 {
+            return Ok(0);
+        }
+        self.disk.write_bytes(self.offset + offset, &buf[..n])
+    }
+
+    fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error> {
+        let n = core::cmp::min(self.len.saturating_sub(offset), len);
+        if n == 0 {
+            return Ok(0);
+        }
+        self.disk.discard(self.offset + offset, n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use core::cell::RefCell;
+    use std::{vec, vec::Vec};
+
+    /// A disk that records the accesses.
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<(char, Offset, Offset)>>);
+
+    impl Read for Recorder {
+        fn read_bytes(&self, offset: Offset, buf: &mut [u8]) -> Result<usize, Error> {
+            self.0.borrow_mut().push(('r', offset, buf.len() as Offset));
+            buf.fill(1);
+            Ok(buf.len())
+        }
+    }
+
+    impl Write for Recorder {
+        fn write_bytes(&self, offset: Offset, buf: &[u8]) -> Result<usize, Error> {
+            self.0.borrow_mut().push(('w', offset, buf.len() as Offset));
+            Ok(buf.len())
+        }
+        fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error> {
+            self.0.borrow_mut().push(('d', offset, len));
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn clipping() {
+        let disk = Recorder::default();
+        let sub = SubDisk::new(&disk, 1000, 100);
+        let mut buf = [0; 30];
+
+        // accesses are moved by the offset and clipped at offset+len
+        assert_eq!(sub.read_bytes(0, &mut buf).unwrap(), 30);
+        assert_eq!(sub.read_bytes(80, &mut buf).unwrap(), 20);
+        assert_eq!(sub.write_bytes(90, &buf).unwrap(), 10);
+        assert_eq!(sub.discard(10, 1000).unwrap(), 90);
+        assert_eq!(
+            *disk.0.borrow(),
+            vec![('r', 1000, 30), ('r', 1080, 20), ('w', 1090, 10), ('d', 1010, 90)]
+        );
+
+        // nothing reaches the disk at or behind the end
+        assert_eq!(sub.read_bytes(100, &mut buf).unwrap(), 0);
+        assert_eq!(sub.write_bytes(200, &buf).unwrap(), 0);
+        assert_eq!(sub.discard(100, 10).unwrap(), 0);
+        assert_eq!(sub.discard(u64::MAX, 10).unwrap(), 0);
+        assert_eq!(disk.0.borrow().len(), 4);
+    }
+}
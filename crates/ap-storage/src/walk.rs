@@ -0,0 +1,612 @@
+//! Generic traversal of directory trees.
+//!
+//! The walker works on any [`File`] and does not allocate. The caller
+//! provides the frames for the pending files and an optional table to
+//! remember the IDs already visited.
+//!
+//! - `pre` is called for every entry, its return value decides whether a directory is entered.
+//! - `post` is called for every directory where `pre` returned `true`, but only in a depth-first walk.
+//! - Parent and unknown entries are never descended into.
+
+use crate::{
+    attr::{Attributes, ID},
+    directory::{DirEntry, DirIterator},
+    file::{File, FileType},
+    msg2err, Error,
+};
+
+/// The order in which the tree is traversed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// Descend into a directory before visiting its siblings.
+    #[default]
+    DepthFirst,
+    /// Visit all entries of a directory before descending.
+    BreadthFirst,
+}
+
+/// How an entry relates to the ones visited before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    /// The ID was not seen before or is not available.
+    New,
+    /// The ID was already visited.
+    HardLink,
+    /// The entry points to one of its own ancestors.
+    Cycle,
+}
+
+/// The walk options.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// The traversal order.
+    pub order: Order,
+    /// Maximum depth. Zero means unlimited.
+    pub max_depth: usize,
+    /// Report the parent and self-pointers as well.
+    pub parents: bool,
+}
+
+/// An entry visited during the walk.
+pub struct Entry<'a, F> {
+    /// The opened file.
+    pub file: &'a F,
+    /// The name of the entry.
+    pub name: &'a [u8],
+    /// The directory entry in the parent.
+    pub dirent: DirEntry,
+    /// The value of the ID attribute.
+    pub id: Option<u64>,
+    /// The depth starting with one for the children of the root.
+    pub depth: usize,
+    /// Whether this entry was seen before.
+    pub link: Link,
+}
+
+/// The callbacks of a walk.
+pub trait Visitor<F: File> {
+    /// Called for every entry.  Return `true` to descend into a directory.
+    fn pre(&mut self, entry: &Entry<'_, F>) -> Result<bool, Error>;
+
+    /// Called after the children of a directory were visited.
+    ///
+    /// A breadth-first walk visits a subtree long after the directory was
+    /// listed, so this is not called at all.
+    fn post(&mut self, _entry: &Entry<'_, F>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Closures are visitors without a post-order callback.
+impl<F: File, T: FnMut(&Entry<'_, F>) -> Result<bool, Error>> Visitor<F> for T {
+    fn pre(&mut self, entry: &Entry<'_, F>) -> Result<bool, Error> {
+        self(entry)
+    }
+}
+
+/// A slot holding a pending file.
+pub struct Frame<F> {
+    file: Option<F>,
+    id: Option<u64>,
+    depth: usize,
+}
+
+impl<F> Frame<F> {
+    /// An unused frame to initialize the stack with.
+    pub const EMPTY: Self = Self {
+        file: None,
+        id: None,
+        depth: 0,
+    };
+}
+
+/// A hash-map from IDs to the IDs of their parents with a fixed capacity.
+///
+/// Every ID takes two slots.  IDs are silently dropped when the table is full.
+struct Seen<'a>(&'a mut [u64]);
+
+impl Seen<'_> {
+    /// Find the slot of a key or the empty slot where it belongs.
+    fn slot(&self, key: u64) -> Option<usize> {
+        let len = self.0.len() / 2;
+        if len == 0 {
+            return None;
+        }
+        let start = (key.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % len;
+        (0..len)
+            .map(|i| (start + i) % len)
+            .find(|&i| self.0[2 * i] == key || self.0[2 * i] == 0)
+    }
+
+    /// Insert an ID with its parent and return whether it was new.
+    fn insert(&mut self, id: u64, parent: Option<u64>) -> bool {
+        // zero marks an empty slot and a missing parent
+        let key = id.wrapping_add(1);
+        if key == 0 {
+            return true;
+        }
+        match self.slot(key) {
+            Some(i) if self.0[2 * i] == key => false,
+            Some(i) => {
+                self.0[2 * i] = key;
+                self.0[2 * i + 1] = parent.map_or(0, |x| x.wrapping_add(1));
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// The parent of an inserted ID.
+    fn parent(&self, id: u64) -> Option<u64> {
+        let key = id.wrapping_add(1);
+        let i = self.slot(key).filter(|&i| self.0[2 * i] == key)?;
+        self.0[2 * i + 1].checked_sub(1)
+    }
+}
+
+/// The IDs of the directories above the current one.
+struct Ancestor<'a> {
+    id: Option<u64>,
+    parent: Option<&'a Ancestor<'a>>,
+}
+
+impl Ancestor<'_> {
+    fn contains(&self, id: u64) -> bool {
+        let mut cur = Some(self);
+        while let Some(x) = cur {
+            if x.id == Some(id) {
+                return true;
+            }
+            cur = x.parent;
+        }
+        false
+    }
+}
+
+/// Walk a directory tree with bounded memory.
+///
+/// The number of frames limits the depth of a depth-first walk and the
+/// number of pending directories of a breadth-first one.  A breadth-first
+/// walk finds the ancestors of a directory in the `seen` table, so cycles
+/// are only detected as far as the table recorded the parents.
+pub struct Walker<'a, F> {
+    options: Options,
+    stack: &'a mut [Frame<F>],
+    seen: Seen<'a>,
+}
+
+impl<'a, F: File> Walker<'a, F> {
+    /// Create a walker.  Every ID takes two slots in the `seen` table.  An empty table disables the
+    /// hard-link detection.
+    pub fn new(options: Options, stack: &'a mut [Frame<F>], seen: &'a mut [u64]) -> Self {
+        seen.fill(0);
+        Self {
+            options,
+            stack,
+            seen: Seen(seen),
+        }
+    }
+
+    /// Visit all entries below the root.
+    pub fn walk(&mut self, root: &F, visitor: &mut impl Visitor<F>) -> Result<(), Error> {
+        let id = file_id(root);
+        if let Some(id) = id {
+            self.seen.insert(id, None);
+        }
+        let root_ancestor = Ancestor { id, parent: None };
+        let stack = core::mem::take(&mut self.stack);
+        let res = match self.options.order {
+            Order::DepthFirst => self.depth_first(stack, root, &root_ancestor, 1, visitor),
+            Order::BreadthFirst => {
+                let mut queue = Queue {
+                    frames: stack,
+                    head: 0,
+                    len: 0,
+                };
+                let res = self.breadth_first(&mut queue, root, &root_ancestor, visitor);
+                // drop the remaining files on errors
+                while queue.pop().is_some() {}
+                res
+            }
+        };
+        self.stack = stack;
+        res
+    }
+
+    /// Should the entry be skipped completely?
+    fn skip(&self, dirent: &DirEntry) -> bool {
+        match dirent.typ {
+            FileType::Unknown => true,
+            FileType::Parent => !self.options.parents,
+            _ => false,
+        }
+    }
+
+    /// Should the walker descend into the entry?
+    fn descend(&self, entry: &Entry<'_, F>) -> bool {
+        entry.dirent.typ == FileType::Directory
+            && entry.link == Link::New
+            && (self.options.max_depth == 0 || entry.depth < self.options.max_depth)
+    }
+
+    /// Is the ID one of the ancestors or recorded above the innermost one?
+    fn is_ancestor(&self, id: u64, ancestors: &Ancestor<'_>) -> bool {
+        if ancestors.contains(id) {
+            return true;
+        }
+        let mut cur = ancestors.id;
+        for _ in 0..self.seen.0.len() / 2 {
+            cur = match cur.and_then(|x| self.seen.parent(x)) {
+                Some(x) if x == id => return true,
+                x => x,
+            };
+        }
+        false
+    }
+
+    /// Detect hard-links and cycles by looking at the ID.
+    fn classify(&mut self, dirent: &DirEntry, file: &F, ancestors: &Ancestor<'_>) -> (Option<u64>, Link) {
+        let id = file_id(file);
+        let link = match id {
+            None => Link::New,
+            Some(id) if self.is_ancestor(id, ancestors) => Link::Cycle,
+            // parent pointers are not recorded to not shadow the real entries
+            Some(_) if dirent.typ == FileType::Parent => Link::New,
+            Some(id) if !self.seen.insert(id, ancestors.id) => Link::HardLink,
+            Some(_) => Link::New,
+        };
+        (id, link)
+    }
+
+    /// Visit the children of a directory recursively.
+    fn depth_first(
+        &mut self,
+        stack: &mut [Frame<F>],
+        dir: &F,
+        ancestors: &Ancestor<'_>,
+        depth: usize,
+        visitor: &mut impl Visitor<F>,
+    ) -> Result<(), Error> {
+        let Some(mut iter) = dir.dir() else {
+            return Ok(());
+        };
+        let mut name = [0u8; 256];
+        while let Some(dirent) = iter.next(&mut name)? {
+            if self.skip(&dirent) {
+                continue;
+            }
+            let Some((slot, rest)) = stack.split_first_mut() else {
+                return Err(msg2err!("walk stack exhausted"));
+            };
+            let file = slot.file.insert(dir.open(dirent.offset)?);
+            let (id, link) = self.classify(&dirent, file, ancestors);
+            let nlen = core::cmp::min(dirent.nlen, name.len());
+            let entry = Entry {
+                file,
+                name: &name[..nlen],
+                dirent,
+                id,
+                depth,
+                link,
+            };
+            if !visitor.pre(&entry)? || dirent.typ != FileType::Directory {
+                continue;
+            }
+            if self.descend(&entry) {
+                let ancestor = Ancestor {
+                    id,
+                    parent: Some(ancestors),
+                };
+                self.depth_first(rest, file, &ancestor, depth + 1, visitor)?;
+            }
+            visitor.post(&entry)?;
+        }
+        if let Some(slot) = stack.first_mut() {
+            slot.file = None;
+        }
+        Ok(())
+    }
+
+    /// Visit the tree level by level.
+    fn breadth_first(
+        &mut self,
+        queue: &mut Queue<'_, F>,
+        root: &F,
+        ancestors: &Ancestor<'_>,
+        visitor: &mut impl Visitor<F>,
+    ) -> Result<(), Error> {
+        self.list(queue, root, ancestors, 1, visitor)?;
+        while let Some(frame) = queue.pop() {
+            let Some(file) = frame.file else { continue };
+            let ancestor = Ancestor {
+                id: frame.id,
+                parent: None,
+            };
+            self.list(queue, &file, &ancestor, frame.depth + 1, visitor)?;
+        }
+        Ok(())
+    }
+
+    /// Visit the children of a single directory and queue the subdirectories.
+    fn list(
+        &mut self,
+        queue: &mut Queue<'_, F>,
+        dir: &F,
+        ancestors: &Ancestor<'_>,
+        depth: usize,
+        visitor: &mut impl Visitor<F>,
+    ) -> Result<(), Error> {
+        let Some(mut iter) = dir.dir() else {
+            return Ok(());
+        };
+        let mut name = [0u8; 256];
+        while let Some(dirent) = iter.next(&mut name)? {
+            if self.skip(&dirent) {
+                continue;
+            }
+            let file = dir.open(dirent.offset)?;
+            let (id, link) = self.classify(&dirent, &file, ancestors);
+            let nlen = core::cmp::min(dirent.nlen, name.len());
+            let entry = Entry {
+                file: &file,
+                name: &name[..nlen],
+                dirent,
+                id,
+                depth,
+                link,
+            };
+            if !visitor.pre(&entry)? || !self.descend(&entry) {
+                continue;
+            }
+            queue.push(Frame {
+                file: Some(file),
+                id,
+                depth,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// A ring-buffer of pending directories.
+struct Queue<'a, F> {
+    frames: &'a mut [Frame<F>],
+    head: usize,
+    len: usize,
+}
+
+impl<F> Queue<'_, F> {
+    fn push(&mut self, frame: Frame<F>) -> Result<(), Error> {
+        if self.len == self.frames.len() {
+            return Err(msg2err!("walk queue exhausted"));
+        }
+        let index = (self.head + self.len) % self.frames.len();
+        self.frames[index] = frame;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Frame<F>> {
+        if self.len == 0 {
+            return None;
+        }
+        let res = core::mem::replace(&mut self.frames[self.head], Frame::EMPTY);
+        self.head = (self.head + 1) % self.frames.len();
+        self.len -= 1;
+        Some(res)
+    }
+}
+
+/// Get the ID attribute of a file.
+fn file_id<F: File>(file: &F) -> Option<u64> {
+    file.attr().get(ID, &mut []).and_then(|x| x.as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::{attr::Value, Offset, Read};
+    use std::vec::Vec;
+
+    use FileType::{Directory as D, File as F, Parent as P};
+
+    /// The name, ID and type of directory entries.
+    type Entries = &'static [(&'static str, u64, FileType)];
+
+    /// The directories of the test tree with their entries by ID.  The directory `a/b/c/loop` points to `a`.
+    const TREE: &[(u64, Entries)] = &[
+        (1, &[("..", 1, P), ("f", 3, F), ("a", 2, D), ("e", 6, D)]),
+        (2, &[("..", 1, P), ("b", 4, D), ("link", 4, D), ("hard", 3, F)]),
+        (4, &[("..", 2, P), ("c", 5, D)]),
+        (5, &[("..", 4, P), ("loop", 2, D), ("d", 8, D), ("g", 7, F)]),
+        (6, &[]),
+        (8, &[]),
+    ];
+
+    struct Node(u64);
+
+    impl Node {
+        fn entries(&self) -> Option<Entries> {
+            TREE.iter().find(|x| x.0 == self.0).map(|x| x.1)
+        }
+    }
+
+    impl Read for Node {
+        fn read_bytes(&self, _offset: Offset, _buf: &mut [u8]) -> Result<usize, Error> {
+            Ok(0)
+        }
+    }
+
+    struct NodeAttr<'a>(&'a Node);
+
+    impl<'a> IntoIterator for NodeAttr<'a> {
+        type Item = &'a &'a str;
+        type IntoIter = core::slice::Iter<'a, &'a str>;
+        fn into_iter(self) -> Self::IntoIter {
+            [ID].iter()
+        }
+    }
+
+    impl<'a> Attributes<'a> for NodeAttr<'a> {
+        fn get(&self, name: &str, _buf: &mut [u8]) -> Option<Value> {
+            (name == ID).then_some(self.0 .0.into())
+        }
+    }
+
+    struct NodeDir(Entries, usize);
+
+    impl DirIterator for NodeDir {
+        fn next(&mut self, name: &mut [u8]) -> Result<Option<DirEntry>, Error> {
+            let Some(&(n, id, typ)) = self.0.get(self.1) else {
+                return Ok(None);
+            };
+            name[..n.len()].copy_from_slice(n.as_bytes());
+            self.1 += 1;
+            Ok(Some(DirEntry {
+                offset: self.1 as u64 - 1,
+                id,
+                nlen: n.len(),
+                typ,
+            }))
+        }
+    }
+
+    impl File for Node {
+        type AttrType<'c> = NodeAttr<'c>;
+        fn attr(&self) -> Self::AttrType<'_> {
+            NodeAttr(self)
+        }
+
+        type DirType<'c> = NodeDir;
+        fn dir(&self) -> Option<Self::DirType<'_>> {
+            self.entries().map(|x| NodeDir(x, 0))
+        }
+
+        fn open(&self, offset: Offset) -> Result<Self, Error> {
+            let entries = self.entries().ok_or(msg2err!("not a directory"))?;
+            Ok(Node(entries[offset as usize].1))
+        }
+    }
+
+    /// Walk the tree from a node and return the name, depth and link of every entry.
+    fn walk(root: u64, options: Options, frames: usize) -> Result<Vec<(&'static str, usize, Link)>, Error> {
+        let mut stack: Vec<_> = (0..frames).map(|_| Frame::EMPTY).collect();
+        let mut seen = [0; 64];
+        let mut res = Vec::new();
+        Walker::new(options, &mut stack, &mut seen).walk(&Node(root), &mut |entry: &Entry<Node>| {
+            let name = ["..", "a", "b", "c", "d", "e", "f", "g", "hard", "link", "loop"];
+            let name = name.into_iter().find(|x| x.as_bytes() == entry.name).unwrap();
+            res.push((name, entry.depth, entry.link));
+            Ok(true)
+        })?;
+        Ok(res)
+    }
+
+    /// Record the IDs of the directories passed to the post-order callback.
+    struct Posts(Vec<u64>);
+
+    impl Visitor<Node> for Posts {
+        fn pre(&mut self, _entry: &Entry<'_, Node>) -> Result<bool, Error> {
+            Ok(true)
+        }
+        fn post(&mut self, entry: &Entry<'_, Node>) -> Result<(), Error> {
+            self.0.push(entry.dirent.id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn orders() {
+        use Link::*;
+        let options = |order| Options {
+            order,
+            parents: true,
+            ..Default::default()
+        };
+        let dfs = walk(1, options(Order::DepthFirst), 4).unwrap();
+        assert_eq!(
+            dfs,
+            [
+                ("..", 1, Cycle),
+                ("f", 1, New),
+                ("a", 1, New),
+                ("..", 2, Cycle),
+                ("b", 2, New),
+                ("..", 3, Cycle),
+                ("c", 3, New),
+                ("..", 4, Cycle),
+                ("loop", 4, Cycle),
+                ("d", 4, New),
+                ("g", 4, New),
+                ("link", 2, HardLink),
+                ("hard", 2, HardLink),
+                ("e", 1, New),
+            ]
+        );
+
+        // both orders classify the entries the same way
+        let mut bfs = walk(1, options(Order::BreadthFirst), 2).unwrap();
+        assert_eq!(
+            bfs[..4],
+            [("..", 1, Cycle), ("f", 1, New), ("a", 1, New), ("e", 1, New)]
+        );
+        assert!(bfs.is_sorted_by_key(|x| x.1));
+        let mut dfs = dfs;
+        dfs.sort_by_key(|x| (x.0, x.1));
+        bfs.sort_by_key(|x| (x.0, x.1));
+        assert_eq!(dfs, bfs);
+    }
+
+    #[test]
+    fn limits() {
+        for order in [Order::DepthFirst, Order::BreadthFirst] {
+            let options = Options {
+                order,
+                max_depth: 2,
+                ..Default::default()
+            };
+            let mut res = walk(1, options, 2).unwrap();
+            res.sort_by_key(|x| (x.0, x.1));
+            assert_eq!(
+                res,
+                [
+                    ("a", 1, Link::New),
+                    ("b", 2, Link::New),
+                    ("e", 1, Link::New),
+                    ("f", 1, Link::New),
+                    ("hard", 2, Link::HardLink),
+                    ("link", 2, Link::HardLink),
+                ]
+            );
+        }
+
+        // frames are only needed for directories with entries
+        let options = Options::default();
+        assert!(walk(1, options.clone(), 3).is_err());
+        assert_eq!(walk(1, options.clone(), 4).unwrap().len(), 10);
+        assert_eq!(walk(6, options, 0).unwrap(), []);
+    }
+
+    #[test]
+    fn post_order() {
+        let mut stack: Vec<_> = (0..4).map(|_| Frame::EMPTY).collect();
+        let mut seen = [0; 64];
+        let mut posts = Posts(Vec::new());
+        Walker::new(Options::default(), &mut stack, &mut seen)
+            .walk(&Node(1), &mut posts)
+            .unwrap();
+        assert_eq!(posts.0, [2, 8, 5, 4, 4, 2, 6]);
+
+        // a breadth-first walk has no post-order
+        let options = Options {
+            order: Order::BreadthFirst,
+            ..Default::default()
+        };
+        posts.0.clear();
+        Walker::new(options, &mut stack, &mut seen)
+            .walk(&Node(1), &mut posts)
+            .unwrap();
+        assert!(posts.0.is_empty());
+    }
+}
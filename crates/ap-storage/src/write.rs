@@ -9,6 +9,16 @@ pub trait Write {
     fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error>;
 }
 
+/// References can be used wherever a writer is expected.
+impl<T: Write + ?Sized> Write for &T {
+    fn write_bytes(&self, offset: Offset, buf: &[u8]) -> Result<usize, Error> {
+        (**self).write_bytes(offset, buf)
+    }
+    fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error> {
+        (**self).discard(offset, len)
+    }
+}
+
 /// Trait extension to simplify writing.
 pub trait WriteExt {
     /// Write the whole buffer.
@@ -41,7 +51,7 @@ impl WriteExt for &dyn Write {
     /// Discard all bytes in the range.
     fn discard_all(&self, mut offset: Offset, len: Offset) -> Result<(), Error> {
         let end = offset + len;
-        while offset < len {
+        while offset < end {
             match self.discard(offset, end - offset)? {
                 0 => return Err(msg2err!(PartialWriteError)),
                 n => offset += n,
@@ -60,3 +70,39 @@ impl core::fmt::Display for PartialWriteError {
         write!(fmt, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use core::cell::RefCell;
+    use std::{vec, vec::Vec};
+
+    /// A disk that discards at most a chunk at a time and records the ranges.
+    struct Chunked(RefCell<Vec<(Offset, Offset)>>);
+
+    impl Write for Chunked {
+        fn write_bytes(&self, _offset: Offset, buf: &[u8]) -> Result<usize, Error> {
+            Ok(buf.len())
+        }
+        fn discard(&self, offset: Offset, len: Offset) -> Result<Offset, Error> {
+            let n = core::cmp::min(len, 100);
+            self.0.borrow_mut().push((offset, n));
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn discard_all() {
+        let disk = Chunked(RefCell::new(Vec::new()));
+        let writer: &dyn Write = &disk;
+
+        // the whole range is discarded even when it starts behind its length
+        writer.discard_all(1000, 250).unwrap();
+        assert_eq!(*disk.0.borrow(), vec![(1000, 100), (1100, 100), (1200, 50)]);
+
+        disk.0.borrow_mut().clear();
+        writer.discard_all(1000, 0).unwrap();
+        assert!(disk.0.borrow().is_empty());
+    }
+}
@@ -14,27 +14,188 @@
 //! ### Types
 //!
 //! - there are signed and unsigned 64-bit values
-//! - there are boolean and raw-byte values
+//! - there are boolean, raw-byte and string values
 //! - times are defined as nano-seconds since UNIX_EPOCH.
+//! - UUIDs are 16 bytes in network order.
+//!
+//! ### Metadata
+//!
+//! Every attribute defined with `new_attr!` has a `META` constant
+//! describing its type and documentation.  It lives in a module of
+//! the same name, so `SIZE` is the name and `SIZE::META` the metadata.
 
 #![no_std]
 
 mod value;
-pub use value::Value;
+pub use value::{Type, Value};
 
-/// Define an attribute name.
+/// Define an attribute name and its metadata.
 #[macro_export]
 macro_rules! new_attr {
     ($name: ident, $typ:ident, $doc:literal) => {
         #[doc=concat!($doc, " (", stringify!($typ), ")")]
         pub const $name: &str = concat!(env!("CARGO_PKG_NAME"), ".", stringify!($name));
+
+        #[doc(hidden)]
+        #[allow(non_snake_case)]
+        pub mod $name {
+            /// The metadata of the attribute.
+            pub const META: $crate::Meta = $crate::Meta {
+                typ: $crate::Type::$typ,
+                writable: false,
+                doc: $doc,
+            };
+        }
+    };
+}
+
+/// Lookup the metadata for one of the listed attributes.
+#[macro_export]
+macro_rules! attr_meta {
+    ($name: expr, [$($($seg: ident)::+),* $(,)?]) => {
+        match $name {
+            $($($seg)::+ => Some($($seg)::+::META),)*
+            _ => None,
+        }
     };
 }
 
+/// Metadata describing an attribute.
+#[derive(Debug, Clone, Copy)]
+pub struct Meta {
+    /// The type of the values.
+    pub typ: Type,
+    /// Whether the attribute can be changed with [`AttributesMut::set`].
+    pub writable: bool,
+    /// A human readable description.
+    pub doc: &'static str,
+}
+
 /// Trait for reading attributes.
 ///
 /// The keys can be iterated.
 pub trait Attributes<'a>: IntoIterator<Item = &'a &'a str> {
     /// Get a value.  The (optional) buffer is used when raw-values are returned.
     fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value>;
+
+    /// Get the metadata of an attribute.
+    fn meta(&self, _name: &str) -> Option<Meta> {
+        None
+    }
+}
+
+/// Trait for changing attributes.
+pub trait AttributesMut {
+    /// Set a value.  The buffer holds the bytes of raw and string values.
+    fn set(&mut self, name: &str, value: Value, buf: &[u8]) -> Result<(), anyhow::Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    new_attr!(COUNT, U64, "A counter.");
+    new_attr!(NAME, Str, "The name.");
+    new_attr!(STAMP, Time, "The last change.");
+    new_attr!(UUID, Uuid, "The identity.");
+
+    /// An object with a few attributes.
+    struct Object {
+        count: u64,
+        name: &'static str,
+        stamp: i64,
+    }
+
+    impl<'a> IntoIterator for &'a Object {
+        type Item = &'a &'a str;
+        type IntoIter = core::slice::Iter<'a, &'a str>;
+        fn into_iter(self) -> Self::IntoIter {
+            [COUNT, NAME, STAMP, UUID].iter()
+        }
+    }
+
+    impl<'a> Attributes<'a> for &'a Object {
+        fn get(&self, name: &str, buf: &mut [u8]) -> Option<Value> {
+            Some(match name {
+                COUNT => self.count.into(),
+                NAME => {
+                    let n = core::cmp::min(self.name.len(), buf.len());
+                    buf[..n].copy_from_slice(&self.name.as_bytes()[..n]);
+                    Value::Str(self.name.len())
+                }
+                STAMP => Value::Time(self.stamp),
+                UUID => Value::Uuid(core::array::from_fn(|i| i as u8)),
+                _ => return None,
+            })
+        }
+
+        fn meta(&self, name: &str) -> Option<Meta> {
+            let meta = attr_meta!(name, [COUNT, NAME, STAMP, UUID])?;
+            Some(Meta {
+                writable: name == COUNT,
+                ..meta
+            })
+        }
+    }
+
+    impl AttributesMut for Object {
+        fn set(&mut self, name: &str, value: Value, _buf: &[u8]) -> Result<(), anyhow::Error> {
+            match (name, value) {
+                (COUNT, Value::U64(x)) => self.count = x,
+                _ => return Err(anyhow::anyhow!("read-only")),
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(COUNT, "ap-util-attr.COUNT");
+        assert_eq!(UUID, "ap-util-attr.UUID");
+        assert_eq!(NAME::META.typ, Type::Str);
+        assert_eq!(NAME::META.doc, "The name.");
+        assert_eq!((STAMP::META.typ, STAMP::META.writable), (Type::Time, false));
+
+        let object = Object {
+            count: 0,
+            name: "object",
+            stamp: -1,
+        };
+        let attr = &object;
+        assert_eq!(attr.meta(COUNT).map(|x| (x.typ, x.writable)), Some((Type::U64, true)));
+        assert_eq!(attr.meta(STAMP).map(|x| (x.typ, x.writable)), Some((Type::Time, false)));
+        assert!(attr.meta("ap-util-attr.NONE").is_none());
+        for name in attr {
+            let value = attr.get(name, &mut []).unwrap();
+            assert_eq!(Some(value.typ()), attr.meta(name).map(|x| x.typ), "{name}");
+        }
+    }
+
+    #[test]
+    fn values() {
+        let mut object = Object {
+            count: 3,
+            name: "object",
+            stamp: -1_000_000_000,
+        };
+        let mut buf = [0; 4];
+        let name = (&object).get(NAME, &mut buf).unwrap();
+        assert_eq!((name, name.as_len(), name.as_u64()), (Value::Str(6), Some(6), None));
+        assert_eq!(&buf, b"obje");
+        let stamp = (&object).get(STAMP, &mut []).unwrap();
+        assert_eq!((stamp.as_time(), stamp.as_i64()), (Some(-1_000_000_000), None));
+        let uuid = (&object).get(UUID, &mut []).unwrap();
+        assert_eq!(uuid.as_uuid().map(|x| x[15]), Some(15));
+        assert_eq!((uuid.as_len(), Value::Raw(7).as_len()), (None, Some(7)));
+        assert_eq!(Value::from(7u8), Value::U64(7));
+        assert_eq!(Value::from(-7i64).as_i64(), Some(-7));
+        assert_eq!(Value::from(true).as_bool(), Some(true));
+
+        // only the writable attribute with the right type can be changed
+        object.set(COUNT, 5u32.into(), &[]).unwrap();
+        assert_eq!((&object).get(COUNT, &mut []), Some(Value::U64(5)));
+        assert!(object.set(COUNT, Value::I64(5), &[]).is_err());
+        assert!(object.set(STAMP, Value::Time(0), &[]).is_err());
+    }
 }
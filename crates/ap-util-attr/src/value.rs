@@ -3,6 +3,7 @@
 //! A union type to simplify the code.
 
 /// A value type of an attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Value {
     /// The length of a raw value.
     Raw(usize),
@@ -12,9 +13,46 @@ pub enum Value {
     I64(i64),
     /// A boolean flag.
     Bool(bool),
+    /// Nano-seconds since UNIX_EPOCH.
+    Time(i64),
+    /// An UUID in network order.
+    Uuid([u8; 16]),
+    /// The length of an UTF-8 string.
+    Str(usize),
+}
+
+/// The type of an attribute value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    /// Raw bytes.
+    Raw,
+    /// A unsigned integer.
+    U64,
+    /// A signed integer.
+    I64,
+    /// A boolean flag.
+    Bool,
+    /// Nano-seconds since UNIX_EPOCH.
+    Time,
+    /// An UUID in network order.
+    Uuid,
+    /// An UTF-8 string.
+    Str,
 }
 
 impl Value {
+    /// The type of the value.
+    pub fn typ(&self) -> Type {
+        match self {
+            Self::Raw(_) => Type::Raw,
+            Self::U64(_) => Type::U64,
+            Self::I64(_) => Type::I64,
+            Self::Bool(_) => Type::Bool,
+            Self::Time(_) => Type::Time,
+            Self::Uuid(_) => Type::Uuid,
+            Self::Str(_) => Type::Str,
+        }
+    }
     /// Get as an u64. Returns None if this is not one.
     pub fn as_u64(&self) -> Option<u64> {
         let Self::U64(x) = self else { return None };
@@ -30,11 +68,23 @@ impl Value {
         let Self::Bool(x) = self else { return None };
         Some(*x)
     }
-    /// Get the length of a Raw value. Returns None if this is not one.
-    pub fn as_len(&self) -> Option<usize> {
-        let Self::Raw(x) = self else { return None };
+    /// Get as a time. Returns None if this is not one.
+    pub fn as_time(&self) -> Option<i64> {
+        let Self::Time(x) = self else { return None };
+        Some(*x)
+    }
+    /// Get as an UUID. Returns None if this is not one.
+    pub fn as_uuid(&self) -> Option<[u8; 16]> {
+        let Self::Uuid(x) = self else { return None };
         Some(*x)
     }
+    /// Get the length of a Raw or Str value. Returns None if this is not one.
+    pub fn as_len(&self) -> Option<usize> {
+        match self {
+            Self::Raw(x) | Self::Str(x) => Some(*x),
+            _ => None,
+        }
+    }
 }
 impl From<u8> for Value {
     fn from(val: u8) -> Self {
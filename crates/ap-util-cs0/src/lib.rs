@@ -0,0 +1,90 @@
+//! Decode OSTA CS0 and UCS-2 names of optical media into UTF-8.
+//!
+//! UDF stores names as OSTA CS0 with a compression ID in front of 8-bit or big-endian 16-bit characters, while
+//! Joliet uses the 16-bit characters alone.  Names are truncated to the output buffer, but the functions return
+//! the full length, so that callers can detect this.
+
+#![no_std]
+
+/// The compression IDs of 8-bit and 16-bit characters.
+pub const COMPRESSION_8: u8 = 8;
+pub const COMPRESSION_16: u8 = 16;
+
+/// Append a character to the output and increment the length.
+fn push(out: &mut [u8], n: &mut usize, ch: char) {
+    let mut tmp = [0; 4];
+    let bytes = ch.encode_utf8(&mut tmp).as_bytes();
+    if *n < out.len() {
+        let x = core::cmp::min(bytes.len(), out.len() - *n);
+        out[*n..*n + x].copy_from_slice(&bytes[..x]);
+    }
+    *n += bytes.len();
+}
+
+/// Decode big-endian UTF-16 and return the length of the UTF-8 encoding.
+///
+/// Unpaired surrogates become the replacement character and a trailing odd byte is ignored.
+pub fn decode_utf16be(bytes: &[u8], out: &mut [u8]) -> usize {
+    let mut n = 0;
+    let units = bytes.chunks_exact(2).map(|x| u16::from_be_bytes([x[0], x[1]]));
+    for ch in char::decode_utf16(units) {
+        push(out, &mut n, ch.unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    n
+}
+
+/// Decode a CS0 string that starts with its compression ID and return the length of the UTF-8 encoding.
+///
+/// Empty strings have no compression ID.  Unknown IDs return `None`.
+pub fn decode(bytes: &[u8], out: &mut [u8]) -> Option<usize> {
+    let Some((id, chars)) = bytes.split_first() else {
+        return Some(0);
+    };
+    match *id {
+        COMPRESSION_8 => {
+            let mut n = 0;
+            for x in chars {
+                push(out, &mut n, *x as char);
+            }
+            Some(n)
+        }
+        COMPRESSION_16 => Some(decode_utf16be(chars, out)),
+        _ => None,
+    }
+}
+
+/// Decode a dstring, which is a fixed field with the length of the CS0 string in its last byte.
+pub fn decode_dstring(field: &[u8], out: &mut [u8]) -> Option<usize> {
+    let (len, rest) = field.split_last()?;
+    decode(rest.get(..*len as usize)?, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let mut buf = [0; 16];
+        assert_eq!(decode(b"\x08caf\xe9", &mut buf), Some(5));
+        assert_eq!(&buf[..5], "café".as_bytes());
+        assert_eq!(decode(b"\x10\x00a\xd8\x3d\xde\x00\x00", &mut buf), Some(5));
+        assert_eq!(&buf[..5], "a😀".as_bytes());
+        assert_eq!(decode(b"", &mut buf), Some(0));
+        assert_eq!(decode(b"\x07abc", &mut buf), None);
+
+        // unpaired surrogates and truncation
+        assert_eq!(decode_utf16be(b"\xd8\x00\x00b", &mut buf), 4);
+        assert_eq!(&buf[..4], "\u{fffd}b".as_bytes());
+        assert_eq!(decode_utf16be(b"\x00x\x00y\x00z", &mut buf[..2]), 3);
+        assert_eq!(&buf[..2], b"xy");
+
+        let mut field = [0; 32];
+        field[..6].copy_from_slice(b"\x08LABEL");
+        field[31] = 6;
+        assert_eq!(decode_dstring(&field, &mut buf), Some(5));
+        assert_eq!(&buf[..5], b"LABEL");
+        field[31] = 40;
+        assert_eq!(decode_dstring(&field, &mut buf), None);
+    }
+}
@@ -18,10 +18,11 @@ pub fn time2ts(hour: u32, minute: u32, second: u32) -> Time {
 /// Timestamp from date components.
 ///
 /// mday in range 1..=31
-/// month in range 1..=12
+/// month in range 1..=12, invalid months are clamped into it
 pub fn date2ts(mday: u32, month: u32, year: u32) -> Time {
     let days_per_month = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
-    let mut day_in_year = days_per_month[core::cmp::max(month as usize, 1) - 1] + mday as i32 - 1;
+    let month = month.clamp(1, 12);
+    let mut day_in_year = days_per_month[month as usize - 1] + mday as i32 - 1;
     if is_leap(year) && month < 3 {
         day_in_year -= 1
     };
@@ -53,6 +54,15 @@ mod tests {
         assert_eq!(0, date2ts(1, 1, 1970));
     }
 
+    #[test]
+    fn test_invalid_month() {
+        assert_eq!(date2ts(1, 0, 2024), date2ts(1, 1, 2024));
+        for month in [13, 14, 15, 200] {
+            assert_eq!(date2ts(1, month, 2024), date2ts(1, 12, 2024));
+        }
+        assert_eq!(dos_date2ts(0x59e1), date2ts(1, 12, 2024));
+    }
+
     #[test]
     fn test_two_ends() {
         assert_eq!(0, ((1 << 32) - date2ts(7, 2, 2106)) / 86400);
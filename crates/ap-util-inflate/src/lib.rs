@@ -0,0 +1,541 @@
+//! Decompress DEFLATE and zlib streams without allocations.
+//!
+//! - one-shot decompression into a buffer that holds the whole output
+//! - streaming through a caller provided window of at least 32k
+//! - the input is pulled byte-wise, so it can come from a slice or a disk
+
+#![no_std]
+
+use ap_storage::{msg2err, Error};
+
+/// The minimal size of the history window.
+pub const WINDOW_SIZE: usize = 1 << 15;
+
+/// The bits of the fast Huffman lookup.
+const FAST_BITS: u32 = 9;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// The order of the code length codes in a dynamic block header.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// A source of compressed bytes.
+pub trait Input {
+    /// The next byte or `None` at the end of the input.
+    fn byte(&mut self) -> Result<Option<u8>, Error>;
+}
+
+impl Input for &[u8] {
+    fn byte(&mut self) -> Result<Option<u8>, Error> {
+        let Some((x, rest)) = self.split_first() else {
+            return Ok(None);
+        };
+        *self = rest;
+        Ok(Some(*x))
+    }
+}
+
+/// A canonical Huffman code with a lookup table for the short codes.
+#[derive(Clone)]
+struct Huffman {
+    /// Symbol and length of the codes up to FAST_BITS indexed by the reversed code.
+    fast: [u16; 1 << FAST_BITS],
+    /// The number of codes per length.
+    counts: [u16; 16],
+    /// The symbols ordered by their code.
+    symbols: [u16; 288],
+}
+
+impl Huffman {
+    const EMPTY: Self = Self {
+        fast: [0; 1 << FAST_BITS],
+        counts: [0; 16],
+        symbols: [0; 288],
+    };
+
+    /// Build the code from the code lengths.  Incomplete codes are allowed.
+    fn build(&mut self, lengths: &[u8]) -> Result<(), Error> {
+        self.counts = [0; 16];
+        for &len in lengths {
+            self.counts[len as usize] += 1;
+        }
+        self.counts[0] = 0;
+        let mut left = 1i32;
+        let mut offs = [0u16; 16];
+        for len in 1..16 {
+            left = (left << 1) - self.counts[len] as i32;
+            if left < 0 {
+                return Err(msg2err!("over-subscribed code"));
+            }
+            offs[len] = if len == 1 {
+                0
+            } else {
+                offs[len - 1] + self.counts[len - 1]
+            };
+        }
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                self.symbols[offs[len as usize] as usize] = symbol as u16;
+                offs[len as usize] += 1;
+            }
+        }
+
+        // assign the canonical codes in symbol order to fill the fast table
+        self.fast = [0; 1 << FAST_BITS];
+        let mut next = [0u32; 16];
+        let mut code = 0;
+        for (len, next) in next.iter_mut().enumerate().skip(1) {
+            code = (code + self.counts[len - 1] as u32) << 1;
+            *next = code;
+        }
+        for (symbol, &len) in lengths.iter().enumerate() {
+            let len = len as u32;
+            if len == 0 || len > FAST_BITS {
+                continue;
+            }
+            let reversed = next[len as usize].reverse_bits() >> (32 - len);
+            next[len as usize] += 1;
+            for i in (reversed as usize..1 << FAST_BITS).step_by(1 << len) {
+                self.fast[i] = (symbol as u16) << 4 | len as u16;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The history of a streaming decompression.
+pub struct Window<'a> {
+    buf: &'a mut [u8],
+    /// The bytes produced so far.
+    pos: u64,
+}
+
+impl<'a> Window<'a> {
+    /// Use a buffer with a power-of-two size of at least [WINDOW_SIZE].
+    pub fn new(buf: &'a mut [u8]) -> Option<Self> {
+        if buf.len() < WINDOW_SIZE || !buf.len().is_power_of_two() {
+            return None;
+        }
+        Some(Self { buf, pos: 0 })
+    }
+
+    /// Continue a stream with the history that an earlier window left in the same buffer.
+    pub fn resume(buf: &'a mut [u8], position: u64) -> Option<Self> {
+        let mut res = Self::new(buf)?;
+        res.pos = position;
+        Some(res)
+    }
+
+    /// The bytes produced since the start of the stream.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Forget the history to restart a stream.
+    pub fn reset(&mut self) {
+        self.pos = 0;
+    }
+}
+
+/// Where the decompressed bytes go.
+trait Sink {
+    /// Whether another byte fits.
+    fn room(&self) -> bool;
+    /// Append a literal.
+    fn put(&mut self, byte: u8) -> Result<(), Error>;
+    /// Copy from the history and return the bytes copied.
+    fn copy(&mut self, dist: usize, len: usize) -> Result<usize, Error>;
+}
+
+/// The whole output is in a single buffer.
+struct Flat<'a> {
+    out: &'a mut [u8],
+    pos: usize,
+}
+
+/// Overflows are errors, so the end of the stream is always reached.
+impl Sink for Flat<'_> {
+    fn room(&self) -> bool {
+        true
+    }
+    fn put(&mut self, byte: u8) -> Result<(), Error> {
+        *self.out.get_mut(self.pos).ok_or(msg2err!("output too small"))? = byte;
+        self.pos += 1;
+        Ok(())
+    }
+    fn copy(&mut self, dist: usize, len: usize) -> Result<usize, Error> {
+        if dist > self.pos {
+            return Err(msg2err!("distance too far back"));
+        }
+        if self.pos + len > self.out.len() {
+            return Err(msg2err!("output too small"));
+        }
+        for i in self.pos..self.pos + len {
+            self.out[i] = self.out[i - dist];
+        }
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// The output goes through a ring buffer.
+struct Ring<'a, 'b> {
+    window: &'a mut Window<'b>,
+    out: &'a mut [u8],
+    pos: usize,
+}
+
+impl Sink for Ring<'_, '_> {
+    fn room(&self) -> bool {
+        self.pos < self.out.len()
+    }
+    fn put(&mut self, byte: u8) -> Result<(), Error> {
+        let mask = self.window.buf.len() - 1;
+        self.window.buf[self.window.pos as usize & mask] = byte;
+        self.window.pos += 1;
+        self.out[self.pos] = byte;
+        self.pos += 1;
+        Ok(())
+    }
+    fn copy(&mut self, dist: usize, len: usize) -> Result<usize, Error> {
+        if dist as u64 > self.window.pos || dist > self.window.buf.len() {
+            return Err(msg2err!("distance too far back"));
+        }
+        let n = core::cmp::min(len, self.out.len() - self.pos);
+        let mask = self.window.buf.len() - 1;
+        for _ in 0..n {
+            let byte = self.window.buf[(self.window.pos as usize).wrapping_sub(dist) & mask];
+            self.put(byte)?;
+        }
+        Ok(n)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// A block header follows.
+    Header,
+    /// Bytes left in a stored block.
+    Stored(u16),
+    /// Inside a compressed block.
+    Codes,
+    /// The last block ended.
+    Done,
+}
+
+/// The state of a DEFLATE decompression.
+#[derive(Clone)]
+pub struct Inflate {
+    state: State,
+    /// The current block is the last one.
+    last: bool,
+    bits: u64,
+    nbits: u32,
+    /// A match that did not fit into the output.
+    pending: (usize, usize),
+    lit: Huffman,
+    dist: Huffman,
+}
+
+impl Default for Inflate {
+    fn default() -> Self {
+        Self {
+            state: State::Header,
+            last: false,
+            bits: 0,
+            nbits: 0,
+            pending: (0, 0),
+            lit: Huffman::EMPTY,
+            dist: Huffman::EMPTY,
+        }
+    }
+}
+
+impl Inflate {
+    /// The final block was decompressed.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done && self.pending.0 == 0
+    }
+
+    /// Decompress the next bytes of a stream into the output.
+    ///
+    /// Returns less than the output only at the end of the stream.
+    pub fn read<I: Input>(&mut self, input: &mut I, window: &mut Window, out: &mut [u8]) -> Result<usize, Error> {
+        let mut sink = Ring { window, out, pos: 0 };
+        self.run(input, &mut sink)?;
+        Ok(sink.pos)
+    }
+
+    /// Try to have at least `n` bits buffered.  Returns false at the end of the input.
+    fn fill<I: Input>(&mut self, input: &mut I, n: u32) -> Result<bool, Error> {
+        while self.nbits < n {
+            let Some(byte) = input.byte()? else {
+                return Ok(false);
+            };
+            self.bits |= (byte as u64) << self.nbits;
+            self.nbits += 8;
+        }
+        Ok(true)
+    }
+
+    /// Take `n` bits from the input.
+    fn take<I: Input>(&mut self, input: &mut I, n: u32) -> Result<u32, Error> {
+        if !self.fill(input, n)? {
+            return Err(msg2err!("truncated stream"));
+        }
+        let res = (self.bits & ((1u64 << n) - 1)) as u32;
+        self.bits >>= n;
+        self.nbits -= n;
+        Ok(res)
+    }
+
+    /// Decode a symbol with one of the tables.
+    fn decode<I: Input>(&mut self, input: &mut I, dist: bool) -> Result<u16, Error> {
+        self.fill(input, FAST_BITS)?;
+        let table = if dist { &self.dist } else { &self.lit };
+        let entry = table.fast[(self.bits & ((1 << FAST_BITS) - 1)) as usize];
+        let len = (entry & 0xf) as u32;
+        if len != 0 && len <= self.nbits {
+            self.bits >>= len;
+            self.nbits -= len;
+            return Ok(entry >> 4);
+        }
+
+        // walk the canonical code bit by bit
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= self.take(input, 1)? as i32;
+            let table = if dist { &self.dist } else { &self.lit };
+            let count = table.counts[len] as i32;
+            if code - first < count {
+                return Ok(table.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(msg2err!("invalid code"))
+    }
+
+    /// Read the code lengths of a dynamic block.
+    fn dynamic<I: Input>(&mut self, input: &mut I) -> Result<(), Error> {
+        let nlit = self.take(input, 5)? as usize + 257;
+        let ndist = self.take(input, 5)? as usize + 1;
+        let nclen = self.take(input, 4)? as usize + 4;
+        if nlit > 286 || ndist > 30 {
+            return Err(msg2err!("too many codes"));
+        }
+        let mut lengths = [0u8; 286 + 30];
+        for &i in &CLEN_ORDER[..nclen] {
+            lengths[i] = self.take(input, 3)? as u8;
+        }
+        self.lit.build(&lengths[..19])?;
+
+        let mut i = 0;
+        while i < nlit + ndist {
+            let symbol = self.decode(input, false)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 if i > 0 => (lengths[i - 1], 3 + self.take(input, 2)?),
+                17 => (0, 3 + self.take(input, 3)?),
+                18 => (0, 11 + self.take(input, 7)?),
+                _ => return Err(msg2err!("invalid code lengths")),
+            };
+            if i + repeat as usize > nlit + ndist {
+                return Err(msg2err!("too many code lengths"));
+            }
+            lengths[i..i + repeat as usize].fill(value);
+            i += repeat as usize;
+        }
+        if lengths[256] == 0 {
+            return Err(msg2err!("missing end-of-block"));
+        }
+        self.lit.build(&lengths[..nlit])?;
+        self.dist.build(&lengths[nlit..nlit + ndist])
+    }
+
+    /// Decompress until the output is full or the stream ends.
+    fn run<I: Input, S: Sink>(&mut self, input: &mut I, sink: &mut S) -> Result<(), Error> {
+        loop {
+            let (len, dist) = self.pending;
+            if len != 0 {
+                let n = sink.copy(dist, len)?;
+                self.pending.0 -= n;
+                if n != len {
+                    return Ok(());
+                }
+            }
+            match self.state {
+                State::Done => return Ok(()),
+                State::Header if self.last => self.state = State::Done,
+                State::Header => {
+                    self.last = self.take(input, 1)? != 0;
+                    match self.take(input, 2)? {
+                        0 => {
+                            // stored blocks start at a byte boundary
+                            let drop = self.nbits % 8;
+                            self.take(input, drop)?;
+                            let len = self.take(input, 16)?;
+                            if len != !self.take(input, 16)? & 0xffff {
+                                return Err(msg2err!("stored block length"));
+                            }
+                            self.state = State::Stored(len as u16);
+                        }
+                        1 => {
+                            let mut lengths = [8u8; 288];
+                            lengths[144..256].fill(9);
+                            lengths[256..280].fill(7);
+                            self.lit.build(&lengths)?;
+                            self.dist.build(&[5; 30])?;
+                            self.state = State::Codes;
+                        }
+                        2 => {
+                            self.dynamic(input)?;
+                            self.state = State::Codes;
+                        }
+                        _ => return Err(msg2err!("invalid block type")),
+                    }
+                }
+                State::Stored(mut n) => {
+                    while n != 0 && sink.room() {
+                        sink.put(self.take(input, 8)? as u8)?;
+                        n -= 1;
+                    }
+                    if n != 0 {
+                        self.state = State::Stored(n);
+                        return Ok(());
+                    }
+                    self.state = State::Header;
+                }
+                State::Codes => {
+                    if !sink.room() {
+                        return Ok(());
+                    }
+                    let symbol = self.decode(input, false)? as usize;
+                    if symbol < 256 {
+                        sink.put(symbol as u8)?;
+                        continue;
+                    }
+                    if symbol == 256 {
+                        self.state = State::Header;
+                        continue;
+                    }
+                    let i = symbol - 257;
+                    if i >= LENGTH_BASE.len() {
+                        return Err(msg2err!("invalid length"));
+                    }
+                    let len = LENGTH_BASE[i] as usize + self.take(input, LENGTH_EXTRA[i] as u32)? as usize;
+                    let i = self.decode(input, true)? as usize;
+                    if i >= DIST_BASE.len() {
+                        return Err(msg2err!("invalid distance"));
+                    }
+                    let dist = DIST_BASE[i] as usize + self.take(input, DIST_EXTRA[i] as u32)? as usize;
+                    self.pending = (len, dist);
+                }
+            }
+        }
+    }
+}
+
+/// Decompress a raw DEFLATE stream into the output and return its length.
+///
+/// The output has to hold the whole stream.
+pub fn inflate<I: Input>(input: &mut I, out: &mut [u8]) -> Result<usize, Error> {
+    let mut state = Inflate::default();
+    let mut sink = Flat { out, pos: 0 };
+    state.run(input, &mut sink)?;
+    Ok(sink.pos)
+}
+
+/// Skip the header of a zlib stream.
+pub fn zlib_header<I: Input>(input: &mut I) -> Result<(), Error> {
+    let (Some(cmf), Some(flg)) = (input.byte()?, input.byte()?) else {
+        return Err(msg2err!("truncated zlib header"));
+    };
+    if cmf & 0xf != 8 || cmf >> 4 > 7 || (cmf as u16 * 256 + flg as u16) % 31 != 0 || flg & 0x20 != 0 {
+        return Err(msg2err!("invalid zlib header"));
+    }
+    Ok(())
+}
+
+/// Decompress a zlib stream into the output and return its length.
+///
+/// The checksum at the end is not verified.
+pub fn inflate_zlib<I: Input>(input: &mut I, out: &mut [u8]) -> Result<usize, Error> {
+    zlib_header(input)?;
+    inflate(input, out)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::{io::Write, process::Command, process::Stdio, vec, vec::Vec};
+
+    /// Compress with gzip and strip the header.  Returns None if the tool is missing.
+    fn deflate(data: &[u8], level: &str) -> Option<Vec<u8>> {
+        let mut child = Command::new("gzip")
+            .args(["-c", "-n", level])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let mut stdin = child.stdin.take()?;
+        let data = data.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&data));
+        let output = child.wait_with_output().ok()?;
+        writer.join().unwrap().ok()?;
+        Some(output.stdout[10..output.stdout.len() - 8].to_vec())
+    }
+
+    #[test]
+    fn roundtrip() {
+        // text-like runs and noise to get fixed, dynamic and stored blocks
+        let mut data: Vec<u8> = (0..100000u32).map(|x| b"abcdefgh "[(x % 9) as usize]).collect();
+        let mut seed = 1u32;
+        data.extend((0..100000).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }));
+        data.extend_from_slice(b"the end");
+
+        for level in ["-1", "-9"] {
+            let Some(compressed) = deflate(&data, level) else {
+                return;
+            };
+            let mut out = vec![0; data.len()];
+            assert_eq!(inflate(&mut &compressed[..], &mut out).unwrap(), data.len());
+            assert!(out == data);
+            assert!(inflate(&mut &compressed[..], &mut out[..1000]).is_err());
+
+            // stream in odd chunks through the smallest window
+            let mut buf = vec![0; WINDOW_SIZE];
+            let mut window = Window::new(&mut buf).unwrap();
+            let mut state = Inflate::default();
+            let mut input = &compressed[..];
+            let mut res = Vec::new();
+            let mut chunk = [0; 777];
+            loop {
+                let n = state.read(&mut input, &mut window, &mut chunk).unwrap();
+                res.extend_from_slice(&chunk[..n]);
+                if n < chunk.len() {
+                    break;
+                }
+            }
+            assert!(state.is_done());
+            assert_eq!(window.position(), data.len() as u64);
+            assert!(res == data);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Bit readers in both directions.
+
+use ap_storage::{msg2err, Error};
+
+/// Read bits from the start of a buffer, lowest bits first.
+pub(crate) struct Forward<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Forward<'a> {
+    pub(crate) fn new(src: &'a [u8]) -> Self {
+        Self { src, pos: 0 }
+    }
+
+    pub(crate) fn read(&mut self, n: u32) -> Result<u32, Error> {
+        if self.pos + n as usize > self.src.len() * 8 {
+            return Err(msg2err!("truncated table"));
+        }
+        let res = peek(self.src, self.pos as isize, n) as u32;
+        self.pos += n as usize;
+        Ok(res)
+    }
+
+    pub(crate) fn rewind(&mut self, n: u32) {
+        self.pos -= n as usize;
+    }
+
+    /// The bytes touched so far.
+    pub(crate) fn bytes(&self) -> usize {
+        self.pos.div_ceil(8)
+    }
+}
+
+/// Read bits from the end of a buffer towards its start.
+///
+/// The highest set bit of the last byte marks the end of the stream.
+/// Reading past the start returns zeros, which is detected by `overflowed`.
+pub(crate) struct Backward<'a> {
+    src: &'a [u8],
+    /// The bits left to read.
+    pos: isize,
+}
+
+impl<'a> Backward<'a> {
+    pub(crate) fn new(src: &'a [u8]) -> Result<Self, Error> {
+        let last = *src.last().ok_or(msg2err!("empty bitstream"))?;
+        if last == 0 {
+            return Err(msg2err!("missing end marker"));
+        }
+        let pos = (src.len() as isize - 1) * 8 + 7 - last.leading_zeros() as isize;
+        Ok(Self { src, pos })
+    }
+
+    pub(crate) fn read(&mut self, n: u32) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        self.pos -= n as isize;
+        peek(self.src, self.pos, n)
+    }
+
+    /// Look at the next bits without consuming them.
+    pub(crate) fn peek(&self, n: u32) -> u64 {
+        peek(self.src, self.pos - n as isize, n)
+    }
+
+    pub(crate) fn consume(&mut self, n: u32) {
+        self.pos -= n as isize;
+    }
+
+    /// The bits left, negative after reading past the start.
+    pub(crate) fn remaining(&self) -> isize {
+        self.pos
+    }
+}
+
+/// Get up to 56 bits starting at a bit position.  Bits outside the buffer are zero.
+fn peek(src: &[u8], start: isize, n: u32) -> u64 {
+    if start < 0 {
+        if start + (n as isize) <= 0 {
+            return 0;
+        }
+        return peek(src, 0, (start + n as isize) as u32) << -start;
+    }
+    let byte = start as usize / 8;
+    let mut buf = [0u8; 8];
+    if byte < src.len() {
+        let n = core::cmp::min(8, src.len() - byte);
+        buf[..n].copy_from_slice(&src[byte..byte + n]);
+    }
+    (u64::from_le_bytes(buf) >> (start % 8)) & ((1u64 << n) - 1)
+}
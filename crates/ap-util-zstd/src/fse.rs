@@ -0,0 +1,151 @@
+//! Finite state entropy tables.
+
+use crate::bits::{Backward, Forward};
+use ap_storage::{msg2err, Error};
+
+/// The most symbols of any table.
+const MAX_SYMBOLS: usize = 256;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Entry {
+    symbol: u8,
+    nbits: u8,
+    base: u16,
+}
+
+/// A decoding table with up to `N` states.
+#[derive(Clone)]
+pub(crate) struct Table<const N: usize> {
+    entries: [Entry; N],
+    log: u32,
+}
+
+impl<const N: usize> Table<N> {
+    pub(crate) const EMPTY: Self = Self {
+        entries: [Entry {
+            symbol: 0,
+            nbits: 0,
+            base: 0,
+        }; N],
+        log: 0,
+    };
+
+    /// A table that always returns the same symbol.
+    pub(crate) fn rle(&mut self, symbol: u8) {
+        self.log = 0;
+        self.entries[0] = Entry {
+            symbol,
+            nbits: 0,
+            base: 0,
+        };
+    }
+
+    /// Read a table description and return the bytes consumed.
+    pub(crate) fn read(&mut self, src: &[u8], max_log: u32, max_symbols: usize) -> Result<usize, Error> {
+        let mut bits = Forward::new(src);
+        let log = 5 + bits.read(4)?;
+        if log > max_log {
+            return Err(msg2err!("accuracy log too large"));
+        }
+        let mut counts = [0i16; MAX_SYMBOLS];
+        let mut remaining = 1i32 << log;
+        let mut symbol = 0;
+        while remaining > 0 && symbol < max_symbols {
+            let n = 32 - ((remaining + 1) as u32).leading_zeros();
+            let mut value = bits.read(n)? as i32;
+            let lower_mask = (1 << (n - 1)) - 1;
+            let threshold = (1 << n) - 1 - (remaining + 1);
+            if value & lower_mask < threshold {
+                bits.rewind(1);
+                value &= lower_mask;
+            } else if value > lower_mask {
+                value -= threshold;
+            }
+            let count = value - 1;
+            remaining -= count.abs();
+            counts[symbol] = count as i16;
+            symbol += 1;
+            if count == 0 {
+                loop {
+                    let repeat = bits.read(2)? as usize;
+                    symbol = core::cmp::min(symbol + repeat, max_symbols);
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+        }
+        if remaining != 0 {
+            return Err(msg2err!("invalid table description"));
+        }
+        self.build(&counts[..symbol], log)?;
+        Ok(bits.bytes())
+    }
+
+    /// Build the table from normalized counts.
+    pub(crate) fn build(&mut self, counts: &[i16], log: u32) -> Result<(), Error> {
+        let size = 1usize << log;
+        if size > N {
+            return Err(msg2err!("table too large"));
+        }
+        self.log = log;
+        let mut next = [0u16; MAX_SYMBOLS];
+
+        // symbols with less than one count go to the end
+        let mut high = size;
+        for (symbol, &count) in counts.iter().enumerate() {
+            if count == -1 {
+                high -= 1;
+                self.entries[high].symbol = symbol as u8;
+                next[symbol] = 1;
+            }
+        }
+
+        // spread the others
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut pos = 0;
+        for (symbol, &count) in counts.iter().enumerate() {
+            if count <= 0 {
+                continue;
+            }
+            next[symbol] = count as u16;
+            for _ in 0..count {
+                self.entries[pos].symbol = symbol as u8;
+                pos = (pos + step) & (size - 1);
+                while pos >= high {
+                    pos = (pos + step) & (size - 1);
+                }
+            }
+        }
+        if pos != 0 {
+            return Err(msg2err!("invalid distribution"));
+        }
+
+        for entry in self.entries[..size].iter_mut() {
+            let state = next[entry.symbol as usize];
+            next[entry.symbol as usize] += 1;
+            let nbits = log - (31 - (state as u32).leading_zeros());
+            entry.nbits = nbits as u8;
+            entry.base = ((state as u32) << nbits) as u16 - size as u16;
+        }
+        Ok(())
+    }
+}
+
+/// The state while decoding.
+pub(crate) struct State(usize);
+
+impl State {
+    pub(crate) fn new<const N: usize>(table: &Table<N>, bits: &mut Backward) -> Self {
+        Self(bits.read(table.log) as usize)
+    }
+
+    pub(crate) fn symbol<const N: usize>(&self, table: &Table<N>) -> u8 {
+        table.entries[self.0].symbol
+    }
+
+    pub(crate) fn update<const N: usize>(&mut self, table: &Table<N>, bits: &mut Backward) {
+        let entry = table.entries[self.0];
+        self.0 = entry.base as usize + bits.read(entry.nbits as u32) as usize;
+    }
+}
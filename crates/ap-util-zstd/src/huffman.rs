@@ -0,0 +1,137 @@
+//! Huffman coded literals.
+
+use crate::{
+    bits::Backward,
+    fse::{State, Table},
+};
+use ap_storage::{msg2err, Error};
+
+/// The longest code.
+const MAX_BITS: u32 = 11;
+
+/// A decoding table indexed by the next `max_bits` bits.
+#[derive(Clone)]
+pub(crate) struct Huffman {
+    /// Symbol and code length.
+    table: [(u8, u8); 1 << MAX_BITS],
+    max_bits: u32,
+}
+
+impl Huffman {
+    pub(crate) const EMPTY: Self = Self {
+        table: [(0, 0); 1 << MAX_BITS],
+        max_bits: 0,
+    };
+
+    /// Whether a table was read before.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.max_bits != 0
+    }
+
+    /// Read a tree description and return the bytes consumed.
+    pub(crate) fn read(&mut self, src: &[u8]) -> Result<usize, Error> {
+        let header = *src.first().ok_or(msg2err!("missing tree"))? as usize;
+        let mut weights = [0u8; 256];
+        let (mut n, used) = if header < 128 {
+            let data = src.get(1..1 + header).ok_or(msg2err!("truncated tree"))?;
+            let mut table = Table::<64>::EMPTY;
+            let start = table.read(data, 6, 12)?;
+            let mut bits = Backward::new(&data[start..])?;
+            let mut first = State::new(&table, &mut bits);
+            let mut second = State::new(&table, &mut bits);
+            let mut n = 0;
+            loop {
+                if n + 2 >= weights.len() {
+                    return Err(msg2err!("too many weights"));
+                }
+                weights[n] = first.symbol(&table);
+                first.update(&table, &mut bits);
+                n += 1;
+                if bits.remaining() < 0 {
+                    weights[n] = second.symbol(&table);
+                    n += 1;
+                    break;
+                }
+                weights[n] = second.symbol(&table);
+                second.update(&table, &mut bits);
+                n += 1;
+                if bits.remaining() < 0 {
+                    weights[n] = first.symbol(&table);
+                    n += 1;
+                    break;
+                }
+            }
+            (n, 1 + header)
+        } else {
+            let n = header - 127;
+            let data = src.get(1..1 + n.div_ceil(2)).ok_or(msg2err!("truncated tree"))?;
+            for (i, weight) in weights[..n].iter_mut().enumerate() {
+                *weight = if i % 2 == 0 {
+                    data[i / 2] >> 4
+                } else {
+                    data[i / 2] & 0xf
+                };
+            }
+            (n, 1 + data.len())
+        };
+
+        // the last weight is implied by the others
+        let mut sum = 0u32;
+        for &weight in &weights[..n] {
+            if weight > MAX_BITS as u8 {
+                return Err(msg2err!("invalid weight"));
+            }
+            if weight != 0 {
+                sum += 1 << (weight - 1);
+            }
+        }
+        if sum == 0 {
+            return Err(msg2err!("empty tree"));
+        }
+        let max_bits = 32 - sum.leading_zeros();
+        let left = (1 << max_bits) - sum;
+        if max_bits > MAX_BITS || !left.is_power_of_two() {
+            return Err(msg2err!("invalid tree"));
+        }
+        weights[n] = left.trailing_zeros() as u8 + 1;
+        n += 1;
+
+        // assign the codes starting with the longest ones
+        let mut counts = [0u32; MAX_BITS as usize + 2];
+        for &weight in &weights[..n] {
+            if weight != 0 {
+                counts[(max_bits + 1 - weight as u32) as usize] += 1;
+            }
+        }
+        let mut index = [0u32; MAX_BITS as usize + 2];
+        for bits in (1..=max_bits as usize).rev() {
+            index[bits - 1] = index[bits] + counts[bits] * (1 << (max_bits as usize - bits));
+        }
+        for (symbol, &weight) in weights[..n].iter().enumerate() {
+            if weight == 0 {
+                continue;
+            }
+            let bits = (max_bits + 1 - weight as u32) as usize;
+            let len = 1 << (max_bits as usize - bits);
+            let start = index[bits] as usize;
+            self.table[start..start + len].fill((symbol as u8, bits as u8));
+            index[bits] += len as u32;
+        }
+        self.max_bits = max_bits;
+        Ok(used)
+    }
+
+    /// Decode a single stream that fills the output.
+    pub(crate) fn decode(&self, src: &[u8], out: &mut [u8]) -> Result<(), Error> {
+        let mut bits = Backward::new(src)?;
+        for byte in out.iter_mut() {
+            let (symbol, nbits) = self.table[bits.peek(self.max_bits) as usize];
+            *byte = symbol;
+            bits.consume(nbits as u32);
+        }
+        if bits.remaining() != 0 {
+            return Err(msg2err!("literal stream size"));
+        }
+        Ok(())
+    }
+}
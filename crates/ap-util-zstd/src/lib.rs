@@ -0,0 +1,479 @@
+//! Decompress zstd frames without allocations.
+//!
+//! - the whole output has to fit into a caller provided buffer, as it doubles as history
+//! - the literals of a block are decoded into a second buffer of up to 128k
+//! - dictionaries are not supported and checksums are not verified
+
+#![no_std]
+
+mod bits;
+mod fse;
+mod huffman;
+
+use ap_storage::{msg2err, Error};
+use bits::Backward;
+use fse::{State, Table};
+use huffman::Huffman;
+
+/// The largest block and therefore the most literals in a block.
+pub const MAX_BLOCK_SIZE: usize = 1 << 17;
+
+const MAGIC: u32 = 0xfd2f_b528;
+
+/// Skippable frames have a magic in this range.
+const SKIPPABLE: core::ops::RangeInclusive<u32> = 0x184d_2a50..=0x184d_2a5f;
+
+/// Baseline and extra bits of the literal length codes.
+const LL_CODES: [(u32, u8); 36] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 0),
+    (12, 0),
+    (13, 0),
+    (14, 0),
+    (15, 0),
+    (16, 1),
+    (18, 1),
+    (20, 1),
+    (22, 1),
+    (24, 2),
+    (28, 2),
+    (32, 3),
+    (40, 3),
+    (48, 4),
+    (64, 6),
+    (128, 7),
+    (256, 8),
+    (512, 9),
+    (1024, 10),
+    (2048, 11),
+    (4096, 12),
+    (8192, 13),
+    (16384, 14),
+    (32768, 15),
+    (65536, 16),
+];
+
+/// Baseline and extra bits of the match length codes.
+const ML_CODES: [(u32, u8); 53] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 0),
+    (12, 0),
+    (13, 0),
+    (14, 0),
+    (15, 0),
+    (16, 0),
+    (17, 0),
+    (18, 0),
+    (19, 0),
+    (20, 0),
+    (21, 0),
+    (22, 0),
+    (23, 0),
+    (24, 0),
+    (25, 0),
+    (26, 0),
+    (27, 0),
+    (28, 0),
+    (29, 0),
+    (30, 0),
+    (31, 0),
+    (32, 0),
+    (33, 0),
+    (34, 0),
+    (35, 1),
+    (37, 1),
+    (39, 1),
+    (41, 1),
+    (43, 2),
+    (47, 2),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 5),
+    (131, 7),
+    (259, 8),
+    (515, 9),
+    (1027, 10),
+    (2051, 11),
+    (4099, 12),
+    (8195, 13),
+    (16387, 14),
+    (32771, 15),
+    (65539, 16),
+];
+
+const LL_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1, -1, -1, -1, -1,
+];
+const ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+/// Little-endian integer of up to eight bytes.
+fn le(buf: &[u8]) -> u64 {
+    buf.iter().rev().fold(0, |acc, &x| acc << 8 | x as u64)
+}
+
+/// Split the first `n` bytes off the input.
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], Error> {
+    if input.len() < n {
+        return Err(msg2err!("truncated frame"));
+    }
+    let (head, tail) = input.split_at(n);
+    *input = tail;
+    Ok(head)
+}
+
+/// The tables and offsets that are kept between the blocks of a frame.
+struct Context {
+    huffman: Huffman,
+    ll: Table<512>,
+    of: Table<256>,
+    ml: Table<512>,
+    offsets: [usize; 3],
+}
+
+impl Context {
+    fn new() -> Self {
+        Self {
+            huffman: Huffman::EMPTY,
+            ll: Table::EMPTY,
+            of: Table::EMPTY,
+            ml: Table::EMPTY,
+            offsets: [1, 4, 8],
+        }
+    }
+
+    /// Decode the literals section and return the bytes consumed and the number of literals.
+    fn literals(&mut self, src: &[u8], literals: &mut [u8]) -> Result<(usize, usize), Error> {
+        let byte0 = *src.first().ok_or(msg2err!("empty block"))?;
+        let format = (byte0 >> 2) & 3;
+        if byte0 & 3 < 2 {
+            // raw and RLE literals
+            let (header, size) = match format {
+                0 | 2 => (1, byte0 as usize >> 3),
+                1 => (2, le(src.get(..2).ok_or(msg2err!("truncated literals"))?) as usize >> 4),
+                _ => (3, le(src.get(..3).ok_or(msg2err!("truncated literals"))?) as usize >> 4),
+            };
+            let out = literals.get_mut(..size).ok_or(msg2err!("too many literals"))?;
+            if byte0 & 3 == 0 {
+                out.copy_from_slice(src.get(header..header + size).ok_or(msg2err!("truncated literals"))?);
+                return Ok((header + size, size));
+            }
+            out.fill(*src.get(header).ok_or(msg2err!("truncated literals"))?);
+            return Ok((header + 1, size));
+        }
+
+        // Huffman coded literals with an optional new tree
+        let (header, bits, streams) = match format {
+            0 => (3, 10, 1),
+            1 => (3, 10, 4),
+            2 => (4, 14, 4),
+            _ => (5, 18, 4),
+        };
+        let value = le(src.get(..header).ok_or(msg2err!("truncated literals"))?) >> 4;
+        let size = (value & ((1 << bits) - 1)) as usize;
+        let compressed = (value >> bits) as usize;
+        let mut data = src
+            .get(header..header + compressed)
+            .ok_or(msg2err!("truncated literals"))?;
+        if byte0 & 3 == 2 {
+            let n = self.huffman.read(data)?;
+            data = &data[n..];
+        } else if !self.huffman.is_valid() {
+            return Err(msg2err!("missing tree"));
+        }
+        let out = literals.get_mut(..size).ok_or(msg2err!("too many literals"))?;
+        if streams == 1 {
+            self.huffman.decode(data, out)?;
+        } else {
+            let jump = data.get(..6).ok_or(msg2err!("truncated jump table"))?;
+            let mut data = &data[6..];
+            let chunk = size.div_ceil(4);
+            for (i, out) in out.chunks_mut(chunk).enumerate() {
+                let n = if i < 3 {
+                    le(&jump[i * 2..i * 2 + 2]) as usize
+                } else {
+                    data.len()
+                };
+                self.huffman.decode(take(&mut data, n)?, out)?;
+            }
+        }
+        Ok((header + compressed, size))
+    }
+
+    /// Read the sequence tables and return the bytes consumed.
+    fn tables(&mut self, src: &[u8]) -> Result<usize, Error> {
+        let modes = *src.first().ok_or(msg2err!("missing modes"))?;
+        let mut pos = 1;
+        fn table<const N: usize>(
+            table: &mut Table<N>,
+            mode: u8,
+            src: &[u8],
+            default: &[i16],
+            log: u32,
+            max_log: u32,
+        ) -> Result<usize, Error> {
+            match mode {
+                0 => table.build(default, log).map(|_| 0),
+                1 => {
+                    table.rle(*src.first().ok_or(msg2err!("truncated sequences"))?);
+                    Ok(1)
+                }
+                2 => table.read(src, max_log, default.len().max(32)),
+                _ => Ok(0),
+            }
+        }
+        pos += table(&mut self.ll, modes >> 6, &src[pos..], &LL_DEFAULT, 6, 9)?;
+        pos += table(&mut self.of, (modes >> 4) & 3, &src[pos..], &OF_DEFAULT, 5, 8)?;
+        pos += table(&mut self.ml, (modes >> 2) & 3, &src[pos..], &ML_DEFAULT, 6, 9)?;
+        Ok(pos)
+    }
+
+    /// Decode a compressed block at position `pos` of the output.
+    fn block(&mut self, src: &[u8], out: &mut [u8], mut pos: usize, literals: &mut [u8]) -> Result<usize, Error> {
+        let (used, size) = self.literals(src, literals)?;
+        let mut src = &src[used..];
+        let byte0 = *take(&mut src, 1)?.first().unwrap() as usize;
+        let count = match byte0 {
+            0..=127 => byte0,
+            128..=254 => ((byte0 - 128) << 8) + take(&mut src, 1)?[0] as usize,
+            _ => le(take(&mut src, 2)?) as usize + 0x7f00,
+        };
+
+        let mut literals = &literals[..size];
+        fn copy(out: &mut [u8], pos: usize, literals: &mut &[u8], n: usize) -> Result<(), Error> {
+            let dst = out.get_mut(pos..pos + n).ok_or(msg2err!("output too small"))?;
+            if literals.len() < n {
+                return Err(msg2err!("too few literals"));
+            }
+            dst.copy_from_slice(&literals[..n]);
+            *literals = &literals[n..];
+            Ok(())
+        }
+        if count != 0 {
+            let n = self.tables(src)?;
+            let mut bits = Backward::new(&src[n..])?;
+            let mut ll = State::new(&self.ll, &mut bits);
+            let mut of = State::new(&self.of, &mut bits);
+            let mut ml = State::new(&self.ml, &mut bits);
+            for i in 0..count {
+                let code = of.symbol(&self.of) as u32;
+                if code > 31 {
+                    return Err(msg2err!("invalid offset code"));
+                }
+                let mut offset = (1usize << code) + bits.read(code) as usize;
+                let (base, extra) = *ML_CODES
+                    .get(ml.symbol(&self.ml) as usize)
+                    .ok_or(msg2err!("invalid match code"))?;
+                let length = base as usize + bits.read(extra as u32) as usize;
+                let (base, extra) = *LL_CODES
+                    .get(ll.symbol(&self.ll) as usize)
+                    .ok_or(msg2err!("invalid literal code"))?;
+                let nlit = base as usize + bits.read(extra as u32) as usize;
+
+                // repeated offsets
+                if offset > 3 {
+                    offset -= 3;
+                    self.offsets = [offset, self.offsets[0], self.offsets[1]];
+                } else {
+                    let index = offset - 1 + (nlit == 0) as usize;
+                    offset = match index {
+                        0 => self.offsets[0],
+                        3 => self.offsets[0].wrapping_sub(1),
+                        _ => self.offsets[index],
+                    };
+                    match index {
+                        0 => {}
+                        1 => self.offsets.swap(0, 1),
+                        _ => self.offsets = [offset, self.offsets[0], self.offsets[1]],
+                    }
+                }
+
+                if i + 1 != count {
+                    ll.update(&self.ll, &mut bits);
+                    ml.update(&self.ml, &mut bits);
+                    of.update(&self.of, &mut bits);
+                }
+
+                copy(out, pos, &mut literals, nlit)?;
+                pos += nlit;
+                if offset == 0 || offset > pos {
+                    return Err(msg2err!("offset out of range"));
+                }
+                if pos + length > out.len() {
+                    return Err(msg2err!("output too small"));
+                }
+                for j in pos..pos + length {
+                    out[j] = out[j - offset];
+                }
+                pos += length;
+            }
+            if bits.remaining() != 0 {
+                return Err(msg2err!("sequence stream size"));
+            }
+        }
+        let rest = literals.len();
+        copy(out, pos, &mut literals, rest)?;
+        Ok(pos + rest)
+    }
+}
+
+/// Decompress all frames of the input and return the bytes written.
+///
+/// The literals buffer should hold [`MAX_BLOCK_SIZE`] bytes unless the blocks are known to be smaller.
+pub fn decompress(mut input: &[u8], out: &mut [u8], literals: &mut [u8]) -> Result<usize, Error> {
+    let mut pos = 0;
+    while !input.is_empty() {
+        let magic = le(&input[..core::cmp::min(4, input.len())]) as u32;
+        if SKIPPABLE.contains(&magic) {
+            take(&mut input, 4)?;
+            let size = le(take(&mut input, 4)?) as usize;
+            take(&mut input, size)?;
+            continue;
+        }
+        pos = frame(&mut input, out, pos, literals)?;
+    }
+    Ok(pos)
+}
+
+/// Decompress a single frame and return the bytes written.
+///
+/// The input is advanced behind the frame, so that trailing padding can be ignored.
+pub fn decompress_frame(input: &mut &[u8], out: &mut [u8], literals: &mut [u8]) -> Result<usize, Error> {
+    frame(input, out, 0, literals)
+}
+
+/// Decompress a frame to the output position and return the position after it.
+fn frame(input: &mut &[u8], out: &mut [u8], mut pos: usize, literals: &mut [u8]) -> Result<usize, Error> {
+    if le(take(input, 4)?) as u32 != MAGIC {
+        return Err(msg2err!("not a zstd frame"));
+    }
+    let descriptor = take(input, 1)?[0];
+    if descriptor & 0x08 != 0 {
+        return Err(msg2err!("reserved bit set"));
+    }
+    let single = descriptor & 0x20 != 0;
+    if !single {
+        take(input, 1)?;
+    }
+    let dict = [0, 1, 2, 4][descriptor as usize & 3];
+    if le(take(input, dict)?) != 0 {
+        return Err(msg2err!("dictionaries are not supported"));
+    }
+    let fcs = match descriptor >> 6 {
+        0 => single as usize,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    take(input, fcs)?;
+
+    let mut context = Context::new();
+    loop {
+        let header = le(take(input, 3)?) as usize;
+        let size = header >> 3;
+        if size > MAX_BLOCK_SIZE {
+            return Err(msg2err!("block too large"));
+        }
+        match (header >> 1) & 3 {
+            0 => {
+                let dst = out.get_mut(pos..pos + size).ok_or(msg2err!("output too small"))?;
+                dst.copy_from_slice(take(input, size)?);
+                pos += size;
+            }
+            1 => {
+                let dst = out.get_mut(pos..pos + size).ok_or(msg2err!("output too small"))?;
+                dst.fill(take(input, 1)?[0]);
+                pos += size;
+            }
+            2 => pos = context.block(take(input, size)?, out, pos, literals)?,
+            _ => return Err(msg2err!("reserved block type")),
+        }
+        if header & 1 != 0 {
+            break;
+        }
+    }
+    if descriptor & 0x04 != 0 {
+        take(input, 4)?;
+    }
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::{io::Write, process::Command, process::Stdio, vec, vec::Vec};
+
+    /// Compress with the zstd tool.  Returns None if it is missing.
+    fn compress(data: &[u8], level: &str) -> Option<Vec<u8>> {
+        let mut child = Command::new("zstd")
+            .args(["-c", "-q", level])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let mut stdin = child.stdin.take()?;
+        let data = data.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&data));
+        let output = child.wait_with_output().ok()?;
+        writer.join().unwrap().ok()?;
+        Some(output.stdout)
+    }
+
+    #[test]
+    fn roundtrip() {
+        // text-like runs, noise and a long run for all block and literal types
+        let mut data: Vec<u8> = (0..300000u32)
+            .map(|x| b"abcdefgh "[(x % 9) as usize] ^ (x % 7 == 0) as u8)
+            .collect();
+        let mut seed = 1u32;
+        data.extend((0..200000).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8 % 16
+        }));
+        data.extend((0..100000).map(|_| 42));
+        data.extend_from_slice(b"the end");
+
+        let mut literals = vec![0; MAX_BLOCK_SIZE];
+        for level in ["-1", "-3", "-19", "--fast=5"] {
+            let Some(compressed) = compress(&data, level) else {
+                return;
+            };
+            let mut out = vec![0; data.len()];
+            assert_eq!(decompress(&compressed, &mut out, &mut literals).unwrap(), data.len());
+            assert!(out == data);
+            assert!(decompress(&compressed, &mut out[..1000], &mut literals).is_err());
+
+            // concatenated frames
+            let mut twice = compressed.clone();
+            twice.extend_from_slice(&compressed);
+            let mut out = vec![0; data.len() * 2];
+            assert_eq!(decompress(&twice, &mut out, &mut literals).unwrap(), data.len() * 2);
+            assert!(out[data.len()..] == data[..]);
+        }
+    }
+}